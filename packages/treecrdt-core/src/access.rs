@@ -0,0 +1,414 @@
+//! Subtree-scoped capability evaluation.
+//!
+//! Mirrors the grant semantics of the sync auth extension (`docs/sync/v0/auth.md`): a capability
+//! names a scope root (optionally depth-limited, with excluded sub-subtrees) and a set of actions.
+//! Whether a node falls inside a scope is decided by walking [`NodeStore::parent`] from the node
+//! towards the scope root.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::error::{Error, Result};
+use crate::ids::{Lamport, NodeId, OperationId};
+use crate::ops::{Operation, OperationKind};
+use crate::traits::{AccessControl, NodeStore, OrphanPolicy};
+use crate::version_vector::VersionVector;
+
+/// Action a capability can grant.
+///
+/// Any write action also grants [`CapabilityAction::Read`], matching the TS auth package.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CapabilityAction {
+    Read,
+    WriteStructure,
+    WritePayload,
+    Delete,
+    Tombstone,
+}
+
+impl CapabilityAction {
+    /// Parse the wire name used in capability tokens (`"write_structure"`, ...).
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "read" | "read_structure" | "read_payload" => Some(Self::Read),
+            "write_structure" => Some(Self::WriteStructure),
+            "write_payload" => Some(Self::WritePayload),
+            "delete" => Some(Self::Delete),
+            "tombstone" => Some(Self::Tombstone),
+            _ => None,
+        }
+    }
+}
+
+/// Tri-state outcome of a scope check.
+///
+/// `Unknown` means the local tree lacks the ancestry needed to decide (e.g. the node or one of
+/// its ancestors has not been received yet).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScopeDecision {
+    Allow,
+    Deny,
+    Unknown,
+}
+
+impl ScopeDecision {
+    pub fn or(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::Allow, _) | (_, Self::Allow) => Self::Allow,
+            (Self::Unknown, _) | (_, Self::Unknown) => Self::Unknown,
+            _ => Self::Deny,
+        }
+    }
+
+    pub fn and(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::Deny, _) | (_, Self::Deny) => Self::Deny,
+            (Self::Unknown, _) | (_, Self::Unknown) => Self::Unknown,
+            _ => Self::Allow,
+        }
+    }
+}
+
+/// The part of the tree a capability applies to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SubtreeScope {
+    pub root: NodeId,
+    /// Maximum number of parent edges between a node and `root`.
+    pub max_depth: Option<u32>,
+    /// Sub-subtrees carved out of the scope.
+    pub exclude: Vec<NodeId>,
+}
+
+impl SubtreeScope {
+    pub fn subtree(root: NodeId) -> Self {
+        Self {
+            root,
+            max_depth: None,
+            exclude: Vec::new(),
+        }
+    }
+
+    pub fn doc_wide() -> Self {
+        Self::subtree(NodeId::ROOT)
+    }
+
+    pub fn is_doc_wide(&self) -> bool {
+        self.root == NodeId::ROOT && self.max_depth.is_none() && self.exclude.is_empty()
+    }
+
+    /// Decide whether `node` lies inside this scope in the tree described by `nodes`.
+    pub fn contains(&self, nodes: &dyn NodeStore, node: NodeId) -> Result<ScopeDecision> {
+        if self.is_doc_wide() {
+            return Ok(ScopeDecision::Allow);
+        }
+
+        let mut current = node;
+        let mut distance: u32 = 0;
+        let mut visited = HashSet::new();
+        loop {
+            if self.exclude.contains(&current) {
+                return Ok(ScopeDecision::Deny);
+            }
+            if current == self.root {
+                return Ok(match self.max_depth {
+                    Some(max) if distance > max => ScopeDecision::Deny,
+                    _ => ScopeDecision::Allow,
+                });
+            }
+            // The reserved ids terminate every chain, materialized or not.
+            if current == NodeId::ROOT || current == NodeId::TRASH {
+                return Ok(ScopeDecision::Deny);
+            }
+            if self.max_depth.is_some_and(|max| distance >= max) {
+                return Ok(ScopeDecision::Deny);
+            }
+            if !visited.insert(current) || !nodes.exists(current)? {
+                return Ok(ScopeDecision::Unknown);
+            }
+            match nodes.parent(current)? {
+                Some(parent) => current = parent,
+                None => return Ok(ScopeDecision::Deny),
+            }
+            distance = distance.saturating_add(1);
+        }
+    }
+}
+
+/// A set of actions granted over one [`SubtreeScope`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Capability {
+    pub scope: SubtreeScope,
+    pub actions: Vec<CapabilityAction>,
+}
+
+impl Capability {
+    pub fn new(scope: SubtreeScope, actions: impl Into<Vec<CapabilityAction>>) -> Self {
+        Self {
+            scope,
+            actions: actions.into(),
+        }
+    }
+
+    pub fn allows(&self, action: CapabilityAction) -> bool {
+        match action {
+            CapabilityAction::Read => !self.actions.is_empty(),
+            _ => self.actions.contains(&action),
+        }
+    }
+}
+
+/// [`AccessControl`] policy evaluating a list of subtree-scoped capabilities.
+///
+/// An op is allowed when, for every node it touches, some capability grants all required
+/// actions and its scope contains the node:
+/// - `Insert`: `WriteStructure` (plus `WritePayload` when it carries one) at the parent, and
+///   `WriteStructure` at the current parent when the node already exists, as for `Move`.
+/// - `Move`: `WriteStructure` at the node's current position *and* at the new parent, so a
+///   node can neither be pulled into nor pushed out of a granted subtree from outside it.
+/// - `Delete` / `Tombstone` / `Payload`: the matching action at the node.
+/// - `Restore`: `Delete` at the node, since it undoes one.
+///
+/// Checks that come back [`ScopeDecision::Unknown`] fail closed.
+#[derive(Clone, Debug, Default)]
+pub struct SubtreePolicy {
+    grants: Vec<Capability>,
+}
+
+impl SubtreePolicy {
+    pub fn new(grants: Vec<Capability>) -> Self {
+        Self { grants }
+    }
+
+    pub fn grant(&mut self, capability: Capability) {
+        self.grants.push(capability);
+    }
+
+    pub fn grants(&self) -> &[Capability] {
+        &self.grants
+    }
+
+    pub fn evaluate_node(
+        &self,
+        nodes: &dyn NodeStore,
+        node: NodeId,
+        actions: &[CapabilityAction],
+    ) -> Result<ScopeDecision> {
+        let mut best = ScopeDecision::Deny;
+        for grant in &self.grants {
+            if !actions.iter().all(|action| grant.allows(*action)) {
+                continue;
+            }
+            best = best.or(grant.scope.contains(nodes, node)?);
+            if best == ScopeDecision::Allow {
+                break;
+            }
+        }
+        Ok(best)
+    }
+
+    pub fn evaluate_op(&self, nodes: &dyn NodeStore, op: &Operation) -> Result<ScopeDecision> {
+        let mut overall = ScopeDecision::Allow;
+        for (node, actions) in required_checks(nodes, op)? {
+            overall = overall.and(self.evaluate_node(nodes, node, &actions)?);
+            if overall == ScopeDecision::Deny {
+                break;
+            }
+        }
+        Ok(overall)
+    }
+}
+
+fn required_checks(
+    nodes: &dyn NodeStore,
+    op: &Operation,
+) -> Result<Vec<(NodeId, Vec<CapabilityAction>)>> {
+    use CapabilityAction::*;
+    Ok(match &op.kind {
+        OperationKind::Insert {
+            parent,
+            node,
+            payload,
+            ..
+        } => {
+            let actions = if payload.is_some() {
+                vec![WriteStructure, WritePayload]
+            } else {
+                vec![WriteStructure]
+            };
+            let mut checks = vec![(*parent, actions)];
+            // Inserting a node that is already attached elsewhere moves it.
+            if nodes.exists(*node)? {
+                if let Some(current) = nodes.parent(*node)? {
+                    checks.push((current, vec![WriteStructure]));
+                }
+            }
+            checks
+        }
+        OperationKind::Move {
+            node, new_parent, ..
+        } => vec![
+            (*node, vec![WriteStructure]),
+            (*new_parent, vec![WriteStructure]),
+        ],
+        OperationKind::Delete { node } | OperationKind::Restore { node } => {
+            vec![(*node, vec![Delete])]
+        }
+        OperationKind::Tombstone { node } => vec![(*node, vec![Tombstone])],
+        OperationKind::Payload { node, .. } | OperationKind::PayloadField { node, .. } => {
+            vec![(*node, vec![WritePayload])]
+        }
+    })
+}
+
+fn decision_to_result(decision: ScopeDecision, what: &str) -> Result<()> {
+    match decision {
+        ScopeDecision::Allow => Ok(()),
+        ScopeDecision::Deny => Err(Error::AccessDenied(format!(
+            "{what} is outside granted scope"
+        ))),
+        ScopeDecision::Unknown => Err(Error::AccessDenied(format!(
+            "{what} cannot be scoped without more tree context"
+        ))),
+    }
+}
+
+impl AccessControl for SubtreePolicy {
+    fn can_apply(&self, nodes: &dyn NodeStore, op: &Operation) -> Result<()> {
+        decision_to_result(self.evaluate_op(nodes, op)?, "operation")
+    }
+
+    fn can_read(&self, nodes: &dyn NodeStore, node: NodeId) -> Result<()> {
+        decision_to_result(
+            self.evaluate_node(nodes, node, &[CapabilityAction::Read])?,
+            "read",
+        )
+    }
+}
+
+/// Authorize a batch of ops in order, before any of them is persisted.
+///
+/// Later ops in a batch often hang off nodes inserted earlier in the same batch; those parents
+/// are not materialized yet, so ancestry is resolved through the batch first and `nodes` second.
+pub fn authorize_ops(
+    access: &dyn AccessControl,
+    nodes: &dyn NodeStore,
+    ops: &[Operation],
+) -> Result<()> {
+    let mut view = BatchNodeView {
+        inner: nodes,
+        parents: HashMap::new(),
+    };
+    for op in ops {
+        access.can_apply(&view, op)?;
+        match &op.kind {
+            OperationKind::Insert { parent, node, .. } => {
+                view.parents.insert(*node, *parent);
+            }
+            OperationKind::Move {
+                node, new_parent, ..
+            } => {
+                view.parents.insert(*node, *new_parent);
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Read-only `NodeStore` overlay with parent pointers from not-yet-applied batch ops.
+struct BatchNodeView<'a> {
+    inner: &'a dyn NodeStore,
+    parents: HashMap<NodeId, NodeId>,
+}
+
+impl BatchNodeView<'_> {
+    fn read_only<T>() -> Result<T> {
+        Err(Error::InvalidOperation(
+            "batch authorization view is read-only".into(),
+        ))
+    }
+}
+
+impl NodeStore for BatchNodeView<'_> {
+    fn reset(&mut self) -> Result<()> {
+        Self::read_only()
+    }
+
+    fn ensure_node(&mut self, _node: NodeId) -> Result<()> {
+        Self::read_only()
+    }
+
+    fn exists(&self, node: NodeId) -> Result<bool> {
+        Ok(self.parents.contains_key(&node) || self.inner.exists(node)?)
+    }
+
+    fn parent(&self, node: NodeId) -> Result<Option<NodeId>> {
+        match self.parents.get(&node) {
+            Some(parent) => Ok(Some(*parent)),
+            None => self.inner.parent(node),
+        }
+    }
+
+    fn order_key(&self, node: NodeId) -> Result<Option<Vec<u8>>> {
+        self.inner.order_key(node)
+    }
+
+    fn children(&self, parent: NodeId) -> Result<Vec<NodeId>> {
+        self.inner.children(parent)
+    }
+
+    fn detach(&mut self, _node: NodeId) -> Result<()> {
+        Self::read_only()
+    }
+
+    fn attach(&mut self, _node: NodeId, _parent: NodeId, _order_key: Vec<u8>) -> Result<()> {
+        Self::read_only()
+    }
+
+    fn tombstone(&self, node: NodeId) -> Result<bool> {
+        self.inner.tombstone(node)
+    }
+
+    fn set_tombstone(&mut self, _node: NodeId, _tombstone: bool) -> Result<()> {
+        Self::read_only()
+    }
+
+    fn last_change(&self, node: NodeId) -> Result<VersionVector> {
+        self.inner.last_change(node)
+    }
+
+    fn merge_last_change(&mut self, _node: NodeId, _delta: &VersionVector) -> Result<()> {
+        Self::read_only()
+    }
+
+    fn deleted_at(&self, node: NodeId) -> Result<Option<VersionVector>> {
+        self.inner.deleted_at(node)
+    }
+
+    fn merge_deleted_at(&mut self, _node: NodeId, _delta: &VersionVector) -> Result<()> {
+        Self::read_only()
+    }
+
+    fn hard_deleted(&self, node: NodeId) -> Result<bool> {
+        self.inner.hard_deleted(node)
+    }
+
+    fn set_hard_deleted(&mut self, _node: NodeId, _hard_deleted: bool) -> Result<()> {
+        Self::read_only()
+    }
+
+    fn deleted_by(&self, node: NodeId) -> Result<Option<(Lamport, OperationId)>> {
+        self.inner.deleted_by(node)
+    }
+
+    fn set_deleted_by(&mut self, _node: NodeId, _op: Option<(Lamport, OperationId)>) -> Result<()> {
+        Self::read_only()
+    }
+
+    fn orphan_policy(&self) -> OrphanPolicy {
+        self.inner.orphan_policy()
+    }
+
+    fn all_nodes(&self) -> Result<Vec<NodeId>> {
+        self.inner.all_nodes()
+    }
+}
@@ -24,7 +24,9 @@ pub(crate) fn affected_parents(
         OperationKind::Move { new_parent, .. } => parents.push(*new_parent),
         OperationKind::Delete { .. }
         | OperationKind::Tombstone { .. }
-        | OperationKind::Payload { .. } => {}
+        | OperationKind::Restore { .. }
+        | OperationKind::Payload { .. }
+        | OperationKind::PayloadField { .. } => {}
     }
     parents.sort();
     parents.dedup();
@@ -68,10 +70,37 @@ pub(crate) fn direct_materialization_changes(
             payload: payload.clone(),
             source,
         }],
-        OperationKind::Delete { .. } | OperationKind::Tombstone { .. } => Vec::new(),
+        OperationKind::PayloadField { node, field, value } => {
+            vec![MaterializationChange::PayloadField {
+                node: *node,
+                field: field.clone(),
+                value: value.clone(),
+                source,
+            }]
+        }
+        // Visibility changes surface through the tombstone refresh that follows.
+        OperationKind::Delete { .. }
+        | OperationKind::Tombstone { .. }
+        | OperationKind::Restore { .. } => Vec::new(),
     }
 }
 
+/// Moves under [`NodeId::LOST_AND_FOUND`] for the `(node, parent_before)` pairs an op rescued.
+pub(crate) fn rescue_materialization_changes(
+    rescued: &[(NodeId, NodeId)],
+    source: Option<MaterializationSource>,
+) -> Vec<MaterializationChange> {
+    rescued
+        .iter()
+        .map(|&(node, parent_before)| MaterializationChange::Move {
+            node,
+            parent_before: Some(parent_before),
+            parent_after: NodeId::LOST_AND_FOUND,
+            source: source.clone(),
+        })
+        .collect()
+}
+
 pub(crate) fn materialization_change_from_tombstone_delta(
     delta: TombstoneDelta,
     source: Option<MaterializationSource>,
@@ -154,6 +183,7 @@ pub(crate) fn coalesce_materialization_changes(
     let mut structural: BTreeMap<NodeId, StructuralChange> = BTreeMap::new();
     let mut tombstone: BTreeMap<NodeId, TombstoneChange> = BTreeMap::new();
     let mut payload: BTreeMap<NodeId, PayloadChange> = BTreeMap::new();
+    let mut fields: BTreeMap<(NodeId, String), PayloadChange> = BTreeMap::new();
 
     for change in changes {
         match change {
@@ -257,6 +287,23 @@ pub(crate) fn coalesce_materialization_changes(
                         source,
                     });
             }
+            MaterializationChange::PayloadField {
+                node,
+                field,
+                value,
+                source,
+            } => {
+                fields
+                    .entry((node, field))
+                    .and_modify(|existing| {
+                        existing.payload_after = value.clone();
+                        existing.source = latest_source(existing.source.clone(), source.clone());
+                    })
+                    .or_insert(PayloadChange {
+                        payload_after: value,
+                        source,
+                    });
+            }
         }
     }
 
@@ -338,5 +385,16 @@ pub(crate) fn coalesce_materialization_changes(
         }
     }
 
+    for ((node, field), change) in fields {
+        if !deleted_nodes.contains(&node) {
+            coalesced.push(MaterializationChange::PayloadField {
+                node,
+                field,
+                value: change.payload_after,
+                source: change.source,
+            });
+        }
+    }
+
     coalesced
 }
@@ -0,0 +1,229 @@
+//! Snapshot checkpoints of materialized tree state.
+//!
+//! A [`Checkpoint`] captures everything `replay_from_storage` would rebuild from a prefix of the
+//! op log: node structure and causal metadata, payload and payload field winners, concurrent
+//! payload values, the version vector and the materialization head. Once a checkpoint exists for a causally stable prefix,
+//! storage backends implementing [`CompactableStorage`](crate::traits::CompactableStorage) can
+//! drop that prefix. The sqlite and postgres adapters do not implement it, so their op logs are
+//! never compacted.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+use crate::ids::{Lamport, NodeId, OperationId};
+use crate::materialization::MaterializationHead;
+use crate::ops::Operation;
+use crate::traits::{NodeStore, PayloadFieldEntry, PayloadMode, PayloadStore, PayloadValue};
+use crate::version_vector::VersionVector;
+
+/// Materialized state of one node inside a [`Checkpoint`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NodeCheckpoint {
+    pub node: NodeId,
+    pub parent: Option<NodeId>,
+    pub order_key: Option<Vec<u8>>,
+    pub tombstone: bool,
+    pub last_change: VersionVector,
+    pub deleted_at: Option<VersionVector>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub hard_deleted: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub deleted_by: Option<(Lamport, OperationId)>,
+}
+
+/// Current LWW payload winner of one node inside a [`Checkpoint`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PayloadCheckpoint {
+    pub node: NodeId,
+    pub payload: Option<Vec<u8>>,
+    pub lamport: Lamport,
+    pub writer: OperationId,
+}
+
+/// Current LWW winner of one payload map field inside a [`Checkpoint`]; `value` is `None` for a
+/// cleared field.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PayloadFieldCheckpoint {
+    pub node: NodeId,
+    pub field: String,
+    pub value: Option<Vec<u8>>,
+    pub lamport: Lamport,
+    pub writer: OperationId,
+}
+
+/// One concurrent payload value of a node inside a [`Checkpoint`], see [`PayloadValue`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PayloadValueCheckpoint {
+    pub node: NodeId,
+    pub payload: Option<Vec<u8>>,
+    pub lamport: Lamport,
+    pub writer: OperationId,
+    pub known_state: VersionVector,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Checkpoint {
+    /// Ops folded into this checkpoint.
+    pub version_vector: VersionVector,
+    /// Last op (in canonical order) folded into this checkpoint; `seq` counts all of them.
+    pub head: Option<MaterializationHead>,
+    pub nodes: Vec<NodeCheckpoint>,
+    pub payloads: Vec<PayloadCheckpoint>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub fields: Vec<PayloadFieldCheckpoint>,
+    /// Only captured from stores in [`PayloadMode::MultiValue`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub values: Vec<PayloadValueCheckpoint>,
+}
+
+impl Checkpoint {
+    /// Read the full materialized state out of `nodes` and `payloads`.
+    pub fn capture(
+        nodes: &dyn NodeStore,
+        payloads: &dyn PayloadStore,
+        version_vector: VersionVector,
+        head: Option<MaterializationHead>,
+    ) -> Result<Self> {
+        let mut ids = nodes.all_nodes()?;
+        ids.sort();
+
+        let mut node_entries = Vec::with_capacity(ids.len());
+        let mut payload_entries = Vec::new();
+        let mut field_entries = Vec::new();
+        let mut value_entries = Vec::new();
+        for node in ids {
+            node_entries.push(NodeCheckpoint {
+                node,
+                parent: nodes.parent(node)?,
+                order_key: nodes.order_key(node)?,
+                tombstone: nodes.tombstone(node)?,
+                last_change: nodes.last_change(node)?,
+                deleted_at: nodes.deleted_at(node)?,
+                hard_deleted: nodes.hard_deleted(node)?,
+                deleted_by: nodes.deleted_by(node)?,
+            });
+            if let Some((lamport, writer)) = payloads.last_writer(node)? {
+                payload_entries.push(PayloadCheckpoint {
+                    node,
+                    payload: payloads.payload(node)?,
+                    lamport,
+                    writer,
+                });
+            }
+            for entry in payloads.payload_fields(node)? {
+                field_entries.push(PayloadFieldCheckpoint {
+                    node,
+                    field: entry.field,
+                    value: entry.value,
+                    lamport: entry.lamport,
+                    writer: entry.writer,
+                });
+            }
+            for value in payloads.payload_values(node)? {
+                value_entries.push(PayloadValueCheckpoint {
+                    node,
+                    payload: value.payload,
+                    lamport: value.lamport,
+                    writer: value.writer,
+                    known_state: value.known_state,
+                });
+            }
+        }
+
+        Ok(Self {
+            version_vector,
+            head,
+            nodes: node_entries,
+            payloads: payload_entries,
+            fields: field_entries,
+            values: value_entries,
+        })
+    }
+
+    /// Reset `nodes` and `payloads` and load this checkpoint's state into them.
+    pub fn restore_into(
+        &self,
+        nodes: &mut dyn NodeStore,
+        payloads: &mut dyn PayloadStore,
+    ) -> Result<()> {
+        nodes.reset()?;
+        payloads.reset()?;
+
+        // Create every node before attaching, so attach never has to invent a parent.
+        for entry in &self.nodes {
+            nodes.ensure_node(entry.node)?;
+        }
+        for entry in &self.nodes {
+            if let Some(parent) = entry.parent {
+                nodes.attach(
+                    entry.node,
+                    parent,
+                    entry.order_key.clone().unwrap_or_default(),
+                )?;
+            }
+            nodes.set_tombstone(entry.node, entry.tombstone)?;
+            if !entry.last_change.is_empty() {
+                nodes.merge_last_change(entry.node, &entry.last_change)?;
+            }
+            if let Some(deleted_at) = &entry.deleted_at {
+                nodes.merge_deleted_at(entry.node, deleted_at)?;
+            }
+            if entry.hard_deleted {
+                nodes.set_hard_deleted(entry.node, true)?;
+            }
+            if entry.deleted_by.is_some() {
+                nodes.set_deleted_by(entry.node, entry.deleted_by.clone())?;
+            }
+        }
+
+        for entry in &self.payloads {
+            payloads.set_payload(
+                entry.node,
+                entry.payload.clone(),
+                (entry.lamport, entry.writer.clone()),
+            )?;
+        }
+        for entry in &self.fields {
+            payloads.set_payload_field(
+                entry.node,
+                PayloadFieldEntry {
+                    field: entry.field.clone(),
+                    value: entry.value.clone(),
+                    lamport: entry.lamport,
+                    writer: entry.writer.clone(),
+                },
+            )?;
+        }
+        // A last-writer-wins store only needs the winners restored above.
+        if payloads.mode() == PayloadMode::MultiValue {
+            for run in self.values.chunk_by(|a, b| a.node == b.node) {
+                let values = run
+                    .iter()
+                    .map(|entry| PayloadValue {
+                        payload: entry.payload.clone(),
+                        lamport: entry.lamport,
+                        writer: entry.writer.clone(),
+                        known_state: entry.known_state.clone(),
+                    })
+                    .collect();
+                payloads.set_payload_values(run[0].node, values)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `op` is already folded into this checkpoint.
+    pub fn covers(&self, op: &Operation) -> bool {
+        self.version_vector.contains(&op.meta.id.replica, op.meta.id.counter)
+    }
+
+    pub fn head_seq(&self) -> u64 {
+        self.head.as_ref().map_or(0, |head| head.seq)
+    }
+}
@@ -0,0 +1,465 @@
+//! Compact binary encoding for operations and version vectors.
+//!
+//! Every encoded value starts with a format version byte and a tag saying what follows, then a
+//! dictionary of the replica ids the value mentions. Ops and version vectors refer to replicas by
+//! their index in that dictionary, so a batch of ops from a handful of writers spells out each
+//! replica id once.
+//!
+//! The encoding is canonical: integers are minimal LEB128 varints, the dictionary is sorted and
+//! holds exactly the replicas that are referenced, and version vector entries are sorted by
+//! replica. Decoders reject anything else, so equal values always have equal bytes.
+//!
+//! ```text
+//! value      := version:u8 tag:u8 dictionary body
+//! dictionary := count:varint (len:varint bytes)*
+//! op         := header:u8 replica:varint counter:varint lamport:varint fields [known_state:vv]
+//!               [group_first:varint group_len:varint]
+//! vv         := count:varint (replica:varint frontier:varint n:varint (gap:varint len:varint)*n)*
+//! ```
+
+use std::collections::BTreeMap;
+
+use crate::error::{Error, Result};
+use crate::ids::{NodeId, OperationId, ReplicaId};
+use crate::ops::{Operation, OperationGroup, OperationKind, OperationMetadata};
+use crate::version_vector::VersionVector;
+
+/// Version byte written at the start of every encoded value.
+pub const CODEC_VERSION: u8 = 1;
+
+const TAG_OP: u8 = 1;
+const TAG_OPS: u8 = 2;
+const TAG_VERSION_VECTOR: u8 = 3;
+
+const KIND_INSERT: u8 = 0;
+const KIND_MOVE: u8 = 1;
+const KIND_DELETE: u8 = 2;
+const KIND_TOMBSTONE: u8 = 3;
+const KIND_PAYLOAD: u8 = 4;
+const KIND_PAYLOAD_FIELD: u8 = 5;
+const KIND_RESTORE: u8 = 6;
+const KIND_MASK: u8 = 0x07;
+const FLAG_KNOWN_STATE: u8 = 0x08;
+const FLAG_PAYLOAD: u8 = 0x10;
+const FLAG_GROUP: u8 = 0x20;
+
+/// Encode a single operation.
+pub fn encode_op(op: &Operation) -> Vec<u8> {
+    encode_value(
+        TAG_OP,
+        |dict| collect_op(dict, op),
+        |dict, out| write_op(dict, op, out),
+    )
+}
+
+/// Decode a value produced by [`encode_op`].
+pub fn decode_op(bytes: &[u8]) -> Result<Operation> {
+    decode_value(bytes, TAG_OP, read_op)
+}
+
+/// Encode a batch of operations, keeping their order.
+pub fn encode_ops(ops: &[Operation]) -> Vec<u8> {
+    encode_value(
+        TAG_OPS,
+        |dict| ops.iter().for_each(|op| collect_op(dict, op)),
+        |dict, out| {
+            write_varint(out, ops.len() as u64);
+            for op in ops {
+                write_op(dict, op, out);
+            }
+        },
+    )
+}
+
+/// Decode a value produced by [`encode_ops`].
+pub fn decode_ops(bytes: &[u8]) -> Result<Vec<Operation>> {
+    decode_value(bytes, TAG_OPS, |reader| {
+        let count = reader.len()?;
+        let mut ops = Vec::with_capacity(count.min(reader.remaining()));
+        for _ in 0..count {
+            ops.push(read_op(reader)?);
+        }
+        Ok(ops)
+    })
+}
+
+/// Encode a version vector.
+pub fn encode_version_vector(vv: &VersionVector) -> Vec<u8> {
+    encode_value(
+        TAG_VERSION_VECTOR,
+        |dict| collect_version_vector(dict, vv),
+        |dict, out| write_version_vector(dict, vv, out),
+    )
+}
+
+/// Decode a value produced by [`encode_version_vector`].
+pub fn decode_version_vector(bytes: &[u8]) -> Result<VersionVector> {
+    decode_value(bytes, TAG_VERSION_VECTOR, read_version_vector)
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::InvalidOperation(format!("binary codec: {msg}"))
+}
+
+type Dictionary<'a> = BTreeMap<&'a [u8], u64>;
+
+fn encode_value<'a>(
+    tag: u8,
+    collect: impl FnOnce(&mut Dictionary<'a>),
+    write_body: impl FnOnce(&Dictionary<'a>, &mut Vec<u8>),
+) -> Vec<u8> {
+    let mut dict = Dictionary::new();
+    collect(&mut dict);
+    for (index, slot) in dict.values_mut().enumerate() {
+        *slot = index as u64;
+    }
+
+    let mut out = vec![CODEC_VERSION, tag];
+    write_varint(&mut out, dict.len() as u64);
+    for replica in dict.keys() {
+        write_bytes(&mut out, replica);
+    }
+    write_body(&dict, &mut out);
+    out
+}
+
+fn decode_value<T>(
+    bytes: &[u8],
+    tag: u8,
+    read_body: impl FnOnce(&mut Reader<'_>) -> Result<T>,
+) -> Result<T> {
+    let mut reader = Reader::new(bytes);
+    if reader.byte()? != CODEC_VERSION {
+        return Err(invalid("unsupported format version"));
+    }
+    if reader.byte()? != tag {
+        return Err(invalid("unexpected value tag"));
+    }
+    let count = reader.len()?;
+    for _ in 0..count {
+        let replica = reader.bytes()?;
+        if reader.dict.last().is_some_and(|last| last.as_bytes() >= replica) {
+            return Err(invalid("replica dictionary is not sorted"));
+        }
+        reader.dict.push(ReplicaId::new(replica));
+    }
+    reader.used = vec![false; count];
+
+    let value = read_body(&mut reader)?;
+    if reader.pos != bytes.len() {
+        return Err(invalid("trailing bytes"));
+    }
+    if reader.used.contains(&false) {
+        return Err(invalid("unused replica in dictionary"));
+    }
+    Ok(value)
+}
+
+fn collect_op<'a>(dict: &mut Dictionary<'a>, op: &'a Operation) {
+    dict.insert(op.meta.id.replica.as_bytes(), 0);
+    if let Some(known_state) = &op.meta.known_state {
+        collect_version_vector(dict, known_state);
+    }
+}
+
+fn collect_version_vector<'a>(dict: &mut Dictionary<'a>, vv: &'a VersionVector) {
+    for (replica, _, _) in vv.raw_entries() {
+        dict.insert(replica.as_bytes(), 0);
+    }
+}
+
+fn write_op(dict: &Dictionary<'_>, op: &Operation, out: &mut Vec<u8>) {
+    let (kind, payload) = match &op.kind {
+        OperationKind::Insert { payload, .. } => (KIND_INSERT, payload.as_ref()),
+        OperationKind::Move { .. } => (KIND_MOVE, None),
+        OperationKind::Delete { .. } => (KIND_DELETE, None),
+        OperationKind::Tombstone { .. } => (KIND_TOMBSTONE, None),
+        OperationKind::Restore { .. } => (KIND_RESTORE, None),
+        OperationKind::Payload { payload, .. } => (KIND_PAYLOAD, payload.as_ref()),
+        OperationKind::PayloadField { value, .. } => (KIND_PAYLOAD_FIELD, value.as_ref()),
+    };
+    let mut header = kind;
+    if op.meta.known_state.is_some() {
+        header |= FLAG_KNOWN_STATE;
+    }
+    if payload.is_some() {
+        header |= FLAG_PAYLOAD;
+    }
+    if op.meta.group.is_some() {
+        header |= FLAG_GROUP;
+    }
+    out.push(header);
+    write_varint(out, dict[op.meta.id.replica.as_bytes()]);
+    write_varint(out, op.meta.id.counter);
+    write_varint(out, op.meta.lamport);
+
+    match &op.kind {
+        OperationKind::Insert {
+            parent,
+            node,
+            order_key,
+            ..
+        } => {
+            write_node(out, *parent);
+            write_node(out, *node);
+            write_bytes(out, order_key);
+        }
+        OperationKind::Move {
+            node,
+            new_parent,
+            order_key,
+        } => {
+            write_node(out, *node);
+            write_node(out, *new_parent);
+            write_bytes(out, order_key);
+        }
+        OperationKind::Delete { node }
+        | OperationKind::Tombstone { node }
+        | OperationKind::Restore { node }
+        | OperationKind::Payload { node, .. } => write_node(out, *node),
+        OperationKind::PayloadField { node, field, .. } => {
+            write_node(out, *node);
+            write_bytes(out, field.as_bytes());
+        }
+    }
+    if let Some(payload) = payload {
+        write_bytes(out, payload);
+    }
+    if let Some(known_state) = &op.meta.known_state {
+        write_version_vector(dict, known_state, out);
+    }
+    if let Some(group) = &op.meta.group {
+        write_varint(out, group.first);
+        write_varint(out, u64::from(group.len));
+    }
+}
+
+fn read_op(reader: &mut Reader<'_>) -> Result<Operation> {
+    let header = reader.byte()?;
+    if header & !(KIND_MASK | FLAG_KNOWN_STATE | FLAG_PAYLOAD | FLAG_GROUP) != 0 {
+        return Err(invalid("unknown op flags"));
+    }
+    let has_payload = header & FLAG_PAYLOAD != 0;
+    let replica = reader.replica()?;
+    let counter = reader.varint()?;
+    let lamport = reader.varint()?;
+
+    let kind = header & KIND_MASK;
+    if has_payload && !matches!(kind, KIND_INSERT | KIND_PAYLOAD | KIND_PAYLOAD_FIELD) {
+        return Err(invalid("payload on an op kind without one"));
+    }
+    // Fields are read in the order they are written.
+    let kind = match kind {
+        KIND_INSERT => OperationKind::Insert {
+            parent: reader.node()?,
+            node: reader.node()?,
+            order_key: reader.bytes()?.to_vec(),
+            payload: reader.payload(has_payload)?,
+        },
+        KIND_MOVE => OperationKind::Move {
+            node: reader.node()?,
+            new_parent: reader.node()?,
+            order_key: reader.bytes()?.to_vec(),
+        },
+        KIND_DELETE => OperationKind::Delete {
+            node: reader.node()?,
+        },
+        KIND_TOMBSTONE => OperationKind::Tombstone {
+            node: reader.node()?,
+        },
+        KIND_RESTORE => OperationKind::Restore {
+            node: reader.node()?,
+        },
+        KIND_PAYLOAD => OperationKind::Payload {
+            node: reader.node()?,
+            payload: reader.payload(has_payload)?,
+        },
+        KIND_PAYLOAD_FIELD => OperationKind::PayloadField {
+            node: reader.node()?,
+            field: String::from_utf8(reader.bytes()?.to_vec())
+                .map_err(|_| invalid("payload field name is not UTF-8"))?,
+            value: reader.payload(has_payload)?,
+        },
+        _ => return Err(invalid("unknown op kind")),
+    };
+    let known_state = if header & FLAG_KNOWN_STATE != 0 {
+        Some(read_version_vector(reader)?)
+    } else {
+        None
+    };
+    let group = if header & FLAG_GROUP != 0 {
+        let first = reader.varint()?;
+        let len = u32::try_from(reader.varint()?).map_err(|_| invalid("group too long"))?;
+        let group = OperationGroup { first, len };
+        if !group.contains(counter) {
+            return Err(invalid("op is not a member of its group"));
+        }
+        Some(group)
+    } else {
+        None
+    };
+
+    Ok(Operation {
+        meta: OperationMetadata {
+            id: OperationId { replica, counter },
+            lamport,
+            known_state,
+            group,
+        },
+        kind,
+    })
+}
+
+fn write_version_vector(dict: &Dictionary<'_>, vv: &VersionVector, out: &mut Vec<u8>) {
+    let entries = vv.raw_entries();
+    write_varint(out, entries.len() as u64);
+    for (replica, frontier, ranges) in entries {
+        write_varint(out, dict[replica.as_bytes()]);
+        write_varint(out, frontier);
+        write_varint(out, ranges.len() as u64);
+        let mut prev = frontier;
+        for &(start, end) in ranges {
+            write_varint(out, start - prev);
+            write_varint(out, end - start);
+            prev = end;
+        }
+    }
+}
+
+fn read_version_vector(reader: &mut Reader<'_>) -> Result<VersionVector> {
+    let count = reader.len()?;
+    let mut vv = VersionVector::new();
+    let mut last: Option<usize> = None;
+    for _ in 0..count {
+        let index = reader.replica_index()?;
+        if last.is_some_and(|last| last >= index) {
+            return Err(invalid("version vector entries are not sorted"));
+        }
+        last = Some(index);
+        let frontier = reader.varint()?;
+        let n = reader.len()?;
+        let mut ranges = Vec::with_capacity(n.min(reader.remaining()));
+        let mut prev = frontier;
+        for _ in 0..n {
+            // Ranges never touch the frontier or each other; those would have been merged.
+            let gap = reader.varint()?;
+            if gap < 2 {
+                return Err(invalid("version vector ranges are not disjoint"));
+            }
+            let start = prev.checked_add(gap).ok_or_else(|| invalid("counter overflow"))?;
+            let end =
+                start.checked_add(reader.varint()?).ok_or_else(|| invalid("counter overflow"))?;
+            ranges.push((start, end));
+            prev = end;
+        }
+        vv.insert_raw(reader.dict[index].clone(), frontier, ranges);
+    }
+    Ok(vv)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn write_node(out: &mut Vec<u8>, node: NodeId) {
+    out.extend_from_slice(&node.0.to_be_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    dict: Vec<ReplicaId>,
+    used: Vec<bool>,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            pos: 0,
+            dict: Vec::new(),
+            used: Vec::new(),
+        }
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if n > self.remaining() {
+            return Err(invalid("unexpected end of input"));
+        }
+        let slice = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            let bits = u64::from(byte & 0x7f);
+            if shift == 63 && bits > 1 {
+                return Err(invalid("varint overflow"));
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                if byte == 0 && shift > 0 {
+                    return Err(invalid("overlong varint"));
+                }
+                return Ok(value);
+            }
+        }
+        Err(invalid("varint overflow"))
+    }
+
+    fn len(&mut self) -> Result<usize> {
+        usize::try_from(self.varint()?).map_err(|_| invalid("length overflow"))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.len()?;
+        self.take(len)
+    }
+
+    fn payload(&mut self, present: bool) -> Result<Option<Vec<u8>>> {
+        Ok(if present {
+            Some(self.bytes()?.to_vec())
+        } else {
+            None
+        })
+    }
+
+    fn node(&mut self) -> Result<NodeId> {
+        let bytes: [u8; 16] = self.take(16)?.try_into().expect("16 bytes");
+        Ok(NodeId(u128::from_be_bytes(bytes)))
+    }
+
+    fn replica_index(&mut self) -> Result<usize> {
+        let index = self.len()?;
+        if index >= self.dict.len() {
+            return Err(invalid("replica index out of range"));
+        }
+        self.used[index] = true;
+        Ok(index)
+    }
+
+    fn replica(&mut self) -> Result<ReplicaId> {
+        let index = self.replica_index()?;
+        Ok(self.dict[index].clone())
+    }
+}
@@ -0,0 +1,150 @@
+//! Holding back op groups until every member has arrived.
+//!
+//! Sync hands over ops in whatever batches it likes, so a peer can receive the first half of a
+//! local transaction and show a half-built structure until the rest shows up. Ops that carry an
+//! [`OperationGroup`] can instead be routed through a [`GroupBuffer`] in front of the apply path:
+//! ungrouped ops pass straight through, grouped ones are held until the whole group is there.
+//!
+//! Buffered ops are not stored anywhere, so a host that restarts simply receives them again from
+//! its peers.
+
+use std::collections::BTreeMap;
+
+use crate::error::Result;
+use crate::ids::ReplicaId;
+use crate::ops::{cmp_ops, Operation, OperationGroup};
+
+/// What to do with a group whose members do not all arrive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartialGroupPolicy {
+    /// Hold it until the missing members arrive, however long that takes.
+    Wait,
+    /// Release the members that did arrive once the group has been waiting this long, measured in
+    /// the units of the `now` values the host passes in (e.g. milliseconds).
+    ReleaseAfter(u64),
+}
+
+#[derive(Clone, Debug)]
+struct PendingGroup {
+    group: OperationGroup,
+    since: u64,
+    members: BTreeMap<u64, Operation>,
+}
+
+/// Incomplete op groups waiting for their remaining members, see the module docs.
+#[derive(Clone, Debug)]
+pub struct GroupBuffer {
+    policy: PartialGroupPolicy,
+    pending: BTreeMap<(ReplicaId, u64), PendingGroup>,
+}
+
+impl GroupBuffer {
+    pub fn new(policy: PartialGroupPolicy) -> Self {
+        Self {
+            policy,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    pub fn policy(&self) -> PartialGroupPolicy {
+        self.policy
+    }
+
+    /// Take in `ops` and return the ones that can be applied now, in canonical order: ungrouped
+    /// ops, every group that is complete with them, and the groups the policy gives up on at
+    /// `now` (see [`Self::release_expired`]).
+    ///
+    /// `is_applied(replica, counter)` reports members applied earlier, e.g. by a previous
+    /// release or because the op was received twice.
+    pub fn admit<F>(
+        &mut self,
+        ops: Vec<Operation>,
+        now: u64,
+        mut is_applied: F,
+    ) -> Result<Vec<Operation>>
+    where
+        F: FnMut(&ReplicaId, u64) -> Result<bool>,
+    {
+        let mut ready = Vec::new();
+        let mut touched = Vec::new();
+        for op in ops {
+            let Some(group) = op.meta.group.filter(|group| group.contains(op.meta.id.counter))
+            else {
+                ready.push(op);
+                continue;
+            };
+            let key = (op.meta.id.replica.clone(), group.first);
+            let pending = self.pending.entry(key.clone()).or_insert_with(|| PendingGroup {
+                group,
+                since: now,
+                members: BTreeMap::new(),
+            });
+            if pending.group != group {
+                // Members disagree about the group, so it can never complete as such.
+                ready.push(op);
+                continue;
+            }
+            pending.members.insert(op.meta.id.counter, op);
+            touched.push(key);
+        }
+
+        touched.sort();
+        touched.dedup();
+        for key in touched {
+            let pending = &self.pending[&key];
+            let mut complete = true;
+            for counter in pending.group.counters() {
+                if !pending.members.contains_key(&counter) && !is_applied(&key.0, counter)? {
+                    complete = false;
+                    break;
+                }
+            }
+            if complete {
+                if let Some(pending) = self.pending.remove(&key) {
+                    ready.extend(pending.members.into_values());
+                }
+            }
+        }
+
+        ready.extend(self.release_expired(now));
+        ready.sort_by(cmp_ops);
+        Ok(ready)
+    }
+
+    /// Release the groups that waited longer than [`PartialGroupPolicy::ReleaseAfter`] allows.
+    /// Always empty under [`PartialGroupPolicy::Wait`].
+    pub fn release_expired(&mut self, now: u64) -> Vec<Operation> {
+        let PartialGroupPolicy::ReleaseAfter(timeout) = self.policy else {
+            return Vec::new();
+        };
+        let mut released = Vec::new();
+        self.pending.retain(|_, pending| {
+            if now.saturating_sub(pending.since) < timeout {
+                return true;
+            }
+            released.extend(std::mem::take(&mut pending.members).into_values());
+            false
+        });
+        released.sort_by(cmp_ops);
+        released
+    }
+
+    /// Take every held-back op, complete or not, in canonical order.
+    pub fn drain(&mut self) -> Vec<Operation> {
+        let mut drained: Vec<Operation> = std::mem::take(&mut self.pending)
+            .into_values()
+            .flat_map(|pending| pending.members.into_values())
+            .collect();
+        drained.sort_by(cmp_ops);
+        drained
+    }
+
+    /// Number of ops currently held back.
+    pub fn pending_ops(&self) -> usize {
+        self.pending.values().map(|pending| pending.members.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
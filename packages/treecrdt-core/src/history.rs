@@ -0,0 +1,284 @@
+//! Read-only views of the tree at a past point of its history.
+//!
+//! The op log is the only history there is: a past state is rebuilt by replaying the ops that
+//! precede the requested point into scratch in-memory stores. The live materialized state is
+//! never touched.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use crate::affected::coalesce_materialization_changes;
+use crate::checkpoint::{Checkpoint, NodeCheckpoint, PayloadCheckpoint, PayloadFieldCheckpoint};
+use crate::error::{Error, Result};
+use crate::ids::{NodeId, ReplicaId};
+use crate::materialization::{cmp_frontiers, frontier_from_op, MaterializationFrontier};
+use crate::ops::{cmp_ops, Operation, OperationKind};
+use crate::traits::{
+    LamportClock, MemoryNodeStore, MemoryPayloadStore, MemoryStorage, OrphanPolicy, PayloadMode,
+    Storage,
+};
+use crate::tree::TreeCrdt;
+use crate::types::{MaterializationChange, MaterializationSource};
+use crate::version_vector::VersionVector;
+
+/// Which ops a historical view includes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HistoryCut {
+    /// Every op the version vector contains, e.g. what a peer had seen at some point.
+    Version(VersionVector),
+    /// Every op up to and including this key in canonical order, e.g. an old materialization
+    /// head.
+    Frontier(MaterializationFrontier),
+}
+
+impl HistoryCut {
+    pub fn includes(&self, op: &Operation) -> bool {
+        match self {
+            HistoryCut::Version(vv) => vv.contains(&op.meta.id.replica, op.meta.id.counter),
+            HistoryCut::Frontier(at) => cmp_frontiers(&frontier_from_op(op), at).is_le(),
+        }
+    }
+
+    fn includes_checkpoint(&self, checkpoint: &Checkpoint) -> bool {
+        match self {
+            HistoryCut::Version(vv) => vv.is_aware_of(&checkpoint.version_vector),
+            HistoryCut::Frontier(at) => {
+                checkpoint.head.as_ref().is_none_or(|head| cmp_frontiers(&head.at, at).is_le())
+            }
+        }
+    }
+}
+
+/// Materialize the tree made of the ops in `storage` (on top of `base`, if the log was
+/// compacted) that `cut` includes.
+///
+/// The result is returned as a [`Checkpoint`]: one row per known node with its parent, order key
+/// and tombstone flag, plus the payload winners. Ops folded into `base` cannot be told apart
+/// anymore, so a cut that does not include all of `base` is a [`Error::MissingDependency`].
+///
+/// `payload_mode` and `orphan_policy` must be the document's, so the past tree resolves
+/// payloads and deletes the way the live one did.
+pub fn materialize_at<S: Storage>(
+    storage: &S,
+    base: Option<&Checkpoint>,
+    cut: &HistoryCut,
+    payload_mode: PayloadMode,
+    orphan_policy: OrphanPolicy,
+) -> Result<Checkpoint> {
+    if base.is_some_and(|base| !cut.includes_checkpoint(base)) {
+        return Err(Error::MissingDependency(
+            "history before the compaction checkpoint has been discarded".into(),
+        ));
+    }
+
+    let mut past = MemoryStorage::default();
+    storage.scan_since(0, &mut |op| {
+        if cut.includes(&op) {
+            past.apply(op)?;
+        }
+        Ok(())
+    })?;
+
+    let replica = ReplicaId::new(Vec::new());
+    let nodes = MemoryNodeStore::with_orphan_policy(orphan_policy);
+    let payloads = MemoryPayloadStore::with_mode(payload_mode);
+    let scratch = match base {
+        Some(base) => TreeCrdt::from_checkpoint(
+            replica,
+            past,
+            LamportClock::default(),
+            nodes,
+            payloads,
+            base.clone(),
+        )?,
+        None => {
+            let mut scratch =
+                TreeCrdt::with_stores(replica, past, LamportClock::default(), nodes, payloads)?;
+            scratch.replay_from_storage()?;
+            scratch
+        }
+    };
+
+    // Replays leave the cached tombstone flags alone; derive them from the replayed state.
+    let mut state = scratch.checkpoint()?;
+    for row in &mut state.nodes {
+        row.tombstone = scratch.is_tombstoned(row.node)?;
+    }
+    Ok(state)
+}
+
+/// The visible changes that turn the tree at `from` into the tree at `to`.
+///
+/// Both states are rebuilt with [`materialize_at`] and compared node by node, so the result is
+/// already coalesced the same way a materialization outcome is: one insert/move/delete/restore
+/// per node plus payload changes that the structural change does not already carry, and one
+/// change per payload field that differs. Sources point at the latest op in `to` but not in
+/// `from` that touched the node (or field), and are left out when no such op exists (e.g. a node
+/// restored by an edit further down its subtree).
+///
+/// `from` is expected to be the earlier cut. Nodes that only exist at `from` are reported as
+/// deleted.
+pub fn diff_between<S: Storage>(
+    storage: &S,
+    base: Option<&Checkpoint>,
+    from: &HistoryCut,
+    to: &HistoryCut,
+    payload_mode: PayloadMode,
+    orphan_policy: OrphanPolicy,
+) -> Result<Vec<MaterializationChange>> {
+    let before = materialize_at(storage, base, from, payload_mode, orphan_policy)?;
+    let after = materialize_at(storage, base, to, payload_mode, orphan_policy)?;
+
+    let mut structural_ops: HashMap<NodeId, Operation> = HashMap::new();
+    let mut payload_ops: HashMap<NodeId, Operation> = HashMap::new();
+    let mut field_ops: HashMap<(NodeId, String), Operation> = HashMap::new();
+    storage.scan_since(0, &mut |op| {
+        if !to.includes(&op) || from.includes(&op) {
+            return Ok(());
+        }
+        let node = op.kind.node();
+        if let OperationKind::PayloadField { field, .. } = &op.kind {
+            keep_latest(&mut field_ops, (node, field.clone()), op);
+            return Ok(());
+        }
+        if matches!(
+            op.kind,
+            OperationKind::Insert {
+                payload: Some(_),
+                ..
+            }
+        ) || matches!(op.kind, OperationKind::Payload { .. })
+        {
+            keep_latest(&mut payload_ops, node, op.clone());
+        }
+        if !matches!(op.kind, OperationKind::Payload { .. }) {
+            keep_latest(&mut structural_ops, node, op);
+        }
+        Ok(())
+    })?;
+    let structural_source =
+        |node: NodeId| structural_ops.get(&node).map(MaterializationSource::from_op);
+    let payload_source = |node: NodeId| payload_ops.get(&node).map(MaterializationSource::from_op);
+    let field_source = |node: NodeId, field: &str| {
+        field_ops.get(&(node, field.to_string())).map(MaterializationSource::from_op)
+    };
+
+    let old_rows: HashMap<NodeId, &NodeCheckpoint> = before
+        .nodes
+        .iter()
+        .filter(|row| row.parent.is_some())
+        .map(|row| (row.node, row))
+        .collect();
+    let old_payloads: HashMap<NodeId, &PayloadCheckpoint> =
+        before.payloads.iter().map(|row| (row.node, row)).collect();
+    let new_payloads: HashMap<NodeId, &PayloadCheckpoint> =
+        after.payloads.iter().map(|row| (row.node, row)).collect();
+    let old_fields = fields_by_node(&before.fields);
+    let new_fields = fields_by_node(&after.fields);
+    let visible_parent = |parent: Option<NodeId>| parent.filter(|parent| *parent != NodeId::TRASH);
+
+    let mut changes = Vec::new();
+    let mut seen = HashSet::new();
+    for row in &after.nodes {
+        let node = row.node;
+        let Some(parent_after) = row.parent else {
+            continue;
+        };
+        if node == NodeId::ROOT || node == NodeId::TRASH {
+            continue;
+        }
+        seen.insert(node);
+        let payload_after = new_payloads.get(&node).and_then(|row| row.payload.clone());
+        let payload_changed = old_payloads.get(&node) != new_payloads.get(&node);
+
+        match old_rows.get(&node) {
+            None => changes.push(MaterializationChange::Insert {
+                node,
+                parent_after,
+                payload: payload_after.clone(),
+                source: structural_source(node),
+            }),
+            Some(old) => {
+                if old.parent != row.parent || old.order_key != row.order_key {
+                    changes.push(MaterializationChange::Move {
+                        node,
+                        parent_before: visible_parent(old.parent),
+                        parent_after,
+                        source: structural_source(node),
+                    });
+                }
+            }
+        }
+
+        let was_tombstoned = old_rows.get(&node).is_some_and(|old| old.tombstone);
+        match (was_tombstoned, row.tombstone) {
+            (true, false) => changes.push(MaterializationChange::Restore {
+                node,
+                parent_after: visible_parent(row.parent),
+                payload: payload_after.clone(),
+                source: structural_source(node),
+            }),
+            (false, true) => changes.push(MaterializationChange::Delete {
+                node,
+                parent_before: visible_parent(row.parent),
+                source: structural_source(node),
+            }),
+            _ => {}
+        }
+
+        if payload_changed {
+            changes.push(MaterializationChange::Payload {
+                node,
+                payload: payload_after,
+                source: payload_source(node),
+            });
+        }
+
+        let no_fields = BTreeMap::new();
+        let old = old_fields.get(&node).unwrap_or(&no_fields);
+        let new = new_fields.get(&node).unwrap_or(&no_fields);
+        let names: BTreeSet<&str> = old.keys().chain(new.keys()).copied().collect();
+        for name in names {
+            if old.get(name) == new.get(name) {
+                continue;
+            }
+            changes.push(MaterializationChange::PayloadField {
+                node,
+                field: name.to_string(),
+                value: new.get(name).and_then(|row| row.value.clone()),
+                source: field_source(node, name),
+            });
+        }
+    }
+
+    for (node, old) in old_rows {
+        if old.tombstone || node == NodeId::ROOT || node == NodeId::TRASH || seen.contains(&node) {
+            continue;
+        }
+        changes.push(MaterializationChange::Delete {
+            node,
+            parent_before: visible_parent(old.parent),
+            source: None,
+        });
+    }
+
+    Ok(coalesce_materialization_changes(changes))
+}
+
+fn keep_latest<K: std::hash::Hash + Eq>(latest: &mut HashMap<K, Operation>, key: K, op: Operation) {
+    match latest.get(&key) {
+        Some(current) if cmp_ops(current, &op).is_ge() => {}
+        _ => {
+            latest.insert(key, op);
+        }
+    }
+}
+
+fn fields_by_node(
+    rows: &[PayloadFieldCheckpoint],
+) -> HashMap<NodeId, BTreeMap<&str, &PayloadFieldCheckpoint>> {
+    let mut fields: HashMap<NodeId, BTreeMap<&str, &PayloadFieldCheckpoint>> = HashMap::new();
+    for row in rows {
+        fields.entry(row.node).or_default().insert(row.field.as_str(), row);
+    }
+    fields
+}
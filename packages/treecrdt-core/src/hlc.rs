@@ -0,0 +1,135 @@
+//! Hybrid logical clock packed into a [`Lamport`] value.
+//!
+//! The upper 48 bits hold physical milliseconds since the Unix epoch and the lower 16 bits a
+//! logical counter, so timestamps stay totally ordered like plain Lamport values while also
+//! carrying approximate wall-clock time.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::{Error, Result};
+use crate::ids::Lamport;
+use crate::traits::Clock;
+
+/// Number of low bits reserved for the logical counter.
+pub const HLC_LOGICAL_BITS: u32 = 16;
+const LOGICAL_MASK: Lamport = (1 << HLC_LOGICAL_BITS) - 1;
+
+/// Default tolerated lead of a remote timestamp over the local physical clock.
+pub const DEFAULT_MAX_DRIFT_MS: u64 = 60_000;
+
+/// Source of physical time in milliseconds since the Unix epoch.
+///
+/// Closures implement this, which keeps tests deterministic:
+/// `HybridLogicalClock::with_time_source(move || now.get())`.
+pub trait TimeSource {
+    fn now_millis(&self) -> u64;
+}
+
+impl<F: Fn() -> u64> TimeSource for F {
+    fn now_millis(&self) -> u64 {
+        self()
+    }
+}
+
+/// Reads [`SystemTime::now`]. Not available on `wasm32-unknown-unknown`; inject a source there.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemTimeSource;
+
+impl TimeSource for SystemTimeSource {
+    fn now_millis(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct HybridLogicalClock<T: TimeSource = SystemTimeSource> {
+    last: Lamport,
+    max_drift_ms: u64,
+    time: T,
+}
+
+impl Default for HybridLogicalClock<SystemTimeSource> {
+    fn default() -> Self {
+        Self::with_time_source(SystemTimeSource)
+    }
+}
+
+impl<T: TimeSource> HybridLogicalClock<T> {
+    pub fn with_time_source(time: T) -> Self {
+        Self {
+            last: 0,
+            max_drift_ms: DEFAULT_MAX_DRIFT_MS,
+            time,
+        }
+    }
+
+    /// Reject observed timestamps whose physical part leads local time by more than `max_drift_ms`.
+    pub fn with_max_drift_ms(mut self, max_drift_ms: u64) -> Self {
+        self.max_drift_ms = max_drift_ms;
+        self
+    }
+
+    pub fn pack(physical_ms: u64, logical: u16) -> Lamport {
+        (physical_ms << HLC_LOGICAL_BITS) | Lamport::from(logical)
+    }
+
+    pub fn physical_ms(lamport: Lamport) -> u64 {
+        lamport >> HLC_LOGICAL_BITS
+    }
+
+    pub fn logical(lamport: Lamport) -> u16 {
+        (lamport & LOGICAL_MASK) as u16
+    }
+
+    /// Wall-clock time encoded in an HLC timestamp, e.g. an op's `meta.lamport`.
+    ///
+    /// Only meaningful for timestamps minted by a `HybridLogicalClock`; plain Lamport counters
+    /// decode to the epoch.
+    pub fn wall_clock(lamport: Lamport) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(Self::physical_ms(lamport))
+    }
+
+    fn physical_now(&self) -> Lamport {
+        Self::pack(self.time.now_millis(), 0)
+    }
+}
+
+impl<T: TimeSource> Clock for HybridLogicalClock<T> {
+    fn tick(&mut self) -> Result<Lamport> {
+        // Take physical time when it moved past us, otherwise bump the logical part (which
+        // carries into the physical bits on overflow, keeping timestamps monotonic).
+        let now = self.physical_now();
+        self.last = if now > self.last {
+            now
+        } else {
+            self.last
+                .checked_add(1)
+                .ok_or_else(|| Error::InvalidOperation("hybrid logical clock exhausted".into()))?
+        };
+        Ok(self.last)
+    }
+
+    fn merge(&mut self, external: Lamport) {
+        self.last = self.last.max(external);
+    }
+
+    fn admit(&self, external: Lamport) -> Result<()> {
+        let now_ms = self.time.now_millis();
+        let external_ms = Self::physical_ms(external);
+        if external_ms > now_ms.saturating_add(self.max_drift_ms) {
+            return Err(Error::InvalidOperation(format!(
+                "timestamp leads local clock by {} ms (max drift {} ms)",
+                external_ms - now_ms,
+                self.max_drift_ms
+            )));
+        }
+        Ok(())
+    }
+
+    fn now(&self) -> Lamport {
+        self.last
+    }
+}
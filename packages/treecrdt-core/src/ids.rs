@@ -27,6 +27,9 @@ pub struct NodeId(pub u128);
 impl NodeId {
     pub const ROOT: NodeId = NodeId(0);
     pub const TRASH: NodeId = NodeId(u128::MAX);
+    /// Parent of the nodes rescued under [`crate::OrphanPolicy::LostAndFound`]. Like `ROOT`, it
+    /// has no parent of its own.
+    pub const LOST_AND_FOUND: NodeId = NodeId(u128::MAX - 1);
 }
 
 /// Globally unique identifier for an operation.
@@ -3,19 +3,44 @@
 //! This crate stays independent of concrete storage engines so it can be embedded in SQLite,
 //! WASM, or any host that can satisfy the traits defined here.
 
+pub mod access;
 pub(crate) mod affected;
+pub mod checkpoint;
+pub mod codec;
 pub mod error;
+pub mod groups;
+pub mod history;
+pub mod hlc;
 pub mod ids;
 pub mod materialization;
+pub mod navigation;
 pub mod ops;
 pub mod order_key;
+pub mod purge;
+pub mod reorder;
+pub mod stability;
 pub mod traits;
+pub mod transaction;
+pub mod trash;
 pub mod tree;
 pub mod types;
+pub mod undo;
 mod validation;
 pub mod version_vector;
 
+pub use access::{
+    authorize_ops, Capability, CapabilityAction, ScopeDecision, SubtreePolicy, SubtreeScope,
+};
+pub use checkpoint::{
+    Checkpoint, NodeCheckpoint, PayloadCheckpoint, PayloadFieldCheckpoint, PayloadValueCheckpoint,
+};
+pub use codec::{
+    decode_op, decode_ops, decode_version_vector, encode_op, encode_ops, encode_version_vector,
+};
 pub use error::{Error, Result};
+pub use groups::{GroupBuffer, PartialGroupPolicy};
+pub use history::{diff_between, materialize_at, HistoryCut};
+pub use hlc::{HybridLogicalClock, SystemTimeSource, TimeSource};
 pub use ids::{Lamport, NodeId, OperationId, ReplicaId};
 pub use materialization::{
     apply_incremental_ops_with_delta, apply_persisted_remote_ops_with_delta,
@@ -27,16 +52,27 @@ pub use materialization::{
     MaterializationStateRef, PayloadNoopShortcut, PersistedRemoteApplyResult,
     PersistedRemoteStores,
 };
-pub use ops::{cmp_op_key, cmp_ops, Operation, OperationKind, OperationMetadata};
+pub use navigation::Descendants;
+pub use ops::{cmp_op_key, cmp_ops, Operation, OperationGroup, OperationKind, OperationMetadata};
+pub use order_key::{
+    Anchored, AppendOptimized, FractionalIndex, Lseq, OrderKeyAllocator, OrderKeyStats,
+};
+pub use purge::{purge_stable_subtrees, PurgeReport};
+pub use reorder::{plan_reorder, ReorderRun};
+pub use stability::StabilityTracker;
 pub use traits::{
-    Clock, ExactNodeStore, ExactPayloadStore, IndexProvider, LamportClock, MemoryNodeStore,
-    MemoryPayloadStore, MemoryStorage, NodeStore, NoopParentOpIndex, NoopStorage, ParentOpIndex,
-    PayloadStore, Storage, TruncatingParentOpIndex,
+    AccessControl, AllowAllAccess, Clock, CompactableStorage, ExactNodeStore, ExactPayloadStore,
+    IndexProvider, LamportClock, MemoryNodeStore, MemoryPayloadStore, MemoryStorage, NodeStore,
+    NoopParentOpIndex, NoopStorage, OrphanPolicy, ParentOpIndex, PayloadFieldEntry, PayloadMode,
+    PayloadStore, PayloadValue, PurgeableNodeStore, Storage, TruncatingParentOpIndex,
 };
+pub use transaction::{DuplicatedSubtree, LocalEdit, LocalTransaction, StagedLocalOp};
+pub use trash::{trash_page, TrashCursor, TrashEntry, TRASH_PREVIEW_LEN};
 pub use tree::TreeCrdt;
 pub use types::{
     ApplyDelta, LocalFinalizePlan, LocalPlacement, MaterializationChange, MaterializationOutcome,
     MaterializationSource, MaterializationSourceOperation, NodeExport, NodeSnapshotExport,
     PreparedLocalOp,
 };
+pub use undo::{UndoManager, UndoRecord};
 pub use version_vector::VersionVector;
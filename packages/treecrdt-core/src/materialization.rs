@@ -5,8 +5,8 @@ use crate::affected::coalesce_materialization_changes;
 use crate::ops::{cmp_op_key, cmp_ops, Operation};
 use crate::traits::{
     Clock, ExactNodeStore, ExactPayloadStore, LamportClock, MemoryNodeStore, MemoryPayloadStore,
-    MemoryStorage, NodeStore, NoopStorage, ParentOpIndex, PayloadStore, Storage,
-    TruncatingParentOpIndex,
+    MemoryStorage, NodeStore, NoopStorage, OrphanPolicy, ParentOpIndex, PayloadMode, PayloadStore,
+    Storage, TruncatingParentOpIndex,
 };
 use crate::tree::TreeCrdt;
 use crate::{
@@ -14,7 +14,11 @@ use crate::{
     Result,
 };
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct MaterializationKey<R = Vec<u8>> {
     pub lamport: Lamport,
     pub replica: R,
@@ -35,6 +39,7 @@ pub type MaterializationFrontier = MaterializationKey<Vec<u8>>;
 pub type MaterializationFrontierRef<'a> = MaterializationKey<&'a [u8]>;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct MaterializationHead<R = Vec<u8>> {
     pub at: MaterializationKey<R>,
     pub seq: u64,
@@ -67,7 +72,7 @@ impl<R: AsRef<[u8]>> MaterializationHead<R> {
 }
 
 impl MaterializationHead {
-    fn from_op(op: &Operation, seq: u64) -> Self {
+    pub(crate) fn from_op(op: &Operation, seq: u64) -> Self {
         Self {
             at: MaterializationKey {
                 lamport: op.meta.lamport,
@@ -392,7 +397,7 @@ impl<'a> ReplayAccumulator<'a> {
     }
 }
 
-fn frontier_from_op(op: &Operation) -> MaterializationFrontier {
+pub(crate) fn frontier_from_op(op: &Operation) -> MaterializationFrontier {
     MaterializationFrontier {
         lamport: op.meta.lamport,
         replica: op.meta.id.replica.as_bytes().to_vec(),
@@ -416,7 +421,7 @@ fn owned_frontier<R: AsRef<[u8]>>(frontier: &MaterializationKey<R>) -> Materiali
     }
 }
 
-fn cmp_frontiers<R1: AsRef<[u8]>, R2: AsRef<[u8]>>(
+pub(crate) fn cmp_frontiers<R1: AsRef<[u8]>, R2: AsRef<[u8]>>(
     a: &MaterializationKey<R1>,
     b: &MaterializationKey<R2>,
 ) -> Ordering {
@@ -450,9 +455,14 @@ fn start_replay_frontier() -> MaterializationFrontier {
 }
 
 fn op_requires_full_replay(op: &Operation) -> bool {
+    // Field writes have no per-field predecessor lookup in `FrontierRewindStorage`, so they take
+    // the conservative path as well.
     matches!(
         op.kind,
-        crate::ops::OperationKind::Delete { .. } | crate::ops::OperationKind::Tombstone { .. }
+        crate::ops::OperationKind::Delete { .. }
+            | crate::ops::OperationKind::Tombstone { .. }
+            | crate::ops::OperationKind::Restore { .. }
+            | crate::ops::OperationKind::PayloadField { .. }
     )
 }
 
@@ -552,9 +562,15 @@ where
                 rewind_payload_op_in_place(payloads, storage, op)?
             }
             crate::ops::OperationKind::Delete { .. }
-            | crate::ops::OperationKind::Tombstone { .. } => {
+            | crate::ops::OperationKind::Tombstone { .. }
+            | crate::ops::OperationKind::Restore { .. } => {
+                return Err(Error::Storage(
+                    "delete/tombstone/restore ops are not supported by direct rewind".into(),
+                ));
+            }
+            crate::ops::OperationKind::PayloadField { .. } => {
                 return Err(Error::Storage(
-                    "delete/tombstone ops are not supported by direct rewind".into(),
+                    "payload field ops are not supported by direct rewind".into(),
                 ));
             }
         }
@@ -590,6 +606,9 @@ fn next_replay_frontier<M: MaterializationCursor>(
 /// This allows adapters to avoid recording a replay frontier for a narrow but common case:
 /// older payload ops that do not change materialized payload state even after being inserted
 /// earlier in the canonical log order.
+///
+/// Only valid for [`PayloadMode::LastWriterWins`] stores: in multi-value mode an older write can
+/// still join the node's concurrent values.
 pub fn try_shortcut_out_of_order_payload_noops<M, LoadWriter, E>(
     meta: &M,
     inserted_ops: Vec<Operation>,
@@ -761,14 +780,16 @@ where
     }
     let run = replay.finish();
 
-    let last = run
-        .head
-        .as_ref()
-        .or_else(|| crdt.head_op())
-        .ok_or_else(|| Error::Storage("expected head op after materialization".into()))?;
+    let head = match run.head.as_ref() {
+        Some(last) => MaterializationHead::from_op(last, run.seq),
+        None => crdt
+            .materialization_head()
+            .map(|head| head.with_seq(run.seq))
+            .ok_or_else(|| Error::Storage("expected head op after materialization".into()))?,
+    };
 
     Ok(IncrementalApplyResult {
-        head: Some(MaterializationHead::from_op(last, run.seq)),
+        head: Some(head),
         outcome: run.outcome,
     })
 }
@@ -825,13 +846,15 @@ fn replay_frontier_in_memory<S: Storage>(
     storage: &S,
     frontier: &MaterializationFrontier,
     replica_id: &ReplicaId,
+    payload_mode: PayloadMode,
+    orphan_policy: OrphanPolicy,
 ) -> Result<(RebuiltMaterialization, u64, MaterializationOutcome)> {
     let mut crdt = TreeCrdt::with_stores(
         replica_id.clone(),
         NoopStorage,
         LamportClock::default(),
-        MemoryNodeStore::default(),
-        MemoryPayloadStore::default(),
+        MemoryNodeStore::with_orphan_policy(orphan_policy),
+        MemoryPayloadStore::with_mode(payload_mode),
     )?;
     let mut index = RecordingIndex::default();
     let mut replay = ReplayAccumulator::new(0, ReplayChangeScope::FromFrontier(frontier));
@@ -864,7 +887,8 @@ fn replay_frontier_in_memory<S: Storage>(
 ///
 /// This rewinds the already-materialized suffix directly on the backend stores, truncates suffix
 /// oprefs, and then replays the full invalidated suffix in canonical order. It deliberately bails
-/// out for delete/tombstone suffixes and for broader recovery cases.
+/// out for delete/tombstone/restore/payload field suffixes, for payload writes into multi-value
+/// stores, for [`OrphanPolicy::LostAndFound`] stores and for broader recovery cases.
 pub fn try_direct_rewind_catch_up_materialized_state<S, C, N, P, I, M, FlushNodes, FlushIndex>(
     storage: &S,
     inserted_op_ids: &HashSet<OperationId>,
@@ -883,6 +907,10 @@ where
     FlushNodes: FnMut(&mut N) -> Result<()>,
     FlushIndex: FnMut(&mut I) -> Result<()>,
 {
+    // Rescues move nodes other than the op's own, which rewinding a single op cannot undo.
+    if stores.nodes.orphan_policy() == OrphanPolicy::LostAndFound {
+        return Ok(None);
+    }
     let state = meta.state();
     let Some(head) = state.head.as_ref() else {
         return Ok(None);
@@ -901,6 +929,8 @@ where
     let mut full_suffix_ops = Vec::new();
     let mut existing_suffix_ops = Vec::new();
     let mut requires_full_replay = false;
+    // Rewinding only restores LWW winners, not the concurrent values of multi-value payloads.
+    let multi_value = stores.payloads.mode() == PayloadMode::MultiValue;
     storage.scan_frontier_range(frontier, &mut |op| {
         // One pass does double duty:
         // - `full_suffix_ops` is the corrected suffix we will replay forward.
@@ -912,7 +942,8 @@ where
         {
             existing_suffix_ops.push(op.clone());
         }
-        requires_full_replay |= op_requires_full_replay(&op);
+        requires_full_replay |=
+            op_requires_full_replay(&op) || (multi_value && op_sets_payload(&op));
         full_suffix_ops.push(op);
         Ok(())
     })?;
@@ -1026,12 +1057,23 @@ where
 
         let deleted_at = rebuilt.crdt.node_store_mut().deleted_at(*node)?;
         nodes.set_deleted_at_exact(*node, deleted_at.as_ref())?;
+        let hard_deleted = rebuilt.crdt.node_store_mut().hard_deleted(*node)?;
+        nodes.set_hard_deleted(*node, hard_deleted)?;
+        let deleted_by = rebuilt.crdt.node_store_mut().deleted_by(*node)?;
+        nodes.set_deleted_by(*node, deleted_by)?;
 
         if let Some(writer) = rebuilt.crdt.payload_last_writer(*node)? {
             payloads.set_payload(*node, rebuilt.crdt.payload(*node)?, writer)?;
         } else {
             payloads.clear_payload(*node)?;
         }
+        payloads.clear_payload_fields(*node)?;
+        for entry in rebuilt.crdt.payload_field_entries(*node)? {
+            payloads.set_payload_field(*node, entry)?;
+        }
+        if payloads.mode() == PayloadMode::MultiValue {
+            payloads.set_payload_values(*node, rebuilt.crdt.payload_values(*node)?)?;
+        }
     }
 
     let mut records: Vec<_> = rebuilt
@@ -1089,8 +1131,13 @@ where
         mut index,
     } = stores;
 
-    let (mut rebuilt, prefix_seq, replay_outcome) =
-        replay_frontier_in_memory(&storage, frontier, &replica_id)?;
+    let (mut rebuilt, prefix_seq, replay_outcome) = replay_frontier_in_memory(
+        &storage,
+        frontier,
+        &replica_id,
+        payloads.mode(),
+        nodes.orphan_policy(),
+    )?;
     let mut affected_nodes = replay_outcome.affected_nodes();
     let mut seen_nodes: HashSet<NodeId> = affected_nodes.iter().copied().collect();
     let mut idx = 0usize;
@@ -1199,7 +1246,7 @@ where
 ///
 /// Adapters still own transactions, dedupe, and concrete backend stores. This helper just
 /// centralizes the repeated control flow around:
-/// - payload noop shortcut
+/// - payload noop shortcut (last-writer-wins payload stores only)
 /// - incremental materialization vs replay frontier scheduling
 /// - direct rewind fast path when the current batch introduced the frontier
 /// - conservative catch-up fallback
@@ -1218,6 +1265,7 @@ pub fn orchestrate_persisted_remote_append<
 >(
     meta: &M,
     inserted_ops: Vec<Operation>,
+    payload_mode: PayloadMode,
     mut load_last_writer: LoadWriter,
     mut materialize_inserted: MaterializeInserted,
     mut update_head: UpdateHead,
@@ -1254,11 +1302,15 @@ where
         inserted_ops.iter().map(|op| op.meta.id.clone()).collect();
     let had_pending_frontier = meta.state().replay_from.is_some();
 
-    let apply_result = if let Some(shortcut) = {
-        try_shortcut_out_of_order_payload_noops(meta, inserted_ops.clone(), |node| {
-            load_last_writer(node)
-        })?
-    } {
+    let shortcut = match payload_mode {
+        PayloadMode::LastWriterWins => {
+            try_shortcut_out_of_order_payload_noops(meta, inserted_ops.clone(), |node| {
+                load_last_writer(node)
+            })?
+        }
+        PayloadMode::MultiValue => None,
+    };
+    let apply_result = if let Some(shortcut) = shortcut {
         if shortcut.remaining_ops.is_empty() {
             update_head(&shortcut.resumed_head)?;
             PersistedRemoteApplyResult::applied(inserted_count, shortcut.outcome)
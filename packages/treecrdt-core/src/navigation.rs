@@ -0,0 +1,106 @@
+//! Read-only walks over the materialized tree, for breadcrumbs and subtree rendering.
+//!
+//! Only visible nodes take part: a node is visible when the path from it to [`NodeId::ROOT`], or
+//! to [`NodeId::LOST_AND_FOUND`] for rescued nodes, passes no tombstoned node. Both roots have
+//! depth 0 and no ancestors.
+
+use crate::error::Result;
+use crate::ids::NodeId;
+use crate::traits::NodeStore;
+
+fn is_root(node: NodeId) -> bool {
+    node == NodeId::ROOT || node == NodeId::LOST_AND_FOUND
+}
+
+/// The parents of `node`, nearest first and ending at its root; `None` if `node` is not visible.
+pub fn ancestors<N: NodeStore>(nodes: &N, node: NodeId) -> Result<Option<Vec<NodeId>>> {
+    let mut out = Vec::new();
+    let mut current = node;
+    while !is_root(current) {
+        if current == NodeId::TRASH || !nodes.exists(current)? || nodes.tombstone(current)? {
+            return Ok(None);
+        }
+        let Some(parent) = nodes.parent(current)? else {
+            return Ok(None);
+        };
+        if parent == node || out.contains(&parent) {
+            return Ok(None);
+        }
+        out.push(parent);
+        current = parent;
+    }
+    Ok(Some(out))
+}
+
+/// Position of `node` among its parent's visible children; `None` for roots and hidden nodes.
+pub fn index_in_parent<N: NodeStore>(nodes: &N, node: NodeId) -> Result<Option<usize>> {
+    let Some(parent) = ancestors(nodes, node)?.and_then(|ancestors| ancestors.first().copied())
+    else {
+        return Ok(None);
+    };
+    Ok(visible_children(nodes, parent)?.iter().position(|child| *child == node))
+}
+
+fn visible_children<N: NodeStore>(nodes: &N, parent: NodeId) -> Result<Vec<NodeId>> {
+    let mut out = Vec::new();
+    for child in nodes.children(parent)? {
+        if !nodes.tombstone(child)? {
+            out.push(child);
+        }
+    }
+    Ok(out)
+}
+
+/// Iterator returned by [`descendants`].
+pub struct Descendants<'a, N> {
+    nodes: &'a N,
+    max_depth: Option<usize>,
+    stack: Vec<(NodeId, usize)>,
+}
+
+/// The visible nodes below `root` in pre-order, each with its depth below `root` (its children
+/// are at 1), down to `max_depth` if given. Nothing if `root` is not visible.
+///
+/// Children are read from the store one node at a time as the walk reaches them.
+pub fn descendants<N: NodeStore>(
+    nodes: &N,
+    root: NodeId,
+    max_depth: Option<usize>,
+) -> Descendants<'_, N> {
+    Descendants {
+        nodes,
+        max_depth,
+        stack: vec![(root, 0)],
+    }
+}
+
+impl<N: NodeStore> Descendants<'_, N> {
+    fn expand(&mut self, node: NodeId, depth: usize) -> Result<()> {
+        if depth == 0 && ancestors(self.nodes, node)?.is_none() {
+            return Ok(());
+        }
+        if self.max_depth.is_some_and(|max_depth| depth >= max_depth) {
+            return Ok(());
+        }
+        let children = visible_children(self.nodes, node)?;
+        self.stack.extend(children.into_iter().rev().map(|child| (child, depth + 1)));
+        Ok(())
+    }
+}
+
+impl<N: NodeStore> Iterator for Descendants<'_, N> {
+    type Item = Result<(NodeId, usize)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (node, depth) = self.stack.pop()?;
+            if let Err(err) = self.expand(node, depth) {
+                self.stack.clear();
+                return Some(Err(err));
+            }
+            if depth > 0 {
+                return Some(Ok((node, depth)));
+            }
+        }
+    }
+}
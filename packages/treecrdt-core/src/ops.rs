@@ -14,6 +14,34 @@ pub struct OperationMetadata {
     pub lamport: Lamport,
     #[cfg_attr(feature = "serde", serde(default))]
     pub known_state: Option<VersionVector>,
+    /// Set when the op is one of several ops that peers should only show together.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub group: Option<OperationGroup>,
+}
+
+/// A run of consecutive ops of one replica that is applied all-or-nothing by peers that buffer
+/// groups (see [`crate::GroupBuffer`]).
+///
+/// Members are the ops of the writing replica with counters `first..first + len`. Every member
+/// carries the same group.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OperationGroup {
+    pub first: u64,
+    pub len: u32,
+}
+
+impl OperationGroup {
+    pub fn contains(&self, counter: u64) -> bool {
+        counter >= self.first && counter - self.first < u64::from(self.len)
+    }
+
+    pub fn counters(&self) -> std::ops::Range<u64> {
+        self.first..self.first.saturating_add(u64::from(self.len))
+    }
 }
 
 /// The CRDT tree mutations.
@@ -38,9 +66,22 @@ pub enum OperationKind {
     Delete {
         node: NodeId,
     },
+    /// Delete a node for good.
+    ///
+    /// Unlike `Delete`, concurrent changes in the subtree do not revive the node and a `Restore`
+    /// cannot bring it back. Meant for moderation and retention jobs that must remove content
+    /// regardless of what other replicas did meanwhile.
     Tombstone {
         node: NodeId,
     },
+    /// Bring a deleted node back where it was.
+    ///
+    /// The restore counts as a structural change of the node, so deletes that did not see it no
+    /// longer cover the node's subtree and stop hiding it (and any deleted ancestors it sits
+    /// under). A delete issued after seeing the restore hides it again.
+    Restore {
+        node: NodeId,
+    },
     /// Update the node payload (application data) as an opaque byte string.
     ///
     /// Merge semantics are last-writer-wins per node, ordered by
@@ -52,6 +93,18 @@ pub enum OperationKind {
         node: NodeId,
         payload: Option<Vec<u8>>,
     },
+    /// Set or clear one named field of the node's payload map.
+    ///
+    /// Fields merge independently of each other and of `Payload`: each `(node, field)` is its own
+    /// last-writer-wins register, so concurrent edits to different fields all survive.
+    ///
+    /// - `value = Some(bytes)` sets the field
+    /// - `value = None` clears the field
+    PayloadField {
+        node: NodeId,
+        field: String,
+        value: Option<Vec<u8>>,
+    },
 }
 
 /// Full operation envelope.
@@ -108,6 +161,7 @@ impl Operation {
                 id: OperationId::new(replica, counter),
                 lamport,
                 known_state: None,
+                group: None,
             },
             kind: OperationKind::Insert {
                 parent,
@@ -131,6 +185,7 @@ impl Operation {
                 id: OperationId::new(replica, counter),
                 lamport,
                 known_state: None,
+                group: None,
             },
             kind: OperationKind::Move {
                 node,
@@ -152,6 +207,7 @@ impl Operation {
                 id: OperationId::new(replica, counter),
                 lamport,
                 known_state,
+                group: None,
             },
             kind: OperationKind::Delete { node },
         }
@@ -163,11 +219,24 @@ impl Operation {
                 id: OperationId::new(replica, counter),
                 lamport,
                 known_state: None,
+                group: None,
             },
             kind: OperationKind::Tombstone { node },
         }
     }
 
+    pub fn restore(replica: &ReplicaId, counter: u64, lamport: Lamport, node: NodeId) -> Self {
+        Self {
+            meta: OperationMetadata {
+                id: OperationId::new(replica, counter),
+                lamport,
+                known_state: None,
+                group: None,
+            },
+            kind: OperationKind::Restore { node },
+        }
+    }
+
     pub fn payload(
         replica: &ReplicaId,
         counter: u64,
@@ -180,6 +249,7 @@ impl Operation {
                 id: OperationId::new(replica, counter),
                 lamport,
                 known_state: None,
+                group: None,
             },
             kind: OperationKind::Payload { node, payload },
         }
@@ -203,6 +273,35 @@ impl Operation {
     ) -> Self {
         Self::payload(replica, counter, lamport, node, None)
     }
+
+    pub fn payload_field(
+        replica: &ReplicaId,
+        counter: u64,
+        lamport: Lamport,
+        node: NodeId,
+        field: impl Into<String>,
+        value: Option<Vec<u8>>,
+    ) -> Self {
+        Self {
+            meta: OperationMetadata {
+                id: OperationId::new(replica, counter),
+                lamport,
+                known_state: None,
+                group: None,
+            },
+            kind: OperationKind::PayloadField {
+                node,
+                field: field.into(),
+                value,
+            },
+        }
+    }
+
+    /// Mark the op as a member of `group`.
+    pub fn with_group(mut self, group: OperationGroup) -> Self {
+        self.meta.group = Some(group);
+        self
+    }
 }
 
 impl OperationKind {
@@ -212,7 +311,9 @@ impl OperationKind {
             | OperationKind::Move { node, .. }
             | OperationKind::Delete { node }
             | OperationKind::Tombstone { node }
-            | OperationKind::Payload { node, .. } => *node,
+            | OperationKind::Restore { node }
+            | OperationKind::Payload { node, .. }
+            | OperationKind::PayloadField { node, .. } => *node,
         }
     }
 }
@@ -1,5 +1,8 @@
 use crate::error::{Error, Result};
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 const ORDER_KEY_DOMAIN: &[u8] = b"treecrdt/order_key/v0";
 const DIGIT_BYTES: usize = 2;
 const DEFAULT_BOUNDARY: u16 = 10;
@@ -67,17 +70,436 @@ fn choose_in_range(seed: &[u8], depth: usize, lo: u16, hi: u16) -> u16 {
 /// Keys are encoded as a variable-length sequence of big-endian `u16` digits, compared
 /// lexicographically. The generator is LSEQ-inspired: it prefers allocating within a bounded
 /// interval near one side to reduce expected key growth in repeated "insert between the same
-/// neighbors" workloads.
+/// neighbors" workloads. This is [`Lseq::default`], the allocator [`crate::TreeCrdt`] uses unless
+/// told otherwise.
 pub fn allocate_between(left: Option<&[u8]>, right: Option<&[u8]>, seed: &[u8]) -> Result<Vec<u8>> {
+    Lseq::default().allocate_between(left, right, seed)
+}
+
+/// Allocate `n` strictly increasing keys between `left` and `right`, spread evenly over the
+/// shortest digit length that has room for all of them.
+///
+/// Unlike `n` chained [`allocate_between`] calls, every key is about as long as a single one
+/// would be, and the gaps between them stay open for later inserts. `seed` shifts the whole run
+/// within its first slot so concurrent bulk inserts between the same neighbours interleave less.
+pub fn allocate_evenly_between(
+    left: Option<&[u8]>,
+    right: Option<&[u8]>,
+    n: usize,
+    seed: &[u8],
+) -> Result<Vec<Vec<u8>>> {
+    if n == 0 {
+        return Ok(Vec::new());
+    }
+    let left_digits = decode_digits(left.unwrap_or_default())?;
+    let right_digits = match right {
+        Some(right) => Some(decode_digits(right)?),
+        None => None,
+    };
+    if let (Some(left), Some(right)) = (left, right) {
+        if right <= left {
+            return Err(Error::InvalidOperation(
+                "cannot allocate order_key: right <= left".into(),
+            ));
+        }
+    }
+
+    // Grow the digit length until the keys of that length strictly between the bounds (the
+    // bounds truncated or zero-padded to it) number at least `n`. The difference grows by a
+    // factor of 2^16 per digit once it reaches 2, so it stays far from overflowing.
+    let wanted = n as u128 + 1;
+    let mut len = 0;
+    let mut diff: u128 = 0;
+    while diff < wanted {
+        if len > 0
+            && diff == 0
+            && len >= left_digits.len().max(right_digits.as_ref().map_or(0, Vec::len))
+        {
+            return Err(Error::InvalidOperation(
+                "cannot allocate order_key: no room between keys".into(),
+            ));
+        }
+        let ld = left_digits.get(len).copied().unwrap_or(0);
+        let rd = match &right_digits {
+            Some(right) => u32::from(right.get(len).copied().unwrap_or(0)),
+            None if len == 0 => 1 << 16,
+            None => 0,
+        };
+        diff = (diff << 16) + u128::from(rd) - u128::from(ld);
+        len += 1;
+    }
+
+    let mut base: Vec<u16> =
+        (0..len).map(|depth| left_digits.get(depth).copied().unwrap_or(0)).collect();
+    let step = diff / wanted;
+    let shift = u128::from(sample_u64(seed, 0)) % step;
+    add_to_digits(&mut base, step - shift);
+
+    let mut keys = Vec::with_capacity(n);
+    for _ in 0..n {
+        let mut key = base.clone();
+        // A trailing zero digit would leave no key between this one and its prefix.
+        if key.last() == Some(&0) {
+            key.push(1);
+        }
+        keys.push(encode_digits(&key));
+        add_to_digits(&mut base, step);
+    }
+    Ok(keys)
+}
+
+fn add_to_digits(digits: &mut [u16], mut value: u128) {
+    for digit in digits.iter_mut().rev() {
+        if value == 0 {
+            break;
+        }
+        let sum = u128::from(*digit) + (value & 0xffff);
+        *digit = sum as u16;
+        value = (value >> 16) + (sum >> 16);
+    }
+}
+
+/// Order key lengths, in bytes, of the visible children of one parent.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct OrderKeyStats {
+    pub children: u64,
+    pub max_len: u64,
+    pub total_len: u64,
+}
+
+impl OrderKeyStats {
+    pub fn from_keys<'a, I>(keys: I) -> Self
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let mut stats = Self::default();
+        for key in keys {
+            let len = key.len() as u64;
+            stats.children += 1;
+            stats.max_len = stats.max_len.max(len);
+            stats.total_len += len;
+        }
+        stats
+    }
+
+    pub fn avg_len(&self) -> f64 {
+        if self.children == 0 {
+            return 0.0;
+        }
+        self.total_len as f64 / self.children as f64
+    }
+}
+
+/// Strategy for picking the order key of a node placed between two siblings.
+///
+/// Every strategy emits keys in the `u16`-digit encoding described at [`allocate_between`], so
+/// replicas using different strategies can still order each other's keys. Ties between equal
+/// keys are broken by node id.
+pub trait OrderKeyAllocator {
+    /// Allocate a key strictly between `left` and `right`; `None` is the start or end of the
+    /// sibling list. `seed` is unique to the op being minted: the minting replica's id followed
+    /// by the op counter as 8 big-endian bytes.
+    fn allocate_between(
+        &self,
+        left: Option<&[u8]>,
+        right: Option<&[u8]>,
+        seed: &[u8],
+    ) -> Result<Vec<u8>>;
+
+    /// Allocate `n` strictly increasing keys between `left` and `right` for a run of new
+    /// siblings. Defaults to [`allocate_evenly_between`].
+    fn allocate_many_between(
+        &self,
+        left: Option<&[u8]>,
+        right: Option<&[u8]>,
+        n: usize,
+        seed: &[u8],
+    ) -> Result<Vec<Vec<u8>>> {
+        allocate_evenly_between(left, right, n, seed)
+    }
+}
+
+/// The default allocator: a random digit within `boundary` of a randomly chosen neighbour.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lseq {
+    /// Width of the window next to the chosen neighbour that new digits are drawn from.
+    pub boundary: u16,
+    /// Largest digit used where there is no right neighbour, i.e. the base minus one. Smaller
+    /// values leave less room for appends but keep the first digits of a level small.
+    pub max_digit: u16,
+}
+
+impl Default for Lseq {
+    fn default() -> Self {
+        Self {
+            boundary: DEFAULT_BOUNDARY,
+            max_digit: u16::MAX,
+        }
+    }
+}
+
+impl OrderKeyAllocator for Lseq {
+    fn allocate_between(
+        &self,
+        left: Option<&[u8]>,
+        right: Option<&[u8]>,
+        seed: &[u8],
+    ) -> Result<Vec<u8>> {
+        let boundary = self.boundary.max(1);
+        allocate_digits(left, right, self.max_digit, |depth, lo, hi, extending| {
+            let gap = hi - lo + 1;
+            if extending || gap <= boundary {
+                return choose_in_range(seed, depth, lo, hi);
+            }
+            if choose_side(seed, depth) {
+                choose_in_range(seed, depth, lo, lo + boundary - 1)
+            } else {
+                choose_in_range(seed, depth, hi - boundary + 1, hi)
+            }
+        })
+    }
+}
+
+/// Plain fractional indexing: always the midpoint of the gap.
+///
+/// Keys stay shortest for inserts spread across the middle of a list, but concurrent inserts at
+/// the same spot get the same key, and repeated appends run out of room after a handful of
+/// digits per level.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FractionalIndex;
+
+impl OrderKeyAllocator for FractionalIndex {
+    fn allocate_between(
+        &self,
+        left: Option<&[u8]>,
+        right: Option<&[u8]>,
+        _seed: &[u8],
+    ) -> Result<Vec<u8>> {
+        allocate_digits(left, right, u16::MAX, |_, lo, hi, _| lo + (hi - lo) / 2)
+    }
+}
+
+/// For lists that grow at the end, like chat logs: new digits always land within `boundary`
+/// of the left neighbour, so the rest of each level stays free for the next append on the right.
+///
+/// Inserting repeatedly before the same sibling grows keys by a digit each time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AppendOptimized {
+    pub boundary: u16,
+}
+
+impl Default for AppendOptimized {
+    fn default() -> Self {
+        Self {
+            boundary: DEFAULT_BOUNDARY,
+        }
+    }
+}
+
+impl OrderKeyAllocator for AppendOptimized {
+    fn allocate_between(
+        &self,
+        left: Option<&[u8]>,
+        right: Option<&[u8]>,
+        seed: &[u8],
+    ) -> Result<Vec<u8>> {
+        let boundary = self.boundary.max(1);
+        allocate_digits(left, right, u16::MAX, |depth, lo, hi, _| {
+            choose_in_range(seed, depth, lo, hi.min(lo.saturating_add(boundary - 1)))
+        })
+    }
+}
+
+// Digits of the anchored layout, see [`Anchored`].
+const ANCHOR_LEFT: u16 = 1;
+const ANCHOR_SELF: u16 = 2;
+const ANCHOR_RIGHT: u16 = 3;
+/// A waypoint opens with `[side, len]`, the minting replica's id in `len` bytes (zero-padded to
+/// whole digits) and the op counter in four digits; the index and `ANCHOR_SELF` follow. The
+/// length goes first so that no waypoint header is a prefix of another.
+const COUNTER_DIGITS: usize = 4;
+/// Index of a waypoint's first node, midway so a run can grow in either direction.
+const WAYPOINT_START: u16 = 0x8000;
+
+/// Ordering that keeps concurrently typed runs of siblings contiguous, in the style of Fugue
+/// and RGA.
+///
+/// The random digits of [`Lseq`] let two replicas that type a run each at the same spot
+/// interleave their siblings. Here every key records where it was anchored instead: a new node
+/// becomes a right child of its left neighbour (or a left child of its right neighbour when that
+/// one already hangs off the left), in a waypoint named after the minting replica and op. A
+/// replica that keeps typing next to its own node, after it or before it, only steps the index
+/// within that node's waypoint, so every run lives under its own waypoint prefix.
+///
+/// Waypoints spell out the whole replica id, so each level of anchoring costs the id's length
+/// plus 14 bytes; replica ids longer than `u16::MAX` bytes always fall back to [`Lseq`]. Since a
+/// run takes one level in either direction for its first 32768 nodes, a key only grows by a
+/// level when its node goes between two nodes that are not neighbours in one of the replica's
+/// own waypoints, e.g. into the middle of another replica's run. Its length is bounded by that
+/// many levels, not by how many nodes were typed.
+///
+/// The anchoring lives in the key itself, so stores keep ordering siblings by `order_key`.
+/// Keys other strategies made are kept as they are; a node placed next to one falls back to
+/// [`Lseq`] whenever the anchored key would not fit between the neighbours.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Anchored;
+
+impl Anchored {
+    fn waypoint(seed: &[u8], side: u16) -> Option<Vec<u16>> {
+        let (replica, counter) = seed.split_at(seed.len().saturating_sub(8));
+        let len = u16::try_from(replica.len()).ok()?;
+        let mut out = Vec::with_capacity(2 + replica.len().div_ceil(DIGIT_BYTES) + COUNTER_DIGITS);
+        out.extend_from_slice(&[side, len]);
+        for chunk in replica.chunks(DIGIT_BYTES) {
+            out.push(u16::from_be_bytes([
+                chunk[0],
+                chunk.get(1).copied().unwrap_or(0),
+            ]));
+        }
+        let counter = counter.iter().fold(0u64, |acc, b| acc << 8 | u64::from(*b));
+        for shift in [48, 32, 16, 0] {
+            out.push((counter >> shift) as u16);
+        }
+        Some(out)
+    }
+
+    /// `key` without its index and `ANCHOR_SELF`: the prefix its waypoint continues under.
+    fn waypoint_prefix(key: &[u16]) -> &[u16] {
+        match key {
+            [prefix @ .., _, ANCHOR_SELF] => prefix,
+            _ => key,
+        }
+    }
+
+    /// The key of a fresh waypoint on `side` of the node keyed `anchor`.
+    fn child_of(anchor: &[u16], side: u16, seed: &[u8]) -> Option<Vec<u16>> {
+        let stem = match anchor.split_last() {
+            Some((&ANCHOR_SELF, stem)) => stem,
+            _ => anchor,
+        };
+        let mut out = stem.to_vec();
+        out.extend_from_slice(&Self::waypoint(seed, side)?);
+        out.extend_from_slice(&[WAYPOINT_START, ANCHOR_SELF]);
+        Some(out)
+    }
+
+    /// The key after (`forward`) or before `key` in its waypoint, if `seed`'s replica opened
+    /// that waypoint.
+    fn continuation(key: &[u16], seed: &[u8], forward: bool) -> Option<Vec<u16>> {
+        let ours = Self::waypoint(seed, 0)?;
+        let n = key.len();
+        if n < ours.len() + 2 || key[n - 1] != ANCHOR_SELF {
+            return None;
+        }
+        let header = &key[n - 2 - ours.len()..n - 2];
+        let named = ours.len() - COUNTER_DIGITS;
+        if !matches!(header[0], ANCHOR_LEFT | ANCHOR_RIGHT) || header[1..named] != ours[1..named] {
+            return None;
+        }
+        let index = if forward {
+            key[n - 2].checked_add(1)?
+        } else {
+            key[n - 2].checked_sub(1)?
+        };
+        let mut out = key[..n - 2].to_vec();
+        out.extend_from_slice(&[index, ANCHOR_SELF]);
+        Some(out)
+    }
+}
+
+impl OrderKeyAllocator for Anchored {
+    fn allocate_between(
+        &self,
+        left: Option<&[u8]>,
+        right: Option<&[u8]>,
+        seed: &[u8],
+    ) -> Result<Vec<u8>> {
+        let left_digits = left.map(decode_digits).transpose()?;
+        let right_digits = right.map(decode_digits).transpose()?;
+        let fits = |key: &[u16]| {
+            left_digits.as_deref().is_none_or(|left| left < key)
+                && right_digits.as_deref().is_none_or(|right| key < right)
+        };
+
+        // Right after or before our own node: keep going in its waypoint.
+        if let Some(key) = left_digits
+            .as_deref()
+            .and_then(|left| Self::continuation(left, seed, true))
+            .filter(|key| fits(key))
+            .or_else(|| {
+                right_digits
+                    .as_deref()
+                    .and_then(|right| Self::continuation(right, seed, false))
+                    .filter(|key| fits(key))
+            })
+        {
+            return Ok(encode_digits(&key));
+        }
+        // A right child of the left neighbour, unless the right neighbour already hangs below
+        // it or continues its waypoint; then a left child of the right neighbour.
+        let candidate = match (&left_digits, &right_digits) {
+            (Some(left), Some(right)) if right.starts_with(Self::waypoint_prefix(left)) => {
+                Self::child_of(right, ANCHOR_LEFT, seed)
+            }
+            (Some(left), Some(right)) => Self::child_of(left, ANCHOR_RIGHT, seed)
+                .filter(|key| fits(key))
+                .or_else(|| Self::child_of(right, ANCHOR_LEFT, seed)),
+            (Some(left), None) => Self::child_of(left, ANCHOR_RIGHT, seed),
+            (None, Some(right)) => Self::child_of(right, ANCHOR_LEFT, seed),
+            (None, None) => Self::child_of(&[], ANCHOR_RIGHT, seed),
+        };
+        if let Some(candidate) = candidate.filter(|key| fits(key)) {
+            return Ok(encode_digits(&candidate));
+        }
+        Lseq::default().allocate_between(left, right, seed)
+    }
+
+    /// A run continues in one waypoint, like typing it node by node would.
+    fn allocate_many_between(
+        &self,
+        left: Option<&[u8]>,
+        right: Option<&[u8]>,
+        n: usize,
+        seed: &[u8],
+    ) -> Result<Vec<Vec<u8>>> {
+        let mut keys: Vec<Vec<u8>> = Vec::with_capacity(n);
+        for _ in 0..n {
+            let left = keys.last().map(Vec::as_slice).or(left);
+            let key = self.allocate_between(left, right, seed)?;
+            keys.push(key);
+        }
+        Ok(keys)
+    }
+}
+
+/// Walk `left` and `right` digit by digit to the first level with room for a new digit and let
+/// `pick(depth, lo, hi, extending)` choose it from `lo..=hi`. `extending` is set when the key
+/// continues `left` past its last digit rather than filling a gap between the two keys.
+fn allocate_digits<F>(
+    left: Option<&[u8]>,
+    right: Option<&[u8]>,
+    max_digit: u16,
+    mut pick: F,
+) -> Result<Vec<u8>>
+where
+    F: FnMut(usize, u16, u16, bool) -> u16,
+{
     let left_digits = decode_digits(left.unwrap_or_default())?;
     let right_digits = decode_digits(right.unwrap_or_default())?;
+    let max_digit = max_digit.max(2);
 
     let mut out: Vec<u16> = Vec::new();
     let mut depth: usize = 0;
 
     loop {
         let ld = left_digits.get(depth).copied().unwrap_or(0);
-        let rd = right_digits.get(depth).copied().unwrap_or(u16::MAX);
+        let rd = right_digits.get(depth).copied().unwrap_or(if ld < max_digit {
+            max_digit
+        } else {
+            u16::MAX
+        });
         if rd < ld {
             return Err(Error::InvalidOperation(
                 "cannot allocate order_key: right < left".into(),
@@ -85,21 +507,7 @@ pub fn allocate_between(left: Option<&[u8]>, right: Option<&[u8]>, seed: &[u8])
         }
 
         if u32::from(rd) > u32::from(ld) + 1 {
-            let gap = rd - ld - 1;
-            let boundary = DEFAULT_BOUNDARY.min(gap);
-            let choose_left = choose_side(seed, depth);
-
-            let (lo, hi) = if gap > boundary {
-                if choose_left {
-                    (ld + 1, ld + boundary)
-                } else {
-                    (rd - boundary, rd - 1)
-                }
-            } else {
-                (ld + 1, rd - 1)
-            };
-
-            out.push(choose_in_range(seed, depth, lo, hi));
+            out.push(pick(depth, ld + 1, rd - 1, false));
             break;
         }
 
@@ -107,7 +515,7 @@ pub fn allocate_between(left: Option<&[u8]>, right: Option<&[u8]>, seed: &[u8])
         // can be extended without comparing it against the right key's suffix.
         if rd > ld && depth < left_digits.len() {
             out.extend_from_slice(&left_digits[depth..]);
-            out.push(choose_in_range(seed, left_digits.len(), 1, u16::MAX));
+            out.push(pick(left_digits.len(), 1, max_digit, true));
             break;
         }
 
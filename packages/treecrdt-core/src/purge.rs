@@ -0,0 +1,158 @@
+//! Permanent removal of deleted subtrees.
+//!
+//! Defensive deletion keeps a deleted node's row around: a concurrent op the deleter had not
+//! seen revives it. Once the deletion is causally settled no such op can still arrive, and the
+//! subtree's node and payload state can be dropped for good.
+
+use std::collections::{BTreeSet, HashSet};
+
+use crate::error::Result;
+use crate::ids::NodeId;
+use crate::traits::{ExactPayloadStore, OrphanPolicy, PurgeableNodeStore};
+use crate::version_vector::VersionVector;
+
+/// What [`purge_stable_subtrees`] removed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PurgeReport {
+    /// Top-most deleted node of every purged subtree, in node-id order.
+    pub roots: Vec<NodeId>,
+    /// Every removed node row, including `roots`.
+    pub nodes: Vec<NodeId>,
+    /// Nodes whose payload or payload field winners were removed.
+    pub payloads: Vec<NodeId>,
+}
+
+impl PurgeReport {
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
+
+/// Drop every tombstoned subtree whose deletion is contained in `stable`.
+///
+/// `stable` must be settled, not just stable: besides every peer having observed the deletion,
+/// every op any peer minted before observing it must already be materialized here (see
+/// [`crate::StabilityTracker::settled_frontier`]). Otherwise a late concurrent op could still
+/// revive a node that no longer exists.
+///
+/// The purged subtree's causal history is folded into its parent's `last_change`, so defensive
+/// deletion of the surviving ancestors evaluates exactly as before. Ops are left untouched;
+/// a later full replay materializes the purged nodes again (still tombstoned).
+pub fn purge_stable_subtrees<N, P>(
+    nodes: &mut N,
+    payloads: &mut P,
+    stable: &VersionVector,
+) -> Result<PurgeReport>
+where
+    N: PurgeableNodeStore,
+    P: ExactPayloadStore,
+{
+    let keep_parent = nodes.orphan_policy() == OrphanPolicy::KeepParent;
+    let mut purgeable = BTreeSet::new();
+    for node in nodes.all_nodes()? {
+        if node == NodeId::ROOT || node == NodeId::TRASH {
+            continue;
+        }
+        let Some(deleted_at) = nodes.deleted_at(node)? else {
+            continue;
+        };
+        if !stable.is_aware_of(&deleted_at) {
+            continue;
+        }
+        let (_, structure_vv, subtree_vv) = subtree(nodes, payloads, node)?;
+        // A hard delete stays whatever the subtree saw; it only has to wait for the subtree's
+        // own ops to settle. Under `KeepParent` a delete must have seen the subtree's inserts
+        // and moves, and the payload writes it ignores must settle.
+        let covered = if nodes.hard_deleted(node)? {
+            stable.is_aware_of(&subtree_vv)
+        } else if keep_parent {
+            deleted_at.is_aware_of(&structure_vv) && stable.is_aware_of(&subtree_vv)
+        } else {
+            deleted_at.is_aware_of(&subtree_vv)
+        };
+        if covered {
+            purgeable.insert(node);
+        }
+    }
+
+    let mut report = PurgeReport::default();
+    for &root in &purgeable {
+        if has_ancestor_in(nodes, root, &purgeable)? {
+            continue;
+        }
+        let (members, structure_vv, subtree_vv) = subtree(nodes, payloads, root)?;
+        if let Some(parent) = nodes.parent(root)?.filter(|&p| p != NodeId::TRASH) {
+            // Only what the ancestors' deletes weigh, so they evaluate as before.
+            let folded = if keep_parent {
+                &structure_vv
+            } else {
+                &subtree_vv
+            };
+            nodes.merge_last_change(parent, folded)?;
+        }
+        for &node in &members {
+            if !payloads.current_writers(node)?.is_empty() {
+                payloads.clear_payload(node)?;
+                payloads.clear_payload_fields(node)?;
+                report.payloads.push(node);
+            }
+        }
+        for &node in members.iter().rev() {
+            nodes.remove_node(node)?;
+        }
+        report.roots.push(root);
+        report.nodes.extend(members);
+    }
+    Ok(report)
+}
+
+/// Nodes of the subtree under `root` (parents before children), the history of their inserts
+/// and moves, and their combined history including payload writes, as
+/// [`crate::TreeCrdt::subtree_version_vector`] computes it.
+fn subtree<N, P>(
+    nodes: &N,
+    payloads: &P,
+    root: NodeId,
+) -> Result<(Vec<NodeId>, VersionVector, VersionVector)>
+where
+    N: PurgeableNodeStore,
+    P: ExactPayloadStore,
+{
+    let mut members = Vec::new();
+    let mut structure = VersionVector::new();
+    let mut vv = VersionVector::new();
+    let mut pending = vec![root];
+    let mut visited = HashSet::new();
+    while let Some(current) = pending.pop() {
+        if !visited.insert(current) || !nodes.exists(current)? {
+            continue;
+        }
+        members.push(current);
+        structure.merge(&nodes.last_change(current)?);
+        for writer in payloads.current_writers(current)? {
+            vv.observe(&writer.replica, writer.counter);
+        }
+        pending.extend(nodes.children(current)?);
+    }
+    vv.merge(&structure);
+    Ok((members, structure, vv))
+}
+
+fn has_ancestor_in<N: PurgeableNodeStore>(
+    nodes: &N,
+    node: NodeId,
+    set: &BTreeSet<NodeId>,
+) -> Result<bool> {
+    let mut visited = HashSet::new();
+    let mut current = nodes.parent(node)?;
+    while let Some(parent) = current {
+        if set.contains(&parent) {
+            return Ok(true);
+        }
+        if parent == NodeId::ROOT || parent == NodeId::TRASH || !visited.insert(parent) {
+            return Ok(false);
+        }
+        current = nodes.parent(parent)?;
+    }
+    Ok(false)
+}
@@ -0,0 +1,101 @@
+//! Reordering siblings with as few moves as possible.
+//!
+//! Re-keying every child after a drag-and-drop or a sort bloats the op log. The children whose
+//! current order already agrees with the desired one (a longest increasing subsequence) can
+//! stay where they are; only the rest need a move, and each run of moved nodes lands between
+//! two children that stay.
+
+use std::collections::HashMap;
+
+use crate::error::{Error, Result};
+use crate::ids::NodeId;
+
+/// Nodes that move to sit, in this order, between two children that keep their keys.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReorderRun {
+    /// The staying child right before the run; `None` at the start.
+    pub after: Option<NodeId>,
+    /// The staying child right after the run; `None` at the end.
+    pub before: Option<NodeId>,
+    pub nodes: Vec<NodeId>,
+}
+
+/// Plan the moves that turn the sibling order `current` into `desired`, which must list the
+/// same nodes. Moves as few nodes as possible; an unchanged order yields no runs.
+pub fn plan_reorder(current: &[NodeId], desired: &[NodeId]) -> Result<Vec<ReorderRun>> {
+    let position: HashMap<NodeId, usize> =
+        current.iter().enumerate().map(|(idx, node)| (*node, idx)).collect();
+    let mut seen = vec![false; current.len()];
+    let mut positions = Vec::with_capacity(desired.len());
+    for node in desired {
+        match position.get(node) {
+            Some(&idx) if !seen[idx] => {
+                seen[idx] = true;
+                positions.push(idx);
+            }
+            _ => {
+                return Err(Error::InvalidOperation(
+                    "desired order must list every visible child exactly once".into(),
+                ))
+            }
+        }
+    }
+    if positions.len() != current.len() {
+        return Err(Error::InvalidOperation(
+            "desired order must list every visible child exactly once".into(),
+        ));
+    }
+
+    let stays = longest_increasing(&positions);
+    let mut runs = Vec::new();
+    let mut after = None;
+    let mut pending = Vec::new();
+    for (idx, node) in desired.iter().enumerate() {
+        if !stays[idx] {
+            pending.push(*node);
+            continue;
+        }
+        if !pending.is_empty() {
+            runs.push(ReorderRun {
+                after,
+                before: Some(*node),
+                nodes: std::mem::take(&mut pending),
+            });
+        }
+        after = Some(*node);
+    }
+    if !pending.is_empty() {
+        runs.push(ReorderRun {
+            after,
+            before: None,
+            nodes: pending,
+        });
+    }
+    Ok(runs)
+}
+
+/// Marks one longest strictly increasing subsequence of `values` (patience sorting).
+fn longest_increasing(values: &[usize]) -> Vec<bool> {
+    // `tails[len]` is the index of the smallest value ending an increasing run of `len + 1`.
+    let mut tails: Vec<usize> = Vec::new();
+    let mut prev: Vec<Option<usize>> = vec![None; values.len()];
+    for (idx, value) in values.iter().enumerate() {
+        let len = tails.partition_point(|&tail| values[tail] < *value);
+        if len > 0 {
+            prev[idx] = Some(tails[len - 1]);
+        }
+        if len == tails.len() {
+            tails.push(idx);
+        } else {
+            tails[len] = idx;
+        }
+    }
+
+    let mut stays = vec![false; values.len()];
+    let mut cursor = tails.last().copied();
+    while let Some(idx) = cursor {
+        stays[idx] = true;
+        cursor = prev[idx];
+    }
+    stays
+}
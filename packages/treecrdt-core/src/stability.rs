@@ -0,0 +1,142 @@
+//! Causal stability tracking.
+//!
+//! An op is causally stable once every replica taking part in a document has observed it: no
+//! replica can still produce an op concurrent with it, so history up to that point can be folded
+//! into a checkpoint ([`crate::TreeCrdt::compact`]) or garbage-collected.
+
+use std::collections::HashMap;
+
+use crate::ids::ReplicaId;
+use crate::ops::Operation;
+use crate::version_vector::VersionVector;
+
+/// Per-peer acknowledged version vectors and their greatest lower bound.
+///
+/// The local replica is a peer like any other and should acknowledge its own version vector;
+/// otherwise ops it has not received yet could be considered stable.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StabilityTracker {
+    acks: HashMap<ReplicaId, VersionVector>,
+}
+
+impl StabilityTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start tracking `peer` before it has acknowledged anything.
+    ///
+    /// Until it does, the stable frontier is empty: a newly joined replica holds back
+    /// garbage collection rather than being ignored.
+    pub fn add_peer(&mut self, peer: ReplicaId) {
+        self.acks.entry(peer).or_default();
+    }
+
+    /// Stop tracking `peer` (e.g. it left the document for good). Returns its last ack.
+    pub fn remove_peer(&mut self, peer: &ReplicaId) -> Option<VersionVector> {
+        self.acks.remove(peer)
+    }
+
+    /// Record that `peer` has observed everything in `acked`.
+    ///
+    /// Acks only ever grow; a stale or reordered ack cannot move the frontier backwards.
+    pub fn acknowledge(&mut self, peer: &ReplicaId, acked: &VersionVector) {
+        self.acks.entry(peer.clone()).or_default().merge(acked);
+    }
+
+    pub fn acked(&self, peer: &ReplicaId) -> Option<&VersionVector> {
+        self.acks.get(peer)
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = (&ReplicaId, &VersionVector)> {
+        self.acks.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.acks.is_empty()
+    }
+
+    /// Greatest lower bound of all acknowledged version vectors.
+    ///
+    /// Empty when no peers are tracked, so an unconfigured tracker never declares anything stable.
+    pub fn stable_frontier(&self) -> VersionVector {
+        let mut acks = self.acks.values();
+        let Some(first) = acks.next() else {
+            return VersionVector::new();
+        };
+        acks.fold(first.clone(), |frontier, ack| frontier.intersection(ack))
+    }
+
+    /// The stable frontier, provided `local` has already seen everything any peer acknowledged;
+    /// empty otherwise.
+    ///
+    /// Purging deleted subtrees needs this stronger bound. An op a peer minted before it saw a
+    /// deletion is part of that peer's ack, and could still revive the subtree once it reaches
+    /// us, even though the deletion itself is stable.
+    pub fn settled_frontier(&self, local: &VersionVector) -> VersionVector {
+        if self.acks.values().all(|ack| local.is_aware_of(ack)) {
+            self.stable_frontier()
+        } else {
+            VersionVector::new()
+        }
+    }
+
+    /// Whether everything in `vv` has been observed by every tracked peer.
+    pub fn is_stable(&self, vv: &VersionVector) -> bool {
+        !self.acks.is_empty() && self.acks.values().all(|ack| ack.is_aware_of(vv))
+    }
+
+    pub fn is_op_stable(&self, op: &Operation) -> bool {
+        !self.acks.is_empty()
+            && self
+                .acks
+                .values()
+                .all(|ack| ack.contains(&op.meta.id.replica, op.meta.id.counter))
+    }
+}
+
+#[cfg(feature = "serde")]
+mod serde_impl {
+    use super::StabilityTracker;
+    use crate::ids::ReplicaId;
+    use crate::version_vector::VersionVector;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    // Replica ids are byte strings, which JSON cannot use as map keys; encode as a sorted list.
+    #[derive(Serialize, Deserialize)]
+    struct PeerAck {
+        peer: Vec<u8>,
+        acked: VersionVector,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct StabilityRepr {
+        peers: Vec<PeerAck>,
+    }
+
+    impl Serialize for StabilityTracker {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut peers: Vec<PeerAck> = self
+                .acks
+                .iter()
+                .map(|(peer, acked)| PeerAck {
+                    peer: peer.0.clone(),
+                    acked: acked.clone(),
+                })
+                .collect();
+            peers.sort_by(|a, b| a.peer.cmp(&b.peer));
+            StabilityRepr { peers }.serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for StabilityTracker {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let repr = StabilityRepr::deserialize(deserializer)?;
+            let mut tracker = StabilityTracker::new();
+            for PeerAck { peer, acked } in repr.peers {
+                tracker.acknowledge(&ReplicaId(peer), &acked);
+            }
+            Ok(tracker)
+        }
+    }
+}
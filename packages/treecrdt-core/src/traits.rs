@@ -1,22 +1,74 @@
 use std::cmp::Ordering;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::Arc;
 
 use crate::error::{Error, Result};
 use crate::ids::{Lamport, NodeId, OperationId, ReplicaId};
+use crate::materialization::{cmp_frontiers, frontier_from_op, MaterializationFrontier};
 use crate::ops::{cmp_ops, Operation};
 use crate::version_vector::VersionVector;
 
 /// Pluggable clock to allow Lamport, Hybrid Logical Clock, or custom time strategies.
 pub trait Clock {
-    fn tick(&mut self) -> Lamport;
-    fn observe(&mut self, external: Lamport);
+    /// Mint the next timestamp. Fails only when the clock cannot move forward any more.
+    fn tick(&mut self) -> Result<Lamport>;
+    /// Advance past a timestamp that is already part of the local log (startup, replay).
+    fn merge(&mut self, external: Lamport);
+    /// Check the timestamp of a remote op before it is stored. Clocks may reject implausible
+    /// values (see [`crate::hlc::HybridLogicalClock`]), in which case the op is not applied.
+    fn admit(&self, _external: Lamport) -> Result<()> {
+        Ok(())
+    }
+    /// [`Self::admit`] a remote op's timestamp and advance past it.
+    fn observe(&mut self, external: Lamport) -> Result<()> {
+        self.admit(external)?;
+        self.merge(external);
+        Ok(())
+    }
     fn now(&self) -> Lamport;
 }
 
 /// Access control hook that can deny operations or reads.
+///
+/// Implementations should return [`Error::AccessDenied`] when a check fails so callers can tell
+/// policy rejections apart from storage or validation errors.
+///
+/// `nodes` is the materialized tree the op is about to be applied to, so policies can resolve
+/// ancestry (see [`crate::access::SubtreePolicy`]).
 pub trait AccessControl {
-    fn can_apply(&self, op: &Operation) -> Result<()>;
-    fn can_read(&self, node: NodeId) -> Result<()>;
+    fn can_apply(&self, nodes: &dyn NodeStore, op: &Operation) -> Result<()>;
+    fn can_read(&self, nodes: &dyn NodeStore, node: NodeId) -> Result<()>;
+}
+
+impl<T: AccessControl + ?Sized> AccessControl for Box<T> {
+    fn can_apply(&self, nodes: &dyn NodeStore, op: &Operation) -> Result<()> {
+        (**self).can_apply(nodes, op)
+    }
+
+    fn can_read(&self, nodes: &dyn NodeStore, node: NodeId) -> Result<()> {
+        (**self).can_read(nodes, node)
+    }
+}
+
+impl<T: AccessControl + ?Sized> AccessControl for Rc<T> {
+    fn can_apply(&self, nodes: &dyn NodeStore, op: &Operation) -> Result<()> {
+        (**self).can_apply(nodes, op)
+    }
+
+    fn can_read(&self, nodes: &dyn NodeStore, node: NodeId) -> Result<()> {
+        (**self).can_read(nodes, node)
+    }
+}
+
+impl<T: AccessControl + ?Sized> AccessControl for Arc<T> {
+    fn can_apply(&self, nodes: &dyn NodeStore, op: &Operation) -> Result<()> {
+        (**self).can_apply(nodes, op)
+    }
+
+    fn can_read(&self, nodes: &dyn NodeStore, node: NodeId) -> Result<()> {
+        (**self).can_read(nodes, node)
+    }
 }
 
 /// Persistent or in-memory operation log.
@@ -58,6 +110,23 @@ pub trait Storage {
     }
 }
 
+/// Op log that can drop a prefix once it has been folded into a [`crate::Checkpoint`].
+///
+/// Only [`MemoryStorage`] implements it. The sqlite and postgres adapters keep their whole op
+/// log: checkpoints taken over them are snapshots and never discard ops.
+pub trait CompactableStorage: Storage {
+    /// Permanently discard every op whose canonical key is `<= through`.
+    ///
+    /// Callers only pass keys at or below a settled frontier (see [`crate::TreeCrdt::compact`]),
+    /// so no op that sorts at or before `through` can still be unseen. Backends should keep
+    /// treating discarded ops as present: re-delivering one must make `apply` return `false`,
+    /// and `latest_lamport` / `latest_counter` must not regress. An op that was never seen but
+    /// sorts at or before `through` cannot be materialized any more; `apply` must reject it
+    /// with an error rather than drop it.
+    /// Returns the number of ops removed.
+    fn discard_through(&mut self, through: &MaterializationFrontier) -> Result<u64>;
+}
+
 /// Storage adapter used when operations are already provided by the caller.
 ///
 /// This is useful for incremental materialization pipelines that need core semantics
@@ -85,6 +154,25 @@ pub trait IndexProvider {
     fn exists(&self, node: NodeId) -> bool;
 }
 
+/// What a delete does with nodes that concurrent inserts or moves placed under the deleted node.
+///
+/// Tombstones are permanent under every policy: their whole subtree goes with them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OrphanPolicy {
+    /// A delete only takes effect once it has seen every change in the subtree, payload writes
+    /// included; until then the deleted node stays visible.
+    #[default]
+    Defensive,
+    /// A deleted node stays alive while its delete has not seen every insert and move in its
+    /// subtree, so nodes placed under it concurrently keep their parent. Unlike under
+    /// `Defensive`, unseen payload writes do not keep it alive.
+    KeepParent,
+    /// Deletes take effect regardless; the topmost nodes of the deleted subtree that carry changes
+    /// the delete did not see are moved under [`NodeId::LOST_AND_FOUND`], as are nodes later
+    /// inserted or moved under a deleted node.
+    LostAndFound,
+}
+
 /// Storage for materialized node state (parent/children ordering + causal metadata).
 ///
 /// This is the seam used by SQLite/wa-sqlite adapters so the core CRDT owns all tree logic
@@ -101,7 +189,8 @@ pub trait NodeStore {
     fn detach(&mut self, node: NodeId) -> Result<()>;
     fn attach(&mut self, node: NodeId, parent: NodeId, order_key: Vec<u8>) -> Result<()>;
 
-    /// Cached tombstone flag for fast queries (derived from `deleted_at` and subtree awareness).
+    /// Cached tombstone flag for fast queries (derived from `deleted_at` and subtree awareness, or
+    /// `hard_deleted`).
     ///
     /// Adapters should treat this as derived state: core helpers can refresh it, and callers may
     /// rely on it for efficient `children()` queries without recomputing awareness recursively.
@@ -118,6 +207,21 @@ pub trait NodeStore {
     fn deleted_at(&self, node: NodeId) -> Result<Option<VersionVector>>;
     fn merge_deleted_at(&mut self, node: NodeId, delta: &VersionVector) -> Result<()>;
 
+    /// Whether a [`crate::OperationKind::Tombstone`] removed this node for good. Unlike
+    /// `deleted_at`, this ignores what happened in the subtree: nothing revives the node.
+    fn hard_deleted(&self, node: NodeId) -> Result<bool>;
+    fn set_hard_deleted(&mut self, node: NodeId, hard_deleted: bool) -> Result<()>;
+
+    /// The newest delete or tombstone op applied to this node, for trash listings.
+    fn deleted_by(&self, node: NodeId) -> Result<Option<(Lamport, OperationId)>>;
+    fn set_deleted_by(&mut self, node: NodeId, op: Option<(Lamport, OperationId)>) -> Result<()>;
+
+    /// The document's [`OrphanPolicy`]. Materialized state depends on it, so stores that let it
+    /// change have to rebuild that state.
+    fn orphan_policy(&self) -> OrphanPolicy {
+        OrphanPolicy::Defensive
+    }
+
     fn has_deleted_at(&self, node: NodeId) -> Result<bool> {
         Ok(self.deleted_at(node)?.is_some())
     }
@@ -132,12 +236,61 @@ pub trait NodeStore {
     fn all_nodes(&self) -> Result<Vec<NodeId>>;
 }
 
+/// Current LWW winner of one field of a node's payload map.
+///
+/// Cleared fields keep their entry with `value: None`, so older writes stay dominated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PayloadFieldEntry {
+    pub field: String,
+    pub value: Option<Vec<u8>>,
+    pub lamport: Lamport,
+    pub writer: OperationId,
+}
+
+/// How a [`PayloadStore`] settles concurrent writes to a node's whole payload.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PayloadMode {
+    /// Keep only the write with the greatest `(lamport, replica, counter)`.
+    #[default]
+    LastWriterWins,
+    /// Additionally keep every write that no other write has seen, so concurrent edits can be
+    /// shown as a conflict instead of silently losing one of them. See [`PayloadValue`].
+    MultiValue,
+}
+
+/// One value of a node's multi-value payload register.
+///
+/// `known_state` holds the payload writes the author had seen, transitively, when it wrote this
+/// value (the op's `known_state`; empty for inserts and last-writer-wins writes). A value is
+/// dropped once a write whose `known_state` contains it arrives.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PayloadValue {
+    pub payload: Option<Vec<u8>>,
+    pub lamport: Lamport,
+    pub writer: OperationId,
+    pub known_state: VersionVector,
+}
+
+impl PayloadValue {
+    /// Whether this value was written by, or with knowledge of, the op `id`.
+    pub fn has_seen(&self, id: &OperationId) -> bool {
+        self.writer == *id || self.known_state.contains(&id.replica, id.counter)
+    }
+}
+
 /// Storage for last-writer-wins node payloads.
 ///
 /// Payloads are application-defined opaque bytes. Merge semantics are last-writer-wins per node,
 /// ordered by `(lamport, replica, counter)`. This trait allows embedders (SQLite, wasm, etc) to
 /// persist payload state without re-implementing CRDT ordering rules.
+///
+/// Besides the whole-node payload, a node can carry a payload map written by
+/// [`crate::OperationKind::PayloadField`] ops, with one LWW winner per field.
+///
+/// Stores that report [`PayloadMode::MultiValue`] also keep the concurrent values of each node's
+/// payload next to the LWW winner, which stays the value `payload` returns.
 pub trait PayloadStore {
+    /// Drop every payload, payload field and concurrent payload value.
     fn reset(&mut self) -> Result<()>;
     fn payload(&self, node: NodeId) -> Result<Option<Vec<u8>>>;
     fn last_writer(&self, node: NodeId) -> Result<Option<(Lamport, OperationId)>>;
@@ -147,15 +300,48 @@ pub trait PayloadStore {
         payload: Option<Vec<u8>>,
         writer: (Lamport, OperationId),
     ) -> Result<()>;
+
+    fn payload_field(&self, node: NodeId, field: &str) -> Result<Option<PayloadFieldEntry>>;
+    /// Every field entry of `node`, cleared ones included, in field name order.
+    fn payload_fields(&self, node: NodeId) -> Result<Vec<PayloadFieldEntry>>;
+    /// Store `entry` as the winner of its field, replacing the previous one.
+    fn set_payload_field(&mut self, node: NodeId, entry: PayloadFieldEntry) -> Result<()>;
+
+    fn mode(&self) -> PayloadMode {
+        PayloadMode::LastWriterWins
+    }
+
+    /// The concurrent payload values of `node`, in op key order. Only maintained in
+    /// [`PayloadMode::MultiValue`].
+    fn payload_values(&self, _node: NodeId) -> Result<Vec<PayloadValue>> {
+        Ok(Vec::new())
+    }
+
+    /// Replace the concurrent payload values of `node`; an empty list drops them.
+    fn set_payload_values(&mut self, _node: NodeId, _values: Vec<PayloadValue>) -> Result<()> {
+        Err(Error::InvalidOperation(
+            "payload store does not keep concurrent values".into(),
+        ))
+    }
+
+    /// The ops whose writes currently make up the payload and payload map of `node`.
+    fn current_writers(&self, node: NodeId) -> Result<Vec<OperationId>> {
+        let mut writers: Vec<OperationId> =
+            self.last_writer(node)?.map(|(_, id)| id).into_iter().collect();
+        writers.extend(self.payload_fields(node)?.into_iter().map(|entry| entry.writer));
+        Ok(writers)
+    }
 }
 
 pub trait ExactPayloadStore: PayloadStore {
-    /// Remove any current payload winner for `node`.
+    /// Remove any current payload winner for `node`, along with its concurrent values.
     ///
     /// `PayloadStore::set_payload(...)` is a forward-materialization API: callers provide the new
     /// winner tuple explicitly. Direct rewind/catch-up also needs an exact "no winner exists"
     /// operation when rolling a payload-bearing suffix back to the pre-suffix state.
     fn clear_payload(&mut self, node: NodeId) -> Result<()>;
+    /// Remove every field entry of `node`, for the same exact-state patching.
+    fn clear_payload_fields(&mut self, node: NodeId) -> Result<()>;
 }
 
 /// Persistent index of operations relevant to a `children(parent)` filter.
@@ -205,6 +391,14 @@ pub trait ExactNodeStore: NodeStore {
     fn set_deleted_at_exact(&mut self, node: NodeId, vv: Option<&VersionVector>) -> Result<()>;
 }
 
+/// Node store that can forget nodes entirely (see [`crate::purge::purge_stable_subtrees`]).
+pub trait PurgeableNodeStore: NodeStore {
+    /// Delete `node`'s row, detaching it from its parent first.
+    ///
+    /// Only the node itself is removed; callers remove descendants explicitly.
+    fn remove_node(&mut self, node: NodeId) -> Result<()>;
+}
+
 /// Basic Lamport clock implementation useful for tests and default flows.
 #[derive(Clone, Debug, Default)]
 pub struct LamportClock {
@@ -212,12 +406,15 @@ pub struct LamportClock {
 }
 
 impl Clock for LamportClock {
-    fn tick(&mut self) -> Lamport {
-        self.counter += 1;
-        self.counter
+    fn tick(&mut self) -> Result<Lamport> {
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| Error::InvalidOperation("lamport clock exhausted".into()))?;
+        Ok(self.counter)
     }
 
-    fn observe(&mut self, external: Lamport) {
+    fn merge(&mut self, external: Lamport) {
         self.counter = self.counter.max(external);
     }
 
@@ -226,15 +423,16 @@ impl Clock for LamportClock {
     }
 }
 
-/// Allows unrestricted access; helpful for early prototyping.
+/// Allows unrestricted access. This is the default policy for [`crate::TreeCrdt`].
+#[derive(Clone, Copy, Debug, Default)]
 pub struct AllowAllAccess;
 
 impl AccessControl for AllowAllAccess {
-    fn can_apply(&self, _op: &Operation) -> Result<()> {
+    fn can_apply(&self, _nodes: &dyn NodeStore, _op: &Operation) -> Result<()> {
         Ok(())
     }
 
-    fn can_read(&self, _node: NodeId) -> Result<()> {
+    fn can_read(&self, _nodes: &dyn NodeStore, _node: NodeId) -> Result<()> {
         Ok(())
     }
 }
@@ -244,13 +442,31 @@ impl AccessControl for AllowAllAccess {
 pub struct MemoryStorage {
     ops: Vec<Operation>,
     ids: HashSet<OperationId>,
+    compacted_through: Option<MaterializationFrontier>,
+    compacted: VersionVector,
+}
+
+impl MemoryStorage {
+    fn is_compacted(&self, op: &Operation) -> bool {
+        self.compacted_through.as_ref().is_some_and(|through| {
+            cmp_frontiers(&frontier_from_op(op), through) != Ordering::Greater
+        })
+    }
 }
 
 impl Storage for MemoryStorage {
     fn apply(&mut self, op: Operation) -> Result<bool> {
-        if self.ids.contains(&op.meta.id) {
+        if self.ids.contains(&op.meta.id)
+            || self.compacted.contains(&op.meta.id.replica, op.meta.id.counter)
+        {
             return Ok(false);
         }
+        if self.is_compacted(&op) {
+            return Err(Error::InconsistentState(format!(
+                "op {:?} sorts inside the compacted prefix but was never folded into it",
+                op.meta.id
+            )));
+        }
         self.ids.insert(op.meta.id.clone());
         self.ops.push(op);
         Ok(true)
@@ -261,7 +477,13 @@ impl Storage for MemoryStorage {
     }
 
     fn latest_lamport(&self) -> Lamport {
-        self.ops.iter().map(|op| op.meta.lamport).max().unwrap_or_default()
+        let compacted = self.compacted_through.as_ref().map_or(0, |through| through.lamport);
+        self.ops
+            .iter()
+            .map(|op| op.meta.lamport)
+            .max()
+            .unwrap_or_default()
+            .max(compacted)
     }
 
     fn latest_counter(&self, replica: &ReplicaId) -> Result<u64> {
@@ -271,7 +493,32 @@ impl Storage for MemoryStorage {
             .filter(|op| &op.meta.id.replica == replica)
             .map(|op| op.meta.id.counter)
             .max()
-            .unwrap_or(0))
+            .unwrap_or(0)
+            .max(self.compacted.get(replica)))
+    }
+}
+
+impl CompactableStorage for MemoryStorage {
+    fn discard_through(&mut self, through: &MaterializationFrontier) -> Result<u64> {
+        let before = self.ops.len();
+        let mut kept = Vec::with_capacity(before);
+        for op in std::mem::take(&mut self.ops) {
+            if cmp_frontiers(&frontier_from_op(&op), through) == Ordering::Greater {
+                kept.push(op);
+            } else {
+                self.ids.remove(&op.meta.id);
+                self.compacted.observe(&op.meta.id.replica, op.meta.id.counter);
+            }
+        }
+        self.ops = kept;
+        let advances = match &self.compacted_through {
+            Some(current) => cmp_frontiers(through, current) == Ordering::Greater,
+            None => true,
+        };
+        if advances {
+            self.compacted_through = Some(through.clone());
+        }
+        Ok((before - self.ops.len()) as u64)
     }
 }
 
@@ -289,14 +536,28 @@ impl IndexProvider for MemoryStorage {
             crate::ops::OperationKind::Move { node: n, .. } => n == node,
             crate::ops::OperationKind::Delete { node: n } => n == node,
             crate::ops::OperationKind::Tombstone { node: n } => n == node,
+            crate::ops::OperationKind::Restore { node: n } => n == node,
             crate::ops::OperationKind::Payload { node: n, .. } => n == node,
+            crate::ops::OperationKind::PayloadField { node: n, .. } => n == node,
         })
     }
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct MemoryPayloadStore {
+    mode: PayloadMode,
     entries: HashMap<NodeId, MemoryPayloadEntry>,
+    fields: HashMap<NodeId, BTreeMap<String, PayloadFieldEntry>>,
+    values: HashMap<NodeId, Vec<PayloadValue>>,
+}
+
+impl MemoryPayloadStore {
+    pub fn with_mode(mode: PayloadMode) -> Self {
+        Self {
+            mode,
+            ..Self::default()
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -308,6 +569,8 @@ struct MemoryPayloadEntry {
 impl PayloadStore for MemoryPayloadStore {
     fn reset(&mut self) -> Result<()> {
         self.entries.clear();
+        self.fields.clear();
+        self.values.clear();
         Ok(())
     }
 
@@ -330,11 +593,56 @@ impl PayloadStore for MemoryPayloadStore {
         entry.last_writer = Some(writer);
         Ok(())
     }
+
+    fn payload_field(&self, node: NodeId, field: &str) -> Result<Option<PayloadFieldEntry>> {
+        Ok(self.fields.get(&node).and_then(|fields| fields.get(field)).cloned())
+    }
+
+    fn payload_fields(&self, node: NodeId) -> Result<Vec<PayloadFieldEntry>> {
+        Ok(self
+            .fields
+            .get(&node)
+            .map(|fields| fields.values().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    fn set_payload_field(&mut self, node: NodeId, entry: PayloadFieldEntry) -> Result<()> {
+        self.fields.entry(node).or_default().insert(entry.field.clone(), entry);
+        Ok(())
+    }
+
+    fn mode(&self) -> PayloadMode {
+        self.mode
+    }
+
+    fn payload_values(&self, node: NodeId) -> Result<Vec<PayloadValue>> {
+        Ok(self.values.get(&node).cloned().unwrap_or_default())
+    }
+
+    fn set_payload_values(&mut self, node: NodeId, values: Vec<PayloadValue>) -> Result<()> {
+        if self.mode != PayloadMode::MultiValue {
+            return Err(Error::InvalidOperation(
+                "payload store does not keep concurrent values".into(),
+            ));
+        }
+        if values.is_empty() {
+            self.values.remove(&node);
+        } else {
+            self.values.insert(node, values);
+        }
+        Ok(())
+    }
 }
 
 impl ExactPayloadStore for MemoryPayloadStore {
     fn clear_payload(&mut self, node: NodeId) -> Result<()> {
         self.entries.remove(&node);
+        self.values.remove(&node);
+        Ok(())
+    }
+
+    fn clear_payload_fields(&mut self, node: NodeId) -> Result<()> {
+        self.fields.remove(&node);
         Ok(())
     }
 }
@@ -347,6 +655,8 @@ struct MemoryNodeState {
     tombstone: bool,
     last_change: VersionVector,
     deleted_at: Option<VersionVector>,
+    hard_deleted: bool,
+    deleted_by: Option<(Lamport, OperationId)>,
 }
 
 impl MemoryNodeState {
@@ -358,6 +668,8 @@ impl MemoryNodeState {
             tombstone: false,
             last_change: VersionVector::new(),
             deleted_at: None,
+            hard_deleted: false,
+            deleted_by: None,
         }
     }
 
@@ -369,6 +681,8 @@ impl MemoryNodeState {
             tombstone: false,
             last_change: VersionVector::new(),
             deleted_at: None,
+            hard_deleted: false,
+            deleted_by: None,
         }
     }
 }
@@ -377,17 +691,28 @@ impl MemoryNodeState {
 #[derive(Clone, Debug)]
 pub struct MemoryNodeStore {
     nodes: HashMap<NodeId, MemoryNodeState>,
+    orphan_policy: OrphanPolicy,
 }
 
 impl Default for MemoryNodeStore {
     fn default() -> Self {
         let mut nodes = HashMap::new();
         nodes.insert(NodeId::ROOT, MemoryNodeState::new_root());
-        Self { nodes }
+        Self {
+            nodes,
+            orphan_policy: OrphanPolicy::default(),
+        }
     }
 }
 
 impl MemoryNodeStore {
+    pub fn with_orphan_policy(orphan_policy: OrphanPolicy) -> Self {
+        Self {
+            orphan_policy,
+            ..Self::default()
+        }
+    }
+
     fn get_state(&self, node: NodeId) -> Result<&MemoryNodeState> {
         self.nodes
             .get(&node)
@@ -510,6 +835,28 @@ impl NodeStore for MemoryNodeStore {
         Ok(())
     }
 
+    fn hard_deleted(&self, node: NodeId) -> Result<bool> {
+        Ok(self.get_state(node)?.hard_deleted)
+    }
+
+    fn set_hard_deleted(&mut self, node: NodeId, hard_deleted: bool) -> Result<()> {
+        self.get_state_mut(node)?.hard_deleted = hard_deleted;
+        Ok(())
+    }
+
+    fn deleted_by(&self, node: NodeId) -> Result<Option<(Lamport, OperationId)>> {
+        Ok(self.get_state(node)?.deleted_by.clone())
+    }
+
+    fn set_deleted_by(&mut self, node: NodeId, op: Option<(Lamport, OperationId)>) -> Result<()> {
+        self.get_state_mut(node)?.deleted_by = op;
+        Ok(())
+    }
+
+    fn orphan_policy(&self) -> OrphanPolicy {
+        self.orphan_policy
+    }
+
     fn all_nodes(&self) -> Result<Vec<NodeId>> {
         Ok(self.nodes.keys().copied().collect())
     }
@@ -528,3 +875,11 @@ impl ExactNodeStore for MemoryNodeStore {
         Ok(())
     }
 }
+
+impl PurgeableNodeStore for MemoryNodeStore {
+    fn remove_node(&mut self, node: NodeId) -> Result<()> {
+        self.detach(node)?;
+        self.nodes.remove(&node);
+        Ok(())
+    }
+}
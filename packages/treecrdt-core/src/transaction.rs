@@ -0,0 +1,247 @@
+//! Several local edits committed as one unit.
+//!
+//! A [`LocalTransaction`] commits each edit as soon as it is staged, so later edits resolve
+//! placements, order keys and delete known states against what the earlier ones did (e.g. "create
+//! a node, then move three siblings under it"). The op log has no rollback: hosts that need
+//! all-or-nothing semantics stage inside their own storage transaction and throw the tree away
+//! when staging fails, the way the SQLite and Postgres adapters run their local op sessions.
+
+use crate::error::{Error, Result};
+use crate::ids::NodeId;
+use crate::ops::{Operation, OperationGroup};
+use crate::traits::{Clock, NodeStore, PayloadStore, Storage};
+use crate::tree::TreeCrdt;
+use crate::types::{LocalFinalizePlan, LocalPlacement, PreparedLocalOp};
+use crate::undo::UndoRecord;
+
+/// One edit of a [`LocalTransaction`], mirroring the `prepare_local_*` methods.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LocalEdit {
+    Insert {
+        parent: NodeId,
+        node: NodeId,
+        placement: LocalPlacement,
+        payload: Option<Vec<u8>>,
+    },
+    Move {
+        node: NodeId,
+        new_parent: NodeId,
+        placement: LocalPlacement,
+    },
+    Delete {
+        node: NodeId,
+    },
+    Tombstone {
+        node: NodeId,
+    },
+    Restore {
+        node: NodeId,
+    },
+    Payload {
+        node: NodeId,
+        payload: Option<Vec<u8>>,
+    },
+    PayloadField {
+        node: NodeId,
+        field: String,
+        value: Option<Vec<u8>>,
+    },
+}
+
+/// A committed edit of a transaction, waiting for [`TreeCrdt::finalize_local_batch_with_outcome`].
+#[derive(Clone, Debug)]
+pub struct StagedLocalOp {
+    pub op: Operation,
+    pub plan: LocalFinalizePlan,
+    /// What reverting `op` needs; record the whole batch as one [`crate::UndoManager`]
+    /// transaction.
+    pub undo: UndoRecord,
+}
+
+/// The result of [`TreeCrdt::local_duplicate`].
+#[derive(Clone, Debug)]
+pub struct DuplicatedSubtree {
+    /// One insert per copied node, parents before children, each followed by the writes that
+    /// copy its payload fields.
+    pub staged: Vec<StagedLocalOp>,
+    /// `(original, copy)` for every copied node, in the order of `staged`.
+    pub mapping: Vec<(NodeId, NodeId)>,
+}
+
+/// Stages local edits against a tree, see the module docs.
+pub struct LocalTransaction<'a, S, C, N, P>
+where
+    S: Storage,
+    C: Clock,
+    N: NodeStore,
+    P: PayloadStore,
+{
+    crdt: &'a mut TreeCrdt<S, C, N, P>,
+    staged: Vec<StagedLocalOp>,
+    group_len: Option<u32>,
+}
+
+impl<'a, S, C, N, P> LocalTransaction<'a, S, C, N, P>
+where
+    S: Storage,
+    C: Clock,
+    N: NodeStore,
+    P: PayloadStore,
+{
+    pub(crate) fn new(crdt: &'a mut TreeCrdt<S, C, N, P>) -> Self {
+        Self {
+            crdt,
+            staged: Vec::new(),
+            group_len: None,
+        }
+    }
+
+    /// Mark the ops as one [`OperationGroup`] of `len` members, starting with the next staged
+    /// edit. Peers buffering groups wait for all `len` ops, so stage exactly that many: staging
+    /// more fails and [`Self::finish`] rejects fewer.
+    pub fn grouped(mut self, len: u32) -> Self {
+        self.group_len = Some(len);
+        self
+    }
+
+    /// Mint, authorize and commit the op for `edit`. On error nothing of `edit` was committed,
+    /// but the edits staged before it were.
+    pub fn stage(&mut self, edit: LocalEdit) -> Result<&Operation> {
+        self.stage_with(|crdt| match edit {
+            LocalEdit::Insert {
+                parent,
+                node,
+                placement,
+                payload,
+            } => crdt.prepare_local_insert(parent, node, placement, payload),
+            LocalEdit::Move {
+                node,
+                new_parent,
+                placement,
+            } => crdt.prepare_local_move(node, new_parent, placement),
+            LocalEdit::Delete { node } => crdt.prepare_local_delete(node),
+            LocalEdit::Tombstone { node } => crdt.prepare_local_tombstone(node),
+            LocalEdit::Restore { node } => crdt.prepare_local_restore(node),
+            LocalEdit::Payload { node, payload } => crdt.prepare_local_payload(node, payload),
+            LocalEdit::PayloadField { node, field, value } => {
+                crdt.prepare_local_payload_field(node, &field, value)
+            }
+        })
+    }
+
+    pub(crate) fn stage_with<F>(&mut self, prepare: F) -> Result<&Operation>
+    where
+        F: FnOnce(&mut TreeCrdt<S, C, N, P>) -> Result<PreparedLocalOp>,
+    {
+        if let Some(len) = self.group_len {
+            if self.staged.len() as u64 >= u64::from(len) {
+                return Err(Error::InvalidOperation(format!(
+                    "op group already has its {len} members"
+                )));
+            }
+        }
+        let mut prepared = prepare(self.crdt)?;
+        if let Some(len) = self.group_len {
+            let first = match self.staged.first() {
+                Some(entry) => entry.op.meta.id.counter,
+                None => prepared.op.meta.id.counter,
+            };
+            prepared.op.meta.group = Some(OperationGroup { first, len });
+        }
+        let undo = self.crdt.undo_record(&prepared.op)?;
+        let (op, plan) = self.crdt.commit_prepared_local(prepared)?;
+        self.staged.push(StagedLocalOp {
+            undo: UndoRecord {
+                op: op.clone(),
+                ..undo
+            },
+            op,
+            plan,
+        });
+        Ok(&self.staged[self.staged.len() - 1].op)
+    }
+
+    pub fn insert(
+        &mut self,
+        parent: NodeId,
+        node: NodeId,
+        placement: LocalPlacement,
+        payload: Option<Vec<u8>>,
+    ) -> Result<&Operation> {
+        self.stage(LocalEdit::Insert {
+            parent,
+            node,
+            placement,
+            payload,
+        })
+    }
+
+    pub fn move_node(
+        &mut self,
+        node: NodeId,
+        new_parent: NodeId,
+        placement: LocalPlacement,
+    ) -> Result<&Operation> {
+        self.stage(LocalEdit::Move {
+            node,
+            new_parent,
+            placement,
+        })
+    }
+
+    pub fn delete(&mut self, node: NodeId) -> Result<&Operation> {
+        self.stage(LocalEdit::Delete { node })
+    }
+
+    pub fn tombstone(&mut self, node: NodeId) -> Result<&Operation> {
+        self.stage(LocalEdit::Tombstone { node })
+    }
+
+    pub fn restore(&mut self, node: NodeId) -> Result<&Operation> {
+        self.stage(LocalEdit::Restore { node })
+    }
+
+    pub fn payload(&mut self, node: NodeId, payload: Option<Vec<u8>>) -> Result<&Operation> {
+        self.stage(LocalEdit::Payload { node, payload })
+    }
+
+    pub fn payload_field(
+        &mut self,
+        node: NodeId,
+        field: &str,
+        value: Option<Vec<u8>>,
+    ) -> Result<&Operation> {
+        self.stage(LocalEdit::PayloadField {
+            node,
+            field: field.to_string(),
+            value,
+        })
+    }
+
+    /// The tree with every staged edit applied.
+    pub fn tree(&self) -> &TreeCrdt<S, C, N, P> {
+        self.crdt
+    }
+
+    pub fn staged(&self) -> &[StagedLocalOp] {
+        &self.staged
+    }
+
+    /// Close the transaction. The ops are already committed; hosts finalize them with
+    /// [`TreeCrdt::finalize_local_batch_with_outcome`].
+    ///
+    /// Fails if the transaction is [`Self::grouped`] but staged a different number of ops than
+    /// the group declares, since peers would wait for the missing members forever. The staged
+    /// ops stay committed, so hosts roll back their own transaction as for a failed edit.
+    pub fn finish(self) -> Result<Vec<StagedLocalOp>> {
+        if let Some(len) = self.group_len {
+            if self.staged.len() as u64 != u64::from(len) {
+                return Err(Error::InvalidOperation(format!(
+                    "op group declares {len} members but {} were staged",
+                    self.staged.len()
+                )));
+            }
+        }
+        Ok(self.staged)
+    }
+}
@@ -0,0 +1,115 @@
+//! Listing of deleted subtrees, e.g. for a trash view that feeds
+//! [`crate::TreeCrdt::local_restore`].
+//!
+//! Only the top of each deleted subtree is listed: a node is in the trash when it is tombstoned
+//! and its parent is not. Entries come newest delete first; nodes deleted before their store
+//! recorded the deleting op sort last.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+use crate::ids::{Lamport, NodeId};
+use crate::traits::{NodeStore, PayloadStore};
+use crate::types::{MaterializationSource, MaterializationSourceOperation};
+use crate::version_vector::VersionVector;
+
+/// Payload bytes kept in [`TrashEntry::payload_preview`].
+pub const TRASH_PREVIEW_LEN: usize = 256;
+
+/// One deleted subtree, see the module docs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct TrashEntry {
+    pub node: NodeId,
+    /// Where the node sat when it was deleted; a restore brings it back there.
+    pub parent: Option<NodeId>,
+    /// The newest delete or tombstone op applied to the node.
+    pub source: Option<MaterializationSource>,
+    pub deleted_at: VersionVector,
+    /// Removed by a [`crate::OperationKind::Tombstone`], so it cannot be restored.
+    pub hard_deleted: bool,
+    /// The first [`TRASH_PREVIEW_LEN`] bytes of the node's payload.
+    pub payload_preview: Option<Vec<u8>>,
+}
+
+/// Position of a [`TrashEntry`] in the listing; pass the last one of a page to get the next.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct TrashCursor {
+    pub lamport: Lamport,
+    pub replica: Vec<u8>,
+    pub counter: u64,
+    pub node: NodeId,
+}
+
+impl TrashEntry {
+    pub fn cursor(&self) -> TrashCursor {
+        let (lamport, replica, counter) = match &self.source {
+            Some(source) => (
+                source.operation.lamport,
+                source.operation.id.replica.as_bytes().to_vec(),
+                source.operation.id.counter,
+            ),
+            None => (0, Vec::new(), 0),
+        };
+        TrashCursor {
+            lamport,
+            replica,
+            counter,
+            node: self.node,
+        }
+    }
+}
+
+/// Up to `limit` trash entries sorting after `after`.
+pub fn trash_page<N, P>(
+    nodes: &N,
+    payloads: &P,
+    after: Option<&TrashCursor>,
+    limit: usize,
+) -> Result<Vec<TrashEntry>>
+where
+    N: NodeStore,
+    P: PayloadStore,
+{
+    let mut page = Vec::new();
+    for node in nodes.all_nodes()? {
+        if node == NodeId::ROOT || node == NodeId::TRASH || !nodes.tombstone(node)? {
+            continue;
+        }
+        let parent = nodes.parent(node)?.filter(|parent| *parent != NodeId::TRASH);
+        if let Some(parent) = parent {
+            if nodes.tombstone(parent)? {
+                continue;
+            }
+        }
+        let source = nodes.deleted_by(node)?.map(|(lamport, id)| MaterializationSource {
+            operation: MaterializationSourceOperation { id, lamport },
+        });
+        let entry = TrashEntry {
+            node,
+            parent,
+            source,
+            deleted_at: nodes.deleted_at(node)?.unwrap_or_default(),
+            hard_deleted: nodes.hard_deleted(node)?,
+            payload_preview: None,
+        };
+        if after.is_some_and(|after| entry.cursor() >= *after) {
+            continue;
+        }
+        page.push(entry);
+    }
+
+    page.sort_by_key(|entry| std::cmp::Reverse(entry.cursor()));
+    page.truncate(limit);
+    for entry in &mut page {
+        entry.payload_preview = payloads.payload(entry.node)?.map(|mut payload| {
+            payload.truncate(TRASH_PREVIEW_LEN);
+            payload
+        });
+    }
+    Ok(page)
+}
@@ -2,24 +2,43 @@ use std::collections::HashSet;
 
 use crate::affected::{
     affected_parents, coalesce_materialization_changes, direct_materialization_changes,
-    materialization_change_from_tombstone_delta, parent_hints_from, TombstoneDelta,
+    materialization_change_from_tombstone_delta, parent_hints_from, rescue_materialization_changes,
+    TombstoneDelta,
 };
+use crate::checkpoint::Checkpoint;
 use crate::error::{Error, Result};
+use crate::groups::GroupBuffer;
+use crate::history::{diff_between, materialize_at, HistoryCut};
 use crate::ids::{Lamport, NodeId, OperationId, ReplicaId};
+use crate::materialization::{
+    cmp_frontiers, frontier_from_op, MaterializationFrontier, MaterializationHead,
+};
+use crate::navigation::{self, Descendants};
 use crate::ops::{cmp_op_key, Operation, OperationKind};
+use crate::order_key::{allocate_evenly_between, Lseq, OrderKeyAllocator, OrderKeyStats};
+use crate::purge::{purge_stable_subtrees, PurgeReport};
+use crate::reorder::plan_reorder;
+use crate::stability::StabilityTracker;
 use crate::traits::{
-    Clock, MemoryNodeStore, MemoryPayloadStore, NodeStore, ParentOpIndex, PayloadStore, Storage,
+    AccessControl, AllowAllAccess, Clock, CompactableStorage, ExactPayloadStore, LamportClock,
+    MemoryNodeStore, MemoryPayloadStore, MemoryStorage, NodeStore, OrphanPolicy, ParentOpIndex,
+    PayloadFieldEntry, PayloadMode, PayloadStore, PayloadValue, PurgeableNodeStore, Storage,
 };
+use crate::transaction::{DuplicatedSubtree, LocalEdit, LocalTransaction, StagedLocalOp};
+use crate::trash::{trash_page, TrashCursor, TrashEntry};
 use crate::types::{
     ApplyDelta, LocalFinalizePlan, LocalPlacement, MaterializationChange, MaterializationOutcome,
     MaterializationSource, NodeExport, NodeSnapshotExport, PreparedLocalOp,
 };
+use crate::undo::UndoRecord;
 use crate::version_vector::VersionVector;
 
 #[derive(Clone)]
 struct NodeSnapshot {
     parent: Option<NodeId>,
     order_key: Option<Vec<u8>>,
+    /// Nodes the op moved under [`NodeId::LOST_AND_FOUND`], with the parent each one left.
+    rescued: Vec<(NodeId, NodeId)>,
 }
 
 fn attach_source_if_missing(
@@ -31,7 +50,8 @@ fn attach_source_if_missing(
         | MaterializationChange::Move { source, .. }
         | MaterializationChange::Delete { source, .. }
         | MaterializationChange::Restore { source, .. }
-        | MaterializationChange::Payload { source, .. } => {
+        | MaterializationChange::Payload { source, .. }
+        | MaterializationChange::PayloadField { source, .. } => {
             if source.is_none() {
                 *source = next_source;
             }
@@ -54,8 +74,12 @@ where
     nodes: N,
     version_vector: VersionVector,
     payloads: P,
-    head: Option<Operation>,
+    head: Option<MaterializationFrontier>,
     op_count: u64,
+    access: Box<dyn AccessControl>,
+    order_keys: Box<dyn OrderKeyAllocator>,
+    /// State folded out of `storage` by [`TreeCrdt::compact`]; replays start from here.
+    base: Option<Checkpoint>,
 }
 
 impl<S, C> TreeCrdt<S, C, MemoryNodeStore>
@@ -90,7 +114,7 @@ where
     ) -> Result<Self> {
         let counter = storage.latest_counter(&replica_id)?;
         let mut clock = clock;
-        clock.observe(storage.latest_lamport());
+        clock.merge(storage.latest_lamport());
         Ok(Self {
             replica_id,
             storage,
@@ -101,30 +125,88 @@ where
             payloads,
             head: None,
             op_count: 0,
+            access: Box::new(AllowAllAccess),
+            order_keys: Box::new(Lseq::default()),
+            base: None,
         })
     }
 
+    /// Rebuild a tree from `checkpoint` plus the ops `storage` holds beyond it.
+    ///
+    /// Ops already covered by the checkpoint are skipped, so `storage` may or may not have been
+    /// compacted. The checkpoint must be a canonical-order prefix of the log: an uncovered op
+    /// sorting before its head is reported as [`Error::InconsistentState`].
+    pub fn from_checkpoint(
+        replica_id: ReplicaId,
+        storage: S,
+        clock: C,
+        nodes: N,
+        payloads: P,
+        checkpoint: Checkpoint,
+    ) -> Result<Self> {
+        let mut crdt = Self::with_stores(replica_id, storage, clock, nodes, payloads)?;
+        crdt.base = Some(checkpoint);
+        crdt.replay_from_storage()?;
+        Ok(crdt)
+    }
+
+    /// Replace the access-control policy consulted by remote applies, local ops and reads.
+    ///
+    /// Ops are checked when they enter the tree (`apply_remote*`, `prepare_local_*`). Replays of
+    /// already persisted ops (`replay_from_storage`, sorted rewind suffixes) are not re-checked.
+    pub fn set_access_control<A: AccessControl + 'static>(&mut self, access: A) {
+        self.access = Box::new(access);
+    }
+
+    /// Replace the strategy local inserts and moves use to pick order keys (default
+    /// [`Lseq`]). Only keys minted from now on are affected; remote ops carry their own.
+    pub fn set_order_key_allocator<A: OrderKeyAllocator + 'static>(&mut self, allocator: A) {
+        self.order_keys = Box::new(allocator);
+    }
+
+    fn authorize_local(&mut self, op: &Operation) -> Result<()> {
+        if let Err(err) = self.access.can_apply(&self.nodes, op) {
+            self.release_counter(op.meta.id.counter);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Hand `counter` back so an op that was never committed does not leave a gap in our own
+    /// history.
+    fn release_counter(&mut self, counter: u64) {
+        self.counter = counter.saturating_sub(1);
+    }
+
+    fn allocate_local_key(
+        &mut self,
+        parent: NodeId,
+        node: NodeId,
+        after: Option<NodeId>,
+        counter: u64,
+        seed: &[u8],
+    ) -> Result<Vec<u8>> {
+        let key = self.allocate_child_key_after(parent, node, after, seed);
+        if key.is_err() {
+            self.release_counter(counter);
+        }
+        key
+    }
+
     fn is_in_order(&self, op: &Operation) -> bool {
         let Some(head) = self.head.as_ref() else {
             return true;
         };
 
-        cmp_op_key(
-            op.meta.lamport,
-            op.meta.id.replica.as_bytes(),
-            op.meta.id.counter,
-            head.meta.lamport,
-            head.meta.id.replica.as_bytes(),
-            head.meta.id.counter,
-        ) == std::cmp::Ordering::Greater
+        cmp_frontiers(&frontier_from_op(op), head) == std::cmp::Ordering::Greater
     }
 
-    fn next_op_meta(&mut self) -> (ReplicaId, u64, Lamport, Vec<u8>) {
+    fn next_op_meta(&mut self) -> Result<(ReplicaId, u64, Lamport, Vec<u8>)> {
+        let lamport = self.clock.tick()?;
         let replica = self.replica_id.clone();
         let counter = self.next_counter();
-        let lamport = self.clock.tick();
         let seed = Self::seed(&replica, counter);
-        (replica, counter, lamport, seed)
+        Ok((replica, counter, lamport, seed))
     }
 
     pub fn resolve_after_for_placement(
@@ -144,7 +226,7 @@ where
                 Ok(Some(after_id))
             }
             LocalPlacement::Last => {
-                let mut children = self.children(parent)?;
+                let mut children = self.visible_children(parent)?;
                 if let Some(excluded) = exclude {
                     children.retain(|child| *child != excluded);
                 }
@@ -172,12 +254,40 @@ where
         payload: Option<Vec<u8>>,
     ) -> Result<PreparedLocalOp> {
         let after = self.resolve_after_for_placement(parent, placement, None)?;
-        let payload_after = payload.clone();
-        let (replica, counter, lamport, seed) = self.next_op_meta();
-        let order_key = self.allocate_child_key_after(parent, node, after, &seed)?;
+        let (replica, counter, lamport, seed) = self.next_op_meta()?;
+        let order_key = self.allocate_local_key(parent, node, after, counter, &seed)?;
+        let op = Operation::insert_with_optional_payload(
+            &replica, counter, lamport, parent, node, order_key, payload,
+        );
+        self.prepare_insert_op(op, parent, node)
+    }
+
+    /// [`Self::prepare_local_insert`] with an order key allocated by the caller.
+    pub(crate) fn prepare_local_insert_keyed(
+        &mut self,
+        parent: NodeId,
+        node: NodeId,
+        order_key: Vec<u8>,
+        payload: Option<Vec<u8>>,
+    ) -> Result<PreparedLocalOp> {
+        let (replica, counter, lamport, _) = self.next_op_meta()?;
         let op = Operation::insert_with_optional_payload(
             &replica, counter, lamport, parent, node, order_key, payload,
         );
+        self.prepare_insert_op(op, parent, node)
+    }
+
+    fn prepare_insert_op(
+        &mut self,
+        op: Operation,
+        parent: NodeId,
+        node: NodeId,
+    ) -> Result<PreparedLocalOp> {
+        self.authorize_local(&op)?;
+        let payload_after = match &op.kind {
+            OperationKind::Insert { payload, .. } => payload.clone(),
+            _ => None,
+        };
         Ok(PreparedLocalOp {
             op,
             plan: LocalFinalizePlan {
@@ -209,11 +319,33 @@ where
         new_parent: NodeId,
         placement: LocalPlacement,
     ) -> Result<PreparedLocalOp> {
-        let old_parent = self.parent(node)?;
         let after = self.resolve_after_for_placement(new_parent, placement, Some(node))?;
-        let (replica, counter, lamport, seed) = self.next_op_meta();
-        let order_key = self.allocate_child_key_after(new_parent, node, after, &seed)?;
+        let (replica, counter, lamport, seed) = self.next_op_meta()?;
+        let order_key = self.allocate_local_key(new_parent, node, after, counter, &seed)?;
+        let op = Operation::move_node(&replica, counter, lamport, node, new_parent, order_key);
+        self.prepare_move_op(op, node, new_parent)
+    }
+
+    /// [`Self::prepare_local_move`] with an order key allocated by the caller.
+    pub(crate) fn prepare_local_move_keyed(
+        &mut self,
+        node: NodeId,
+        new_parent: NodeId,
+        order_key: Vec<u8>,
+    ) -> Result<PreparedLocalOp> {
+        let (replica, counter, lamport, _) = self.next_op_meta()?;
         let op = Operation::move_node(&replica, counter, lamport, node, new_parent, order_key);
+        self.prepare_move_op(op, node, new_parent)
+    }
+
+    fn prepare_move_op(
+        &mut self,
+        op: Operation,
+        node: NodeId,
+        new_parent: NodeId,
+    ) -> Result<PreparedLocalOp> {
+        let old_parent = self.parent(node)?;
+        self.authorize_local(&op)?;
 
         let mut parent_hints = vec![new_parent];
         if let Some(parent) = old_parent {
@@ -222,7 +354,7 @@ where
 
         let mut extra_index_records: Vec<(NodeId, OperationId)> = Vec::new();
         if old_parent != Some(new_parent) && new_parent != NodeId::TRASH {
-            if let Some((_lamport, payload_id)) = self.payload_last_writer(node)? {
+            for payload_id in self.payloads.current_writers(node)? {
                 extra_index_records.push((new_parent, payload_id));
             }
         }
@@ -249,9 +381,35 @@ where
 
     pub fn prepare_local_delete(&mut self, node: NodeId) -> Result<PreparedLocalOp> {
         let old_parent = self.parent(node)?;
-        let (replica, counter, lamport, _seed) = self.next_op_meta();
+        let (replica, counter, lamport, _seed) = self.next_op_meta()?;
         let known_state = Some(self.subtree_version_vector(node)?);
         let op = Operation::delete(&replica, counter, lamport, node, known_state);
+        self.authorize_local(&op)?;
+        Ok(PreparedLocalOp {
+            op,
+            plan: LocalFinalizePlan {
+                parent_hints: parent_hints_from(old_parent),
+                extra_index_records: Vec::new(),
+                changes: vec![MaterializationChange::Delete {
+                    node,
+                    parent_before: old_parent.filter(|parent| *parent != NodeId::TRASH),
+                    source: None,
+                }],
+            },
+        })
+    }
+
+    /// Delete `node` for good, see [`OperationKind::Tombstone`].
+    pub fn local_tombstone(&mut self, node: NodeId) -> Result<(Operation, LocalFinalizePlan)> {
+        let prepared = self.prepare_local_tombstone(node)?;
+        self.commit_prepared_local(prepared)
+    }
+
+    pub fn prepare_local_tombstone(&mut self, node: NodeId) -> Result<PreparedLocalOp> {
+        let old_parent = self.parent(node)?;
+        let (replica, counter, lamport, _seed) = self.next_op_meta()?;
+        let op = Operation::tombstone(&replica, counter, lamport, node);
+        self.authorize_local(&op)?;
         Ok(PreparedLocalOp {
             op,
             plan: LocalFinalizePlan {
@@ -266,6 +424,63 @@ where
         })
     }
 
+    /// Bring back `node` and any deleted ancestors it sits under, see
+    /// [`OperationKind::Restore`]. Fails if nothing on the way to the root is deleted.
+    pub fn local_restore(&mut self, node: NodeId) -> Result<(Operation, LocalFinalizePlan)> {
+        let prepared = self.prepare_local_restore(node)?;
+        self.commit_prepared_local(prepared)
+    }
+
+    pub fn prepare_local_restore(&mut self, node: NodeId) -> Result<PreparedLocalOp> {
+        if !self.nodes.exists(node)? || node == NodeId::ROOT || node == NodeId::TRASH {
+            return Err(Error::InvalidOperation(
+                "cannot restore an unknown node".into(),
+            ));
+        }
+        // The restore is newer than every delete, so each tombstoned node from `node` up to the
+        // root comes back.
+        let mut parent_hints = Vec::new();
+        let mut changes = Vec::new();
+        let mut visited = HashSet::new();
+        let mut current = Some(node);
+        while let Some(id) = current {
+            if id == NodeId::ROOT || id == NodeId::TRASH || !visited.insert(id) {
+                break;
+            }
+            let parent = self.nodes.parent(id)?;
+            if self.nodes.hard_deleted(id)? {
+                return Err(Error::InvalidOperation(
+                    "node was permanently deleted".into(),
+                ));
+            }
+            if self.is_tombstoned(id)? {
+                parent_hints.extend(parent);
+                changes.push(MaterializationChange::Restore {
+                    node: id,
+                    parent_after: parent.filter(|parent| *parent != NodeId::TRASH),
+                    payload: self.payloads.payload(id)?,
+                    source: None,
+                });
+            }
+            current = parent;
+        }
+        if changes.is_empty() {
+            return Err(Error::InvalidOperation("node is not deleted".into()));
+        }
+
+        let (replica, counter, lamport, _seed) = self.next_op_meta()?;
+        let op = Operation::restore(&replica, counter, lamport, node);
+        self.authorize_local(&op)?;
+        Ok(PreparedLocalOp {
+            op,
+            plan: LocalFinalizePlan {
+                parent_hints,
+                extra_index_records: Vec::new(),
+                changes,
+            },
+        })
+    }
+
     pub fn local_payload(
         &mut self,
         node: NodeId,
@@ -282,12 +497,15 @@ where
     ) -> Result<PreparedLocalOp> {
         let parent = self.parent(node)?;
         let payload_after = payload.clone();
-        let (replica, counter, lamport, _seed) = self.next_op_meta();
-        let op = if let Some(payload) = payload {
+        let known_state = self.payload_known_state(node)?;
+        let (replica, counter, lamport, _seed) = self.next_op_meta()?;
+        let mut op = if let Some(payload) = payload {
             Operation::set_payload(&replica, counter, lamport, node, payload)
         } else {
             Operation::clear_payload(&replica, counter, lamport, node)
         };
+        op.meta.known_state = known_state;
+        self.authorize_local(&op)?;
         Ok(PreparedLocalOp {
             op,
             plan: LocalFinalizePlan {
@@ -302,12 +520,242 @@ where
         })
     }
 
+    /// In [`PayloadMode::MultiValue`], the payload writes a local write to `node` has seen: every
+    /// current value and everything those values had seen. Writing with it collapses the
+    /// node's conflict set into the new value.
+    fn payload_known_state(&self, node: NodeId) -> Result<Option<VersionVector>> {
+        if self.payloads.mode() != PayloadMode::MultiValue {
+            return Ok(None);
+        }
+        let mut known_state = VersionVector::new();
+        for value in Self::current_payload_values(&self.payloads, node)? {
+            known_state.merge(&value.known_state);
+            known_state.observe(&value.writer.replica, value.writer.counter);
+        }
+        Ok(Some(known_state))
+    }
+
+    /// Set (`Some`) or clear (`None`) one field of `node`'s payload map, see
+    /// [`OperationKind::PayloadField`].
+    pub fn local_payload_field(
+        &mut self,
+        node: NodeId,
+        field: &str,
+        value: Option<Vec<u8>>,
+    ) -> Result<(Operation, LocalFinalizePlan)> {
+        let prepared = self.prepare_local_payload_field(node, field, value)?;
+        self.commit_prepared_local(prepared)
+    }
+
+    pub fn prepare_local_payload_field(
+        &mut self,
+        node: NodeId,
+        field: &str,
+        value: Option<Vec<u8>>,
+    ) -> Result<PreparedLocalOp> {
+        let parent = self.parent(node)?;
+        let (replica, counter, lamport, _seed) = self.next_op_meta()?;
+        let op = Operation::payload_field(&replica, counter, lamport, node, field, value.clone());
+        self.authorize_local(&op)?;
+        Ok(PreparedLocalOp {
+            op,
+            plan: LocalFinalizePlan {
+                parent_hints: parent_hints_from(parent),
+                extra_index_records: Vec::new(),
+                changes: vec![MaterializationChange::PayloadField {
+                    node,
+                    field: field.to_string(),
+                    value,
+                    source: None,
+                }],
+            },
+        })
+    }
+
     pub fn commit_prepared_local(
         &mut self,
         prepared: PreparedLocalOp,
     ) -> Result<(Operation, LocalFinalizePlan)> {
-        let op = self.commit_local(prepared.op)?;
-        Ok((op, prepared.plan))
+        let (op, rescued) = self.commit_local(prepared.op)?;
+        let mut plan = prepared.plan;
+        if !rescued.is_empty() {
+            plan.changes.extend(rescue_materialization_changes(&rescued, None));
+            plan.extra_index_records.push((NodeId::LOST_AND_FOUND, op.meta.id.clone()));
+            for (node, _) in &rescued {
+                for payload_id in self.payloads.current_writers(*node)? {
+                    plan.extra_index_records.push((NodeId::LOST_AND_FOUND, payload_id));
+                }
+            }
+        }
+        Ok((op, plan))
+    }
+
+    /// Capture the state `op` is about to replace, so the op can be undone later.
+    ///
+    /// Call this between `prepare_local_*` and [`Self::commit_prepared_local`]: once the op is
+    /// applied, the node's prior position and payload are no longer available.
+    pub fn undo_record(&self, op: &Operation) -> Result<UndoRecord> {
+        let node = op.kind.node();
+        let before = if self.nodes.exists(node)? {
+            NodeSnapshotExport {
+                parent: self.nodes.parent(node)?,
+                order_key: self.nodes.order_key(node)?,
+            }
+        } else {
+            NodeSnapshotExport {
+                parent: None,
+                order_key: None,
+            }
+        };
+        let payload_before = match &op.kind {
+            OperationKind::Payload { .. } => self.payloads.payload(node)?,
+            OperationKind::PayloadField { field, .. } => {
+                self.payloads.payload_field(node, field)?.and_then(|entry| entry.value)
+            }
+            _ => None,
+        };
+        Ok(UndoRecord {
+            op: op.clone(),
+            before,
+            deleted_before: self.is_tombstoned(node)?,
+            payload_before,
+        })
+    }
+
+    /// Prepare the local op that reverts `record.op`, or `None` if there is nothing to revert.
+    ///
+    /// Inverses are ordinary new ops, so they merge with concurrent edits like any other local
+    /// op. A record is skipped once ops seen since then have taken over the state it changed:
+    /// - an insert, once the node is deleted;
+    /// - a move, once the node was moved again or deleted (a move that revived a deleted node is
+    ///   reverted by deleting it again);
+    /// - a delete, once the node was revived;
+    /// - a restore, once the node is hidden again (it is reverted by deleting the node);
+    /// - a payload or payload field write, once another write won LWW or the node was deleted.
+    ///
+    /// Moves and deletes are reverted by moving the node back to its recorded parent and order
+    /// key. That is skipped as well if the old parent is no longer visible or now lies inside the
+    /// node's subtree, since the move would revive deleted ancestors or be dropped as a cycle.
+    pub fn prepare_inverse(&mut self, record: &UndoRecord) -> Result<Option<PreparedLocalOp>> {
+        if record.op.meta.id.replica != self.replica_id {
+            return Err(Error::InvalidOperation(
+                "can only invert ops of the local replica".into(),
+            ));
+        }
+        match &record.op.kind {
+            OperationKind::Insert { node, .. } => {
+                if !self.nodes.exists(*node)? || self.is_tombstoned(*node)? {
+                    return Ok(None);
+                }
+                self.prepare_local_delete(*node).map(Some)
+            }
+            OperationKind::Move {
+                node,
+                new_parent,
+                order_key,
+            } => {
+                if self.nodes.parent(*node)? != Some(*new_parent)
+                    || self.nodes.order_key(*node)?.as_ref() != Some(order_key)
+                    || self.is_tombstoned(*node)?
+                {
+                    return Ok(None);
+                }
+                if record.deleted_before {
+                    return self.prepare_local_delete(*node).map(Some);
+                }
+                self.prepare_restore_position(*node, &record.before)
+            }
+            OperationKind::Delete { node } => {
+                if !self.is_tombstoned(*node)? || self.nodes.hard_deleted(*node)? {
+                    return Ok(None);
+                }
+                self.prepare_restore_position(*node, &record.before)
+            }
+            // Permanent by design.
+            OperationKind::Tombstone { .. } => Ok(None),
+            OperationKind::Restore { node } => {
+                if !self.is_visible(*node)? {
+                    return Ok(None);
+                }
+                self.prepare_local_delete(*node).map(Some)
+            }
+            OperationKind::Payload { node, .. } => {
+                let still_winning = self
+                    .payloads
+                    .last_writer(*node)?
+                    .is_some_and(|(_, id)| id == record.op.meta.id);
+                if !still_winning || self.is_tombstoned(*node)? {
+                    return Ok(None);
+                }
+                self.prepare_local_payload(*node, record.payload_before.clone()).map(Some)
+            }
+            OperationKind::PayloadField { node, field, .. } => {
+                let still_winning = self
+                    .payloads
+                    .payload_field(*node, field)?
+                    .is_some_and(|entry| entry.writer == record.op.meta.id);
+                if !still_winning || self.is_tombstoned(*node)? {
+                    return Ok(None);
+                }
+                self.prepare_local_payload_field(*node, field, record.payload_before.clone())
+                    .map(Some)
+            }
+        }
+    }
+
+    /// Apply the inverse of `record` (see [`Self::prepare_inverse`]). Returns the committed op
+    /// together with the record that reverts it in turn.
+    pub fn local_inverse(
+        &mut self,
+        record: &UndoRecord,
+    ) -> Result<Option<(Operation, LocalFinalizePlan, UndoRecord)>> {
+        let Some(prepared) = self.prepare_inverse(record)? else {
+            return Ok(None);
+        };
+        let inverse = self.undo_record(&prepared.op)?;
+        let (op, plan) = self.commit_prepared_local(prepared)?;
+        Ok(Some((op.clone(), plan, UndoRecord { op, ..inverse })))
+    }
+
+    fn prepare_restore_position(
+        &mut self,
+        node: NodeId,
+        before: &NodeSnapshotExport,
+    ) -> Result<Option<PreparedLocalOp>> {
+        let (Some(parent), Some(order_key)) = (before.parent, before.order_key.clone()) else {
+            return Ok(None);
+        };
+        if parent != NodeId::TRASH
+            && (parent == node
+                || !self.is_visible(parent)?
+                || Self::introduces_cycle(&self.nodes, node, parent)?)
+        {
+            return Ok(None);
+        }
+        let (replica, counter, lamport, _seed) = self.next_op_meta()?;
+        let op = Operation::move_node(&replica, counter, lamport, node, parent, order_key);
+        self.prepare_move_op(op, node, parent).map(Some)
+    }
+
+    /// Whether `node` is reachable from the root, or from [`NodeId::LOST_AND_FOUND`], without
+    /// passing a tombstoned node.
+    fn is_visible(&self, node: NodeId) -> Result<bool> {
+        let mut visited = HashSet::new();
+        let mut current = node;
+        while current != NodeId::ROOT && current != NodeId::LOST_AND_FOUND {
+            if current == NodeId::TRASH
+                || !visited.insert(current)
+                || !self.nodes.exists(current)?
+                || self.is_tombstoned(current)?
+            {
+                return Ok(false);
+            }
+            match self.nodes.parent(current)? {
+                Some(parent) => current = parent,
+                None => return Ok(false),
+            }
+        }
+        Ok(true)
     }
 
     pub fn apply_remote(&mut self, op: Operation) -> Result<()> {
@@ -321,22 +769,30 @@ where
     /// - `Some(delta)` for in-order applies where an exact changed-node set is known,
     /// - `None` for duplicate/not-applied ops or paths that require replay.
     pub fn apply_remote_with_delta(&mut self, op: Operation) -> Result<Option<ApplyDelta>> {
-        self.clock.observe(op.meta.lamport);
+        self.access.can_apply(&self.nodes, &op)?;
+        self.clock.admit(op.meta.lamport)?;
+        // Storage may still reject the op (e.g. unseen inside a compacted prefix), so only
+        // advance the clock and version vector once it has taken it or already had it.
+        let inserted = self.storage.apply(op.clone())?;
+        self.clock.merge(op.meta.lamport);
         self.version_vector.observe(&op.meta.id.replica, op.meta.id.counter);
         if op.meta.id.replica == self.replica_id {
             self.counter = self.counter.max(op.meta.id.counter);
         }
-
-        if !self.storage.apply(op.clone())? {
+        if !inserted {
             return Ok(None);
         }
 
         if self.is_in_order(&op) {
             let snapshot = Self::apply_forward(&mut self.nodes, &mut self.payloads, &op)?;
             self.op_count += 1;
-            self.head = Some(op.clone());
+            self.head = Some(frontier_from_op(&op));
 
-            let changes = direct_materialization_changes(snapshot.parent, &op);
+            let mut changes = direct_materialization_changes(snapshot.parent, &op);
+            changes.extend(rescue_materialization_changes(
+                &snapshot.rescued,
+                Some(MaterializationSource::from_op(&op)),
+            ));
             return Ok(Some(ApplyDelta {
                 snapshot: NodeSnapshotExport {
                     parent: snapshot.parent,
@@ -351,6 +807,25 @@ where
         Ok(None)
     }
 
+    /// Apply remote `ops` through `buffer`, so members of an op group only become visible once
+    /// the whole group is here. Returns the ops that were released and applied, in canonical
+    /// order.
+    pub fn apply_remote_grouped(
+        &mut self,
+        ops: Vec<Operation>,
+        buffer: &mut GroupBuffer,
+        now: u64,
+    ) -> Result<Vec<Operation>> {
+        let version_vector = &self.version_vector;
+        let ready = buffer.admit(ops, now, |replica, counter| {
+            Ok(version_vector.contains(replica, counter))
+        })?;
+        for op in &ready {
+            self.apply_remote(op.clone())?;
+        }
+        Ok(ready)
+    }
+
     /// Apply a remote op with full materialization bookkeeping.
     ///
     /// This wires together core CRDT semantics (`apply_remote_with_delta`),
@@ -372,6 +847,7 @@ where
         let snapshot = NodeSnapshot {
             parent: delta.snapshot.parent,
             order_key: delta.snapshot.order_key,
+            rescued: Vec::new(),
         };
         Ok(Some(self.finalize_materialized_apply(
             snapshot,
@@ -393,7 +869,7 @@ where
         index: &mut I,
         seq: u64,
     ) -> Result<ApplyDelta> {
-        self.clock.observe(op.meta.lamport);
+        self.clock.merge(op.meta.lamport);
         self.version_vector.observe(&op.meta.id.replica, op.meta.id.counter);
         if op.meta.id.replica == self.replica_id {
             self.counter = self.counter.max(op.meta.id.counter);
@@ -401,9 +877,13 @@ where
 
         let snapshot = Self::apply_forward(&mut self.nodes, &mut self.payloads, &op)?;
         self.op_count = seq;
-        self.head = Some(op.clone());
+        self.head = Some(frontier_from_op(&op));
 
-        let changes = direct_materialization_changes(snapshot.parent, &op);
+        let mut changes = direct_materialization_changes(snapshot.parent, &op);
+        changes.extend(rescue_materialization_changes(
+            &snapshot.rescued,
+            Some(MaterializationSource::from_op(&op)),
+        ));
         self.finalize_materialized_apply(snapshot, &op, index, seq, changes)
     }
 
@@ -434,16 +914,37 @@ where
             index.record(*parent, &op.meta.id, seq)?;
         }
 
-        // Ensure the latest payload op for `op_node` is discoverable under its current parent.
+        // Ensure the winning payload ops for `op_node` are discoverable under its current parent.
         // This supports partial sync subscribers that only track `children(parent)` opRefs.
         if let Some(parent_after) = parent_after {
             if parent_after != NodeId::TRASH && snapshot.parent != Some(parent_after) {
-                if let Some((_lamport, payload_id)) = self.payload_last_writer(op_node)? {
+                for payload_id in self.payloads.current_writers(op_node)? {
                     index.record(parent_after, &payload_id, seq)?;
                 }
             }
         }
 
+        // Rescued nodes have to be discoverable under LOST_AND_FOUND the same way.
+        let mut recorded_rescue = parents.contains(&NodeId::LOST_AND_FOUND);
+        for change in &changes {
+            if let MaterializationChange::Move {
+                node,
+                parent_after: NodeId::LOST_AND_FOUND,
+                ..
+            } = change
+            {
+                if !recorded_rescue {
+                    index.record(NodeId::LOST_AND_FOUND, &op.meta.id, seq)?;
+                    recorded_rescue = true;
+                }
+                if *node != op_node {
+                    for payload_id in self.payloads.current_writers(*node)? {
+                        index.record(NodeId::LOST_AND_FOUND, &payload_id, seq)?;
+                    }
+                }
+            }
+        }
+
         let mut starts = parents;
         starts.push(op_node);
         let tombstone_changed = self.refresh_tombstones_upward_with_delta(starts)?;
@@ -518,45 +1019,349 @@ where
         Ok(self.finalize_local_with_outcome(op, index, head_seq, plan)?.head_seq)
     }
 
-    fn refresh_tombstones_upward<I>(&mut self, starts: I) -> Result<()>
+    /// Start staging several local edits, see [`LocalTransaction`].
+    pub fn local_transaction(&mut self) -> LocalTransaction<'_, S, C, N, P> {
+        LocalTransaction::new(self)
+    }
+
+    /// Stage `edits` in order and return the committed ops. Stops at the first edit that fails;
+    /// the ones before it stay committed, so run this inside a host transaction that is rolled
+    /// back on error.
+    ///
+    /// Batches of more than one edit are marked as one [`crate::OperationGroup`], so peers that
+    /// buffer groups show them all at once.
+    pub fn local_batch<E>(&mut self, edits: E) -> Result<Vec<StagedLocalOp>>
     where
-        I: IntoIterator<Item = NodeId>,
+        E: IntoIterator<Item = LocalEdit>,
     {
-        let _ = self.refresh_tombstones_upward_with_delta(starts)?;
-        Ok(())
+        let edits: Vec<LocalEdit> = edits.into_iter().collect();
+        let mut tx = self.local_transaction();
+        if edits.len() > 1 {
+            tx = tx.grouped(edits.len() as u32);
+        }
+        for edit in edits {
+            tx.stage(edit)?;
+        }
+        tx.finish()
     }
 
-    /// Refresh tombstone cache for nodes on the upward closure of `starts`.
+    /// Insert `nodes` with their payloads as consecutive children of `parent`, the first one at
+    /// `placement`. The order keys come from a single
+    /// [`crate::OrderKeyAllocator::allocate_many_between`] call, so a long paste gets short,
+    /// evenly spaced keys instead of each one nesting after the previous.
     ///
-    /// Returns every node whose cached tombstone value actually changed.
-    fn refresh_tombstones_upward_with_delta<I>(&mut self, starts: I) -> Result<Vec<TombstoneDelta>>
+    /// Like [`Self::local_batch`], the inserts are one op group and a failure leaves the ones
+    /// before it committed.
+    pub fn local_insert_many<I>(
+        &mut self,
+        parent: NodeId,
+        placement: LocalPlacement,
+        nodes: I,
+    ) -> Result<Vec<StagedLocalOp>>
     where
-        I: IntoIterator<Item = NodeId>,
+        I: IntoIterator<Item = (NodeId, Option<Vec<u8>>)>,
     {
-        let mut stack: Vec<NodeId> = starts.into_iter().collect();
-        let mut visited: HashSet<NodeId> = HashSet::new();
-        let mut changed: Vec<TombstoneDelta> = Vec::new();
+        let nodes: Vec<(NodeId, Option<Vec<u8>>)> = nodes.into_iter().collect();
+        if nodes.is_empty() {
+            return Ok(Vec::new());
+        }
+        let after = self.resolve_after_for_placement(parent, placement, None)?;
+        let keys = if parent == NodeId::TRASH {
+            vec![Vec::new(); nodes.len()]
+        } else {
+            let (left, right) = self.neighbor_keys(parent, None, after)?;
+            let seed = Self::seed(&self.replica_id, self.counter + 1);
+            self.order_keys.allocate_many_between(
+                left.as_deref(),
+                right.as_deref(),
+                nodes.len(),
+                &seed,
+            )?
+        };
 
-        while let Some(node) = stack.pop() {
-            if node == NodeId::ROOT || node == NodeId::TRASH {
-                continue;
-            }
-            if !visited.insert(node) {
-                continue;
-            }
-            let Some((parent, has_deleted_at)) = self.nodes.parent_and_has_deleted_at(node)? else {
-                continue;
-            };
+        let mut tx = self.local_transaction();
+        if nodes.len() > 1 {
+            tx = tx.grouped(nodes.len() as u32);
+        }
+        for ((node, payload), order_key) in nodes.into_iter().zip(keys) {
+            tx.stage_with(|crdt| {
+                crdt.prepare_local_insert_keyed(parent, node, order_key, payload)
+            })?;
+        }
+        tx.finish()
+    }
 
-            if has_deleted_at {
-                let previous = self.nodes.tombstone(node)?;
-                let tombstoned = self.is_tombstoned(node)?;
-                if previous != tombstoned {
-                    self.nodes.set_tombstone(node, tombstoned)?;
-                    changed.push(TombstoneDelta {
-                        node,
-                        parent,
-                        previous,
+    /// Order key lengths of `parent`'s visible children, to spot parents that need a
+    /// [`Self::local_rebalance`].
+    pub fn order_key_stats(&self, parent: NodeId) -> Result<OrderKeyStats> {
+        let mut keys = Vec::new();
+        for child in self.children(parent)? {
+            keys.push(self.nodes.order_key(child)?.unwrap_or_default());
+        }
+        Ok(OrderKeyStats::from_keys(keys.iter().map(Vec::as_slice)))
+    }
+
+    /// Stage moves that shorten the overlong order keys among `parent`'s visible children,
+    /// keeping their current order. A key is overlong if it is more than one digit longer than
+    /// the keys an even spread of all the children would get. Each run of overlong keys is
+    /// re-keyed between the neighbours around it, which keep their keys; a run with too little
+    /// room between them takes the neighbours in.
+    ///
+    /// The re-keys are ordinary moves in one op group, so replicas converge on them like on any
+    /// other edit. A concurrent move of one of the children wins if it sorts after the re-key
+    /// and is overridden otherwise. A sibling inserted concurrently against the old keys stays
+    /// between the same unchanged neighbours, though within a re-keyed run its place among the
+    /// run is up to the new keys. Like [`Self::local_batch`], a failure leaves the moves before
+    /// it committed.
+    ///
+    /// A run's new keys depend only on the keys of the neighbours around it and the ids of the
+    /// nodes in it, so replicas that rebalance the same children concurrently mint the same
+    /// keys. Replicas that rebalance different views of the children (one has seen an insert
+    /// or move the other has not) mint different keys, and the merged run may come out in an
+    /// order neither of them had.
+    pub fn local_rebalance(&mut self, parent: NodeId) -> Result<Vec<StagedLocalOp>> {
+        if parent == NodeId::TRASH {
+            return Err(Error::InvalidOperation(
+                "trash children have no order".into(),
+            ));
+        }
+        let children = self.children(parent)?;
+        if children.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut keys = Vec::with_capacity(children.len());
+        for &child in &children {
+            keys.push(self.nodes.order_key(child)?.unwrap_or_default());
+        }
+        // Runs are spread evenly whatever the configured allocator, since a rebalance should
+        // leave room everywhere. One digit of slack over a full re-key leaves room for runs
+        // between close neighbours; a run spanning all children gets exactly the full re-key, so
+        // it always fits.
+        let spread = allocate_evenly_between(
+            None,
+            None,
+            children.len(),
+            &Self::rebalance_seed(None, None, &children),
+        )?;
+        let limit = spread.iter().map(Vec::len).max().unwrap_or(0) + 2;
+        let mut rekey: Vec<bool> = keys.iter().map(|key| key.len() > limit).collect();
+
+        let mut moves = Vec::new();
+        'plan: loop {
+            moves.clear();
+            let mut end = 0;
+            while end < children.len() {
+                if !rekey[end] {
+                    end += 1;
+                    continue;
+                }
+                let start = end;
+                while end < children.len() && rekey[end] {
+                    end += 1;
+                }
+                let left = start.checked_sub(1).map(|i| keys[i].as_slice());
+                let right = keys.get(end).map(Vec::as_slice);
+                let seed = Self::rebalance_seed(left, right, &children[start..end]);
+                let fitted = allocate_evenly_between(left, right, end - start, &seed)
+                    .ok()
+                    .filter(|run| run.iter().all(|key| key.len() <= limit));
+                let Some(run) = fitted else {
+                    if start > 0 {
+                        rekey[start - 1] = true;
+                    }
+                    if end < children.len() {
+                        rekey[end] = true;
+                    }
+                    continue 'plan;
+                };
+                moves.extend(children[start..end].iter().copied().zip(run));
+            }
+            break;
+        }
+
+        let mut tx = self.local_transaction();
+        if moves.len() > 1 {
+            tx = tx.grouped(moves.len() as u32);
+        }
+        for (child, order_key) in moves {
+            tx.stage_with(|crdt| crdt.prepare_local_move_keyed(child, parent, order_key))?;
+        }
+        tx.finish()
+    }
+
+    /// Stage the fewest moves that put `parent`'s visible children in the `desired` order,
+    /// which must list each of them exactly once (see [`crate::reorder`]). Moved nodes get keys
+    /// between the neighbours that stay, so the op log grows with the nodes that actually
+    /// changed place rather than with the number of children.
+    ///
+    /// Like [`Self::local_batch`], the moves are one op group and a failure leaves the ones
+    /// before it committed.
+    pub fn local_reorder(
+        &mut self,
+        parent: NodeId,
+        desired: &[NodeId],
+    ) -> Result<Vec<StagedLocalOp>> {
+        if parent == NodeId::TRASH {
+            return Err(Error::InvalidOperation(
+                "trash children have no order".into(),
+            ));
+        }
+        let current = self.children(parent)?;
+        let seed = Self::seed(&self.replica_id, self.counter + 1);
+        let mut moves = Vec::new();
+        for run in plan_reorder(&current, desired)? {
+            let left = match run.after {
+                Some(node) => self.nodes.order_key(node)?,
+                None => None,
+            };
+            let right = match run.before {
+                Some(node) => self.nodes.order_key(node)?,
+                None => None,
+            };
+            let keys = self.order_keys.allocate_many_between(
+                left.as_deref(),
+                right.as_deref(),
+                run.nodes.len(),
+                &seed,
+            )?;
+            moves.extend(run.nodes.into_iter().zip(keys));
+        }
+
+        let mut tx = self.local_transaction();
+        if moves.len() > 1 {
+            tx = tx.grouped(moves.len() as u32);
+        }
+        for (node, order_key) in moves {
+            tx.stage_with(|crdt| crdt.prepare_local_move_keyed(node, parent, order_key))?;
+        }
+        tx.finish()
+    }
+
+    /// Stage inserts recreating the visible subtree of `source` under `new_parent`: the copy of
+    /// `source` goes to `placement`, every copied node carries the payload and payload fields of
+    /// its original and siblings keep their order. `fresh_id` names the copy of each source node.
+    ///
+    /// Like [`Self::local_batch`], the copy is one op group and a failure leaves the inserts
+    /// before it committed. Copying a node into its own subtree copies it as it was before.
+    pub fn local_duplicate<F>(
+        &mut self,
+        source: NodeId,
+        new_parent: NodeId,
+        placement: LocalPlacement,
+        mut fresh_id: F,
+    ) -> Result<DuplicatedSubtree>
+    where
+        F: FnMut(NodeId) -> NodeId,
+    {
+        if source == NodeId::ROOT || source == NodeId::TRASH {
+            return Err(Error::InvalidOperation(
+                "cannot duplicate the root or trash".into(),
+            ));
+        }
+        if !self.is_known(source)? || self.is_tombstoned(source)? {
+            return Err(Error::InvalidOperation(
+                "duplicate source is not visible".into(),
+            ));
+        }
+
+        // Snapshot the subtree in pre-order before inserting anything, so parents are copied
+        // before their children and a copy under `source` is not copied again.
+        let mut mapping: Vec<(NodeId, NodeId)> = Vec::new();
+        let mut edits = Vec::new();
+        let mut stack = vec![(source, new_parent, Some(placement))];
+        while let Some((node, parent, placement)) = stack.pop() {
+            let copy = fresh_id(node);
+            if self.is_known(copy)? || mapping.iter().any(|(_, seen)| *seen == copy) {
+                return Err(Error::InvalidOperation(
+                    "duplicate target id is already in use".into(),
+                ));
+            }
+            mapping.push((node, copy));
+            edits.push(LocalEdit::Insert {
+                parent,
+                node: copy,
+                placement: placement.unwrap_or(LocalPlacement::Last),
+                payload: self.payload(node)?,
+            });
+            for (field, value) in self.payload_fields(node)? {
+                edits.push(LocalEdit::PayloadField {
+                    node: copy,
+                    field,
+                    value: Some(value),
+                });
+            }
+            for child in self.children(node)?.into_iter().rev() {
+                stack.push((child, copy, None));
+            }
+        }
+
+        let staged = self.local_batch(edits)?;
+        Ok(DuplicatedSubtree { staged, mapping })
+    }
+
+    /// [`Self::finalize_local_with_outcome`] for every op of a transaction, in staging order.
+    ///
+    /// Each op takes the next materialization seq after `head_seq`; the changes of all of them
+    /// are coalesced into one outcome, e.g. an insert followed by a payload edit of the same
+    /// node is reported as a single insert carrying the final payload.
+    pub fn finalize_local_batch_with_outcome<I: ParentOpIndex>(
+        &mut self,
+        staged: &[StagedLocalOp],
+        index: &mut I,
+        head_seq: u64,
+    ) -> Result<MaterializationOutcome> {
+        let mut seq = head_seq;
+        let mut changes = Vec::new();
+        for entry in staged {
+            let outcome = self.finalize_local_with_outcome(&entry.op, index, seq, &entry.plan)?;
+            seq = outcome.head_seq;
+            changes.extend(outcome.changes);
+        }
+        Ok(MaterializationOutcome {
+            head_seq: seq,
+            changes: coalesce_materialization_changes(changes),
+        })
+    }
+
+    fn refresh_tombstones_upward<I>(&mut self, starts: I) -> Result<()>
+    where
+        I: IntoIterator<Item = NodeId>,
+    {
+        let _ = self.refresh_tombstones_upward_with_delta(starts)?;
+        Ok(())
+    }
+
+    /// Refresh tombstone cache for nodes on the upward closure of `starts`.
+    ///
+    /// Returns every node whose cached tombstone value actually changed.
+    fn refresh_tombstones_upward_with_delta<I>(&mut self, starts: I) -> Result<Vec<TombstoneDelta>>
+    where
+        I: IntoIterator<Item = NodeId>,
+    {
+        let mut stack: Vec<NodeId> = starts.into_iter().collect();
+        let mut visited: HashSet<NodeId> = HashSet::new();
+        let mut changed: Vec<TombstoneDelta> = Vec::new();
+
+        while let Some(node) = stack.pop() {
+            if node == NodeId::ROOT || node == NodeId::TRASH || node == NodeId::LOST_AND_FOUND {
+                continue;
+            }
+            if !visited.insert(node) {
+                continue;
+            }
+            let Some((parent, has_deleted_at)) = self.nodes.parent_and_has_deleted_at(node)? else {
+                continue;
+            };
+
+            if has_deleted_at {
+                let previous = self.nodes.tombstone(node)?;
+                let tombstoned = self.is_tombstoned(node)?;
+                if previous != tombstoned {
+                    self.nodes.set_tombstone(node, tombstoned)?;
+                    changed.push(TombstoneDelta {
+                        node,
+                        parent,
+                        previous,
                         tombstoned,
                         payload_after: if tombstoned {
                             None
@@ -581,27 +1386,49 @@ where
         self.storage.load_since(lamport)
     }
 
+    /// Rebuild materialized state from the op log, starting at the compaction checkpoint if any.
     pub fn replay_from_storage(&mut self) -> Result<()> {
-        self.version_vector = VersionVector::new();
-        self.nodes.reset()?;
-        self.payloads.reset()?;
-        self.head = None;
-        self.op_count = 0;
+        let base_head = match &self.base {
+            Some(base) => {
+                base.restore_into(&mut self.nodes, &mut self.payloads)?;
+                self.version_vector = base.version_vector.clone();
+                base.head.clone()
+            }
+            None => {
+                self.nodes.reset()?;
+                self.payloads.reset()?;
+                self.version_vector = VersionVector::new();
+                None
+            }
+        };
+        if let Some(base_head) = &base_head {
+            self.clock.merge(base_head.at.lamport);
+        }
 
+        let base = self.base.as_ref();
         let storage = &self.storage;
         let nodes = &mut self.nodes;
         let payloads = &mut self.payloads;
         let clock = &mut self.clock;
         let version_vector = &mut self.version_vector;
 
-        let mut seq: u64 = 0;
-        let mut head: Option<Operation> = None;
+        let mut seq: u64 = base_head.as_ref().map_or(0, |head| head.seq);
+        let mut head: Option<MaterializationFrontier> = base_head.map(|head| head.at);
         storage.scan_since(0, &mut |op| {
-            clock.observe(op.meta.lamport);
+            if base.is_some_and(|base| base.covers(&op)) {
+                return Ok(());
+            }
+            let key = frontier_from_op(&op);
+            if head.as_ref().is_some_and(|head| cmp_frontiers(&key, head).is_lt()) {
+                return Err(Error::InconsistentState(
+                    "operation sorts before the compaction checkpoint".into(),
+                ));
+            }
+            clock.merge(op.meta.lamport);
             version_vector.observe(&op.meta.id.replica, op.meta.id.counter);
             let _ = Self::apply_forward(nodes, payloads, &op)?;
             seq += 1;
-            head = Some(op);
+            head = Some(key);
             Ok(())
         })?;
 
@@ -612,6 +1439,11 @@ where
     }
 
     pub fn children(&self, parent: NodeId) -> Result<Vec<NodeId>> {
+        self.access.can_read(&self.nodes, parent)?;
+        self.visible_children(parent)
+    }
+
+    fn visible_children(&self, parent: NodeId) -> Result<Vec<NodeId>> {
         if !self.nodes.exists(parent)? {
             return Ok(Vec::new());
         }
@@ -635,23 +1467,114 @@ where
         Ok(self.nodes.parent(node)?.filter(|&p| p != NodeId::TRASH))
     }
 
+    /// The parents of `node`, nearest first, up to [`NodeId::ROOT`] (or
+    /// [`NodeId::LOST_AND_FOUND`] for rescued nodes). Empty for the roots and for nodes that are
+    /// not visible; see [`crate::navigation`].
+    pub fn ancestors(&self, node: NodeId) -> Result<Vec<NodeId>> {
+        self.access.can_read(&self.nodes, node)?;
+        Ok(navigation::ancestors(&self.nodes, node)?.unwrap_or_default())
+    }
+
+    /// How many parents `node` has up to its root; `None` if it is not visible.
+    pub fn depth(&self, node: NodeId) -> Result<Option<usize>> {
+        self.access.can_read(&self.nodes, node)?;
+        Ok(navigation::ancestors(&self.nodes, node)?.map(|ancestors| ancestors.len()))
+    }
+
+    /// Whether visible `node` sits somewhere below `ancestor`. A node is not its own descendant.
+    pub fn is_descendant(&self, node: NodeId, ancestor: NodeId) -> Result<bool> {
+        Ok(self.ancestors(node)?.contains(&ancestor))
+    }
+
+    /// Visible nodes below `root` in pre-order with their depth below it, see
+    /// [`navigation::descendants`].
+    pub fn descendants(
+        &self,
+        root: NodeId,
+        max_depth: Option<usize>,
+    ) -> Result<Descendants<'_, N>> {
+        self.access.can_read(&self.nodes, root)?;
+        Ok(navigation::descendants(&self.nodes, root, max_depth))
+    }
+
+    /// Position of `node` in [`Self::children`] of its parent; `None` for roots and hidden nodes.
+    pub fn index_in_parent(&self, node: NodeId) -> Result<Option<usize>> {
+        self.access.can_read(&self.nodes, node)?;
+        navigation::index_in_parent(&self.nodes, node)
+    }
+
     pub fn payload(&self, node: NodeId) -> Result<Option<Vec<u8>>> {
+        self.access.can_read(&self.nodes, node)?;
         self.payloads.payload(node)
     }
 
     pub fn payload_last_writer(&self, node: NodeId) -> Result<Option<(Lamport, OperationId)>> {
+        self.access.can_read(&self.nodes, node)?;
         self.payloads.last_writer(node)
     }
 
+    pub fn orphan_policy(&self) -> OrphanPolicy {
+        self.nodes.orphan_policy()
+    }
+
+    pub fn payload_mode(&self) -> PayloadMode {
+        self.payloads.mode()
+    }
+
+    /// The concurrent values of `node`'s payload in [`PayloadMode::MultiValue`], in op key order;
+    /// the last one is what [`Self::payload`] returns. More than one value means the node was
+    /// edited concurrently, until a local payload write resolves it. Always empty in
+    /// [`PayloadMode::LastWriterWins`].
+    pub fn payload_values(&self, node: NodeId) -> Result<Vec<PayloadValue>> {
+        self.access.can_read(&self.nodes, node)?;
+        self.payloads.payload_values(node)
+    }
+
+    /// Current value of one payload map field; `None` if it was never set or is cleared.
+    pub fn payload_field(&self, node: NodeId, field: &str) -> Result<Option<Vec<u8>>> {
+        self.access.can_read(&self.nodes, node)?;
+        Ok(self.payloads.payload_field(node, field)?.and_then(|entry| entry.value))
+    }
+
+    /// The set fields of `node`'s payload map, in field name order.
+    pub fn payload_fields(&self, node: NodeId) -> Result<Vec<(String, Vec<u8>)>> {
+        self.access.can_read(&self.nodes, node)?;
+        Ok(self
+            .payloads
+            .payload_fields(node)?
+            .into_iter()
+            .filter_map(|entry| entry.value.map(|value| (entry.field, value)))
+            .collect())
+    }
+
+    /// Every field entry of `node`, cleared ones and their writers included.
+    pub fn payload_field_entries(&self, node: NodeId) -> Result<Vec<PayloadFieldEntry>> {
+        self.access.can_read(&self.nodes, node)?;
+        self.payloads.payload_fields(node)
+    }
+
     pub fn is_tombstoned(&self, node: NodeId) -> Result<bool> {
-        if !self.nodes.exists(node)? {
-            return Ok(false);
+        Self::node_tombstoned(&self.nodes, &self.payloads, node)
+    }
+
+    /// Up to `limit` deleted subtrees after `after`, newest delete first. See [`crate::trash`].
+    ///
+    /// Entries the access policy hides are skipped, so a page can come back shorter than `limit`
+    /// before the listing ends.
+    pub fn trash_page(&self, after: Option<&TrashCursor>, limit: usize) -> Result<Vec<TrashEntry>> {
+        let mut page = Vec::new();
+        for entry in trash_page(&self.nodes, &self.payloads, after, limit)? {
+            match self.access.can_read(&self.nodes, entry.node) {
+                Ok(()) => page.push(entry),
+                Err(Error::AccessDenied(_)) => {}
+                Err(err) => return Err(err),
+            }
         }
-        let Some(deleted_vv) = self.nodes.deleted_at(node)? else {
-            return Ok(false);
-        };
-        let subtree_vv = self.subtree_version_vector(node)?;
-        Ok(deleted_vv.is_aware_of(&subtree_vv))
+        Ok(page)
+    }
+
+    pub fn replica_id(&self) -> &ReplicaId {
+        &self.replica_id
     }
 
     pub fn lamport(&self) -> Lamport {
@@ -661,7 +1584,11 @@ where
     pub fn nodes(&self) -> Result<Vec<(NodeId, Option<NodeId>)>> {
         let mut pairs = Vec::new();
         for id in self.nodes.all_nodes()? {
-            if id == NodeId::TRASH || id == NodeId::ROOT || self.is_tombstoned(id)? {
+            if id == NodeId::TRASH
+                || id == NodeId::ROOT
+                || id == NodeId::LOST_AND_FOUND
+                || self.is_tombstoned(id)?
+            {
                 continue;
             }
             pairs.push((id, self.nodes.parent(id)?));
@@ -673,26 +1600,10 @@ where
     /// Return the operations that contribute to the subtree's current effective state.
     ///
     /// Structural history is gap-aware, while payload history contributes only each node's
-    /// current LWW writer. Superseded payload writes do not represent surviving content and
+    /// current LWW writers (of the payload and of every payload field). Superseded payload writes do not represent surviving content and
     /// therefore cannot veto a defensive deletion.
     pub fn subtree_version_vector(&self, node: NodeId) -> Result<VersionVector> {
-        let mut subtree_vv = VersionVector::new();
-        let mut pending = vec![node];
-        let mut visited = HashSet::new();
-
-        while let Some(current) = pending.pop() {
-            if !visited.insert(current) || !self.nodes.exists(current)? {
-                continue;
-            }
-
-            subtree_vv.merge(&self.nodes.last_change(current)?);
-            if let Some((_, writer)) = self.payloads.last_writer(current)? {
-                subtree_vv.observe(&writer.replica, writer.counter);
-            }
-            pending.extend(self.nodes.children(current)?);
-        }
-
-        Ok(subtree_vv)
+        Self::subtree_vv(&self.nodes, &self.payloads, node, true)
     }
 
     pub fn export_nodes(&self) -> Result<Vec<NodeExport>> {
@@ -704,13 +1615,60 @@ where
                 children: self.nodes.children(node)?,
                 last_change: self.nodes.last_change(node)?,
                 deleted_at: self.nodes.deleted_at(node)?,
+                hard_deleted: self.nodes.hard_deleted(node)?,
             });
         }
         Ok(nodes)
     }
 
-    pub fn head_op(&self) -> Option<&Operation> {
-        self.head.as_ref()
+    /// Canonical key of the last materialized op and the number of ops materialized so far.
+    pub fn materialization_head(&self) -> Option<MaterializationHead> {
+        self.head.as_ref().map(|at| MaterializationHead {
+            at: at.clone(),
+            seq: self.op_count,
+        })
+    }
+
+    /// Capture the current materialized state as a [`Checkpoint`].
+    pub fn checkpoint(&self) -> Result<Checkpoint> {
+        Checkpoint::capture(
+            &self.nodes,
+            &self.payloads,
+            self.version_vector.clone(),
+            self.materialization_head(),
+        )
+    }
+
+    /// The checkpoint replays currently start from, if the log has been compacted.
+    pub fn compaction_checkpoint(&self) -> Option<&Checkpoint> {
+        self.base.as_ref()
+    }
+
+    /// The tree as it was at `cut`, rebuilt from the op log. See [`materialize_at`].
+    pub fn tree_at(&self, cut: &HistoryCut) -> Result<Checkpoint> {
+        materialize_at(
+            &self.storage,
+            self.base.as_ref(),
+            cut,
+            self.payload_mode(),
+            self.orphan_policy(),
+        )
+    }
+
+    /// What changed between the trees at `from` and `to`. See [`diff_between`].
+    pub fn tree_diff(
+        &self,
+        from: &HistoryCut,
+        to: &HistoryCut,
+    ) -> Result<Vec<MaterializationChange>> {
+        diff_between(
+            &self.storage,
+            self.base.as_ref(),
+            from,
+            to,
+            self.payload_mode(),
+            self.orphan_policy(),
+        )
     }
 
     pub(crate) fn node_store(&self) -> &N {
@@ -721,18 +1679,19 @@ where
         &mut self.nodes
     }
 
-    fn commit_local(&mut self, op: Operation) -> Result<Operation> {
+    /// Apply a local op, returning it with the nodes it rescued (see [`NodeSnapshot`]).
+    fn commit_local(&mut self, op: Operation) -> Result<(Operation, Vec<(NodeId, NodeId)>)> {
         self.version_vector.observe(&self.replica_id, op.meta.id.counter);
         if !self.storage.apply(op.clone())? {
-            return Ok(op);
+            return Ok((op, Vec::new()));
         }
         let snapshot = Self::apply_forward(&mut self.nodes, &mut self.payloads, &op)?;
         let mut starts = affected_parents(snapshot.parent, &op.kind);
         starts.push(op.kind.node());
         self.refresh_tombstones_upward(starts)?;
         self.op_count += 1;
-        self.head = Some(op.clone());
-        Ok(op)
+        self.head = Some(frontier_from_op(&op));
+        Ok((op, snapshot.rescued))
     }
 
     fn seed(replica: &ReplicaId, counter: u64) -> Vec<u8> {
@@ -742,6 +1701,20 @@ where
         out
     }
 
+    /// Seed for re-keying `run` between `left` and `right`, the same on every replica.
+    fn rebalance_seed(left: Option<&[u8]>, right: Option<&[u8]>, run: &[NodeId]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for bound in [left, right] {
+            let bound = bound.unwrap_or_default();
+            out.extend_from_slice(&(bound.len() as u32).to_be_bytes());
+            out.extend_from_slice(bound);
+        }
+        for node in run {
+            out.extend_from_slice(&node.0.to_be_bytes());
+        }
+        out
+    }
+
     fn allocate_child_key_after(
         &self,
         parent: NodeId,
@@ -753,8 +1726,22 @@ where
             return Ok(Vec::new());
         }
 
-        let mut children = self.children(parent)?;
-        children.retain(|child| *child != node);
+        let (left, right) = self.neighbor_keys(parent, Some(node), after)?;
+        self.order_keys.allocate_between(left.as_deref(), right.as_deref(), seed)
+    }
+
+    /// Order keys of the siblings a node placed after `after` (or first) lands between,
+    /// ignoring `exclude`.
+    fn neighbor_keys(
+        &self,
+        parent: NodeId,
+        exclude: Option<NodeId>,
+        after: Option<NodeId>,
+    ) -> Result<(Option<Vec<u8>>, Option<Vec<u8>>)> {
+        let mut children = self.visible_children(parent)?;
+        if let Some(excluded) = exclude {
+            children.retain(|child| *child != excluded);
+        }
 
         let (left, right) = if let Some(after) = after {
             let idx = children.iter().position(|c| *c == after).ok_or_else(|| {
@@ -775,8 +1762,7 @@ where
             };
             (None, right)
         };
-
-        crate::order_key::allocate_between(left.as_deref(), right.as_deref(), seed)
+        Ok((left, right))
     }
 
     fn next_counter(&mut self) -> u64 {
@@ -785,6 +1771,84 @@ where
     }
 }
 
+impl<S, C, N, P> TreeCrdt<S, C, N, P>
+where
+    S: CompactableStorage,
+    C: Clock,
+    N: NodeStore,
+    P: PayloadStore,
+{
+    /// Fold the settled prefix of the op log into a checkpoint and drop it from storage.
+    ///
+    /// Only ops inside [`StabilityTracker::settled_frontier`] of our own version vector are
+    /// folded. Once we have seen everything any peer acknowledged, an op we have not seen yet was
+    /// minted after its author acknowledged that frontier, so it sorts after every settled op.
+    /// The plain stable frontier is not enough: a peer may have acknowledged a concurrent op
+    /// that has not reached us, and it could sort inside the folded prefix. The checkpoint
+    /// covers the longest canonical-order prefix of the log made only of settled ops; settled
+    /// ops sorting after an unsettled one stay in storage.
+    ///
+    /// Returns the new checkpoint (for embedders to persist), or `None` if nothing was folded.
+    pub fn compact(&mut self, stability: &StabilityTracker) -> Result<Option<Checkpoint>> {
+        let stable = &stability.settled_frontier(&self.version_vector);
+        let base = self.base.as_ref();
+        let mut prefix = Vec::new();
+        let mut in_prefix = true;
+        self.storage.scan_since(0, &mut |op| {
+            if !in_prefix || base.is_some_and(|base| base.covers(&op)) {
+                return Ok(());
+            }
+            if stable.contains(&op.meta.id.replica, op.meta.id.counter) {
+                prefix.push(op);
+            } else {
+                in_prefix = false;
+            }
+            Ok(())
+        })?;
+        let Some(through) = prefix.last().map(frontier_from_op) else {
+            return Ok(None);
+        };
+
+        let checkpoint = if self.head.as_ref() == Some(&through) {
+            self.checkpoint()?
+        } else {
+            // Materialize just the prefix on the side; our own stores are ahead of it.
+            let mut prefix_storage = MemoryStorage::default();
+            for op in prefix {
+                prefix_storage.apply(op)?;
+            }
+            let mut scratch = TreeCrdt::with_stores(
+                self.replica_id.clone(),
+                prefix_storage,
+                LamportClock::default(),
+                MemoryNodeStore::with_orphan_policy(self.nodes.orphan_policy()),
+                MemoryPayloadStore::with_mode(self.payloads.mode()),
+            )?;
+            scratch.base = self.base.clone();
+            scratch.replay_from_storage()?;
+            scratch.checkpoint()?
+        };
+
+        self.base = Some(checkpoint.clone());
+        self.storage.discard_through(&through)?;
+        Ok(Some(checkpoint))
+    }
+}
+
+impl<S, C, N, P> TreeCrdt<S, C, N, P>
+where
+    S: Storage,
+    C: Clock,
+    N: PurgeableNodeStore,
+    P: ExactPayloadStore,
+{
+    /// Permanently drop tombstoned subtrees whose deletion lies inside the settled frontier
+    /// `stable`. See [`purge_stable_subtrees`] for what `stable` has to guarantee.
+    pub fn purge_stable(&mut self, stable: &VersionVector) -> Result<PurgeReport> {
+        purge_stable_subtrees(&mut self.nodes, &mut self.payloads, stable)
+    }
+}
+
 impl<S, C, N, P> TreeCrdt<S, C, N, P>
 where
     S: Storage,
@@ -797,7 +1861,7 @@ where
     }
 
     fn apply_forward(nodes: &mut N, payloads: &mut P, op: &Operation) -> Result<NodeSnapshot> {
-        let snapshot = Self::snapshot(nodes, op)?;
+        let mut snapshot = Self::snapshot(nodes, op)?;
         match &op.kind {
             OperationKind::Insert {
                 parent,
@@ -816,26 +1880,171 @@ where
                 order_key,
             } => Self::apply_move(nodes, op, *node, *new_parent, order_key.clone())?,
             OperationKind::Delete { node } => Self::apply_delete(nodes, op, *node)?,
-            OperationKind::Tombstone { node } => Self::apply_delete(nodes, op, *node)?,
+            OperationKind::Tombstone { node } => Self::apply_tombstone(nodes, op, *node)?,
+            OperationKind::Restore { node } => Self::apply_restore(nodes, op, *node)?,
             OperationKind::Payload { node, payload } => {
                 Self::apply_payload(nodes, payloads, op, *node, payload.as_deref())?
             }
+            OperationKind::PayloadField { node, field, value } => {
+                Self::apply_payload_field(nodes, payloads, op, *node, field, value.as_deref())?
+            }
+        }
+        if nodes.orphan_policy() == OrphanPolicy::LostAndFound {
+            Self::rescue_around(nodes, payloads, op, &mut snapshot.rescued)?;
         }
         Ok(snapshot)
     }
 
+    /// Under [`OrphanPolicy::LostAndFound`], rescue what `op` left unseen in a deleted subtree:
+    /// the subtree of a node it deleted, or of a deleted ancestor of the node it changed.
+    /// Nothing escapes from under a tombstone.
+    fn rescue_around(
+        nodes: &mut N,
+        payloads: &P,
+        op: &Operation,
+        rescued: &mut Vec<(NodeId, NodeId)>,
+    ) -> Result<()> {
+        let node = op.kind.node();
+        let mut current = match op.kind {
+            OperationKind::Delete { .. } | OperationKind::Tombstone { .. } => Some(node),
+            _ => nodes.parent(node)?,
+        };
+        let mut visited = HashSet::new();
+        let mut deleted = Vec::new();
+        while let Some(n) = current {
+            if n == NodeId::ROOT
+                || n == NodeId::TRASH
+                || n == NodeId::LOST_AND_FOUND
+                || !visited.insert(n)
+            {
+                break;
+            }
+            if nodes.hard_deleted(n)? {
+                return Ok(());
+            }
+            if nodes.has_deleted_at(n)? {
+                deleted.push(n);
+            }
+            current = nodes.parent(n)?;
+        }
+        for n in deleted {
+            Self::rescue_orphans(nodes, payloads, n, rescued)?;
+        }
+        Ok(())
+    }
+
+    /// Move the topmost nodes under the deleted `node` whose own changes its delete did not see
+    /// to [`NodeId::LOST_AND_FOUND`], and count their subtrees as seen, so the delete removes
+    /// just what it saw. Nothing moves if unseen changes of `node` itself revive it anyway.
+    fn rescue_orphans(
+        nodes: &mut N,
+        payloads: &P,
+        node: NodeId,
+        rescued: &mut Vec<(NodeId, NodeId)>,
+    ) -> Result<()> {
+        let Some(deleted_vv) = nodes.deleted_at(node)? else {
+            return Ok(());
+        };
+        let mut covered = deleted_vv.clone();
+        let mut orphans = Vec::new();
+        let mut pending = nodes.children(node)?;
+        while let Some(child) = pending.pop() {
+            if nodes.has_deleted_at(child)? && Self::node_tombstoned(nodes, payloads, child)? {
+                // Already gone through its own delete, which rescued its orphans.
+                covered.merge(&Self::subtree_vv(nodes, payloads, child, true)?);
+            } else if deleted_vv.is_aware_of(&Self::own_vv(nodes, payloads, child)?) {
+                pending.extend(nodes.children(child)?);
+            } else {
+                covered.merge(&Self::subtree_vv(nodes, payloads, child, true)?);
+                orphans.push(child);
+            }
+        }
+        if orphans.is_empty() || !covered.is_aware_of(&Self::own_vv(nodes, payloads, node)?) {
+            return Ok(());
+        }
+
+        nodes.ensure_node(NodeId::LOST_AND_FOUND)?;
+        for orphan in orphans {
+            let parent = nodes.parent(orphan)?.unwrap_or(node);
+            let order_key = nodes.order_key(orphan)?.unwrap_or_default();
+            nodes.detach(orphan)?;
+            nodes.attach(orphan, NodeId::LOST_AND_FOUND, order_key)?;
+            rescued.push((orphan, parent));
+        }
+        nodes.merge_deleted_at(node, &covered)
+    }
+
+    /// Structural history and current payload writers of `node` alone.
+    fn own_vv(nodes: &N, payloads: &P, node: NodeId) -> Result<VersionVector> {
+        let mut vv = nodes.last_change(node)?;
+        for writer in payloads.current_writers(node)? {
+            vv.observe(&writer.replica, writer.counter);
+        }
+        Ok(vv)
+    }
+
+    /// See [`Self::subtree_version_vector`]; `with_payloads: false` leaves out payload writers.
+    fn subtree_vv(
+        nodes: &N,
+        payloads: &P,
+        node: NodeId,
+        with_payloads: bool,
+    ) -> Result<VersionVector> {
+        let mut subtree_vv = VersionVector::new();
+        let mut pending = vec![node];
+        let mut visited = HashSet::new();
+
+        while let Some(current) = pending.pop() {
+            if !visited.insert(current) || !nodes.exists(current)? {
+                continue;
+            }
+
+            subtree_vv.merge(&nodes.last_change(current)?);
+            if with_payloads {
+                for writer in payloads.current_writers(current)? {
+                    subtree_vv.observe(&writer.replica, writer.counter);
+                }
+            }
+            pending.extend(nodes.children(current)?);
+        }
+
+        Ok(subtree_vv)
+    }
+
+    fn node_tombstoned(nodes: &N, payloads: &P, node: NodeId) -> Result<bool> {
+        if !nodes.exists(node)? {
+            return Ok(false);
+        }
+        let Some(deleted_vv) = nodes.deleted_at(node)? else {
+            return Ok(false);
+        };
+        if nodes.hard_deleted(node)? {
+            return Ok(true);
+        }
+        // Under `KeepParent` only inserts and moves the delete missed keep the node.
+        let with_payloads = nodes.orphan_policy() != OrphanPolicy::KeepParent;
+        let subtree_vv = Self::subtree_vv(nodes, payloads, node, with_payloads)?;
+        Ok(deleted_vv.is_aware_of(&subtree_vv))
+    }
+
     fn snapshot(nodes: &mut N, op: &Operation) -> Result<NodeSnapshot> {
         let node_id = match &op.kind {
             OperationKind::Insert { node, .. }
             | OperationKind::Move { node, .. }
             | OperationKind::Delete { node }
             | OperationKind::Tombstone { node }
-            | OperationKind::Payload { node, .. } => *node,
+            | OperationKind::Restore { node }
+            | OperationKind::Payload { node, .. }
+            | OperationKind::PayloadField { node, .. } => *node,
         };
         nodes.ensure_node(node_id)?;
         let parent = nodes.parent(node_id)?;
         let order_key = nodes.order_key(node_id)?;
-        Ok(NodeSnapshot { parent, order_key })
+        Ok(NodeSnapshot {
+            parent,
+            order_key,
+            rescued: Vec::new(),
+        })
     }
 
     fn apply_insert(
@@ -905,9 +2114,46 @@ where
         }
 
         nodes.merge_deleted_at(node, &delete_vv)?;
+
+        let newer = match nodes.deleted_by(node)? {
+            Some((lamport, id)) => {
+                cmp_op_key(
+                    op.meta.lamport,
+                    op.meta.id.replica.as_bytes(),
+                    op.meta.id.counter,
+                    lamport,
+                    id.replica.as_bytes(),
+                    id.counter,
+                ) == std::cmp::Ordering::Greater
+            }
+            None => true,
+        };
+        if newer {
+            nodes.set_deleted_by(node, Some((op.meta.lamport, op.meta.id.clone())))?;
+        }
         Ok(())
     }
 
+    /// Like a delete, but the node stays deleted whatever its subtree saw. `deleted_at` is still
+    /// merged so the tombstone refresh picks the node up.
+    fn apply_tombstone(nodes: &mut N, op: &Operation, node: NodeId) -> Result<()> {
+        if node == NodeId::ROOT || node == NodeId::TRASH {
+            return Ok(());
+        }
+        Self::apply_delete(nodes, op, node)?;
+        nodes.set_hard_deleted(node, true)
+    }
+
+    /// Record the restore as a change of `node`. No delete has seen it yet, so none of them
+    /// covers the subtree any more.
+    fn apply_restore(nodes: &mut N, op: &Operation, node: NodeId) -> Result<()> {
+        if node == NodeId::ROOT || node == NodeId::TRASH {
+            return Ok(());
+        }
+        nodes.ensure_node(node)?;
+        Self::update_structural_last_change(nodes, op, node)
+    }
+
     fn apply_payload(
         nodes: &mut N,
         payloads: &mut P,
@@ -917,6 +2163,10 @@ where
     ) -> Result<()> {
         nodes.ensure_node(node)?;
 
+        if payloads.mode() == PayloadMode::MultiValue {
+            Self::merge_payload_value(payloads, op, node, payload)?;
+        }
+
         if let Some((lamport, id)) = payloads.last_writer(node)? {
             if cmp_op_key(
                 op.meta.lamport,
@@ -939,6 +2189,93 @@ where
         Ok(())
     }
 
+    /// A store switched to multi-value on existing data has winners without values; such a
+    /// winner counts as the single value of its node.
+    fn current_payload_values(payloads: &P, node: NodeId) -> Result<Vec<PayloadValue>> {
+        let values = payloads.payload_values(node)?;
+        if !values.is_empty() {
+            return Ok(values);
+        }
+        let Some((lamport, writer)) = payloads.last_writer(node)? else {
+            return Ok(values);
+        };
+        Ok(vec![PayloadValue {
+            payload: payloads.payload(node)?,
+            lamport,
+            writer,
+            known_state: VersionVector::new(),
+        }])
+    }
+
+    /// Add the write to `node`'s concurrent values unless one of them has already seen it, and drop
+    /// the values it has seen. Since writers inherit what the values they saw had seen, the
+    /// result does not depend on arrival order.
+    fn merge_payload_value(
+        payloads: &mut P,
+        op: &Operation,
+        node: NodeId,
+        payload: Option<&[u8]>,
+    ) -> Result<()> {
+        let mut values = Self::current_payload_values(payloads, node)?;
+        if values.iter().any(|value| value.has_seen(&op.meta.id)) {
+            return Ok(());
+        }
+        let known_state = op.meta.known_state.clone().unwrap_or_default();
+        values.retain(|value| !known_state.contains(&value.writer.replica, value.writer.counter));
+        values.push(PayloadValue {
+            payload: payload.map(|bytes| bytes.to_vec()),
+            lamport: op.meta.lamport,
+            writer: op.meta.id.clone(),
+            known_state,
+        });
+        values.sort_by(|a, b| {
+            cmp_op_key(
+                a.lamport,
+                a.writer.replica.as_bytes(),
+                a.writer.counter,
+                b.lamport,
+                b.writer.replica.as_bytes(),
+                b.writer.counter,
+            )
+        });
+        payloads.set_payload_values(node, values)
+    }
+
+    fn apply_payload_field(
+        nodes: &mut N,
+        payloads: &mut P,
+        op: &Operation,
+        node: NodeId,
+        field: &str,
+        value: Option<&[u8]>,
+    ) -> Result<()> {
+        nodes.ensure_node(node)?;
+
+        if let Some(current) = payloads.payload_field(node, field)? {
+            if cmp_op_key(
+                op.meta.lamport,
+                op.meta.id.replica.as_bytes(),
+                op.meta.id.counter,
+                current.lamport,
+                current.writer.replica.as_bytes(),
+                current.writer.counter,
+            ) != std::cmp::Ordering::Greater
+            {
+                return Ok(());
+            }
+        }
+
+        payloads.set_payload_field(
+            node,
+            PayloadFieldEntry {
+                field: field.to_string(),
+                value: value.map(|bytes| bytes.to_vec()),
+                lamport: op.meta.lamport,
+                writer: op.meta.id.clone(),
+            },
+        )
+    }
+
     fn operation_version_vector(op: &Operation) -> VersionVector {
         let mut vv = VersionVector::new();
         vv.observe(&op.meta.id.replica, op.meta.id.counter);
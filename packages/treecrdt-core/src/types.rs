@@ -14,9 +14,14 @@ pub struct NodeExport {
     /// Gap-aware structural history. Effective payload awareness is derived from its LWW writer.
     pub last_change: VersionVector,
     pub deleted_at: Option<VersionVector>,
+    /// Set by a [`crate::OperationKind::Tombstone`]; the node stays deleted whatever its subtree
+    /// saw.
+    pub hard_deleted: bool,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
 pub struct NodeSnapshotExport {
     pub parent: Option<NodeId>,
     pub order_key: Option<Vec<u8>>,
@@ -56,7 +61,8 @@ impl MaterializationSource {
 /// A coalesced visible change produced while advancing materialized state.
 ///
 /// This is intentionally higher-level than raw operations: a replay pass may collapse multiple
-/// operations for the same node into one final visible insert/move/delete/restore/payload change.
+/// operations for the same node into one final visible insert/move/delete/restore/payload change,
+/// plus one change per payload field whose value moved.
 #[derive(Clone, Debug, Eq, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[cfg_attr(feature = "serde", serde(tag = "kind", rename_all = "camelCase"))]
@@ -109,6 +115,17 @@ pub enum MaterializationChange {
         )]
         source: Option<MaterializationSource>,
     },
+    /// One field of the node's payload map changed; `value: None` means it was cleared.
+    PayloadField {
+        node: NodeId,
+        field: String,
+        value: Option<Vec<u8>>,
+        #[cfg_attr(
+            feature = "serde",
+            serde(default, skip_serializing_if = "Option::is_none")
+        )]
+        source: Option<MaterializationSource>,
+    },
 }
 
 impl MaterializationChange {
@@ -118,7 +135,8 @@ impl MaterializationChange {
             | Self::Move { node, .. }
             | Self::Delete { node, .. }
             | Self::Restore { node, .. }
-            | Self::Payload { node, .. } => *node,
+            | Self::Payload { node, .. }
+            | Self::PayloadField { node, .. } => *node,
         }
     }
 
@@ -143,7 +161,7 @@ impl MaterializationChange {
             }
             Self::Delete { parent_before, .. } => nodes.extend(parent_before.iter().copied()),
             Self::Restore { parent_after, .. } => nodes.extend(parent_after.iter().copied()),
-            Self::Payload { .. } => {}
+            Self::Payload { .. } | Self::PayloadField { .. } => {}
         }
         nodes.retain(|node| *node != NodeId::TRASH);
         nodes
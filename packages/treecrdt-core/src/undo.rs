@@ -0,0 +1,174 @@
+//! Per-replica undo and redo.
+//!
+//! Nothing is ever rolled back in the op log: undoing a local op mints a new local op that
+//! reverts its effect (see [`crate::TreeCrdt::prepare_inverse`]). That op syncs and merges like
+//! any other edit, and ops other replicas made in the meantime are left alone.
+
+use crate::error::Result;
+use crate::ops::Operation;
+use crate::traits::{Clock, NodeStore, PayloadStore, Storage};
+use crate::tree::TreeCrdt;
+use crate::types::NodeSnapshotExport;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A local op plus the state it replaced, captured by [`crate::TreeCrdt::undo_record`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct UndoRecord {
+    pub op: Operation,
+    /// Parent and order key of the node right before `op` was applied.
+    pub before: NodeSnapshotExport,
+    /// Whether the node was deleted right before `op`, i.e. `op` revived it.
+    pub deleted_before: bool,
+    /// Payload of the node right before `op`, or of the written field for payload field ops;
+    /// only captured for those two kinds.
+    pub payload_before: Option<Vec<u8>>,
+}
+
+/// Undo and redo stacks of one replica's edits, grouped into transactions.
+///
+/// Every recorded op is its own transaction unless it is recorded between [`Self::begin`] and
+/// [`Self::commit`]. Transactions nest; only the outermost `commit` closes the group.
+#[derive(Clone, Debug, Default)]
+pub struct UndoManager {
+    undo: Vec<Vec<UndoRecord>>,
+    redo: Vec<Vec<UndoRecord>>,
+    open: Vec<UndoRecord>,
+    depth: usize,
+}
+
+impl UndoManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn begin(&mut self) {
+        self.depth += 1;
+    }
+
+    pub fn commit(&mut self) {
+        if self.depth == 0 {
+            return;
+        }
+        self.depth -= 1;
+        if self.depth == 0 && !self.open.is_empty() {
+            self.undo.push(std::mem::take(&mut self.open));
+        }
+    }
+
+    /// Record a new local edit. This forgets everything that could be redone.
+    pub fn record(&mut self, record: UndoRecord) {
+        self.redo.clear();
+        if self.depth > 0 {
+            self.open.push(record);
+        } else {
+            self.undo.push(vec![record]);
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty() || !self.open.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Revert the most recent transaction.
+    ///
+    /// `invert` applies the inverse of one record and returns the record of the op it committed,
+    /// or `None` if the record was superseded (see [`crate::TreeCrdt::local_inverse`]). Records
+    /// are inverted newest first; the inverses become one redo transaction. An open transaction
+    /// is closed first.
+    ///
+    /// Returns the committed inverse ops, which is empty if every record was superseded. On error
+    /// the inverses committed so far become the redo transaction and only the records not yet
+    /// inverted stay on the undo stack, so a retry does not invert anything twice. Hosts that roll
+    /// their storage back on error should put back a clone of the manager taken before the call.
+    pub fn undo_with<F>(&mut self, invert: F) -> Result<Vec<Operation>>
+    where
+        F: FnMut(&UndoRecord) -> Result<Option<UndoRecord>>,
+    {
+        self.depth = 0;
+        if !self.open.is_empty() {
+            self.undo.push(std::mem::take(&mut self.open));
+        }
+        step(&mut self.undo, &mut self.redo, invert)
+    }
+
+    /// Re-apply the most recently undone transaction. See [`Self::undo_with`].
+    pub fn redo_with<F>(&mut self, invert: F) -> Result<Vec<Operation>>
+    where
+        F: FnMut(&UndoRecord) -> Result<Option<UndoRecord>>,
+    {
+        step(&mut self.redo, &mut self.undo, invert)
+    }
+
+    /// [`Self::undo_with`] against an in-memory tree.
+    pub fn undo<S, C, N, P>(&mut self, crdt: &mut TreeCrdt<S, C, N, P>) -> Result<Vec<Operation>>
+    where
+        S: Storage,
+        C: Clock,
+        N: NodeStore,
+        P: PayloadStore,
+    {
+        self.undo_with(|record| Ok(crdt.local_inverse(record)?.map(|(_, _, inverse)| inverse)))
+    }
+
+    /// [`Self::redo_with`] against an in-memory tree.
+    pub fn redo<S, C, N, P>(&mut self, crdt: &mut TreeCrdt<S, C, N, P>) -> Result<Vec<Operation>>
+    where
+        S: Storage,
+        C: Clock,
+        N: NodeStore,
+        P: PayloadStore,
+    {
+        self.redo_with(|record| Ok(crdt.local_inverse(record)?.map(|(_, _, inverse)| inverse)))
+    }
+}
+
+/// Invert the top transaction of `from`, newest record first, and push the inverses onto `to`.
+/// If a record fails, it and the older ones go back onto `from`.
+fn step<F>(
+    from: &mut Vec<Vec<UndoRecord>>,
+    to: &mut Vec<Vec<UndoRecord>>,
+    mut invert: F,
+) -> Result<Vec<Operation>>
+where
+    F: FnMut(&UndoRecord) -> Result<Option<UndoRecord>>,
+{
+    let Some(mut group) = from.pop() else {
+        return Ok(Vec::new());
+    };
+    let mut inverses = Vec::new();
+    let mut failed = None;
+    while let Some(record) = group.pop() {
+        match invert(&record) {
+            Ok(Some(inverse)) => inverses.push(inverse),
+            Ok(None) => {}
+            Err(err) => {
+                group.push(record);
+                failed = Some(err);
+                break;
+            }
+        }
+    }
+    let ops = inverses.iter().map(|record| record.op.clone()).collect();
+    if !inverses.is_empty() {
+        to.push(inverses);
+    }
+    match failed {
+        Some(err) => {
+            from.push(group);
+            Err(err)
+        }
+        None => Ok(ops),
+    }
+}
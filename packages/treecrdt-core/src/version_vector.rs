@@ -184,6 +184,40 @@ impl ReplicaVersion {
             self.absorb_frontier_ranges();
         }
     }
+
+    fn intervals(&self) -> Vec<(u64, u64)> {
+        let mut out = Vec::with_capacity(self.ranges.len() + 1);
+        if self.frontier > 0 {
+            out.push((1, self.frontier));
+        }
+        out.extend(self.ranges.iter().copied());
+        out
+    }
+
+    fn intersection(&self, other: &ReplicaVersion) -> ReplicaVersion {
+        let (a, b) = (self.intervals(), other.intervals());
+        let (mut i, mut j) = (0, 0);
+        let mut common: Vec<(u64, u64)> = Vec::new();
+        while i < a.len() && j < b.len() {
+            let start = a[i].0.max(b[j].0);
+            let end = a[i].1.min(b[j].1);
+            if start <= end {
+                common.push((start, end));
+            }
+            if a[i].1 < b[j].1 {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+
+        let mut out = ReplicaVersion::default();
+        if common.first().map(|&(s, _)| s) == Some(1) {
+            out.frontier = common.remove(0).1;
+        }
+        out.ranges = common;
+        out
+    }
 }
 
 /// Gap-aware version vector (frontier + ranges) keyed by per-replica operation counters.
@@ -289,6 +323,21 @@ impl VersionVector {
         }
     }
 
+    /// Greatest lower bound: the ops observed by both `self` and `other`.
+    pub fn intersection(&self, other: &VersionVector) -> VersionVector {
+        let mut entries = HashMap::new();
+        for (replica, version) in &self.entries {
+            let Some(other_version) = other.entries.get(replica) else {
+                continue;
+            };
+            let common = version.intersection(other_version);
+            if common.frontier > 0 || !common.ranges.is_empty() {
+                entries.insert(replica.clone(), common);
+            }
+        }
+        VersionVector { entries }
+    }
+
     pub fn is_aware_of(&self, other: &VersionVector) -> bool {
         for (replica, other_replica) in &other.entries {
             let self_replica = self.entries.get(replica).cloned().unwrap_or_default();
@@ -299,6 +348,11 @@ impl VersionVector {
         true
     }
 
+    /// Check whether the op `(replica, counter)` has been observed, honoring gaps.
+    pub fn contains(&self, replica: &ReplicaId, counter: u64) -> bool {
+        self.entries.get(replica).is_some_and(|v| v.contains_range(counter, counter))
+    }
+
     /// Get the maximum observed counter for a specific replica, or 0 if not present.
     ///
     /// Note: this is NOT the contiguous frontier; use `frontier()` when you need gap-aware semantics.
@@ -328,4 +382,25 @@ impl VersionVector {
     pub fn frontiers(&self) -> HashMap<ReplicaId, u64> {
         self.entries.iter().map(|(replica, v)| (replica.clone(), v.frontier)).collect()
     }
+
+    /// Every replica's frontier and extra ranges, sorted by replica.
+    pub(crate) fn raw_entries(&self) -> Vec<(&ReplicaId, u64, &[(u64, u64)])> {
+        let mut entries: Vec<_> = self
+            .entries
+            .iter()
+            .map(|(replica, v)| (replica, v.frontier, v.ranges.as_slice()))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        entries
+    }
+
+    /// Set one replica's entry as-is. The caller guarantees the ranges invariant.
+    pub(crate) fn insert_raw(
+        &mut self,
+        replica: ReplicaId,
+        frontier: u64,
+        ranges: Vec<(u64, u64)>,
+    ) {
+        self.entries.insert(replica, ReplicaVersion { frontier, ranges });
+    }
 }
@@ -0,0 +1,116 @@
+use treecrdt_core::{
+    AccessControl, Error, LamportClock, LocalPlacement, MemoryStorage, NodeId, NodeStore,
+    Operation, OperationKind, ReplicaId, Result, TreeCrdt,
+};
+
+/// Denies payload writes everywhere and any access to one fenced-off node.
+struct DenyPayloadsAndNode(NodeId);
+
+impl AccessControl for DenyPayloadsAndNode {
+    fn can_apply(&self, _nodes: &dyn NodeStore, op: &Operation) -> Result<()> {
+        if matches!(op.kind, OperationKind::Payload { .. }) {
+            return Err(Error::AccessDenied("payload writes are not allowed".into()));
+        }
+        if op.kind.node() == self.0 {
+            return Err(Error::AccessDenied("node is fenced off".into()));
+        }
+        Ok(())
+    }
+
+    fn can_read(&self, _nodes: &dyn NodeStore, node: NodeId) -> Result<()> {
+        if node == self.0 {
+            return Err(Error::AccessDenied("node is fenced off".into()));
+        }
+        Ok(())
+    }
+}
+
+fn new_crdt(replica: &[u8]) -> TreeCrdt<MemoryStorage, LamportClock> {
+    TreeCrdt::new(
+        ReplicaId::new(replica),
+        MemoryStorage::default(),
+        LamportClock::default(),
+    )
+    .unwrap()
+}
+
+#[test]
+fn default_policy_allows_everything() {
+    let mut crdt = new_crdt(b"a");
+    crdt.local_insert(
+        NodeId::ROOT,
+        NodeId(1),
+        LocalPlacement::First,
+        Some(vec![1]),
+    )
+    .unwrap();
+    crdt.local_payload(NodeId(1), Some(vec![2])).unwrap();
+    assert_eq!(crdt.children(NodeId::ROOT).unwrap(), vec![NodeId(1)]);
+    assert_eq!(crdt.payload(NodeId(1)).unwrap(), Some(vec![2]));
+}
+
+#[test]
+fn denied_remote_ops_are_not_stored_or_applied() {
+    let fenced = NodeId(2);
+    let mut source = new_crdt(b"a");
+    let (allowed, _) = source
+        .local_insert(NodeId::ROOT, NodeId(1), LocalPlacement::First, None)
+        .unwrap();
+    let (denied_insert, _) =
+        source.local_insert(NodeId::ROOT, fenced, LocalPlacement::Last, None).unwrap();
+    let (denied_payload, _) = source.local_payload(NodeId(1), Some(vec![9])).unwrap();
+
+    let mut target = new_crdt(b"b");
+    target.set_access_control(DenyPayloadsAndNode(fenced));
+    target.apply_remote(allowed).unwrap();
+    assert!(matches!(
+        target.apply_remote(denied_insert),
+        Err(Error::AccessDenied(_))
+    ));
+    assert!(matches!(
+        target.apply_remote(denied_payload),
+        Err(Error::AccessDenied(_))
+    ));
+
+    assert_eq!(target.children(NodeId::ROOT).unwrap(), vec![NodeId(1)]);
+    assert_eq!(target.payload(NodeId(1)).unwrap(), None);
+    assert_eq!(target.operations_since(0).unwrap().len(), 1);
+}
+
+#[test]
+fn denied_local_ops_do_not_consume_a_counter() {
+    let fenced = NodeId(2);
+    let mut crdt = new_crdt(b"a");
+    crdt.set_access_control(DenyPayloadsAndNode(fenced));
+    assert!(matches!(
+        crdt.local_insert(NodeId::ROOT, fenced, LocalPlacement::First, None),
+        Err(Error::AccessDenied(_))
+    ));
+    let (op, _) = crdt.local_insert(NodeId::ROOT, NodeId(1), LocalPlacement::First, None).unwrap();
+    assert_eq!(op.meta.id.counter, 1);
+    assert!(matches!(
+        crdt.local_payload(NodeId(1), Some(vec![1])),
+        Err(Error::AccessDenied(_))
+    ));
+    let (op, _) = crdt.local_move(NodeId(1), NodeId::ROOT, LocalPlacement::Last).unwrap();
+    assert_eq!(op.meta.id.counter, 2);
+}
+
+#[test]
+fn denied_reads_return_access_denied() {
+    let fenced = NodeId(2);
+    let mut crdt = new_crdt(b"a");
+    crdt.local_insert(NodeId::ROOT, fenced, LocalPlacement::First, Some(vec![1]))
+        .unwrap();
+    crdt.local_insert(fenced, NodeId(3), LocalPlacement::First, None).unwrap();
+    crdt.set_access_control(DenyPayloadsAndNode(fenced));
+
+    assert!(matches!(crdt.children(fenced), Err(Error::AccessDenied(_))));
+    assert!(matches!(crdt.payload(fenced), Err(Error::AccessDenied(_))));
+    assert!(matches!(
+        crdt.payload_last_writer(fenced),
+        Err(Error::AccessDenied(_))
+    ));
+    assert_eq!(crdt.children(NodeId::ROOT).unwrap(), vec![fenced]);
+    assert_eq!(crdt.payload(NodeId(3)).unwrap(), None);
+}
@@ -0,0 +1,137 @@
+use proptest::prelude::*;
+use treecrdt_core::{
+    decode_op, decode_ops, decode_version_vector, encode_op, encode_ops, encode_version_vector,
+    Error, NodeId, Operation, OperationGroup, ReplicaId, VersionVector,
+};
+
+fn sample_ops() -> Vec<Operation> {
+    let a = ReplicaId::new(b"alice");
+    let b = ReplicaId::new(b"bob");
+    let mut known = VersionVector::new();
+    for counter in [1, 2, 3, 7, 8, 20] {
+        known.observe(&a, counter);
+    }
+    known.observe(&b, 1);
+    vec![
+        Operation::insert(&a, 1, 1, NodeId::ROOT, NodeId(1), vec![0x80]),
+        Operation::insert_with_payload(&b, 1, 2, NodeId(1), NodeId(2), vec![0x40], b"hi".to_vec()),
+        Operation::move_node(&a, 2, 3, NodeId(2), NodeId::ROOT, vec![0x90, 0x01]),
+        Operation::delete(&a, 3, 4, NodeId(1), Some(known)),
+        Operation::tombstone(&b, 2, 5, NodeId(2)),
+        Operation::restore(&a, 9, 10, NodeId(1)),
+        Operation::set_payload(&a, 4, 6, NodeId(u128::MAX - 1), vec![]),
+        Operation::clear_payload(&b, 3, u64::MAX, NodeId(2)),
+        Operation::payload_field(&a, 5, 7, NodeId(2), "title", Some(b"Groceries".to_vec())),
+        Operation::payload_field(&b, 4, 8, NodeId(2), "", None),
+        Operation::move_node(&a, 8, 9, NodeId(2), NodeId(1), vec![0x10])
+            .with_group(OperationGroup { first: 7, len: 3 }),
+    ]
+}
+
+#[test]
+fn ops_and_batches_roundtrip() {
+    let ops = sample_ops();
+    for op in &ops {
+        assert_eq!(&decode_op(&encode_op(op)).unwrap(), op);
+    }
+    let bytes = encode_ops(&ops);
+    assert_eq!(decode_ops(&bytes).unwrap(), ops);
+    assert!(decode_ops(&encode_ops(&[])).unwrap().is_empty());
+
+    // Each replica id is spelled out once per batch.
+    let spelled = bytes.windows(5).filter(|window| *window == b"alice").count();
+    assert_eq!(spelled, 1);
+}
+
+#[test]
+fn equal_version_vectors_encode_to_equal_bytes() {
+    let a = ReplicaId::new(b"a");
+    let b = ReplicaId::new(b"b");
+    let mut left = VersionVector::new();
+    let mut right = VersionVector::new();
+    for counter in [5, 1, 2, 9] {
+        left.observe(&a, counter);
+    }
+    left.observe(&b, 3);
+    right.observe(&b, 3);
+    for counter in [9, 2, 5, 1] {
+        right.observe(&a, counter);
+    }
+
+    let bytes = encode_version_vector(&left);
+    assert_eq!(bytes, encode_version_vector(&right));
+    assert_eq!(decode_version_vector(&bytes).unwrap(), left);
+}
+
+#[test]
+fn non_canonical_input_is_rejected() {
+    let op = &sample_ops()[0];
+    let bytes = encode_op(op);
+    let rejects = |bytes: &[u8]| matches!(decode_op(bytes), Err(Error::InvalidOperation(_)));
+
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert!(rejects(&trailing));
+    assert!(rejects(&bytes[..bytes.len() - 1]));
+
+    let mut version = bytes.clone();
+    version[0] = 2;
+    assert!(rejects(&version));
+    assert!(matches!(
+        decode_ops(&bytes),
+        Err(Error::InvalidOperation(_))
+    ));
+
+    // An unused dictionary entry ahead of "alice".
+    let mut unused = vec![bytes[0], bytes[1], 2, 1, b'0'];
+    unused.extend_from_slice(&bytes[3..]);
+    assert!(rejects(&unused));
+
+    // Counter 1 spelled as a two-byte varint.
+    let header = 2 + 1 + 1 + b"alice".len();
+    let mut overlong = bytes[..header + 2].to_vec();
+    overlong.extend_from_slice(&[0x81, 0x00]);
+    overlong.extend_from_slice(&bytes[header + 3..]);
+    assert!(rejects(&overlong));
+
+    // A group that does not contain the op's own counter.
+    let outside = sample_ops()[0].clone().with_group(OperationGroup { first: 2, len: 2 });
+    assert!(rejects(&encode_op(&outside)));
+}
+
+proptest! {
+    #[test]
+    fn arbitrary_batches_roundtrip(
+        raw in prop::collection::vec(
+            (0u8..5, 0u8..3, 1u64..1_000, any::<u64>(), any::<u128>(), any::<u128>(),
+             prop::collection::vec(any::<u8>(), 0..8), prop::option::of(prop::collection::vec(any::<u8>(), 0..8)),
+             prop::collection::vec((0u8..3, 1u64..64), 0..6)),
+            0..20,
+        )
+    ) {
+        let ops: Vec<Operation> = raw
+            .into_iter()
+            .map(|(kind, writer, counter, lamport, node, parent, key, payload, seen)| {
+                let replica = ReplicaId::new(vec![b'r', writer]);
+                let mut known = VersionVector::new();
+                for (writer, counter) in seen {
+                    known.observe(&ReplicaId::new(vec![b'r', writer]), counter);
+                }
+                let (node, parent) = (NodeId(node), NodeId(parent));
+                match kind {
+                    0 => Operation::insert_with_optional_payload(
+                        &replica, counter, lamport, parent, node, key, payload,
+                    ),
+                    1 => Operation::move_node(&replica, counter, lamport, node, parent, key),
+                    2 => Operation::delete(&replica, counter, lamport, node, Some(known)),
+                    3 => Operation::tombstone(&replica, counter, lamport, node),
+                    _ => Operation::payload(&replica, counter, lamport, node, payload),
+                }
+            })
+            .collect();
+        let bytes = encode_ops(&ops);
+        let decoded = decode_ops(&bytes).unwrap();
+        prop_assert_eq!(&decoded, &ops);
+        prop_assert_eq!(encode_ops(&decoded), bytes);
+    }
+}
@@ -0,0 +1,25 @@
+//! Fixtures shared by the integration tests. Each test crate uses only some of them.
+#![allow(dead_code)]
+
+use treecrdt_core::{LamportClock, MemoryStorage, Operation, ReplicaId, TreeCrdt, VersionVector};
+
+pub type Tree = TreeCrdt<MemoryStorage, LamportClock>;
+
+/// An empty in-memory tree for `replica`.
+pub fn tree(replica: &ReplicaId) -> Tree {
+    TreeCrdt::new(
+        replica.clone(),
+        MemoryStorage::default(),
+        LamportClock::default(),
+    )
+    .unwrap()
+}
+
+/// The version vector that has seen exactly `ops`.
+pub fn vv_of<'a>(ops: impl IntoIterator<Item = &'a Operation>) -> VersionVector {
+    let mut vv = VersionVector::new();
+    for op in ops {
+        vv.observe(&op.meta.id.replica, op.meta.id.counter);
+    }
+    vv
+}
@@ -0,0 +1,177 @@
+use treecrdt_core::{
+    Error, LamportClock, LocalPlacement, MemoryNodeStore, MemoryPayloadStore, MemoryStorage,
+    NodeId, Operation, ReplicaId, StabilityTracker, Storage, TreeCrdt, VersionVector,
+};
+
+mod common;
+
+use common::{tree, vv_of, Tree};
+
+fn rebuild(replica: &ReplicaId, from: &Tree) -> Tree {
+    let mut storage = MemoryStorage::default();
+    for op in from.operations_since(0).unwrap() {
+        storage.apply(op).unwrap();
+    }
+    let checkpoint = from.compaction_checkpoint().cloned().expect("compacted");
+    TreeCrdt::from_checkpoint(
+        replica.clone(),
+        storage,
+        LamportClock::default(),
+        MemoryNodeStore::default(),
+        MemoryPayloadStore::default(),
+        checkpoint,
+    )
+    .unwrap()
+}
+
+fn state(crdt: &Tree) -> Vec<(NodeId, Option<NodeId>, Option<Vec<u8>>)> {
+    let mut out = Vec::new();
+    for (node, parent) in crdt.nodes().unwrap() {
+        out.push((node, parent, crdt.payload(node).unwrap()));
+    }
+    out
+}
+
+fn acked_by(peer: &ReplicaId, acked: &VersionVector) -> StabilityTracker {
+    let mut stability = StabilityTracker::new();
+    stability.acknowledge(peer, acked);
+    stability
+}
+
+#[test]
+fn compacting_everything_preserves_state_and_local_ids() {
+    let a = ReplicaId::new(b"a");
+    let mut crdt = tree(&a);
+    crdt.local_insert(
+        NodeId::ROOT,
+        NodeId(1),
+        LocalPlacement::First,
+        Some(b"one".to_vec()),
+    )
+    .unwrap();
+    crdt.local_insert(NodeId::ROOT, NodeId(2), LocalPlacement::Last, None).unwrap();
+    crdt.local_insert(NodeId(1), NodeId(3), LocalPlacement::First, None).unwrap();
+    crdt.local_move(NodeId(3), NodeId(2), LocalPlacement::First).unwrap();
+    let (last, _) = crdt.local_delete(NodeId(1)).unwrap();
+
+    let stable = acked_by(&a, &vv_of(&crdt.operations_since(0).unwrap()));
+    let checkpoint = crdt.compact(&stable).unwrap().expect("prefix folded");
+    assert_eq!(checkpoint.head_seq(), 5);
+    assert!(crdt.operations_since(0).unwrap().is_empty());
+    assert!(crdt.compact(&stable).unwrap().is_none());
+
+    // Replays now start from the checkpoint instead of an empty tree.
+    let before = state(&crdt);
+    crdt.replay_from_storage().unwrap();
+    assert_eq!(state(&crdt), before);
+
+    let mut restored = rebuild(&a, &crdt);
+    assert_eq!(state(&restored), before);
+    assert_eq!(restored.children(NodeId(2)).unwrap(), vec![NodeId(3)]);
+    assert_eq!(restored.materialization_head(), crdt.materialization_head());
+
+    let (next, _) = restored
+        .local_insert(NodeId::ROOT, NodeId(4), LocalPlacement::Last, None)
+        .unwrap();
+    assert_eq!(next.meta.id.counter, last.meta.id.counter + 1);
+    assert!(next.meta.lamport > last.meta.lamport);
+}
+
+#[test]
+fn compaction_stops_at_first_unstable_op() {
+    let a = ReplicaId::new(b"a");
+    let b = ReplicaId::new(b"b");
+    let mut crdt = tree(&a);
+
+    let (a1, _) = crdt.local_insert(NodeId::ROOT, NodeId(1), LocalPlacement::First, None).unwrap();
+    let b1 = Operation::insert(&b, 1, 2, NodeId::ROOT, NodeId(2), vec![0, 2]);
+    crdt.apply_remote(b1.clone()).unwrap();
+    let (a2, _) = crdt.local_payload(NodeId(1), Some(b"x".to_vec())).unwrap();
+
+    // `b1` has not been acknowledged by everyone, so `a2` (which sorts after it) stays in the log.
+    let stable = acked_by(&a, &vv_of([&a1, &a2]));
+    let checkpoint = crdt.compact(&stable).unwrap().expect("prefix folded");
+    assert_eq!(checkpoint.head_seq(), 1);
+    assert!(checkpoint.payloads.is_empty());
+    assert_eq!(crdt.operations_since(0).unwrap().len(), 2);
+
+    let restored = rebuild(&a, &crdt);
+    assert_eq!(state(&restored), state(&crdt));
+    assert_eq!(restored.payload(NodeId(1)).unwrap(), Some(b"x".to_vec()));
+
+    // Redelivering a folded op is a no-op.
+    assert!(crdt.apply_remote_with_delta(a1.clone()).unwrap().is_none());
+
+    let stable = acked_by(&a, &vv_of([&a1, &b1, &a2]));
+    let checkpoint = crdt.compact(&stable).unwrap().expect("rest folded");
+    assert_eq!(checkpoint.head_seq(), 3);
+    assert!(crdt.operations_since(0).unwrap().is_empty());
+    assert_eq!(
+        rebuild(&a, &crdt).children(NodeId::ROOT).unwrap(),
+        crdt.children(NodeId::ROOT).unwrap()
+    );
+}
+
+#[test]
+fn compaction_waits_for_ops_other_peers_acknowledged() {
+    let l = ReplicaId::new(b"l");
+    let a = ReplicaId::new(b"a");
+    let b = ReplicaId::new(b"b");
+    let mut crdt = tree(&l);
+
+    let a1 = Operation::insert(&a, 1, 5, NodeId::ROOT, NodeId(10), vec![0x40]);
+    let b1 = Operation::insert(&b, 1, 1, NodeId::ROOT, NodeId(20), vec![0x80]);
+    crdt.apply_remote(a1.clone()).unwrap();
+
+    // Everyone has seen `a1`, so it is stable, but `a` and `b` also saw the concurrent `b1`,
+    // which sorts before it and has not reached us yet.
+    let mut stability = StabilityTracker::new();
+    stability.acknowledge(&l, &vv_of([&a1]));
+    stability.acknowledge(&a, &vv_of([&a1, &b1]));
+    stability.acknowledge(&b, &vv_of([&a1, &b1]));
+    assert!(crdt.compact(&stability).unwrap().is_none());
+
+    crdt.apply_remote(b1.clone()).unwrap();
+    assert_eq!(
+        crdt.children(NodeId::ROOT).unwrap(),
+        vec![NodeId(10), NodeId(20)]
+    );
+
+    stability.acknowledge(&l, &vv_of([&a1, &b1]));
+    let checkpoint = crdt.compact(&stability).unwrap().expect("settled prefix folded");
+    assert_eq!(checkpoint.head_seq(), 2);
+    assert_eq!(
+        rebuild(&l, &crdt).children(NodeId::ROOT).unwrap(),
+        vec![NodeId(10), NodeId(20)]
+    );
+}
+
+#[test]
+fn unseen_op_inside_the_compacted_prefix_is_an_error() {
+    let a = ReplicaId::new(b"a");
+    let mut crdt = tree(&a);
+    let (a1, _) = crdt.local_insert(NodeId::ROOT, NodeId(1), LocalPlacement::First, None).unwrap();
+    crdt.local_insert(NodeId::ROOT, NodeId(2), LocalPlacement::Last, None).unwrap();
+
+    // A tracker that does not know about `c` declares everything settled.
+    let stable = acked_by(&a, &vv_of(&crdt.operations_since(0).unwrap()));
+    crdt.compact(&stable).unwrap().expect("prefix folded");
+
+    let c1 = Operation::insert(
+        &ReplicaId::new(b"c"),
+        1,
+        1,
+        NodeId::ROOT,
+        NodeId(3),
+        vec![0x20],
+    );
+    assert!(matches!(
+        crdt.apply_remote(c1),
+        Err(Error::InconsistentState(_))
+    ));
+    // The rejected op must not count as seen.
+    let version_vector = crdt.checkpoint().unwrap().version_vector;
+    assert_eq!(version_vector.get(&ReplicaId::new(b"c")), 0);
+    // Folded ops are still recognised as duplicates.
+    crdt.apply_remote(a1).unwrap();
+}
@@ -1,7 +1,38 @@
 use treecrdt_core::{
-    LamportClock, LocalPlacement, MemoryStorage, NodeId, Operation, ReplicaId, TreeCrdt,
+    Anchored, LamportClock, LocalPlacement, MemoryStorage, NodeId, Operation, ReplicaId, TreeCrdt,
 };
 
+fn anchored_crdt(replica: &[u8]) -> TreeCrdt<MemoryStorage, LamportClock> {
+    let mut crdt = TreeCrdt::new(
+        ReplicaId::new(replica),
+        MemoryStorage::default(),
+        LamportClock::default(),
+    )
+    .unwrap();
+    crdt.set_order_key_allocator(Anchored);
+    crdt
+}
+
+fn exchange(
+    a: &mut TreeCrdt<MemoryStorage, LamportClock>,
+    b: &mut TreeCrdt<MemoryStorage, LamportClock>,
+) {
+    let (ops_a, ops_b) = (
+        a.operations_since(0).unwrap(),
+        b.operations_since(0).unwrap(),
+    );
+    for op in ops_b {
+        a.apply_remote(op).unwrap();
+    }
+    for op in ops_a {
+        b.apply_remote(op).unwrap();
+    }
+}
+
+fn is_contiguous(children: &[NodeId], run: &[NodeId]) -> bool {
+    children.windows(run.len()).any(|window| window == run)
+}
+
 #[test]
 fn higher_lamport_wins_on_conflict() {
     let mut crdt_a = TreeCrdt::new(
@@ -115,3 +146,110 @@ fn same_lamport_orders_by_op_id() {
     // ReplicaId "b" > "a" so move_b wins at equal lamport
     assert_eq!(crdt.parent(x).unwrap(), Some(b));
 }
+
+#[test]
+fn anchored_concurrent_runs_between_the_same_neighbours_stay_contiguous() {
+    let root = NodeId::ROOT;
+    let left = NodeId(1);
+    let right = NodeId(2);
+    let mut crdt_a = anchored_crdt(b"a");
+    crdt_a.local_insert(root, left, LocalPlacement::Last, None).unwrap();
+    crdt_a.local_insert(root, right, LocalPlacement::Last, None).unwrap();
+    let mut crdt_b = anchored_crdt(b"b");
+    exchange(&mut crdt_a, &mut crdt_b);
+
+    // both replicas type a run right after `left`
+    let run_a: Vec<NodeId> = (10..20).map(NodeId).collect();
+    let run_b: Vec<NodeId> = (20..30).map(NodeId).collect();
+    for (crdt, run) in [(&mut crdt_a, &run_a), (&mut crdt_b, &run_b)] {
+        let mut prev = left;
+        for node in run {
+            crdt.local_insert(root, *node, LocalPlacement::After(prev), None).unwrap();
+            prev = *node;
+        }
+    }
+    exchange(&mut crdt_a, &mut crdt_b);
+
+    let children = crdt_a.children(root).unwrap();
+    assert_eq!(children, crdt_b.children(root).unwrap());
+    assert_eq!(children.len(), 22);
+    assert_eq!(children.first(), Some(&left));
+    assert_eq!(children.last(), Some(&right));
+    assert!(is_contiguous(&children, &run_a));
+    assert!(is_contiguous(&children, &run_b));
+}
+
+#[test]
+fn anchored_runs_of_replicas_with_similar_ids_stay_apart() {
+    // these ids collide in the low 32 bits of the order key hash and both replicas mint their
+    // runs with the same counters; only the full ids tell their waypoints apart
+    let root = NodeId::ROOT;
+    let left = NodeId(1);
+    let mut crdt_a = anchored_crdt(b"replica-287659");
+    let mut crdt_b = anchored_crdt(b"replica-1292194");
+    let (insert_left, _) = crdt_a.local_insert(root, left, LocalPlacement::Last, None).unwrap();
+    crdt_b.apply_remote(insert_left).unwrap();
+
+    let run_a: Vec<NodeId> = (10..20).map(NodeId).collect();
+    let run_b: Vec<NodeId> = (20..30).map(NodeId).collect();
+    for (crdt, run) in [(&mut crdt_a, &run_a), (&mut crdt_b, &run_b)] {
+        let mut prev = left;
+        for node in run {
+            crdt.local_insert(root, *node, LocalPlacement::After(prev), None).unwrap();
+            prev = *node;
+        }
+    }
+    exchange(&mut crdt_a, &mut crdt_b);
+
+    let children = crdt_a.children(root).unwrap();
+    assert_eq!(children, crdt_b.children(root).unwrap());
+    assert!(is_contiguous(&children, &run_a));
+    assert!(is_contiguous(&children, &run_b));
+}
+
+#[test]
+fn anchored_concurrent_runs_typed_backwards_stay_contiguous() {
+    let root = NodeId::ROOT;
+    let left = NodeId(1);
+    let mut crdt_a = anchored_crdt(b"a");
+    crdt_a.local_insert(root, left, LocalPlacement::Last, None).unwrap();
+    let mut crdt_b = anchored_crdt(b"b");
+    exchange(&mut crdt_a, &mut crdt_b);
+
+    // each node goes right after `left`, in front of the previous one
+    for n in 10..15 {
+        crdt_a.local_insert(root, NodeId(n), LocalPlacement::After(left), None).unwrap();
+        crdt_b
+            .local_insert(root, NodeId(n + 10), LocalPlacement::After(left), None)
+            .unwrap();
+    }
+    exchange(&mut crdt_a, &mut crdt_b);
+
+    let children = crdt_a.children(root).unwrap();
+    assert_eq!(children, crdt_b.children(root).unwrap());
+    let run_a: Vec<NodeId> = (10..15).rev().map(NodeId).collect();
+    let run_b: Vec<NodeId> = (20..25).rev().map(NodeId).collect();
+    assert!(is_contiguous(&children, &run_a));
+    assert!(is_contiguous(&children, &run_b));
+}
+
+#[test]
+fn anchored_runs_into_an_empty_parent_stay_contiguous() {
+    let root = NodeId::ROOT;
+    let mut crdt_a = anchored_crdt(b"a");
+    let mut crdt_b = anchored_crdt(b"b");
+    let run_a: Vec<NodeId> = (100..150).map(NodeId).collect();
+    let run_b: Vec<NodeId> = (200..250).map(NodeId).collect();
+    for (crdt, run) in [(&mut crdt_a, &run_a), (&mut crdt_b, &run_b)] {
+        crdt.local_insert(root, run[0], LocalPlacement::First, None).unwrap();
+        for pair in run.windows(2) {
+            crdt.local_insert(root, pair[1], LocalPlacement::After(pair[0]), None).unwrap();
+        }
+    }
+    exchange(&mut crdt_a, &mut crdt_b);
+
+    let children = crdt_a.children(root).unwrap();
+    assert_eq!(children, crdt_b.children(root).unwrap());
+    assert!(is_contiguous(&children, &run_a));
+    assert!(is_contiguous(&children, &run_b));
+}
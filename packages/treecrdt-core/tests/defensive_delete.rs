@@ -1,5 +1,6 @@
 use treecrdt_core::{
-    LamportClock, LocalPlacement, MemoryStorage, NodeId, NoopParentOpIndex, ReplicaId, TreeCrdt,
+    Error, LamportClock, LocalPlacement, MemoryStorage, NodeId, NoopParentOpIndex, ReplicaId,
+    TreeCrdt,
 };
 
 #[test]
@@ -934,3 +935,53 @@ fn materialized_apply_delta_includes_parent_restored_by_unseen_payload_change()
         "delta should include ancestor tombstone flip"
     );
 }
+
+#[test]
+fn tombstone_ignores_unseen_subtree_changes_and_cannot_be_restored() {
+    let mut crdt_a = TreeCrdt::new(
+        ReplicaId::new(b"a"),
+        MemoryStorage::default(),
+        LamportClock::default(),
+    )
+    .unwrap();
+    let mut crdt_b = TreeCrdt::new(
+        ReplicaId::new(b"b"),
+        MemoryStorage::default(),
+        LamportClock::default(),
+    )
+    .unwrap();
+
+    let parent = NodeId(1);
+    let child = NodeId(2);
+
+    let (parent_op, _) =
+        crdt_a.local_insert(NodeId::ROOT, parent, LocalPlacement::First, None).unwrap();
+    crdt_b.apply_remote(parent_op).unwrap();
+
+    // Either of these would revive a parent removed by a plain delete.
+    let (insert_child_op, _) =
+        crdt_b.local_insert(parent, child, LocalPlacement::First, None).unwrap();
+    let (set_payload_op, _) = crdt_b.local_payload(parent, Some(b"hello".to_vec())).unwrap();
+
+    let (tombstone_op, _) = crdt_a.local_tombstone(parent).unwrap();
+    assert!(crdt_a.is_tombstoned(parent).unwrap());
+
+    crdt_a.apply_remote(insert_child_op).unwrap();
+    crdt_a.apply_remote(set_payload_op).unwrap();
+    crdt_b.apply_remote(tombstone_op).unwrap();
+    for crdt in [&crdt_a, &crdt_b] {
+        assert!(crdt.is_tombstoned(parent).unwrap());
+        assert!(crdt.children(NodeId::ROOT).unwrap().is_empty());
+        crdt.validate_invariants().unwrap();
+    }
+    assert_eq!(crdt_a.nodes().unwrap(), crdt_b.nodes().unwrap());
+
+    assert!(matches!(
+        crdt_b.local_restore(child),
+        Err(Error::InvalidOperation(_))
+    ));
+    let (move_op, _) = crdt_b.local_move(parent, NodeId::ROOT, LocalPlacement::First).unwrap();
+    crdt_a.apply_remote(move_op).unwrap();
+    assert!(crdt_a.is_tombstoned(parent).unwrap());
+    assert!(crdt_b.is_tombstoned(parent).unwrap());
+}
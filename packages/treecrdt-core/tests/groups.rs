@@ -0,0 +1,122 @@
+use treecrdt_core::{
+    GroupBuffer, LocalEdit, LocalPlacement, NodeId, Operation, OperationGroup, PartialGroupPolicy,
+    ReplicaId,
+};
+
+mod common;
+
+use common::tree;
+
+/// A folder with three children, created in one batch on replica "a".
+fn folder_batch() -> Vec<Operation> {
+    let mut crdt = tree(&ReplicaId::new(b"a"));
+    let folder = NodeId(10);
+    let mut edits = vec![LocalEdit::Insert {
+        parent: NodeId::ROOT,
+        node: folder,
+        placement: LocalPlacement::Last,
+        payload: None,
+    }];
+    for n in 1..=3 {
+        edits.push(LocalEdit::Insert {
+            parent: folder,
+            node: NodeId(n),
+            placement: LocalPlacement::Last,
+            payload: None,
+        });
+    }
+    crdt.local_batch(edits).unwrap().into_iter().map(|entry| entry.op).collect()
+}
+
+#[test]
+fn local_batches_are_grouped() {
+    let ops = folder_batch();
+    let group = OperationGroup { first: 1, len: 4 };
+    assert!(ops.iter().all(|op| op.meta.group == Some(group)));
+
+    let mut crdt = tree(&ReplicaId::new(b"a"));
+    let staged = crdt
+        .local_batch([LocalEdit::Insert {
+            parent: NodeId::ROOT,
+            node: NodeId(1),
+            placement: LocalPlacement::Last,
+            payload: None,
+        }])
+        .unwrap();
+    assert_eq!(staged[0].op.meta.group, None);
+}
+
+#[test]
+fn partial_groups_stay_invisible_until_complete() {
+    let ops = folder_batch();
+    let mut peer = tree(&ReplicaId::new(b"b"));
+    let mut buffer = GroupBuffer::new(PartialGroupPolicy::Wait);
+
+    let unrelated = Operation::insert(
+        &ReplicaId::new(b"c"),
+        1,
+        1,
+        NodeId::ROOT,
+        NodeId(20),
+        vec![0x80],
+    );
+    let applied = peer
+        .apply_remote_grouped(
+            vec![ops[2].clone(), ops[0].clone(), unrelated],
+            &mut buffer,
+            0,
+        )
+        .unwrap();
+    assert_eq!(applied.len(), 1);
+    assert_eq!(peer.children(NodeId::ROOT).unwrap(), vec![NodeId(20)]);
+    assert_eq!(buffer.pending_ops(), 2);
+
+    // A resent member is not counted twice.
+    peer.apply_remote_grouped(vec![ops[0].clone(), ops[1].clone()], &mut buffer, 5)
+        .unwrap();
+    assert_eq!(buffer.pending_ops(), 3);
+    assert_eq!(peer.parent(NodeId(10)).unwrap(), None);
+
+    let applied = peer.apply_remote_grouped(vec![ops[3].clone()], &mut buffer, 10).unwrap();
+    assert_eq!(applied.len(), 4);
+    assert!(buffer.is_empty());
+    assert_eq!(
+        peer.children(NodeId(10)).unwrap(),
+        vec![NodeId(1), NodeId(2), NodeId(3)]
+    );
+
+    // Members arriving again after the group was applied pass straight through.
+    let applied = peer.apply_remote_grouped(vec![ops[1].clone()], &mut buffer, 20).unwrap();
+    assert_eq!(applied.len(), 1);
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn incomplete_groups_are_released_after_the_timeout() {
+    let ops = folder_batch();
+    let mut peer = tree(&ReplicaId::new(b"b"));
+    let mut buffer = GroupBuffer::new(PartialGroupPolicy::ReleaseAfter(100));
+
+    let applied = peer
+        .apply_remote_grouped(vec![ops[0].clone(), ops[1].clone()], &mut buffer, 1_000)
+        .unwrap();
+    assert!(applied.is_empty());
+    assert!(buffer.release_expired(1_099).is_empty());
+
+    let applied = peer.apply_remote_grouped(Vec::new(), &mut buffer, 1_100).unwrap();
+    assert_eq!(applied, ops[..2].to_vec());
+    assert_eq!(peer.children(NodeId(10)).unwrap(), vec![NodeId(1)]);
+
+    // The stragglers are applied on arrival once their group was given up on.
+    let applied = peer.apply_remote_grouped(ops[2..].to_vec(), &mut buffer, 1_200).unwrap();
+    assert_eq!(applied.len(), 2);
+    assert_eq!(
+        peer.children(NodeId(10)).unwrap(),
+        vec![NodeId(1), NodeId(2), NodeId(3)]
+    );
+
+    let mut waiting = GroupBuffer::new(PartialGroupPolicy::Wait);
+    waiting.admit(vec![ops[0].clone()], 0, |_, _| Ok(false)).unwrap();
+    assert!(waiting.release_expired(u64::MAX).is_empty());
+    assert_eq!(waiting.drain(), vec![ops[0].clone()]);
+}
@@ -0,0 +1,179 @@
+use treecrdt_core::{
+    Error, HistoryCut, LamportClock, LocalPlacement, MaterializationChange, MaterializationSource,
+    MemoryNodeStore, MemoryPayloadStore, MemoryStorage, NodeId, Operation, OrphanPolicy, ReplicaId,
+    StabilityTracker, TreeCrdt, VersionVector,
+};
+
+mod common;
+
+use common::{tree, vv_of};
+
+#[test]
+fn tree_at_rebuilds_past_states_without_touching_the_live_tree() {
+    let a = ReplicaId::new(b"a");
+    let mut crdt = tree(&a);
+    crdt.local_insert(
+        NodeId::ROOT,
+        NodeId(1),
+        LocalPlacement::First,
+        Some(b"v1".to_vec()),
+    )
+    .unwrap();
+    crdt.local_insert(NodeId::ROOT, NodeId(2), LocalPlacement::Last, None).unwrap();
+    let past = crdt.checkpoint().unwrap();
+    let past_vv = vv_of(&crdt.operations_since(0).unwrap());
+    let past_head = crdt.materialization_head().unwrap().at;
+
+    crdt.local_payload(NodeId(1), Some(b"v2".to_vec())).unwrap();
+    crdt.local_move(NodeId(2), NodeId(1), LocalPlacement::First).unwrap();
+    crdt.local_delete(NodeId(1)).unwrap();
+    let now = crdt.checkpoint().unwrap();
+
+    assert_eq!(crdt.tree_at(&HistoryCut::Version(past_vv)).unwrap(), past);
+    assert_eq!(
+        crdt.tree_at(&HistoryCut::Frontier(past_head)).unwrap(),
+        past
+    );
+    let everything = vv_of(&crdt.operations_since(0).unwrap());
+    let at_now = crdt.tree_at(&HistoryCut::Version(everything)).unwrap();
+    assert_eq!(at_now, now);
+    assert!(at_now.nodes.iter().any(|row| row.node == NodeId(1) && row.tombstone));
+
+    let empty = crdt.tree_at(&HistoryCut::Version(VersionVector::new())).unwrap();
+    assert!(empty
+        .nodes
+        .iter()
+        .all(|row| row.node == NodeId::ROOT || row.node == NodeId::TRASH));
+    assert!(empty.payloads.is_empty());
+    assert_eq!(crdt.checkpoint().unwrap(), now);
+}
+
+#[test]
+fn tree_at_resolves_deletes_with_the_document_orphan_policy() {
+    let lost_and_found = |replica: &[u8]| {
+        TreeCrdt::with_stores(
+            ReplicaId::new(replica),
+            MemoryStorage::default(),
+            LamportClock::default(),
+            MemoryNodeStore::with_orphan_policy(OrphanPolicy::LostAndFound),
+            MemoryPayloadStore::default(),
+        )
+        .unwrap()
+    };
+    let (mut a, mut b) = (lost_and_found(b"a"), lost_and_found(b"b"));
+    let (folder, file) = (NodeId(1), NodeId(2));
+    let (op, _) = a.local_insert(NodeId::ROOT, folder, LocalPlacement::Last, None).unwrap();
+    b.apply_remote(op).unwrap();
+    let (insert, _) = b.local_insert(folder, file, LocalPlacement::Last, None).unwrap();
+    a.local_delete(folder).unwrap();
+    a.apply_remote(insert).unwrap();
+    assert_eq!(a.parent(file).unwrap(), Some(NodeId::LOST_AND_FOUND));
+
+    let everything = vv_of(&a.operations_since(0).unwrap());
+    let at_now = a.tree_at(&HistoryCut::Version(everything)).unwrap();
+    assert_eq!(at_now, a.checkpoint().unwrap());
+    assert!(at_now
+        .nodes
+        .iter()
+        .any(|row| row.node == file && row.parent == Some(NodeId::LOST_AND_FOUND)));
+}
+
+#[test]
+fn tree_at_only_reaches_back_to_the_compaction_checkpoint() {
+    let a = ReplicaId::new(b"a");
+    let mut crdt = tree(&a);
+    crdt.local_insert(NodeId::ROOT, NodeId(1), LocalPlacement::First, None).unwrap();
+    let early = vv_of(&crdt.operations_since(0).unwrap());
+    crdt.local_insert(NodeId::ROOT, NodeId(2), LocalPlacement::Last, None).unwrap();
+    let stable = vv_of(&crdt.operations_since(0).unwrap());
+    let mut stability = StabilityTracker::new();
+    stability.acknowledge(&a, &stable);
+    crdt.compact(&stability).unwrap().unwrap();
+    crdt.local_insert(NodeId::ROOT, NodeId(3), LocalPlacement::Last, None).unwrap();
+
+    assert!(matches!(
+        crdt.tree_at(&HistoryCut::Version(early)),
+        Err(Error::MissingDependency(_))
+    ));
+    let at_stable = crdt.tree_at(&HistoryCut::Version(stable)).unwrap();
+    let nodes: Vec<NodeId> = at_stable
+        .nodes
+        .iter()
+        .map(|row| row.node)
+        .filter(|node| *node != NodeId::ROOT)
+        .collect();
+    assert_eq!(nodes, vec![NodeId(1), NodeId(2)]);
+}
+
+#[test]
+fn tree_diff_reports_coalesced_changes_with_their_latest_source() {
+    let a = ReplicaId::new(b"a");
+    let mut crdt = tree(&a);
+    for n in 1..=4 {
+        crdt.local_insert(NodeId::ROOT, NodeId(n), LocalPlacement::Last, None).unwrap();
+    }
+    crdt.local_delete(NodeId(4)).unwrap();
+    let seen = HistoryCut::Version(vv_of(&crdt.operations_since(0).unwrap()));
+
+    crdt.local_payload(NodeId(1), Some(b"v1".to_vec())).unwrap();
+    crdt.local_payload(NodeId(1), Some(b"v2".to_vec())).unwrap();
+    let (moved, _) = crdt.local_move(NodeId(2), NodeId(1), LocalPlacement::First).unwrap();
+    let (deleted, _) = crdt.local_delete(NodeId(3)).unwrap();
+    let (inserted, _) = crdt
+        .local_insert(
+            NodeId(1),
+            NodeId(5),
+            LocalPlacement::Last,
+            Some(b"new".to_vec()),
+        )
+        .unwrap();
+    // Editing inside the deleted node brings it back.
+    let (revived, _) =
+        crdt.local_insert(NodeId(4), NodeId(6), LocalPlacement::First, None).unwrap();
+    let (renamed, _) = crdt.local_payload(NodeId(1), Some(b"v3".to_vec())).unwrap();
+    let now = HistoryCut::Version(vv_of(&crdt.operations_since(0).unwrap()));
+
+    let source = |op: &Operation| Some(MaterializationSource::from_op(op));
+    let changes = crdt.tree_diff(&seen, &now).unwrap();
+    assert_eq!(
+        changes,
+        vec![
+            MaterializationChange::Move {
+                node: NodeId(2),
+                parent_before: Some(NodeId::ROOT),
+                parent_after: NodeId(1),
+                source: source(&moved),
+            },
+            MaterializationChange::Insert {
+                node: NodeId(5),
+                parent_after: NodeId(1),
+                payload: Some(b"new".to_vec()),
+                source: source(&inserted),
+            },
+            MaterializationChange::Insert {
+                node: NodeId(6),
+                parent_after: NodeId(4),
+                payload: None,
+                source: source(&revived),
+            },
+            MaterializationChange::Delete {
+                node: NodeId(3),
+                parent_before: Some(NodeId::ROOT),
+                source: source(&deleted),
+            },
+            MaterializationChange::Restore {
+                node: NodeId(4),
+                parent_after: Some(NodeId::ROOT),
+                payload: None,
+                source: None,
+            },
+            MaterializationChange::Payload {
+                node: NodeId(1),
+                payload: Some(b"v3".to_vec()),
+                source: source(&renamed),
+            },
+        ]
+    );
+
+    assert!(crdt.tree_diff(&now, &now).unwrap().is_empty());
+}
@@ -0,0 +1,144 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use treecrdt_core::{
+    Clock, Error, HybridLogicalClock, LocalPlacement, MemoryStorage, NodeId, Operation, ReplicaId,
+    Storage, TreeCrdt,
+};
+
+type Hlc = HybridLogicalClock<Box<dyn Fn() -> u64>>;
+
+fn manual_clock(start_ms: u64) -> (Rc<Cell<u64>>, Hlc) {
+    let now = Rc::new(Cell::new(start_ms));
+    let source = now.clone();
+    let clock = HybridLogicalClock::with_time_source(Box::new(move || source.get()) as Box<_>);
+    (now, clock)
+}
+
+#[test]
+fn tick_follows_physical_time_and_falls_back_to_logical_counter() {
+    let (now, mut clock) = manual_clock(1_000);
+
+    let a = clock.tick().unwrap();
+    assert_eq!(Hlc::physical_ms(a), 1_000);
+    assert_eq!(Hlc::logical(a), 0);
+
+    let b = clock.tick().unwrap();
+    assert_eq!(Hlc::physical_ms(b), 1_000);
+    assert_eq!(Hlc::logical(b), 1);
+
+    // Wall clock going backwards must not make timestamps go backwards.
+    now.set(900);
+    let c = clock.tick().unwrap();
+    assert!(c > b);
+    assert_eq!(Hlc::physical_ms(c), 1_000);
+
+    now.set(2_000);
+    let d = clock.tick().unwrap();
+    assert_eq!(d, Hlc::pack(2_000, 0));
+    assert_eq!(clock.now(), d);
+}
+
+#[test]
+fn observe_merges_remote_time_and_rejects_excessive_drift() {
+    let (_now, clock) = manual_clock(10_000);
+    let mut clock = clock.with_max_drift_ms(500);
+
+    let remote = Hlc::pack(10_400, 3);
+    clock.observe(remote).unwrap();
+    let next = clock.tick().unwrap();
+    assert_eq!(next, Hlc::pack(10_400, 4));
+
+    let before = clock.now();
+    let too_far = Hlc::pack(10_501, 0);
+    assert!(matches!(
+        clock.observe(too_far),
+        Err(Error::InvalidOperation(_))
+    ));
+    assert_eq!(clock.now(), before);
+}
+
+#[test]
+fn tick_fails_instead_of_wrapping_at_the_end_of_the_clock() {
+    let (_now, mut clock) = manual_clock(1_000);
+    clock.merge(u64::MAX);
+    assert!(matches!(clock.tick(), Err(Error::InvalidOperation(_))));
+    assert_eq!(clock.now(), u64::MAX);
+}
+
+#[test]
+fn wall_clock_is_recoverable_from_op_lamport() {
+    let (_now, clock) = manual_clock(1_700_000_000_123);
+    let mut crdt = TreeCrdt::new(ReplicaId::new(b"a"), MemoryStorage::default(), clock).unwrap();
+
+    let (op, _) = crdt.local_insert(NodeId::ROOT, NodeId(1), LocalPlacement::First, None).unwrap();
+    assert_eq!(
+        Hlc::wall_clock(op.meta.lamport),
+        UNIX_EPOCH + Duration::from_millis(1_700_000_000_123)
+    );
+}
+
+#[test]
+fn tree_rejects_remote_ops_from_the_future_without_storing_them() {
+    let (now, clock) = manual_clock(5_000);
+    let mut crdt = TreeCrdt::new(
+        ReplicaId::new(b"a"),
+        MemoryStorage::default(),
+        clock.with_max_drift_ms(1_000),
+    )
+    .unwrap();
+
+    let remote = ReplicaId::new(b"b");
+    let future = Operation::insert(
+        &remote,
+        1,
+        Hlc::pack(9_000, 0),
+        NodeId::ROOT,
+        NodeId(1),
+        vec![0, 1],
+    );
+    assert!(matches!(
+        crdt.apply_remote(future.clone()),
+        Err(Error::InvalidOperation(_))
+    ));
+    assert_eq!(crdt.parent(NodeId(1)).unwrap(), None);
+    assert!(crdt.operations_since(0).unwrap().is_empty());
+
+    // Once local time catches up, the same op is accepted and later local ops order after it.
+    now.set(8_500);
+    crdt.apply_remote(future.clone()).unwrap();
+    assert_eq!(crdt.parent(NodeId(1)).unwrap(), Some(NodeId::ROOT));
+    let (local, _) =
+        crdt.local_insert(NodeId::ROOT, NodeId(2), LocalPlacement::Last, None).unwrap();
+    assert!(local.meta.lamport > future.meta.lamport);
+}
+
+#[test]
+fn persisted_ops_are_not_rejected_for_drift_on_reload() {
+    let future = Operation::insert(
+        &ReplicaId::new(b"b"),
+        1,
+        Hlc::pack(9_000, 0),
+        NodeId::ROOT,
+        NodeId(1),
+        vec![0, 1],
+    );
+    let mut storage = MemoryStorage::default();
+    storage.apply(future.clone()).unwrap();
+
+    // The op was admitted earlier (e.g. before the local wall clock was set back).
+    let (_now, clock) = manual_clock(5_000);
+    let mut crdt = TreeCrdt::new(
+        ReplicaId::new(b"a"),
+        storage,
+        clock.with_max_drift_ms(1_000),
+    )
+    .unwrap();
+    crdt.replay_from_storage().unwrap();
+    assert_eq!(crdt.parent(NodeId(1)).unwrap(), Some(NodeId::ROOT));
+
+    let (local, _) =
+        crdt.local_insert(NodeId::ROOT, NodeId(2), LocalPlacement::Last, None).unwrap();
+    assert!(local.meta.lamport > future.meta.lamport);
+}
@@ -0,0 +1,95 @@
+use treecrdt_core::{LamportClock, LocalPlacement, MemoryStorage, NodeId, ReplicaId, TreeCrdt};
+
+mod common;
+
+use common::Tree;
+
+/// ROOT
+/// ├── a
+/// │   ├── b
+/// │   │   └── c
+/// │   └── d
+/// └── e
+fn sample() -> (Tree, [NodeId; 5]) {
+    let mut crdt = TreeCrdt::new(
+        ReplicaId::new(b"nav"),
+        MemoryStorage::default(),
+        LamportClock::default(),
+    )
+    .unwrap();
+    let [a, b, c, d, e] = [1, 2, 3, 4, 5].map(NodeId);
+    for (parent, node) in [(NodeId::ROOT, a), (a, b), (b, c), (a, d), (NodeId::ROOT, e)] {
+        crdt.local_insert(parent, node, LocalPlacement::Last, None).unwrap();
+    }
+    (crdt, [a, b, c, d, e])
+}
+
+fn descendants(crdt: &Tree, root: NodeId, max_depth: Option<usize>) -> Vec<(NodeId, usize)> {
+    crdt.descendants(root, max_depth).unwrap().collect::<Result<_, _>>().unwrap()
+}
+
+#[test]
+fn ancestors_depth_and_descent_follow_the_parent_chain() {
+    let (crdt, [a, b, c, _, e]) = sample();
+    assert_eq!(crdt.ancestors(c).unwrap(), vec![b, a, NodeId::ROOT]);
+    assert_eq!(crdt.depth(c).unwrap(), Some(3));
+    assert_eq!(crdt.depth(e).unwrap(), Some(1));
+    assert!(crdt.ancestors(NodeId::ROOT).unwrap().is_empty());
+    assert_eq!(crdt.depth(NodeId::ROOT).unwrap(), Some(0));
+
+    assert!(crdt.is_descendant(c, a).unwrap());
+    assert!(crdt.is_descendant(c, NodeId::ROOT).unwrap());
+    assert!(!crdt.is_descendant(a, c).unwrap());
+    assert!(!crdt.is_descendant(a, a).unwrap());
+    assert!(!crdt.is_descendant(e, a).unwrap());
+
+    assert_eq!(crdt.depth(NodeId(99)).unwrap(), None);
+}
+
+#[test]
+fn descendants_walk_in_pre_order_down_to_max_depth() {
+    let (crdt, [a, b, c, d, e]) = sample();
+    assert_eq!(
+        descendants(&crdt, NodeId::ROOT, None),
+        vec![(a, 1), (b, 2), (c, 3), (d, 2), (e, 1)]
+    );
+    assert_eq!(
+        descendants(&crdt, NodeId::ROOT, Some(1)),
+        vec![(a, 1), (e, 1)]
+    );
+    assert_eq!(descendants(&crdt, a, Some(1)), vec![(b, 1), (d, 1)]);
+    assert!(descendants(&crdt, c, None).is_empty());
+    assert!(descendants(&crdt, NodeId::ROOT, Some(0)).is_empty());
+
+    // Streaming: taking a prefix does not need the rest of the walk.
+    let first = crdt.descendants(NodeId::ROOT, None).unwrap().next().unwrap().unwrap();
+    assert_eq!(first, (a, 1));
+}
+
+#[test]
+fn index_in_parent_counts_visible_siblings() {
+    let (crdt, [a, _, _, d, e]) = sample();
+    assert_eq!(crdt.index_in_parent(a).unwrap(), Some(0));
+    assert_eq!(crdt.index_in_parent(e).unwrap(), Some(1));
+    assert_eq!(crdt.index_in_parent(d).unwrap(), Some(1));
+    assert_eq!(crdt.index_in_parent(NodeId::ROOT).unwrap(), None);
+}
+
+#[test]
+fn deleted_subtrees_drop_out_of_every_query() {
+    let (mut crdt, [a, b, c, d, e]) = sample();
+    crdt.local_delete(b).unwrap();
+
+    for node in [b, c] {
+        assert!(crdt.ancestors(node).unwrap().is_empty());
+        assert_eq!(crdt.depth(node).unwrap(), None);
+        assert_eq!(crdt.index_in_parent(node).unwrap(), None);
+        assert!(!crdt.is_descendant(node, a).unwrap());
+    }
+    assert!(descendants(&crdt, b, None).is_empty());
+    assert_eq!(
+        descendants(&crdt, NodeId::ROOT, None),
+        vec![(a, 1), (d, 2), (e, 1)]
+    );
+    assert_eq!(crdt.index_in_parent(d).unwrap(), Some(0));
+}
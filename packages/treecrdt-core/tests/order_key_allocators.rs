@@ -0,0 +1,343 @@
+use treecrdt_core::order_key::{allocate_between, allocate_evenly_between};
+use treecrdt_core::{
+    Anchored, AppendOptimized, FractionalIndex, LamportClock, LocalPlacement, Lseq, MemoryStorage,
+    NodeId, OperationKind, OrderKeyAllocator, ReplicaId, StagedLocalOp, TreeCrdt,
+};
+
+fn allocators() -> Vec<(&'static str, Box<dyn OrderKeyAllocator>)> {
+    vec![
+        ("lseq", Box::new(Lseq::default())),
+        (
+            "lseq small base",
+            Box::new(Lseq {
+                boundary: 4,
+                max_digit: 15,
+            }),
+        ),
+        ("fractional", Box::new(FractionalIndex)),
+        ("append", Box::new(AppendOptimized::default())),
+        ("anchored", Box::new(Anchored)),
+    ]
+}
+
+#[test]
+fn every_allocator_keeps_keys_strictly_ordered() {
+    for (name, allocator) in allocators() {
+        let mut keys: Vec<Vec<u8>> = Vec::new();
+        for i in 0u32..300 {
+            // Mix appends, prepends and inserts into the middle.
+            let at = match i % 3 {
+                0 => keys.len(),
+                1 => 0,
+                _ => keys.len() / 2,
+            };
+            let left = at.checked_sub(1).map(|idx| keys[idx].as_slice());
+            let right = keys.get(at).map(Vec::as_slice);
+            let key = allocator.allocate_between(left, right, &i.to_be_bytes()).unwrap();
+            assert!(left.is_none_or(|left| left < key.as_slice()), "{name}");
+            assert!(right.is_none_or(|right| key.as_slice() < right), "{name}");
+            keys.insert(at, key);
+        }
+    }
+}
+
+#[test]
+fn default_lseq_matches_allocate_between() {
+    let left = [0x00, 0x05];
+    let right = [0x00, 0x09, 0x12, 0x34];
+    for seed in [&b"a"[..], b"b", b"seed"] {
+        assert_eq!(
+            Lseq::default().allocate_between(Some(&left), Some(&right), seed).unwrap(),
+            allocate_between(Some(&left), Some(&right), seed).unwrap()
+        );
+    }
+}
+
+#[test]
+fn append_optimized_keeps_appended_keys_one_digit_long() {
+    let allocator = AppendOptimized::default();
+    let mut last: Option<Vec<u8>> = None;
+    for i in 0u32..1000 {
+        let key = allocator.allocate_between(last.as_deref(), None, &i.to_be_bytes()).unwrap();
+        assert_eq!(key.len(), 2);
+        last = Some(key);
+    }
+
+    // Fractional indexing halves the remaining room on every append.
+    let mut last: Option<Vec<u8>> = None;
+    for i in 0u32..20 {
+        last = Some(
+            FractionalIndex
+                .allocate_between(last.as_deref(), None, &i.to_be_bytes())
+                .unwrap(),
+        );
+    }
+    assert!(last.unwrap().len() > 2);
+}
+
+#[test]
+fn tree_uses_the_configured_allocator() {
+    let mut crdt = TreeCrdt::new(
+        ReplicaId::new(b"a"),
+        MemoryStorage::default(),
+        LamportClock::default(),
+    )
+    .unwrap();
+    crdt.set_order_key_allocator(FractionalIndex);
+    for n in 1..=3 {
+        crdt.local_insert(NodeId::ROOT, NodeId(n), LocalPlacement::Last, None).unwrap();
+    }
+    crdt.local_insert(
+        NodeId::ROOT,
+        NodeId(4),
+        LocalPlacement::After(NodeId(1)),
+        None,
+    )
+    .unwrap();
+
+    assert_eq!(
+        crdt.children(NodeId::ROOT).unwrap(),
+        vec![NodeId(1), NodeId(4), NodeId(2), NodeId(3)]
+    );
+    let keys: Vec<Vec<u8>> = crdt
+        .operations_since(0)
+        .unwrap()
+        .into_iter()
+        .map(|op| match op.kind {
+            OperationKind::Insert { order_key, .. } => order_key,
+            other => panic!("unexpected op {other:?}"),
+        })
+        .collect();
+    assert_eq!(
+        keys,
+        vec![
+            vec![0x7f, 0xff],
+            vec![0xbf, 0xff],
+            vec![0xdf, 0xff],
+            vec![0x9f, 0xff]
+        ]
+    );
+}
+
+#[test]
+fn evenly_spaced_keys_stay_short_and_ordered() {
+    let keys = allocate_evenly_between(None, None, 500, b"paste").unwrap();
+    assert_eq!(keys.len(), 500);
+    assert!(keys.iter().all(|key| key.len() == 2));
+    assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+
+    // Bounds only 16 keys apart need a third digit, and the run stays inside them.
+    let (left, right) = ([0x00, 0x05, 0xff, 0xf0], [0x00, 0x06]);
+    let keys = allocate_evenly_between(Some(&left), Some(&right), 1000, b"seed").unwrap();
+    assert!(keys.iter().all(|key| key.len() <= 8));
+    assert!(left.as_slice() < keys[0].as_slice());
+    assert!(keys[999].as_slice() < right.as_slice());
+    assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+    // Every gap is left open for later inserts.
+    for pair in keys.windows(2) {
+        allocate_between(Some(&pair[0]), Some(&pair[1]), b"later").unwrap();
+    }
+
+    assert!(allocate_evenly_between(Some(&right), Some(&left), 1, b"seed").is_err());
+    assert!(allocate_evenly_between(
+        Some(&[0x00, 0x05]),
+        Some(&[0x00, 0x05, 0x00, 0x00]),
+        1,
+        b"s"
+    )
+    .is_err());
+}
+
+#[test]
+fn rebalance_shortens_keys_and_keeps_order() {
+    let mut crdt = TreeCrdt::new(
+        ReplicaId::new(b"a"),
+        MemoryStorage::default(),
+        LamportClock::default(),
+    )
+    .unwrap();
+    let mut peer = TreeCrdt::new(
+        ReplicaId::new(b"b"),
+        MemoryStorage::default(),
+        LamportClock::default(),
+    )
+    .unwrap();
+    // Always inserting right after the first child nests every key a bit deeper.
+    crdt.local_insert(NodeId::ROOT, NodeId(1), LocalPlacement::Last, None).unwrap();
+    crdt.local_insert(NodeId::ROOT, NodeId(2), LocalPlacement::Last, None).unwrap();
+    for n in 3..200 {
+        crdt.local_insert(
+            NodeId::ROOT,
+            NodeId(n),
+            LocalPlacement::After(NodeId(1)),
+            None,
+        )
+        .unwrap();
+    }
+    for op in crdt.operations_since(0).unwrap() {
+        peer.apply_remote(op).unwrap();
+    }
+    let order = crdt.children(NodeId::ROOT).unwrap();
+    let before = crdt.order_key_stats(NodeId::ROOT).unwrap();
+    assert_eq!(before.children, 199);
+    assert!(before.max_len > 8);
+
+    let staged = crdt.local_rebalance(NodeId::ROOT).unwrap();
+    assert!(!staged.is_empty() && staged.len() <= 199);
+    let after = crdt.order_key_stats(NodeId::ROOT).unwrap();
+    assert!(after.max_len <= 4);
+    assert!(after.avg_len() < before.avg_len());
+    assert_eq!(crdt.children(NodeId::ROOT).unwrap(), order);
+
+    // A sibling the peer adds meanwhile still ends up in the same place on both sides.
+    peer.local_insert(
+        NodeId::ROOT,
+        NodeId(500),
+        LocalPlacement::After(NodeId(2)),
+        None,
+    )
+    .unwrap();
+    let theirs = peer.operations_since(0).unwrap();
+    for op in staged.into_iter().map(|entry| entry.op) {
+        peer.apply_remote(op).unwrap();
+    }
+    for op in theirs {
+        crdt.apply_remote(op).unwrap();
+    }
+    let merged = crdt.children(NodeId::ROOT).unwrap();
+    assert_eq!(merged, peer.children(NodeId::ROOT).unwrap());
+    assert_eq!(merged.len(), 200);
+    assert_eq!(merged[..199], order[..]);
+
+    assert!(crdt.local_rebalance(NodeId::TRASH).is_err());
+}
+
+#[test]
+fn rebalance_keeps_concurrent_inserts_between_their_neighbours() {
+    let mut crdt = TreeCrdt::new(
+        ReplicaId::new(b"a"),
+        MemoryStorage::default(),
+        LamportClock::default(),
+    )
+    .unwrap();
+    let mut peer = TreeCrdt::new(
+        ReplicaId::new(b"b"),
+        MemoryStorage::default(),
+        LamportClock::default(),
+    )
+    .unwrap();
+    crdt.set_order_key_allocator(AppendOptimized::default());
+    for n in 1..=10 {
+        crdt.local_insert(NodeId::ROOT, NodeId(n), LocalPlacement::Last, None).unwrap();
+    }
+    // Only the keys between 5 and 6 grow long.
+    for n in 100..300 {
+        crdt.local_insert(
+            NodeId::ROOT,
+            NodeId(n),
+            LocalPlacement::After(NodeId(5)),
+            None,
+        )
+        .unwrap();
+    }
+    for op in crdt.operations_since(0).unwrap() {
+        peer.apply_remote(op).unwrap();
+    }
+    let order = crdt.children(NodeId::ROOT).unwrap();
+
+    let staged = crdt.local_rebalance(NodeId::ROOT).unwrap();
+    assert!(!staged.is_empty());
+    for entry in &staged {
+        let node = entry.op.kind.node();
+        assert!(node.0 >= 100 || node == NodeId(5) || node == NodeId(6));
+    }
+    assert_eq!(crdt.children(NodeId::ROOT).unwrap(), order);
+    assert!(crdt.order_key_stats(NodeId::ROOT).unwrap().max_len <= 4);
+    assert!(crdt.local_rebalance(NodeId::ROOT).unwrap().is_empty());
+
+    // Siblings the peer adds meanwhile keep their neighbours on both sides.
+    peer.local_insert(
+        NodeId::ROOT,
+        NodeId(500),
+        LocalPlacement::After(NodeId(2)),
+        None,
+    )
+    .unwrap();
+    peer.local_insert(
+        NodeId::ROOT,
+        NodeId(501),
+        LocalPlacement::After(NodeId(8)),
+        None,
+    )
+    .unwrap();
+    let theirs = peer.operations_since(0).unwrap();
+    for op in staged.into_iter().map(|entry| entry.op) {
+        peer.apply_remote(op).unwrap();
+    }
+    for op in theirs {
+        crdt.apply_remote(op).unwrap();
+    }
+    let merged = crdt.children(NodeId::ROOT).unwrap();
+    assert_eq!(merged, peer.children(NodeId::ROOT).unwrap());
+    let at = |node: u128| merged.iter().position(|n| *n == NodeId(node)).unwrap();
+    assert_eq!(at(500), at(2) + 1);
+    assert_eq!(at(3), at(500) + 1);
+    assert_eq!(at(501), at(8) + 1);
+    assert_eq!(at(9), at(501) + 1);
+    let without: Vec<NodeId> = merged.into_iter().filter(|n| n.0 != 500 && n.0 != 501).collect();
+    assert_eq!(without, order);
+}
+
+#[test]
+fn concurrent_rebalances_of_the_same_children_agree() {
+    let mut crdt = TreeCrdt::new(
+        ReplicaId::new(b"a"),
+        MemoryStorage::default(),
+        LamportClock::default(),
+    )
+    .unwrap();
+    let mut peer = TreeCrdt::new(
+        ReplicaId::new(b"b"),
+        MemoryStorage::default(),
+        LamportClock::default(),
+    )
+    .unwrap();
+    crdt.set_order_key_allocator(AppendOptimized::default());
+    for n in 1..=10 {
+        crdt.local_insert(NodeId::ROOT, NodeId(n), LocalPlacement::Last, None).unwrap();
+    }
+    for n in 100..300 {
+        crdt.local_insert(
+            NodeId::ROOT,
+            NodeId(n),
+            LocalPlacement::After(NodeId(5)),
+            None,
+        )
+        .unwrap();
+    }
+    for op in crdt.operations_since(0).unwrap() {
+        peer.apply_remote(op).unwrap();
+    }
+    let order = crdt.children(NodeId::ROOT).unwrap();
+
+    // Both replicas rebalance the same view, so they mint the same keys.
+    let ours = crdt.local_rebalance(NodeId::ROOT).unwrap();
+    let theirs = peer.local_rebalance(NodeId::ROOT).unwrap();
+    assert!(!ours.is_empty());
+    let key = |entry: &StagedLocalOp| match &entry.op.kind {
+        OperationKind::Move { order_key, .. } => (entry.op.kind.node(), order_key.clone()),
+        kind => panic!("unexpected op {kind:?}"),
+    };
+    assert_eq!(
+        ours.iter().map(key).collect::<Vec<_>>(),
+        theirs.iter().map(key).collect::<Vec<_>>()
+    );
+    for entry in theirs {
+        crdt.apply_remote(entry.op).unwrap();
+    }
+    for entry in ours {
+        peer.apply_remote(entry.op).unwrap();
+    }
+    assert_eq!(crdt.children(NodeId::ROOT).unwrap(), order);
+    assert_eq!(peer.children(NodeId::ROOT).unwrap(), order);
+}
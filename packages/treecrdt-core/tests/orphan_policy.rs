@@ -0,0 +1,125 @@
+use treecrdt_core::{
+    LamportClock, LocalPlacement, MaterializationChange, MemoryNodeStore, MemoryPayloadStore,
+    MemoryStorage, NodeId, OrphanPolicy, ReplicaId, TreeCrdt,
+};
+
+mod common;
+
+use common::Tree;
+
+fn with_policy(replica: &[u8], policy: OrphanPolicy) -> Tree {
+    TreeCrdt::with_stores(
+        ReplicaId::new(replica),
+        MemoryStorage::default(),
+        LamportClock::default(),
+        MemoryNodeStore::with_orphan_policy(policy),
+        MemoryPayloadStore::default(),
+    )
+    .unwrap()
+}
+
+/// `a` deletes `folder` while `b`, which has not seen the delete, adds `file` to it. Both
+/// replicas end up with every op, each applying the other's last.
+fn delete_against_insert(policy: OrphanPolicy, tombstone: bool) -> (Tree, Tree) {
+    let (mut a, mut b) = (with_policy(b"a", policy), with_policy(b"b", policy));
+    let (folder, old, file) = (NodeId(1), NodeId(2), NodeId(3));
+    for (parent, node) in [(NodeId::ROOT, folder), (folder, old)] {
+        let (op, _) = a.local_insert(parent, node, LocalPlacement::Last, None).unwrap();
+        b.apply_remote(op).unwrap();
+    }
+
+    let (insert, _) = b.local_insert(folder, file, LocalPlacement::Last, None).unwrap();
+    let (delete, _) = if tombstone {
+        a.local_tombstone(folder).unwrap()
+    } else {
+        a.local_delete(folder).unwrap()
+    };
+    a.apply_remote(insert).unwrap();
+    b.apply_remote(delete).unwrap();
+    for crdt in [&a, &b] {
+        crdt.validate_invariants().unwrap();
+    }
+    assert_eq!(a.nodes().unwrap(), b.nodes().unwrap());
+    (a, b)
+}
+
+#[test]
+fn lost_and_found_rescues_the_unseen_insert_and_keeps_the_delete() {
+    let (folder, file) = (NodeId(1), NodeId(3));
+    let (a, b) = delete_against_insert(OrphanPolicy::LostAndFound, false);
+    for crdt in [&a, &b] {
+        assert_eq!(crdt.orphan_policy(), OrphanPolicy::LostAndFound);
+        assert!(crdt.is_tombstoned(folder).unwrap());
+        assert!(crdt.children(NodeId::ROOT).unwrap().is_empty());
+        // `old` was seen by the delete, so it stays hidden with `folder`.
+        assert_eq!(crdt.children(NodeId::LOST_AND_FOUND).unwrap(), vec![file]);
+        assert_eq!(crdt.parent(file).unwrap(), Some(NodeId::LOST_AND_FOUND));
+    }
+
+    // Nothing escapes a tombstone.
+    let (a, b) = delete_against_insert(OrphanPolicy::LostAndFound, true);
+    for crdt in [&a, &b] {
+        assert!(crdt.is_tombstoned(folder).unwrap());
+        assert!(crdt.children(NodeId::LOST_AND_FOUND).unwrap().is_empty());
+    }
+}
+
+#[test]
+fn lost_and_found_reports_the_rescue_as_a_move() {
+    let (mut a, mut b) = (
+        with_policy(b"a", OrphanPolicy::LostAndFound),
+        with_policy(b"b", OrphanPolicy::LostAndFound),
+    );
+    let (folder, file) = (NodeId(1), NodeId(2));
+    let (insert, _) = a.local_insert(NodeId::ROOT, folder, LocalPlacement::Last, None).unwrap();
+    b.apply_remote(insert).unwrap();
+    let (insert, _) = b.local_insert(folder, file, LocalPlacement::Last, None).unwrap();
+    a.local_delete(folder).unwrap();
+
+    let delta = a.apply_remote_with_delta(insert).unwrap().unwrap();
+    assert!(delta.changes.iter().any(|change| matches!(
+        change,
+        MaterializationChange::Move {
+            node,
+            parent_before: Some(before),
+            parent_after: NodeId::LOST_AND_FOUND,
+            ..
+        } if *node == file && *before == folder
+    )));
+    assert_eq!(a.parent(file).unwrap(), Some(NodeId::LOST_AND_FOUND));
+    assert!(a.is_tombstoned(folder).unwrap());
+}
+
+#[test]
+fn keep_parent_leaves_tombstones_permanent() {
+    // The unseen insert under `folder` does not bring it back.
+    let folder = NodeId(1);
+    let (a, b) = delete_against_insert(OrphanPolicy::KeepParent, true);
+    for crdt in [&a, &b] {
+        assert!(crdt.is_tombstoned(folder).unwrap());
+        assert!(crdt.children(NodeId::ROOT).unwrap().is_empty());
+    }
+}
+
+#[test]
+fn keep_parent_lets_unseen_inserts_but_not_payload_edits_outlive_a_delete() {
+    let folder = NodeId(1);
+    let (a, b) = delete_against_insert(OrphanPolicy::KeepParent, false);
+    for crdt in [&a, &b] {
+        assert!(!crdt.is_tombstoned(folder).unwrap());
+        assert_eq!(crdt.children(folder).unwrap(), vec![NodeId(2), NodeId(3)]);
+    }
+
+    let (mut a, mut b) = (
+        with_policy(b"a", OrphanPolicy::KeepParent),
+        with_policy(b"b", OrphanPolicy::KeepParent),
+    );
+    let (insert, _) = a.local_insert(NodeId::ROOT, folder, LocalPlacement::Last, None).unwrap();
+    b.apply_remote(insert).unwrap();
+    let (edit, _) = b.local_payload(folder, Some(b"renamed".to_vec())).unwrap();
+    let (delete, _) = a.local_delete(folder).unwrap();
+    a.apply_remote(edit).unwrap();
+    b.apply_remote(delete).unwrap();
+    assert!(a.is_tombstoned(folder).unwrap());
+    assert!(b.is_tombstoned(folder).unwrap());
+}
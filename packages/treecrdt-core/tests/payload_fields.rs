@@ -0,0 +1,218 @@
+use treecrdt_core::{
+    Capability, CapabilityAction, Error, HistoryCut, LocalPlacement, MaterializationChange, NodeId,
+    NoopParentOpIndex, Operation, ReplicaId, StabilityTracker, SubtreePolicy, SubtreeScope,
+    UndoManager, VersionVector,
+};
+
+mod common;
+
+use common::tree;
+
+fn fields(pairs: &[(&str, &[u8])]) -> Vec<(String, Vec<u8>)> {
+    pairs.iter().map(|(field, value)| (field.to_string(), value.to_vec())).collect()
+}
+
+#[test]
+fn concurrent_writes_to_different_fields_all_survive() {
+    let (ra, rb) = (ReplicaId::new(b"a"), ReplicaId::new(b"b"));
+    let node = NodeId(1);
+    let insert = Operation::insert(&ReplicaId::new(b"s"), 1, 1, NodeId::ROOT, node, vec![0x80]);
+
+    let ops = vec![
+        Operation::payload_field(&ra, 1, 5, node, "title", Some(b"Milk".to_vec())),
+        Operation::payload_field(&rb, 1, 5, node, "color", Some(b"red".to_vec())),
+        Operation::payload_field(&rb, 2, 6, node, "checked", Some(vec![1])),
+        // Same field, same lamport: the higher replica wins, like whole payloads.
+        Operation::payload_field(&ra, 2, 7, node, "checked", Some(vec![0])),
+        Operation::payload_field(&rb, 3, 7, node, "checked", Some(vec![1])),
+        Operation::payload_field(&ra, 3, 8, node, "title", None),
+        Operation::payload_field(&rb, 4, 4, node, "title", Some(b"stale".to_vec())),
+    ];
+
+    let mut forward = tree(&ra);
+    let mut backward = tree(&rb);
+    forward.apply_remote(insert.clone()).unwrap();
+    backward.apply_remote(insert).unwrap();
+    for op in &ops {
+        forward.apply_remote(op.clone()).unwrap();
+    }
+    for op in ops.iter().rev() {
+        backward.apply_remote(op.clone()).unwrap();
+    }
+
+    let expected = fields(&[("checked", &[1]), ("color", b"red")]);
+    assert_eq!(forward.payload_fields(node).unwrap(), expected);
+    assert_eq!(backward.payload_fields(node).unwrap(), expected);
+    assert_eq!(forward.payload_field(node, "title").unwrap(), None);
+    // The whole-node payload is a separate register.
+    assert_eq!(forward.payload(node).unwrap(), None);
+    assert_eq!(
+        forward.checkpoint().unwrap(),
+        backward.checkpoint().unwrap()
+    );
+}
+
+#[test]
+fn local_field_writes_report_the_field_and_undo_per_field() {
+    let a = ReplicaId::new(b"a");
+    let mut crdt = tree(&a);
+    let node = NodeId(1);
+    crdt.local_insert(
+        NodeId::ROOT,
+        node,
+        LocalPlacement::Last,
+        Some(b"note".to_vec()),
+    )
+    .unwrap();
+    crdt.local_payload_field(node, "title", Some(b"Draft".to_vec())).unwrap();
+
+    let mut undo = UndoManager::new();
+    let prepared = crdt
+        .prepare_local_payload_field(node, "title", Some(b"Final".to_vec()))
+        .unwrap();
+    undo.record(crdt.undo_record(&prepared.op).unwrap());
+    let (op, plan) = crdt.commit_prepared_local(prepared).unwrap();
+    let outcome = crdt.finalize_local_with_outcome(&op, &mut NoopParentOpIndex, 2, &plan).unwrap();
+    assert_eq!(
+        outcome.changes,
+        vec![MaterializationChange::PayloadField {
+            node,
+            field: "title".into(),
+            value: Some(b"Final".to_vec()),
+            source: Some(treecrdt_core::MaterializationSource::from_op(&op)),
+        }]
+    );
+
+    crdt.local_payload_field(node, "color", Some(b"blue".to_vec())).unwrap();
+    undo.undo(&mut crdt).unwrap();
+    assert_eq!(
+        crdt.payload_fields(node).unwrap(),
+        fields(&[("color", b"blue"), ("title", b"Draft")])
+    );
+    assert_eq!(crdt.payload(node).unwrap(), Some(b"note".to_vec()));
+
+    // A concurrent write that wins the field is not reverted by a later undo.
+    undo.redo(&mut crdt).unwrap();
+    let lamport = crdt.lamport();
+    crdt.apply_remote(Operation::payload_field(
+        &ReplicaId::new(b"b"),
+        1,
+        lamport + 1,
+        node,
+        "title",
+        Some(b"Theirs".to_vec()),
+    ))
+    .unwrap();
+    undo.undo(&mut crdt).unwrap();
+    assert_eq!(
+        crdt.payload_field(node, "title").unwrap(),
+        Some(b"Theirs".to_vec())
+    );
+}
+
+#[test]
+fn field_reads_respect_the_access_policy() {
+    let a = ReplicaId::new(b"a");
+    let mut crdt = tree(&a);
+    let (shared, private) = (NodeId(1), NodeId(2));
+    for node in [shared, private] {
+        crdt.local_insert(NodeId::ROOT, node, LocalPlacement::Last, None).unwrap();
+        crdt.local_payload_field(node, "title", Some(b"Draft".to_vec())).unwrap();
+    }
+    crdt.set_access_control(SubtreePolicy::new(vec![Capability::new(
+        SubtreeScope::subtree(shared),
+        [CapabilityAction::Read],
+    )]));
+
+    assert_eq!(crdt.payload_field_entries(shared).unwrap().len(), 1);
+    assert!(matches!(
+        crdt.payload_field_entries(private),
+        Err(Error::AccessDenied(_))
+    ));
+    assert!(matches!(
+        crdt.payload_fields(private),
+        Err(Error::AccessDenied(_))
+    ));
+}
+
+#[test]
+fn unseen_field_writes_revive_a_deleted_node() {
+    let (ra, rb) = (ReplicaId::new(b"a"), ReplicaId::new(b"b"));
+    let mut a = tree(&ra);
+    let mut b = tree(&rb);
+    let node = NodeId(1);
+    let (insert, _) = a.local_insert(NodeId::ROOT, node, LocalPlacement::Last, None).unwrap();
+    b.apply_remote(insert).unwrap();
+
+    let (edit, _) = b.local_payload_field(node, "title", Some(b"kept".to_vec())).unwrap();
+    let (delete, _) = a.local_delete(node).unwrap();
+    a.apply_remote(edit).unwrap();
+    b.apply_remote(delete).unwrap();
+
+    for crdt in [&a, &b] {
+        assert_eq!(crdt.children(NodeId::ROOT).unwrap(), vec![node]);
+        assert_eq!(
+            crdt.payload_field(node, "title").unwrap(),
+            Some(b"kept".to_vec())
+        );
+    }
+}
+
+#[test]
+fn fields_survive_replay_compaction_and_show_up_in_diffs() {
+    let a = ReplicaId::new(b"a");
+    let mut crdt = tree(&a);
+    let node = NodeId(1);
+    crdt.local_insert(NodeId::ROOT, node, LocalPlacement::Last, None).unwrap();
+    crdt.local_payload_field(node, "title", Some(b"one".to_vec())).unwrap();
+    let mut seen = VersionVector::new();
+    for counter in 1..=2 {
+        seen.observe(&a, counter);
+    }
+
+    // An out-of-order field write forces a replay from storage.
+    crdt.apply_remote(Operation::payload_field(
+        &ReplicaId::new(b"0"),
+        1,
+        1,
+        node,
+        "color",
+        Some(b"red".to_vec()),
+    ))
+    .unwrap();
+    crdt.local_payload_field(node, "title", None).unwrap();
+    let expected = fields(&[("color", b"red")]);
+    assert_eq!(crdt.payload_fields(node).unwrap(), expected);
+
+    let mut everything = seen.clone();
+    everything.observe(&a, 3);
+    everything.observe(&ReplicaId::new(b"0"), 1);
+    let changes = crdt
+        .tree_diff(
+            &HistoryCut::Version(seen),
+            &HistoryCut::Version(everything.clone()),
+        )
+        .unwrap();
+    let mut reported: Vec<(String, Option<Vec<u8>>)> = changes
+        .into_iter()
+        .filter_map(|change| match change {
+            MaterializationChange::PayloadField { field, value, .. } => Some((field, value)),
+            _ => None,
+        })
+        .collect();
+    reported.sort();
+    assert_eq!(
+        reported,
+        vec![
+            ("color".to_string(), Some(b"red".to_vec())),
+            ("title".to_string(), None)
+        ]
+    );
+
+    let mut stability = StabilityTracker::new();
+    stability.acknowledge(&a, &everything);
+    crdt.compact(&stability).unwrap().unwrap();
+    crdt.replay_from_storage().unwrap();
+    assert_eq!(crdt.payload_fields(node).unwrap(), expected);
+    assert_eq!(crdt.compaction_checkpoint().unwrap().fields.len(), 2);
+}
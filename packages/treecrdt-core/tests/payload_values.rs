@@ -0,0 +1,141 @@
+use treecrdt_core::{
+    LamportClock, LocalPlacement, MemoryNodeStore, MemoryPayloadStore, MemoryStorage, NodeId,
+    Operation, PayloadMode, ReplicaId, StabilityTracker, TreeCrdt, VersionVector,
+};
+
+mod common;
+
+use common::Tree;
+
+fn multi_value(replica: &ReplicaId) -> Tree {
+    TreeCrdt::with_stores(
+        replica.clone(),
+        MemoryStorage::default(),
+        LamportClock::default(),
+        MemoryNodeStore::default(),
+        MemoryPayloadStore::with_mode(PayloadMode::MultiValue),
+    )
+    .unwrap()
+}
+
+fn values(crdt: &Tree, node: NodeId) -> Vec<Option<Vec<u8>>> {
+    crdt.payload_values(node)
+        .unwrap()
+        .into_iter()
+        .map(|value| value.payload)
+        .collect()
+}
+
+#[test]
+fn concurrent_writes_are_kept_until_a_write_that_saw_them() {
+    let (ra, rb, rc) = (
+        ReplicaId::new(b"a"),
+        ReplicaId::new(b"b"),
+        ReplicaId::new(b"c"),
+    );
+    let (mut a, mut b, mut c) = (multi_value(&ra), multi_value(&rb), multi_value(&rc));
+    let node = NodeId(1);
+    let (insert, _) = a
+        .local_insert(
+            NodeId::ROOT,
+            node,
+            LocalPlacement::Last,
+            Some(b"draft".to_vec()),
+        )
+        .unwrap();
+    b.apply_remote(insert.clone()).unwrap();
+
+    let (ours, _) = a.local_payload(node, Some(b"ours".to_vec())).unwrap();
+    let (theirs, _) = b.local_payload(node, Some(b"theirs".to_vec())).unwrap();
+    assert_eq!(values(&a, node), vec![Some(b"ours".to_vec())]);
+    a.apply_remote(theirs.clone()).unwrap();
+    b.apply_remote(ours.clone()).unwrap();
+
+    // Both writes only saw the insert, so neither replaces the other.
+    let expected = vec![Some(b"ours".to_vec()), Some(b"theirs".to_vec())];
+    assert_eq!(values(&a, node), expected);
+    assert_eq!(values(&b, node), expected);
+    assert_eq!(a.payload(node).unwrap(), Some(b"theirs".to_vec()));
+    assert_eq!(a.payload(node).unwrap(), b.payload(node).unwrap());
+
+    // A local write sees every current value and collapses the conflict.
+    let (resolved, _) = b.local_payload(node, Some(b"merged".to_vec())).unwrap();
+    assert_eq!(values(&b, node), vec![Some(b"merged".to_vec())]);
+
+    // A peer receiving the history backwards ends up in the same place, and the writes the
+    // resolution saw do not come back when they arrive late.
+    for op in [resolved.clone(), ours, theirs, insert] {
+        c.apply_remote(op).unwrap();
+    }
+    a.apply_remote(resolved).unwrap();
+    for crdt in [&a, &c] {
+        assert_eq!(values(crdt, node), vec![Some(b"merged".to_vec())]);
+        assert_eq!(crdt.payload(node).unwrap(), Some(b"merged".to_vec()));
+    }
+}
+
+#[test]
+fn resolutions_inherit_what_the_values_they_saw_had_seen() {
+    let (ra, rb) = (ReplicaId::new(b"a"), ReplicaId::new(b"b"));
+    let mut a = multi_value(&ra);
+    let mut b = multi_value(&rb);
+    let node = NodeId(1);
+    let (insert, _) = a.local_insert(NodeId::ROOT, node, LocalPlacement::Last, None).unwrap();
+    let (first, _) = a.local_payload(node, Some(b"one".to_vec())).unwrap();
+    let (second, _) = a.local_payload(node, Some(b"two".to_vec())).unwrap();
+
+    // `b` never receives `first`, yet its write must still dominate it.
+    b.apply_remote(insert).unwrap();
+    b.apply_remote(second).unwrap();
+    let (third, _) = b.local_payload(node, Some(b"three".to_vec())).unwrap();
+    assert!(third.meta.known_state.as_ref().unwrap().contains(&ra, first.meta.id.counter));
+
+    b.apply_remote(first).unwrap();
+    assert_eq!(values(&b, node), vec![Some(b"three".to_vec())]);
+}
+
+#[test]
+fn last_writer_wins_trees_keep_no_values_and_send_no_known_state() {
+    let a = ReplicaId::new(b"a");
+    let mut crdt = TreeCrdt::new(a, MemoryStorage::default(), LamportClock::default()).unwrap();
+    let node = NodeId(1);
+    crdt.local_insert(NodeId::ROOT, node, LocalPlacement::Last, None).unwrap();
+    let (op, _) = crdt.local_payload(node, Some(b"x".to_vec())).unwrap();
+
+    assert_eq!(crdt.payload_mode(), PayloadMode::LastWriterWins);
+    assert_eq!(op.meta.known_state, None);
+    assert!(crdt.payload_values(node).unwrap().is_empty());
+}
+
+#[test]
+fn concurrent_values_survive_out_of_order_replay_and_compaction() {
+    let a = ReplicaId::new(b"a");
+    let mut crdt = multi_value(&a);
+    let node = NodeId(1);
+    crdt.local_insert(NodeId::ROOT, node, LocalPlacement::Last, None).unwrap();
+    crdt.local_payload(node, Some(b"ours".to_vec())).unwrap();
+
+    // Sorts before our writes, so it is applied through a replay from storage.
+    let early = ReplicaId::new(b"0");
+    crdt.apply_remote(Operation::set_payload(
+        &early,
+        1,
+        1,
+        node,
+        b"early".to_vec(),
+    ))
+    .unwrap();
+    let expected = vec![Some(b"early".to_vec()), Some(b"ours".to_vec())];
+    assert_eq!(values(&crdt, node), expected);
+
+    let mut everything = VersionVector::new();
+    everything.observe(&a, 1);
+    everything.observe(&a, 2);
+    everything.observe(&early, 1);
+    let mut stability = StabilityTracker::new();
+    stability.acknowledge(&a, &everything);
+    let checkpoint = crdt.compact(&stability).unwrap().unwrap();
+    assert_eq!(checkpoint.values.len(), 2);
+    crdt.replay_from_storage().unwrap();
+    assert_eq!(values(&crdt, node), expected);
+}
@@ -0,0 +1,132 @@
+use treecrdt_core::{
+    LamportClock, LocalPlacement, MemoryNodeStore, MemoryPayloadStore, MemoryStorage, NodeId,
+    Operation, OrphanPolicy, ReplicaId, StabilityTracker, TreeCrdt, VersionVector,
+};
+
+mod common;
+
+use common::{tree, vv_of};
+
+#[test]
+fn purge_removes_stable_deleted_subtree_and_keeps_visible_state() {
+    let a = ReplicaId::new(b"a");
+    let mut crdt = tree(&a);
+    crdt.local_insert(
+        NodeId::ROOT,
+        NodeId(1),
+        LocalPlacement::First,
+        Some(b"one".to_vec()),
+    )
+    .unwrap();
+    crdt.local_insert(
+        NodeId(1),
+        NodeId(2),
+        LocalPlacement::First,
+        Some(b"two".to_vec()),
+    )
+    .unwrap();
+    crdt.local_insert(NodeId::ROOT, NodeId(3), LocalPlacement::Last, None).unwrap();
+    crdt.local_delete(NodeId(1)).unwrap();
+
+    // Nothing is stable yet.
+    assert!(crdt.purge_stable(&VersionVector::new()).unwrap().is_empty());
+    assert!(crdt.is_known(NodeId(1)).unwrap());
+
+    let root_vv = crdt.subtree_version_vector(NodeId::ROOT).unwrap();
+    let report = crdt.purge_stable(&vv_of(&crdt.operations_since(0).unwrap())).unwrap();
+    assert_eq!(report.roots, vec![NodeId(1)]);
+    assert_eq!(report.nodes, vec![NodeId(1), NodeId(2)]);
+    assert_eq!(report.payloads, vec![NodeId(1), NodeId(2)]);
+
+    assert!(!crdt.is_known(NodeId(1)).unwrap());
+    assert!(!crdt.is_known(NodeId(2)).unwrap());
+    assert_eq!(crdt.children(NodeId::ROOT).unwrap(), vec![NodeId(3)]);
+    assert_eq!(crdt.subtree_version_vector(NodeId::ROOT).unwrap(), root_vv);
+    assert!(crdt
+        .purge_stable(&vv_of(&crdt.operations_since(0).unwrap()))
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn purge_skips_revived_and_unsettled_deletions() {
+    let a = ReplicaId::new(b"a");
+    let b = ReplicaId::new(b"b");
+    let mut crdt = tree(&a);
+    crdt.local_insert(NodeId::ROOT, NodeId(1), LocalPlacement::First, None).unwrap();
+    crdt.local_insert(NodeId::ROOT, NodeId(2), LocalPlacement::Last, None).unwrap();
+    let (delete_1, _) = crdt.local_delete(NodeId(1)).unwrap();
+    crdt.local_delete(NodeId(2)).unwrap();
+
+    // `b` inserted under node 1 without having seen the delete, which revives it.
+    crdt.apply_remote(Operation::insert(
+        &b,
+        1,
+        2,
+        NodeId(1),
+        NodeId(10),
+        vec![0, 1],
+    ))
+    .unwrap();
+    assert_eq!(crdt.children(NodeId::ROOT).unwrap(), vec![NodeId(1)]);
+
+    // Peer `b` has seen the deletes but we have not caught up with everything it acknowledged.
+    let local = vv_of(&crdt.operations_since(0).unwrap());
+    let mut b_ack = local.clone();
+    b_ack.observe(&b, 2);
+    let mut tracker = StabilityTracker::new();
+    tracker.acknowledge(&a, &local);
+    tracker.acknowledge(&b, &b_ack);
+    assert!(tracker.stable_frontier().contains(&a, delete_1.meta.id.counter));
+    assert!(tracker.settled_frontier(&local).is_empty());
+
+    tracker.acknowledge(&a, &b_ack);
+    let mut caught_up = local.clone();
+    caught_up.observe(&b, 2);
+    let settled = tracker.settled_frontier(&caught_up);
+    let report = crdt.purge_stable(&settled).unwrap();
+    assert_eq!(report.roots, vec![NodeId(2)]);
+    assert!(crdt.is_known(NodeId(1)).unwrap());
+    assert_eq!(crdt.children(NodeId(1)).unwrap(), vec![NodeId(10)]);
+}
+
+#[test]
+fn purge_under_keep_parent_follows_what_keeps_nodes_alive() {
+    let keep_parent = |replica: &[u8]| {
+        TreeCrdt::with_stores(
+            ReplicaId::new(replica),
+            MemoryStorage::default(),
+            LamportClock::default(),
+            MemoryNodeStore::with_orphan_policy(OrphanPolicy::KeepParent),
+            MemoryPayloadStore::default(),
+        )
+        .unwrap()
+    };
+    let (mut a, mut b) = (keep_parent(b"a"), keep_parent(b"b"));
+    let (edited, kept, hard) = (NodeId(1), NodeId(2), NodeId(3));
+    let (file, other) = (NodeId(10), NodeId(11));
+    for node in [edited, kept, hard] {
+        let (op, _) = a.local_insert(NodeId::ROOT, node, LocalPlacement::Last, None).unwrap();
+        b.apply_remote(op).unwrap();
+    }
+    // `b` renames one node and adds a file to each of the others before it sees `a` delete
+    // them all, the last one for good.
+    let (edit, _) = b.local_payload(edited, Some(b"renamed".to_vec())).unwrap();
+    let (insert, _) = b.local_insert(kept, file, LocalPlacement::Last, None).unwrap();
+    let (insert_hard, _) = b.local_insert(hard, other, LocalPlacement::Last, None).unwrap();
+    a.local_delete(edited).unwrap();
+    a.local_delete(kept).unwrap();
+    a.local_tombstone(hard).unwrap();
+    for op in [edit, insert, insert_hard] {
+        a.apply_remote(op).unwrap();
+    }
+    assert!(a.is_tombstoned(edited).unwrap());
+    assert!(!a.is_tombstoned(kept).unwrap());
+    assert!(a.is_tombstoned(hard).unwrap());
+
+    let report = a.purge_stable(&vv_of(&a.operations_since(0).unwrap())).unwrap();
+    assert_eq!(report.roots, vec![edited, hard]);
+    assert_eq!(a.children(NodeId::ROOT).unwrap(), vec![kept]);
+    assert_eq!(a.children(kept).unwrap(), vec![file]);
+    assert!(!a.is_known(other).unwrap());
+}
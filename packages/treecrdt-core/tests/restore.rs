@@ -0,0 +1,127 @@
+use treecrdt_core::{
+    Error, LocalPlacement, MaterializationChange, MaterializationSource, NodeId, NoopParentOpIndex,
+    ReplicaId, UndoManager,
+};
+
+mod common;
+
+use common::tree;
+
+#[test]
+fn restore_wins_over_deletes_it_did_not_see() {
+    let (mut a, mut b, mut c) = (
+        tree(&ReplicaId::new(b"a")),
+        tree(&ReplicaId::new(b"b")),
+        tree(&ReplicaId::new(b"c")),
+    );
+    let (parent, child) = (NodeId(1), NodeId(2));
+    let (insert_parent, _) =
+        a.local_insert(NodeId::ROOT, parent, LocalPlacement::First, None).unwrap();
+    let (insert_child, _) = a.local_insert(parent, child, LocalPlacement::First, None).unwrap();
+    let (delete, _) = a.local_delete(parent).unwrap();
+    for op in [insert_parent.clone(), insert_child.clone(), delete.clone()] {
+        b.apply_remote(op).unwrap();
+    }
+
+    // `b` restores while `a` deletes again without having seen the restore.
+    let (restore, _) = b.local_restore(parent).unwrap();
+    assert_eq!(b.children(NodeId::ROOT).unwrap(), vec![parent]);
+    assert_eq!(b.children(parent).unwrap(), vec![child]);
+    let (delete_again, _) = a.local_delete(parent).unwrap();
+
+    a.apply_remote(restore.clone()).unwrap();
+    b.apply_remote(delete_again.clone()).unwrap();
+    for op in [restore, delete_again, delete, insert_child, insert_parent] {
+        c.apply_remote(op).unwrap();
+    }
+    for crdt in [&a, &b, &c] {
+        assert!(!crdt.is_tombstoned(parent).unwrap());
+        assert_eq!(crdt.children(parent).unwrap(), vec![child]);
+        crdt.validate_invariants().unwrap();
+    }
+    assert_eq!(a.nodes().unwrap(), c.nodes().unwrap());
+
+    // A delete that saw the restore hides the node again.
+    let (delete_seen, _) = a.local_delete(parent).unwrap();
+    b.apply_remote(delete_seen).unwrap();
+    assert!(a.is_tombstoned(parent).unwrap());
+    assert!(b.is_tombstoned(parent).unwrap());
+}
+
+#[test]
+fn restoring_a_node_brings_back_its_deleted_ancestors() {
+    let (mut a, mut b) = (tree(&ReplicaId::new(b"a")), tree(&ReplicaId::new(b"b")));
+    let (outer, inner, leaf) = (NodeId(1), NodeId(2), NodeId(3));
+    let mut ops = vec![
+        a.local_insert(NodeId::ROOT, outer, LocalPlacement::First, None).unwrap().0,
+        a.local_insert(outer, inner, LocalPlacement::First, None).unwrap().0,
+        a.local_insert(inner, leaf, LocalPlacement::First, None).unwrap().0,
+        a.local_payload(outer, Some(b"kept".to_vec())).unwrap().0,
+    ];
+    ops.push(a.local_delete(inner).unwrap().0);
+    ops.push(a.local_delete(outer).unwrap().0);
+    assert!(a.children(NodeId::ROOT).unwrap().is_empty());
+
+    let (restore, plan) = a.local_restore(leaf).unwrap();
+    let outcome = a
+        .finalize_local_with_outcome(&restore, &mut NoopParentOpIndex, ops.len() as u64, &plan)
+        .unwrap();
+    let source = Some(MaterializationSource::from_op(&restore));
+    assert_eq!(
+        outcome.changes,
+        vec![
+            MaterializationChange::Restore {
+                node: outer,
+                parent_after: Some(NodeId::ROOT),
+                payload: Some(b"kept".to_vec()),
+                source: source.clone(),
+            },
+            MaterializationChange::Restore {
+                node: inner,
+                parent_after: Some(outer),
+                payload: None,
+                source: source.clone(),
+            },
+        ]
+    );
+    assert_eq!(a.children(inner).unwrap(), vec![leaf]);
+
+    // Peers report the same changes, attributed to the restore.
+    let mut seq = 0;
+    for op in ops {
+        b.apply_remote_with_materialization_seq(op, &mut NoopParentOpIndex, &mut seq)
+            .unwrap();
+    }
+    let delta = b
+        .apply_remote_with_materialization_seq(restore, &mut NoopParentOpIndex, &mut seq)
+        .unwrap()
+        .unwrap();
+    assert_eq!(delta.changes, outcome.changes);
+}
+
+#[test]
+fn restore_rejects_visible_nodes_and_is_undone_by_deleting() {
+    let mut crdt = tree(&ReplicaId::new(b"a"));
+    let node = NodeId(1);
+    crdt.local_insert(NodeId::ROOT, node, LocalPlacement::First, None).unwrap();
+    assert!(matches!(
+        crdt.local_restore(node),
+        Err(Error::InvalidOperation(_))
+    ));
+    assert!(matches!(
+        crdt.local_restore(NodeId(9)),
+        Err(Error::InvalidOperation(_))
+    ));
+
+    crdt.local_delete(node).unwrap();
+    let mut undo = UndoManager::new();
+    let prepared = crdt.prepare_local_restore(node).unwrap();
+    undo.record(crdt.undo_record(&prepared.op).unwrap());
+    crdt.commit_prepared_local(prepared).unwrap();
+    assert_eq!(crdt.children(NodeId::ROOT).unwrap(), vec![node]);
+
+    undo.undo(&mut crdt).unwrap();
+    assert!(crdt.is_tombstoned(node).unwrap());
+    undo.redo(&mut crdt).unwrap();
+    assert!(!crdt.is_tombstoned(node).unwrap());
+}
@@ -0,0 +1,104 @@
+use treecrdt_core::{
+    Anchored, LamportClock, LocalPlacement, MemoryStorage, NodeId, Operation, ReplicaId, TreeCrdt,
+};
+
+mod common;
+
+use common::Tree;
+
+fn anchored(replica: &[u8]) -> Tree {
+    let mut crdt = TreeCrdt::new(
+        ReplicaId::new(replica),
+        MemoryStorage::default(),
+        LamportClock::default(),
+    )
+    .unwrap();
+    crdt.set_order_key_allocator(Anchored);
+    crdt
+}
+
+/// Insert `nodes` one by one, each right after the previous one, starting after `after`.
+fn type_forward(crdt: &mut Tree, after: NodeId, nodes: &[NodeId]) -> Vec<Operation> {
+    let mut prev = after;
+    let mut ops = Vec::new();
+    for node in nodes {
+        let (op, _) = crdt
+            .local_insert(NodeId::ROOT, *node, LocalPlacement::After(prev), None)
+            .unwrap();
+        ops.push(op);
+        prev = *node;
+    }
+    ops
+}
+
+fn ids(range: std::ops::Range<u128>) -> Vec<NodeId> {
+    range.map(NodeId).collect()
+}
+
+#[test]
+fn runs_keep_one_level_of_key_in_either_direction() {
+    let mut a = anchored(b"a");
+    let first = NodeId(1);
+    a.local_insert(NodeId::ROOT, first, LocalPlacement::First, None).unwrap();
+    type_forward(&mut a, first, &ids(2..300));
+    // One waypoint header for "a" (seven digits) plus the index and its marker.
+    assert_eq!(a.order_key_stats(NodeId::ROOT).unwrap().max_len, 18);
+
+    // Typing backwards, each node in front of the last, stays in the first node's waypoint too.
+    for n in 300..600 {
+        a.local_insert(NodeId::ROOT, NodeId(n), LocalPlacement::First, None).unwrap();
+    }
+    assert_eq!(a.order_key_stats(NodeId::ROOT).unwrap().max_len, 18);
+
+    // Typing backwards in the middle of the list nests once, then stays there.
+    let middle = NodeId(150);
+    for n in 600..900 {
+        a.local_insert(NodeId::ROOT, NodeId(n), LocalPlacement::After(middle), None)
+            .unwrap();
+    }
+    assert!(a.order_key_stats(NodeId::ROOT).unwrap().max_len <= 2 * 18);
+    let children = a.children(NodeId::ROOT).unwrap();
+    let mut expected: Vec<NodeId> = ids(300..600).into_iter().rev().collect();
+    expected.extend(ids(1..151));
+    expected.extend(ids(600..900).into_iter().rev());
+    expected.extend(ids(151..300));
+    assert_eq!(children, expected);
+}
+
+#[test]
+fn anchored_keys_respect_placement_next_to_other_keys() {
+    // Siblings keyed by the default allocator, then edited in anchored mode.
+    let mut crdt = TreeCrdt::new(
+        ReplicaId::new(b"a"),
+        MemoryStorage::default(),
+        LamportClock::default(),
+    )
+    .unwrap();
+    for n in 1..=3 {
+        crdt.local_insert(NodeId::ROOT, NodeId(n), LocalPlacement::Last, None).unwrap();
+    }
+    crdt.set_order_key_allocator(Anchored);
+    crdt.local_insert(NodeId::ROOT, NodeId(4), LocalPlacement::First, None).unwrap();
+    crdt.local_insert(
+        NodeId::ROOT,
+        NodeId(5),
+        LocalPlacement::After(NodeId(1)),
+        None,
+    )
+    .unwrap();
+    crdt.local_insert(
+        NodeId::ROOT,
+        NodeId(6),
+        LocalPlacement::After(NodeId(5)),
+        None,
+    )
+    .unwrap();
+    crdt.local_move(NodeId(3), NodeId::ROOT, LocalPlacement::After(NodeId(4)))
+        .unwrap();
+    crdt.local_insert(NodeId::ROOT, NodeId(7), LocalPlacement::Last, None).unwrap();
+
+    assert_eq!(
+        crdt.children(NodeId::ROOT).unwrap(),
+        [4, 3, 1, 5, 6, 2, 7].map(NodeId).to_vec()
+    );
+}
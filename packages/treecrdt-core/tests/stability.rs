@@ -0,0 +1,81 @@
+use treecrdt_core::{Operation, ReplicaId, StabilityTracker, VersionVector};
+
+fn vv(entries: &[(&ReplicaId, &[u64])]) -> VersionVector {
+    let mut vv = VersionVector::new();
+    for (replica, counters) in entries {
+        for counter in *counters {
+            vv.observe(replica, *counter);
+        }
+    }
+    vv
+}
+
+#[test]
+fn intersection_keeps_only_commonly_observed_counters() {
+    let a = ReplicaId::new(b"a");
+    let b = ReplicaId::new(b"b");
+    let left = vv(&[(&a, &[1, 2, 3, 4, 7, 8]), (&b, &[1])]);
+    let right = vv(&[(&a, &[1, 2, 5, 6, 7])]);
+
+    let common = left.intersection(&right);
+    assert_eq!(common, vv(&[(&a, &[1, 2, 7])]));
+    assert_eq!(common.frontier(&a), 2);
+    assert!(common.contains(&a, 7));
+    assert!(!common.contains(&a, 3));
+    assert!(!common.contains(&b, 1));
+    assert_eq!(right.intersection(&left), common);
+}
+
+#[test]
+fn stable_frontier_is_greatest_lower_bound_of_acks() {
+    let a = ReplicaId::new(b"a");
+    let b = ReplicaId::new(b"b");
+    let c = ReplicaId::new(b"c");
+
+    let mut tracker = StabilityTracker::new();
+    assert!(tracker.stable_frontier().is_empty());
+
+    tracker.acknowledge(&a, &vv(&[(&a, &[1, 2, 3]), (&b, &[1, 2])]));
+    tracker.acknowledge(&b, &vv(&[(&a, &[1, 2]), (&b, &[1, 2, 3])]));
+    assert_eq!(
+        tracker.stable_frontier(),
+        vv(&[(&a, &[1, 2]), (&b, &[1, 2])])
+    );
+
+    // A peer that has not acknowledged anything yet blocks stability entirely.
+    tracker.add_peer(c.clone());
+    assert!(tracker.stable_frontier().is_empty());
+    let op = Operation::delete(&a, 1, 1, treecrdt_core::NodeId(1), None);
+    assert!(!tracker.is_op_stable(&op));
+
+    tracker.acknowledge(&c, &vv(&[(&a, &[1, 2, 3]), (&b, &[1])]));
+    assert_eq!(tracker.stable_frontier(), vv(&[(&a, &[1, 2]), (&b, &[1])]));
+    assert!(tracker.is_op_stable(&op));
+    assert!(tracker.is_stable(&vv(&[(&b, &[1])])));
+    assert!(!tracker.is_stable(&vv(&[(&b, &[2])])));
+
+    // Stale acks never move the frontier back.
+    tracker.acknowledge(&c, &vv(&[(&a, &[1])]));
+    assert_eq!(tracker.stable_frontier(), vv(&[(&a, &[1, 2]), (&b, &[1])]));
+
+    tracker.remove_peer(&c);
+    assert_eq!(
+        tracker.stable_frontier(),
+        vv(&[(&a, &[1, 2]), (&b, &[1, 2])])
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn tracker_json_roundtrips() {
+    let a = ReplicaId::new(b"a");
+    let b = ReplicaId::new(b"b");
+    let mut tracker = StabilityTracker::new();
+    tracker.acknowledge(&a, &vv(&[(&a, &[1, 2]), (&b, &[1])]));
+    tracker.add_peer(b);
+
+    let bytes = serde_json::to_vec(&tracker).expect("serialize StabilityTracker");
+    let roundtrip: StabilityTracker =
+        serde_json::from_slice(&bytes).expect("deserialize StabilityTracker");
+    assert_eq!(roundtrip, tracker);
+}
@@ -0,0 +1,300 @@
+use treecrdt_core::{
+    authorize_ops, Capability, CapabilityAction, Error, LamportClock, LocalPlacement,
+    MemoryNodeStore, MemoryStorage, NodeId, NodeStore, Operation, ReplicaId, ScopeDecision,
+    SubtreePolicy, SubtreeScope, TreeCrdt,
+};
+
+use CapabilityAction::{Delete, Read, WritePayload, WriteStructure};
+
+// ROOT
+// ├── 1
+// │   └── 11
+// │       └── 12
+// └── 2
+//     └── 21
+fn build_tree() -> MemoryNodeStore {
+    let mut nodes = MemoryNodeStore::default();
+    for (node, parent) in [
+        (NodeId(1), NodeId::ROOT),
+        (NodeId(11), NodeId(1)),
+        (NodeId(12), NodeId(11)),
+        (NodeId(2), NodeId::ROOT),
+        (NodeId(21), NodeId(2)),
+    ] {
+        nodes.ensure_node(node).unwrap();
+        nodes.attach(node, parent, node.0.to_be_bytes().to_vec()).unwrap();
+    }
+    nodes
+}
+
+fn replica() -> ReplicaId {
+    ReplicaId::new(b"r")
+}
+
+#[test]
+fn scope_walks_ancestry_with_depth_and_exclusions() {
+    let nodes = build_tree();
+    let scope = SubtreeScope::subtree(NodeId(1));
+    assert_eq!(
+        scope.contains(&nodes, NodeId(1)).unwrap(),
+        ScopeDecision::Allow
+    );
+    assert_eq!(
+        scope.contains(&nodes, NodeId(12)).unwrap(),
+        ScopeDecision::Allow
+    );
+    assert_eq!(
+        scope.contains(&nodes, NodeId(21)).unwrap(),
+        ScopeDecision::Deny
+    );
+    assert_eq!(
+        scope.contains(&nodes, NodeId::ROOT).unwrap(),
+        ScopeDecision::Deny
+    );
+    assert_eq!(
+        scope.contains(&nodes, NodeId(99)).unwrap(),
+        ScopeDecision::Unknown
+    );
+
+    let shallow = SubtreeScope {
+        max_depth: Some(1),
+        ..SubtreeScope::subtree(NodeId(1))
+    };
+    assert_eq!(
+        shallow.contains(&nodes, NodeId(11)).unwrap(),
+        ScopeDecision::Allow
+    );
+    assert_eq!(
+        shallow.contains(&nodes, NodeId(12)).unwrap(),
+        ScopeDecision::Deny
+    );
+
+    let carved = SubtreeScope {
+        exclude: vec![NodeId(11)],
+        ..SubtreeScope::subtree(NodeId(1))
+    };
+    assert_eq!(
+        carved.contains(&nodes, NodeId(1)).unwrap(),
+        ScopeDecision::Allow
+    );
+    assert_eq!(
+        carved.contains(&nodes, NodeId(12)).unwrap(),
+        ScopeDecision::Deny
+    );
+
+    assert_eq!(
+        SubtreeScope::doc_wide().contains(&nodes, NodeId(99)).unwrap(),
+        ScopeDecision::Allow
+    );
+}
+
+#[test]
+fn ops_require_matching_actions_inside_scope() {
+    let nodes = build_tree();
+    let policy = SubtreePolicy::new(vec![Capability::new(
+        SubtreeScope::subtree(NodeId(1)),
+        [WriteStructure],
+    )]);
+    let r = replica();
+
+    let insert = Operation::insert(&r, 1, 1, NodeId(11), NodeId(13), vec![1]);
+    assert_eq!(
+        policy.evaluate_op(&nodes, &insert).unwrap(),
+        ScopeDecision::Allow
+    );
+
+    let insert_elsewhere = Operation::insert(&r, 2, 2, NodeId(2), NodeId(22), vec![1]);
+    assert_eq!(
+        policy.evaluate_op(&nodes, &insert_elsewhere).unwrap(),
+        ScopeDecision::Deny
+    );
+
+    let insert_with_payload =
+        Operation::insert_with_payload(&r, 3, 3, NodeId(11), NodeId(14), vec![1], vec![7]);
+    assert_eq!(
+        policy.evaluate_op(&nodes, &insert_with_payload).unwrap(),
+        ScopeDecision::Deny
+    );
+
+    let payload = Operation::set_payload(&r, 4, 4, NodeId(12), vec![1]);
+    assert_eq!(
+        policy.evaluate_op(&nodes, &payload).unwrap(),
+        ScopeDecision::Deny
+    );
+
+    let delete = Operation::delete(&r, 5, 5, NodeId(12), None);
+    assert_eq!(
+        policy.evaluate_op(&nodes, &delete).unwrap(),
+        ScopeDecision::Deny
+    );
+
+    let policy = SubtreePolicy::new(vec![Capability::new(
+        SubtreeScope::subtree(NodeId(1)),
+        [WritePayload, Delete],
+    )]);
+    assert_eq!(
+        policy.evaluate_op(&nodes, &payload).unwrap(),
+        ScopeDecision::Allow
+    );
+    assert_eq!(
+        policy.evaluate_op(&nodes, &delete).unwrap(),
+        ScopeDecision::Allow
+    );
+    assert_eq!(
+        policy.evaluate_node(&nodes, NodeId(12), &[Read]).unwrap(),
+        ScopeDecision::Allow
+    );
+}
+
+#[test]
+fn moves_must_be_authorized_at_source_and_destination() {
+    let nodes = build_tree();
+    let r = replica();
+    let policy = SubtreePolicy::new(vec![Capability::new(
+        SubtreeScope::subtree(NodeId(1)),
+        [WriteStructure],
+    )]);
+
+    let within = Operation::move_node(&r, 1, 1, NodeId(12), NodeId(1), vec![1]);
+    assert_eq!(
+        policy.evaluate_op(&nodes, &within).unwrap(),
+        ScopeDecision::Allow
+    );
+
+    let out_of_scope = Operation::move_node(&r, 2, 2, NodeId(12), NodeId(2), vec![1]);
+    assert_eq!(
+        policy.evaluate_op(&nodes, &out_of_scope).unwrap(),
+        ScopeDecision::Deny
+    );
+
+    let into_scope = Operation::move_node(&r, 3, 3, NodeId(21), NodeId(11), vec![1]);
+    assert_eq!(
+        policy.evaluate_op(&nodes, &into_scope).unwrap(),
+        ScopeDecision::Deny
+    );
+
+    let mut both = policy.clone();
+    both.grant(Capability::new(
+        SubtreeScope::subtree(NodeId(2)),
+        [WriteStructure],
+    ));
+    assert_eq!(
+        both.evaluate_op(&nodes, &out_of_scope).unwrap(),
+        ScopeDecision::Allow
+    );
+    assert_eq!(
+        both.evaluate_op(&nodes, &into_scope).unwrap(),
+        ScopeDecision::Allow
+    );
+}
+
+#[test]
+fn inserting_an_existing_node_is_authorized_like_a_move() {
+    let mut nodes = build_tree();
+    let r = replica();
+    let policy = SubtreePolicy::new(vec![Capability::new(
+        SubtreeScope::subtree(NodeId(1)),
+        [WriteStructure],
+    )]);
+
+    let fresh = Operation::insert(&r, 1, 1, NodeId(11), NodeId(50), vec![1]);
+    assert_eq!(
+        policy.evaluate_op(&nodes, &fresh).unwrap(),
+        ScopeDecision::Allow
+    );
+
+    let pull_in = Operation::insert(&r, 2, 2, NodeId(11), NodeId(21), vec![1]);
+    assert_eq!(
+        policy.evaluate_op(&nodes, &pull_in).unwrap(),
+        ScopeDecision::Deny
+    );
+
+    let mut both = policy.clone();
+    both.grant(Capability::new(
+        SubtreeScope::subtree(NodeId(2)),
+        [WriteStructure],
+    ));
+    assert_eq!(
+        both.evaluate_op(&nodes, &pull_in).unwrap(),
+        ScopeDecision::Allow
+    );
+
+    // A detached placeholder (e.g. a payload that arrived first) has no position to guard.
+    nodes.ensure_node(NodeId(60)).unwrap();
+    let placeholder = Operation::insert(&r, 3, 3, NodeId(11), NodeId(60), vec![1]);
+    assert_eq!(
+        policy.evaluate_op(&nodes, &placeholder).unwrap(),
+        ScopeDecision::Allow
+    );
+}
+
+#[test]
+fn unknown_ancestry_fails_closed_and_batches_resolve_their_own_parents() {
+    let nodes = build_tree();
+    let r = replica();
+    let policy = SubtreePolicy::new(vec![Capability::new(
+        SubtreeScope::subtree(NodeId(1)),
+        [WriteStructure],
+    )]);
+
+    let parent = Operation::insert(&r, 1, 1, NodeId(11), NodeId(30), vec![1]);
+    let child = Operation::insert(&r, 2, 2, NodeId(30), NodeId(31), vec![1]);
+    assert_eq!(
+        policy.evaluate_op(&nodes, &child).unwrap(),
+        ScopeDecision::Unknown
+    );
+    assert!(matches!(
+        authorize_ops(&policy, &nodes, std::slice::from_ref(&child)),
+        Err(Error::AccessDenied(_))
+    ));
+    authorize_ops(&policy, &nodes, &[parent, child]).unwrap();
+
+    let outside = Operation::insert(&r, 3, 3, NodeId(2), NodeId(40), vec![1]);
+    let under_outside = Operation::insert(&r, 4, 4, NodeId(40), NodeId(41), vec![1]);
+    assert!(matches!(
+        authorize_ops(&policy, &nodes, &[outside, under_outside]),
+        Err(Error::AccessDenied(_))
+    ));
+}
+
+#[test]
+fn tree_crdt_enforces_subtree_policy_for_local_ops_and_reads() {
+    let mut crdt =
+        TreeCrdt::new(replica(), MemoryStorage::default(), LamportClock::default()).unwrap();
+    let shared = NodeId(1);
+    let private = NodeId(2);
+    crdt.local_insert(NodeId::ROOT, shared, LocalPlacement::First, None).unwrap();
+    crdt.local_insert(NodeId::ROOT, private, LocalPlacement::Last, None).unwrap();
+
+    crdt.set_access_control(SubtreePolicy::new(vec![Capability::new(
+        SubtreeScope::subtree(shared),
+        [WriteStructure, WritePayload],
+    )]));
+
+    crdt.local_insert(shared, NodeId(3), LocalPlacement::First, None).unwrap();
+    crdt.local_payload(NodeId(3), Some(vec![1])).unwrap();
+    assert_eq!(crdt.children(shared).unwrap(), vec![NodeId(3)]);
+    assert_eq!(crdt.payload(NodeId(3)).unwrap(), Some(vec![1]));
+
+    assert!(matches!(
+        crdt.local_move(NodeId(3), private, LocalPlacement::First),
+        Err(Error::AccessDenied(_))
+    ));
+    assert!(matches!(
+        crdt.local_insert(private, NodeId(4), LocalPlacement::First, None),
+        Err(Error::AccessDenied(_))
+    ));
+    assert!(matches!(
+        crdt.local_delete(NodeId(3)),
+        Err(Error::AccessDenied(_))
+    ));
+    assert!(matches!(
+        crdt.children(private),
+        Err(Error::AccessDenied(_))
+    ));
+    assert!(matches!(
+        crdt.children(NodeId::ROOT),
+        Err(Error::AccessDenied(_))
+    ));
+    assert_eq!(crdt.parent(NodeId(3)).unwrap(), Some(shared));
+}
@@ -0,0 +1,317 @@
+use treecrdt_core::{
+    plan_reorder, Error, LocalEdit, LocalPlacement, MaterializationChange, NodeId,
+    NoopParentOpIndex, Operation, OperationKind, ReorderRun, ReplicaId, UndoManager,
+};
+
+mod common;
+
+use common::tree;
+
+#[test]
+fn later_edits_see_what_earlier_ones_staged() {
+    let a = ReplicaId::new(b"a");
+    let mut crdt = tree(&a);
+    for n in 1..=3 {
+        crdt.local_insert(NodeId::ROOT, NodeId(n), LocalPlacement::Last, None).unwrap();
+    }
+    let lamport = crdt.lamport();
+
+    let folder = NodeId(10);
+    let staged = crdt
+        .local_batch([
+            LocalEdit::Insert {
+                parent: NodeId::ROOT,
+                node: folder,
+                placement: LocalPlacement::First,
+                payload: None,
+            },
+            LocalEdit::Payload {
+                node: folder,
+                payload: Some(b"folder".to_vec()),
+            },
+            LocalEdit::Move {
+                node: NodeId(1),
+                new_parent: folder,
+                placement: LocalPlacement::Last,
+            },
+            LocalEdit::Move {
+                node: NodeId(3),
+                new_parent: folder,
+                placement: LocalPlacement::After(NodeId(1)),
+            },
+            LocalEdit::Move {
+                node: NodeId(2),
+                new_parent: folder,
+                placement: LocalPlacement::After(NodeId(3)),
+            },
+        ])
+        .unwrap();
+
+    let counters: Vec<u64> = staged.iter().map(|entry| entry.op.meta.id.counter).collect();
+    assert_eq!(counters, vec![4, 5, 6, 7, 8]);
+    assert!(staged.iter().all(|entry| entry.op.meta.lamport > lamport));
+    assert_eq!(crdt.children(NodeId::ROOT).unwrap(), vec![folder]);
+    assert_eq!(
+        crdt.children(folder).unwrap(),
+        vec![NodeId(1), NodeId(3), NodeId(2)]
+    );
+
+    let outcome = crdt
+        .finalize_local_batch_with_outcome(&staged, &mut NoopParentOpIndex, 7)
+        .unwrap();
+    assert_eq!(outcome.head_seq, 12);
+    assert_eq!(outcome.changes.len(), 4);
+    assert!(outcome.changes.contains(&MaterializationChange::Insert {
+        node: folder,
+        parent_after: NodeId::ROOT,
+        payload: Some(b"folder".to_vec()),
+        source: Some(treecrdt_core::MaterializationSource::from_op(&staged[1].op)),
+    }));
+
+    // A peer receiving the ops ends up with the same tree.
+    let mut peer = tree(&ReplicaId::new(b"b"));
+    for op in crdt.operations_since(0).unwrap() {
+        peer.apply_remote(op).unwrap();
+    }
+    assert_eq!(
+        peer.children(folder).unwrap(),
+        vec![NodeId(1), NodeId(3), NodeId(2)]
+    );
+
+    // The batch undoes as one group.
+    let mut undo = UndoManager::new();
+    undo.begin();
+    for entry in &staged {
+        undo.record(entry.undo.clone());
+    }
+    undo.commit();
+    undo.undo(&mut crdt).unwrap();
+    assert_eq!(
+        crdt.children(NodeId::ROOT).unwrap(),
+        vec![NodeId(1), NodeId(2), NodeId(3)]
+    );
+    assert!(!undo.can_undo());
+}
+
+#[test]
+fn staging_stops_at_the_first_failing_edit() {
+    let a = ReplicaId::new(b"a");
+    let mut crdt = tree(&a);
+    crdt.local_insert(NodeId::ROOT, NodeId(1), LocalPlacement::Last, None).unwrap();
+
+    let mut tx = crdt.local_transaction();
+    tx.insert(NodeId::ROOT, NodeId(2), LocalPlacement::Last, None).unwrap();
+    let err = tx.move_node(NodeId(2), NodeId(1), LocalPlacement::After(NodeId(2)));
+    assert!(matches!(err, Err(Error::InvalidOperation(_))));
+    let err = tx.insert(NodeId(1), NodeId(3), LocalPlacement::After(NodeId(9)), None);
+    assert!(matches!(err, Err(Error::InvalidOperation(_))));
+    assert_eq!(tx.staged().len(), 1);
+    assert_eq!(
+        tx.tree().children(NodeId::ROOT).unwrap(),
+        vec![NodeId(1), NodeId(2)]
+    );
+
+    // Failed edits do not burn counters.
+    tx.delete(NodeId(1)).unwrap();
+    let staged = tx.finish().unwrap();
+    assert_eq!(staged[1].op.meta.id.counter, 3);
+}
+
+#[test]
+fn grouped_transactions_stage_exactly_the_declared_members() {
+    let mut crdt = tree(&ReplicaId::new(b"a"));
+    let mut tx = crdt.local_transaction().grouped(2);
+    tx.insert(NodeId::ROOT, NodeId(1), LocalPlacement::Last, None).unwrap();
+    tx.insert(NodeId::ROOT, NodeId(2), LocalPlacement::Last, None).unwrap();
+    let err = tx.insert(NodeId::ROOT, NodeId(3), LocalPlacement::Last, None);
+    assert!(matches!(err, Err(Error::InvalidOperation(_))));
+    assert_eq!(tx.finish().unwrap().len(), 2);
+    assert_eq!(crdt.children(NodeId::ROOT).unwrap().len(), 2);
+
+    let mut tx = crdt.local_transaction().grouped(3);
+    tx.insert(NodeId::ROOT, NodeId(4), LocalPlacement::Last, None).unwrap();
+    assert!(matches!(tx.finish(), Err(Error::InvalidOperation(_))));
+}
+
+#[test]
+fn duplicate_copies_the_visible_subtree_in_order() {
+    let mut crdt = tree(&ReplicaId::new(b"a"));
+    let folder = NodeId(1);
+    crdt.local_insert(
+        NodeId::ROOT,
+        folder,
+        LocalPlacement::Last,
+        Some(b"folder".to_vec()),
+    )
+    .unwrap();
+    for n in [2, 3, 4] {
+        crdt.local_insert(folder, NodeId(n), LocalPlacement::Last, Some(vec![n as u8]))
+            .unwrap();
+    }
+    crdt.local_insert(NodeId(3), NodeId(5), LocalPlacement::Last, None).unwrap();
+    crdt.local_delete(NodeId(4)).unwrap();
+    crdt.local_insert(NodeId::ROOT, NodeId(9), LocalPlacement::Last, None).unwrap();
+
+    let copy_of = |node: NodeId| NodeId(node.0 + 100);
+    let copy = crdt
+        .local_duplicate(folder, NodeId::ROOT, LocalPlacement::After(folder), copy_of)
+        .unwrap();
+    assert_eq!(
+        copy.mapping,
+        vec![
+            (folder, NodeId(101)),
+            (NodeId(2), NodeId(102)),
+            (NodeId(3), NodeId(103)),
+            (NodeId(5), NodeId(105)),
+        ]
+    );
+    assert_eq!(copy.staged.len(), 4);
+    assert!(copy.staged.iter().all(|entry| entry.op.meta.group.is_some()));
+    assert_eq!(
+        crdt.children(NodeId::ROOT).unwrap(),
+        vec![folder, NodeId(101), NodeId(9)]
+    );
+    assert_eq!(
+        crdt.children(NodeId(101)).unwrap(),
+        vec![NodeId(102), NodeId(103)]
+    );
+    assert_eq!(crdt.children(NodeId(103)).unwrap(), vec![NodeId(105)]);
+    assert_eq!(crdt.payload(NodeId(101)).unwrap(), Some(b"folder".to_vec()));
+    assert_eq!(crdt.payload(NodeId(103)).unwrap(), Some(vec![3]));
+
+    // Copying into its own subtree copies the subtree as it was.
+    let copy = crdt
+        .local_duplicate(NodeId(3), NodeId(5), LocalPlacement::Last, |node| {
+            NodeId(node.0 + 200)
+        })
+        .unwrap();
+    assert_eq!(copy.mapping.len(), 2);
+    assert_eq!(crdt.children(NodeId(5)).unwrap(), vec![NodeId(203)]);
+    assert_eq!(crdt.children(NodeId(203)).unwrap(), vec![NodeId(205)]);
+
+    assert!(matches!(
+        crdt.local_duplicate(NodeId(4), NodeId::ROOT, LocalPlacement::Last, copy_of),
+        Err(Error::InvalidOperation(_))
+    ));
+    assert!(matches!(
+        crdt.local_duplicate(NodeId(2), NodeId::ROOT, LocalPlacement::Last, copy_of),
+        Err(Error::InvalidOperation(_))
+    ));
+}
+
+#[test]
+fn insert_many_places_a_run_of_siblings_in_one_group() {
+    let mut crdt = tree(&ReplicaId::new(b"a"));
+    for n in [1, 2] {
+        crdt.local_insert(NodeId::ROOT, NodeId(n), LocalPlacement::Last, None).unwrap();
+    }
+
+    let lines: Vec<(NodeId, Option<Vec<u8>>)> =
+        (10..110).map(|n| (NodeId(n), Some(vec![n as u8]))).collect();
+    let staged = crdt
+        .local_insert_many(NodeId::ROOT, LocalPlacement::After(NodeId(1)), lines)
+        .unwrap();
+    assert_eq!(staged.len(), 100);
+    assert!(staged.iter().all(|entry| entry.op.meta.group.map(|g| g.len) == Some(100)));
+
+    let mut expected = vec![NodeId(1)];
+    expected.extend((10..110).map(NodeId));
+    expected.push(NodeId(2));
+    assert_eq!(crdt.children(NodeId::ROOT).unwrap(), expected);
+    assert_eq!(crdt.payload(NodeId(42)).unwrap(), Some(vec![42]));
+
+    assert!(matches!(
+        crdt.local_insert_many(
+            NodeId::ROOT,
+            LocalPlacement::After(NodeId(7)),
+            [(NodeId(200), None)]
+        ),
+        Err(Error::InvalidOperation(_))
+    ));
+}
+
+#[test]
+fn reorder_plans_the_fewest_moves() {
+    let ids = |ns: &[u128]| ns.iter().map(|n| NodeId(*n)).collect::<Vec<_>>();
+    let current = ids(&[1, 2, 3, 4, 5]);
+
+    assert!(plan_reorder(&current, &current).unwrap().is_empty());
+    assert_eq!(
+        plan_reorder(&current, &ids(&[2, 3, 4, 5, 1])).unwrap(),
+        vec![ReorderRun {
+            after: Some(NodeId(5)),
+            before: None,
+            nodes: ids(&[1]),
+        }]
+    );
+    assert_eq!(
+        plan_reorder(&current, &ids(&[4, 5, 1, 2, 3])).unwrap(),
+        vec![ReorderRun {
+            after: None,
+            before: Some(NodeId(1)),
+            nodes: ids(&[4, 5]),
+        }]
+    );
+    let reversed = plan_reorder(&current, &ids(&[5, 4, 3, 2, 1])).unwrap();
+    assert_eq!(reversed.iter().map(|run| run.nodes.len()).sum::<usize>(), 4);
+
+    for bad in [
+        ids(&[1, 2, 3, 4]),
+        ids(&[1, 2, 3, 4, 4]),
+        ids(&[1, 2, 3, 4, 6]),
+    ] {
+        assert!(matches!(
+            plan_reorder(&current, &bad),
+            Err(Error::InvalidOperation(_))
+        ));
+    }
+}
+
+#[test]
+fn reorder_moves_only_the_children_out_of_place() {
+    let mut crdt = tree(&ReplicaId::new(b"a"));
+    let names = ["pear", "apple", "banana", "fig", "kiwi", "grape", "lime"];
+    for (n, name) in names.iter().enumerate() {
+        crdt.local_insert(
+            NodeId::ROOT,
+            NodeId(n as u128 + 1),
+            LocalPlacement::Last,
+            Some(name.as_bytes().to_vec()),
+        )
+        .unwrap();
+    }
+
+    // Sorting by payload only moves pear and one of kiwi and grape.
+    let mut sorted = crdt.children(NodeId::ROOT).unwrap();
+    sorted.sort_by_key(|node| crdt.payload(*node).unwrap());
+    let staged = crdt.local_reorder(NodeId::ROOT, &sorted).unwrap();
+    assert_eq!(staged.len(), 2);
+    assert!(staged.iter().all(|entry| entry.op.meta.group.map(|g| g.len) == Some(2)));
+    assert_eq!(crdt.children(NodeId::ROOT).unwrap(), sorted);
+    assert!(crdt.local_reorder(NodeId::ROOT, &sorted).unwrap().is_empty());
+
+    let mut reversed = sorted.clone();
+    reversed.reverse();
+    let staged = crdt.local_reorder(NodeId::ROOT, &reversed).unwrap();
+    assert_eq!(staged.len(), names.len() - 1);
+    assert!(staged.iter().all(|entry| entry.op.meta.group.map(|g| g.len) == Some(6)));
+    assert_eq!(crdt.children(NodeId::ROOT).unwrap(), reversed);
+
+    let mut peer = tree(&ReplicaId::new(b"b"));
+    let ops: Vec<Operation> = crdt.operations_since(0).unwrap();
+    let moves = ops.iter().filter(|op| matches!(op.kind, OperationKind::Move { .. })).count();
+    assert_eq!(moves, 2 + names.len() - 1);
+    for op in ops {
+        peer.apply_remote(op).unwrap();
+    }
+    assert_eq!(peer.children(NodeId::ROOT).unwrap(), reversed);
+
+    assert!(matches!(
+        crdt.local_reorder(NodeId::ROOT, &reversed[1..]),
+        Err(Error::InvalidOperation(_))
+    ));
+    assert!(matches!(
+        crdt.local_reorder(NodeId::TRASH, &[]),
+        Err(Error::InvalidOperation(_))
+    ));
+}
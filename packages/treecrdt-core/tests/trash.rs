@@ -0,0 +1,86 @@
+use treecrdt_core::{
+    Capability, CapabilityAction, LocalPlacement, MaterializationSource, NodeId, ReplicaId,
+    SubtreePolicy, SubtreeScope, TRASH_PREVIEW_LEN,
+};
+
+mod common;
+
+use common::tree;
+
+#[test]
+fn trash_lists_the_top_of_each_deleted_subtree_newest_first() {
+    let mut crdt = tree(&ReplicaId::new(b"a"));
+    let (folder, file, note) = (NodeId(1), NodeId(2), NodeId(3));
+    let long = vec![7u8; TRASH_PREVIEW_LEN + 10];
+    crdt.local_insert(NodeId::ROOT, folder, LocalPlacement::Last, None).unwrap();
+    crdt.local_insert(folder, file, LocalPlacement::Last, Some(b"file".to_vec()))
+        .unwrap();
+    crdt.local_insert(NodeId::ROOT, note, LocalPlacement::Last, Some(long.clone()))
+        .unwrap();
+
+    let (delete_file, _) = crdt.local_delete(file).unwrap();
+    let (delete_note, _) = crdt.local_delete(note).unwrap();
+    let (delete_folder, _) = crdt.local_delete(folder).unwrap();
+
+    // `file` sits inside the deleted folder, so only the folder is listed for it.
+    let all = crdt.trash_page(None, 10).unwrap();
+    assert_eq!(
+        all.iter().map(|entry| entry.node).collect::<Vec<_>>(),
+        vec![folder, note]
+    );
+    assert_eq!(all[0].parent, Some(NodeId::ROOT));
+    assert_eq!(
+        all[0].source,
+        Some(MaterializationSource::from_op(&delete_folder))
+    );
+    assert!(all[0].deleted_at.contains(&delete_folder.meta.id.replica, 6));
+    assert_eq!(
+        all[1].payload_preview,
+        Some(long[..TRASH_PREVIEW_LEN].to_vec())
+    );
+    assert!(!all[1].hard_deleted);
+
+    let first = crdt.trash_page(None, 1).unwrap();
+    assert_eq!(first, all[..1]);
+    let rest = crdt.trash_page(Some(&first[0].cursor()), 10).unwrap();
+    assert_eq!(rest, all[1..]);
+
+    // Restoring the folder leaves the file, deleted on its own, in the trash.
+    crdt.local_restore(folder).unwrap();
+    let entries = crdt.trash_page(None, 10).unwrap();
+    assert_eq!(
+        entries.iter().map(|entry| entry.node).collect::<Vec<_>>(),
+        vec![note, file]
+    );
+    assert_eq!(
+        entries[0].source,
+        Some(MaterializationSource::from_op(&delete_note))
+    );
+    assert_eq!(
+        entries[1].source,
+        Some(MaterializationSource::from_op(&delete_file))
+    );
+    assert_eq!(entries[1].parent, Some(folder));
+}
+
+#[test]
+fn trash_skips_entries_the_access_policy_hides() {
+    let mut crdt = tree(&ReplicaId::new(b"a"));
+    let (shared, private) = (NodeId(1), NodeId(2));
+    crdt.local_insert(NodeId::ROOT, shared, LocalPlacement::Last, None).unwrap();
+    for (parent, node) in [(shared, NodeId(3)), (NodeId::ROOT, private)] {
+        crdt.local_insert(parent, node, LocalPlacement::Last, Some(b"secret".to_vec()))
+            .unwrap();
+        crdt.local_delete(node).unwrap();
+    }
+
+    crdt.set_access_control(SubtreePolicy::new(vec![Capability::new(
+        SubtreeScope::subtree(shared),
+        [CapabilityAction::Read],
+    )]));
+    let entries = crdt.trash_page(None, 10).unwrap();
+    assert_eq!(
+        entries.iter().map(|entry| entry.node).collect::<Vec<_>>(),
+        vec![NodeId(3)]
+    );
+}
@@ -0,0 +1,234 @@
+use treecrdt_core::{
+    AccessControl, AllowAllAccess, Error, LocalPlacement, NodeId, NodeStore, Operation, ReplicaId,
+    Result, UndoManager,
+};
+
+mod common;
+
+use common::{tree, Tree};
+
+fn record_insert(
+    crdt: &mut Tree,
+    undo: &mut UndoManager,
+    parent: NodeId,
+    node: NodeId,
+    placement: LocalPlacement,
+) {
+    let prepared = crdt.prepare_local_insert(parent, node, placement, None).unwrap();
+    undo.record(crdt.undo_record(&prepared.op).unwrap());
+    crdt.commit_prepared_local(prepared).unwrap();
+}
+
+fn record_move(crdt: &mut Tree, undo: &mut UndoManager, node: NodeId, parent: NodeId) {
+    let prepared = crdt.prepare_local_move(node, parent, LocalPlacement::Last).unwrap();
+    undo.record(crdt.undo_record(&prepared.op).unwrap());
+    crdt.commit_prepared_local(prepared).unwrap();
+}
+
+fn record_delete(crdt: &mut Tree, undo: &mut UndoManager, node: NodeId) {
+    let prepared = crdt.prepare_local_delete(node).unwrap();
+    undo.record(crdt.undo_record(&prepared.op).unwrap());
+    crdt.commit_prepared_local(prepared).unwrap();
+}
+
+fn record_payload(crdt: &mut Tree, undo: &mut UndoManager, node: NodeId, payload: &[u8]) {
+    let prepared = crdt.prepare_local_payload(node, Some(payload.to_vec())).unwrap();
+    undo.record(crdt.undo_record(&prepared.op).unwrap());
+    crdt.commit_prepared_local(prepared).unwrap();
+}
+
+#[test]
+fn undo_and_redo_revert_transactions_with_new_ops() {
+    let a = ReplicaId::new(b"a");
+    let mut crdt = tree(&a);
+    let mut undo = UndoManager::new();
+    record_insert(
+        &mut crdt,
+        &mut undo,
+        NodeId::ROOT,
+        NodeId(1),
+        LocalPlacement::First,
+    );
+    record_insert(
+        &mut crdt,
+        &mut undo,
+        NodeId::ROOT,
+        NodeId(2),
+        LocalPlacement::Last,
+    );
+    record_insert(
+        &mut crdt,
+        &mut undo,
+        NodeId::ROOT,
+        NodeId(3),
+        LocalPlacement::Last,
+    );
+    record_payload(&mut crdt, &mut undo, NodeId(2), b"v1");
+
+    undo.begin();
+    record_payload(&mut crdt, &mut undo, NodeId(2), b"v2");
+    record_move(&mut crdt, &mut undo, NodeId(2), NodeId(3));
+    record_delete(&mut crdt, &mut undo, NodeId(1));
+    undo.commit();
+    assert_eq!(crdt.children(NodeId::ROOT).unwrap(), vec![NodeId(3)]);
+
+    let before = crdt.operations_since(0).unwrap().len();
+    let ops = undo.undo(&mut crdt).unwrap();
+    assert_eq!(ops.len(), 3);
+    assert_eq!(crdt.operations_since(0).unwrap().len(), before + 3);
+    assert_eq!(
+        crdt.children(NodeId::ROOT).unwrap(),
+        vec![NodeId(1), NodeId(2), NodeId(3)]
+    );
+    assert_eq!(crdt.payload(NodeId(2)).unwrap(), Some(b"v1".to_vec()));
+    assert!(undo.can_redo());
+
+    undo.redo(&mut crdt).unwrap();
+    assert_eq!(crdt.children(NodeId::ROOT).unwrap(), vec![NodeId(3)]);
+    assert_eq!(crdt.children(NodeId(3)).unwrap(), vec![NodeId(2)]);
+    assert_eq!(crdt.payload(NodeId(2)).unwrap(), Some(b"v2".to_vec()));
+    assert!(!undo.can_redo());
+
+    // Undoing back to the start removes the inserts again.
+    while undo.can_undo() {
+        undo.undo(&mut crdt).unwrap();
+    }
+    assert!(crdt.children(NodeId::ROOT).unwrap().is_empty());
+
+    // A peer replaying the whole log converges on the same tree.
+    let mut peer = tree(&ReplicaId::new(b"b"));
+    for op in crdt.operations_since(0).unwrap() {
+        peer.apply_remote(op).unwrap();
+    }
+    assert!(peer.children(NodeId::ROOT).unwrap().is_empty());
+}
+
+#[test]
+fn undo_skips_state_taken_over_by_concurrent_ops() {
+    let a = ReplicaId::new(b"a");
+    let b = ReplicaId::new(b"b");
+    let mut crdt = tree(&a);
+    let mut undo = UndoManager::new();
+    record_insert(
+        &mut crdt,
+        &mut undo,
+        NodeId::ROOT,
+        NodeId(1),
+        LocalPlacement::First,
+    );
+    record_insert(
+        &mut crdt,
+        &mut undo,
+        NodeId::ROOT,
+        NodeId(2),
+        LocalPlacement::Last,
+    );
+    undo.clear();
+
+    record_payload(&mut crdt, &mut undo, NodeId(1), b"mine");
+    record_move(&mut crdt, &mut undo, NodeId(2), NodeId(1));
+    let lamport = crdt.lamport();
+    crdt.apply_remote(Operation::set_payload(
+        &b,
+        1,
+        lamport + 1,
+        NodeId(1),
+        b"theirs".to_vec(),
+    ))
+    .unwrap();
+    crdt.apply_remote(Operation::move_node(
+        &b,
+        2,
+        lamport + 2,
+        NodeId(2),
+        NodeId::ROOT,
+        vec![0xf0],
+    ))
+    .unwrap();
+
+    // Both edits were overwritten by `b`, so there is nothing left to undo.
+    assert!(undo.undo(&mut crdt).unwrap().is_empty());
+    assert!(undo.undo(&mut crdt).unwrap().is_empty());
+    assert_eq!(crdt.payload(NodeId(1)).unwrap(), Some(b"theirs".to_vec()));
+    assert_eq!(crdt.parent(NodeId(2)).unwrap(), Some(NodeId::ROOT));
+
+    // `b` revived the deleted node by editing inside it, which leaves nothing to undo.
+    record_delete(&mut crdt, &mut undo, NodeId(2));
+    let lamport = crdt.lamport();
+    crdt.apply_remote(Operation::insert(
+        &b,
+        3,
+        lamport + 1,
+        NodeId(2),
+        NodeId(20),
+        vec![0x80],
+    ))
+    .unwrap();
+    assert!(undo.undo(&mut crdt).unwrap().is_empty());
+    assert_eq!(crdt.children(NodeId(2)).unwrap(), vec![NodeId(20)]);
+
+    // Undoing a delete moves the node back where it was, with the payload `b` wrote.
+    record_delete(&mut crdt, &mut undo, NodeId(1));
+    assert_eq!(undo.undo(&mut crdt).unwrap().len(), 1);
+    assert_eq!(crdt.parent(NodeId(1)).unwrap(), Some(NodeId::ROOT));
+    assert_eq!(crdt.payload(NodeId(1)).unwrap(), Some(b"theirs".to_vec()));
+
+    // Only the local replica's own ops can be inverted.
+    let foreign = crdt.undo_record(&Operation::clear_payload(&b, 4, 99, NodeId(1))).unwrap();
+    assert!(crdt.prepare_inverse(&foreign).is_err());
+}
+
+/// Denies every op on one node.
+struct DenyNode(NodeId);
+
+impl AccessControl for DenyNode {
+    fn can_apply(&self, _nodes: &dyn NodeStore, op: &Operation) -> Result<()> {
+        if op.kind.node() == self.0 {
+            return Err(Error::AccessDenied("node is fenced off".into()));
+        }
+        Ok(())
+    }
+
+    fn can_read(&self, _nodes: &dyn NodeStore, _node: NodeId) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn failed_undo_keeps_only_the_records_it_did_not_invert() {
+    let mut crdt = tree(&ReplicaId::new(b"a"));
+    let mut undo = UndoManager::new();
+    undo.begin();
+    for node in [NodeId(1), NodeId(2)] {
+        record_insert(
+            &mut crdt,
+            &mut undo,
+            NodeId::ROOT,
+            node,
+            LocalPlacement::Last,
+        );
+    }
+    undo.commit();
+
+    // The newest record is inverted first, so the insert of node 1 is the one denied.
+    crdt.set_access_control(DenyNode(NodeId(1)));
+    assert!(matches!(undo.undo(&mut crdt), Err(Error::AccessDenied(_))));
+    assert_eq!(crdt.children(NodeId::ROOT).unwrap(), vec![NodeId(1)]);
+    assert!(undo.can_undo());
+    assert!(undo.can_redo());
+
+    // The retry only reverts what is left instead of deleting node 2 a second time.
+    crdt.set_access_control(AllowAllAccess);
+    let before = crdt.operations_since(0).unwrap().len();
+    assert_eq!(undo.undo(&mut crdt).unwrap().len(), 1);
+    assert_eq!(crdt.operations_since(0).unwrap().len(), before + 1);
+    assert!(crdt.children(NodeId::ROOT).unwrap().is_empty());
+    assert!(!undo.can_undo());
+
+    undo.redo(&mut crdt).unwrap();
+    undo.redo(&mut crdt).unwrap();
+    assert_eq!(
+        crdt.children(NodeId::ROOT).unwrap(),
+        vec![NodeId(1), NodeId(2)]
+    );
+}
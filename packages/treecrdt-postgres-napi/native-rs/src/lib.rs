@@ -3,11 +3,16 @@
 use napi::bindgen_prelude::*;
 use napi_derive::napi;
 use postgres::{Client, NoTls};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
 use treecrdt_core::{
-    Error as CoreError, Lamport, MaterializationChange, MaterializationOutcome,
-    MaterializationSource, NodeId, Operation, OperationId, OperationKind, ReplicaId,
-    Result as CoreResult, VersionVector,
+    Error as CoreError, GroupBuffer, Lamport, LocalEdit, LocalPlacement, MaterializationChange,
+    MaterializationOutcome, MaterializationSource, NodeId, Operation, OperationGroup, OperationId,
+    OperationKind, OrphanPolicy, PartialGroupPolicy, PayloadMode, ReplicaId, Result as CoreResult,
+    TrashCursor, UndoManager, VersionVector,
 };
+use treecrdt_postgres::PgClient;
 
 fn map_err(e: impl std::fmt::Display) -> napi::Error {
     napi::Error::new(Status::GenericFailure, format!("{e}"))
@@ -17,8 +22,8 @@ fn map_core_err(e: CoreError) -> napi::Error {
     map_err(format!("{e:?}"))
 }
 
-fn connect(url: &str) -> napi::Result<Client> {
-    Client::connect(url, NoTls).map_err(map_err)
+fn connect(url: &str) -> napi::Result<PgClient> {
+    Client::connect(url, NoTls).map(PgClient::from).map_err(map_err)
 }
 
 fn bytes16_to_node(bytes: &[u8]) -> CoreResult<NodeId> {
@@ -58,6 +63,11 @@ pub struct NativeOp {
     pub order_key: Option<Buffer>,
     pub payload: Option<Buffer>,
     pub known_state: Option<Buffer>,
+    /// Counter of the first op of the op group this op belongs to, if any.
+    pub group_first: Option<BigInt>,
+    pub group_len: Option<u32>,
+    /// Payload map field written by a `payload_field` op; the value travels in `payload`.
+    pub field: Option<String>,
 }
 
 #[napi(object)]
@@ -74,6 +84,12 @@ pub struct NativeTreeRow {
     pub tombstone: bool,
 }
 
+#[napi(object)]
+pub struct NativeTreeDescendant {
+    pub node: Buffer,
+    pub depth: u32,
+}
+
 #[napi(object)]
 pub struct NativeMaterializationChange {
     pub kind: String,
@@ -81,7 +97,35 @@ pub struct NativeMaterializationChange {
     pub parent_before: Option<Buffer>,
     pub parent_after: Option<Buffer>,
     pub payload: Option<Buffer>,
+    /// Set for `payloadField` changes, whose new value is in `payload`.
+    pub field: Option<String>,
+    pub source: Option<NativeMaterializationSource>,
+}
+
+#[napi(object)]
+pub struct NativePayloadField {
+    pub field: String,
+    pub value: Buffer,
+}
+
+/// One concurrent value of a node's payload; `known_state` is a serialized version vector.
+#[napi(object)]
+pub struct NativePayloadValue {
+    pub payload: Option<Buffer>,
+    pub lamport: BigInt,
+    pub writer: NativeOperationId,
+    pub known_state: Buffer,
+}
+
+/// One deleted subtree for a trash view; `deleted_at` is a serialized version vector.
+#[napi(object)]
+pub struct NativeTrashEntry {
+    pub node: Buffer,
+    pub parent: Option<Buffer>,
     pub source: Option<NativeMaterializationSource>,
+    pub deleted_at: Buffer,
+    pub hard_deleted: bool,
+    pub payload_preview: Option<Buffer>,
 }
 
 #[napi(object)]
@@ -113,9 +157,98 @@ pub struct NativeLocalOpResult {
     pub outcome: NativeMaterializationOutcome,
 }
 
+/// One edit of a `localBatch` call; `kind` is insert/move/delete/payload/payload_field.
+#[napi(object)]
+pub struct NativeLocalEdit {
+    pub kind: String,
+    pub node: Buffer,
+    pub parent: Option<Buffer>,
+    pub new_parent: Option<Buffer>,
+    pub placement: Option<String>,
+    pub after: Option<Buffer>,
+    pub payload: Option<Buffer>,
+    pub field: Option<String>,
+}
+
+#[napi(object)]
+pub struct NativeLocalBatchResult {
+    pub ops: Vec<NativeOp>,
+    pub outcome: NativeMaterializationOutcome,
+}
+
+#[napi(object)]
+pub struct NativeDuplicatedNode {
+    pub source: Buffer,
+    pub copy: Buffer,
+}
+
+#[napi(object)]
+pub struct NativeLocalDuplicateResult {
+    pub ops: Vec<NativeOp>,
+    pub outcome: NativeMaterializationOutcome,
+    pub mapping: Vec<NativeDuplicatedNode>,
+}
+
+fn native_to_local_edit(edit: NativeLocalEdit) -> CoreResult<LocalEdit> {
+    let node = bytes16_to_node(&edit.node)?;
+    let required = |value: Option<Buffer>, field: &str| match value {
+        Some(bytes) => bytes16_to_node(&bytes),
+        None => Err(CoreError::InvalidOperation(format!(
+            "{} edit missing {field}",
+            edit.kind
+        ))),
+    };
+    let after = match &edit.after {
+        None => None,
+        Some(b) => Some(bytes16_to_node(b)?),
+    };
+    let placement = || LocalPlacement::from_parts(edit.placement.as_deref().unwrap_or(""), after);
+    match edit.kind.as_str() {
+        "insert" => Ok(LocalEdit::Insert {
+            parent: required(edit.parent.clone(), "parent")?,
+            node,
+            placement: placement()?,
+            payload: edit.payload.as_ref().map(|p| p.to_vec()),
+        }),
+        "move" => Ok(LocalEdit::Move {
+            node,
+            new_parent: required(edit.new_parent.clone(), "newParent")?,
+            placement: placement()?,
+        }),
+        "delete" => Ok(LocalEdit::Delete { node }),
+        "tombstone" => Ok(LocalEdit::Tombstone { node }),
+        "restore" => Ok(LocalEdit::Restore { node }),
+        "payload" => Ok(LocalEdit::Payload {
+            node,
+            payload: edit.payload.as_ref().map(|p| p.to_vec()),
+        }),
+        "payload_field" => Ok(LocalEdit::PayloadField {
+            node,
+            field: edit.field.clone().ok_or_else(|| {
+                CoreError::InvalidOperation("payload_field edit missing field".into())
+            })?,
+            value: edit.payload.as_ref().map(|p| p.to_vec()),
+        }),
+        _ => Err(CoreError::InvalidOperation("invalid edit kind".into())),
+    }
+}
+
+/// Per-replica undo history of one backend handle.
+type UndoHistory = Rc<RefCell<HashMap<Vec<u8>, UndoManager>>>;
+
+fn local_op_result_to_native(
+    result: treecrdt_postgres::LocalOpResult,
+) -> napi::Result<NativeLocalOpResult> {
+    Ok(NativeLocalOpResult {
+        op: core_to_native_op(result.op).map_err(map_core_err)?,
+        outcome: outcome_to_native(result.outcome),
+    })
+}
+
 #[napi]
 pub struct NativePreparedLocalOpTx {
     tx: Option<treecrdt_postgres::PreparedLocalOpTx>,
+    undo: UndoHistory,
 }
 
 #[napi]
@@ -136,10 +269,12 @@ impl NativePreparedLocalOpTx {
             .take()
             .ok_or_else(|| map_err("prepared local op transaction is already closed"))?;
         let result = tx.commit().map_err(map_core_err)?;
-        Ok(NativeLocalOpResult {
-            op: core_to_native_op(result.op).map_err(map_core_err)?,
-            outcome: outcome_to_native(result.outcome),
-        })
+        self.undo
+            .borrow_mut()
+            .entry(result.op.meta.id.replica.as_bytes().to_vec())
+            .or_default()
+            .record(result.undo.clone());
+        local_op_result_to_native(result)
     }
 
     #[napi]
@@ -151,21 +286,19 @@ impl NativePreparedLocalOpTx {
     }
 }
 
-fn outcome_to_native(outcome: MaterializationOutcome) -> NativeMaterializationOutcome {
-    fn source_to_native(
-        source: Option<MaterializationSource>,
-    ) -> Option<NativeMaterializationSource> {
-        source.map(|source| NativeMaterializationSource {
-            operation: NativeMaterializationSourceOperation {
-                id: NativeOperationId {
-                    replica: Buffer::from(source.operation.id.replica.as_bytes().to_vec()),
-                    counter: BigInt::from(source.operation.id.counter),
-                },
-                lamport: BigInt::from(source.operation.lamport),
+fn source_to_native(source: Option<MaterializationSource>) -> Option<NativeMaterializationSource> {
+    source.map(|source| NativeMaterializationSource {
+        operation: NativeMaterializationSourceOperation {
+            id: NativeOperationId {
+                replica: Buffer::from(source.operation.id.replica.as_bytes().to_vec()),
+                counter: BigInt::from(source.operation.id.counter),
             },
-        })
-    }
+            lamport: BigInt::from(source.operation.lamport),
+        },
+    })
+}
 
+fn outcome_to_native(outcome: MaterializationOutcome) -> NativeMaterializationOutcome {
     let changes = outcome
         .changes
         .into_iter()
@@ -181,6 +314,7 @@ fn outcome_to_native(outcome: MaterializationOutcome) -> NativeMaterializationOu
                 parent_before: None,
                 parent_after: Some(node_buffer(parent_after)),
                 payload: payload.map(Buffer::from),
+                field: None,
                 source: source_to_native(source),
             },
             MaterializationChange::Move {
@@ -194,6 +328,7 @@ fn outcome_to_native(outcome: MaterializationOutcome) -> NativeMaterializationOu
                 parent_before: parent_before.map(node_buffer),
                 parent_after: Some(node_buffer(parent_after)),
                 payload: None,
+                field: None,
                 source: source_to_native(source),
             },
             MaterializationChange::Delete {
@@ -206,6 +341,7 @@ fn outcome_to_native(outcome: MaterializationOutcome) -> NativeMaterializationOu
                 parent_before: parent_before.map(node_buffer),
                 parent_after: None,
                 payload: None,
+                field: None,
                 source: source_to_native(source),
             },
             MaterializationChange::Restore {
@@ -219,6 +355,7 @@ fn outcome_to_native(outcome: MaterializationOutcome) -> NativeMaterializationOu
                 parent_before: None,
                 parent_after: parent_after.map(node_buffer),
                 payload: payload.map(Buffer::from),
+                field: None,
                 source: source_to_native(source),
             },
             MaterializationChange::Payload {
@@ -231,6 +368,21 @@ fn outcome_to_native(outcome: MaterializationOutcome) -> NativeMaterializationOu
                 parent_before: None,
                 parent_after: None,
                 payload: payload.map(Buffer::from),
+                field: None,
+                source: source_to_native(source),
+            },
+            MaterializationChange::PayloadField {
+                node,
+                field,
+                value,
+                source,
+            } => NativeMaterializationChange {
+                kind: "payloadField".to_string(),
+                node: node_buffer(node),
+                parent_before: None,
+                parent_after: None,
+                payload: value.map(Buffer::from),
+                field: Some(field),
                 source: source_to_native(source),
             },
         })
@@ -266,10 +418,24 @@ fn native_to_core_op(op: NativeOp) -> CoreResult<Operation> {
         Some(b) => Some(vv_from_bytes(&b)?),
     };
 
+    let group = match (op.group_first, op.group_len) {
+        (Some(first), Some(len)) => Some(OperationGroup {
+            first: bigint_to_u64("groupFirst", first)?,
+            len,
+        }),
+        (None, None) => None,
+        _ => {
+            return Err(CoreError::InvalidOperation(
+                "groupFirst and groupLen must be set together".into(),
+            ))
+        }
+    };
+
     let meta = treecrdt_core::OperationMetadata {
         id,
         lamport: lamport_u64 as Lamport,
         known_state,
+        group,
     };
 
     let node = bytes16_to_node(&op.node)?;
@@ -305,10 +471,18 @@ fn native_to_core_op(op: NativeOp) -> CoreResult<Operation> {
         }
         "delete" => OperationKind::Delete { node },
         "tombstone" => OperationKind::Tombstone { node },
+        "restore" => OperationKind::Restore { node },
         "payload" => OperationKind::Payload {
             node,
             payload: op.payload.map(|p| p.to_vec()),
         },
+        "payload_field" => OperationKind::PayloadField {
+            node,
+            field: op
+                .field
+                .ok_or_else(|| CoreError::Storage("payload_field op missing field".into()))?,
+            value: op.payload.map(|p| p.to_vec()),
+        },
         other => return Err(CoreError::Storage(format!("unknown op kind: {other}"))),
     };
 
@@ -323,6 +497,8 @@ fn core_to_native_op(op: Operation) -> CoreResult<NativeOp> {
         None => None,
         Some(vv) => Some(Buffer::from(vv_to_bytes(vv)?)),
     };
+    let group_first = op.meta.group.map(|group| BigInt::from(group.first));
+    let group_len = op.meta.group.map(|group| group.len);
 
     match op.kind {
         OperationKind::Insert {
@@ -341,6 +517,9 @@ fn core_to_native_op(op: Operation) -> CoreResult<NativeOp> {
             order_key: Some(Buffer::from(order_key)),
             payload: payload.map(Buffer::from),
             known_state,
+            group_first,
+            group_len,
+            field: None,
         }),
         OperationKind::Move {
             node,
@@ -357,6 +536,9 @@ fn core_to_native_op(op: Operation) -> CoreResult<NativeOp> {
             order_key: Some(Buffer::from(order_key)),
             payload: None,
             known_state,
+            group_first,
+            group_len,
+            field: None,
         }),
         OperationKind::Delete { node } => Ok(NativeOp {
             lamport,
@@ -369,6 +551,9 @@ fn core_to_native_op(op: Operation) -> CoreResult<NativeOp> {
             order_key: None,
             payload: None,
             known_state,
+            group_first,
+            group_len,
+            field: None,
         }),
         OperationKind::Tombstone { node } => Ok(NativeOp {
             lamport,
@@ -381,6 +566,24 @@ fn core_to_native_op(op: Operation) -> CoreResult<NativeOp> {
             order_key: None,
             payload: None,
             known_state,
+            group_first,
+            group_len,
+            field: None,
+        }),
+        OperationKind::Restore { node } => Ok(NativeOp {
+            lamport,
+            replica: Buffer::from(op.meta.id.replica.as_bytes().to_vec()),
+            counter,
+            kind: "restore".to_string(),
+            parent: None,
+            node: Buffer::from(node_to_bytes16(node).to_vec()),
+            new_parent: None,
+            order_key: None,
+            payload: None,
+            known_state: None,
+            group_first,
+            group_len,
+            field: None,
         }),
         OperationKind::Payload { node, payload } => Ok(NativeOp {
             lamport,
@@ -393,6 +596,24 @@ fn core_to_native_op(op: Operation) -> CoreResult<NativeOp> {
             order_key: None,
             payload: payload.map(Buffer::from),
             known_state,
+            group_first,
+            group_len,
+            field: None,
+        }),
+        OperationKind::PayloadField { node, field, value } => Ok(NativeOp {
+            lamport,
+            replica: Buffer::from(op.meta.id.replica.as_bytes().to_vec()),
+            counter,
+            kind: "payload_field".to_string(),
+            parent: None,
+            node: Buffer::from(node_to_bytes16(node).to_vec()),
+            new_parent: None,
+            order_key: None,
+            payload: value.map(Buffer::from),
+            known_state,
+            group_first,
+            group_len,
+            field: Some(field),
         }),
     }
 }
@@ -422,7 +643,7 @@ impl PgFactory {
         // Test-only convenience: wipe all docs.
         client
             .batch_execute(
-                "TRUNCATE treecrdt_oprefs_children, treecrdt_payload, treecrdt_nodes, treecrdt_ops, treecrdt_meta",
+                "TRUNCATE treecrdt_oprefs_children, treecrdt_payload, treecrdt_payload_fields, treecrdt_nodes, treecrdt_ops, treecrdt_meta",
             )
             .map_err(map_err)?;
         Ok(())
@@ -440,6 +661,8 @@ impl PgFactory {
         PgBackend {
             url: self.url.clone(),
             doc_id,
+            undo: UndoHistory::default(),
+            groups: RefCell::new(None),
         }
     }
 }
@@ -448,6 +671,16 @@ impl PgFactory {
 pub struct PgBackend {
     url: String,
     doc_id: String,
+    undo: UndoHistory,
+    /// Set while atomic op groups are enabled, see `set_atomic_groups`.
+    groups: RefCell<Option<GroupBuffer>>,
+}
+
+fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
 }
 
 #[napi]
@@ -517,6 +750,23 @@ impl PgBackend {
         Ok(out)
     }
 
+    /// `ops_since` in the binary op encoding (see `treecrdt_core::encode_ops`).
+    #[napi]
+    pub fn ops_since_blob(&self, lamport: BigInt, root: Option<Buffer>) -> napi::Result<Buffer> {
+        let client = connect(&self.url)?;
+        let client = std::rc::Rc::new(std::cell::RefCell::new(client));
+        let lamport_u64 = bigint_to_u64("lamport", lamport).map_err(map_core_err)?;
+        let root_id = match root {
+            None => None,
+            Some(b) => Some(bytes16_to_node(&b).map_err(map_core_err)?),
+        };
+
+        let ops =
+            treecrdt_postgres::ops_since(&client, &self.doc_id, lamport_u64 as Lamport, root_id)
+                .map_err(map_core_err)?;
+        Ok(Buffer::from(treecrdt_core::encode_ops(&ops)))
+    }
+
     #[napi]
     pub fn tree_children(&self, parent: Buffer) -> napi::Result<Vec<Buffer>> {
         let client = connect(&self.url)?;
@@ -557,6 +807,53 @@ impl PgBackend {
             .collect())
     }
 
+    /// Deleted subtrees, newest delete first. The cursor is the `source` operation (lamport,
+    /// replica, counter; all zero/empty when absent) and node of the previous page's last entry.
+    #[napi]
+    pub fn tree_trash_page(
+        &self,
+        cursor_lamport: Option<BigInt>,
+        cursor_replica: Option<Buffer>,
+        cursor_counter: Option<BigInt>,
+        cursor_node: Option<Buffer>,
+        limit: u32,
+    ) -> napi::Result<Vec<NativeTrashEntry>> {
+        let client = connect(&self.url)?;
+        let client = std::rc::Rc::new(std::cell::RefCell::new(client));
+
+        let cursor = match (cursor_lamport, cursor_replica, cursor_counter, cursor_node) {
+            (None, None, None, None) => None,
+            (Some(lamport), Some(replica), Some(counter), Some(node)) => Some(TrashCursor {
+                lamport: bigint_to_u64("cursorLamport", lamport).map_err(map_core_err)?,
+                replica: replica.to_vec(),
+                counter: bigint_to_u64("cursorCounter", counter).map_err(map_core_err)?,
+                node: bytes16_to_node(&node).map_err(map_core_err)?,
+            }),
+            _ => {
+                return Err(map_err(
+                    "invalid cursor (expected lamport, replica, counter and node)",
+                ))
+            }
+        };
+
+        let entries =
+            treecrdt_postgres::tree_trash_page(&client, &self.doc_id, cursor.as_ref(), limit)
+                .map_err(map_core_err)?;
+        entries
+            .into_iter()
+            .map(|entry| {
+                Ok(NativeTrashEntry {
+                    node: node_buffer(entry.node),
+                    parent: entry.parent.map(node_buffer),
+                    source: source_to_native(entry.source),
+                    deleted_at: Buffer::from(vv_to_bytes(&entry.deleted_at).map_err(map_core_err)?),
+                    hard_deleted: entry.hard_deleted,
+                    payload_preview: entry.payload_preview.map(Buffer::from),
+                })
+            })
+            .collect()
+    }
+
     #[napi]
     pub fn tree_dump(&self) -> napi::Result<Vec<NativeTreeRow>> {
         let client = connect(&self.url)?;
@@ -600,6 +897,74 @@ impl PgBackend {
         treecrdt_postgres::tree_exists(&client, &self.doc_id, node).map_err(map_core_err)
     }
 
+    #[napi]
+    pub fn tree_ancestors(&self, node: Buffer) -> napi::Result<Vec<Buffer>> {
+        let client = connect(&self.url)?;
+        let client = std::rc::Rc::new(std::cell::RefCell::new(client));
+        let node = bytes16_to_node(&node).map_err(map_core_err)?;
+        let ancestors =
+            treecrdt_postgres::tree_ancestors(&client, &self.doc_id, node).map_err(map_core_err)?;
+        Ok(ancestors
+            .into_iter()
+            .map(|n| Buffer::from(node_to_bytes16(n).to_vec()))
+            .collect())
+    }
+
+    #[napi]
+    pub fn tree_depth(&self, node: Buffer) -> napi::Result<Option<u32>> {
+        let client = connect(&self.url)?;
+        let client = std::rc::Rc::new(std::cell::RefCell::new(client));
+        let node = bytes16_to_node(&node).map_err(map_core_err)?;
+        let depth =
+            treecrdt_postgres::tree_depth(&client, &self.doc_id, node).map_err(map_core_err)?;
+        Ok(depth.map(|d| d as u32))
+    }
+
+    #[napi]
+    pub fn tree_is_descendant(&self, node: Buffer, ancestor: Buffer) -> napi::Result<bool> {
+        let client = connect(&self.url)?;
+        let client = std::rc::Rc::new(std::cell::RefCell::new(client));
+        let node = bytes16_to_node(&node).map_err(map_core_err)?;
+        let ancestor = bytes16_to_node(&ancestor).map_err(map_core_err)?;
+        treecrdt_postgres::tree_is_descendant(&client, &self.doc_id, node, ancestor)
+            .map_err(map_core_err)
+    }
+
+    #[napi]
+    pub fn tree_descendants(
+        &self,
+        root: Buffer,
+        max_depth: Option<u32>,
+    ) -> napi::Result<Vec<NativeTreeDescendant>> {
+        let client = connect(&self.url)?;
+        let client = std::rc::Rc::new(std::cell::RefCell::new(client));
+        let root = bytes16_to_node(&root).map_err(map_core_err)?;
+        let nodes = treecrdt_postgres::tree_descendants(
+            &client,
+            &self.doc_id,
+            root,
+            max_depth.map(|d| d as usize),
+        )
+        .map_err(map_core_err)?;
+        Ok(nodes
+            .into_iter()
+            .map(|(node, depth)| NativeTreeDescendant {
+                node: Buffer::from(node_to_bytes16(node).to_vec()),
+                depth: depth as u32,
+            })
+            .collect())
+    }
+
+    #[napi]
+    pub fn tree_index_in_parent(&self, node: Buffer) -> napi::Result<Option<u32>> {
+        let client = connect(&self.url)?;
+        let client = std::rc::Rc::new(std::cell::RefCell::new(client));
+        let node = bytes16_to_node(&node).map_err(map_core_err)?;
+        let index = treecrdt_postgres::tree_index_in_parent(&client, &self.doc_id, node)
+            .map_err(map_core_err)?;
+        Ok(index.map(|i| i as u32))
+    }
+
     #[napi]
     pub fn tree_payload(&self, node: Buffer) -> napi::Result<Option<Buffer>> {
         let client = connect(&self.url)?;
@@ -610,6 +975,80 @@ impl PgBackend {
         Ok(payload.map(Buffer::from))
     }
 
+    #[napi]
+    pub fn tree_payload_fields(&self, node: Buffer) -> napi::Result<Vec<NativePayloadField>> {
+        let client = connect(&self.url)?;
+        let client = std::rc::Rc::new(std::cell::RefCell::new(client));
+        let node = bytes16_to_node(&node).map_err(map_core_err)?;
+        let fields = treecrdt_postgres::tree_payload_fields(&client, &self.doc_id, node)
+            .map_err(map_core_err)?;
+        Ok(fields
+            .into_iter()
+            .map(|(field, value)| NativePayloadField {
+                field,
+                value: Buffer::from(value),
+            })
+            .collect())
+    }
+
+    #[napi]
+    pub fn tree_payload_values(&self, node: Buffer) -> napi::Result<Vec<NativePayloadValue>> {
+        let client = connect(&self.url)?;
+        let client = std::rc::Rc::new(std::cell::RefCell::new(client));
+        let node = bytes16_to_node(&node).map_err(map_core_err)?;
+        let values = treecrdt_postgres::tree_payload_values(&client, &self.doc_id, node)
+            .map_err(map_core_err)?;
+        values
+            .into_iter()
+            .map(|value| {
+                Ok(NativePayloadValue {
+                    payload: value.payload.map(Buffer::from),
+                    lamport: BigInt::from(value.lamport),
+                    writer: NativeOperationId {
+                        replica: Buffer::from(value.writer.replica.as_bytes().to_vec()),
+                        counter: BigInt::from(value.writer.counter),
+                    },
+                    known_state: Buffer::from(
+                        vv_to_bytes(&value.known_state).map_err(map_core_err)?,
+                    ),
+                })
+            })
+            .collect()
+    }
+
+    /// `mode` is `last_writer_wins` (the default) or `multi_value`, which keeps concurrent payload
+    /// writes for `tree_payload_values` until a local payload write resolves them.
+    #[napi]
+    pub fn set_payload_mode(&self, mode: String) -> napi::Result<()> {
+        let mode = match mode.as_str() {
+            "last_writer_wins" => PayloadMode::LastWriterWins,
+            "multi_value" => PayloadMode::MultiValue,
+            _ => return Err(map_err("mode must be last_writer_wins or multi_value")),
+        };
+        let client = connect(&self.url)?;
+        let client = std::rc::Rc::new(std::cell::RefCell::new(client));
+        treecrdt_postgres::set_payload_mode(&client, &self.doc_id, mode).map_err(map_core_err)
+    }
+
+    /// `policy` is `defensive` (the default), `keep_parent` or `lost_and_found`; see
+    /// `OrphanPolicy`. Changing it rematerializes the doc.
+    #[napi]
+    pub fn set_orphan_policy(&self, policy: String) -> napi::Result<()> {
+        let policy = match policy.as_str() {
+            "defensive" => OrphanPolicy::Defensive,
+            "keep_parent" => OrphanPolicy::KeepParent,
+            "lost_and_found" => OrphanPolicy::LostAndFound,
+            _ => {
+                return Err(map_err(
+                    "policy must be defensive, keep_parent or lost_and_found",
+                ))
+            }
+        };
+        let client = connect(&self.url)?;
+        let client = std::rc::Rc::new(std::cell::RefCell::new(client));
+        treecrdt_postgres::set_orphan_policy(&client, &self.doc_id, policy).map_err(map_core_err)
+    }
+
     #[napi]
     pub fn replica_max_counter(&self, replica: Buffer) -> napi::Result<BigInt> {
         let client = connect(&self.url)?;
@@ -655,10 +1094,88 @@ impl PgBackend {
             core_ops.push(native_to_core_op(op).map_err(map_core_err)?);
         }
 
+        self.append(&client, core_ops)
+    }
+
+    /// `apply_ops` for a batch in the binary op encoding (see `treecrdt_core::encode_ops`).
+    #[napi]
+    pub fn apply_ops_blob(&self, ops: Buffer) -> napi::Result<NativeMaterializationOutcome> {
+        let client = connect(&self.url)?;
+        let client = std::rc::Rc::new(std::cell::RefCell::new(client));
+
+        let core_ops = treecrdt_core::decode_ops(&ops).map_err(map_core_err)?;
+        self.append(&client, core_ops)
+    }
+
+    fn append(
+        &self,
+        client: &Rc<RefCell<PgClient>>,
+        ops: Vec<Operation>,
+    ) -> napi::Result<NativeMaterializationOutcome> {
+        let mut groups = self.groups.borrow_mut();
+        let outcome = match groups.as_mut() {
+            Some(buffer) => treecrdt_postgres::append_ops_grouped(
+                client,
+                &self.doc_id,
+                ops,
+                buffer,
+                now_millis(),
+            ),
+            None => treecrdt_postgres::append_ops_with_materialization_outcome(
+                client,
+                &self.doc_id,
+                &ops,
+            ),
+        }
+        .map_err(map_core_err)?;
+        Ok(outcome_to_native(outcome))
+    }
+
+    /// Hold back grouped ops passed to `apply_ops*` until their whole group has arrived. With
+    /// `release_after_ms`, incomplete groups are applied once they waited that long; without it
+    /// they wait for their missing members. Disabling applies whatever is still held back.
+    #[napi]
+    pub fn set_atomic_groups(
+        &self,
+        enabled: bool,
+        release_after_ms: Option<i64>,
+    ) -> napi::Result<NativeMaterializationOutcome> {
+        let policy = match release_after_ms {
+            Some(ms) if ms < 0 => return Err(map_err("releaseAfterMs must be non-negative")),
+            Some(ms) => PartialGroupPolicy::ReleaseAfter(ms as u64),
+            None => PartialGroupPolicy::Wait,
+        };
+        let previous = if enabled {
+            self.groups.replace(Some(GroupBuffer::new(policy)))
+        } else {
+            self.groups.take()
+        };
+        let held = previous.map(|mut buffer| buffer.drain()).unwrap_or_default();
+
+        let client = connect(&self.url)?;
+        let client = std::rc::Rc::new(std::cell::RefCell::new(client));
         let outcome = treecrdt_postgres::append_ops_with_materialization_outcome(
             &client,
             &self.doc_id,
-            &core_ops,
+            &held,
+        )
+        .map_err(map_core_err)?;
+        Ok(outcome_to_native(outcome))
+    }
+
+    /// Apply the held-back members of groups that waited longer than `set_atomic_groups` allows.
+    #[napi]
+    pub fn release_groups(&self) -> napi::Result<NativeMaterializationOutcome> {
+        let released = match self.groups.borrow_mut().as_mut() {
+            Some(buffer) => buffer.release_expired(now_millis()),
+            None => Vec::new(),
+        };
+        let client = connect(&self.url)?;
+        let client = std::rc::Rc::new(std::cell::RefCell::new(client));
+        let outcome = treecrdt_postgres::append_ops_with_materialization_outcome(
+            &client,
+            &self.doc_id,
+            &released,
         )
         .map_err(map_core_err)?;
         Ok(outcome_to_native(outcome))
@@ -720,7 +1237,10 @@ impl PgBackend {
             payload.map(|p| p.to_vec()),
         )
         .map_err(map_core_err)?;
-        Ok(NativePreparedLocalOpTx { tx: Some(tx) })
+        Ok(NativePreparedLocalOpTx {
+            tx: Some(tx),
+            undo: self.undo.clone(),
+        })
     }
 
     #[napi]
@@ -766,7 +1286,10 @@ impl PgBackend {
             after_id,
         )
         .map_err(map_core_err)?;
-        Ok(NativePreparedLocalOpTx { tx: Some(tx) })
+        Ok(NativePreparedLocalOpTx {
+            tx: Some(tx),
+            undo: self.undo.clone(),
+        })
     }
 
     #[napi]
@@ -788,7 +1311,69 @@ impl PgBackend {
         let node = bytes16_to_node(&node).map_err(map_core_err)?;
         let tx = treecrdt_postgres::prepare_local_delete_tx(&client, &self.doc_id, &replica, node)
             .map_err(map_core_err)?;
-        Ok(NativePreparedLocalOpTx { tx: Some(tx) })
+        Ok(NativePreparedLocalOpTx {
+            tx: Some(tx),
+            undo: self.undo.clone(),
+        })
+    }
+
+    #[napi]
+    pub fn local_tombstone(
+        &self,
+        replica: Buffer,
+        node: Buffer,
+    ) -> napi::Result<NativeLocalOpResult> {
+        let mut tx = self.prepare_local_tombstone(replica, node)?;
+        tx.commit()
+    }
+
+    #[napi]
+    pub fn prepare_local_tombstone(
+        &self,
+        replica: Buffer,
+        node: Buffer,
+    ) -> napi::Result<NativePreparedLocalOpTx> {
+        let client = connect(&self.url)?;
+        let client = std::rc::Rc::new(std::cell::RefCell::new(client));
+
+        let replica = ReplicaId(replica.to_vec());
+        let node = bytes16_to_node(&node).map_err(map_core_err)?;
+        let tx =
+            treecrdt_postgres::prepare_local_tombstone_tx(&client, &self.doc_id, &replica, node)
+                .map_err(map_core_err)?;
+        Ok(NativePreparedLocalOpTx {
+            tx: Some(tx),
+            undo: self.undo.clone(),
+        })
+    }
+
+    #[napi]
+    pub fn local_restore(
+        &self,
+        replica: Buffer,
+        node: Buffer,
+    ) -> napi::Result<NativeLocalOpResult> {
+        let mut tx = self.prepare_local_restore(replica, node)?;
+        tx.commit()
+    }
+
+    #[napi]
+    pub fn prepare_local_restore(
+        &self,
+        replica: Buffer,
+        node: Buffer,
+    ) -> napi::Result<NativePreparedLocalOpTx> {
+        let client = connect(&self.url)?;
+        let client = std::rc::Rc::new(std::cell::RefCell::new(client));
+
+        let replica = ReplicaId(replica.to_vec());
+        let node = bytes16_to_node(&node).map_err(map_core_err)?;
+        let tx = treecrdt_postgres::prepare_local_restore_tx(&client, &self.doc_id, &replica, node)
+            .map_err(map_core_err)?;
+        Ok(NativePreparedLocalOpTx {
+            tx: Some(tx),
+            undo: self.undo.clone(),
+        })
     }
 
     #[napi]
@@ -822,6 +1407,201 @@ impl PgBackend {
             payload.map(|p| p.to_vec()),
         )
         .map_err(map_core_err)?;
-        Ok(NativePreparedLocalOpTx { tx: Some(tx) })
+        Ok(NativePreparedLocalOpTx {
+            tx: Some(tx),
+            undo: self.undo.clone(),
+        })
+    }
+
+    #[napi]
+    pub fn local_payload_field(
+        &self,
+        replica: Buffer,
+        node: Buffer,
+        field: String,
+        value: Option<Buffer>,
+    ) -> napi::Result<NativeLocalOpResult> {
+        let mut tx = self.prepare_local_payload_field(replica, node, field, value)?;
+        tx.commit()
+    }
+
+    #[napi]
+    pub fn prepare_local_payload_field(
+        &self,
+        replica: Buffer,
+        node: Buffer,
+        field: String,
+        value: Option<Buffer>,
+    ) -> napi::Result<NativePreparedLocalOpTx> {
+        let client = connect(&self.url)?;
+        let client = std::rc::Rc::new(std::cell::RefCell::new(client));
+
+        let replica = ReplicaId(replica.to_vec());
+        let node = bytes16_to_node(&node).map_err(map_core_err)?;
+        let tx = treecrdt_postgres::prepare_local_payload_field_tx(
+            &client,
+            &self.doc_id,
+            &replica,
+            node,
+            &field,
+            value.map(|v| v.to_vec()),
+        )
+        .map_err(map_core_err)?;
+        Ok(NativePreparedLocalOpTx {
+            tx: Some(tx),
+            undo: self.undo.clone(),
+        })
+    }
+
+    /// Commit several local edits in one transaction; later edits see the earlier ones. The
+    /// batch is recorded as one undo transaction.
+    #[napi]
+    pub fn local_batch(
+        &self,
+        replica: Buffer,
+        edits: Vec<NativeLocalEdit>,
+    ) -> napi::Result<NativeLocalBatchResult> {
+        let client = connect(&self.url)?;
+        let client = std::rc::Rc::new(std::cell::RefCell::new(client));
+
+        let replica = ReplicaId(replica.to_vec());
+        let edits = edits
+            .into_iter()
+            .map(native_to_local_edit)
+            .collect::<CoreResult<Vec<_>>>()
+            .map_err(map_core_err)?;
+        let result = treecrdt_postgres::local_batch(&client, &self.doc_id, &replica, edits)
+            .map_err(map_core_err)?;
+        {
+            let mut undo = self.undo.borrow_mut();
+            let manager = undo.entry(replica.0).or_default();
+            manager.begin();
+            for record in result.undo {
+                manager.record(record);
+            }
+            manager.commit();
+        }
+        Ok(NativeLocalBatchResult {
+            ops: result
+                .ops
+                .into_iter()
+                .map(core_to_native_op)
+                .collect::<CoreResult<Vec<_>>>()
+                .map_err(map_core_err)?,
+            outcome: outcome_to_native(result.outcome),
+        })
+    }
+
+    /// Copy the visible subtree of `source` under `new_parent` with fresh node ids, in one
+    /// transaction that is recorded as one undo transaction.
+    #[napi]
+    pub fn local_duplicate(
+        &self,
+        replica: Buffer,
+        source: Buffer,
+        new_parent: Buffer,
+        placement: String,
+        after: Option<Buffer>,
+    ) -> napi::Result<NativeLocalDuplicateResult> {
+        let client = connect(&self.url)?;
+        let client = std::rc::Rc::new(std::cell::RefCell::new(client));
+
+        let replica = ReplicaId(replica.to_vec());
+        let source = bytes16_to_node(&source).map_err(map_core_err)?;
+        let new_parent = bytes16_to_node(&new_parent).map_err(map_core_err)?;
+        let after = match after {
+            None => None,
+            Some(b) => Some(bytes16_to_node(&b).map_err(map_core_err)?),
+        };
+        let placement = LocalPlacement::from_parts(&placement, after).map_err(map_core_err)?;
+        let result = treecrdt_postgres::local_duplicate(
+            &client,
+            &self.doc_id,
+            &replica,
+            source,
+            new_parent,
+            placement,
+        )
+        .map_err(map_core_err)?;
+        {
+            let mut undo = self.undo.borrow_mut();
+            let manager = undo.entry(replica.0).or_default();
+            manager.begin();
+            for record in result.undo {
+                manager.record(record);
+            }
+            manager.commit();
+        }
+        Ok(NativeLocalDuplicateResult {
+            ops: result
+                .ops
+                .into_iter()
+                .map(core_to_native_op)
+                .collect::<CoreResult<Vec<_>>>()
+                .map_err(map_core_err)?,
+            outcome: outcome_to_native(result.outcome),
+            mapping: result
+                .mapping
+                .into_iter()
+                .map(|(source, copy)| NativeDuplicatedNode {
+                    source: node_buffer(source),
+                    copy: node_buffer(copy),
+                })
+                .collect(),
+        })
+    }
+
+    /// Revert `replica`'s most recent local transaction made through this handle.
+    #[napi]
+    pub fn undo(&self, replica: Buffer) -> napi::Result<Vec<NativeLocalOpResult>> {
+        self.undo_step(replica, treecrdt_postgres::undo)
+    }
+
+    /// Re-apply `replica`'s most recently undone transaction.
+    #[napi]
+    pub fn redo(&self, replica: Buffer) -> napi::Result<Vec<NativeLocalOpResult>> {
+        self.undo_step(replica, treecrdt_postgres::redo)
+    }
+
+    /// Group `replica`'s following local ops into one undo transaction until `undoCommit`.
+    #[napi]
+    pub fn undo_begin(&self, replica: Buffer) {
+        self.undo.borrow_mut().entry(replica.to_vec()).or_default().begin();
+    }
+
+    #[napi]
+    pub fn undo_commit(&self, replica: Buffer) {
+        self.undo.borrow_mut().entry(replica.to_vec()).or_default().commit();
+    }
+
+    #[napi]
+    pub fn can_undo(&self, replica: Buffer) -> bool {
+        self.undo.borrow().get(replica.as_ref()).is_some_and(UndoManager::can_undo)
+    }
+
+    #[napi]
+    pub fn can_redo(&self, replica: Buffer) -> bool {
+        self.undo.borrow().get(replica.as_ref()).is_some_and(UndoManager::can_redo)
+    }
+}
+
+type UndoStep = fn(
+    &Rc<RefCell<PgClient>>,
+    &str,
+    &ReplicaId,
+    &mut UndoManager,
+) -> CoreResult<Vec<treecrdt_postgres::LocalOpResult>>;
+
+impl PgBackend {
+    fn undo_step(&self, replica: Buffer, step: UndoStep) -> napi::Result<Vec<NativeLocalOpResult>> {
+        let client = connect(&self.url)?;
+        let client = Rc::new(RefCell::new(client));
+        let replica = ReplicaId(replica.to_vec());
+        // Not borrowed across the call: ops committed by the step must not record new history.
+        let mut manager =
+            std::mem::take(self.undo.borrow_mut().entry(replica.0.clone()).or_default());
+        let res = step(&client, &self.doc_id, &replica, &mut manager);
+        self.undo.borrow_mut().insert(replica.0, manager);
+        res.map_err(map_core_err)?.into_iter().map(local_op_result_to_native).collect()
     }
 }
@@ -16,6 +16,9 @@ export type NativeOp = {
   orderKey?: Uint8Array | null;
   payload?: Uint8Array | null;
   knownState?: Uint8Array | null;
+  groupFirst?: bigint | number | null;
+  groupLen?: number | null;
+  field?: string | null;
 };
 
 export type NativeMaterializationChange = {
@@ -24,6 +27,7 @@ export type NativeMaterializationChange = {
   parentBefore?: Uint8Array | null;
   parentAfter?: Uint8Array | null;
   payload?: Uint8Array | null;
+  field?: string | null;
   source?: NativeMaterializationSource | null;
 };
 
@@ -37,6 +41,25 @@ export type NativeMaterializationSource = {
   };
 };
 
+export type NativePayloadValue = {
+  payload?: Uint8Array | null;
+  lamport: bigint | number;
+  writer: {
+    replica: Uint8Array;
+    counter: bigint | number;
+  };
+  knownState: Uint8Array;
+};
+
+export type NativeTrashEntry = {
+  node: Uint8Array;
+  parent?: Uint8Array | null;
+  source?: NativeMaterializationSource | null;
+  deletedAt: Uint8Array;
+  hardDeleted: boolean;
+  payloadPreview?: Uint8Array | null;
+};
+
 export type NativeMaterializationOutcome = {
   headSeq: bigint | number;
   changes: NativeMaterializationChange[];
@@ -47,6 +70,28 @@ export type NativeLocalOpResult = {
   outcome: NativeMaterializationOutcome;
 };
 
+export type NativeLocalEdit = {
+  kind: 'insert' | 'move' | 'delete' | 'tombstone' | 'restore' | 'payload' | 'payload_field';
+  node: Uint8Array;
+  parent?: Uint8Array | null;
+  newParent?: Uint8Array | null;
+  placement?: string | null;
+  after?: Uint8Array | null;
+  payload?: Uint8Array | null;
+  field?: string | null;
+};
+
+export type NativeLocalBatchResult = {
+  ops: NativeOp[];
+  outcome: NativeMaterializationOutcome;
+};
+
+export type NativeLocalDuplicateResult = {
+  ops: NativeOp[];
+  outcome: NativeMaterializationOutcome;
+  mapping: { source: Uint8Array; copy: Uint8Array }[];
+};
+
 export type NativePreparedLocalOpTx = {
   op(): NativeOp;
   commit(): NativeLocalOpResult;
@@ -59,6 +104,7 @@ export type NativeBackend = {
   listOpRefsChildren(parent: Uint8Array): Uint8Array[];
   listOpRefsChildrenWithParentPayload(parent: Uint8Array): Uint8Array[];
   opsSince(lamport: bigint, root: Uint8Array | null): NativeOp[];
+  opsSinceBlob(lamport: bigint, root: Uint8Array | null): Uint8Array;
   getOpsByOpRefs(opRefs: Uint8Array[]): NativeOp[];
   treeChildren(parent: Uint8Array): Uint8Array[];
   treeChildrenPage(
@@ -67,6 +113,13 @@ export type NativeBackend = {
     cursorNode: Uint8Array | null,
     limit: number,
   ): { node: Uint8Array; orderKey: Uint8Array | null }[];
+  treeTrashPage(
+    cursorLamport: bigint | number | null,
+    cursorReplica: Uint8Array | null,
+    cursorCounter: bigint | number | null,
+    cursorNode: Uint8Array | null,
+    limit: number,
+  ): NativeTrashEntry[];
   treeDump(): {
     node: Uint8Array;
     parent: Uint8Array | null;
@@ -76,9 +129,24 @@ export type NativeBackend = {
   treeNodeCount(): bigint;
   treeParent(node: Uint8Array): Uint8Array | null;
   treeExists(node: Uint8Array): boolean;
+  treeAncestors(node: Uint8Array): Uint8Array[];
+  treeDepth(node: Uint8Array): number | null;
+  treeIsDescendant(node: Uint8Array, ancestor: Uint8Array): boolean;
+  treeDescendants(
+    root: Uint8Array,
+    maxDepth: number | null,
+  ): { node: Uint8Array; depth: number }[];
+  treeIndexInParent(node: Uint8Array): number | null;
   treePayload(node: Uint8Array): Uint8Array | null;
+  treePayloadFields(node: Uint8Array): { field: string; value: Uint8Array }[];
+  treePayloadValues(node: Uint8Array): NativePayloadValue[];
+  setPayloadMode(mode: 'last_writer_wins' | 'multi_value'): void;
+  setOrphanPolicy(policy: 'defensive' | 'keep_parent' | 'lost_and_found'): void;
   replicaMaxCounter(replica: Uint8Array): bigint;
   applyOps(ops: NativeOp[]): NativeMaterializationOutcome;
+  applyOpsBlob(ops: Uint8Array): NativeMaterializationOutcome;
+  setAtomicGroups(enabled: boolean, releaseAfterMs?: number | null): NativeMaterializationOutcome;
+  releaseGroups(): NativeMaterializationOutcome;
   ensureMaterialized(): NativeMaterializationOutcome;
   localInsert(
     replica: Uint8Array,
@@ -112,6 +180,10 @@ export type NativeBackend = {
   ): NativePreparedLocalOpTx;
   localDelete(replica: Uint8Array, node: Uint8Array): NativeLocalOpResult;
   prepareLocalDelete(replica: Uint8Array, node: Uint8Array): NativePreparedLocalOpTx;
+  localTombstone(replica: Uint8Array, node: Uint8Array): NativeLocalOpResult;
+  prepareLocalTombstone(replica: Uint8Array, node: Uint8Array): NativePreparedLocalOpTx;
+  localRestore(replica: Uint8Array, node: Uint8Array): NativeLocalOpResult;
+  prepareLocalRestore(replica: Uint8Array, node: Uint8Array): NativePreparedLocalOpTx;
   localPayload(
     replica: Uint8Array,
     node: Uint8Array,
@@ -122,6 +194,32 @@ export type NativeBackend = {
     node: Uint8Array,
     payload: Uint8Array | null,
   ): NativePreparedLocalOpTx;
+  localPayloadField(
+    replica: Uint8Array,
+    node: Uint8Array,
+    field: string,
+    value: Uint8Array | null,
+  ): NativeLocalOpResult;
+  prepareLocalPayloadField(
+    replica: Uint8Array,
+    node: Uint8Array,
+    field: string,
+    value: Uint8Array | null,
+  ): NativePreparedLocalOpTx;
+  localBatch(replica: Uint8Array, edits: NativeLocalEdit[]): NativeLocalBatchResult;
+  localDuplicate(
+    replica: Uint8Array,
+    source: Uint8Array,
+    newParent: Uint8Array,
+    placement: string,
+    after: Uint8Array | null,
+  ): NativeLocalDuplicateResult;
+  undo(replica: Uint8Array): NativeLocalOpResult[];
+  redo(replica: Uint8Array): NativeLocalOpResult[];
+  undoBegin(replica: Uint8Array): void;
+  undoCommit(replica: Uint8Array): void;
+  canUndo(replica: Uint8Array): boolean;
+  canRedo(replica: Uint8Array): boolean;
 };
 
 export type NativeFactory = {
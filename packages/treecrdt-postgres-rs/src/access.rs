@@ -0,0 +1,86 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use treecrdt_core::{authorize_ops, AccessControl, Error, NodeId, Operation, Result};
+
+use crate::client::PgClient;
+use crate::store::{ensure_materialized, ensure_materialized_in_tx, PgCtx, PgNodeStore};
+
+/// Install (or with `None`, remove) the access-control policy for one client.
+///
+/// Once set, `append_ops*` reject ops the policy denies before inserting anything, `local_*`
+/// run their ops through the policy before minting them, and `tree_children*`, `tree_payload`
+/// and `list_op_refs_children*` check read access to the requested node. `ops_since`,
+/// `get_ops_by_op_refs`, `tree_dump*` and `tree_diff` leave out what the caller may not read.
+/// The policy is kept on the [`PgClient`] and dropped with it.
+pub fn set_access_control(client: &Rc<RefCell<PgClient>>, policy: Option<Rc<dyn AccessControl>>) {
+    client.borrow_mut().access = policy;
+}
+
+pub(crate) fn access_control(client: &Rc<RefCell<PgClient>>) -> Option<Rc<dyn AccessControl>> {
+    client.borrow().access.clone()
+}
+
+/// Authorize a batch of incoming ops against the client's policy, if any.
+///
+/// Scope checks walk materialized ancestry, so this catches the doc up first. Must run inside
+/// the caller's transaction.
+pub(crate) fn check_ops(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    ops: &[Operation],
+) -> Result<()> {
+    let Some(policy) = access_control(client) else {
+        return Ok(());
+    };
+    ensure_materialized_in_tx(client, doc_id)?;
+    let nodes = PgNodeStore::new(PgCtx::new(client.clone(), doc_id)?)?;
+    authorize_ops(&*policy, &nodes, ops)
+}
+
+/// Check read access to `node`; callers must have materialized the doc already.
+pub(crate) fn check_read(client: &Rc<RefCell<PgClient>>, doc_id: &str, node: NodeId) -> Result<()> {
+    let Some(policy) = access_control(client) else {
+        return Ok(());
+    };
+    let nodes = PgNodeStore::new(PgCtx::new(client.clone(), doc_id)?)?;
+    policy.can_read(&nodes, node)
+}
+
+/// Leave out the `items` about nodes the client's policy does not let the caller read, as
+/// `tree_trash_page` does. `node` names the node an item is about.
+pub(crate) fn retain_readable<T>(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    items: &mut Vec<T>,
+    node: impl Fn(&T) -> NodeId,
+) -> Result<()> {
+    let Some(policy) = access_control(client) else {
+        return Ok(());
+    };
+    ensure_materialized(client, doc_id)?;
+    let nodes = PgNodeStore::new(PgCtx::new(client.clone(), doc_id)?)?;
+    let mut readable: HashMap<NodeId, bool> = HashMap::new();
+    let mut kept = Vec::with_capacity(items.len());
+    for item in items.drain(..) {
+        let node = node(&item);
+        let allowed = match readable.get(&node) {
+            Some(&allowed) => allowed,
+            None => {
+                let allowed = match policy.can_read(&nodes, node) {
+                    Ok(()) => true,
+                    Err(Error::AccessDenied(_)) => false,
+                    Err(err) => return Err(err),
+                };
+                readable.insert(node, allowed);
+                allowed
+            }
+        };
+        if allowed {
+            kept.push(item);
+        }
+    }
+    *items = kept;
+    Ok(())
+}
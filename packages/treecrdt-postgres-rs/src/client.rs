@@ -0,0 +1,38 @@
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+
+use postgres::Client;
+
+use treecrdt_core::AccessControl;
+
+/// A Postgres connection together with the in-memory state this crate keeps for it.
+///
+/// Derefs to [`Client`], so it works wherever a plain client does. State such as the access
+/// policy lives here rather than in a side table, so it goes away with the connection.
+pub struct PgClient {
+    client: Client,
+    pub(crate) access: Option<Rc<dyn AccessControl>>,
+}
+
+impl From<Client> for PgClient {
+    fn from(client: Client) -> Self {
+        Self {
+            client,
+            access: None,
+        }
+    }
+}
+
+impl Deref for PgClient {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        &self.client
+    }
+}
+
+impl DerefMut for PgClient {
+    fn deref_mut(&mut self) -> &mut Client {
+        &mut self.client
+    }
+}
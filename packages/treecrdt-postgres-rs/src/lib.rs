@@ -4,22 +4,42 @@
 //! Goal: keep all CRDT semantics in `treecrdt-core` (defensive delete, payload LWW, oprefs_children),
 //! while storing state in vanilla PostgreSQL so it works on Aurora Postgres / Supabase / self-hosted.
 
+mod access;
+mod client;
 mod local_ops;
 mod opref;
+mod orphan_policy;
+mod payload_mode;
 mod profile;
+mod purge;
 mod reads;
 mod schema;
+mod stability;
 mod store;
 
+pub use access::set_access_control;
+pub use client::PgClient;
 pub use local_ops::{
-    local_delete, local_insert, local_move, local_payload, prepare_local_delete_tx,
-    prepare_local_insert_tx, prepare_local_move_tx, prepare_local_payload_tx, PreparedLocalOpTx,
+    local_batch, local_delete, local_duplicate, local_insert, local_insert_many, local_move,
+    local_payload, local_payload_field, local_rebalance, local_reorder, local_restore,
+    local_tombstone, prepare_local_delete_tx, prepare_local_insert_tx, prepare_local_move_tx,
+    prepare_local_payload_field_tx, prepare_local_payload_tx, prepare_local_restore_tx,
+    prepare_local_tombstone_tx, redo, undo, LocalBatchResult, LocalDuplicateResult, LocalOpResult,
+    PreparedLocalOpTx,
 };
+pub use orphan_policy::set_orphan_policy;
+pub use payload_mode::set_payload_mode;
+pub use purge::{purge_stable, PurgeResult};
 pub use reads::{
     get_ops_by_op_refs, list_op_refs_all, list_op_refs_children,
     list_op_refs_children_with_parent_payload, max_lamport, ops_since, replica_max_counter,
-    tree_children, tree_children_page, tree_dump, tree_exists, tree_node_count, tree_parent,
-    tree_payload, TreeChildRow, TreeRow,
+    tree_ancestors, tree_children, tree_children_page, tree_depth, tree_descendants, tree_diff,
+    tree_dump, tree_dump_at, tree_exists, tree_index_in_parent, tree_is_descendant,
+    tree_node_count, tree_order_key_stats, tree_parent, tree_payload, tree_payload_fields,
+    tree_payload_values, tree_trash_page, TreeChildRow, TreeRow,
 };
 pub use schema::{ensure_schema, reset_doc_for_tests};
-pub use store::{append_ops, append_ops_with_materialization_outcome, ensure_materialized};
+pub use stability::{ack_version_vector, forget_peer, stable_frontier};
+pub use store::{
+    append_ops, append_ops_grouped, append_ops_with_materialization_outcome, ensure_materialized,
+};
@@ -1,13 +1,14 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use postgres::Client;
-
 use treecrdt_core::{
-    Error, LamportClock, LocalFinalizePlan, LocalPlacement, MaterializationCursor,
-    MaterializationOutcome, NodeId, Operation, PreparedLocalOp, ReplicaId, Result, TreeCrdt,
+    Error, LamportClock, LocalEdit, LocalFinalizePlan, LocalPlacement, MaterializationCursor,
+    MaterializationOutcome, NodeId, Operation, PreparedLocalOp, ReplicaId, Result, StagedLocalOp,
+    TreeCrdt, UndoManager, UndoRecord,
 };
 
+use crate::access::access_control;
+use crate::client::PgClient;
 use crate::store::{
     ensure_materialized_in_tx, load_tree_meta_for_update, set_tree_meta_replay_frontier,
     update_tree_meta_head, PgCtx, PgNodeStore, PgOpStorage, PgParentOpIndex, PgPayloadStore,
@@ -27,6 +28,8 @@ struct LocalOpSession {
 pub struct LocalOpResult {
     pub op: Operation,
     pub outcome: MaterializationOutcome,
+    /// What reverting `op` needs; feed it to an [`UndoManager`].
+    pub undo: UndoRecord,
 }
 
 impl std::ops::Deref for LocalOpResult {
@@ -37,23 +40,23 @@ impl std::ops::Deref for LocalOpResult {
     }
 }
 
-fn begin_tx(client: &Rc<RefCell<Client>>) -> Result<()> {
+fn begin_tx(client: &Rc<RefCell<PgClient>>) -> Result<()> {
     let mut c = client.borrow_mut();
     c.batch_execute("BEGIN").map_err(|e| Error::Storage(e.to_string()))
 }
 
-fn commit_tx(client: &Rc<RefCell<Client>>) -> Result<()> {
+fn commit_tx(client: &Rc<RefCell<PgClient>>) -> Result<()> {
     let mut c = client.borrow_mut();
     c.batch_execute("COMMIT").map_err(|e| Error::Storage(e.to_string()))
 }
 
-fn rollback_tx(client: &Rc<RefCell<Client>>) -> Result<()> {
+fn rollback_tx(client: &Rc<RefCell<PgClient>>) -> Result<()> {
     let mut c = client.borrow_mut();
     c.batch_execute("ROLLBACK").map_err(|e| Error::Storage(e.to_string()))
 }
 
 fn begin_local_core_op(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
     replica: &ReplicaId,
 ) -> Result<LocalOpSession> {
@@ -64,15 +67,18 @@ fn begin_local_core_op(
     let ctx = PgCtx::new(client.clone(), doc_id)?;
 
     let storage = PgOpStorage::new(ctx.clone());
-    let nodes = PgNodeStore::new(ctx.clone());
-    let payloads = PgPayloadStore::new(ctx.clone());
-    let crdt = TreeCrdt::with_stores(
+    let nodes = PgNodeStore::new(ctx.clone())?;
+    let payloads = PgPayloadStore::new(ctx.clone())?;
+    let mut crdt = TreeCrdt::with_stores(
         replica.clone(),
         storage,
         LamportClock::default(),
         nodes.clone(),
         payloads,
     )?;
+    if let Some(policy) = access_control(client) {
+        crdt.set_access_control(policy);
+    }
 
     Ok(LocalOpSession {
         ctx,
@@ -87,17 +93,30 @@ fn finish_local_core_op(
     op: &Operation,
     plan: LocalFinalizePlan,
 ) -> Result<MaterializationOutcome> {
+    finish_local_core_ops(session, op, |crdt, op_index, head_seq| {
+        crdt.finalize_local_with_outcome(op, op_index, head_seq, &plan)
+    })
+}
+
+/// Finalize the ops the session committed, `last` being the newest one, and advance the tree head.
+fn finish_local_core_ops<F>(
+    session: &mut LocalOpSession,
+    last: &Operation,
+    finalize: F,
+) -> Result<MaterializationOutcome>
+where
+    F: FnOnce(&mut LocalCrdt, &mut PgParentOpIndex, u64) -> Result<MaterializationOutcome>,
+{
     let mut post_materialization_ok = true;
     let mut outcome = MaterializationOutcome::empty(session.meta.state().head_seq());
 
     let mut op_index = PgParentOpIndex::new(session.ctx.clone());
-    // commit_prepared_local() already persisted the op and updated node/payload state. The finalize
-    // step refreshes adapter-owned derived state that lives outside TreeCrdt itself.
-    match session.crdt.finalize_local_with_outcome(
-        op,
+    // commit_prepared_local() already persisted the ops and updated node/payload state. The
+    // finalize step refreshes adapter-owned derived state that lives outside TreeCrdt itself.
+    match finalize(
+        &mut session.crdt,
         &mut op_index,
         session.meta.state().head_seq(),
-        &plan,
     ) {
         Ok(v) => {
             outcome = v;
@@ -110,9 +129,9 @@ fn finish_local_core_op(
 
     let head = treecrdt_core::MaterializationHead {
         at: treecrdt_core::MaterializationKey {
-            lamport: op.meta.lamport,
-            replica: op.meta.id.replica.as_bytes(),
-            counter: op.meta.id.counter,
+            lamport: last.meta.lamport,
+            replica: last.meta.id.replica.as_bytes(),
+            counter: last.meta.id.counter,
         },
         seq: outcome.head_seq,
     };
@@ -137,6 +156,23 @@ fn finish_local_core_op(
     Ok(outcome)
 }
 
+fn commit_in_session(
+    session: &mut LocalOpSession,
+    prepared: PreparedLocalOp,
+) -> Result<LocalOpResult> {
+    let undo = session.crdt.undo_record(&prepared.op)?;
+    let (op, plan) = session.crdt.commit_prepared_local(prepared)?;
+    let outcome = finish_local_core_op(session, &op, plan)?;
+    Ok(LocalOpResult {
+        undo: UndoRecord {
+            op: op.clone(),
+            ..undo
+        },
+        op,
+        outcome,
+    })
+}
+
 pub struct PreparedLocalOpTx {
     session: Option<LocalOpSession>,
     prepared: Option<PreparedLocalOp>,
@@ -150,11 +186,7 @@ impl PreparedLocalOpTx {
     pub fn commit(mut self) -> Result<LocalOpResult> {
         let mut session = self.session.take().expect("prepared local op already closed");
         let prepared = self.prepared.take().expect("prepared local op already closed");
-        let res = (|| {
-            let (op, plan) = session.crdt.commit_prepared_local(prepared)?;
-            let outcome = finish_local_core_op(&mut session, &op, plan)?;
-            Ok(LocalOpResult { op, outcome })
-        })();
+        let res = commit_in_session(&mut session, prepared);
 
         match res {
             Ok(v) => {
@@ -189,7 +221,7 @@ impl Drop for PreparedLocalOpTx {
 }
 
 fn prepare_local_core_op<F>(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
     replica: &ReplicaId,
     build: F,
@@ -214,7 +246,7 @@ where
 
 #[allow(clippy::too_many_arguments)]
 pub fn local_insert(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
     replica: &ReplicaId,
     parent: NodeId,
@@ -231,7 +263,7 @@ pub fn local_insert(
 
 #[allow(clippy::too_many_arguments)]
 pub fn prepare_local_insert_tx(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
     replica: &ReplicaId,
     parent: NodeId,
@@ -247,7 +279,7 @@ pub fn prepare_local_insert_tx(
 }
 
 pub fn local_move(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
     replica: &ReplicaId,
     node: NodeId,
@@ -259,7 +291,7 @@ pub fn local_move(
 }
 
 pub fn prepare_local_move_tx(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
     replica: &ReplicaId,
     node: NodeId,
@@ -274,7 +306,7 @@ pub fn prepare_local_move_tx(
 }
 
 pub fn local_delete(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
     replica: &ReplicaId,
     node: NodeId,
@@ -283,7 +315,7 @@ pub fn local_delete(
 }
 
 pub fn prepare_local_delete_tx(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
     replica: &ReplicaId,
     node: NodeId,
@@ -293,8 +325,50 @@ pub fn prepare_local_delete_tx(
     })
 }
 
+/// Delete a node for good: concurrent edits under it do not revive it and it cannot be restored.
+pub fn local_tombstone(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    replica: &ReplicaId,
+    node: NodeId,
+) -> Result<LocalOpResult> {
+    prepare_local_tombstone_tx(client, doc_id, replica, node)?.commit()
+}
+
+pub fn prepare_local_tombstone_tx(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    replica: &ReplicaId,
+    node: NodeId,
+) -> Result<PreparedLocalOpTx> {
+    prepare_local_core_op(client, doc_id, replica, |crdt| {
+        crdt.prepare_local_tombstone(node)
+    })
+}
+
+/// Bring back a deleted node and any deleted ancestors it sits under.
+pub fn local_restore(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    replica: &ReplicaId,
+    node: NodeId,
+) -> Result<LocalOpResult> {
+    prepare_local_restore_tx(client, doc_id, replica, node)?.commit()
+}
+
+pub fn prepare_local_restore_tx(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    replica: &ReplicaId,
+    node: NodeId,
+) -> Result<PreparedLocalOpTx> {
+    prepare_local_core_op(client, doc_id, replica, |crdt| {
+        crdt.prepare_local_restore(node)
+    })
+}
+
 pub fn local_payload(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
     replica: &ReplicaId,
     node: NodeId,
@@ -304,7 +378,7 @@ pub fn local_payload(
 }
 
 pub fn prepare_local_payload_tx(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
     replica: &ReplicaId,
     node: NodeId,
@@ -314,3 +388,268 @@ pub fn prepare_local_payload_tx(
         crdt.prepare_local_payload(node, payload)
     })
 }
+
+/// Set (or, with `None`, clear) one field of `node`'s payload map.
+pub fn local_payload_field(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    replica: &ReplicaId,
+    node: NodeId,
+    field: &str,
+    value: Option<Vec<u8>>,
+) -> Result<LocalOpResult> {
+    prepare_local_payload_field_tx(client, doc_id, replica, node, field, value)?.commit()
+}
+
+pub fn prepare_local_payload_field_tx(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    replica: &ReplicaId,
+    node: NodeId,
+    field: &str,
+    value: Option<Vec<u8>>,
+) -> Result<PreparedLocalOpTx> {
+    prepare_local_core_op(client, doc_id, replica, |crdt| {
+        crdt.prepare_local_payload_field(node, field, value)
+    })
+}
+
+#[derive(Clone, Debug)]
+pub struct LocalBatchResult {
+    /// The committed ops, in the order of the edits.
+    pub ops: Vec<Operation>,
+    /// One outcome covering every op of the batch.
+    pub outcome: MaterializationOutcome,
+    /// Undo records of `ops`; record them as one [`UndoManager`] transaction.
+    pub undo: Vec<UndoRecord>,
+}
+
+/// Stage `edits` against the materialized tree and commit them in one Postgres transaction.
+///
+/// Each edit sees the ones before it (see [`TreeCrdt::local_transaction`]). If any edit fails,
+/// the transaction is rolled back and nothing is committed.
+pub fn local_batch(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    replica: &ReplicaId,
+    edits: Vec<LocalEdit>,
+) -> Result<LocalBatchResult> {
+    run_local_staged(client, doc_id, replica, |crdt| {
+        Ok((crdt.local_batch(edits)?, ()))
+    })
+    .map(|(batch, ())| batch)
+}
+
+/// Insert `nodes` with their payloads as consecutive children of `parent`, the first one at
+/// `placement`, in one transaction.
+///
+/// The order keys are spaced evenly between the neighbours (see
+/// [`TreeCrdt::local_insert_many`]). If any insert fails, nothing is committed.
+pub fn local_insert_many(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    replica: &ReplicaId,
+    parent: NodeId,
+    placement: LocalPlacement,
+    nodes: Vec<(NodeId, Option<Vec<u8>>)>,
+) -> Result<LocalBatchResult> {
+    run_local_staged(client, doc_id, replica, |crdt| {
+        Ok((crdt.local_insert_many(parent, placement, nodes)?, ()))
+    })
+    .map(|(batch, ())| batch)
+}
+
+/// Re-key the runs of overlong order keys among `parent`'s visible children in their current
+/// order, in one transaction (see [`TreeCrdt::local_rebalance`]).
+pub fn local_rebalance(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    replica: &ReplicaId,
+    parent: NodeId,
+) -> Result<LocalBatchResult> {
+    run_local_staged(client, doc_id, replica, |crdt| {
+        Ok((crdt.local_rebalance(parent)?, ()))
+    })
+    .map(|(batch, ())| batch)
+}
+
+/// Move `parent`'s visible children into the `desired` order with as few moves as possible, in
+/// one transaction (see [`TreeCrdt::local_reorder`]).
+pub fn local_reorder(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    replica: &ReplicaId,
+    parent: NodeId,
+    desired: &[NodeId],
+) -> Result<LocalBatchResult> {
+    run_local_staged(client, doc_id, replica, |crdt| {
+        Ok((crdt.local_reorder(parent, desired)?, ()))
+    })
+    .map(|(batch, ())| batch)
+}
+
+#[derive(Clone, Debug)]
+pub struct LocalDuplicateResult {
+    /// One insert per copied node, parents before children.
+    pub ops: Vec<Operation>,
+    pub outcome: MaterializationOutcome,
+    /// Undo records of `ops`; record them as one [`UndoManager`] transaction.
+    pub undo: Vec<UndoRecord>,
+    /// `(original, copy)` for every copied node, in the order of `ops`.
+    pub mapping: Vec<(NodeId, NodeId)>,
+}
+
+const DUPLICATE_ID_DOMAIN: &[u8] = b"treecrdt/duplicate/v0";
+
+/// Id of the copy of `source` made by `replica` when its clock stood at `lamport`; every local op
+/// ticks the clock, so repeated copies of one node get different ids.
+fn duplicate_node_id(doc_id: &str, replica: &ReplicaId, lamport: u64, source: NodeId) -> NodeId {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(DUPLICATE_ID_DOMAIN);
+    hasher.update(&(doc_id.len() as u32).to_be_bytes());
+    hasher.update(doc_id.as_bytes());
+    hasher.update(&(replica.as_bytes().len() as u32).to_be_bytes());
+    hasher.update(replica.as_bytes());
+    hasher.update(&lamport.to_be_bytes());
+    hasher.update(&source.0.to_be_bytes());
+    let mut out = [0u8; 16];
+    out.copy_from_slice(&hasher.finalize().as_bytes()[..16]);
+    NodeId(u128::from_be_bytes(out))
+}
+
+/// Copy the visible subtree of `source` under `new_parent` in one Postgres transaction (see
+/// [`TreeCrdt::local_duplicate`]). Copies get fresh ids derived from the doc, replica and clock.
+pub fn local_duplicate(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    replica: &ReplicaId,
+    source: NodeId,
+    new_parent: NodeId,
+    placement: LocalPlacement,
+) -> Result<LocalDuplicateResult> {
+    let (batch, mapping) = run_local_staged(client, doc_id, replica, |crdt| {
+        let lamport = crdt.lamport();
+        let copy = crdt.local_duplicate(source, new_parent, placement, |node| {
+            duplicate_node_id(doc_id, replica, lamport, node)
+        })?;
+        Ok((copy.staged, copy.mapping))
+    })?;
+    Ok(LocalDuplicateResult {
+        ops: batch.ops,
+        outcome: batch.outcome,
+        undo: batch.undo,
+        mapping,
+    })
+}
+
+fn run_local_staged<T>(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    replica: &ReplicaId,
+    stage: impl FnOnce(&mut LocalCrdt) -> Result<(Vec<StagedLocalOp>, T)>,
+) -> Result<(LocalBatchResult, T)> {
+    begin_tx(client)?;
+    let res = (|| {
+        let mut session = begin_local_core_op(client, doc_id, replica)?;
+        let (staged, extra) = stage(&mut session.crdt)?;
+        let Some(last) = staged.last().map(|entry| entry.op.clone()) else {
+            let batch = LocalBatchResult {
+                ops: Vec::new(),
+                outcome: MaterializationOutcome::empty(session.meta.state().head_seq()),
+                undo: Vec::new(),
+            };
+            return Ok((batch, extra));
+        };
+        let outcome = finish_local_core_ops(&mut session, &last, |crdt, op_index, head_seq| {
+            crdt.finalize_local_batch_with_outcome(&staged, op_index, head_seq)
+        })?;
+        let (ops, undo) = staged.into_iter().map(|entry| (entry.op, entry.undo)).unzip();
+        Ok((LocalBatchResult { ops, outcome, undo }, extra))
+    })();
+    match res {
+        Ok(v) => {
+            if let Err(e) = commit_tx(client) {
+                let _ = rollback_tx(client);
+                return Err(e);
+            }
+            Ok(v)
+        }
+        Err(e) => {
+            let _ = rollback_tx(client);
+            Err(e)
+        }
+    }
+}
+
+type InvertFn<'a> = dyn FnMut(&UndoRecord) -> Result<Option<UndoRecord>> + 'a;
+
+fn run_undo_step<F>(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    replica: &ReplicaId,
+    step: F,
+) -> Result<Vec<LocalOpResult>>
+where
+    F: FnOnce(&mut InvertFn<'_>) -> Result<Vec<Operation>>,
+{
+    begin_tx(client)?;
+    let mut results = Vec::new();
+    let res = step(&mut |record: &UndoRecord| {
+        // A fresh session per inverse: each one reloads the head the previous one advanced.
+        let mut session = begin_local_core_op(client, doc_id, replica)?;
+        let Some(prepared) = session.crdt.prepare_inverse(record)? else {
+            return Ok(None);
+        };
+        let result = commit_in_session(&mut session, prepared)?;
+        let inverse = result.undo.clone();
+        results.push(result);
+        Ok(Some(inverse))
+    });
+    match res {
+        Ok(_) => {
+            if let Err(e) = commit_tx(client) {
+                let _ = rollback_tx(client);
+                return Err(e);
+            }
+            Ok(results)
+        }
+        Err(e) => {
+            let _ = rollback_tx(client);
+            Err(e)
+        }
+    }
+}
+
+/// Revert `replica`'s most recent transaction in `undo` with new local ops, all in one Postgres
+/// transaction. Superseded records are skipped (see [`TreeCrdt::prepare_inverse`]); the result is
+/// empty if nothing was left to revert.
+pub fn undo(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    replica: &ReplicaId,
+    undo: &mut UndoManager,
+) -> Result<Vec<LocalOpResult>> {
+    let before = undo.clone();
+    let res = run_undo_step(client, doc_id, replica, |invert| undo.undo_with(invert));
+    if res.is_err() {
+        // The transaction rolled every inverse back, so the history goes back as well.
+        *undo = before;
+    }
+    res
+}
+
+/// Re-apply the transaction most recently reverted with [`undo`].
+pub fn redo(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    replica: &ReplicaId,
+    undo: &mut UndoManager,
+) -> Result<Vec<LocalOpResult>> {
+    let before = undo.clone();
+    let res = run_undo_step(client, doc_id, replica, |invert| undo.redo_with(invert));
+    if res.is_err() {
+        // The transaction rolled every inverse back, so the history goes back as well.
+        *undo = before;
+    }
+    res
+}
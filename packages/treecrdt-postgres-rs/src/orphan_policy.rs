@@ -0,0 +1,88 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use treecrdt_core::{Error, MaterializationFrontier, OrphanPolicy, Result};
+
+use crate::client::PgClient;
+use crate::store::{
+    ensure_doc_meta, ensure_materialized_in_tx, set_tree_meta_replay_frontier, storage_debug,
+};
+
+fn policy_name(policy: OrphanPolicy) -> &'static str {
+    match policy {
+        OrphanPolicy::Defensive => "defensive",
+        OrphanPolicy::KeepParent => "keep_parent",
+        OrphanPolicy::LostAndFound => "lost_and_found",
+    }
+}
+
+pub(crate) fn load_orphan_policy(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+) -> Result<OrphanPolicy> {
+    ensure_doc_meta(client, doc_id)?;
+    let mut c = client.borrow_mut();
+    let row = c
+        .query_one(
+            "SELECT orphan_policy FROM treecrdt_meta WHERE doc_id = $1 LIMIT 1",
+            &[&doc_id],
+        )
+        .map_err(storage_debug)?;
+    match row.get::<_, Option<String>>(0).as_deref() {
+        Some("defensive") | None => Ok(OrphanPolicy::Defensive),
+        Some("keep_parent") => Ok(OrphanPolicy::KeepParent),
+        Some("lost_and_found") => Ok(OrphanPolicy::LostAndFound),
+        Some(other) => Err(Error::Storage(format!("unknown orphan policy: {other}"))),
+    }
+}
+
+/// Choose what deletes in this doc do with nodes placed under them concurrently.
+///
+/// Materialized state depends on the policy, so changing it replays the doc's whole op log in
+/// the same transaction.
+pub fn set_orphan_policy(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    policy: OrphanPolicy,
+) -> Result<()> {
+    {
+        let mut c = client.borrow_mut();
+        c.batch_execute("BEGIN").map_err(|e| Error::Storage(e.to_string()))?;
+    }
+
+    let res = (|| {
+        if load_orphan_policy(client, doc_id)? == policy {
+            return Ok(());
+        }
+        {
+            let mut c = client.borrow_mut();
+            c.execute(
+                "UPDATE treecrdt_meta SET orphan_policy = $2 WHERE doc_id = $1",
+                &[&doc_id, &policy_name(policy)],
+            )
+            .map_err(storage_debug)?;
+        }
+        set_tree_meta_replay_frontier(
+            client,
+            doc_id,
+            &MaterializationFrontier {
+                lamport: 0,
+                replica: Vec::new(),
+                counter: 0,
+            },
+        )?;
+        ensure_materialized_in_tx(client, doc_id).map(|_| ())
+    })();
+
+    let mut c = client.borrow_mut();
+    match res {
+        Ok(()) => {
+            c.batch_execute("COMMIT").map_err(|e| Error::Storage(e.to_string()))?;
+            Ok(())
+        }
+        Err(e) => {
+            let _ = c.batch_execute("ROLLBACK");
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use treecrdt_core::{Error, PayloadMode, Result};
+
+use crate::client::PgClient;
+use crate::store::{ensure_doc_meta, storage_debug};
+
+fn mode_name(mode: PayloadMode) -> &'static str {
+    match mode {
+        PayloadMode::LastWriterWins => "last_writer_wins",
+        PayloadMode::MultiValue => "multi_value",
+    }
+}
+
+pub(crate) fn load_payload_mode(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+) -> Result<PayloadMode> {
+    ensure_doc_meta(client, doc_id)?;
+    let mut c = client.borrow_mut();
+    let row = c
+        .query_one(
+            "SELECT payload_mode FROM treecrdt_meta WHERE doc_id = $1 LIMIT 1",
+            &[&doc_id],
+        )
+        .map_err(storage_debug)?;
+    match row.get::<_, Option<String>>(0).as_deref() {
+        Some("multi_value") => Ok(PayloadMode::MultiValue),
+        Some("last_writer_wins") | None => Ok(PayloadMode::LastWriterWins),
+        Some(other) => Err(Error::Storage(format!("unknown payload mode: {other}"))),
+    }
+}
+
+/// Choose how concurrent payload writes to this doc are materialized.
+///
+/// In [`PayloadMode::MultiValue`], `treecrdt_payload_values` keeps every payload write of a node
+/// that no other write has seen, and local payload writes resolve them. Switching it on starts
+/// from the current winners; switching it off drops the doc's concurrent values.
+pub fn set_payload_mode(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    mode: PayloadMode,
+) -> Result<()> {
+    ensure_doc_meta(client, doc_id)?;
+    let mut c = client.borrow_mut();
+    // One statement, so the mode and the values it implies change together.
+    c.execute(
+        "WITH cleared AS ( \
+           DELETE FROM treecrdt_payload_values \
+           WHERE doc_id = $1 AND $2::text = 'last_writer_wins' \
+         ) \
+         UPDATE treecrdt_meta SET payload_mode = $2 WHERE doc_id = $1",
+        &[&doc_id, &mode_name(mode)],
+    )
+    .map_err(storage_debug)?;
+    Ok(())
+}
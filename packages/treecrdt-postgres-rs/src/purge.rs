@@ -0,0 +1,159 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use postgres::Client;
+
+use treecrdt_core::{purge_stable_subtrees, Error, PurgeReport, ReplicaId, Result, VersionVector};
+
+use crate::client::PgClient;
+use crate::stability::load_stability;
+use crate::store::{
+    ensure_materialized_in_tx, node_to_bytes, storage_debug, vv_from_bytes, vv_to_bytes, PgCtx,
+    PgNodeStore, PgPayloadStore,
+};
+
+/// What [`purge_stable`] removed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PurgeResult {
+    /// Node and payload rows removed from the materialized tree.
+    pub purged: PurgeReport,
+    /// `treecrdt_oprefs_children` rows removed.
+    pub oprefs_children: u64,
+    /// `treecrdt_ops` rows removed (only with `include_ops`).
+    pub ops: u64,
+}
+
+/// Ids of the ops `include_ops` dropped from the log.
+///
+/// They still count as seen: re-delivered copies are ignored, and local counters and the
+/// lamport clock never fall back below them (see `purged_lamport`).
+pub(crate) fn load_purged_version_vector(c: &mut Client, doc_id: &str) -> Result<VersionVector> {
+    let rows = c
+        .query(
+            "SELECT purged_version_vector FROM treecrdt_meta WHERE doc_id = $1 LIMIT 1",
+            &[&doc_id],
+        )
+        .map_err(storage_debug)?;
+    match rows.first().and_then(|row| row.get::<_, Option<Vec<u8>>>(0)) {
+        Some(bytes) => vv_from_bytes(&bytes),
+        None => Ok(VersionVector::new()),
+    }
+}
+
+fn local_version_vector(client: &Rc<RefCell<PgClient>>, doc_id: &str) -> Result<VersionVector> {
+    let mut c = client.borrow_mut();
+    let mut vv = load_purged_version_vector(&mut c, doc_id)?;
+    let rows = c
+        .query(
+            "SELECT replica, counter FROM treecrdt_ops WHERE doc_id = $1",
+            &[&doc_id],
+        )
+        .map_err(storage_debug)?;
+    for row in rows {
+        let replica: Vec<u8> = row.get(0);
+        let counter: i64 = row.get(1);
+        vv.observe(&ReplicaId::new(replica), counter.max(0) as u64);
+    }
+    Ok(vv)
+}
+
+fn purge_stable_in_tx(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    include_ops: bool,
+) -> Result<PurgeResult> {
+    ensure_materialized_in_tx(client, doc_id)?;
+    // Lock the meta row so acks recorded concurrently are not missed.
+    let tracker = load_stability(client, doc_id, true)?;
+    let settled = tracker.settled_frontier(&local_version_vector(client, doc_id)?);
+
+    let ctx = PgCtx::new(client.clone(), doc_id)?;
+    let mut nodes = PgNodeStore::new(ctx.clone())?;
+    let mut payloads = PgPayloadStore::new(ctx)?;
+    let purged = purge_stable_subtrees(&mut nodes, &mut payloads, &settled)?;
+    nodes.flush_last_change()?;
+    if purged.is_empty() {
+        return Ok(PurgeResult::default());
+    }
+
+    let purged_nodes: Vec<Vec<u8>> =
+        purged.nodes.iter().map(|&node| node_to_bytes(node).to_vec()).collect();
+    let mut c = client.borrow_mut();
+    let mut oprefs_children = c
+        .execute(
+            "DELETE FROM treecrdt_oprefs_children WHERE doc_id = $1 AND parent = ANY($2)",
+            &[&doc_id, &purged_nodes],
+        )
+        .map_err(storage_debug)?;
+    let mut ops = 0;
+    if include_ops {
+        oprefs_children += c
+            .execute(
+                "DELETE FROM treecrdt_oprefs_children WHERE doc_id = $1 AND op_ref IN \
+                 (SELECT op_ref FROM treecrdt_ops WHERE doc_id = $1 AND node = ANY($2))",
+                &[&doc_id, &purged_nodes],
+            )
+            .map_err(storage_debug)?;
+        let dropped = c
+            .query(
+                "DELETE FROM treecrdt_ops WHERE doc_id = $1 AND node = ANY($2) \
+                 RETURNING replica, counter, lamport",
+                &[&doc_id, &purged_nodes],
+            )
+            .map_err(storage_debug)?;
+        ops = dropped.len() as u64;
+        let mut purged_vv = load_purged_version_vector(&mut c, doc_id)?;
+        let mut purged_lamport = 0i64;
+        for row in &dropped {
+            let replica: Vec<u8> = row.get(0);
+            let counter: i64 = row.get(1);
+            purged_vv.observe(&ReplicaId::new(replica), counter.max(0) as u64);
+            purged_lamport = purged_lamport.max(row.get(2));
+        }
+        c.execute(
+            "UPDATE treecrdt_meta \
+             SET purged_version_vector = $2, purged_lamport = GREATEST(purged_lamport, $3) \
+             WHERE doc_id = $1",
+            &[&doc_id, &vv_to_bytes(&purged_vv)?, &purged_lamport],
+        )
+        .map_err(storage_debug)?;
+    }
+
+    Ok(PurgeResult {
+        purged,
+        oprefs_children,
+        ops,
+    })
+}
+
+/// Permanently remove deleted subtrees of `doc_id` that can no longer be revived.
+///
+/// Uses the settled frontier of the acks recorded with [`crate::ack_version_vector`], so nothing
+/// is purged until every tracked peer has acknowledged the deletions. With `include_ops` the
+/// purged nodes' ops are dropped from the log too. Their ids and lamport are remembered in the
+/// doc meta, so copies peers send again are ignored and locally minted ids never reuse them.
+pub fn purge_stable(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    include_ops: bool,
+) -> Result<PurgeResult> {
+    {
+        let mut c = client.borrow_mut();
+        c.batch_execute("BEGIN").map_err(|e| Error::Storage(e.to_string()))?;
+    }
+
+    let res = purge_stable_in_tx(client, doc_id, include_ops);
+
+    match res {
+        Ok(v) => {
+            let mut c = client.borrow_mut();
+            c.batch_execute("COMMIT").map_err(|e| Error::Storage(e.to_string()))?;
+            Ok(v)
+        }
+        Err(e) => {
+            let mut c = client.borrow_mut();
+            let _ = c.batch_execute("ROLLBACK");
+            Err(e)
+        }
+    }
+}
@@ -1,23 +1,35 @@
 use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 
-use postgres::Client;
-
-use treecrdt_core::{Error, Lamport, NodeId, Operation, Result};
+use treecrdt_core::{
+    diff_between, materialize_at, Checkpoint, Error, HistoryCut, Lamport, MaterializationChange,
+    MaterializationSource, MaterializationSourceOperation, NodeId, Operation, OrderKeyStats,
+    PayloadStore, PayloadValue, ReplicaId, Result, TrashCursor, TrashEntry, VersionVector,
+    TRASH_PREVIEW_LEN,
+};
 
+use crate::access::{check_read, retain_readable};
+use crate::client::PgClient;
 use crate::opref::{derive_op_ref_v0, OPREF_V0_WIDTH};
+use crate::orphan_policy::load_orphan_policy;
+use crate::payload_mode::load_payload_mode;
+use crate::purge::load_purged_version_vector;
 use crate::store::{
-    bytes_to_node, ensure_doc_meta, ensure_materialized, node_to_bytes, op_ref_from_bytes,
-    row_to_op, row_to_op_at, storage_debug, PgCtx,
+    bytes_to_node, deleted_by_from_row, ensure_doc_meta, ensure_materialized, node_to_bytes,
+    op_ref_from_bytes, row_to_op, row_to_op_at, storage_debug, vv_from_bytes, PgCtx, PgOpStorage,
+    PgPayloadStore,
 };
 
-pub fn max_lamport(client: &Rc<RefCell<Client>>, doc_id: &str) -> Result<Lamport> {
+pub fn max_lamport(client: &Rc<RefCell<PgClient>>, doc_id: &str) -> Result<Lamport> {
     ensure_doc_meta(client, doc_id)?;
     let ctx = PgCtx::new(client.clone(), doc_id)?;
     let mut c = client.borrow_mut();
     let stmt = ctx.stmt(
         &mut c,
-        "SELECT COALESCE(MAX(lamport), 0) FROM treecrdt_ops WHERE doc_id = $1",
+        "SELECT GREATEST( \
+           (SELECT COALESCE(MAX(lamport), 0) FROM treecrdt_ops WHERE doc_id = $1), \
+           (SELECT COALESCE(MAX(purged_lamport), 0) FROM treecrdt_meta WHERE doc_id = $1))",
     )?;
     let rows = c.query(&stmt, &[&doc_id]).map_err(storage_debug)?;
     let row = rows.first().ok_or_else(|| Error::Storage("missing MAX(lamport) row".into()))?;
@@ -25,7 +37,7 @@ pub fn max_lamport(client: &Rc<RefCell<Client>>, doc_id: &str) -> Result<Lamport
 }
 
 pub fn list_op_refs_all(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
 ) -> Result<Vec<[u8; OPREF_V0_WIDTH]>> {
     ensure_doc_meta(client, doc_id)?;
@@ -45,11 +57,12 @@ pub fn list_op_refs_all(
 }
 
 pub fn list_op_refs_children(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
     parent: NodeId,
 ) -> Result<Vec<[u8; OPREF_V0_WIDTH]>> {
     ensure_materialized(client, doc_id)?;
+    check_read(client, doc_id, parent)?;
     let ctx = PgCtx::new(client.clone(), doc_id)?;
     let parent_bytes = node_to_bytes(parent);
     let mut c = client.borrow_mut();
@@ -67,11 +80,12 @@ pub fn list_op_refs_children(
 }
 
 pub fn list_op_refs_children_with_parent_payload(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
     parent: NodeId,
 ) -> Result<Vec<[u8; OPREF_V0_WIDTH]>> {
     ensure_materialized(client, doc_id)?;
+    check_read(client, doc_id, parent)?;
     let ctx = PgCtx::new(client.clone(), doc_id)?;
     let parent_bytes = node_to_bytes(parent);
     let mut c = client.borrow_mut();
@@ -135,7 +149,7 @@ pub fn list_op_refs_children_with_parent_payload(
 }
 
 pub fn get_ops_by_op_refs(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
     op_refs: &[[u8; OPREF_V0_WIDTH]],
 ) -> Result<Vec<Operation>> {
@@ -152,7 +166,7 @@ pub fn get_ops_by_op_refs(
         &mut c,
         "SELECT \
           i.ord, \
-          o.lamport, o.replica, o.counter, o.kind, o.parent, o.node, o.new_parent, o.order_key, o.payload, o.known_state \
+          o.lamport, o.replica, o.counter, o.kind, o.parent, o.node, o.new_parent, o.order_key, o.payload, o.known_state, o.group_first, o.group_len, o.field \
          FROM unnest($2::bytea[]) WITH ORDINALITY AS i(op_ref, ord) \
          LEFT JOIN treecrdt_ops o \
            ON o.doc_id = $1 AND o.op_ref = i.op_ref \
@@ -168,11 +182,13 @@ pub fn get_ops_by_op_refs(
         }
         out.push(row_to_op_at(&row, 1)?);
     }
+    drop(c);
+    retain_readable(client, doc_id, &mut out, |op| op.kind.node())?;
     Ok(out)
 }
 
 pub fn ops_since(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
     lamport: Lamport,
     root: Option<NodeId>,
@@ -183,7 +199,7 @@ pub fn ops_since(
     let mut c = client.borrow_mut();
     let stmt = ctx.stmt(
         &mut c,
-        "SELECT lamport, replica, counter, kind, parent, node, new_parent, order_key, payload, known_state \
+        "SELECT lamport, replica, counter, kind, parent, node, new_parent, order_key, payload, known_state, group_first, group_len, field \
          FROM treecrdt_ops \
          WHERE doc_id = $1 AND lamport > $2 \
            AND ($3::bytea IS NULL OR parent = $3 OR node = $3 OR new_parent = $3) \
@@ -192,7 +208,10 @@ pub fn ops_since(
     let rows = c
         .query(&stmt, &[&doc_id, &(lamport as i64), &root_bytes])
         .map_err(storage_debug)?;
-    rows.into_iter().map(row_to_op).collect()
+    drop(c);
+    let mut ops = rows.into_iter().map(row_to_op).collect::<Result<Vec<_>>>()?;
+    retain_readable(client, doc_id, &mut ops, |op| op.kind.node())?;
+    Ok(ops)
 }
 
 #[derive(Clone, Debug)]
@@ -210,11 +229,12 @@ pub struct TreeRow {
 }
 
 pub fn tree_children(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
     parent: NodeId,
 ) -> Result<Vec<NodeId>> {
     ensure_materialized(client, doc_id)?;
+    check_read(client, doc_id, parent)?;
     if parent == NodeId::TRASH {
         return Ok(Vec::new());
     }
@@ -236,14 +256,45 @@ pub fn tree_children(
     Ok(out)
 }
 
+/// Order key lengths of `parent`'s visible children, see [`treecrdt_core::OrderKeyStats`].
+pub fn tree_order_key_stats(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    parent: NodeId,
+) -> Result<OrderKeyStats> {
+    ensure_materialized(client, doc_id)?;
+    check_read(client, doc_id, parent)?;
+    let ctx = PgCtx::new(client.clone(), doc_id)?;
+    let parent_bytes = node_to_bytes(parent);
+    let mut c = client.borrow_mut();
+    let stmt = ctx.stmt(
+        &mut c,
+        "SELECT COUNT(*), \
+         COALESCE(MAX(octet_length(order_key)), 0)::bigint, \
+         COALESCE(SUM(octet_length(order_key)), 0)::bigint \
+         FROM treecrdt_nodes \
+         WHERE doc_id = $1 AND parent = $2 AND tombstone = FALSE",
+    )?;
+    let rows = c.query(&stmt, &[&doc_id, &parent_bytes.as_slice()]).map_err(storage_debug)?;
+    let row = rows
+        .first()
+        .ok_or_else(|| Error::Storage("missing order key stats row".into()))?;
+    Ok(OrderKeyStats {
+        children: row.get::<_, i64>(0).max(0) as u64,
+        max_len: row.get::<_, i64>(1).max(0) as u64,
+        total_len: row.get::<_, i64>(2).max(0) as u64,
+    })
+}
+
 pub fn tree_children_page(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
     parent: NodeId,
     cursor: Option<(Vec<u8>, Vec<u8>)>,
     limit: u32,
 ) -> Result<Vec<TreeChildRow>> {
     ensure_materialized(client, doc_id)?;
+    check_read(client, doc_id, parent)?;
     if parent == NodeId::TRASH {
         return Ok(Vec::new());
     }
@@ -287,7 +338,89 @@ pub fn tree_children_page(
     Ok(out)
 }
 
-pub fn tree_dump(client: &Rc<RefCell<Client>>, doc_id: &str) -> Result<Vec<TreeRow>> {
+/// Up to `limit` deleted subtrees after `cursor`, newest delete first; see
+/// [`treecrdt_core::trash`]. Entries the access policy hides are skipped, so a page can come back
+/// shorter than `limit` before the listing ends.
+pub fn tree_trash_page(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    cursor: Option<&TrashCursor>,
+    limit: u32,
+) -> Result<Vec<TrashEntry>> {
+    ensure_materialized(client, doc_id)?;
+    let ctx = PgCtx::new(client.clone(), doc_id)?;
+    let after_lamport = cursor.map(|cursor| cursor.lamport as i64);
+    let after_replica = cursor.map(|cursor| cursor.replica.clone());
+    let after_counter = cursor.map(|cursor| cursor.counter as i64);
+    let after_node = cursor.map(|cursor| node_to_bytes(cursor.node).to_vec());
+
+    let rows = {
+        let mut c = client.borrow_mut();
+        let stmt = ctx.stmt(
+            &mut c,
+            "SELECT n.node, n.parent, n.deleted_at, n.hard_deleted, \
+             substring(p.payload from 1 for $7::int), \
+             n.deleted_lamport, n.deleted_replica, n.deleted_counter \
+             FROM treecrdt_nodes n \
+             LEFT JOIN treecrdt_nodes up ON up.doc_id = n.doc_id AND up.node = n.parent \
+             LEFT JOIN treecrdt_payload p ON p.doc_id = n.doc_id AND p.node = n.node \
+             WHERE n.doc_id = $1 AND n.tombstone = TRUE AND COALESCE(up.tombstone, FALSE) = FALSE \
+               AND ($2::bigint IS NULL OR (COALESCE(n.deleted_lamport, 0), \
+                    COALESCE(n.deleted_replica, ''::bytea), COALESCE(n.deleted_counter, 0), n.node) \
+                    < ($2::bigint, $3::bytea, $4::bigint, $5::bytea)) \
+             ORDER BY COALESCE(n.deleted_lamport, 0) DESC, \
+               COALESCE(n.deleted_replica, ''::bytea) DESC, \
+               COALESCE(n.deleted_counter, 0) DESC, n.node DESC \
+             LIMIT $6",
+        )?;
+        c.query(
+            &stmt,
+            &[
+                &doc_id,
+                &after_lamport,
+                &after_replica,
+                &after_counter,
+                &after_node,
+                &(limit as i64),
+                &(TRASH_PREVIEW_LEN as i32),
+            ],
+        )
+        .map_err(storage_debug)?
+    };
+
+    let mut out = Vec::with_capacity(rows.len());
+    for row in rows {
+        let node: Vec<u8> = row.get(0);
+        let node = bytes_to_node(&node)?;
+        match check_read(client, doc_id, node) {
+            Ok(()) => {}
+            Err(Error::AccessDenied(_)) => continue,
+            Err(err) => return Err(err),
+        }
+        let parent: Option<Vec<u8>> = row.get(1);
+        let parent = match parent {
+            None => None,
+            Some(b) => Some(bytes_to_node(&b)?),
+        };
+        let deleted_at: Option<Vec<u8>> = row.get(2);
+        out.push(TrashEntry {
+            node,
+            parent: parent.filter(|parent| *parent != NodeId::TRASH),
+            source: deleted_by_from_row(&row, 5).map(|(lamport, id)| MaterializationSource {
+                operation: MaterializationSourceOperation { id, lamport },
+            }),
+            deleted_at: match deleted_at {
+                Some(bytes) => vv_from_bytes(&bytes)?,
+                None => VersionVector::new(),
+            },
+            hard_deleted: row.get(3),
+            payload_preview: row.get(4),
+        });
+    }
+    Ok(out)
+}
+
+pub fn tree_dump(client: &Rc<RefCell<PgClient>>, doc_id: &str) -> Result<Vec<TreeRow>> {
     ensure_materialized(client, doc_id)?;
     let ctx = PgCtx::new(client.clone(), doc_id)?;
     let mut c = client.borrow_mut();
@@ -299,6 +432,7 @@ pub fn tree_dump(client: &Rc<RefCell<Client>>, doc_id: &str) -> Result<Vec<TreeR
          ORDER BY node",
     )?;
     let rows = c.query(&stmt, &[&doc_id]).map_err(storage_debug)?;
+    drop(c);
 
     let mut out = Vec::with_capacity(rows.len());
     for row in rows {
@@ -316,15 +450,64 @@ pub fn tree_dump(client: &Rc<RefCell<Client>>, doc_id: &str) -> Result<Vec<TreeR
             tombstone,
         });
     }
+    retain_readable(client, doc_id, &mut out, |row| row.node)?;
     Ok(out)
 }
 
+/// The tree of `doc_id` as it was at `cut`, rebuilt in memory from `treecrdt_ops`.
+///
+/// Unlike [`tree_dump`] this reads only the op log and leaves the materialized tables alone.
+/// Nodes come with their parent, order key and tombstone flag as of `cut`; payloads are the
+/// winners at that point, both resolved with the document's payload mode and orphan policy.
+pub fn tree_dump_at(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    cut: &HistoryCut,
+) -> Result<Checkpoint> {
+    let storage = PgOpStorage::new(PgCtx::new(client.clone(), doc_id)?);
+    let mut state = materialize_at(
+        &storage,
+        None,
+        cut,
+        load_payload_mode(client, doc_id)?,
+        load_orphan_policy(client, doc_id)?,
+    )?;
+    retain_readable(client, doc_id, &mut state.nodes, |row| row.node)?;
+    let kept: HashSet<NodeId> = state.nodes.iter().map(|row| row.node).collect();
+    state.payloads.retain(|row| kept.contains(&row.node));
+    state.fields.retain(|row| kept.contains(&row.node));
+    state.values.retain(|row| kept.contains(&row.node));
+    Ok(state)
+}
+
+/// The coalesced visible changes between the trees of `doc_id` at `from` and `to`, rebuilt from
+/// the op log like [`tree_dump_at`].
+pub fn tree_diff(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    from: &HistoryCut,
+    to: &HistoryCut,
+) -> Result<Vec<MaterializationChange>> {
+    let storage = PgOpStorage::new(PgCtx::new(client.clone(), doc_id)?);
+    let mut changes = diff_between(
+        &storage,
+        None,
+        from,
+        to,
+        load_payload_mode(client, doc_id)?,
+        load_orphan_policy(client, doc_id)?,
+    )?;
+    retain_readable(client, doc_id, &mut changes, MaterializationChange::node)?;
+    Ok(changes)
+}
+
 pub fn tree_payload(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
     node: NodeId,
 ) -> Result<Option<Vec<u8>>> {
     ensure_materialized(client, doc_id)?;
+    check_read(client, doc_id, node)?;
     let ctx = PgCtx::new(client.clone(), doc_id)?;
     let node_bytes = node_to_bytes(node);
     let mut c = client.borrow_mut();
@@ -340,7 +523,41 @@ pub fn tree_payload(
     Ok(payload)
 }
 
-pub fn tree_node_count(client: &Rc<RefCell<Client>>, doc_id: &str) -> Result<u64> {
+/// The set fields of `node`'s payload map as `(field, value)` pairs in field name order.
+pub fn tree_payload_fields(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    node: NodeId,
+) -> Result<Vec<(String, Vec<u8>)>> {
+    ensure_materialized(client, doc_id)?;
+    check_read(client, doc_id, node)?;
+    let ctx = PgCtx::new(client.clone(), doc_id)?;
+    let node_bytes = node_to_bytes(node);
+    let mut c = client.borrow_mut();
+    let stmt = ctx.stmt(
+        &mut c,
+        "SELECT field, value FROM treecrdt_payload_fields \
+         WHERE doc_id = $1 AND node = $2 AND value IS NOT NULL ORDER BY field",
+    )?;
+    let rows = c.query(&stmt, &[&doc_id, &node_bytes.as_slice()]).map_err(storage_debug)?;
+    Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+}
+
+/// The concurrent values of `node`'s payload in op key order, see
+/// [`treecrdt_core::PayloadValue`]. More than one value means the node was edited concurrently;
+/// always empty unless the doc is in [`treecrdt_core::PayloadMode::MultiValue`].
+pub fn tree_payload_values(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    node: NodeId,
+) -> Result<Vec<PayloadValue>> {
+    ensure_materialized(client, doc_id)?;
+    check_read(client, doc_id, node)?;
+    let ctx = PgCtx::new(client.clone(), doc_id)?;
+    PgPayloadStore::new(ctx)?.payload_values(node)
+}
+
+pub fn tree_node_count(client: &Rc<RefCell<PgClient>>, doc_id: &str) -> Result<u64> {
     ensure_materialized(client, doc_id)?;
     let ctx = PgCtx::new(client.clone(), doc_id)?;
     let root_bytes = node_to_bytes(NodeId::ROOT);
@@ -356,7 +573,7 @@ pub fn tree_node_count(client: &Rc<RefCell<Client>>, doc_id: &str) -> Result<u64
 }
 
 pub fn tree_parent(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
     node: NodeId,
 ) -> Result<Option<NodeId>> {
@@ -377,7 +594,7 @@ pub fn tree_parent(
     parent.map(|b| bytes_to_node(&b)).transpose()
 }
 
-pub fn tree_exists(client: &Rc<RefCell<Client>>, doc_id: &str, node: NodeId) -> Result<bool> {
+pub fn tree_exists(client: &Rc<RefCell<PgClient>>, doc_id: &str, node: NodeId) -> Result<bool> {
     ensure_materialized(client, doc_id)?;
     let ctx = PgCtx::new(client.clone(), doc_id)?;
     let node_bytes = node_to_bytes(node);
@@ -390,8 +607,193 @@ pub fn tree_exists(client: &Rc<RefCell<Client>>, doc_id: &str, node: NodeId) ->
     Ok(!rows.is_empty())
 }
 
+fn is_tree_root(node: NodeId) -> bool {
+    node == NodeId::ROOT || node == NodeId::LOST_AND_FOUND
+}
+
+/// Parent chain of `node` in one recursive query; `None` if it is not visible, as in
+/// [`treecrdt_core::navigation::ancestors`].
+fn visible_ancestors(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    node: NodeId,
+) -> Result<Option<Vec<NodeId>>> {
+    if is_tree_root(node) {
+        return Ok(Some(Vec::new()));
+    }
+    let ctx = PgCtx::new(client.clone(), doc_id)?;
+    let node_bytes = node_to_bytes(node);
+    let root_bytes = node_to_bytes(NodeId::ROOT);
+    let lost_and_found_bytes = node_to_bytes(NodeId::LOST_AND_FOUND);
+    let mut c = client.borrow_mut();
+    // UNION rather than UNION ALL, so even a corrupted parent cycle ends the walk.
+    let stmt = ctx.stmt(
+        &mut c,
+        "WITH RECURSIVE up(node, parent, tombstone) AS ( \
+           SELECT node, parent, tombstone FROM treecrdt_nodes WHERE doc_id = $1 AND node = $2 \
+           UNION \
+           SELECT n.node, n.parent, n.tombstone FROM treecrdt_nodes n \
+           JOIN up ON n.doc_id = $1 AND n.node = up.parent \
+           WHERE up.tombstone = FALSE AND up.node <> $3 AND up.node <> $4 \
+         ) \
+         SELECT node, parent, tombstone FROM up",
+    )?;
+    let rows = c
+        .query(
+            &stmt,
+            &[
+                &doc_id,
+                &node_bytes.as_slice(),
+                &root_bytes.as_slice(),
+                &lost_and_found_bytes.as_slice(),
+            ],
+        )
+        .map_err(storage_debug)?;
+    let mut chain = HashMap::with_capacity(rows.len());
+    for row in rows {
+        let row_node: Vec<u8> = row.get(0);
+        let parent: Option<Vec<u8>> = row.get(1);
+        let tombstone: bool = row.get(2);
+        chain.insert(
+            bytes_to_node(&row_node)?,
+            (parent.map(|b| bytes_to_node(&b)).transpose()?, tombstone),
+        );
+    }
+
+    let mut out = Vec::new();
+    let mut current = node;
+    while !is_tree_root(current) {
+        let Some(&(Some(parent), tombstone)) = chain.get(&current) else {
+            return Ok(None);
+        };
+        if current == NodeId::TRASH || tombstone || parent == node || out.contains(&parent) {
+            return Ok(None);
+        }
+        out.push(parent);
+        current = parent;
+    }
+    Ok(Some(out))
+}
+
+/// The parents of `node`, nearest first and ending at its root; empty for roots and for nodes
+/// that are not visible.
+pub fn tree_ancestors(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    node: NodeId,
+) -> Result<Vec<NodeId>> {
+    ensure_materialized(client, doc_id)?;
+    check_read(client, doc_id, node)?;
+    Ok(visible_ancestors(client, doc_id, node)?.unwrap_or_default())
+}
+
+/// Number of parents between `node` and its root; `None` if `node` is not visible.
+pub fn tree_depth(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    node: NodeId,
+) -> Result<Option<usize>> {
+    ensure_materialized(client, doc_id)?;
+    check_read(client, doc_id, node)?;
+    Ok(visible_ancestors(client, doc_id, node)?.map(|ancestors| ancestors.len()))
+}
+
+/// Whether the visible `node` sits somewhere below `ancestor`; a node is not its own descendant.
+pub fn tree_is_descendant(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    node: NodeId,
+    ancestor: NodeId,
+) -> Result<bool> {
+    ensure_materialized(client, doc_id)?;
+    check_read(client, doc_id, node)?;
+    Ok(visible_ancestors(client, doc_id, node)?
+        .is_some_and(|ancestors| ancestors.contains(&ancestor)))
+}
+
+/// The visible nodes below `root` in pre-order, each with its depth below `root` (its children
+/// are at 1), down to `max_depth` if given. Empty if `root` is not visible.
+pub fn tree_descendants(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    root: NodeId,
+    max_depth: Option<usize>,
+) -> Result<Vec<(NodeId, usize)>> {
+    ensure_materialized(client, doc_id)?;
+    check_read(client, doc_id, root)?;
+    if max_depth == Some(0) || visible_ancestors(client, doc_id, root)?.is_none() {
+        return Ok(Vec::new());
+    }
+    let ctx = PgCtx::new(client.clone(), doc_id)?;
+    let root_bytes = node_to_bytes(root);
+    let max_depth = max_depth.map(|max_depth| max_depth.min(i64::MAX as usize) as i64);
+    let mut c = client.borrow_mut();
+    // `path` holds (order_key, node) for every level, and arrays compare element by element with
+    // prefixes first, so sorting by it gives the pre-order walk.
+    let stmt = ctx.stmt(
+        &mut c,
+        "WITH RECURSIVE down(node, depth, path) AS ( \
+           SELECT node, 1::BIGINT, ARRAY[COALESCE(order_key, ''::BYTEA), node] \
+           FROM treecrdt_nodes \
+           WHERE doc_id = $1 AND parent = $2 AND tombstone = FALSE \
+           UNION ALL \
+           SELECT n.node, down.depth + 1, \
+                  down.path || ARRAY[COALESCE(n.order_key, ''::BYTEA), n.node] \
+           FROM treecrdt_nodes n JOIN down ON n.doc_id = $1 AND n.parent = down.node \
+           WHERE n.tombstone = FALSE AND ($3::BIGINT IS NULL OR down.depth < $3::BIGINT) \
+         ) \
+         SELECT node, depth FROM down ORDER BY path",
+    )?;
+    let rows = c
+        .query(&stmt, &[&doc_id, &root_bytes.as_slice(), &max_depth])
+        .map_err(storage_debug)?;
+    let mut out = Vec::with_capacity(rows.len());
+    for row in rows {
+        let node: Vec<u8> = row.get(0);
+        let depth: i64 = row.get(1);
+        out.push((bytes_to_node(&node)?, depth.max(0) as usize));
+    }
+    Ok(out)
+}
+
+/// Position of `node` among its parent's visible children; `None` for roots and for nodes that
+/// are not visible.
+pub fn tree_index_in_parent(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    node: NodeId,
+) -> Result<Option<usize>> {
+    ensure_materialized(client, doc_id)?;
+    check_read(client, doc_id, node)?;
+    let Some(parent) =
+        visible_ancestors(client, doc_id, node)?.and_then(|ancestors| ancestors.first().copied())
+    else {
+        return Ok(None);
+    };
+    let ctx = PgCtx::new(client.clone(), doc_id)?;
+    let node_bytes = node_to_bytes(node);
+    let parent_bytes = node_to_bytes(parent);
+    let mut c = client.borrow_mut();
+    let stmt = ctx.stmt(
+        &mut c,
+        "SELECT COUNT(*) FROM treecrdt_nodes s JOIN treecrdt_nodes n \
+           ON n.doc_id = s.doc_id AND n.node = $2 \
+         WHERE s.doc_id = $1 AND s.parent = $3 AND s.tombstone = FALSE \
+           AND (COALESCE(s.order_key, ''::BYTEA), s.node) \
+             < (COALESCE(n.order_key, ''::BYTEA), n.node)",
+    )?;
+    let rows = c
+        .query(
+            &stmt,
+            &[&doc_id, &node_bytes.as_slice(), &parent_bytes.as_slice()],
+        )
+        .map_err(storage_debug)?;
+    let row = rows.first().ok_or_else(|| Error::Storage("missing COUNT(*) row".into()))?;
+    Ok(Some(row.get::<_, i64>(0).max(0) as usize))
+}
+
 pub fn replica_max_counter(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
     replica: &[u8],
 ) -> Result<u64> {
@@ -404,5 +806,6 @@ pub fn replica_max_counter(
     )?;
     let rows = c.query(&stmt, &[&doc_id, &replica]).map_err(storage_debug)?;
     let row = rows.first().ok_or_else(|| Error::Storage("missing MAX(counter) row".into()))?;
-    Ok(row.get::<_, i64>(0).max(0) as u64)
+    let purged = load_purged_version_vector(&mut c, doc_id)?;
+    Ok((row.get::<_, i64>(0).max(0) as u64).max(purged.get(&ReplicaId::new(replica))))
 }
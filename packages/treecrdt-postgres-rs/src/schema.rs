@@ -17,10 +17,17 @@ CREATE TABLE IF NOT EXISTS treecrdt_ops (
   order_key BYTEA,
   payload BYTEA,
   known_state BYTEA,
+  group_first BIGINT,
+  group_len INTEGER,
+  field TEXT,
   PRIMARY KEY (doc_id, op_ref),
   UNIQUE (doc_id, replica, counter)
 );
 
+ALTER TABLE treecrdt_ops ADD COLUMN IF NOT EXISTS group_first BIGINT;
+ALTER TABLE treecrdt_ops ADD COLUMN IF NOT EXISTS group_len INTEGER;
+ALTER TABLE treecrdt_ops ADD COLUMN IF NOT EXISTS field TEXT;
+
 CREATE INDEX IF NOT EXISTS idx_treecrdt_ops_doc_order
   ON treecrdt_ops (doc_id, lamport, replica, counter);
 
@@ -35,9 +42,20 @@ CREATE TABLE IF NOT EXISTS treecrdt_meta (
   head_seq BIGINT NOT NULL DEFAULT 0,
   replay_lamport BIGINT,
   replay_replica BYTEA,
-  replay_counter BIGINT
+  replay_counter BIGINT,
+  stability_acks BYTEA,
+  payload_mode TEXT,
+  orphan_policy TEXT,
+  purged_version_vector BYTEA,
+  purged_lamport BIGINT NOT NULL DEFAULT 0
 );
 
+ALTER TABLE treecrdt_meta ADD COLUMN IF NOT EXISTS stability_acks BYTEA;
+ALTER TABLE treecrdt_meta ADD COLUMN IF NOT EXISTS payload_mode TEXT;
+ALTER TABLE treecrdt_meta ADD COLUMN IF NOT EXISTS orphan_policy TEXT;
+ALTER TABLE treecrdt_meta ADD COLUMN IF NOT EXISTS purged_version_vector BYTEA;
+ALTER TABLE treecrdt_meta ADD COLUMN IF NOT EXISTS purged_lamport BIGINT NOT NULL DEFAULT 0;
+
 CREATE TABLE IF NOT EXISTS treecrdt_nodes (
   doc_id TEXT NOT NULL,
   node BYTEA NOT NULL,
@@ -46,9 +64,18 @@ CREATE TABLE IF NOT EXISTS treecrdt_nodes (
   tombstone BOOLEAN NOT NULL DEFAULT FALSE,
   last_change BYTEA,
   deleted_at BYTEA,
+  hard_deleted BOOLEAN NOT NULL DEFAULT FALSE,
+  deleted_lamport BIGINT,
+  deleted_replica BYTEA,
+  deleted_counter BIGINT,
   PRIMARY KEY (doc_id, node)
 );
 
+ALTER TABLE treecrdt_nodes ADD COLUMN IF NOT EXISTS hard_deleted BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE treecrdt_nodes ADD COLUMN IF NOT EXISTS deleted_lamport BIGINT;
+ALTER TABLE treecrdt_nodes ADD COLUMN IF NOT EXISTS deleted_replica BYTEA;
+ALTER TABLE treecrdt_nodes ADD COLUMN IF NOT EXISTS deleted_counter BIGINT;
+
 CREATE INDEX IF NOT EXISTS idx_treecrdt_nodes_doc_parent
   ON treecrdt_nodes (doc_id, parent, order_key, node);
 
@@ -62,6 +89,28 @@ CREATE TABLE IF NOT EXISTS treecrdt_payload (
   PRIMARY KEY (doc_id, node)
 );
 
+CREATE TABLE IF NOT EXISTS treecrdt_payload_fields (
+  doc_id TEXT NOT NULL,
+  node BYTEA NOT NULL,
+  field TEXT NOT NULL,
+  value BYTEA,
+  last_lamport BIGINT NOT NULL,
+  last_replica BYTEA NOT NULL,
+  last_counter BIGINT NOT NULL,
+  PRIMARY KEY (doc_id, node, field)
+);
+
+CREATE TABLE IF NOT EXISTS treecrdt_payload_values (
+  doc_id TEXT NOT NULL,
+  node BYTEA NOT NULL,
+  payload BYTEA,
+  lamport BIGINT NOT NULL,
+  replica BYTEA NOT NULL,
+  counter BIGINT NOT NULL,
+  known_state BYTEA NOT NULL,
+  PRIMARY KEY (doc_id, node, replica, counter)
+);
+
 CREATE TABLE IF NOT EXISTS treecrdt_oprefs_children (
   doc_id TEXT NOT NULL,
   parent BYTEA NOT NULL,
@@ -99,6 +148,18 @@ pub fn reset_doc_for_tests(client: &mut Client, doc_id: &str) -> Result<()> {
     client
         .execute("DELETE FROM treecrdt_payload WHERE doc_id = $1", &[&doc_id])
         .map_err(|e| Error::Storage(format!("{e:?}")))?;
+    client
+        .execute(
+            "DELETE FROM treecrdt_payload_fields WHERE doc_id = $1",
+            &[&doc_id],
+        )
+        .map_err(|e| Error::Storage(format!("{e:?}")))?;
+    client
+        .execute(
+            "DELETE FROM treecrdt_payload_values WHERE doc_id = $1",
+            &[&doc_id],
+        )
+        .map_err(|e| Error::Storage(format!("{e:?}")))?;
     client
         .execute("DELETE FROM treecrdt_nodes WHERE doc_id = $1", &[&doc_id])
         .map_err(|e| Error::Storage(format!("{e:?}")))?;
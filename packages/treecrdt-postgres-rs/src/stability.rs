@@ -0,0 +1,97 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use treecrdt_core::{Error, ReplicaId, Result, StabilityTracker, VersionVector};
+
+use crate::client::PgClient;
+use crate::store::{ensure_doc_meta, storage_debug};
+
+pub(crate) fn load_stability(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    for_update: bool,
+) -> Result<StabilityTracker> {
+    ensure_doc_meta(client, doc_id)?;
+    let sql = if for_update {
+        "SELECT stability_acks FROM treecrdt_meta WHERE doc_id = $1 FOR UPDATE"
+    } else {
+        "SELECT stability_acks FROM treecrdt_meta WHERE doc_id = $1 LIMIT 1"
+    };
+    let mut c = client.borrow_mut();
+    let row = c.query_one(sql, &[&doc_id]).map_err(storage_debug)?;
+    match row.get::<_, Option<Vec<u8>>>(0) {
+        Some(bytes) => serde_json::from_slice(&bytes).map_err(|e| Error::Storage(e.to_string())),
+        None => Ok(StabilityTracker::new()),
+    }
+}
+
+fn store_stability(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    tracker: &StabilityTracker,
+) -> Result<()> {
+    let bytes = serde_json::to_vec(tracker).map_err(|e| Error::Storage(e.to_string()))?;
+    let mut c = client.borrow_mut();
+    c.execute(
+        "UPDATE treecrdt_meta SET stability_acks = $2 WHERE doc_id = $1",
+        &[&doc_id, &bytes],
+    )
+    .map_err(storage_debug)?;
+    Ok(())
+}
+
+/// Load the doc's tracker under a row lock, apply `update` and write it back in one transaction.
+fn update_stability<T>(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    update: impl FnOnce(&mut StabilityTracker) -> T,
+) -> Result<(StabilityTracker, T)> {
+    {
+        let mut c = client.borrow_mut();
+        c.batch_execute("BEGIN").map_err(|e| Error::Storage(e.to_string()))?;
+    }
+
+    let res = load_stability(client, doc_id, true).and_then(|mut tracker| {
+        let out = update(&mut tracker);
+        store_stability(client, doc_id, &tracker)?;
+        Ok((tracker, out))
+    });
+
+    match res {
+        Ok(v) => {
+            let mut c = client.borrow_mut();
+            c.batch_execute("COMMIT").map_err(|e| Error::Storage(e.to_string()))?;
+            Ok(v)
+        }
+        Err(e) => {
+            let mut c = client.borrow_mut();
+            let _ = c.batch_execute("ROLLBACK");
+            Err(e)
+        }
+    }
+}
+
+/// Record that `peer` has observed everything in `acked`; returns the doc's new stable frontier.
+pub fn ack_version_vector(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    peer: &ReplicaId,
+    acked: &VersionVector,
+) -> Result<VersionVector> {
+    let (tracker, ()) =
+        update_stability(client, doc_id, |tracker| tracker.acknowledge(peer, acked))?;
+    Ok(tracker.stable_frontier())
+}
+
+/// Stop tracking `peer` for this doc. Returns whether it was tracked.
+pub fn forget_peer(client: &Rc<RefCell<PgClient>>, doc_id: &str, peer: &ReplicaId) -> Result<bool> {
+    let (_, removed) = update_stability(client, doc_id, |tracker| {
+        tracker.remove_peer(peer).is_some()
+    })?;
+    Ok(removed)
+}
+
+/// Greatest lower bound of every tracked peer's acknowledged version vector.
+pub fn stable_frontier(client: &Rc<RefCell<PgClient>>, doc_id: &str) -> Result<VersionVector> {
+    Ok(load_stability(client, doc_id, false)?.stable_frontier())
+}
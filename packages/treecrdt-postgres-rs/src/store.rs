@@ -10,14 +10,20 @@ use postgres::{Client, Row};
 
 use treecrdt_core::{
     Error, ExactNodeStore, ExactPayloadStore, FrontierRewindStorage, Lamport, NodeId, NodeStore,
-    Operation, OperationId, OperationKind, PayloadStore, ReplicaId, Result, Storage,
+    Operation, OperationGroup, OperationId, OperationKind, OrphanPolicy, PayloadFieldEntry,
+    PayloadMode, PayloadStore, PayloadValue, PurgeableNodeStore, ReplicaId, Result, Storage,
     TruncatingParentOpIndex, VersionVector,
 };
 
 use crate::opref::{derive_op_ref_v0, OPREF_V0_WIDTH};
+use crate::orphan_policy::load_orphan_policy;
+use crate::payload_mode::load_payload_mode;
+use crate::purge::load_purged_version_vector;
 
 pub(crate) use self::append::ensure_materialized_in_tx;
-pub use self::append::{append_ops, append_ops_with_materialization_outcome, ensure_materialized};
+pub use self::append::{
+    append_ops, append_ops_grouped, append_ops_with_materialization_outcome, ensure_materialized,
+};
 pub(crate) use self::meta::{
     ensure_doc_meta, load_tree_meta_for_update, set_tree_meta_replay_frontier,
     update_tree_meta_head, PgCtx, TreeMeta,
@@ -49,7 +55,7 @@ pub(crate) fn op_ref_from_bytes(bytes: &[u8]) -> Result<[u8; OPREF_V0_WIDTH]> {
     Ok(arr)
 }
 
-fn vv_to_bytes(vv: &VersionVector) -> Result<Vec<u8>> {
+pub(crate) fn vv_to_bytes(vv: &VersionVector) -> Result<Vec<u8>> {
     serde_json::to_vec(vv).map_err(|e| Error::Storage(e.to_string()))
 }
 
@@ -57,6 +63,20 @@ pub(crate) fn vv_from_bytes(bytes: &[u8]) -> Result<VersionVector> {
     serde_json::from_slice(bytes).map_err(|e| Error::Storage(e.to_string()))
 }
 
+/// The `deleted_lamport, deleted_replica, deleted_counter` columns starting at `first`.
+pub(crate) fn deleted_by_from_row(row: &Row, first: usize) -> Option<(Lamport, OperationId)> {
+    let lamport: Option<i64> = row.get(first);
+    let replica: Option<Vec<u8>> = row.get(first + 1);
+    let counter: Option<i64> = row.get(first + 2);
+    Some((
+        lamport?.max(0) as Lamport,
+        OperationId {
+            replica: ReplicaId(replica.unwrap_or_default()),
+            counter: counter.unwrap_or(0).max(0) as u64,
+        },
+    ))
+}
+
 #[derive(Clone, Debug)]
 struct CachedNodeRow {
     parent: Option<NodeId>,
@@ -64,6 +84,8 @@ struct CachedNodeRow {
     tombstone: bool,
     last_change: Option<Vec<u8>>,
     deleted_at: Option<Vec<u8>>,
+    hard_deleted: bool,
+    deleted_by: Option<(Lamport, OperationId)>,
 }
 
 #[derive(Clone, Debug)]
@@ -77,17 +99,20 @@ struct CachedPayloadRow {
 #[derive(Clone)]
 pub(crate) struct PgNodeStore {
     ctx: PgCtx,
+    orphan_policy: OrphanPolicy,
     cache: Rc<RefCell<HashMap<NodeId, Option<CachedNodeRow>>>>,
     pending_last_change: Rc<RefCell<HashSet<NodeId>>>,
 }
 
 impl PgNodeStore {
-    pub(crate) fn new(ctx: PgCtx) -> Self {
-        Self {
+    pub(crate) fn new(ctx: PgCtx) -> Result<Self> {
+        let orphan_policy = load_orphan_policy(&ctx.client, &ctx.doc_id)?;
+        Ok(Self {
             ctx,
+            orphan_policy,
             cache: Rc::new(RefCell::new(HashMap::new())),
             pending_last_change: Rc::new(RefCell::new(HashSet::new())),
-        }
+        })
     }
 
     fn load_node_row(&self, node: NodeId) -> Result<Option<CachedNodeRow>> {
@@ -96,7 +121,8 @@ impl PgNodeStore {
         let mut c = self.ctx.client.borrow_mut();
         let stmt = self.ctx.stmt(
             &mut c,
-            "SELECT parent, order_key, tombstone, last_change, deleted_at \
+            "SELECT parent, order_key, tombstone, last_change, deleted_at, hard_deleted, \
+             deleted_lamport, deleted_replica, deleted_counter \
              FROM treecrdt_nodes WHERE doc_id = $1 AND node = $2 LIMIT 1",
         )?;
         let rows = c
@@ -116,6 +142,8 @@ impl PgNodeStore {
         let tombstone: bool = row.get(2);
         let last_change: Option<Vec<u8>> = row.get(3);
         let deleted_at: Option<Vec<u8>> = row.get(4);
+        let hard_deleted: bool = row.get(5);
+        let deleted_by = deleted_by_from_row(row, 6);
         if let Some(profile) = &self.ctx.append_profile {
             let elapsed_ms = started_at.elapsed().as_secs_f64() * 1000.0;
             let mut profile = profile.borrow_mut();
@@ -128,6 +156,8 @@ impl PgNodeStore {
             tombstone,
             last_change,
             deleted_at,
+            hard_deleted,
+            deleted_by,
         }))
     }
 
@@ -168,7 +198,9 @@ impl PgNodeStore {
                 }
                 OperationKind::Delete { .. }
                 | OperationKind::Tombstone { .. }
-                | OperationKind::Payload { .. } => {}
+                | OperationKind::Restore { .. }
+                | OperationKind::Payload { .. }
+                | OperationKind::PayloadField { .. } => {}
             }
         }
 
@@ -189,7 +221,8 @@ impl PgNodeStore {
             // pre-apply materialized state before core starts mutating it.
             let stmt = self.ctx.stmt(
                 &mut c,
-                "SELECT node, parent, order_key, tombstone, last_change, deleted_at \
+                "SELECT node, parent, order_key, tombstone, last_change, deleted_at, hard_deleted, \
+                 deleted_lamport, deleted_replica, deleted_counter \
                  FROM treecrdt_nodes \
                  WHERE doc_id = $1 \
                    AND node IN (SELECT DISTINCT i.node FROM unnest($2::bytea[]) AS i(node))",
@@ -209,6 +242,8 @@ impl PgNodeStore {
                 let tombstone: bool = row.get(3);
                 let last_change: Option<Vec<u8>> = row.get(4);
                 let deleted_at: Option<Vec<u8>> = row.get(5);
+                let hard_deleted: bool = row.get(6);
+                let deleted_by = deleted_by_from_row(&row, 7);
                 loaded_nodes.insert(node);
                 cache.insert(
                     node,
@@ -218,6 +253,8 @@ impl PgNodeStore {
                         tombstone,
                         last_change,
                         deleted_at,
+                        hard_deleted,
+                        deleted_by,
                     }),
                 );
             }
@@ -265,6 +302,8 @@ impl PgNodeStore {
                         tombstone: false,
                         last_change: None,
                         deleted_at: None,
+                        hard_deleted: false,
+                        deleted_by: None,
                     }),
                 );
             }
@@ -355,6 +394,8 @@ impl treecrdt_core::NodeStore for PgNodeStore {
                 tombstone: false,
                 last_change: None,
                 deleted_at: None,
+                hard_deleted: false,
+                deleted_by: None,
             }),
         );
         Ok(())
@@ -386,6 +427,8 @@ impl treecrdt_core::NodeStore for PgNodeStore {
                     tombstone: false,
                     last_change: None,
                     deleted_at: None,
+                    hard_deleted: false,
+                    deleted_by: None,
                 }),
             );
         }
@@ -577,6 +620,77 @@ impl treecrdt_core::NodeStore for PgNodeStore {
         Ok(())
     }
 
+    fn hard_deleted(&self, node: NodeId) -> Result<bool> {
+        let Some(row) = self.node_row(node)? else {
+            return Ok(false);
+        };
+        Ok(row.hard_deleted)
+    }
+
+    fn set_hard_deleted(&mut self, node: NodeId, hard_deleted: bool) -> Result<()> {
+        self.ensure_node(node)?;
+        let node_bytes = node_to_bytes(node);
+        let mut c = self.ctx.client.borrow_mut();
+        let stmt = self.ctx.stmt(
+            &mut c,
+            "UPDATE treecrdt_nodes \
+             SET hard_deleted = $3 \
+             WHERE doc_id = $1 AND node = $2",
+        )?;
+        c.execute(
+            &stmt,
+            &[&self.ctx.doc_id, &node_bytes.as_slice(), &hard_deleted],
+        )
+        .map_err(storage_debug)?;
+
+        if let Some(Some(row)) = self.cache.borrow_mut().get_mut(&node) {
+            row.hard_deleted = hard_deleted;
+        }
+        Ok(())
+    }
+
+    fn deleted_by(&self, node: NodeId) -> Result<Option<(Lamport, OperationId)>> {
+        let Some(row) = self.node_row(node)? else {
+            return Ok(None);
+        };
+        Ok(row.deleted_by)
+    }
+
+    fn set_deleted_by(&mut self, node: NodeId, op: Option<(Lamport, OperationId)>) -> Result<()> {
+        self.ensure_node(node)?;
+        let node_bytes = node_to_bytes(node);
+        let lamport = op.as_ref().map(|(lamport, _)| *lamport as i64);
+        let replica = op.as_ref().map(|(_, id)| id.replica.as_bytes());
+        let counter = op.as_ref().map(|(_, id)| id.counter as i64);
+        let mut c = self.ctx.client.borrow_mut();
+        let stmt = self.ctx.stmt(
+            &mut c,
+            "UPDATE treecrdt_nodes \
+             SET deleted_lamport = $3, deleted_replica = $4, deleted_counter = $5 \
+             WHERE doc_id = $1 AND node = $2",
+        )?;
+        c.execute(
+            &stmt,
+            &[
+                &self.ctx.doc_id,
+                &node_bytes.as_slice(),
+                &lamport,
+                &replica,
+                &counter,
+            ],
+        )
+        .map_err(storage_debug)?;
+
+        if let Some(Some(row)) = self.cache.borrow_mut().get_mut(&node) {
+            row.deleted_by = op;
+        }
+        Ok(())
+    }
+
+    fn orphan_policy(&self) -> OrphanPolicy {
+        self.orphan_policy
+    }
+
     fn last_change(&self, node: NodeId) -> Result<VersionVector> {
         let Some(row) = self.node_row(node)? else {
             return Ok(VersionVector::new());
@@ -712,17 +826,38 @@ impl ExactNodeStore for PgNodeStore {
     }
 }
 
+impl PurgeableNodeStore for PgNodeStore {
+    fn remove_node(&mut self, node: NodeId) -> Result<()> {
+        // Children are found by their `parent` column, so deleting the row also detaches it.
+        let node_bytes = node_to_bytes(node);
+        let mut c = self.ctx.client.borrow_mut();
+        let stmt = self.ctx.stmt(
+            &mut c,
+            "DELETE FROM treecrdt_nodes WHERE doc_id = $1 AND node = $2",
+        )?;
+        c.execute(&stmt, &[&self.ctx.doc_id, &node_bytes.as_slice()])
+            .map_err(storage_debug)?;
+
+        self.cache.borrow_mut().insert(node, None);
+        self.pending_last_change.borrow_mut().remove(&node);
+        Ok(())
+    }
+}
+
 pub(crate) struct PgPayloadStore {
     ctx: PgCtx,
+    mode: PayloadMode,
     cache: RefCell<HashMap<NodeId, Option<CachedPayloadRow>>>,
 }
 
 impl PgPayloadStore {
-    pub(crate) fn new(ctx: PgCtx) -> Self {
-        Self {
+    pub(crate) fn new(ctx: PgCtx) -> Result<Self> {
+        let mode = load_payload_mode(&ctx.client, &ctx.doc_id)?;
+        Ok(Self {
             ctx,
+            mode,
             cache: RefCell::new(HashMap::new()),
-        }
+        })
     }
 
     fn load_payload_row(&self, node: NodeId) -> Result<Option<CachedPayloadRow>> {
@@ -775,6 +910,16 @@ impl treecrdt_core::PayloadStore for PgPayloadStore {
         let mut c = self.ctx.client.borrow_mut();
         let stmt = self.ctx.stmt(&mut c, "DELETE FROM treecrdt_payload WHERE doc_id = $1")?;
         c.execute(&stmt, &[&self.ctx.doc_id]).map_err(storage_debug)?;
+        let stmt = self.ctx.stmt(
+            &mut c,
+            "DELETE FROM treecrdt_payload_fields WHERE doc_id = $1",
+        )?;
+        c.execute(&stmt, &[&self.ctx.doc_id]).map_err(storage_debug)?;
+        let stmt = self.ctx.stmt(
+            &mut c,
+            "DELETE FROM treecrdt_payload_values WHERE doc_id = $1",
+        )?;
+        c.execute(&stmt, &[&self.ctx.doc_id]).map_err(storage_debug)?;
         Ok(())
     }
 
@@ -845,6 +990,141 @@ impl treecrdt_core::PayloadStore for PgPayloadStore {
         }
         Ok(())
     }
+
+    fn payload_field(&self, node: NodeId, field: &str) -> Result<Option<PayloadFieldEntry>> {
+        let node_bytes = node_to_bytes(node);
+        let mut c = self.ctx.client.borrow_mut();
+        let stmt = self.ctx.stmt(
+            &mut c,
+            "SELECT field, value, last_lamport, last_replica, last_counter \
+             FROM treecrdt_payload_fields WHERE doc_id = $1 AND node = $2 AND field = $3 LIMIT 1",
+        )?;
+        let rows = c
+            .query(&stmt, &[&self.ctx.doc_id, &node_bytes.as_slice(), &field])
+            .map_err(storage_debug)?;
+        Ok(rows.first().map(row_to_payload_field))
+    }
+
+    fn payload_fields(&self, node: NodeId) -> Result<Vec<PayloadFieldEntry>> {
+        let node_bytes = node_to_bytes(node);
+        let mut c = self.ctx.client.borrow_mut();
+        let stmt = self.ctx.stmt(
+            &mut c,
+            "SELECT field, value, last_lamport, last_replica, last_counter \
+             FROM treecrdt_payload_fields WHERE doc_id = $1 AND node = $2 ORDER BY field",
+        )?;
+        let rows = c
+            .query(&stmt, &[&self.ctx.doc_id, &node_bytes.as_slice()])
+            .map_err(storage_debug)?;
+        Ok(rows.iter().map(row_to_payload_field).collect())
+    }
+
+    fn set_payload_field(&mut self, node: NodeId, entry: PayloadFieldEntry) -> Result<()> {
+        let node_bytes = node_to_bytes(node);
+        let mut c = self.ctx.client.borrow_mut();
+        let stmt = self.ctx.stmt(
+            &mut c,
+            "INSERT INTO treecrdt_payload_fields(doc_id, node, field, value, last_lamport, last_replica, last_counter) VALUES ($1,$2,$3,$4,$5,$6,$7) \
+             ON CONFLICT (doc_id, node, field) DO UPDATE SET value = EXCLUDED.value, last_lamport = EXCLUDED.last_lamport, last_replica = EXCLUDED.last_replica, last_counter = EXCLUDED.last_counter",
+        )?;
+        c.execute(
+            &stmt,
+            &[
+                &self.ctx.doc_id,
+                &node_bytes.as_slice(),
+                &entry.field,
+                &entry.value,
+                &(entry.lamport as i64),
+                &entry.writer.replica.as_bytes(),
+                &(entry.writer.counter as i64),
+            ],
+        )
+        .map_err(storage_debug)?;
+        Ok(())
+    }
+
+    fn mode(&self) -> PayloadMode {
+        self.mode
+    }
+
+    fn payload_values(&self, node: NodeId) -> Result<Vec<PayloadValue>> {
+        if self.mode != PayloadMode::MultiValue {
+            return Ok(Vec::new());
+        }
+        let node_bytes = node_to_bytes(node);
+        let mut c = self.ctx.client.borrow_mut();
+        let stmt = self.ctx.stmt(
+            &mut c,
+            "SELECT payload, lamport, replica, counter, known_state \
+             FROM treecrdt_payload_values WHERE doc_id = $1 AND node = $2 \
+             ORDER BY lamport, replica, counter",
+        )?;
+        let rows = c
+            .query(&stmt, &[&self.ctx.doc_id, &node_bytes.as_slice()])
+            .map_err(storage_debug)?;
+        rows.iter().map(row_to_payload_value).collect()
+    }
+
+    fn set_payload_values(&mut self, node: NodeId, values: Vec<PayloadValue>) -> Result<()> {
+        if self.mode != PayloadMode::MultiValue {
+            return Err(Error::InvalidOperation(
+                "payload mode is not multi_value".into(),
+            ));
+        }
+        let node_bytes = node_to_bytes(node);
+        let mut c = self.ctx.client.borrow_mut();
+        let stmt = self.ctx.stmt(
+            &mut c,
+            "DELETE FROM treecrdt_payload_values WHERE doc_id = $1 AND node = $2",
+        )?;
+        c.execute(&stmt, &[&self.ctx.doc_id, &node_bytes.as_slice()])
+            .map_err(storage_debug)?;
+        let stmt = self.ctx.stmt(
+            &mut c,
+            "INSERT INTO treecrdt_payload_values(doc_id, node, payload, lamport, replica, counter, known_state) VALUES ($1,$2,$3,$4,$5,$6,$7)",
+        )?;
+        for value in &values {
+            let known_state = vv_to_bytes(&value.known_state)?;
+            c.execute(
+                &stmt,
+                &[
+                    &self.ctx.doc_id,
+                    &node_bytes.as_slice(),
+                    &value.payload,
+                    &(value.lamport as i64),
+                    &value.writer.replica.as_bytes(),
+                    &(value.writer.counter as i64),
+                    &known_state,
+                ],
+            )
+            .map_err(storage_debug)?;
+        }
+        Ok(())
+    }
+}
+
+fn row_to_payload_value(row: &Row) -> Result<PayloadValue> {
+    Ok(PayloadValue {
+        payload: row.get(0),
+        lamport: row.get::<_, i64>(1).max(0) as Lamport,
+        writer: OperationId {
+            replica: ReplicaId(row.get(2)),
+            counter: row.get::<_, i64>(3).max(0) as u64,
+        },
+        known_state: vv_from_bytes(&row.get::<_, Vec<u8>>(4))?,
+    })
+}
+
+fn row_to_payload_field(row: &Row) -> PayloadFieldEntry {
+    PayloadFieldEntry {
+        field: row.get(0),
+        value: row.get(1),
+        lamport: row.get::<_, i64>(2).max(0) as Lamport,
+        writer: OperationId {
+            replica: ReplicaId(row.get(3)),
+            counter: row.get::<_, i64>(4).max(0) as u64,
+        },
+    }
 }
 
 impl ExactPayloadStore for PgPayloadStore {
@@ -855,11 +1135,29 @@ impl ExactPayloadStore for PgPayloadStore {
             &mut c,
             "DELETE FROM treecrdt_payload WHERE doc_id = $1 AND node = $2",
         )?;
+        c.execute(&stmt, &[&self.ctx.doc_id, &node_bytes.as_slice()])
+            .map_err(storage_debug)?;
+        let stmt = self.ctx.stmt(
+            &mut c,
+            "DELETE FROM treecrdt_payload_values WHERE doc_id = $1 AND node = $2",
+        )?;
         c.execute(&stmt, &[&self.ctx.doc_id, &node_bytes.as_slice()])
             .map_err(storage_debug)?;
         self.cache.borrow_mut().insert(node, None);
         Ok(())
     }
+
+    fn clear_payload_fields(&mut self, node: NodeId) -> Result<()> {
+        let node_bytes = node_to_bytes(node);
+        let mut c = self.ctx.client.borrow_mut();
+        let stmt = self.ctx.stmt(
+            &mut c,
+            "DELETE FROM treecrdt_payload_fields WHERE doc_id = $1 AND node = $2",
+        )?;
+        c.execute(&stmt, &[&self.ctx.doc_id, &node_bytes.as_slice()])
+            .map_err(storage_debug)?;
+        Ok(())
+    }
 }
 
 pub(crate) struct PgParentOpIndex {
@@ -987,7 +1285,7 @@ impl Storage for PgOpStorage {
         let mut c = self.ctx.client.borrow_mut();
         let stmt = self.ctx.stmt(
             &mut c,
-            "SELECT lamport, replica, counter, kind, parent, node, new_parent, order_key, payload, known_state \
+            "SELECT lamport, replica, counter, kind, parent, node, new_parent, order_key, payload, known_state, group_first, group_len, field \
              FROM treecrdt_ops WHERE doc_id = $1 AND lamport > $2 ORDER BY lamport, replica, counter",
         )?;
         let rows = c.query(&stmt, &[&self.ctx.doc_id, &(lamport as i64)]).map_err(storage_debug)?;
@@ -998,7 +1296,9 @@ impl Storage for PgOpStorage {
         let mut c = self.ctx.client.borrow_mut();
         let stmt = match self.ctx.stmt(
             &mut c,
-            "SELECT COALESCE(MAX(lamport), 0) FROM treecrdt_ops WHERE doc_id = $1",
+            "SELECT GREATEST( \
+               (SELECT COALESCE(MAX(lamport), 0) FROM treecrdt_ops WHERE doc_id = $1), \
+               (SELECT COALESCE(MAX(purged_lamport), 0) FROM treecrdt_meta WHERE doc_id = $1))",
         ) {
             Ok(stmt) => stmt,
             Err(_) => return 0,
@@ -1019,7 +1319,8 @@ impl Storage for PgOpStorage {
         )?;
         let rows = c.query(&stmt, &[&self.ctx.doc_id, &replica.0]).map_err(storage_debug)?;
         let row = rows.first().ok_or_else(|| Error::Storage("missing MAX(counter) row".into()))?;
-        Ok(row.get::<_, i64>(0).max(0) as u64)
+        let purged = load_purged_version_vector(&mut c, &self.ctx.doc_id)?;
+        Ok((row.get::<_, i64>(0).max(0) as u64).max(purged.get(replica)))
     }
 
     fn scan_since(
@@ -1044,7 +1345,7 @@ impl FrontierRewindStorage for PgOpStorage {
         let mut c = self.ctx.client.borrow_mut();
         let stmt = self.ctx.stmt(
             &mut c,
-            "SELECT lamport, replica, counter, kind, parent, node, new_parent, order_key, payload, known_state \
+            "SELECT lamport, replica, counter, kind, parent, node, new_parent, order_key, payload, known_state, group_first, group_len, field \
              FROM treecrdt_ops \
              WHERE doc_id = $1 \
                AND (lamport > $2 OR (lamport = $2 AND (replica > $3 OR (replica = $3 AND counter >= $4)))) \
@@ -1077,7 +1378,7 @@ impl FrontierRewindStorage for PgOpStorage {
         let mut c = self.ctx.client.borrow_mut();
         let stmt = self.ctx.stmt(
             &mut c,
-            "SELECT lamport, replica, counter, kind, parent, node, new_parent, order_key, payload, known_state \
+            "SELECT lamport, replica, counter, kind, parent, node, new_parent, order_key, payload, known_state, group_first, group_len, field \
              FROM treecrdt_ops \
              WHERE doc_id = $1 \
                AND node = $2 \
@@ -1109,7 +1410,7 @@ impl FrontierRewindStorage for PgOpStorage {
         let mut c = self.ctx.client.borrow_mut();
         let stmt = self.ctx.stmt(
             &mut c,
-            "SELECT lamport, replica, counter, kind, parent, node, new_parent, order_key, payload, known_state \
+            "SELECT lamport, replica, counter, kind, parent, node, new_parent, order_key, payload, known_state, group_first, group_len, field \
              FROM treecrdt_ops \
              WHERE doc_id = $1 \
                AND node = $2 \
@@ -1150,6 +1451,13 @@ pub(crate) fn row_to_op(row: Row) -> Result<Operation> {
         Some(b) if b.is_empty() => None,
         Some(b) => Some(vv_from_bytes(&b)?),
     };
+    let group_first: Option<i64> = row.get(10);
+    let group_len: Option<i32> = row.get(11);
+    let field: Option<String> = row.get(12);
+    let group = group_first.zip(group_len).map(|(first, len)| OperationGroup {
+        first: first.max(0) as u64,
+        len: len.max(0) as u32,
+    });
 
     let replica_id = ReplicaId(replica);
     let op_id = OperationId::new(&replica_id, counter);
@@ -1157,6 +1465,7 @@ pub(crate) fn row_to_op(row: Row) -> Result<Operation> {
         id: op_id,
         lamport,
         known_state,
+        group,
     };
 
     let kind = match kind.as_str() {
@@ -1188,10 +1497,18 @@ pub(crate) fn row_to_op(row: Row) -> Result<Operation> {
         "tombstone" => OperationKind::Tombstone {
             node: bytes_to_node(&node)?,
         },
+        "restore" => OperationKind::Restore {
+            node: bytes_to_node(&node)?,
+        },
         "payload" => OperationKind::Payload {
             node: bytes_to_node(&node)?,
             payload,
         },
+        "payload_field" => OperationKind::PayloadField {
+            node: bytes_to_node(&node)?,
+            field: field.ok_or_else(|| Error::Storage("payload_field op missing field".into()))?,
+            value: payload,
+        },
         other => return Err(Error::Storage(format!("unknown op kind: {other}"))),
     };
 
@@ -1214,6 +1531,13 @@ pub(crate) fn row_to_op_at(row: &Row, base: usize) -> Result<Operation> {
         Some(b) if b.is_empty() => None,
         Some(b) => Some(vv_from_bytes(&b)?),
     };
+    let group_first: Option<i64> = row.get(base + 10);
+    let group_len: Option<i32> = row.get(base + 11);
+    let field: Option<String> = row.get(base + 12);
+    let group = group_first.zip(group_len).map(|(first, len)| OperationGroup {
+        first: first.max(0) as u64,
+        len: len.max(0) as u32,
+    });
 
     let replica_id = ReplicaId(replica);
     let op_id = OperationId::new(&replica_id, counter);
@@ -1221,6 +1545,7 @@ pub(crate) fn row_to_op_at(row: &Row, base: usize) -> Result<Operation> {
         id: op_id,
         lamport,
         known_state,
+        group,
     };
 
     let kind = match kind.as_str() {
@@ -1252,10 +1577,18 @@ pub(crate) fn row_to_op_at(row: &Row, base: usize) -> Result<Operation> {
         "tombstone" => OperationKind::Tombstone {
             node: bytes_to_node(&node)?,
         },
+        "restore" => OperationKind::Restore {
+            node: bytes_to_node(&node)?,
+        },
         "payload" => OperationKind::Payload {
             node: bytes_to_node(&node)?,
             payload,
         },
+        "payload_field" => OperationKind::PayloadField {
+            node: bytes_to_node(&node)?,
+            field: field.ok_or_else(|| Error::Storage("payload_field op missing field".into()))?,
+            value: payload,
+        },
         other => return Err(Error::Storage(format!("unknown op kind: {other}"))),
     };
 
@@ -1270,6 +1603,7 @@ struct OpDbFields {
     order_key: Option<Vec<u8>>,
     payload: Option<Vec<u8>>,
     known_state: Option<Vec<u8>>,
+    field: Option<String>,
 }
 
 fn op_kind_to_db(op: &Operation) -> Result<OpDbFields> {
@@ -1292,6 +1626,7 @@ fn op_kind_to_db(op: &Operation) -> Result<OpDbFields> {
             order_key: Some(order_key.clone()),
             payload: payload.clone(),
             known_state,
+            field: None,
         }),
         OperationKind::Move {
             node,
@@ -1305,6 +1640,7 @@ fn op_kind_to_db(op: &Operation) -> Result<OpDbFields> {
             order_key: Some(order_key.clone()),
             payload: None,
             known_state,
+            field: None,
         }),
         OperationKind::Delete { node } => {
             let Some(vv) = op.meta.known_state.as_ref() else {
@@ -1326,6 +1662,7 @@ fn op_kind_to_db(op: &Operation) -> Result<OpDbFields> {
                 order_key: None,
                 payload: None,
                 known_state: Some(bytes),
+                field: None,
             })
         }
         OperationKind::Tombstone { node } => Ok(OpDbFields {
@@ -1336,6 +1673,17 @@ fn op_kind_to_db(op: &Operation) -> Result<OpDbFields> {
             order_key: None,
             payload: None,
             known_state,
+            field: None,
+        }),
+        OperationKind::Restore { node } => Ok(OpDbFields {
+            kind: "restore",
+            parent: None,
+            node: node_to_bytes(*node).to_vec(),
+            new_parent: None,
+            order_key: None,
+            payload: None,
+            known_state: None,
+            field: None,
         }),
         OperationKind::Payload { node, payload } => Ok(OpDbFields {
             kind: "payload",
@@ -1345,6 +1693,17 @@ fn op_kind_to_db(op: &Operation) -> Result<OpDbFields> {
             order_key: None,
             payload: payload.clone(),
             known_state,
+            field: None,
+        }),
+        OperationKind::PayloadField { node, field, value } => Ok(OpDbFields {
+            kind: "payload_field",
+            parent: None,
+            node: node_to_bytes(*node).to_vec(),
+            new_parent: None,
+            order_key: None,
+            payload: value.clone(),
+            known_state,
+            field: Some(field.clone()),
         }),
     }
 }
@@ -1352,13 +1711,16 @@ fn op_kind_to_db(op: &Operation) -> Result<OpDbFields> {
 fn insert_op_in_tx(ctx: &PgCtx, c: &mut Client, op: &Operation) -> Result<bool> {
     let replica = op.meta.id.replica.as_bytes();
     let counter = op.meta.id.counter;
+    if load_purged_version_vector(c, &ctx.doc_id)?.contains(&op.meta.id.replica, counter) {
+        return Ok(false);
+    }
     let op_ref = derive_op_ref_v0(&ctx.doc_id, replica, counter);
     let row = op_kind_to_db(op)?;
 
     let stmt = ctx.stmt(
         c,
-        "INSERT INTO treecrdt_ops (doc_id, op_ref, lamport, replica, counter, kind, parent, node, new_parent, order_key, payload, known_state) \
-         VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12) \
+        "INSERT INTO treecrdt_ops (doc_id, op_ref, lamport, replica, counter, kind, parent, node, new_parent, order_key, payload, known_state, group_first, group_len, field) \
+         VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12,$13,$14,$15) \
          ON CONFLICT (doc_id, op_ref) DO NOTHING",
     )?;
     let inserted = c
@@ -1377,6 +1739,9 @@ fn insert_op_in_tx(ctx: &PgCtx, c: &mut Client, op: &Operation) -> Result<bool>
                 &row.order_key,
                 &row.payload,
                 &row.known_state,
+                &op.meta.group.map(|group| group.first as i64),
+                &op.meta.group.map(|group| group.len as i32),
+                &row.field,
             ],
         )
         .map_err(storage_debug)?;
@@ -1399,8 +1764,15 @@ fn bulk_insert_ops_in_tx(ctx: &PgCtx, c: &mut Client, ops: &[Operation]) -> Resu
     let mut order_keys: Vec<Option<Vec<u8>>> = Vec::with_capacity(ops.len());
     let mut payloads: Vec<Option<Vec<u8>>> = Vec::with_capacity(ops.len());
     let mut known_states: Vec<Option<Vec<u8>>> = Vec::with_capacity(ops.len());
+    let mut group_firsts: Vec<Option<i64>> = Vec::with_capacity(ops.len());
+    let mut group_lens: Vec<Option<i32>> = Vec::with_capacity(ops.len());
+    let mut fields: Vec<Option<String>> = Vec::with_capacity(ops.len());
 
+    let purged = load_purged_version_vector(c, &ctx.doc_id)?;
     for op in ops {
+        if purged.contains(&op.meta.id.replica, op.meta.id.counter) {
+            continue;
+        }
         let replica = op.meta.id.replica.as_bytes();
         let counter = op.meta.id.counter;
         let op_ref = derive_op_ref_v0(&ctx.doc_id, replica, counter);
@@ -1417,14 +1789,17 @@ fn bulk_insert_ops_in_tx(ctx: &PgCtx, c: &mut Client, ops: &[Operation]) -> Resu
         order_keys.push(row.order_key);
         payloads.push(row.payload);
         known_states.push(row.known_state);
+        group_firsts.push(op.meta.group.map(|group| group.first as i64));
+        group_lens.push(op.meta.group.map(|group| group.len as i32));
+        fields.push(row.field);
     }
 
     let stmt = ctx.stmt(
         c,
-        "INSERT INTO treecrdt_ops (doc_id, op_ref, lamport, replica, counter, kind, parent, node, new_parent, order_key, payload, known_state) \
+        "INSERT INTO treecrdt_ops (doc_id, op_ref, lamport, replica, counter, kind, parent, node, new_parent, order_key, payload, known_state, group_first, group_len, field) \
          SELECT \
            $1, \
-           src.op_ref, src.lamport, src.replica, src.counter, src.kind, src.parent, src.node, src.new_parent, src.order_key, src.payload, src.known_state \
+           src.op_ref, src.lamport, src.replica, src.counter, src.kind, src.parent, src.node, src.new_parent, src.order_key, src.payload, src.known_state, src.group_first, src.group_len, src.field \
          FROM unnest( \
            $2::bytea[], \
            $3::bigint[], \
@@ -1436,8 +1811,11 @@ fn bulk_insert_ops_in_tx(ctx: &PgCtx, c: &mut Client, ops: &[Operation]) -> Resu
            $9::bytea[], \
            $10::bytea[], \
            $11::bytea[], \
-           $12::bytea[] \
-         ) AS src(op_ref, lamport, replica, counter, kind, parent, node, new_parent, order_key, payload, known_state) \
+           $12::bytea[], \
+           $13::bigint[], \
+           $14::integer[], \
+           $15::text[] \
+         ) AS src(op_ref, lamport, replica, counter, kind, parent, node, new_parent, order_key, payload, known_state, group_first, group_len, field) \
          ON CONFLICT (doc_id, op_ref) DO NOTHING \
          RETURNING op_ref",
     )?;
@@ -1458,6 +1836,9 @@ fn bulk_insert_ops_in_tx(ctx: &PgCtx, c: &mut Client, ops: &[Operation]) -> Resu
                 &order_keys,
                 &payloads,
                 &known_states,
+                &group_firsts,
+                &group_lens,
+                &fields,
             ],
         )
         .map_err(storage_debug)?;
@@ -2,15 +2,15 @@ use std::cell::RefCell;
 use std::rc::Rc;
 use std::time::Instant;
 
-use postgres::Client;
-
 use treecrdt_core::{
     catch_up_materialized_state, materialize_persisted_remote_ops_with_delta,
     orchestrate_persisted_remote_append, try_direct_rewind_catch_up_materialized_state, Error,
-    LamportClock, MaterializationCursor, MaterializationHead, MaterializationOutcome, Operation,
-    OperationKind, PersistedRemoteStores, ReplicaId, Result,
+    GroupBuffer, LamportClock, MaterializationCursor, MaterializationHead, MaterializationOutcome,
+    Operation, OperationKind, PersistedRemoteStores, ReplicaId, Result,
 };
 
+use crate::access::check_ops;
+use crate::client::PgClient;
 use crate::profile::{append_profile_enabled, PgAppendProfile};
 
 use super::meta::load_tree_meta;
@@ -28,14 +28,19 @@ fn materialize_inserted_ops(
             // Scratch identity for the temporary TreeCrdt; replayed ops keep their own ids.
             replica_id: ReplicaId::new(b"postgres"),
             clock: LamportClock::default(),
-            nodes: PgNodeStore::new(ctx.clone()),
-            payloads: PgPayloadStore::new(ctx.clone()),
+            nodes: PgNodeStore::new(ctx.clone())?,
+            payloads: PgPayloadStore::new(ctx.clone())?,
             index: PgParentOpIndex::new(ctx.clone()),
         },
         &meta,
         ops,
         |nodes, ops| {
-            if ops.iter().any(|op| matches!(op.kind, OperationKind::Payload { .. })) {
+            if ops.iter().any(|op| {
+                matches!(
+                    op.kind,
+                    OperationKind::Payload { .. } | OperationKind::PayloadField { .. }
+                )
+            }) {
                 // Payload ops can depend on the current node row, so front-load the reads here.
                 nodes.preload_for_ops(ops)?;
             }
@@ -46,7 +51,7 @@ fn materialize_inserted_ops(
     )
 }
 
-pub fn append_ops(client: &Rc<RefCell<Client>>, doc_id: &str, ops: &[Operation]) -> Result<u64> {
+pub fn append_ops(client: &Rc<RefCell<PgClient>>, doc_id: &str, ops: &[Operation]) -> Result<u64> {
     {
         let mut c = client.borrow_mut();
         c.batch_execute("BEGIN").map_err(|e| Error::Storage(e.to_string()))?;
@@ -69,7 +74,7 @@ pub fn append_ops(client: &Rc<RefCell<Client>>, doc_id: &str, ops: &[Operation])
 }
 
 pub fn append_ops_with_materialization_outcome(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
     ops: &[Operation],
 ) -> Result<MaterializationOutcome> {
@@ -94,6 +99,64 @@ pub fn append_ops_with_materialization_outcome(
     }
 }
 
+/// [`append_ops_with_materialization_outcome`] with grouped ops routed through `buffer`, so
+/// members of an op group are only appended once the whole group has arrived. `now` is the time
+/// `buffer`'s [`treecrdt_core::PartialGroupPolicy`] is measured against.
+///
+/// `buffer` lives in the caller (one per doc and connection); it is left untouched when the
+/// append fails.
+pub fn append_ops_grouped(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    ops: Vec<Operation>,
+    buffer: &mut GroupBuffer,
+    now: u64,
+) -> Result<MaterializationOutcome> {
+    {
+        let mut c = client.borrow_mut();
+        c.batch_execute("BEGIN").map_err(|e| Error::Storage(e.to_string()))?;
+    }
+
+    let snapshot = buffer.clone();
+    let res = admit_grouped_in_tx(client, doc_id, ops, buffer, now)
+        .and_then(|ready| append_ops_in_tx(client, doc_id, &ready));
+
+    match res {
+        Ok(v) => {
+            let mut c = client.borrow_mut();
+            c.batch_execute("COMMIT").map_err(|e| Error::Storage(e.to_string()))?;
+            Ok(v.outcome)
+        }
+        Err(e) => {
+            *buffer = snapshot;
+            let mut c = client.borrow_mut();
+            let _ = c.batch_execute("ROLLBACK");
+            Err(e)
+        }
+    }
+}
+
+fn admit_grouped_in_tx(
+    client: &Rc<RefCell<PgClient>>,
+    doc_id: &str,
+    ops: Vec<Operation>,
+    buffer: &mut GroupBuffer,
+    now: u64,
+) -> Result<Vec<Operation>> {
+    let ctx = PgCtx::new(client.clone(), doc_id)?;
+    buffer.admit(ops, now, |replica, counter| {
+        let mut c = client.borrow_mut();
+        let stmt = ctx.stmt(
+            &mut c,
+            "SELECT 1 FROM treecrdt_ops WHERE doc_id = $1 AND replica = $2 AND counter = $3",
+        )?;
+        let rows = c
+            .query(&stmt, &[&doc_id, &replica.as_bytes(), &(counter as i64)])
+            .map_err(storage_debug)?;
+        Ok(!rows.is_empty())
+    })
+}
+
 #[derive(Default)]
 struct AppendOpsResult {
     inserted_count: u64,
@@ -101,10 +164,11 @@ struct AppendOpsResult {
 }
 
 fn append_ops_in_tx(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
     ops: &[Operation],
 ) -> Result<AppendOpsResult> {
+    check_ops(client, doc_id, ops)?;
     // Serialize per-doc writers across all server instances (incremental materialization updates
     // derived tables + head_seq and is not safe to run concurrently for the same doc_id).
     let meta = load_tree_meta_for_update(client, doc_id)?;
@@ -147,13 +211,12 @@ fn append_ops_in_tx(
         update_head_ms += started_at.elapsed().as_secs_f64() * 1000.0;
         result
     };
+    let payloads = PgPayloadStore::new(ctx.clone())?;
     let apply_result = orchestrate_persisted_remote_append(
         &meta,
         inserted_ops,
-        {
-            let payloads = PgPayloadStore::new(ctx.clone());
-            move |node| payloads.last_writer(node)
-        },
+        payloads.mode(),
+        move |node| payloads.last_writer(node),
         |meta, inserted| materialize_inserted_ops(ctx.clone(), meta, inserted),
         &mut update_head,
         |frontier| set_tree_meta_replay_frontier(client, doc_id, frontier),
@@ -165,8 +228,8 @@ fn append_ops_in_tx(
                 PersistedRemoteStores {
                     replica_id: ReplicaId::new(b"postgres"),
                     clock: LamportClock::default(),
-                    nodes: PgNodeStore::new(ctx.clone()),
-                    payloads: PgPayloadStore::new(ctx.clone()),
+                    nodes: PgNodeStore::new(ctx.clone())?,
+                    payloads: PgPayloadStore::new(ctx.clone())?,
                     index: PgParentOpIndex::new(ctx.clone()),
                 },
                 &meta,
@@ -180,8 +243,8 @@ fn append_ops_in_tx(
                 PersistedRemoteStores {
                     replica_id: ReplicaId::new(b"postgres"),
                     clock: LamportClock::default(),
-                    nodes: PgNodeStore::new(ctx.clone()),
-                    payloads: PgPayloadStore::new(ctx.clone()),
+                    nodes: PgNodeStore::new(ctx.clone())?,
+                    payloads: PgPayloadStore::new(ctx.clone())?,
                     index: PgParentOpIndex::new(ctx.clone()),
                 },
                 &meta,
@@ -212,7 +275,7 @@ fn append_ops_in_tx(
 }
 
 pub fn ensure_materialized(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
 ) -> Result<MaterializationOutcome> {
     {
@@ -237,7 +300,7 @@ pub fn ensure_materialized(
 }
 
 pub(crate) fn ensure_materialized_in_tx(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
 ) -> Result<MaterializationOutcome> {
     let meta = load_tree_meta(client, doc_id)?;
@@ -258,8 +321,8 @@ pub(crate) fn ensure_materialized_in_tx(
         PersistedRemoteStores {
             replica_id: ReplicaId::new(b"postgres"),
             clock: LamportClock::default(),
-            nodes: PgNodeStore::new(ctx.clone()),
-            payloads: PgPayloadStore::new(ctx.clone()),
+            nodes: PgNodeStore::new(ctx.clone())?,
+            payloads: PgPayloadStore::new(ctx.clone())?,
             index: PgParentOpIndex::new(ctx.clone()),
         },
         &meta,
@@ -9,6 +9,7 @@ use treecrdt_core::{
     MaterializationKey, MaterializationState, Result,
 };
 
+use crate::client::PgClient;
 use crate::profile::PgAppendProfile;
 
 use super::storage_debug;
@@ -22,7 +23,7 @@ impl MaterializationCursor for TreeMeta {
     }
 }
 
-pub(crate) fn ensure_doc_meta(client: &Rc<RefCell<Client>>, doc_id: &str) -> Result<()> {
+pub(crate) fn ensure_doc_meta(client: &Rc<RefCell<PgClient>>, doc_id: &str) -> Result<()> {
     let mut c = client.borrow_mut();
     c.execute(
         "INSERT INTO treecrdt_meta(doc_id) VALUES ($1) ON CONFLICT (doc_id) DO NOTHING",
@@ -33,7 +34,7 @@ pub(crate) fn ensure_doc_meta(client: &Rc<RefCell<Client>>, doc_id: &str) -> Res
 }
 
 fn load_tree_meta_row(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
     for_update: bool,
 ) -> Result<TreeMeta> {
@@ -91,19 +92,19 @@ fn load_tree_meta_row(
     Ok(TreeMeta(MaterializationState { head, replay_from }))
 }
 
-pub(super) fn load_tree_meta(client: &Rc<RefCell<Client>>, doc_id: &str) -> Result<TreeMeta> {
+pub(super) fn load_tree_meta(client: &Rc<RefCell<PgClient>>, doc_id: &str) -> Result<TreeMeta> {
     load_tree_meta_row(client, doc_id, false)
 }
 
 pub(crate) fn load_tree_meta_for_update(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
 ) -> Result<TreeMeta> {
     load_tree_meta_row(client, doc_id, true)
 }
 
 pub(crate) fn set_tree_meta_replay_frontier(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
     frontier: &MaterializationFrontier,
 ) -> Result<()> {
@@ -125,7 +126,7 @@ pub(crate) fn set_tree_meta_replay_frontier(
 }
 
 pub(crate) fn update_tree_meta_head<R: AsRef<[u8]>>(
-    client: &Rc<RefCell<Client>>,
+    client: &Rc<RefCell<PgClient>>,
     doc_id: &str,
     head: Option<&MaterializationHead<R>>,
 ) -> Result<()> {
@@ -165,18 +166,18 @@ pub(crate) fn update_tree_meta_head<R: AsRef<[u8]>>(
 #[derive(Clone)]
 pub(crate) struct PgCtx {
     pub(crate) doc_id: String,
-    pub(crate) client: Rc<RefCell<Client>>,
+    pub(crate) client: Rc<RefCell<PgClient>>,
     stmts: Rc<RefCell<HashMap<&'static str, Statement>>>,
     pub(super) append_profile: Option<Rc<RefCell<PgAppendProfile>>>,
 }
 
 impl PgCtx {
-    pub(crate) fn new(client: Rc<RefCell<Client>>, doc_id: &str) -> Result<Self> {
+    pub(crate) fn new(client: Rc<RefCell<PgClient>>, doc_id: &str) -> Result<Self> {
         Self::new_with_profile(client, doc_id, None)
     }
 
     pub(super) fn new_with_profile(
-        client: Rc<RefCell<Client>>,
+        client: Rc<RefCell<PgClient>>,
         doc_id: &str,
         append_profile: Option<Rc<RefCell<PgAppendProfile>>>,
     ) -> Result<Self> {
@@ -5,25 +5,35 @@ use std::sync::OnceLock;
 use postgres::{Client, NoTls};
 use uuid::Uuid;
 
-use treecrdt_core::{MaterializationOutcome, NodeId, Operation, ReplicaId, VersionVector};
+use treecrdt_core::{
+    AccessControl, GroupBuffer, HistoryCut, LocalEdit, LocalPlacement, MaterializationChange,
+    MaterializationOutcome, NodeId, NodeStore, Operation, OperationGroup, OrphanPolicy,
+    PartialGroupPolicy, PayloadMode, ReplicaId, UndoManager, VersionVector,
+};
 use treecrdt_postgres::{
-    append_ops, append_ops_with_materialization_outcome, ensure_materialized, ensure_schema,
-    get_ops_by_op_refs, list_op_refs_all, list_op_refs_children, local_delete, local_insert,
-    local_move, local_payload, max_lamport, prepare_local_insert_tx, replica_max_counter,
-    reset_doc_for_tests, tree_children, tree_payload,
+    ack_version_vector, append_ops, append_ops_grouped, append_ops_with_materialization_outcome,
+    ensure_materialized, ensure_schema, forget_peer, get_ops_by_op_refs, list_op_refs_all,
+    list_op_refs_children, local_batch, local_delete, local_duplicate, local_insert,
+    local_insert_many, local_move, local_payload, local_payload_field, local_rebalance,
+    local_reorder, local_restore, local_tombstone, max_lamport, ops_since, prepare_local_insert_tx,
+    purge_stable, redo, replica_max_counter, reset_doc_for_tests, set_access_control,
+    set_orphan_policy, set_payload_mode, stable_frontier, tree_ancestors, tree_children,
+    tree_depth, tree_descendants, tree_diff, tree_dump, tree_dump_at, tree_index_in_parent,
+    tree_is_descendant, tree_order_key_stats, tree_payload, tree_payload_fields,
+    tree_payload_values, tree_trash_page, undo, PgClient,
 };
 use treecrdt_test_support::{
     self as materialization_conformance, node, order_key_from_position,
     MaterializationConformanceHarness,
 };
 
-fn connect() -> Option<Rc<RefCell<Client>>> {
+fn connect() -> Option<Rc<RefCell<PgClient>>> {
     let url = std::env::var("TREECRDT_POSTGRES_URL").ok()?;
     let client = Client::connect(&url, NoTls).ok()?;
-    Some(Rc::new(RefCell::new(client)))
+    Some(Rc::new(RefCell::new(PgClient::from(client))))
 }
 
-fn ensure_schema_once(client: &Rc<RefCell<Client>>) {
+fn ensure_schema_once(client: &Rc<RefCell<PgClient>>) {
     static ONCE: OnceLock<()> = OnceLock::new();
     ONCE.get_or_init(|| {
         let mut c = client.borrow_mut();
@@ -31,7 +41,7 @@ fn ensure_schema_once(client: &Rc<RefCell<Client>>) {
     });
 }
 
-fn op_count(client: &Rc<RefCell<Client>>, doc_id: &str) -> u64 {
+fn op_count(client: &Rc<RefCell<PgClient>>, doc_id: &str) -> u64 {
     let mut c = client.borrow_mut();
     let row = c
         .query_one(
@@ -43,7 +53,7 @@ fn op_count(client: &Rc<RefCell<Client>>, doc_id: &str) -> u64 {
 }
 
 struct PgConformanceHarness {
-    client: Rc<RefCell<Client>>,
+    client: Rc<RefCell<PgClient>>,
     doc_id: String,
 }
 
@@ -134,7 +144,9 @@ impl MaterializationConformanceHarness for PgConformanceHarness {
                 treecrdt_core::OperationKind::Move { .. } => "move",
                 treecrdt_core::OperationKind::Delete { .. } => "delete",
                 treecrdt_core::OperationKind::Tombstone { .. } => "tombstone",
+                treecrdt_core::OperationKind::Restore { .. } => "restore",
                 treecrdt_core::OperationKind::Payload { .. } => "payload",
+                treecrdt_core::OperationKind::PayloadField { .. } => "payload_field",
             })
             .map(str::to_string)
             .collect()
@@ -744,3 +756,1264 @@ fn postgres_backend_prepared_local_tx_rolls_back_until_committed() {
         Some(vec![7])
     );
 }
+
+struct DenyNode(NodeId);
+
+impl AccessControl for DenyNode {
+    fn can_apply(&self, _nodes: &dyn NodeStore, op: &Operation) -> treecrdt_core::Result<()> {
+        if op.kind.node() == self.0 {
+            return Err(treecrdt_core::Error::AccessDenied(
+                "node is fenced off".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn can_read(&self, _nodes: &dyn NodeStore, node: NodeId) -> treecrdt_core::Result<()> {
+        if node == self.0 {
+            return Err(treecrdt_core::Error::AccessDenied(
+                "node is fenced off".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn postgres_backend_access_control_denies_appends_local_ops_and_reads() {
+    let Some(client) = connect() else {
+        return;
+    };
+    ensure_schema_once(&client);
+
+    let doc_id = format!("test-{}", Uuid::new_v4());
+    {
+        let mut c = client.borrow_mut();
+        reset_doc_for_tests(&mut c, &doc_id).unwrap();
+    }
+
+    let replica = ReplicaId::new(b"acl");
+    let allowed = node(1201);
+    let fenced = node(1202);
+    append_ops(
+        &client,
+        &doc_id,
+        &[Operation::insert(
+            &replica,
+            1,
+            1,
+            NodeId::ROOT,
+            allowed,
+            order_key_from_position(0),
+        )],
+    )
+    .unwrap();
+
+    set_access_control(&client, Some(Rc::new(DenyNode(fenced))));
+    let denied_append = append_ops(
+        &client,
+        &doc_id,
+        &[Operation::insert(
+            &replica,
+            2,
+            2,
+            NodeId::ROOT,
+            fenced,
+            order_key_from_position(1),
+        )],
+    );
+    let denied_local = local_insert(
+        &client,
+        &doc_id,
+        &replica,
+        NodeId::ROOT,
+        fenced,
+        "last",
+        None,
+        None,
+    );
+    let denied_children = tree_children(&client, &doc_id, fenced);
+    let denied_payload = tree_payload(&client, &doc_id, fenced);
+    let allowed_children = tree_children(&client, &doc_id, NodeId::ROOT).unwrap();
+    // The policy belongs to its client; other connections are not restricted by it.
+    let other = connect().unwrap();
+    assert!(tree_children(&other, &doc_id, fenced).unwrap().is_empty());
+    set_access_control(&client, None);
+
+    assert!(matches!(
+        denied_append,
+        Err(treecrdt_core::Error::AccessDenied(_))
+    ));
+    assert!(matches!(
+        denied_local,
+        Err(treecrdt_core::Error::AccessDenied(_))
+    ));
+    assert!(matches!(
+        denied_children,
+        Err(treecrdt_core::Error::AccessDenied(_))
+    ));
+    assert!(matches!(
+        denied_payload,
+        Err(treecrdt_core::Error::AccessDenied(_))
+    ));
+    assert_eq!(allowed_children, vec![allowed]);
+    assert_eq!(op_count(&client, &doc_id), 1);
+}
+
+#[test]
+fn postgres_backend_access_control_filters_bulk_reads() {
+    let Some(client) = connect() else {
+        return;
+    };
+    ensure_schema_once(&client);
+
+    let doc_id = format!("test-{}", Uuid::new_v4());
+    {
+        let mut c = client.borrow_mut();
+        reset_doc_for_tests(&mut c, &doc_id).unwrap();
+    }
+
+    let replica = ReplicaId::new(b"acl");
+    let allowed = node(1211);
+    let fenced = node(1212);
+    let ops: Vec<Operation> = [allowed, fenced]
+        .into_iter()
+        .enumerate()
+        .map(|(i, node)| {
+            let seq = i as u64 + 1;
+            Operation::insert(
+                &replica,
+                seq,
+                seq,
+                NodeId::ROOT,
+                node,
+                order_key_from_position(i as u16),
+            )
+        })
+        .collect();
+    append_ops(&client, &doc_id, &ops).unwrap();
+    let mut now = VersionVector::new();
+    for counter in 1..=2 {
+        now.observe(&replica, counter);
+    }
+
+    set_access_control(&client, Some(Rc::new(DenyNode(fenced))));
+    let since = ops_since(&client, &doc_id, 0, None).unwrap();
+    let dump = tree_dump(&client, &doc_id).unwrap();
+    let past = tree_dump_at(&client, &doc_id, &HistoryCut::Version(now.clone())).unwrap();
+    let changes = tree_diff(
+        &client,
+        &doc_id,
+        &HistoryCut::Version(VersionVector::new()),
+        &HistoryCut::Version(now),
+    )
+    .unwrap();
+    set_access_control(&client, None);
+
+    assert_eq!(
+        since.iter().map(|op| op.kind.node()).collect::<Vec<_>>(),
+        vec![allowed]
+    );
+    assert!(dump.iter().all(|row| row.node != fenced));
+    assert!(dump.iter().any(|row| row.node == allowed));
+    assert!(past.nodes.iter().all(|row| row.node != fenced));
+    assert!(past.nodes.iter().any(|row| row.node == allowed));
+    assert_eq!(
+        changes.iter().map(MaterializationChange::node).collect::<Vec<_>>(),
+        vec![allowed]
+    );
+    assert_eq!(ops_since(&client, &doc_id, 0, None).unwrap().len(), 2);
+}
+
+#[test]
+fn postgres_backend_stability_acks_persist_per_doc() {
+    let Some(client) = connect() else {
+        return;
+    };
+    ensure_schema_once(&client);
+
+    let doc_id = format!("test-{}", Uuid::new_v4());
+    let other_doc = format!("test-{}", Uuid::new_v4());
+    {
+        let mut c = client.borrow_mut();
+        reset_doc_for_tests(&mut c, &doc_id).unwrap();
+        reset_doc_for_tests(&mut c, &other_doc).unwrap();
+    }
+
+    let a = ReplicaId::new(b"a");
+    let b = ReplicaId::new(b"b");
+    let mut a_vv = VersionVector::new();
+    a_vv.observe(&a, 1);
+    a_vv.observe(&a, 2);
+    let mut b_vv = VersionVector::new();
+    b_vv.observe(&a, 1);
+    b_vv.observe(&b, 1);
+
+    assert!(stable_frontier(&client, &doc_id).unwrap().is_empty());
+    ack_version_vector(&client, &doc_id, &a, &a_vv).unwrap();
+    let stable = ack_version_vector(&client, &doc_id, &b, &b_vv).unwrap();
+
+    let mut expected = VersionVector::new();
+    expected.observe(&a, 1);
+    assert_eq!(stable, expected);
+    assert_eq!(stable_frontier(&client, &doc_id).unwrap(), expected);
+    assert!(stable_frontier(&client, &other_doc).unwrap().is_empty());
+
+    assert!(forget_peer(&client, &doc_id, &b).unwrap());
+    assert!(!forget_peer(&client, &doc_id, &b).unwrap());
+    assert_eq!(stable_frontier(&client, &doc_id).unwrap(), a_vv);
+}
+
+#[test]
+fn postgres_backend_purge_stable_drops_acknowledged_deleted_subtrees() {
+    let Some(client) = connect() else {
+        return;
+    };
+    ensure_schema_once(&client);
+
+    let doc_id = format!("test-{}", Uuid::new_v4());
+    {
+        let mut c = client.borrow_mut();
+        reset_doc_for_tests(&mut c, &doc_id).unwrap();
+    }
+
+    let replica = ReplicaId::new(b"purge");
+    let parent = node(1301);
+    let child = node(1302);
+    let sibling = node(1303);
+    local_insert(
+        &client,
+        &doc_id,
+        &replica,
+        NodeId::ROOT,
+        parent,
+        "last",
+        None,
+        None,
+    )
+    .unwrap();
+    let insert_child = local_insert(
+        &client,
+        &doc_id,
+        &replica,
+        parent,
+        child,
+        "last",
+        None,
+        Some(vec![1]),
+    )
+    .unwrap();
+    local_insert(
+        &client,
+        &doc_id,
+        &replica,
+        NodeId::ROOT,
+        sibling,
+        "last",
+        None,
+        None,
+    )
+    .unwrap();
+    let delete = local_delete(&client, &doc_id, &replica, parent).unwrap();
+
+    assert!(purge_stable(&client, &doc_id, false).unwrap().purged.is_empty());
+
+    let mut vv = VersionVector::new();
+    for counter in 1..=delete.meta.id.counter {
+        vv.observe(&replica, counter);
+    }
+    ack_version_vector(&client, &doc_id, &replica, &vv).unwrap();
+
+    let result = purge_stable(&client, &doc_id, true).unwrap();
+    assert_eq!(result.purged.roots, vec![parent]);
+    assert_eq!(result.purged.nodes, vec![parent, child]);
+    assert_eq!(result.purged.payloads, vec![child]);
+    assert_eq!(result.ops, 3);
+    assert_eq!(op_count(&client, &doc_id), 1);
+    assert_eq!(
+        tree_children(&client, &doc_id, NodeId::ROOT).unwrap(),
+        vec![sibling]
+    );
+    assert_eq!(tree_payload(&client, &doc_id, child).unwrap(), None);
+
+    // The dropped ops still count as seen: heads do not fall back and copies sent again are
+    // ignored.
+    assert_eq!(
+        replica_max_counter(&client, &doc_id, replica.as_bytes()).unwrap(),
+        delete.meta.id.counter
+    );
+    assert_eq!(max_lamport(&client, &doc_id).unwrap(), delete.meta.lamport);
+    append_ops(
+        &client,
+        &doc_id,
+        &[insert_child.op.clone(), delete.op.clone()],
+    )
+    .unwrap();
+    assert_eq!(op_count(&client, &doc_id), 1);
+    assert_eq!(
+        tree_children(&client, &doc_id, NodeId::ROOT).unwrap(),
+        vec![sibling]
+    );
+    let next = local_insert(
+        &client,
+        &doc_id,
+        &replica,
+        NodeId::ROOT,
+        node(1304),
+        "last",
+        None,
+        None,
+    )
+    .unwrap();
+    assert_eq!(next.meta.id.counter, delete.meta.id.counter + 1);
+    assert!(next.meta.lamport > delete.meta.lamport);
+}
+
+#[test]
+fn postgres_backend_undo_and_redo_append_inverse_ops() {
+    let Some(client) = connect() else {
+        return;
+    };
+    ensure_schema_once(&client);
+
+    let doc_id = format!("test-{}", Uuid::new_v4());
+    {
+        let mut c = client.borrow_mut();
+        reset_doc_for_tests(&mut c, &doc_id).unwrap();
+    }
+
+    let replica = ReplicaId::new(b"undo");
+    let a = node(1401);
+    let b = node(1402);
+    let mut history = UndoManager::new();
+    history.begin();
+    for n in [a, b] {
+        let res = local_insert(
+            &client,
+            &doc_id,
+            &replica,
+            NodeId::ROOT,
+            n,
+            "last",
+            None,
+            None,
+        )
+        .unwrap();
+        history.record(res.undo);
+    }
+    history.commit();
+    let res = local_move(&client, &doc_id, &replica, b, a, "first", None).unwrap();
+    history.record(res.undo);
+    let res = local_payload(&client, &doc_id, &replica, a, Some(vec![9])).unwrap();
+    history.record(res.undo);
+
+    assert_eq!(
+        undo(&client, &doc_id, &replica, &mut history).unwrap().len(),
+        1
+    );
+    assert_eq!(tree_payload(&client, &doc_id, a).unwrap(), None);
+    assert_eq!(
+        undo(&client, &doc_id, &replica, &mut history).unwrap().len(),
+        1
+    );
+    assert_eq!(
+        tree_children(&client, &doc_id, NodeId::ROOT).unwrap(),
+        vec![a, b]
+    );
+    assert_eq!(
+        undo(&client, &doc_id, &replica, &mut history).unwrap().len(),
+        2
+    );
+    assert!(tree_children(&client, &doc_id, NodeId::ROOT).unwrap().is_empty());
+
+    assert_eq!(
+        redo(&client, &doc_id, &replica, &mut history).unwrap().len(),
+        2
+    );
+    assert_eq!(
+        tree_children(&client, &doc_id, NodeId::ROOT).unwrap(),
+        vec![a, b]
+    );
+    assert_eq!(op_count(&client, &doc_id), 10);
+}
+
+#[test]
+fn postgres_backend_tree_dump_at_rebuilds_past_state() {
+    let Some(client) = connect() else {
+        return;
+    };
+    ensure_schema_once(&client);
+
+    let doc_id = format!("test-{}", Uuid::new_v4());
+    {
+        let mut c = client.borrow_mut();
+        reset_doc_for_tests(&mut c, &doc_id).unwrap();
+    }
+
+    let replica = ReplicaId::new(b"hist");
+    let a = node(1501);
+    let b = node(1502);
+    for n in [a, b] {
+        local_insert(
+            &client,
+            &doc_id,
+            &replica,
+            NodeId::ROOT,
+            n,
+            "last",
+            None,
+            Some(vec![1]),
+        )
+        .unwrap();
+    }
+    local_payload(&client, &doc_id, &replica, a, Some(vec![2])).unwrap();
+    local_delete(&client, &doc_id, &replica, b).unwrap();
+
+    let mut vv = VersionVector::new();
+    for counter in 1..=2 {
+        vv.observe(&replica, counter);
+    }
+    let past = tree_dump_at(&client, &doc_id, &HistoryCut::Version(vv.clone())).unwrap();
+    let rows: Vec<_> = past
+        .nodes
+        .iter()
+        .filter(|row| row.node == a || row.node == b)
+        .map(|row| (row.node, row.parent, row.tombstone))
+        .collect();
+    assert_eq!(
+        rows,
+        vec![
+            (a, Some(NodeId::ROOT), false),
+            (b, Some(NodeId::ROOT), false)
+        ]
+    );
+    assert!(past.payloads.iter().all(|p| p.payload == Some(vec![1])));
+
+    // The live tree is not rewound.
+    assert_eq!(
+        tree_children(&client, &doc_id, NodeId::ROOT).unwrap(),
+        vec![a]
+    );
+    assert_eq!(tree_payload(&client, &doc_id, a).unwrap(), Some(vec![2]));
+
+    let mut now = vv.clone();
+    for counter in 3..=4 {
+        now.observe(&replica, counter);
+    }
+    let changes = tree_diff(
+        &client,
+        &doc_id,
+        &HistoryCut::Version(vv),
+        &HistoryCut::Version(now),
+    )
+    .unwrap();
+    assert!(matches!(
+        changes.as_slice(),
+        [
+            MaterializationChange::Delete { node: deleted, .. },
+            MaterializationChange::Payload { node: renamed, payload: Some(p), .. },
+        ] if *deleted == b && *renamed == a && p == &vec![2]
+    ));
+}
+
+#[test]
+fn postgres_backend_local_batch_commits_all_edits_or_none() {
+    let Some(client) = connect() else {
+        return;
+    };
+    ensure_schema_once(&client);
+
+    let doc_id = format!("test-{}", Uuid::new_v4());
+    {
+        let mut c = client.borrow_mut();
+        reset_doc_for_tests(&mut c, &doc_id).unwrap();
+    }
+
+    let replica = ReplicaId::new(b"batch");
+    let folder = node(1500);
+    let (a, b) = (node(1501), node(1502));
+    for n in [a, b] {
+        local_insert(
+            &client,
+            &doc_id,
+            &replica,
+            NodeId::ROOT,
+            n,
+            "last",
+            None,
+            None,
+        )
+        .unwrap();
+    }
+    let insert_folder = LocalEdit::Insert {
+        parent: NodeId::ROOT,
+        node: folder,
+        placement: LocalPlacement::First,
+        payload: None,
+    };
+
+    // `a` is not a child of `folder`, so the whole batch is rolled back.
+    assert!(local_batch(
+        &client,
+        &doc_id,
+        &replica,
+        vec![
+            insert_folder.clone(),
+            LocalEdit::Move {
+                node: b,
+                new_parent: folder,
+                placement: LocalPlacement::After(a),
+            },
+        ],
+    )
+    .is_err());
+    assert_eq!(
+        tree_children(&client, &doc_id, NodeId::ROOT).unwrap(),
+        vec![a, b]
+    );
+
+    let res = local_batch(
+        &client,
+        &doc_id,
+        &replica,
+        vec![
+            insert_folder,
+            LocalEdit::Payload {
+                node: folder,
+                payload: Some(vec![1]),
+            },
+            LocalEdit::Move {
+                node: b,
+                new_parent: folder,
+                placement: LocalPlacement::Last,
+            },
+            LocalEdit::Move {
+                node: a,
+                new_parent: folder,
+                placement: LocalPlacement::After(b),
+            },
+        ],
+    )
+    .unwrap();
+    let counters: Vec<u64> = res.ops.iter().map(|op| op.meta.id.counter).collect();
+    assert_eq!(counters, vec![3, 4, 5, 6]);
+    assert_eq!(res.outcome.changes.len(), 3);
+    assert_eq!(
+        tree_children(&client, &doc_id, NodeId::ROOT).unwrap(),
+        vec![folder]
+    );
+    assert_eq!(tree_children(&client, &doc_id, folder).unwrap(), vec![b, a]);
+    assert_eq!(
+        tree_payload(&client, &doc_id, folder).unwrap(),
+        Some(vec![1])
+    );
+
+    let mut history = UndoManager::new();
+    history.begin();
+    for record in res.undo {
+        history.record(record);
+    }
+    history.commit();
+    assert_eq!(
+        undo(&client, &doc_id, &replica, &mut history).unwrap().len(),
+        4
+    );
+    assert_eq!(
+        tree_children(&client, &doc_id, NodeId::ROOT).unwrap(),
+        vec![a, b]
+    );
+}
+
+#[test]
+fn postgres_backend_local_insert_many_pastes_a_run_of_siblings() {
+    let Some(client) = connect() else {
+        return;
+    };
+    ensure_schema_once(&client);
+
+    let doc_id = format!("test-{}", Uuid::new_v4());
+    {
+        let mut c = client.borrow_mut();
+        reset_doc_for_tests(&mut c, &doc_id).unwrap();
+    }
+
+    let replica = ReplicaId::new(b"paste");
+    let (first, last) = (node(1700), node(1701));
+    for n in [first, last] {
+        local_insert(
+            &client,
+            &doc_id,
+            &replica,
+            NodeId::ROOT,
+            n,
+            "last",
+            None,
+            None,
+        )
+        .unwrap();
+    }
+
+    let lines: Vec<(NodeId, Option<Vec<u8>>)> =
+        (0..50).map(|i| (node(1710 + i), Some(vec![i as u8]))).collect();
+    let res = local_insert_many(
+        &client,
+        &doc_id,
+        &replica,
+        NodeId::ROOT,
+        LocalPlacement::After(first),
+        lines,
+    )
+    .unwrap();
+    assert_eq!(res.ops.len(), 50);
+    assert_eq!(res.undo.len(), 50);
+
+    let mut expected = vec![first];
+    expected.extend((0..50).map(|i| node(1710 + i)));
+    expected.push(last);
+    assert_eq!(
+        tree_children(&client, &doc_id, NodeId::ROOT).unwrap(),
+        expected
+    );
+    assert_eq!(
+        tree_payload(&client, &doc_id, node(1720)).unwrap(),
+        Some(vec![10])
+    );
+}
+
+#[test]
+fn postgres_backend_rebalance_shortens_order_keys() {
+    let Some(client) = connect() else {
+        return;
+    };
+    ensure_schema_once(&client);
+
+    let doc_id = format!("test-{}", Uuid::new_v4());
+    {
+        let mut c = client.borrow_mut();
+        reset_doc_for_tests(&mut c, &doc_id).unwrap();
+    }
+
+    let replica = ReplicaId::new(b"rekey");
+    let first = node(1800);
+    local_insert(
+        &client,
+        &doc_id,
+        &replica,
+        NodeId::ROOT,
+        first,
+        "last",
+        None,
+        None,
+    )
+    .unwrap();
+    for i in 1..60 {
+        local_insert(
+            &client,
+            &doc_id,
+            &replica,
+            NodeId::ROOT,
+            node(1800 + i),
+            "after",
+            Some(first),
+            None,
+        )
+        .unwrap();
+    }
+    let before = tree_order_key_stats(&client, &doc_id, NodeId::ROOT).unwrap();
+    assert_eq!(before.children, 60);
+    assert!(before.max_len > 8);
+    let order = tree_children(&client, &doc_id, NodeId::ROOT).unwrap();
+
+    let res = local_rebalance(&client, &doc_id, &replica, NodeId::ROOT).unwrap();
+    assert!(!res.ops.is_empty());
+    let after = tree_order_key_stats(&client, &doc_id, NodeId::ROOT).unwrap();
+    assert!(after.max_len <= 4);
+    assert!(after.avg_len() < before.avg_len());
+    assert_eq!(
+        tree_children(&client, &doc_id, NodeId::ROOT).unwrap(),
+        order
+    );
+}
+
+#[test]
+fn postgres_backend_reorder_moves_out_of_place_children() {
+    let Some(client) = connect() else {
+        return;
+    };
+    ensure_schema_once(&client);
+
+    let doc_id = format!("test-{}", Uuid::new_v4());
+    {
+        let mut c = client.borrow_mut();
+        reset_doc_for_tests(&mut c, &doc_id).unwrap();
+    }
+
+    let replica = ReplicaId::new(b"sort");
+    for i in 0..5 {
+        local_insert(
+            &client,
+            &doc_id,
+            &replica,
+            NodeId::ROOT,
+            node(1900 + i),
+            "last",
+            None,
+            None,
+        )
+        .unwrap();
+    }
+
+    let desired = vec![node(1904), node(1900), node(1901), node(1902), node(1903)];
+    let res = local_reorder(&client, &doc_id, &replica, NodeId::ROOT, &desired).unwrap();
+    assert_eq!(res.ops.len(), 1);
+    assert_eq!(
+        tree_children(&client, &doc_id, NodeId::ROOT).unwrap(),
+        desired
+    );
+    let res = local_reorder(&client, &doc_id, &replica, NodeId::ROOT, &desired).unwrap();
+    assert!(res.ops.is_empty());
+    assert!(local_reorder(&client, &doc_id, &replica, NodeId::ROOT, &desired[1..]).is_err());
+}
+
+#[test]
+fn postgres_backend_payload_fields_merge_per_field() {
+    let Some(client) = connect() else {
+        return;
+    };
+    ensure_schema_once(&client);
+
+    let doc_id = format!("test-{}", Uuid::new_v4());
+    {
+        let mut c = client.borrow_mut();
+        reset_doc_for_tests(&mut c, &doc_id).unwrap();
+    }
+
+    let replica = ReplicaId::new(b"fields");
+    let item = node(1950);
+    local_insert(
+        &client,
+        &doc_id,
+        &replica,
+        NodeId::ROOT,
+        item,
+        "last",
+        None,
+        Some(vec![1]),
+    )
+    .unwrap();
+    local_payload_field(
+        &client,
+        &doc_id,
+        &replica,
+        item,
+        "title",
+        Some(b"hi".to_vec()),
+    )
+    .unwrap();
+
+    let remote = ReplicaId::new(b"remote");
+    append_ops(
+        &client,
+        &doc_id,
+        &[
+            Operation::payload_field(&remote, 1, 5, item, "color", Some(b"red".to_vec())),
+            Operation::payload_field(&remote, 2, 1, item, "title", Some(b"old".to_vec())),
+        ],
+    )
+    .unwrap();
+    assert_eq!(
+        tree_payload_fields(&client, &doc_id, item).unwrap(),
+        vec![
+            ("color".to_string(), b"red".to_vec()),
+            ("title".to_string(), b"hi".to_vec()),
+        ]
+    );
+    assert_eq!(tree_payload(&client, &doc_id, item).unwrap(), Some(vec![1]));
+
+    local_payload_field(&client, &doc_id, &replica, item, "color", None).unwrap();
+    assert_eq!(
+        tree_payload_fields(&client, &doc_id, item).unwrap(),
+        vec![("title".to_string(), b"hi".to_vec())]
+    );
+}
+
+#[test]
+fn postgres_backend_multi_value_payloads_keep_concurrent_writes_until_resolved() {
+    let Some(client) = connect() else {
+        return;
+    };
+    ensure_schema_once(&client);
+
+    let doc_id = format!("test-{}", Uuid::new_v4());
+    {
+        let mut c = client.borrow_mut();
+        reset_doc_for_tests(&mut c, &doc_id).unwrap();
+    }
+    set_payload_mode(&client, &doc_id, PayloadMode::MultiValue).unwrap();
+
+    let replica = ReplicaId::new(b"mv");
+    let item = node(1960);
+    let insert = local_insert(
+        &client,
+        &doc_id,
+        &replica,
+        NodeId::ROOT,
+        item,
+        "last",
+        None,
+        Some(b"draft".to_vec()),
+    )
+    .unwrap();
+    let ours = local_payload(&client, &doc_id, &replica, item, Some(b"ours".to_vec())).unwrap();
+    assert!(ours.op.meta.known_state.is_some());
+
+    // A remote write that only saw the insert is concurrent with ours.
+    let mut seen_insert = VersionVector::new();
+    seen_insert.observe(&replica, insert.op.meta.id.counter);
+    let mut theirs =
+        Operation::set_payload(&ReplicaId::new(b"remote"), 1, 2, item, b"theirs".to_vec());
+    theirs.meta.known_state = Some(seen_insert);
+    append_ops(&client, &doc_id, &[theirs]).unwrap();
+
+    let values = |client: &Rc<RefCell<PgClient>>| -> Vec<Option<Vec<u8>>> {
+        tree_payload_values(client, &doc_id, item)
+            .unwrap()
+            .into_iter()
+            .map(|value| value.payload)
+            .collect()
+    };
+    assert_eq!(
+        values(&client),
+        vec![Some(b"ours".to_vec()), Some(b"theirs".to_vec())]
+    );
+    assert_eq!(
+        tree_payload(&client, &doc_id, item).unwrap(),
+        Some(b"theirs".to_vec())
+    );
+
+    local_payload(&client, &doc_id, &replica, item, Some(b"merged".to_vec())).unwrap();
+    assert_eq!(values(&client), vec![Some(b"merged".to_vec())]);
+
+    set_payload_mode(&client, &doc_id, PayloadMode::LastWriterWins).unwrap();
+    assert!(values(&client).is_empty());
+    assert_eq!(
+        tree_payload(&client, &doc_id, item).unwrap(),
+        Some(b"merged".to_vec())
+    );
+}
+
+#[test]
+fn postgres_backend_orphan_policy_decides_where_concurrent_children_of_deleted_nodes_go() {
+    let Some(client) = connect() else {
+        return;
+    };
+    ensure_schema_once(&client);
+
+    let doc_id = format!("test-{}", Uuid::new_v4());
+    {
+        let mut c = client.borrow_mut();
+        reset_doc_for_tests(&mut c, &doc_id).unwrap();
+    }
+    set_orphan_policy(&client, &doc_id, OrphanPolicy::LostAndFound).unwrap();
+
+    let replica = ReplicaId::new(b"orphans");
+    let (folder, old, file) = (node(1970), node(1971), node(1972));
+    for (parent, n) in [(NodeId::ROOT, folder), (folder, old)] {
+        local_insert(&client, &doc_id, &replica, parent, n, "last", None, None).unwrap();
+    }
+    local_delete(&client, &doc_id, &replica, folder).unwrap();
+
+    // Inserted concurrently with the delete, and ordered before it.
+    let insert = Operation::insert(&ReplicaId::new(b"remote"), 1, 2, folder, file, vec![0x80]);
+    append_ops(&client, &doc_id, &[insert]).unwrap();
+    assert!(tree_children(&client, &doc_id, NodeId::ROOT).unwrap().is_empty());
+    assert_eq!(
+        tree_children(&client, &doc_id, NodeId::LOST_AND_FOUND).unwrap(),
+        vec![file]
+    );
+
+    // Switching policies rematerializes the doc.
+    set_orphan_policy(&client, &doc_id, OrphanPolicy::Defensive).unwrap();
+    assert_eq!(
+        tree_children(&client, &doc_id, NodeId::ROOT).unwrap(),
+        vec![folder]
+    );
+    assert_eq!(
+        tree_children(&client, &doc_id, folder).unwrap(),
+        vec![old, file]
+    );
+    assert!(tree_children(&client, &doc_id, NodeId::LOST_AND_FOUND).unwrap().is_empty());
+}
+
+#[test]
+fn postgres_backend_local_duplicate_copies_a_branch() {
+    let Some(client) = connect() else {
+        return;
+    };
+    ensure_schema_once(&client);
+
+    let doc_id = format!("test-{}", Uuid::new_v4());
+    {
+        let mut c = client.borrow_mut();
+        reset_doc_for_tests(&mut c, &doc_id).unwrap();
+    }
+
+    let replica = ReplicaId::new(b"dup");
+    let (folder, a, b) = (node(1600), node(1601), node(1602));
+    for (parent, n, payload) in [
+        (NodeId::ROOT, folder, b"folder".to_vec()),
+        (folder, a, vec![1]),
+        (folder, b, vec![2]),
+    ] {
+        local_insert(
+            &client,
+            &doc_id,
+            &replica,
+            parent,
+            n,
+            "last",
+            None,
+            Some(payload),
+        )
+        .unwrap();
+    }
+
+    let res = local_duplicate(
+        &client,
+        &doc_id,
+        &replica,
+        folder,
+        NodeId::ROOT,
+        LocalPlacement::After(folder),
+    )
+    .unwrap();
+    assert_eq!(res.ops.len(), 3);
+    let sources: Vec<NodeId> = res.mapping.iter().map(|(source, _)| *source).collect();
+    assert_eq!(sources, vec![folder, a, b]);
+    let (copy_folder, copy_a, copy_b) = (res.mapping[0].1, res.mapping[1].1, res.mapping[2].1);
+    assert_eq!(
+        tree_children(&client, &doc_id, NodeId::ROOT).unwrap(),
+        vec![folder, copy_folder]
+    );
+    assert_eq!(
+        tree_children(&client, &doc_id, copy_folder).unwrap(),
+        vec![copy_a, copy_b]
+    );
+    assert_eq!(
+        tree_payload(&client, &doc_id, copy_b).unwrap(),
+        Some(vec![2])
+    );
+
+    // A missing source fails without inserting anything.
+    assert!(local_duplicate(
+        &client,
+        &doc_id,
+        &replica,
+        node(1699),
+        NodeId::ROOT,
+        LocalPlacement::Last,
+    )
+    .is_err());
+    assert_eq!(
+        tree_children(&client, &doc_id, NodeId::ROOT).unwrap(),
+        vec![folder, copy_folder]
+    );
+}
+
+#[test]
+fn postgres_backend_append_ops_grouped_waits_for_whole_groups() {
+    let Some(client) = connect() else {
+        return;
+    };
+    ensure_schema_once(&client);
+
+    let doc_id = format!("test-{}", Uuid::new_v4());
+    {
+        let mut c = client.borrow_mut();
+        reset_doc_for_tests(&mut c, &doc_id).unwrap();
+    }
+
+    let replica = ReplicaId::new(b"writer");
+    let group = OperationGroup { first: 1, len: 3 };
+    let (folder, a, b) = (node(1600), node(1601), node(1602));
+    let ops: Vec<Operation> = vec![
+        Operation::insert(
+            &replica,
+            1,
+            1,
+            NodeId::ROOT,
+            folder,
+            order_key_from_position(0),
+        ),
+        Operation::insert(&replica, 2, 2, folder, a, order_key_from_position(0)),
+        Operation::insert(&replica, 3, 3, folder, b, order_key_from_position(1)),
+    ]
+    .into_iter()
+    .map(|op| op.with_group(group))
+    .collect();
+
+    let mut buffer = GroupBuffer::new(PartialGroupPolicy::Wait);
+    let outcome = append_ops_grouped(&client, &doc_id, ops[..2].to_vec(), &mut buffer, 0).unwrap();
+    assert!(outcome.changes.is_empty());
+    assert!(tree_children(&client, &doc_id, NodeId::ROOT).unwrap().is_empty());
+    assert_eq!(buffer.pending_ops(), 2);
+
+    let outcome = append_ops_grouped(&client, &doc_id, ops[2..].to_vec(), &mut buffer, 1).unwrap();
+    assert_eq!(outcome.changes.len(), 3);
+    assert!(buffer.is_empty());
+    assert_eq!(tree_children(&client, &doc_id, folder).unwrap(), vec![a, b]);
+
+    // The group is stored with the ops.
+    let stored = get_ops_by_op_refs(
+        &client,
+        &doc_id,
+        &list_op_refs_all(&client, &doc_id).unwrap(),
+    )
+    .unwrap();
+    assert!(stored.iter().all(|op| op.meta.group == Some(group)));
+}
+
+#[test]
+fn postgres_backend_local_restore_brings_back_deleted_ancestors() {
+    let Some(client) = connect() else {
+        return;
+    };
+    ensure_schema_once(&client);
+
+    let (doc_id, peer_doc) = (
+        format!("test-{}", Uuid::new_v4()),
+        format!("test-{}", Uuid::new_v4()),
+    );
+    {
+        let mut c = client.borrow_mut();
+        reset_doc_for_tests(&mut c, &doc_id).unwrap();
+        reset_doc_for_tests(&mut c, &peer_doc).unwrap();
+    }
+
+    let replica = ReplicaId::new(b"restore");
+    let (folder, file) = (node(2000), node(2001));
+    local_insert(
+        &client,
+        &doc_id,
+        &replica,
+        NodeId::ROOT,
+        folder,
+        "last",
+        None,
+        None,
+    )
+    .unwrap();
+    local_insert(&client, &doc_id, &replica, folder, file, "last", None, None).unwrap();
+    local_delete(&client, &doc_id, &replica, folder).unwrap();
+    assert!(tree_children(&client, &doc_id, NodeId::ROOT).unwrap().is_empty());
+
+    let restored = local_restore(&client, &doc_id, &replica, file).unwrap();
+    assert_eq!(
+        restored.outcome.changes,
+        vec![MaterializationChange::Restore {
+            node: folder,
+            parent_after: Some(NodeId::ROOT),
+            payload: None,
+            source: Some(treecrdt_core::MaterializationSource::from_op(&restored.op)),
+        }]
+    );
+    assert_eq!(
+        tree_children(&client, &doc_id, NodeId::ROOT).unwrap(),
+        vec![folder]
+    );
+    assert!(local_restore(&client, &doc_id, &replica, folder).is_err());
+
+    // A peer receiving the log backwards converges.
+    let mut ops = ops_since(&client, &doc_id, 0, None).unwrap();
+    ops.reverse();
+    for op in ops {
+        append_ops(&client, &peer_doc, &[op]).unwrap();
+    }
+    assert_eq!(
+        tree_children(&client, &peer_doc, NodeId::ROOT).unwrap(),
+        vec![folder]
+    );
+    assert_eq!(
+        tree_children(&client, &peer_doc, folder).unwrap(),
+        vec![file]
+    );
+}
+
+#[test]
+fn postgres_backend_local_tombstone_is_not_revived_by_concurrent_inserts() {
+    let Some(client) = connect() else {
+        return;
+    };
+    ensure_schema_once(&client);
+
+    let (doc_a, doc_b) = (
+        format!("test-{}", Uuid::new_v4()),
+        format!("test-{}", Uuid::new_v4()),
+    );
+    {
+        let mut c = client.borrow_mut();
+        reset_doc_for_tests(&mut c, &doc_a).unwrap();
+        reset_doc_for_tests(&mut c, &doc_b).unwrap();
+    }
+
+    let (ra, rb) = (ReplicaId::new(b"a"), ReplicaId::new(b"b"));
+    let (folder, file) = (node(2100), node(2101));
+    let insert_folder = local_insert(
+        &client,
+        &doc_a,
+        &ra,
+        NodeId::ROOT,
+        folder,
+        "last",
+        None,
+        None,
+    )
+    .unwrap()
+    .op;
+    append_ops(&client, &doc_b, &[insert_folder]).unwrap();
+
+    // The tombstone never sees `b`'s insert, which would revive a plain delete.
+    let insert_file =
+        local_insert(&client, &doc_b, &rb, folder, file, "last", None, None).unwrap().op;
+    let tombstone = local_tombstone(&client, &doc_a, &ra, folder).unwrap();
+    assert_eq!(
+        tombstone.outcome.changes,
+        vec![MaterializationChange::Delete {
+            node: folder,
+            parent_before: Some(NodeId::ROOT),
+            source: Some(treecrdt_core::MaterializationSource::from_op(&tombstone.op)),
+        }]
+    );
+
+    append_ops(&client, &doc_a, &[insert_file]).unwrap();
+    append_ops(&client, &doc_b, &[tombstone.op]).unwrap();
+    for doc_id in [&doc_a, &doc_b] {
+        assert!(tree_children(&client, doc_id, NodeId::ROOT).unwrap().is_empty());
+    }
+    assert!(local_restore(&client, &doc_b, &rb, file).is_err());
+}
+
+#[test]
+fn postgres_backend_tree_trash_page_lists_deleted_subtrees_newest_first() {
+    let Some(client) = connect() else {
+        return;
+    };
+    ensure_schema_once(&client);
+
+    let doc_id = format!("test-{}", Uuid::new_v4());
+    {
+        let mut c = client.borrow_mut();
+        reset_doc_for_tests(&mut c, &doc_id).unwrap();
+    }
+
+    let replica = ReplicaId::new(b"a");
+    let (folder, file, note) = (node(2200), node(2201), node(2202));
+    let preview = vec![7u8; treecrdt_core::TRASH_PREVIEW_LEN + 10];
+    local_insert(
+        &client,
+        &doc_id,
+        &replica,
+        NodeId::ROOT,
+        folder,
+        "last",
+        None,
+        None,
+    )
+    .unwrap();
+    local_insert(&client, &doc_id, &replica, folder, file, "last", None, None).unwrap();
+    local_insert(
+        &client,
+        &doc_id,
+        &replica,
+        NodeId::ROOT,
+        note,
+        "last",
+        None,
+        Some(preview.clone()),
+    )
+    .unwrap();
+
+    local_delete(&client, &doc_id, &replica, file).unwrap();
+    let delete_note = local_delete(&client, &doc_id, &replica, note).unwrap().op;
+    let delete_folder = local_tombstone(&client, &doc_id, &replica, folder).unwrap().op;
+
+    // `file` is inside the deleted folder, so only the folder is listed for it.
+    let all = tree_trash_page(&client, &doc_id, None, 10).unwrap();
+    assert_eq!(
+        all.iter().map(|entry| entry.node).collect::<Vec<_>>(),
+        vec![folder, note]
+    );
+    assert_eq!(all[0].parent, Some(NodeId::ROOT));
+    assert_eq!(
+        all[0].source,
+        Some(treecrdt_core::MaterializationSource::from_op(
+            &delete_folder
+        ))
+    );
+    assert!(all[0].hard_deleted);
+    assert_eq!(
+        all[1].source,
+        Some(treecrdt_core::MaterializationSource::from_op(&delete_note))
+    );
+    assert_eq!(
+        all[1].payload_preview,
+        Some(preview[..treecrdt_core::TRASH_PREVIEW_LEN].to_vec())
+    );
+
+    let first = tree_trash_page(&client, &doc_id, None, 1).unwrap();
+    assert_eq!(first, all[..1]);
+    let rest = tree_trash_page(&client, &doc_id, Some(&first[0].cursor()), 10).unwrap();
+    assert_eq!(rest, all[1..]);
+}
+
+#[test]
+fn postgres_backend_navigation_queries_walk_the_visible_tree() {
+    let Some(client) = connect() else {
+        return;
+    };
+    ensure_schema_once(&client);
+
+    let doc_id = format!("test-{}", Uuid::new_v4());
+    {
+        let mut c = client.borrow_mut();
+        reset_doc_for_tests(&mut c, &doc_id).unwrap();
+    }
+
+    let replica = ReplicaId::new(b"nav");
+    let [a, b, c, d, e] = [2300, 2301, 2302, 2303, 2304].map(node);
+    for (parent, n) in [(NodeId::ROOT, a), (a, b), (b, c), (a, d), (NodeId::ROOT, e)] {
+        local_insert(&client, &doc_id, &replica, parent, n, "last", None, None).unwrap();
+    }
+
+    assert_eq!(
+        tree_ancestors(&client, &doc_id, c).unwrap(),
+        vec![b, a, NodeId::ROOT]
+    );
+    assert_eq!(tree_depth(&client, &doc_id, c).unwrap(), Some(3));
+    assert_eq!(tree_depth(&client, &doc_id, NodeId::ROOT).unwrap(), Some(0));
+    assert!(tree_is_descendant(&client, &doc_id, c, a).unwrap());
+    assert!(!tree_is_descendant(&client, &doc_id, a, c).unwrap());
+    assert_eq!(
+        tree_descendants(&client, &doc_id, NodeId::ROOT, None).unwrap(),
+        vec![(a, 1), (b, 2), (c, 3), (d, 2), (e, 1)]
+    );
+    assert_eq!(
+        tree_descendants(&client, &doc_id, NodeId::ROOT, Some(1)).unwrap(),
+        vec![(a, 1), (e, 1)]
+    );
+    assert_eq!(tree_index_in_parent(&client, &doc_id, d).unwrap(), Some(1));
+    assert_eq!(
+        tree_index_in_parent(&client, &doc_id, NodeId::ROOT).unwrap(),
+        None
+    );
+
+    local_delete(&client, &doc_id, &replica, b).unwrap();
+    assert!(tree_ancestors(&client, &doc_id, c).unwrap().is_empty());
+    assert_eq!(tree_depth(&client, &doc_id, c).unwrap(), None);
+    assert!(tree_descendants(&client, &doc_id, b, None).unwrap().is_empty());
+    assert_eq!(
+        tree_descendants(&client, &doc_id, NodeId::ROOT, None).unwrap(),
+        vec![(a, 1), (d, 2), (e, 1)]
+    );
+    assert_eq!(tree_index_in_parent(&client, &doc_id, d).unwrap(), Some(0));
+}
@@ -5,33 +5,66 @@
 
 #![allow(non_snake_case)]
 
+mod access;
 mod append;
 mod doc_id;
+mod groups;
+mod history;
 mod local_ops;
 mod materialize;
+mod navigation;
 mod node_store;
 mod op_index;
 mod op_storage;
 mod oprefs;
 mod ops;
+mod order_keys;
+mod orphan_policy;
+mod payload_mode;
 mod payload_store;
+mod purge;
 mod schema;
 mod sqlite_api;
+mod stability;
 mod statement;
+mod trash;
+mod undo;
 mod util;
 
-use append::{treecrdt_append_op, treecrdt_append_ops};
+pub use access::{set_access_control, SharedAccessControl};
+use append::{treecrdt_append_op, treecrdt_append_ops, treecrdt_append_ops_blob};
 use doc_id::{treecrdt_doc_id, treecrdt_set_doc_id};
+pub use groups::clear_group_buffer;
+use history::{treecrdt_tree_at, treecrdt_tree_diff};
 use local_ops::{
-    treecrdt_local_delete, treecrdt_local_insert, treecrdt_local_move, treecrdt_local_payload,
+    treecrdt_local_batch, treecrdt_local_delete, treecrdt_local_duplicate, treecrdt_local_insert,
+    treecrdt_local_insert_many, treecrdt_local_move, treecrdt_local_payload,
+    treecrdt_local_payload_field, treecrdt_local_rebalance, treecrdt_local_reorder,
+    treecrdt_local_restore, treecrdt_local_tombstone,
 };
 use materialize::{append_ops_impl, ensure_materialized, treecrdt_ensure_materialized};
+use navigation::{
+    treecrdt_ancestors, treecrdt_depth, treecrdt_descendants, treecrdt_index_in_parent,
+    treecrdt_is_descendant,
+};
 use oprefs::{treecrdt_oprefs_all, treecrdt_oprefs_children};
-use ops::{treecrdt_ops_by_oprefs, treecrdt_ops_since};
+use ops::{
+    treecrdt_head_lamport, treecrdt_ops_by_oprefs, treecrdt_ops_since, treecrdt_ops_since_blob,
+    treecrdt_replica_max_counter,
+};
+use order_keys::treecrdt_order_key_stats;
+use orphan_policy::treecrdt_set_orphan_policy;
+use payload_mode::treecrdt_set_payload_mode;
+use purge::treecrdt_purge_stable;
 use schema::*;
 use sqlite_api::*;
+use stability::{treecrdt_ack_version_vector, treecrdt_forget_peer, treecrdt_stable_frontier};
+use trash::treecrdt_trash_list;
+pub use undo::clear_undo_history;
+use undo::{treecrdt_redo, treecrdt_undo, treecrdt_undo_begin, treecrdt_undo_commit};
 use util::drop_cstring;
 
+use groups::{treecrdt_release_groups, treecrdt_set_atomic_groups};
 use std::ffi::CString;
 use std::os::raw::{c_char, c_int, c_void};
 use std::ptr::null_mut;
@@ -80,6 +113,15 @@ unsafe extern "C" fn treecrdt_version_fn(
     sqlite_result_text(ctx, ptr as *const c_char, len, Some(drop_cstring));
 }
 
+/// Destructor of the `treecrdt_version` registration, which SQLite runs when the connection
+/// closes: drops the in-memory state kept for it, so a later connection that reuses the handle
+/// starts clean.
+unsafe extern "C" fn release_connection_state(p_app: *mut c_void) {
+    let db = p_app as *mut sqlite3;
+    clear_undo_history(db);
+    clear_group_buffer(db);
+}
+
 #[no_mangle]
 pub extern "C" fn sqlite3_treecrdt_init(
     db: *mut sqlite3,
@@ -109,11 +151,11 @@ pub extern "C" fn sqlite3_treecrdt_init(
             name.as_ptr(),
             0,
             SQLITE_UTF8 as c_int,
-            null_mut(),
+            db as *mut c_void,
             Some(treecrdt_version_fn),
             None,
             None,
-            None,
+            Some(release_connection_state),
         )
     };
 
@@ -122,7 +164,7 @@ pub extern "C" fn sqlite3_treecrdt_init(
         sqlite_create_function_v2(
             db,
             name.as_ptr(),
-            9,
+            -1,
             SQLITE_UTF8 as c_int,
             null_mut(),
             Some(treecrdt_append_op),
@@ -231,6 +273,34 @@ pub extern "C" fn sqlite3_treecrdt_init(
             None,
         )
     };
+    let rc_head_lamport = {
+        let name = CString::new("treecrdt_head_lamport").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            0,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_head_lamport),
+            None,
+            None,
+            None,
+        )
+    };
+    let rc_replica_max_counter = {
+        let name = CString::new("treecrdt_replica_max_counter").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            1,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_replica_max_counter),
+            None,
+            None,
+            None,
+        )
+    };
 
     let rc_since = {
         let name = CString::new("treecrdt_ops_since").expect("static name");
@@ -290,6 +360,34 @@ pub extern "C" fn sqlite3_treecrdt_init(
             None,
         )
     };
+    let rc_local_tombstone = {
+        let name = CString::new("treecrdt_local_tombstone").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            2,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_local_tombstone),
+            None,
+            None,
+            None,
+        )
+    };
+    let rc_local_restore = {
+        let name = CString::new("treecrdt_local_restore").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            2,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_local_restore),
+            None,
+            None,
+            None,
+        )
+    };
     let rc_local_payload = {
         let name = CString::new("treecrdt_local_payload").expect("static name");
         sqlite_create_function_v2(
@@ -305,6 +403,436 @@ pub extern "C" fn sqlite3_treecrdt_init(
         )
     };
 
+    let rc_ack_vv = {
+        let name = CString::new("treecrdt_ack_version_vector").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            2,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_ack_version_vector),
+            None,
+            None,
+            None,
+        )
+    };
+    let rc_forget_peer = {
+        let name = CString::new("treecrdt_forget_peer").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            1,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_forget_peer),
+            None,
+            None,
+            None,
+        )
+    };
+    let rc_stable_frontier = {
+        let name = CString::new("treecrdt_stable_frontier").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            0,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_stable_frontier),
+            None,
+            None,
+            None,
+        )
+    };
+    let rc_purge_stable = {
+        let name = CString::new("treecrdt_purge_stable").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            -1,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_purge_stable),
+            None,
+            None,
+            None,
+        )
+    };
+
+    let rc_undo = {
+        let name = CString::new("treecrdt_undo").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            1,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_undo),
+            None,
+            None,
+            None,
+        )
+    };
+    let rc_redo = {
+        let name = CString::new("treecrdt_redo").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            1,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_redo),
+            None,
+            None,
+            None,
+        )
+    };
+    let rc_undo_begin = {
+        let name = CString::new("treecrdt_undo_begin").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            1,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_undo_begin),
+            None,
+            None,
+            None,
+        )
+    };
+    let rc_undo_commit = {
+        let name = CString::new("treecrdt_undo_commit").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            1,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_undo_commit),
+            None,
+            None,
+            None,
+        )
+    };
+
+    let rc_tree_at = {
+        let name = CString::new("treecrdt_tree_at").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            1,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_tree_at),
+            None,
+            None,
+            None,
+        )
+    };
+
+    let rc_tree_diff = {
+        let name = CString::new("treecrdt_tree_diff").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            2,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_tree_diff),
+            None,
+            None,
+            None,
+        )
+    };
+
+    let rc_append_ops_blob = {
+        let name = CString::new("treecrdt_append_ops_blob").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            1,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_append_ops_blob),
+            None,
+            None,
+            None,
+        )
+    };
+
+    let rc_ops_since_blob = {
+        let name = CString::new("treecrdt_ops_since_blob").expect("static name");
+        // -1 allows 1 or 2 args, like treecrdt_ops_since
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            -1,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_ops_since_blob),
+            None,
+            None,
+            None,
+        )
+    };
+
+    let rc_local_batch = {
+        let name = CString::new("treecrdt_local_batch").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            2,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_local_batch),
+            None,
+            None,
+            None,
+        )
+    };
+
+    let rc_set_atomic_groups = {
+        let name = CString::new("treecrdt_set_atomic_groups").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            -1,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_set_atomic_groups),
+            None,
+            None,
+            None,
+        )
+    };
+
+    let rc_release_groups = {
+        let name = CString::new("treecrdt_release_groups").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            0,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_release_groups),
+            None,
+            None,
+            None,
+        )
+    };
+
+    let rc_local_duplicate = {
+        let name = CString::new("treecrdt_local_duplicate").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            5,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_local_duplicate),
+            None,
+            None,
+            None,
+        )
+    };
+
+    let rc_local_insert_many = {
+        let name = CString::new("treecrdt_local_insert_many").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            5,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_local_insert_many),
+            None,
+            None,
+            None,
+        )
+    };
+
+    let rc_local_rebalance = {
+        let name = CString::new("treecrdt_local_rebalance").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            2,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_local_rebalance),
+            None,
+            None,
+            None,
+        )
+    };
+
+    let rc_order_key_stats = {
+        let name = CString::new("treecrdt_order_key_stats").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            1,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_order_key_stats),
+            None,
+            None,
+            None,
+        )
+    };
+
+    let rc_trash_list = {
+        let name = CString::new("treecrdt_trash_list").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            2,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_trash_list),
+            None,
+            None,
+            None,
+        )
+    };
+
+    let rc_ancestors = {
+        let name = CString::new("treecrdt_ancestors").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            1,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_ancestors),
+            None,
+            None,
+            None,
+        )
+    };
+
+    let rc_depth = {
+        let name = CString::new("treecrdt_depth").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            1,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_depth),
+            None,
+            None,
+            None,
+        )
+    };
+
+    let rc_is_descendant = {
+        let name = CString::new("treecrdt_is_descendant").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            2,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_is_descendant),
+            None,
+            None,
+            None,
+        )
+    };
+
+    let rc_descendants = {
+        let name = CString::new("treecrdt_descendants").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            2,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_descendants),
+            None,
+            None,
+            None,
+        )
+    };
+
+    let rc_index_in_parent = {
+        let name = CString::new("treecrdt_index_in_parent").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            1,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_index_in_parent),
+            None,
+            None,
+            None,
+        )
+    };
+
+    let rc_local_reorder = {
+        let name = CString::new("treecrdt_local_reorder").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            3,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_local_reorder),
+            None,
+            None,
+            None,
+        )
+    };
+
+    let rc_local_payload_field = {
+        let name = CString::new("treecrdt_local_payload_field").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            4,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_local_payload_field),
+            None,
+            None,
+            None,
+        )
+    };
+
+    let rc_set_payload_mode = {
+        let name = CString::new("treecrdt_set_payload_mode").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            1,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_set_payload_mode),
+            None,
+            None,
+            None,
+        )
+    };
+
+    let rc_set_orphan_policy = {
+        let name = CString::new("treecrdt_set_orphan_policy").expect("static name");
+        sqlite_create_function_v2(
+            db,
+            name.as_ptr(),
+            1,
+            SQLITE_UTF8 as c_int,
+            null_mut(),
+            Some(treecrdt_set_orphan_policy),
+            None,
+            None,
+            None,
+        )
+    };
+
     if rc != SQLITE_OK as c_int
         || rc_append != SQLITE_OK as c_int
         || rc_set_doc_id != SQLITE_OK as c_int
@@ -313,11 +841,44 @@ pub extern "C" fn sqlite3_treecrdt_init(
         || rc_oprefs_all != SQLITE_OK as c_int
         || rc_oprefs_children != SQLITE_OK as c_int
         || rc_ops_by_oprefs != SQLITE_OK as c_int
+        || rc_head_lamport != SQLITE_OK as c_int
+        || rc_replica_max_counter != SQLITE_OK as c_int
         || rc_since != SQLITE_OK as c_int
         || rc_local_insert != SQLITE_OK as c_int
         || rc_local_move != SQLITE_OK as c_int
         || rc_local_delete != SQLITE_OK as c_int
+        || rc_local_tombstone != SQLITE_OK as c_int
+        || rc_local_restore != SQLITE_OK as c_int
         || rc_local_payload != SQLITE_OK as c_int
+        || rc_ack_vv != SQLITE_OK as c_int
+        || rc_forget_peer != SQLITE_OK as c_int
+        || rc_stable_frontier != SQLITE_OK as c_int
+        || rc_purge_stable != SQLITE_OK as c_int
+        || rc_undo != SQLITE_OK as c_int
+        || rc_redo != SQLITE_OK as c_int
+        || rc_undo_begin != SQLITE_OK as c_int
+        || rc_undo_commit != SQLITE_OK as c_int
+        || rc_tree_at != SQLITE_OK as c_int
+        || rc_tree_diff != SQLITE_OK as c_int
+        || rc_append_ops_blob != SQLITE_OK as c_int
+        || rc_ops_since_blob != SQLITE_OK as c_int
+        || rc_local_batch != SQLITE_OK as c_int
+        || rc_set_atomic_groups != SQLITE_OK as c_int
+        || rc_release_groups != SQLITE_OK as c_int
+        || rc_local_duplicate != SQLITE_OK as c_int
+        || rc_local_insert_many != SQLITE_OK as c_int
+        || rc_local_rebalance != SQLITE_OK as c_int
+        || rc_order_key_stats != SQLITE_OK as c_int
+        || rc_trash_list != SQLITE_OK as c_int
+        || rc_local_reorder != SQLITE_OK as c_int
+        || rc_local_payload_field != SQLITE_OK as c_int
+        || rc_set_payload_mode != SQLITE_OK as c_int
+        || rc_set_orphan_policy != SQLITE_OK as c_int
+        || rc_ancestors != SQLITE_OK as c_int
+        || rc_depth != SQLITE_OK as c_int
+        || rc_is_descendant != SQLITE_OK as c_int
+        || rc_descendants != SQLITE_OK as c_int
+        || rc_index_in_parent != SQLITE_OK as c_int
     {
         unsafe {
             if !pz_err_msg.is_null() {
@@ -342,14 +903,80 @@ pub extern "C" fn sqlite3_treecrdt_init(
             rc_oprefs_children
         } else if rc_ops_by_oprefs != SQLITE_OK as c_int {
             rc_ops_by_oprefs
+        } else if rc_head_lamport != SQLITE_OK as c_int {
+            rc_head_lamport
+        } else if rc_replica_max_counter != SQLITE_OK as c_int {
+            rc_replica_max_counter
         } else if rc_local_insert != SQLITE_OK as c_int {
             rc_local_insert
         } else if rc_local_move != SQLITE_OK as c_int {
             rc_local_move
         } else if rc_local_delete != SQLITE_OK as c_int {
             rc_local_delete
+        } else if rc_local_tombstone != SQLITE_OK as c_int {
+            rc_local_tombstone
+        } else if rc_local_restore != SQLITE_OK as c_int {
+            rc_local_restore
         } else if rc_local_payload != SQLITE_OK as c_int {
             rc_local_payload
+        } else if rc_ack_vv != SQLITE_OK as c_int {
+            rc_ack_vv
+        } else if rc_forget_peer != SQLITE_OK as c_int {
+            rc_forget_peer
+        } else if rc_stable_frontier != SQLITE_OK as c_int {
+            rc_stable_frontier
+        } else if rc_purge_stable != SQLITE_OK as c_int {
+            rc_purge_stable
+        } else if rc_undo != SQLITE_OK as c_int {
+            rc_undo
+        } else if rc_redo != SQLITE_OK as c_int {
+            rc_redo
+        } else if rc_undo_begin != SQLITE_OK as c_int {
+            rc_undo_begin
+        } else if rc_undo_commit != SQLITE_OK as c_int {
+            rc_undo_commit
+        } else if rc_tree_at != SQLITE_OK as c_int {
+            rc_tree_at
+        } else if rc_tree_diff != SQLITE_OK as c_int {
+            rc_tree_diff
+        } else if rc_append_ops_blob != SQLITE_OK as c_int {
+            rc_append_ops_blob
+        } else if rc_ops_since_blob != SQLITE_OK as c_int {
+            rc_ops_since_blob
+        } else if rc_local_batch != SQLITE_OK as c_int {
+            rc_local_batch
+        } else if rc_set_atomic_groups != SQLITE_OK as c_int {
+            rc_set_atomic_groups
+        } else if rc_release_groups != SQLITE_OK as c_int {
+            rc_release_groups
+        } else if rc_local_duplicate != SQLITE_OK as c_int {
+            rc_local_duplicate
+        } else if rc_local_insert_many != SQLITE_OK as c_int {
+            rc_local_insert_many
+        } else if rc_local_rebalance != SQLITE_OK as c_int {
+            rc_local_rebalance
+        } else if rc_order_key_stats != SQLITE_OK as c_int {
+            rc_order_key_stats
+        } else if rc_trash_list != SQLITE_OK as c_int {
+            rc_trash_list
+        } else if rc_local_reorder != SQLITE_OK as c_int {
+            rc_local_reorder
+        } else if rc_local_payload_field != SQLITE_OK as c_int {
+            rc_local_payload_field
+        } else if rc_set_payload_mode != SQLITE_OK as c_int {
+            rc_set_payload_mode
+        } else if rc_set_orphan_policy != SQLITE_OK as c_int {
+            rc_set_orphan_policy
+        } else if rc_ancestors != SQLITE_OK as c_int {
+            rc_ancestors
+        } else if rc_depth != SQLITE_OK as c_int {
+            rc_depth
+        } else if rc_is_descendant != SQLITE_OK as c_int {
+            rc_is_descendant
+        } else if rc_descendants != SQLITE_OK as c_int {
+            rc_descendants
+        } else if rc_index_in_parent != SQLITE_OK as c_int {
+            rc_index_in_parent
         } else {
             rc_since
         };
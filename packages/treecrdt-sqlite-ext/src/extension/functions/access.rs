@@ -0,0 +1,108 @@
+use super::materialize::ensure_materialized;
+use super::node_store::SqliteNodeStore;
+use super::*;
+
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::sync::Arc;
+
+use treecrdt_core::AccessControl;
+
+use super::util::sqlite_err_from_core;
+
+/// Access-control policy shared between a connection's SQL functions.
+pub type SharedAccessControl = Arc<dyn AccessControl + Send + Sync>;
+
+// Kept in the connection's client data, so SQLite drops it when the connection closes.
+const CLIENT_DATA_KEY: &CStr = c"treecrdt.access_control";
+
+unsafe extern "C" fn drop_policy(data: *mut c_void) {
+    drop(unsafe { Box::from_raw(data as *mut SharedAccessControl) });
+}
+
+/// Install (or with `None`, remove) the access-control policy for one connection.
+///
+/// Once set, `treecrdt_append_op(s)` reject ops the policy denies, `treecrdt_local_*` run their
+/// ops through the policy before minting them, and the reads that return nodes, their ops or
+/// their history either check read access to the node asked about or leave out what the caller
+/// may not read. The `tree_nodes` and payload tables themselves are not guarded, so hosts must
+/// not expose them to untrusted SQL.
+///
+/// The policy lives in the connection's client data and is dropped when the connection closes.
+/// Fails with `SQLITE_ERROR` on SQLite older than 3.44, which has no client data.
+pub fn set_access_control(
+    db: *mut sqlite3,
+    policy: Option<SharedAccessControl>,
+) -> Result<(), c_int> {
+    let data = match policy {
+        Some(policy) => Box::into_raw(Box::new(policy)) as *mut c_void,
+        None => null_mut(),
+    };
+    match sqlite_set_clientdata(db, CLIENT_DATA_KEY.as_ptr(), data, Some(drop_policy)) {
+        rc if rc == SQLITE_OK as c_int => Ok(()),
+        rc => Err(rc),
+    }
+}
+
+pub(super) fn access_control(db: *mut sqlite3) -> Option<SharedAccessControl> {
+    let data = sqlite_get_clientdata(db, CLIENT_DATA_KEY.as_ptr()) as *const SharedAccessControl;
+    unsafe { data.as_ref() }.cloned()
+}
+
+/// Authorize a batch of incoming ops against the connection's policy, if any.
+///
+/// Ancestry comes from the materialized `tree_nodes` table, so callers must catch it up first.
+pub(super) fn check_ops(
+    db: *mut sqlite3,
+    ops: &[treecrdt_core::Operation],
+) -> treecrdt_core::Result<()> {
+    let Some(policy) = access_control(db) else {
+        return Ok(());
+    };
+    let nodes = SqliteNodeStore::prepare(db)?;
+    treecrdt_core::authorize_ops(&*policy, &nodes, ops)
+}
+
+pub(super) fn check_read(db: *mut sqlite3, node: NodeId) -> treecrdt_core::Result<()> {
+    let Some(policy) = access_control(db) else {
+        return Ok(());
+    };
+    let nodes = SqliteNodeStore::prepare(db)?;
+    policy.can_read(&nodes, node)
+}
+
+/// Leave out the `items` about nodes the connection's policy does not let the caller read, as
+/// the trash listing does. `node` names the node an item is about.
+pub(super) fn retain_readable<T>(
+    db: *mut sqlite3,
+    items: &mut Vec<T>,
+    node: impl Fn(&T) -> NodeId,
+) -> Result<(), c_int> {
+    let Some(policy) = access_control(db) else {
+        return Ok(());
+    };
+    ensure_materialized(db)?;
+    let nodes = SqliteNodeStore::prepare(db).map_err(sqlite_err_from_core)?;
+    let mut readable: HashMap<NodeId, bool> = HashMap::new();
+    let mut kept = Vec::with_capacity(items.len());
+    for item in items.drain(..) {
+        let node = node(&item);
+        let allowed = match readable.get(&node) {
+            Some(&allowed) => allowed,
+            None => {
+                let allowed = match policy.can_read(&nodes, node) {
+                    Ok(()) => true,
+                    Err(treecrdt_core::Error::AccessDenied(_)) => false,
+                    Err(err) => return Err(sqlite_err_from_core(err)),
+                };
+                readable.insert(node, allowed);
+                allowed
+            }
+        };
+        if allowed {
+            kept.push(item);
+        }
+    }
+    *items = kept;
+    Ok(())
+}
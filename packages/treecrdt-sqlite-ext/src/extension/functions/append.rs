@@ -1,9 +1,13 @@
-use super::materialize::json_outcome_from_core;
-use super::util::sqlite_result_json;
+use super::groups::append_remote_ops;
+use super::materialize::{json_append_op_to_operation, json_outcome_from_core};
+use super::util::{read_blob, read_text, sqlite_result_json};
 use super::*;
 
 /// Append an operation row to the `ops` table. Args:
-/// replica BLOB, counter INT, lamport INT, kind TEXT, parent BLOB|null, node BLOB, new_parent BLOB|null, order_key BLOB|null, known_state_or_payload BLOB|null
+/// replica BLOB, counter INT, lamport INT, kind TEXT, parent BLOB|null, node BLOB, new_parent BLOB|null, order_key BLOB|null, known_state_or_payload BLOB|null [, field TEXT]
+///
+/// `field` is required for `payload_field` ops and ignored otherwise. Payload ops appended here
+/// carry no known_state; multi-value payload writes go through `treecrdt_append_ops`.
 pub(super) unsafe extern "C" fn treecrdt_append_op(
     ctx: *mut sqlite3_context,
     argc: c_int,
@@ -14,10 +18,10 @@ pub(super) unsafe extern "C" fn treecrdt_append_op(
         return;
     }
 
-    if argc != 9 {
+    if !(argc == 9 || argc == 10) {
         sqlite_result_error(
             ctx,
-            b"treecrdt_append_op expects 9 args\0".as_ptr() as *const c_char,
+            b"treecrdt_append_op expects 9 or 10 args\0".as_ptr() as *const c_char,
         );
         return;
     }
@@ -106,7 +110,7 @@ pub(super) unsafe extern "C" fn treecrdt_append_op(
         "delete" => (known_state_or_payload, None),
         // Payload ops are represented as `kind = "payload"` plus an optional payload blob.
         // NULL payload clears.
-        "payload" => (None, known_state_or_payload),
+        "payload" | "payload_field" => (None, known_state_or_payload),
         // Inserts can carry an optional initial payload in the last arg.
         "insert" => (None, known_state_or_payload),
         _ => (known_state_or_payload, None),
@@ -129,6 +133,19 @@ pub(super) unsafe extern "C" fn treecrdt_append_op(
         }
     }
 
+    let field = if argc == 10 && unsafe { sqlite_value_type(args[9]) } != SQLITE_NULL as c_int {
+        Some(read_text(args[9]))
+    } else {
+        None
+    };
+    if kind == "payload_field" && field.is_none() {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_append_op: payload_field op missing field\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+
     if (kind == "insert" || kind == "move") && order_key.is_none() {
         sqlite_result_error(
             ctx,
@@ -148,6 +165,8 @@ pub(super) unsafe extern "C" fn treecrdt_append_op(
         order_key,
         known_state,
         payload,
+        group: None,
+        field,
     };
 
     match append_ops_impl(db, &doc_id, "treecrdt_append_op", std::slice::from_ref(&op)) {
@@ -170,6 +189,10 @@ pub(super) struct JsonAppendOp {
     pub(super) known_state: Option<Vec<u8>>,
     #[serde(default)]
     pub(super) payload: Option<Vec<u8>>,
+    #[serde(default)]
+    pub(super) group: Option<treecrdt_core::OperationGroup>,
+    #[serde(default)]
+    pub(super) field: Option<String>,
 }
 
 /// Batch append: accepts a single JSON array argument with fields matching the ops table.
@@ -264,7 +287,78 @@ pub(super) unsafe extern "C" fn treecrdt_append_ops(
         }
     }
 
-    match append_ops_impl(db, &doc_id, "treecrdt_append_ops", &ops) {
+    let appended = ops
+        .iter()
+        .map(json_append_op_to_operation)
+        .collect::<Result<Vec<_>, c_int>>()
+        .and_then(|operations| append_remote_ops(db, &doc_id, "treecrdt_append_ops", operations));
+    match appended {
+        Ok(outcome) => sqlite_result_json(ctx, &json_outcome_from_core(&outcome)),
+        Err(rc) => sqlite_result_error_code(ctx, rc),
+    }
+}
+
+/// Batch append from the binary op encoding (`treecrdt_core::encode_ops`). Args: ops BLOB.
+/// Returns the same JSON materialization outcome as `treecrdt_append_ops`.
+pub(super) unsafe extern "C" fn treecrdt_append_ops_blob(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if argc != 1 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_append_ops_blob expects a single BLOB argument\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+    let args = unsafe { std::slice::from_raw_parts(argv, argc as usize) };
+    let Some(bytes) = read_blob(args[0]) else {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_append_ops_blob: NULL ops\0".as_ptr() as *const c_char,
+        );
+        return;
+    };
+    let ops = match treecrdt_core::decode_ops(&bytes) {
+        Ok(ops) => ops,
+        Err(_) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_append_ops_blob failed to decode ops\0".as_ptr() as *const c_char,
+            );
+            return;
+        }
+    };
+    if ops.iter().any(|op| {
+        matches!(op.kind, treecrdt_core::OperationKind::Delete { .. })
+            && op.meta.known_state.as_ref().map_or(true, VersionVector::is_empty)
+    }) {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_append_ops_blob: delete op missing known_state\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+
+    let db = sqlite_context_db_handle(ctx);
+    let doc_id = match load_doc_id(db) {
+        Ok(Some(v)) => v,
+        Ok(None) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_append_ops_blob: doc_id not set (call treecrdt_set_doc_id)\0".as_ptr()
+                    as *const c_char,
+            );
+            return;
+        }
+        Err(rc) => {
+            sqlite_result_error_code(ctx, rc);
+            return;
+        }
+    };
+
+    match append_remote_ops(db, &doc_id, "treecrdt_append_ops_blob", ops) {
         Ok(outcome) => sqlite_result_json(ctx, &json_outcome_from_core(&outcome)),
         Err(rc) => sqlite_result_error_code(ctx, rc),
     }
@@ -0,0 +1,229 @@
+use super::materialize::{append_operations_impl, json_outcome_from_core};
+use super::statement::LazyStatement;
+use super::util::sqlite_result_json;
+use super::*;
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use treecrdt_core::{GroupBuffer, MaterializationOutcome, Operation, PartialGroupPolicy};
+
+// Like undo history, held-back group members are connection state: they are not written to the
+// op log until their group completes, and a restarted peer receives them again through sync.
+// Entries are dropped when the connection closes (see `release_connection_state`).
+fn registry() -> &'static Mutex<HashMap<usize, GroupBuffer>> {
+    static REGISTRY: OnceLock<Mutex<HashMap<usize, GroupBuffer>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn is_applied(stmt: &LazyStatement, replica: &[u8], counter: u64) -> treecrdt_core::Result<bool> {
+    let stmt = stmt.get()?;
+    unsafe {
+        sqlite_clear_bindings(stmt);
+        sqlite_reset(stmt);
+        let mut bind_err = sqlite_bind_blob(
+            stmt,
+            1,
+            replica.as_ptr() as *const c_void,
+            replica.len() as c_int,
+            None,
+        ) != SQLITE_OK as c_int;
+        bind_err |= sqlite_bind_int64(stmt, 2, counter as i64) != SQLITE_OK as c_int;
+        if bind_err {
+            sqlite_reset(stmt);
+            return Err(treecrdt_core::Error::Storage(
+                "bind op lookup failed".into(),
+            ));
+        }
+        let step_rc = sqlite_step(stmt);
+        sqlite_reset(stmt);
+        match step_rc {
+            rc if rc == SQLITE_ROW as c_int => Ok(true),
+            rc if rc == SQLITE_DONE as c_int => Ok(false),
+            rc => Err(treecrdt_core::Error::Storage(format!(
+                "op lookup failed (rc={rc})"
+            ))),
+        }
+    }
+}
+
+/// Take ops out of this connection's group buffer with `take` and `append` them. If taking or
+/// appending fails, the buffer is put back as it was, so held-back ops are never lost: the peer
+/// that sent them will not send them again.
+fn append_from_buffer<T, A>(
+    db: *mut sqlite3,
+    take: T,
+    append: A,
+) -> Result<MaterializationOutcome, c_int>
+where
+    T: FnOnce(&mut HashMap<usize, GroupBuffer>) -> Result<Vec<Operation>, c_int>,
+    A: FnOnce(Vec<Operation>) -> Result<MaterializationOutcome, c_int>,
+{
+    let key = db as usize;
+    let mut buffers = registry().lock().unwrap_or_else(|e| e.into_inner());
+    let snapshot = buffers.get(&key).cloned();
+    let restore = |buffers: &mut HashMap<usize, GroupBuffer>| match snapshot {
+        Some(buffer) => {
+            buffers.insert(key, buffer);
+        }
+        None => {
+            buffers.remove(&key);
+        }
+    };
+    let ops = match take(&mut buffers) {
+        Ok(ops) => ops,
+        Err(rc) => {
+            restore(&mut buffers);
+            return Err(rc);
+        }
+    };
+    drop(buffers);
+
+    let result = append(ops);
+    if result.is_err() {
+        restore(&mut registry().lock().unwrap_or_else(|e| e.into_inner()));
+    }
+    result
+}
+
+/// Append remote `ops`, routed through this connection's group buffer if atomic groups are
+/// enabled.
+pub(super) fn append_remote_ops(
+    db: *mut sqlite3,
+    doc_id: &[u8],
+    savepoint_name: &str,
+    ops: Vec<Operation>,
+) -> Result<MaterializationOutcome, c_int> {
+    append_from_buffer(
+        db,
+        |buffers| {
+            let Some(buffer) = buffers.get_mut(&(db as usize)) else {
+                return Ok(ops);
+            };
+            let lookup = LazyStatement::new(
+                db,
+                c"SELECT 1 FROM ops WHERE replica = ?1 AND counter = ?2 LIMIT 1",
+            );
+            buffer
+                .admit(ops, now_millis(), |replica, counter| {
+                    is_applied(&lookup, replica.as_bytes(), counter)
+                })
+                .map_err(|_| SQLITE_ERROR as c_int)
+        },
+        |ops| append_operations_impl(db, doc_id, savepoint_name, ops),
+    )
+}
+
+/// Drop the held-back ops of one connection. Closing the connection does this too.
+pub fn clear_group_buffer(db: *mut sqlite3) {
+    let mut buffers = registry().lock().unwrap_or_else(|e| e.into_inner());
+    buffers.remove(&(db as usize));
+}
+
+fn append_released(
+    db: *mut sqlite3,
+    savepoint_name: &str,
+    ops: Vec<Operation>,
+) -> Result<MaterializationOutcome, c_int> {
+    if ops.is_empty() {
+        return append_operations_impl(db, &[], savepoint_name, ops);
+    }
+    let doc_id = load_doc_id(db)?.ok_or(SQLITE_ERROR as c_int)?;
+    append_operations_impl(db, &doc_id, savepoint_name, ops)
+}
+
+/// Make `treecrdt_append_ops` and `treecrdt_append_ops_blob` hold back grouped ops until their
+/// whole group has arrived. Args: enabled INT [, release_after_ms INT].
+///
+/// Without `release_after_ms` an incomplete group waits forever; with it, the members that did
+/// arrive are appended once the group has waited that long (checked on every append and by
+/// `treecrdt_release_groups`). Disabling appends whatever is still held back. Returns the JSON
+/// materialization outcome of the ops this appended; if appending them fails, the setting is left
+/// as it was.
+pub(super) unsafe extern "C" fn treecrdt_set_atomic_groups(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if !(argc == 1 || argc == 2) {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_set_atomic_groups expects enabled [, release_after_ms]\0".as_ptr()
+                as *const c_char,
+        );
+        return;
+    }
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+    let enabled = unsafe { sqlite_value_int64(args[0]) } != 0;
+    let policy = if argc == 2 && unsafe { sqlite_value_type(args[1]) } != SQLITE_NULL as c_int {
+        let timeout = unsafe { sqlite_value_int64(args[1]) };
+        if timeout < 0 {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_set_atomic_groups: release_after_ms must not be negative\0".as_ptr()
+                    as *const c_char,
+            );
+            return;
+        }
+        PartialGroupPolicy::ReleaseAfter(timeout as u64)
+    } else {
+        PartialGroupPolicy::Wait
+    };
+
+    let db = sqlite_context_db_handle(ctx);
+    let switched = append_from_buffer(
+        db,
+        |buffers| {
+            let previous = if enabled {
+                buffers.insert(db as usize, GroupBuffer::new(policy))
+            } else {
+                buffers.remove(&(db as usize))
+            };
+            Ok(previous.map(|mut buffer| buffer.drain()).unwrap_or_default())
+        },
+        |held| append_released(db, "treecrdt_set_atomic_groups", held),
+    );
+    match switched {
+        Ok(outcome) => sqlite_result_json(ctx, &json_outcome_from_core(&outcome)),
+        Err(rc) => sqlite_result_error_code(ctx, rc),
+    }
+}
+
+/// Append the held-back members of groups that waited longer than the `release_after_ms` given
+/// to `treecrdt_set_atomic_groups`. No args. Returns the JSON materialization outcome.
+pub(super) unsafe extern "C" fn treecrdt_release_groups(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    _argv: *mut *mut sqlite3_value,
+) {
+    if argc != 0 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_release_groups expects no args\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+    let db = sqlite_context_db_handle(ctx);
+    let released = append_from_buffer(
+        db,
+        |buffers| {
+            Ok(buffers
+                .get_mut(&(db as usize))
+                .map(|buffer| buffer.release_expired(now_millis()))
+                .unwrap_or_default())
+        },
+        |released| append_released(db, "treecrdt_release_groups", released),
+    );
+    match released {
+        Ok(outcome) => sqlite_result_json(ctx, &json_outcome_from_core(&outcome)),
+        Err(rc) => sqlite_result_error_code(ctx, rc),
+    }
+}
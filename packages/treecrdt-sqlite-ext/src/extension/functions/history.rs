@@ -0,0 +1,158 @@
+use super::access::retain_readable;
+use super::materialize::{json_changes_from_core, node_hex, JsonMaterializationChange};
+use super::op_storage::SqliteOpStorage;
+use super::orphan_policy::load_orphan_policy;
+use super::payload_mode::load_payload_mode;
+use super::util::{sqlite_err_from_core, sqlite_result_json};
+use super::*;
+
+use std::collections::{BTreeMap, HashMap};
+
+use treecrdt_core::{diff_between, materialize_at, HistoryCut, MaterializationChange};
+
+unsafe fn read_version_vector(arg: *mut sqlite3_value) -> Option<VersionVector> {
+    let ptr = unsafe { sqlite_value_text(arg) } as *const u8;
+    let len = unsafe { sqlite_value_bytes(arg) } as usize;
+    if ptr.is_null() {
+        return None;
+    }
+    deserialize_version_vector(unsafe { slice::from_raw_parts(ptr, len) }).ok()
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonHistoricalNode {
+    node: String,
+    parent: Option<String>,
+    order_key: Option<Vec<u8>>,
+    tombstone: bool,
+    payload: Option<Vec<u8>>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    fields: BTreeMap<String, Vec<u8>>,
+}
+
+fn tree_at(db: *mut sqlite3, at: VersionVector) -> Result<Vec<JsonHistoricalNode>, c_int> {
+    let doc_id = load_doc_id(db)?.unwrap_or_default();
+    let storage = SqliteOpStorage::with_doc_id(db, doc_id);
+    let state = materialize_at(
+        &storage,
+        None,
+        &HistoryCut::Version(at),
+        load_payload_mode(db)?,
+        load_orphan_policy(db)?,
+    )
+    .map_err(sqlite_err_from_core)?;
+    let mut nodes = state.nodes;
+    retain_readable(db, &mut nodes, |row| row.node)?;
+
+    let mut payloads: HashMap<NodeId, Option<Vec<u8>>> =
+        state.payloads.into_iter().map(|row| (row.node, row.payload)).collect();
+    let mut fields: HashMap<NodeId, BTreeMap<String, Vec<u8>>> = HashMap::new();
+    for row in state.fields {
+        if let Some(value) = row.value {
+            fields.entry(row.node).or_default().insert(row.field, value);
+        }
+    }
+    Ok(nodes
+        .into_iter()
+        .filter(|row| row.node != NodeId::TRASH)
+        .map(|row| JsonHistoricalNode {
+            node: node_hex(row.node),
+            parent: row.parent.map(node_hex),
+            order_key: row.order_key,
+            tombstone: row.tombstone,
+            payload: payloads.remove(&row.node).flatten(),
+            fields: fields.remove(&row.node).unwrap_or_default(),
+        })
+        .collect())
+}
+
+/// The tree as it was once every op in a version vector had been applied. Args: vv TEXT
+/// (VersionVector JSON).
+///
+/// Rebuilt from the `ops` table in memory; the materialized tables are not touched. Ops dropped
+/// by `treecrdt_purge_stable(1)` are gone from history as well.
+///
+/// Returns `[{node, parent, orderKey, tombstone, payload, fields?}]`, one row per known node in
+/// node-id order; `fields` maps the node's set payload fields to their values.
+pub(super) unsafe extern "C" fn treecrdt_tree_at(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if argc != 1 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_tree_at expects 1 arg (vv)\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+    let Some(at) = (unsafe { read_version_vector(args[0]) }) else {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_tree_at: invalid vv json\0".as_ptr() as *const c_char,
+        );
+        return;
+    };
+
+    match tree_at(sqlite_context_db_handle(ctx), at) {
+        Ok(rows) => sqlite_result_json(ctx, &rows),
+        Err(rc) => sqlite_result_error_code(ctx, rc),
+    }
+}
+
+fn tree_diff(
+    db: *mut sqlite3,
+    from: VersionVector,
+    to: VersionVector,
+) -> Result<Vec<JsonMaterializationChange>, c_int> {
+    let doc_id = load_doc_id(db)?.unwrap_or_default();
+    let storage = SqliteOpStorage::with_doc_id(db, doc_id);
+    let mut changes = diff_between(
+        &storage,
+        None,
+        &HistoryCut::Version(from),
+        &HistoryCut::Version(to),
+        load_payload_mode(db)?,
+        load_orphan_policy(db)?,
+    )
+    .map_err(sqlite_err_from_core)?;
+    retain_readable(db, &mut changes, MaterializationChange::node)?;
+    Ok(json_changes_from_core(&changes))
+}
+
+/// What changed between the trees at two version vectors. Args: from_vv TEXT, to_vv TEXT
+/// (VersionVector JSON).
+///
+/// Returns the coalesced changes in the same JSON shape as the `changes` of a materialization
+/// outcome, each attributed to the latest op in `to_vv` but not in `from_vv` that touched the
+/// node.
+pub(super) unsafe extern "C" fn treecrdt_tree_diff(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if argc != 2 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_tree_diff expects 2 args (from_vv, to_vv)\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+    let from = unsafe { read_version_vector(args[0]) };
+    let to = unsafe { read_version_vector(args[1]) };
+    let (Some(from), Some(to)) = (from, to) else {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_tree_diff: invalid vv json\0".as_ptr() as *const c_char,
+        );
+        return;
+    };
+
+    match tree_diff(sqlite_context_db_handle(ctx), from, to) {
+        Ok(changes) => sqlite_result_json(ctx, &changes),
+        Err(rc) => sqlite_result_error_code(ctx, rc),
+    }
+}
@@ -1,16 +1,18 @@
+use super::access::access_control;
 use super::materialize::{json_outcome_from_core, JsonMaterializationOutcome};
 use super::node_store::SqliteNodeStore;
 use super::op_index::SqliteParentOpIndex;
 use super::op_storage::SqliteOpStorage;
 use super::payload_store::SqlitePayloadStore;
+use super::undo::{record_undo, record_undo_group};
 use super::util::{
     read_blob, read_blob16, read_optional_blob16, read_required_blob, read_text,
     sqlite_err_from_core, sqlite_result_json,
 };
 use super::*;
 use treecrdt_core::{
-    LamportClock, LocalFinalizePlan, LocalPlacement, MaterializationCursor, Operation,
-    OperationKind, PreparedLocalOp, ReplicaId, TreeCrdt,
+    LamportClock, LocalEdit, LocalFinalizePlan, LocalPlacement, MaterializationCursor, Operation,
+    OperationKind, PreparedLocalOp, ReplicaId, StagedLocalOp, TreeCrdt, UndoRecord,
 };
 
 #[derive(serde::Serialize)]
@@ -25,11 +27,15 @@ struct JsonOp {
     order_key: Option<Vec<u8>>,
     known_state: Option<Vec<u8>>,
     payload: Option<Vec<u8>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group: Option<treecrdt_core::OperationGroup>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field: Option<String>,
 }
 
 #[derive(serde::Serialize)]
 #[serde(rename_all = "camelCase")]
-struct JsonLocalOpResult {
+pub(super) struct JsonLocalOpResult {
     op: JsonOp,
     outcome: JsonMaterializationOutcome,
 }
@@ -61,6 +67,7 @@ fn json_op_from_operation(op: Operation) -> Result<JsonOp, c_int> {
     let replica = op.meta.id.replica.as_bytes().to_vec();
     let counter = op.meta.id.counter;
     let lamport = op.meta.lamport;
+    let group = op.meta.group;
     let known_state = op
         .meta
         .known_state
@@ -85,6 +92,8 @@ fn json_op_from_operation(op: Operation) -> Result<JsonOp, c_int> {
             order_key: Some(order_key),
             known_state: None,
             payload,
+            group,
+            field: None,
         }),
         OperationKind::Move {
             node,
@@ -101,6 +110,8 @@ fn json_op_from_operation(op: Operation) -> Result<JsonOp, c_int> {
             order_key: Some(order_key),
             known_state: None,
             payload: None,
+            group,
+            field: None,
         }),
         OperationKind::Delete { node } => Ok(JsonOp {
             replica,
@@ -113,6 +124,8 @@ fn json_op_from_operation(op: Operation) -> Result<JsonOp, c_int> {
             order_key: None,
             known_state,
             payload: None,
+            group,
+            field: None,
         }),
         OperationKind::Tombstone { node } => Ok(JsonOp {
             replica,
@@ -125,6 +138,22 @@ fn json_op_from_operation(op: Operation) -> Result<JsonOp, c_int> {
             order_key: None,
             known_state,
             payload: None,
+            group,
+            field: None,
+        }),
+        OperationKind::Restore { node } => Ok(JsonOp {
+            replica,
+            counter,
+            lamport,
+            kind: "restore".to_string(),
+            parent: None,
+            node: node.0.to_be_bytes(),
+            new_parent: None,
+            order_key: None,
+            known_state: None,
+            payload: None,
+            group,
+            field: None,
         }),
         OperationKind::Payload { node, payload } => Ok(JsonOp {
             replica,
@@ -135,8 +164,24 @@ fn json_op_from_operation(op: Operation) -> Result<JsonOp, c_int> {
             node: node.0.to_be_bytes(),
             new_parent: None,
             order_key: None,
-            known_state: None,
+            known_state,
             payload,
+            group,
+            field: None,
+        }),
+        OperationKind::PayloadField { node, field, value } => Ok(JsonOp {
+            replica,
+            counter,
+            lamport,
+            kind: "payload_field".to_string(),
+            parent: None,
+            node: node.0.to_be_bytes(),
+            new_parent: None,
+            order_key: None,
+            known_state: None,
+            payload: value,
+            group,
+            field: Some(field),
         }),
     }
 }
@@ -178,7 +223,7 @@ fn begin_local_core_op(
     };
     let storage = SqliteOpStorage::with_doc_id(db, doc_id.to_vec());
     let replica_id = ReplicaId::new(replica.to_vec());
-    let crdt = match TreeCrdt::with_stores(
+    let mut crdt = match TreeCrdt::with_stores(
         replica_id,
         storage,
         LamportClock::default(),
@@ -191,6 +236,9 @@ fn begin_local_core_op(
             return Err(SQLITE_ERROR as c_int);
         }
     };
+    if let Some(policy) = access_control(db) {
+        crdt.set_access_control(policy);
+    }
     Ok(LocalOpSession {
         db,
         doc_id: doc_id.to_vec(),
@@ -200,11 +248,20 @@ fn begin_local_core_op(
     })
 }
 
-fn finish_local_core_op(
-    mut session: LocalOpSession,
-    op: Operation,
-    plan: LocalFinalizePlan,
-) -> Result<JsonLocalOpResult, c_int> {
+/// Finalize the ops `session` committed and move the tree head to `last`. A failure here leaves
+/// the ops in place and forces a replay on the next read instead.
+fn finalize_local_core_ops<F>(
+    session: &mut LocalOpSession,
+    last: &Operation,
+    finalize: F,
+) -> Result<treecrdt_core::MaterializationOutcome, c_int>
+where
+    F: FnOnce(
+        &mut LocalCrdt,
+        &mut SqliteParentOpIndex,
+        u64,
+    ) -> treecrdt_core::Result<treecrdt_core::MaterializationOutcome>,
+{
     let mut post_materialization_ok = true;
     let mut head_seq = 0u64;
     let mut outcome = treecrdt_core::MaterializationOutcome::empty(0);
@@ -214,9 +271,7 @@ fn finish_local_core_op(
     }
     if post_materialization_ok {
         let finalize_rc = match SqliteParentOpIndex::prepare(session.db, session.doc_id.clone()) {
-            Ok(mut op_index) => session
-                .crdt
-                .finalize_local_with_outcome(&op, &mut op_index, head_seq, &plan)
+            Ok(mut op_index) => finalize(&mut session.crdt, &mut op_index, head_seq)
                 .map_err(|_| SQLITE_ERROR as c_int),
             Err(_) => Err(SQLITE_ERROR as c_int),
         };
@@ -227,9 +282,9 @@ fn finish_local_core_op(
     }
     let head = treecrdt_core::MaterializationHead {
         at: treecrdt_core::MaterializationKey {
-            lamport: op.meta.lamport,
-            replica: op.meta.id.replica.as_bytes(),
-            counter: op.meta.id.counter,
+            lamport: last.meta.lamport,
+            replica: last.meta.id.replica.as_bytes(),
+            counter: last.meta.id.counter,
         },
         seq: outcome.head_seq,
     };
@@ -246,21 +301,14 @@ fn finish_local_core_op(
             },
         )?;
     }
+    Ok(outcome)
+}
 
-    let op = match json_op_from_operation(op) {
+fn release_local_session<T>(session: LocalOpSession, out: Result<T, c_int>) -> Result<T, c_int> {
+    let out = match out {
         Ok(v) => v,
-        Err(rc) => {
-            sqlite_exec(
-                session.db,
-                session.rollback_sql.as_ptr(),
-                None,
-                null_mut(),
-                null_mut(),
-            );
-            return Err(rc);
-        }
+        Err(rc) => return Err(session.rollback(rc)),
     };
-
     let commit_rc = sqlite_exec(
         session.db,
         session.commit_sql.as_ptr(),
@@ -269,20 +317,63 @@ fn finish_local_core_op(
         null_mut(),
     );
     if commit_rc != SQLITE_OK as c_int {
-        sqlite_exec(
-            session.db,
-            session.rollback_sql.as_ptr(),
-            None,
-            null_mut(),
-            null_mut(),
-        );
-        return Err(commit_rc);
+        return Err(session.rollback(commit_rc));
     }
+    Ok(out)
+}
 
-    Ok(JsonLocalOpResult {
-        op,
-        outcome: json_outcome_from_core(&outcome),
+fn finish_local_core_op(
+    mut session: LocalOpSession,
+    op: Operation,
+    plan: LocalFinalizePlan,
+) -> Result<JsonLocalOpResult, c_int> {
+    let out = finalize_local_core_ops(&mut session, &op, |crdt, op_index, head_seq| {
+        crdt.finalize_local_with_outcome(&op, op_index, head_seq, &plan)
     })
+    .and_then(|outcome| {
+        Ok(JsonLocalOpResult {
+            op: json_op_from_operation(op)?,
+            outcome: json_outcome_from_core(&outcome),
+        })
+    });
+    release_local_session(session, out)
+}
+
+/// Mint, store and materialize one local op. `build` returning `None` leaves the db untouched.
+///
+/// Also returns the undo record of the committed op.
+fn commit_local_core_op<F>(
+    db: *mut sqlite3,
+    doc_id: &[u8],
+    replica: &[u8],
+    savepoint_name: &str,
+    build: F,
+) -> Result<Option<(JsonLocalOpResult, UndoRecord)>, c_int>
+where
+    F: FnOnce(&mut LocalCrdt) -> treecrdt_core::Result<Option<PreparedLocalOp>>,
+{
+    let mut session = begin_local_core_op(db, doc_id, replica, savepoint_name)?;
+    let prepared = match build(&mut session.crdt) {
+        Ok(Some(v)) => v,
+        Ok(None) => {
+            session.rollback(SQLITE_OK as c_int);
+            return Ok(None);
+        }
+        Err(err) => return Err(session.rollback(sqlite_err_from_core(err))),
+    };
+    let record = match session.crdt.undo_record(&prepared.op) {
+        Ok(v) => v,
+        Err(err) => return Err(session.rollback(sqlite_err_from_core(err))),
+    };
+    let (op, plan) = match session.crdt.commit_prepared_local(prepared) {
+        Ok(v) => v,
+        Err(err) => return Err(session.rollback(sqlite_err_from_core(err))),
+    };
+    let record = UndoRecord {
+        op: op.clone(),
+        ..record
+    };
+    finish_local_core_op(session, op, plan).map(|out| Some((out, record)))
 }
 
 fn run_local_core_op<F>(
@@ -295,16 +386,191 @@ fn run_local_core_op<F>(
 where
     F: FnOnce(&mut LocalCrdt) -> treecrdt_core::Result<PreparedLocalOp>,
 {
+    let (out, record) = commit_local_core_op(db, &doc_id, &replica, savepoint_name, |crdt| {
+        build(crdt).map(Some)
+    })?
+    .ok_or(SQLITE_ERROR as c_int)?;
+    record_undo(db, &replica, record);
+    Ok(out)
+}
+
+/// Apply the inverse of one of `replica`'s undo records, see [`treecrdt_core::TreeCrdt::local_inverse`].
+pub(super) fn run_inverse_op(
+    db: *mut sqlite3,
+    doc_id: &[u8],
+    replica: &[u8],
+    record: &UndoRecord,
+) -> Result<Option<(JsonLocalOpResult, UndoRecord)>, c_int> {
+    commit_local_core_op(db, doc_id, replica, "treecrdt_inverse", |crdt| {
+        crdt.prepare_inverse(record)
+    })
+}
+
+/// One edit of a `treecrdt_local_batch` call. Node ids are 16-byte arrays, like in op JSON.
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonLocalEdit {
+    kind: String,
+    #[serde(default)]
+    parent: Option<[u8; 16]>,
+    node: [u8; 16],
+    #[serde(default)]
+    new_parent: Option<[u8; 16]>,
+    #[serde(default)]
+    placement: Option<String>,
+    #[serde(default)]
+    after: Option<[u8; 16]>,
+    #[serde(default)]
+    payload: Option<Vec<u8>>,
+    #[serde(default)]
+    field: Option<String>,
+}
+
+impl JsonLocalEdit {
+    fn into_core(self) -> treecrdt_core::Result<LocalEdit> {
+        let to_node = |bytes: [u8; 16]| NodeId(u128::from_be_bytes(bytes));
+        let node = to_node(self.node);
+        let placement = || {
+            LocalPlacement::from_parts(
+                self.placement.as_deref().unwrap_or_default(),
+                self.after.map(to_node),
+            )
+        };
+        let missing = |field: &str| {
+            treecrdt_core::Error::InvalidOperation(format!("{} edit missing {field}", self.kind))
+        };
+        match self.kind.as_str() {
+            "insert" => Ok(LocalEdit::Insert {
+                parent: to_node(self.parent.ok_or_else(|| missing("parent"))?),
+                node,
+                placement: placement()?,
+                payload: self.payload.clone(),
+            }),
+            "move" => Ok(LocalEdit::Move {
+                node,
+                new_parent: to_node(self.new_parent.ok_or_else(|| missing("new_parent"))?),
+                placement: placement()?,
+            }),
+            "delete" => Ok(LocalEdit::Delete { node }),
+            "tombstone" => Ok(LocalEdit::Tombstone { node }),
+            "restore" => Ok(LocalEdit::Restore { node }),
+            "payload" => Ok(LocalEdit::Payload {
+                node,
+                payload: self.payload.clone(),
+            }),
+            "payload_field" => Ok(LocalEdit::PayloadField {
+                node,
+                field: self.field.clone().ok_or_else(|| missing("field"))?,
+                value: self.payload.clone(),
+            }),
+            _ => Err(treecrdt_core::Error::InvalidOperation(
+                "invalid edit kind".into(),
+            )),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonLocalBatchResult {
+    ops: Vec<JsonOp>,
+    outcome: JsonMaterializationOutcome,
+}
+
+/// Run `stage` against the materialized tree and commit the ops it staged in one savepoint,
+/// recorded as one undo transaction.
+fn run_local_core_staged<T>(
+    db: *mut sqlite3,
+    doc_id: Vec<u8>,
+    replica: Vec<u8>,
+    savepoint_name: &str,
+    stage: impl FnOnce(&mut LocalCrdt) -> treecrdt_core::Result<(Vec<StagedLocalOp>, T)>,
+) -> Result<(JsonLocalBatchResult, T), c_int> {
     let mut session = begin_local_core_op(db, &doc_id, &replica, savepoint_name)?;
-    let prepared = match build(&mut session.crdt) {
+    let (staged, extra) = match stage(&mut session.crdt) {
         Ok(v) => v,
         Err(err) => return Err(session.rollback(sqlite_err_from_core(err))),
     };
-    let (op, plan) = match session.crdt.commit_prepared_local(prepared) {
-        Ok(v) => v,
-        Err(err) => return Err(session.rollback(sqlite_err_from_core(err))),
+    let Some(last) = staged.last().map(|entry| entry.op.clone()) else {
+        let empty = JsonLocalBatchResult {
+            ops: Vec::new(),
+            outcome: json_outcome_from_core(&treecrdt_core::MaterializationOutcome::empty(0)),
+        };
+        return release_local_session(session, Ok((empty, extra)));
     };
-    finish_local_core_op(session, op, plan)
+    let out = finalize_local_core_ops(&mut session, &last, |crdt, op_index, head_seq| {
+        crdt.finalize_local_batch_with_outcome(&staged, op_index, head_seq)
+    })
+    .and_then(|outcome| {
+        Ok(JsonLocalBatchResult {
+            ops: staged
+                .iter()
+                .map(|entry| json_op_from_operation(entry.op.clone()))
+                .collect::<Result<_, _>>()?,
+            outcome: json_outcome_from_core(&outcome),
+        })
+    });
+    let out = release_local_session(session, out)?;
+    record_undo_group(
+        db,
+        &replica,
+        staged.into_iter().map(|entry| entry.undo).collect(),
+    );
+    Ok((out, extra))
+}
+
+fn run_local_core_batch(
+    db: *mut sqlite3,
+    doc_id: Vec<u8>,
+    replica: Vec<u8>,
+    edits: Vec<LocalEdit>,
+) -> Result<JsonLocalBatchResult, c_int> {
+    run_local_core_staged(db, doc_id, replica, "treecrdt_local_batch", |crdt| {
+        Ok((crdt.local_batch(edits)?, ()))
+    })
+    .map(|(out, ())| out)
+}
+
+/// One node of a `treecrdt_local_insert_many` call.
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonNewNode {
+    node: [u8; 16],
+    #[serde(default)]
+    payload: Option<Vec<u8>>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonDuplicatedNode {
+    source: [u8; 16],
+    copy: [u8; 16],
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonLocalDuplicateResult {
+    ops: Vec<JsonOp>,
+    outcome: JsonMaterializationOutcome,
+    mapping: Vec<JsonDuplicatedNode>,
+}
+
+const DUPLICATE_ID_DOMAIN: &[u8] = b"treecrdt/duplicate/v0";
+
+/// Id of the copy of `source` made by `replica` when its clock stood at `lamport`. Every local
+/// op ticks the clock, so each duplicate call gets its own ids.
+fn duplicate_node_id(doc_id: &[u8], replica: &[u8], lamport: Lamport, source: NodeId) -> NodeId {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(DUPLICATE_ID_DOMAIN);
+    hasher.update(&(doc_id.len() as u32).to_be_bytes());
+    hasher.update(doc_id);
+    hasher.update(&(replica.len() as u32).to_be_bytes());
+    hasher.update(replica);
+    hasher.update(&lamport.to_be_bytes());
+    hasher.update(&source.0.to_be_bytes());
+    let mut out = [0u8; 16];
+    out.copy_from_slice(&hasher.finalize().as_bytes()[..16]);
+    NodeId(u128::from_be_bytes(out))
 }
 
 pub(super) unsafe extern "C" fn treecrdt_local_insert(
@@ -583,6 +849,150 @@ pub(super) unsafe extern "C" fn treecrdt_local_delete(
     sqlite_result_json(ctx, &out);
 }
 
+/// Delete a node for good: unlike `treecrdt_local_delete`, concurrent edits under it do not revive
+/// it and it cannot be restored. Args: replica BLOB, node BLOB. Returns `{op, outcome}`.
+pub(super) unsafe extern "C" fn treecrdt_local_tombstone(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if let Err(rc) = ensure_api_initialized() {
+        sqlite_result_error_code(ctx, rc);
+        return;
+    }
+
+    if argc != 2 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_local_tombstone expects 2 args (replica,node)\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+    let replica = match read_required_blob(args[0]) {
+        Ok(v) => v,
+        Err(_) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_tombstone: NULL replica\0".as_ptr() as *const c_char,
+            );
+            return;
+        }
+    };
+    let node = match read_blob16(args[1]) {
+        Ok(v) => v,
+        Err(_) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_tombstone: node must be 16-byte BLOB\0".as_ptr() as *const c_char,
+            );
+            return;
+        }
+    };
+
+    let db = sqlite_context_db_handle(ctx);
+    let doc_id = match load_doc_id(db) {
+        Ok(Some(v)) => v,
+        Ok(None) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_tombstone: doc_id not set (call treecrdt_set_doc_id)\0".as_ptr()
+                    as *const c_char,
+            );
+            return;
+        }
+        Err(rc) => {
+            sqlite_result_error_code(ctx, rc);
+            return;
+        }
+    };
+
+    let node_id = NodeId(u128::from_be_bytes(node));
+    let out = match run_local_core_op(db, doc_id, replica, "treecrdt_local_tombstone", |crdt| {
+        crdt.prepare_local_tombstone(node_id)
+    }) {
+        Ok(v) => v,
+        Err(rc) => {
+            sqlite_result_error_code(ctx, rc);
+            return;
+        }
+    };
+    sqlite_result_json(ctx, &out);
+}
+
+/// Bring a deleted node back, together with any deleted ancestors it sits under. Args: replica
+/// BLOB, node BLOB. Returns `{op, outcome}`; fails if the node is not deleted.
+pub(super) unsafe extern "C" fn treecrdt_local_restore(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if let Err(rc) = ensure_api_initialized() {
+        sqlite_result_error_code(ctx, rc);
+        return;
+    }
+
+    if argc != 2 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_local_restore expects 2 args (replica,node)\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+    let replica = match read_required_blob(args[0]) {
+        Ok(v) => v,
+        Err(_) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_restore: NULL replica\0".as_ptr() as *const c_char,
+            );
+            return;
+        }
+    };
+    let node = match read_blob16(args[1]) {
+        Ok(v) => v,
+        Err(_) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_restore: node must be 16-byte BLOB\0".as_ptr() as *const c_char,
+            );
+            return;
+        }
+    };
+
+    let db = sqlite_context_db_handle(ctx);
+    let doc_id = match load_doc_id(db) {
+        Ok(Some(v)) => v,
+        Ok(None) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_restore: doc_id not set (call treecrdt_set_doc_id)\0".as_ptr()
+                    as *const c_char,
+            );
+            return;
+        }
+        Err(rc) => {
+            sqlite_result_error_code(ctx, rc);
+            return;
+        }
+    };
+
+    let node_id = NodeId(u128::from_be_bytes(node));
+    let out = match run_local_core_op(db, doc_id, replica, "treecrdt_local_restore", |crdt| {
+        crdt.prepare_local_restore(node_id)
+    }) {
+        Ok(v) => v,
+        Err(rc) => {
+            sqlite_result_error_code(ctx, rc);
+            return;
+        }
+    };
+    sqlite_result_json(ctx, &out);
+}
+
 pub(super) unsafe extern "C" fn treecrdt_local_payload(
     ctx: *mut sqlite3_context,
     argc: c_int,
@@ -654,3 +1064,575 @@ pub(super) unsafe extern "C" fn treecrdt_local_payload(
     };
     sqlite_result_json(ctx, &out);
 }
+
+/// Set or clear one field of a node's payload map. Args: replica BLOB, node BLOB, field TEXT,
+/// value BLOB|null. Other fields and the whole-node payload are left alone.
+pub(super) unsafe extern "C" fn treecrdt_local_payload_field(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if let Err(rc) = ensure_api_initialized() {
+        sqlite_result_error_code(ctx, rc);
+        return;
+    }
+
+    if argc != 4 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_local_payload_field expects 4 args (replica,node,field,value)\0".as_ptr()
+                as *const c_char,
+        );
+        return;
+    }
+
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+    let replica = match read_required_blob(args[0]) {
+        Ok(v) => v,
+        Err(_) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_payload_field: NULL replica\0".as_ptr() as *const c_char,
+            );
+            return;
+        }
+    };
+    let node = match read_blob16(args[1]) {
+        Ok(v) => v,
+        Err(_) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_payload_field: node must be 16-byte BLOB\0".as_ptr()
+                    as *const c_char,
+            );
+            return;
+        }
+    };
+    if unsafe { sqlite_value_type(args[2]) } == SQLITE_NULL as c_int {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_local_payload_field: NULL field\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+    let field = read_text(args[2]);
+    let value = read_blob(args[3]);
+
+    let db = sqlite_context_db_handle(ctx);
+    let doc_id = match load_doc_id(db) {
+        Ok(Some(v)) => v,
+        Ok(None) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_payload_field: doc_id not set (call treecrdt_set_doc_id)\0"
+                    .as_ptr() as *const c_char,
+            );
+            return;
+        }
+        Err(rc) => {
+            sqlite_result_error_code(ctx, rc);
+            return;
+        }
+    };
+
+    let node_id = NodeId(u128::from_be_bytes(node));
+    let out = match run_local_core_op(
+        db,
+        doc_id,
+        replica,
+        "treecrdt_local_payload_field",
+        |crdt| crdt.prepare_local_payload_field(node_id, &field, value.clone()),
+    ) {
+        Ok(v) => v,
+        Err(rc) => {
+            sqlite_result_error_code(ctx, rc);
+            return;
+        }
+    };
+    sqlite_result_json(ctx, &out);
+}
+
+/// Commit several local edits atomically. Args: replica BLOB, edits JSON array of
+/// `{kind, node, parent?, new_parent?, placement?, after?, payload?, field?}` objects, where `kind`
+/// is one of insert/move/delete/payload/payload_field and placement is first/last/after as in
+/// `treecrdt_local_insert`. A payload_field edit writes `payload` to `field`.
+///
+/// Every edit sees the ones before it, so an edit may place nodes after a node inserted earlier
+/// in the same batch. Returns `{ops, outcome}` with one merged materialization outcome; if any
+/// edit fails, nothing is committed.
+pub(super) unsafe extern "C" fn treecrdt_local_batch(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if let Err(rc) = ensure_api_initialized() {
+        sqlite_result_error_code(ctx, rc);
+        return;
+    }
+
+    if argc != 2 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_local_batch expects 2 args (replica,edits)\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+    let replica = match read_required_blob(args[0]) {
+        Ok(v) => v,
+        Err(_) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_batch: NULL replica\0".as_ptr() as *const c_char,
+            );
+            return;
+        }
+    };
+    let edits: Vec<JsonLocalEdit> = match serde_json::from_str(&read_text(args[1])) {
+        Ok(v) => v,
+        Err(_) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_batch failed to parse JSON array\0".as_ptr() as *const c_char,
+            );
+            return;
+        }
+    };
+    let edits = match edits
+        .into_iter()
+        .map(JsonLocalEdit::into_core)
+        .collect::<treecrdt_core::Result<Vec<_>>>()
+    {
+        Ok(v) => v,
+        Err(err) => {
+            sqlite_result_error_code(ctx, sqlite_err_from_core(err));
+            return;
+        }
+    };
+    if edits.is_empty() {
+        sqlite_result_json(
+            ctx,
+            &JsonLocalBatchResult {
+                ops: Vec::new(),
+                outcome: json_outcome_from_core(&treecrdt_core::MaterializationOutcome::empty(0)),
+            },
+        );
+        return;
+    }
+
+    let db = sqlite_context_db_handle(ctx);
+    let doc_id = match load_doc_id(db) {
+        Ok(Some(v)) => v,
+        Ok(None) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_batch: doc_id not set (call treecrdt_set_doc_id)\0".as_ptr()
+                    as *const c_char,
+            );
+            return;
+        }
+        Err(rc) => {
+            sqlite_result_error_code(ctx, rc);
+            return;
+        }
+    };
+
+    match run_local_core_batch(db, doc_id, replica, edits) {
+        Ok(out) => sqlite_result_json(ctx, &out),
+        Err(rc) => sqlite_result_error_code(ctx, rc),
+    }
+}
+
+/// Insert a run of sibling nodes atomically. Args: replica BLOB, parent BLOB, placement TEXT,
+/// after BLOB|null, nodes JSON array of `{node, payload?}` objects. The first node goes to the
+/// placement (as in `treecrdt_local_insert`) and the rest follow it in array order.
+///
+/// The order keys are spaced evenly between the neighbours, so they stay short however many
+/// nodes are inserted. Returns `{ops, outcome}`; if any insert fails, nothing is committed.
+pub(super) unsafe extern "C" fn treecrdt_local_insert_many(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if let Err(rc) = ensure_api_initialized() {
+        sqlite_result_error_code(ctx, rc);
+        return;
+    }
+
+    if argc != 5 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_local_insert_many expects 5 args (replica,parent,placement,after,nodes)\0"
+                .as_ptr() as *const c_char,
+        );
+        return;
+    }
+
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+    let replica = match read_required_blob(args[0]) {
+        Ok(v) => v,
+        Err(_) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_insert_many: NULL replica\0".as_ptr() as *const c_char,
+            );
+            return;
+        }
+    };
+    let parent = match read_blob16(args[1]) {
+        Ok(v) => v,
+        Err(_) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_insert_many: parent must be 16-byte BLOB\0".as_ptr()
+                    as *const c_char,
+            );
+            return;
+        }
+    };
+    let placement = read_text(args[2]);
+    let after = match read_optional_blob16(args[3]) {
+        Ok(v) => v,
+        Err(_) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_insert_many: after must be 16-byte BLOB or NULL\0".as_ptr()
+                    as *const c_char,
+            );
+            return;
+        }
+    };
+    let nodes: Vec<JsonNewNode> = match serde_json::from_str(&read_text(args[4])) {
+        Ok(v) => v,
+        Err(_) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_insert_many failed to parse JSON array\0".as_ptr()
+                    as *const c_char,
+            );
+            return;
+        }
+    };
+    if nodes.is_empty() {
+        sqlite_result_json(
+            ctx,
+            &JsonLocalBatchResult {
+                ops: Vec::new(),
+                outcome: json_outcome_from_core(&treecrdt_core::MaterializationOutcome::empty(0)),
+            },
+        );
+        return;
+    }
+
+    let db = sqlite_context_db_handle(ctx);
+    let doc_id = match load_doc_id(db) {
+        Ok(Some(v)) => v,
+        Ok(None) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_insert_many: doc_id not set (call treecrdt_set_doc_id)\0".as_ptr()
+                    as *const c_char,
+            );
+            return;
+        }
+        Err(rc) => {
+            sqlite_result_error_code(ctx, rc);
+            return;
+        }
+    };
+
+    let parent_id = NodeId(u128::from_be_bytes(parent));
+    let after_id = after.map(|id| NodeId(u128::from_be_bytes(id)));
+    let placement = match LocalPlacement::from_parts(placement.as_str(), after_id) {
+        Ok(v) => v,
+        Err(err) => {
+            sqlite_result_error_code(ctx, sqlite_err_from_core(err));
+            return;
+        }
+    };
+    let nodes = nodes
+        .into_iter()
+        .map(|entry| (NodeId(u128::from_be_bytes(entry.node)), entry.payload));
+    let result = run_local_core_staged(db, doc_id, replica, "treecrdt_local_insert_many", |crdt| {
+        Ok((crdt.local_insert_many(parent_id, placement, nodes)?, ()))
+    });
+    match result {
+        Ok((out, ())) => sqlite_result_json(ctx, &out),
+        Err(rc) => sqlite_result_error_code(ctx, rc),
+    }
+}
+
+/// Re-key the runs of overlong order keys among a parent's visible children to short, evenly
+/// spaced keys without changing their order. Args: replica BLOB, parent BLOB.
+///
+/// Emits one move per re-keyed child, committed and undone together; children around a run keep
+/// their keys. Returns `{ops, outcome}`; a parent without overlong keys yields no ops.
+pub(super) unsafe extern "C" fn treecrdt_local_rebalance(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if let Err(rc) = ensure_api_initialized() {
+        sqlite_result_error_code(ctx, rc);
+        return;
+    }
+
+    if argc != 2 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_local_rebalance expects 2 args (replica,parent)\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+    let replica = match read_required_blob(args[0]) {
+        Ok(v) => v,
+        Err(_) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_rebalance: NULL replica\0".as_ptr() as *const c_char,
+            );
+            return;
+        }
+    };
+    let parent = match read_blob16(args[1]) {
+        Ok(v) => v,
+        Err(_) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_rebalance: parent must be 16-byte BLOB\0".as_ptr()
+                    as *const c_char,
+            );
+            return;
+        }
+    };
+
+    let db = sqlite_context_db_handle(ctx);
+    let doc_id = match load_doc_id(db) {
+        Ok(Some(v)) => v,
+        Ok(None) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_rebalance: doc_id not set (call treecrdt_set_doc_id)\0".as_ptr()
+                    as *const c_char,
+            );
+            return;
+        }
+        Err(rc) => {
+            sqlite_result_error_code(ctx, rc);
+            return;
+        }
+    };
+
+    let parent_id = NodeId(u128::from_be_bytes(parent));
+    let result = run_local_core_staged(db, doc_id, replica, "treecrdt_local_rebalance", |crdt| {
+        Ok((crdt.local_rebalance(parent_id)?, ()))
+    });
+    match result {
+        Ok((out, ())) => sqlite_result_json(ctx, &out),
+        Err(rc) => sqlite_result_error_code(ctx, rc),
+    }
+}
+
+/// Put a parent's visible children in a new order with as few moves as possible. Args: replica
+/// BLOB, parent BLOB, children TEXT (JSON array listing every visible child id once, as 16-byte
+/// arrays, in the desired order).
+///
+/// Children already in the right relative order keep their keys. Returns `{ops, outcome}`; an
+/// unchanged order yields no ops.
+pub(super) unsafe extern "C" fn treecrdt_local_reorder(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if let Err(rc) = ensure_api_initialized() {
+        sqlite_result_error_code(ctx, rc);
+        return;
+    }
+
+    if argc != 3 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_local_reorder expects 3 args (replica,parent,children)\0".as_ptr()
+                as *const c_char,
+        );
+        return;
+    }
+
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+    let replica = match read_required_blob(args[0]) {
+        Ok(v) => v,
+        Err(_) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_reorder: NULL replica\0".as_ptr() as *const c_char,
+            );
+            return;
+        }
+    };
+    let parent = match read_blob16(args[1]) {
+        Ok(v) => v,
+        Err(_) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_reorder: parent must be 16-byte BLOB\0".as_ptr() as *const c_char,
+            );
+            return;
+        }
+    };
+    let children: Vec<[u8; 16]> = match serde_json::from_str(&read_text(args[2])) {
+        Ok(v) => v,
+        Err(_) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_reorder failed to parse JSON array\0".as_ptr() as *const c_char,
+            );
+            return;
+        }
+    };
+
+    let db = sqlite_context_db_handle(ctx);
+    let doc_id = match load_doc_id(db) {
+        Ok(Some(v)) => v,
+        Ok(None) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_reorder: doc_id not set (call treecrdt_set_doc_id)\0".as_ptr()
+                    as *const c_char,
+            );
+            return;
+        }
+        Err(rc) => {
+            sqlite_result_error_code(ctx, rc);
+            return;
+        }
+    };
+
+    let parent_id = NodeId(u128::from_be_bytes(parent));
+    let desired: Vec<NodeId> =
+        children.into_iter().map(|id| NodeId(u128::from_be_bytes(id))).collect();
+    let result = run_local_core_staged(db, doc_id, replica, "treecrdt_local_reorder", |crdt| {
+        Ok((crdt.local_reorder(parent_id, &desired)?, ()))
+    });
+    match result {
+        Ok((out, ())) => sqlite_result_json(ctx, &out),
+        Err(rc) => sqlite_result_error_code(ctx, rc),
+    }
+}
+
+/// Copy the visible subtree of a node. Args: replica BLOB, source BLOB, new_parent BLOB,
+/// placement TEXT, after BLOB|null (placement as in `treecrdt_local_insert`).
+///
+/// Copies get fresh ids derived from the document, replica and clock, carry their original's
+/// payload and keep sibling order. Returns `{ops, outcome, mapping}` where `mapping` lists
+/// `{source, copy}` per copied node; like `treecrdt_local_batch`, it is all or nothing.
+pub(super) unsafe extern "C" fn treecrdt_local_duplicate(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if let Err(rc) = ensure_api_initialized() {
+        sqlite_result_error_code(ctx, rc);
+        return;
+    }
+
+    if argc != 5 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_local_duplicate expects 5 args (replica,source,new_parent,placement,after)\0"
+                .as_ptr() as *const c_char,
+        );
+        return;
+    }
+
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+    let replica = match read_required_blob(args[0]) {
+        Ok(v) => v,
+        Err(_) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_duplicate: NULL replica\0".as_ptr() as *const c_char,
+            );
+            return;
+        }
+    };
+    let (source, new_parent) = match (read_blob16(args[1]), read_blob16(args[2])) {
+        (Ok(source), Ok(new_parent)) => (source, new_parent),
+        _ => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_duplicate: source and new_parent must be 16-byte BLOBs\0".as_ptr()
+                    as *const c_char,
+            );
+            return;
+        }
+    };
+    let placement = read_text(args[3]);
+    let after = match read_optional_blob16(args[4]) {
+        Ok(v) => v,
+        Err(_) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_duplicate: after must be 16-byte BLOB or NULL\0".as_ptr()
+                    as *const c_char,
+            );
+            return;
+        }
+    };
+
+    let db = sqlite_context_db_handle(ctx);
+    let doc_id = match load_doc_id(db) {
+        Ok(Some(v)) => v,
+        Ok(None) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_local_duplicate: doc_id not set (call treecrdt_set_doc_id)\0".as_ptr()
+                    as *const c_char,
+            );
+            return;
+        }
+        Err(rc) => {
+            sqlite_result_error_code(ctx, rc);
+            return;
+        }
+    };
+
+    let source_id = NodeId(u128::from_be_bytes(source));
+    let new_parent_id = NodeId(u128::from_be_bytes(new_parent));
+    let after_id = after.map(|id| NodeId(u128::from_be_bytes(id)));
+    let placement = match LocalPlacement::from_parts(placement.as_str(), after_id) {
+        Ok(v) => v,
+        Err(err) => {
+            sqlite_result_error_code(ctx, sqlite_err_from_core(err));
+            return;
+        }
+    };
+    let id_doc = doc_id.clone();
+    let id_replica = replica.clone();
+    let result = run_local_core_staged(db, doc_id, replica, "treecrdt_local_duplicate", |crdt| {
+        let lamport = crdt.lamport();
+        let copy = crdt.local_duplicate(source_id, new_parent_id, placement, |node| {
+            duplicate_node_id(&id_doc, &id_replica, lamport, node)
+        })?;
+        Ok((copy.staged, copy.mapping))
+    });
+    match result {
+        Ok((out, mapping)) => sqlite_result_json(
+            ctx,
+            &JsonLocalDuplicateResult {
+                ops: out.ops,
+                outcome: out.outcome,
+                mapping: mapping
+                    .into_iter()
+                    .map(|(source, copy)| JsonDuplicatedNode {
+                        source: source.0.to_be_bytes(),
+                        copy: copy.0.to_be_bytes(),
+                    })
+                    .collect(),
+            },
+        ),
+        Err(rc) => sqlite_result_error_code(ctx, rc),
+    }
+}
@@ -1,3 +1,4 @@
+use super::access::{access_control, check_ops};
 use super::append::JsonAppendOp;
 use super::node_store::SqliteNodeStore;
 use super::op_index::SqliteParentOpIndex;
@@ -26,7 +27,7 @@ pub(super) struct JsonMaterializationOutcome {
     rename_all = "camelCase",
     rename_all_fields = "camelCase"
 )]
-enum JsonMaterializationChange {
+pub(super) enum JsonMaterializationChange {
     Insert {
         node: String,
         parent_after: String,
@@ -60,24 +61,31 @@ enum JsonMaterializationChange {
         #[serde(skip_serializing_if = "Option::is_none")]
         source: Option<JsonMaterializationSource>,
     },
+    PayloadField {
+        node: String,
+        field: String,
+        value: Option<Vec<u8>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        source: Option<JsonMaterializationSource>,
+    },
 }
 
 #[derive(serde::Serialize)]
 #[serde(rename_all = "camelCase")]
-struct JsonMaterializationSource {
+pub(super) struct JsonMaterializationSource {
     operation: JsonMaterializationSourceOperation,
 }
 
 #[derive(serde::Serialize)]
 #[serde(rename_all = "camelCase")]
-struct JsonMaterializationSourceOperation {
+pub(super) struct JsonMaterializationSourceOperation {
     id: JsonOperationId,
     lamport: u64,
 }
 
 #[derive(serde::Serialize)]
 #[serde(rename_all = "camelCase")]
-struct JsonOperationId {
+pub(super) struct JsonOperationId {
     replica: Vec<u8>,
     counter: u64,
 }
@@ -89,11 +97,13 @@ fn json_operation_id(id: &OperationId) -> JsonOperationId {
     }
 }
 
-fn node_hex(node: NodeId) -> String {
+pub(super) fn node_hex(node: NodeId) -> String {
     format!("{:032x}", node.0)
 }
 
-fn json_source(source: &Option<MaterializationSource>) -> Option<JsonMaterializationSource> {
+pub(super) fn json_source(
+    source: &Option<MaterializationSource>,
+) -> Option<JsonMaterializationSource> {
     source.as_ref().map(|source| JsonMaterializationSource {
         operation: JsonMaterializationSourceOperation {
             id: json_operation_id(&source.operation.id),
@@ -105,8 +115,16 @@ fn json_source(source: &Option<MaterializationSource>) -> Option<JsonMaterializa
 pub(super) fn json_outcome_from_core(
     outcome: &MaterializationOutcome,
 ) -> JsonMaterializationOutcome {
-    let changes = outcome
-        .changes
+    JsonMaterializationOutcome {
+        head_seq: outcome.head_seq,
+        changes: json_changes_from_core(&outcome.changes),
+    }
+}
+
+pub(super) fn json_changes_from_core(
+    changes: &[MaterializationChange],
+) -> Vec<JsonMaterializationChange> {
+    changes
         .iter()
         .map(|change| match change {
             MaterializationChange::Insert {
@@ -160,12 +178,19 @@ pub(super) fn json_outcome_from_core(
                 payload: payload.clone(),
                 source: json_source(source),
             },
+            MaterializationChange::PayloadField {
+                node,
+                field,
+                value,
+                source,
+            } => JsonMaterializationChange::PayloadField {
+                node: node_hex(*node),
+                field: field.clone(),
+                value: value.clone(),
+                source: json_source(source),
+            },
         })
-        .collect();
-    JsonMaterializationOutcome {
-        head_seq: outcome.head_seq,
-        changes,
-    }
+        .collect()
 }
 
 fn parse_node_id(bytes: &[u8]) -> Result<NodeId, c_int> {
@@ -184,7 +209,9 @@ fn parse_optional_node_id(bytes: &Option<Vec<u8>>) -> Result<Option<NodeId>, c_i
     }
 }
 
-fn json_append_op_to_operation(op: &JsonAppendOp) -> Result<treecrdt_core::Operation, c_int> {
+pub(super) fn json_append_op_to_operation(
+    op: &JsonAppendOp,
+) -> Result<treecrdt_core::Operation, c_int> {
     use treecrdt_core::{Operation, OperationId, OperationKind, OperationMetadata, ReplicaId};
 
     let node = parse_node_id(&op.node)?;
@@ -219,11 +246,21 @@ fn json_append_op_to_operation(op: &JsonAppendOp) -> Result<treecrdt_core::Opera
             Some(parsed_known_state.ok_or(SQLITE_ERROR as c_int)?),
         ),
         "tombstone" => (OperationKind::Tombstone { node }, parsed_known_state),
+        "restore" => (OperationKind::Restore { node }, None),
+        // Multi-value payload writes carry what their author had seen.
         "payload" => (
             OperationKind::Payload {
                 node,
                 payload: op.payload.clone(),
             },
+            parsed_known_state,
+        ),
+        "payload_field" => (
+            OperationKind::PayloadField {
+                node,
+                field: op.field.clone().ok_or(SQLITE_ERROR as c_int)?,
+                value: op.payload.clone(),
+            },
             None,
         ),
         _ => return Err(SQLITE_ERROR as c_int),
@@ -237,6 +274,7 @@ fn json_append_op_to_operation(op: &JsonAppendOp) -> Result<treecrdt_core::Opera
             },
             lamport: op.lamport,
             known_state,
+            group: op.group,
         },
         kind,
     })
@@ -383,11 +421,28 @@ pub(super) fn append_ops_impl(
     savepoint_name: &str,
     ops: &[JsonAppendOp],
 ) -> Result<MaterializationOutcome, c_int> {
-    if ops.is_empty() {
+    let operations =
+        ops.iter().map(json_append_op_to_operation).collect::<Result<Vec<_>, c_int>>()?;
+    append_operations_impl(db, doc_id, savepoint_name, operations)
+}
+
+pub(super) fn append_operations_impl(
+    db: *mut sqlite3,
+    doc_id: &[u8],
+    savepoint_name: &str,
+    operations: Vec<treecrdt_core::Operation>,
+) -> Result<MaterializationOutcome, c_int> {
+    if operations.is_empty() {
         let meta = load_tree_meta(db)?;
         return Ok(MaterializationOutcome::empty(meta.state().head_seq()));
     }
 
+    if access_control(db).is_some() {
+        // Scope checks walk materialized ancestry, so it has to be current before we decide.
+        ensure_materialized(db)?;
+        check_ops(db, &operations).map_err(sqlite_err_from_core)?;
+    }
+
     let meta = load_tree_meta(db)?;
 
     let begin = CString::new(format!("SAVEPOINT {savepoint_name}")).expect("savepoint begin");
@@ -402,17 +457,9 @@ pub(super) fn append_ops_impl(
     }
 
     let mut storage = super::op_storage::SqliteOpStorage::with_doc_id(db, doc_id.to_vec());
-    let mut inserted_ops: Vec<treecrdt_core::Operation> = Vec::with_capacity(ops.len());
-
-    for op in ops {
-        let operation = match json_append_op_to_operation(op) {
-            Ok(v) => v,
-            Err(rc) => {
-                sqlite_exec(db, rollback.as_ptr(), None, null_mut(), null_mut());
-                return Err(rc);
-            }
-        };
+    let mut inserted_ops: Vec<treecrdt_core::Operation> = Vec::with_capacity(operations.len());
 
+    for operation in operations {
         let inserted_now = match storage.apply(operation.clone()) {
             Ok(v) => v,
             Err(err) => {
@@ -424,13 +471,12 @@ pub(super) fn append_ops_impl(
             inserted_ops.push(operation);
         }
     }
+    let payloads = SqlitePayloadStore::prepare(db).map_err(|_| SQLITE_ERROR as c_int)?;
     let apply_result = orchestrate_persisted_remote_append(
         &meta,
         inserted_ops,
-        {
-            let payloads = SqlitePayloadStore::prepare(db).map_err(|_| SQLITE_ERROR as c_int)?;
-            move |node| payloads.last_writer(node).map_err(sqlite_err_from_core)
-        },
+        payloads.mode(),
+        move |node| payloads.last_writer(node).map_err(sqlite_err_from_core),
         |meta, inserted| materialize_inserted_ops(db, doc_id, meta, inserted),
         |head| update_tree_meta_head(db, Some(head)),
         |frontier| set_tree_meta_replay_frontier(db, frontier),
@@ -0,0 +1,403 @@
+use super::access::check_read;
+use super::materialize::node_hex;
+use super::util::{read_blob16, sqlite_err_from_core, sqlite_result_json};
+use super::*;
+
+use std::collections::HashMap;
+
+// Each function answers with one recursive query instead of a round trip per level. Visibility
+// follows `treecrdt_core::navigation`: the path up to the root (or lost+found) must not pass a
+// tombstoned node.
+
+#[derive(serde::Serialize)]
+struct JsonDescendant {
+    node: String,
+    depth: u64,
+}
+
+/// Prepare `sql` with `blobs` bound to `?1`, `?2`, ...
+fn prepare_with_blobs(
+    db: *mut sqlite3,
+    sql: &str,
+    blobs: &[&[u8]],
+) -> Result<*mut sqlite3_stmt, c_int> {
+    let sql = CString::new(sql).expect("static sql");
+    let mut stmt: *mut sqlite3_stmt = null_mut();
+    let rc = sqlite_prepare_v2(db, sql.as_ptr(), -1, &mut stmt, null_mut());
+    if rc != SQLITE_OK as c_int {
+        return Err(rc);
+    }
+    for (i, blob) in blobs.iter().enumerate() {
+        let bind_rc = unsafe {
+            sqlite_bind_blob(
+                stmt,
+                i as c_int + 1,
+                blob.as_ptr() as *const c_void,
+                blob.len() as c_int,
+                None,
+            )
+        };
+        if bind_rc != SQLITE_OK as c_int {
+            unsafe { sqlite_finalize(stmt) };
+            return Err(bind_rc);
+        }
+    }
+    Ok(stmt)
+}
+
+unsafe fn column_node(stmt: *mut sqlite3_stmt, col: c_int) -> Result<Option<NodeId>, c_int> {
+    if unsafe { sqlite_column_type(stmt, col) } == SQLITE_NULL as c_int {
+        return Ok(None);
+    }
+    let ptr = unsafe { sqlite_column_blob(stmt, col) } as *const u8;
+    let len = unsafe { sqlite_column_bytes(stmt, col) } as usize;
+    if ptr.is_null() || len != 16 {
+        return Err(SQLITE_ERROR as c_int);
+    }
+    let bytes: [u8; 16] = unsafe { slice::from_raw_parts(ptr, len) }
+        .try_into()
+        .map_err(|_| SQLITE_ERROR as c_int)?;
+    Ok(Some(NodeId(u128::from_be_bytes(bytes))))
+}
+
+/// Step `stmt` to the end, handing each row to `row`, then finalize it.
+fn for_each_row(
+    stmt: *mut sqlite3_stmt,
+    mut row: impl FnMut(*mut sqlite3_stmt) -> Result<(), c_int>,
+) -> Result<(), c_int> {
+    loop {
+        let step_rc = unsafe { sqlite_step(stmt) };
+        if step_rc == SQLITE_DONE as c_int {
+            break;
+        }
+        if step_rc != SQLITE_ROW as c_int {
+            unsafe { sqlite_finalize(stmt) };
+            return Err(step_rc);
+        }
+        if let Err(rc) = row(stmt) {
+            unsafe { sqlite_finalize(stmt) };
+            return Err(rc);
+        }
+    }
+    let finalize_rc = unsafe { sqlite_finalize(stmt) };
+    if finalize_rc != SQLITE_OK as c_int {
+        return Err(finalize_rc);
+    }
+    Ok(())
+}
+
+fn is_root(node: NodeId) -> bool {
+    node == NodeId::ROOT || node == NodeId::LOST_AND_FOUND
+}
+
+/// The parents of `node`, nearest first; `None` if it is not visible.
+fn ancestors(db: *mut sqlite3, node: NodeId) -> Result<Option<Vec<NodeId>>, c_int> {
+    if is_root(node) {
+        return Ok(Some(Vec::new()));
+    }
+    // UNION rather than UNION ALL, so even a corrupted parent cycle ends the walk.
+    let stmt = prepare_with_blobs(
+        db,
+        "WITH RECURSIVE up(node, parent, tombstone) AS ( \
+           SELECT node, parent, tombstone FROM tree_nodes WHERE node = ?1 \
+           UNION \
+           SELECT n.node, n.parent, n.tombstone FROM tree_nodes n JOIN up ON n.node = up.parent \
+           WHERE up.tombstone = 0 AND up.node NOT IN (?2, ?3) \
+         ) \
+         SELECT node, parent, tombstone FROM up",
+        &[
+            &node.0.to_be_bytes(),
+            &NodeId::ROOT.0.to_be_bytes(),
+            &NodeId::LOST_AND_FOUND.0.to_be_bytes(),
+        ],
+    )?;
+    let mut rows = HashMap::new();
+    for_each_row(stmt, |stmt| {
+        let node = unsafe { column_node(stmt, 0) }?.ok_or(SQLITE_ERROR as c_int)?;
+        let parent = unsafe { column_node(stmt, 1) }?;
+        let tombstone = unsafe { sqlite_column_int64(stmt, 2) } != 0;
+        rows.insert(node, (parent, tombstone));
+        Ok(())
+    })?;
+
+    let mut out = Vec::new();
+    let mut current = node;
+    while !is_root(current) {
+        let Some(&(parent, tombstone)) = rows.get(&current) else {
+            return Ok(None);
+        };
+        let Some(parent) = parent else {
+            return Ok(None);
+        };
+        if current == NodeId::TRASH || tombstone || parent == node || out.contains(&parent) {
+            return Ok(None);
+        }
+        out.push(parent);
+        current = parent;
+    }
+    Ok(Some(out))
+}
+
+/// Visible nodes below `root` in pre-order, with their depth below it.
+fn descendants(
+    db: *mut sqlite3,
+    root: NodeId,
+    max_depth: Option<i64>,
+) -> Result<Vec<(NodeId, u64)>, c_int> {
+    if max_depth.is_some_and(|max_depth| max_depth < 1) || ancestors(db, root)?.is_none() {
+        return Ok(Vec::new());
+    }
+    // `path` spells out the (order_key, node) of every level, so sorting by it is the pre-order
+    // walk: the separators sort below any hex digit, so shorter order keys still come first.
+    let stmt = prepare_with_blobs(
+        db,
+        "WITH RECURSIVE down(node, depth, path) AS ( \
+           SELECT node, 1, hex(order_key) || ',' || hex(node) || '.' FROM tree_nodes \
+           WHERE parent = ?1 AND tombstone = 0 \
+           UNION ALL \
+           SELECT n.node, down.depth + 1, \
+                  down.path || hex(n.order_key) || ',' || hex(n.node) || '.' \
+           FROM tree_nodes n JOIN down ON n.parent = down.node \
+           WHERE n.tombstone = 0 AND (?2 IS NULL OR down.depth < ?2) \
+         ) \
+         SELECT node, depth FROM down ORDER BY path",
+        &[&root.0.to_be_bytes()],
+    )?;
+    let bind_rc = unsafe {
+        match max_depth {
+            Some(max_depth) => sqlite_bind_int64(stmt, 2, max_depth),
+            None => sqlite_bind_null(stmt, 2),
+        }
+    };
+    if bind_rc != SQLITE_OK as c_int {
+        unsafe { sqlite_finalize(stmt) };
+        return Err(bind_rc);
+    }
+    let mut out = Vec::new();
+    for_each_row(stmt, |stmt| {
+        let node = unsafe { column_node(stmt, 0) }?.ok_or(SQLITE_ERROR as c_int)?;
+        let depth = unsafe { sqlite_column_int64(stmt, 1) }.max(0) as u64;
+        out.push((node, depth));
+        Ok(())
+    })?;
+    Ok(out)
+}
+
+fn index_in_parent(db: *mut sqlite3, node: NodeId) -> Result<Option<i64>, c_int> {
+    let Some(parent) = ancestors(db, node)?.and_then(|ancestors| ancestors.first().copied()) else {
+        return Ok(None);
+    };
+    let stmt = prepare_with_blobs(
+        db,
+        "SELECT COUNT(*) FROM tree_nodes s, tree_nodes n \
+         WHERE n.node = ?1 AND s.parent = ?2 AND s.tombstone = 0 \
+           AND (COALESCE(s.order_key, X''), s.node) < (COALESCE(n.order_key, X''), n.node)",
+        &[&node.0.to_be_bytes(), &parent.0.to_be_bytes()],
+    )?;
+    let mut index = None;
+    for_each_row(stmt, |stmt| {
+        index = Some(unsafe { sqlite_column_int64(stmt, 0) });
+        Ok(())
+    })?;
+    Ok(index)
+}
+
+/// Read the 16-byte node in `arg` and prepare to answer about it: catch materialization up and
+/// check read access. Reports the error on `ctx` and returns `None` on failure.
+fn read_node_arg(
+    ctx: *mut sqlite3_context,
+    arg: *mut sqlite3_value,
+    error: &'static [u8],
+) -> Option<(*mut sqlite3, NodeId)> {
+    let Ok(bytes) = read_blob16(arg) else {
+        sqlite_result_error(ctx, error.as_ptr() as *const c_char);
+        return None;
+    };
+    let node = NodeId(u128::from_be_bytes(bytes));
+    let db = sqlite_context_db_handle(ctx);
+    if let Err(rc) = ensure_materialized(db) {
+        sqlite_result_error_code(ctx, rc);
+        return None;
+    }
+    if let Err(err) = check_read(db, node) {
+        sqlite_result_error_code(ctx, sqlite_err_from_core(err));
+        return None;
+    }
+    Some((db, node))
+}
+
+/// The parents of a node, nearest first up to the root. Args: node BLOB.
+///
+/// Returns a JSON array of hex node ids; empty for the root and for deleted or unknown nodes.
+pub(super) unsafe extern "C" fn treecrdt_ancestors(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if argc != 1 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_ancestors expects 1 arg (node)\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+    let Some((db, node)) = read_node_arg(
+        ctx,
+        args[0],
+        b"treecrdt_ancestors: node must be 16-byte BLOB\0",
+    ) else {
+        return;
+    };
+    match ancestors(db, node) {
+        Ok(ancestors) => {
+            let ancestors: Vec<String> =
+                ancestors.unwrap_or_default().into_iter().map(node_hex).collect();
+            sqlite_result_json(ctx, &ancestors)
+        }
+        Err(rc) => sqlite_result_error_code(ctx, rc),
+    }
+}
+
+/// How many parents a node has up to the root. Args: node BLOB.
+///
+/// Returns 0 for the root and NULL for deleted or unknown nodes.
+pub(super) unsafe extern "C" fn treecrdt_depth(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if argc != 1 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_depth expects 1 arg (node)\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+    let Some((db, node)) =
+        read_node_arg(ctx, args[0], b"treecrdt_depth: node must be 16-byte BLOB\0")
+    else {
+        return;
+    };
+    match ancestors(db, node) {
+        Ok(Some(ancestors)) => sqlite_result_int(ctx, ancestors.len() as c_int),
+        Ok(None) => sqlite_result_null(ctx),
+        Err(rc) => sqlite_result_error_code(ctx, rc),
+    }
+}
+
+/// Whether a visible node sits somewhere below another. Args: node BLOB, ancestor BLOB.
+///
+/// Returns 1 or 0; a node is not its own descendant.
+pub(super) unsafe extern "C" fn treecrdt_is_descendant(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if argc != 2 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_is_descendant expects 2 args (node,ancestor)\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+    let Ok(ancestor) = read_blob16(args[1]) else {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_is_descendant: ancestor must be 16-byte BLOB\0".as_ptr() as *const c_char,
+        );
+        return;
+    };
+    let Some((db, node)) = read_node_arg(
+        ctx,
+        args[0],
+        b"treecrdt_is_descendant: node must be 16-byte BLOB\0",
+    ) else {
+        return;
+    };
+    let ancestor = NodeId(u128::from_be_bytes(ancestor));
+    match ancestors(db, node) {
+        Ok(ancestors) => sqlite_result_int(
+            ctx,
+            ancestors.is_some_and(|ancestors| ancestors.contains(&ancestor)) as c_int,
+        ),
+        Err(rc) => sqlite_result_error_code(ctx, rc),
+    }
+}
+
+/// The visible subtree below a node, in the order a tree view renders it. Args: root BLOB,
+/// max_depth INTEGER (NULL for no limit).
+///
+/// Returns `[{node, depth}]` in pre-order, `depth` counting from 1 for the root's children.
+/// Empty if the root is deleted or unknown.
+pub(super) unsafe extern "C" fn treecrdt_descendants(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if argc != 2 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_descendants expects 2 args (root,max_depth)\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+    let max_depth = if unsafe { sqlite_value_type(args[1]) } == SQLITE_NULL as c_int {
+        None
+    } else {
+        Some(unsafe { sqlite_value_int64(args[1]) })
+    };
+    let Some((db, root)) = read_node_arg(
+        ctx,
+        args[0],
+        b"treecrdt_descendants: root must be 16-byte BLOB\0",
+    ) else {
+        return;
+    };
+    match descendants(db, root, max_depth) {
+        Ok(nodes) => {
+            let nodes: Vec<JsonDescendant> = nodes
+                .into_iter()
+                .map(|(node, depth)| JsonDescendant {
+                    node: node_hex(node),
+                    depth,
+                })
+                .collect();
+            sqlite_result_json(ctx, &nodes)
+        }
+        Err(rc) => sqlite_result_error_code(ctx, rc),
+    }
+}
+
+/// Position of a node among its parent's visible children. Args: node BLOB.
+///
+/// Returns NULL for the root and for deleted or unknown nodes.
+pub(super) unsafe extern "C" fn treecrdt_index_in_parent(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if argc != 1 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_index_in_parent expects 1 arg (node)\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+    let Some((db, node)) = read_node_arg(
+        ctx,
+        args[0],
+        b"treecrdt_index_in_parent: node must be 16-byte BLOB\0",
+    ) else {
+        return;
+    };
+    match index_in_parent(db, node) {
+        Ok(Some(index)) => sqlite_result_int(ctx, index as c_int),
+        Ok(None) => sqlite_result_null(ctx),
+        Err(rc) => sqlite_result_error_code(ctx, rc),
+    }
+}
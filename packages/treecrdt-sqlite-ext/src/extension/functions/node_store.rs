@@ -1,7 +1,8 @@
+use super::orphan_policy::load_orphan_policy;
 use super::statement::LazyStatement;
 use super::*;
 use std::slice;
-use treecrdt_core::NodeStore;
+use treecrdt_core::{NodeStore, OrphanPolicy};
 
 fn sqlite_node_id_bytes(node: NodeId) -> [u8; 16] {
     node.0.to_be_bytes()
@@ -25,10 +26,13 @@ fn vv_from_bytes(bytes: &[u8]) -> treecrdt_core::Result<VersionVector> {
 
 pub(super) struct SqliteNodeStore {
     db: *mut sqlite3,
+    orphan_policy: OrphanPolicy,
     ensure_node: LazyStatement,
     exists: LazyStatement,
     select_node: LazyStatement,
     select_tombstone: LazyStatement,
+    select_hard_deleted: LazyStatement,
+    select_deleted_by: LazyStatement,
     select_children: LazyStatement,
     all_nodes: LazyStatement,
     clear_parent_order_key: LazyStatement,
@@ -36,12 +40,18 @@ pub(super) struct SqliteNodeStore {
     update_tombstone: LazyStatement,
     update_last_change: LazyStatement,
     update_deleted_at: LazyStatement,
+    update_hard_deleted: LazyStatement,
+    update_deleted_by: LazyStatement,
+    delete_node: LazyStatement,
 }
 
 impl SqliteNodeStore {
     pub(super) fn prepare(db: *mut sqlite3) -> treecrdt_core::Result<Self> {
+        let orphan_policy = load_orphan_policy(db)
+            .map_err(|rc| sqlite_rc_error(rc, "load orphan policy failed"))?;
         Ok(Self {
             db,
+            orphan_policy,
             ensure_node: LazyStatement::new(
                 db,
                 c"INSERT OR IGNORE INTO tree_nodes(node,parent,order_key,tombstone) VALUES (?1,NULL,NULL,0)",
@@ -58,6 +68,14 @@ impl SqliteNodeStore {
                 db,
                 c"SELECT tombstone FROM tree_nodes WHERE node = ?1 LIMIT 1",
             ),
+            select_hard_deleted: LazyStatement::new(
+                db,
+                c"SELECT hard_deleted FROM tree_nodes WHERE node = ?1 LIMIT 1",
+            ),
+            select_deleted_by: LazyStatement::new(
+                db,
+                c"SELECT deleted_lamport,deleted_replica,deleted_counter FROM tree_nodes WHERE node = ?1 LIMIT 1",
+            ),
             select_children: LazyStatement::new(
                 db,
                 c"SELECT node FROM tree_nodes WHERE parent = ?1 ORDER BY order_key, node",
@@ -83,6 +101,15 @@ impl SqliteNodeStore {
                 db,
                 c"UPDATE tree_nodes SET deleted_at = ?2 WHERE node = ?1",
             ),
+            update_hard_deleted: LazyStatement::new(
+                db,
+                c"UPDATE tree_nodes SET hard_deleted = ?2 WHERE node = ?1",
+            ),
+            update_deleted_by: LazyStatement::new(
+                db,
+                c"UPDATE tree_nodes SET deleted_lamport = ?2, deleted_replica = ?3, deleted_counter = ?4 WHERE node = ?1",
+            ),
+            delete_node: LazyStatement::new(db, c"DELETE FROM tree_nodes WHERE node = ?1"),
         })
     }
 }
@@ -482,6 +509,175 @@ impl treecrdt_core::NodeStore for SqliteNodeStore {
         Ok(())
     }
 
+    fn hard_deleted(&self, node: NodeId) -> treecrdt_core::Result<bool> {
+        let bytes = sqlite_node_id_bytes(node);
+        let stmt = self.select_hard_deleted.get()?;
+        unsafe {
+            sqlite_clear_bindings(stmt);
+            sqlite_reset(stmt);
+            let bind_rc = sqlite_bind_blob(
+                stmt,
+                1,
+                bytes.as_ptr() as *const c_void,
+                bytes.len() as c_int,
+                None,
+            );
+            if bind_rc != SQLITE_OK as c_int {
+                sqlite_reset(stmt);
+                return Err(sqlite_rc_error(bind_rc, "bind select_hard_deleted failed"));
+            }
+
+            let step_rc = sqlite_step(stmt);
+            let out = if step_rc == SQLITE_ROW as c_int {
+                sqlite_column_int64(stmt, 0) != 0
+            } else if step_rc == SQLITE_DONE as c_int {
+                false
+            } else {
+                sqlite_reset(stmt);
+                return Err(sqlite_rc_error(step_rc, "select_hard_deleted step failed"));
+            };
+            sqlite_reset(stmt);
+            Ok(out)
+        }
+    }
+
+    fn set_hard_deleted(&mut self, node: NodeId, hard_deleted: bool) -> treecrdt_core::Result<()> {
+        self.ensure_node(node)?;
+        let bytes = sqlite_node_id_bytes(node);
+        let stmt = self.update_hard_deleted.get()?;
+        unsafe {
+            sqlite_clear_bindings(stmt);
+            sqlite_reset(stmt);
+            let mut bind_err = false;
+            bind_err |= sqlite_bind_blob(
+                stmt,
+                1,
+                bytes.as_ptr() as *const c_void,
+                bytes.len() as c_int,
+                None,
+            ) != SQLITE_OK as c_int;
+            bind_err |=
+                sqlite_bind_int64(stmt, 2, if hard_deleted { 1 } else { 0 }) != SQLITE_OK as c_int;
+            if bind_err {
+                sqlite_reset(stmt);
+                return Err(sqlite_rc_error(
+                    SQLITE_ERROR as c_int,
+                    "bind update_hard_deleted failed",
+                ));
+            }
+            let step_rc = sqlite_step(stmt);
+            sqlite_reset(stmt);
+            if step_rc != SQLITE_DONE as c_int {
+                return Err(sqlite_rc_error(step_rc, "update_hard_deleted step failed"));
+            }
+        }
+        Ok(())
+    }
+
+    fn deleted_by(
+        &self,
+        node: NodeId,
+    ) -> treecrdt_core::Result<Option<(Lamport, treecrdt_core::OperationId)>> {
+        let bytes = sqlite_node_id_bytes(node);
+        let stmt = self.select_deleted_by.get()?;
+        unsafe {
+            sqlite_clear_bindings(stmt);
+            sqlite_reset(stmt);
+            let bind_rc = sqlite_bind_blob(
+                stmt,
+                1,
+                bytes.as_ptr() as *const c_void,
+                bytes.len() as c_int,
+                None,
+            );
+            if bind_rc != SQLITE_OK as c_int {
+                sqlite_reset(stmt);
+                return Err(sqlite_rc_error(bind_rc, "bind select_deleted_by failed"));
+            }
+
+            let step_rc = sqlite_step(stmt);
+            let out = if step_rc == SQLITE_ROW as c_int {
+                if sqlite_column_type(stmt, 0) == SQLITE_NULL as c_int {
+                    None
+                } else {
+                    let lamport = sqlite_column_int64(stmt, 0).max(0) as Lamport;
+                    let rep_ptr = sqlite_column_blob(stmt, 1) as *const u8;
+                    let rep_len = sqlite_column_bytes(stmt, 1) as usize;
+                    let replica = if rep_ptr.is_null() || rep_len == 0 {
+                        Vec::new()
+                    } else {
+                        slice::from_raw_parts(rep_ptr, rep_len).to_vec()
+                    };
+                    let counter = sqlite_column_int64(stmt, 2).max(0) as u64;
+                    Some((
+                        lamport,
+                        treecrdt_core::OperationId {
+                            replica: treecrdt_core::ReplicaId(replica),
+                            counter,
+                        },
+                    ))
+                }
+            } else if step_rc == SQLITE_DONE as c_int {
+                None
+            } else {
+                sqlite_reset(stmt);
+                return Err(sqlite_rc_error(step_rc, "select_deleted_by step failed"));
+            };
+            sqlite_reset(stmt);
+            Ok(out)
+        }
+    }
+
+    fn set_deleted_by(
+        &mut self,
+        node: NodeId,
+        op: Option<(Lamport, treecrdt_core::OperationId)>,
+    ) -> treecrdt_core::Result<()> {
+        self.ensure_node(node)?;
+        let bytes = sqlite_node_id_bytes(node);
+        let stmt = self.update_deleted_by.get()?;
+        unsafe {
+            sqlite_clear_bindings(stmt);
+            sqlite_reset(stmt);
+            let mut bind_err = sqlite_bind_blob(
+                stmt,
+                1,
+                bytes.as_ptr() as *const c_void,
+                bytes.len() as c_int,
+                None,
+            ) != SQLITE_OK as c_int;
+            // Left NULL when cleared: the bindings were reset above.
+            if let Some((lamport, id)) = &op {
+                bind_err |= sqlite_bind_int64(stmt, 2, *lamport as i64) != SQLITE_OK as c_int;
+                bind_err |= sqlite_bind_blob(
+                    stmt,
+                    3,
+                    id.replica.as_bytes().as_ptr() as *const c_void,
+                    id.replica.as_bytes().len() as c_int,
+                    None,
+                ) != SQLITE_OK as c_int;
+                bind_err |= sqlite_bind_int64(stmt, 4, id.counter as i64) != SQLITE_OK as c_int;
+            }
+            if bind_err {
+                sqlite_reset(stmt);
+                return Err(sqlite_rc_error(
+                    SQLITE_ERROR as c_int,
+                    "bind update_deleted_by failed",
+                ));
+            }
+            let step_rc = sqlite_step(stmt);
+            sqlite_reset(stmt);
+            if step_rc != SQLITE_DONE as c_int {
+                return Err(sqlite_rc_error(step_rc, "update_deleted_by step failed"));
+            }
+        }
+        Ok(())
+    }
+
+    fn orphan_policy(&self) -> OrphanPolicy {
+        self.orphan_policy
+    }
+
     fn has_deleted_at(&self, node: NodeId) -> treecrdt_core::Result<bool> {
         let bytes = sqlite_node_id_bytes(node);
         let stmt = self.select_node.get()?;
@@ -835,3 +1031,29 @@ impl treecrdt_core::ExactNodeStore for SqliteNodeStore {
         Ok(())
     }
 }
+
+impl treecrdt_core::PurgeableNodeStore for SqliteNodeStore {
+    fn remove_node(&mut self, node: NodeId) -> treecrdt_core::Result<()> {
+        // Children are found by their `parent` column, so deleting the row also detaches it.
+        let node_bytes = sqlite_node_id_bytes(node);
+        let stmt = self.delete_node.get()?;
+        unsafe {
+            sqlite_clear_bindings(stmt);
+            sqlite_reset(stmt);
+            sqlite_bind_blob(
+                stmt,
+                1,
+                node_bytes.as_ptr() as *const c_void,
+                node_bytes.len() as c_int,
+                None,
+            );
+            let step_rc = sqlite_step(stmt);
+            sqlite_reset(stmt);
+            sqlite_clear_bindings(stmt);
+            if step_rc != SQLITE_DONE as c_int {
+                return Err(sqlite_rc_error(step_rc, "delete node failed"));
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,8 @@
+use super::purge::{load_purged_ops, PurgedOps};
 use super::*;
 
+use std::cell::OnceCell;
+
 fn sqlite_rc_error(rc: c_int, context: &str) -> treecrdt_core::Error {
     treecrdt_core::Error::Storage(format!("{context} (rc={rc})"))
 }
@@ -84,6 +87,15 @@ fn read_operation_row(stmt: *mut sqlite3_stmt) -> treecrdt_core::Result<treecrdt
         }
     };
 
+    let group = if unsafe { sqlite_column_type(stmt, 10) } == SQLITE_NULL as c_int {
+        None
+    } else {
+        Some(treecrdt_core::OperationGroup {
+            first: unsafe { sqlite_column_int64(stmt, 10).max(0) as u64 },
+            len: unsafe { sqlite_column_int64(stmt, 11).max(0) as u32 },
+        })
+    };
+
     let op_kind = match kind {
         "insert" => {
             let parent = parent
@@ -110,10 +122,30 @@ fn read_operation_row(stmt: *mut sqlite3_stmt) -> treecrdt_core::Result<treecrdt
         "tombstone" => treecrdt_core::OperationKind::Tombstone {
             node: sqlite_bytes_to_node_id(node),
         },
+        "restore" => treecrdt_core::OperationKind::Restore {
+            node: sqlite_bytes_to_node_id(node),
+        },
         "payload" => treecrdt_core::OperationKind::Payload {
             node: sqlite_bytes_to_node_id(node),
             payload,
         },
+        "payload_field" => {
+            if unsafe { sqlite_column_type(stmt, 12) } == SQLITE_NULL as c_int {
+                return Err(sqlite_rc_error(
+                    SQLITE_ERROR as c_int,
+                    "payload_field missing field",
+                ));
+            }
+            let ptr = unsafe { sqlite_column_text(stmt, 12) } as *const u8;
+            let len = unsafe { sqlite_column_bytes(stmt, 12) } as usize;
+            let field = std::str::from_utf8(unsafe { slice::from_raw_parts(ptr, len) })
+                .map_err(|_| sqlite_rc_error(SQLITE_ERROR as c_int, "field is not UTF-8"))?;
+            treecrdt_core::OperationKind::PayloadField {
+                node: sqlite_bytes_to_node_id(node),
+                field: field.to_string(),
+                value: payload,
+            }
+        }
         _ => {
             return Err(sqlite_rc_error(
                 SQLITE_ERROR as c_int,
@@ -130,6 +162,7 @@ fn read_operation_row(stmt: *mut sqlite3_stmt) -> treecrdt_core::Result<treecrdt
             },
             lamport: lamport_val,
             known_state,
+            group,
         },
         kind: op_kind,
     })
@@ -138,6 +171,7 @@ fn read_operation_row(stmt: *mut sqlite3_stmt) -> treecrdt_core::Result<treecrdt
 pub(super) struct SqliteOpStorage {
     db: *mut sqlite3,
     doc_id: Option<Vec<u8>>,
+    purged: OnceCell<PurgedOps>,
 }
 
 impl SqliteOpStorage {
@@ -145,7 +179,18 @@ impl SqliteOpStorage {
         Self {
             db,
             doc_id: Some(doc_id),
+            purged: OnceCell::new(),
+        }
+    }
+
+    /// Ops dropped by `treecrdt_purge_stable`, which still count as part of the log.
+    fn purged(&self) -> treecrdt_core::Result<&PurgedOps> {
+        if let Some(purged) = self.purged.get() {
+            return Ok(purged);
         }
+        let purged =
+            load_purged_ops(self.db).map_err(|rc| sqlite_rc_error(rc, "load purged ops failed"))?;
+        Ok(self.purged.get_or_init(|| purged))
     }
 
     fn ensure_doc_id(&mut self) -> treecrdt_core::Result<&[u8]> {
@@ -161,8 +206,12 @@ impl SqliteOpStorage {
 
 impl treecrdt_core::Storage for SqliteOpStorage {
     fn apply(&mut self, op: treecrdt_core::Operation) -> treecrdt_core::Result<bool> {
+        if self.purged()?.version_vector.contains(&op.meta.id.replica, op.meta.id.counter) {
+            return Ok(false);
+        }
         let doc_id = self.ensure_doc_id()?;
 
+        let mut field = None;
         let (kind, parent, node, new_parent, order_key, known_state, payload) = match op.kind {
             treecrdt_core::OperationKind::Insert {
                 parent,
@@ -209,15 +258,43 @@ impl treecrdt_core::Storage for SqliteOpStorage {
                 op.meta.known_state.clone(),
                 None,
             ),
+            treecrdt_core::OperationKind::Restore { node } => (
+                "restore",
+                None,
+                sqlite_node_id_bytes(node).to_vec(),
+                None,
+                None,
+                None,
+                None,
+            ),
             treecrdt_core::OperationKind::Payload { node, payload } => (
                 "payload",
                 None,
                 sqlite_node_id_bytes(node).to_vec(),
                 None,
                 None,
-                None,
+                op.meta.known_state.clone(),
                 payload,
             ),
+            treecrdt_core::OperationKind::PayloadField {
+                node,
+                field: name,
+                value,
+            } => {
+                field =
+                    Some(CString::new(name).map_err(|_| {
+                        sqlite_rc_error(SQLITE_ERROR as c_int, "field contains NUL")
+                    })?);
+                (
+                    "payload_field",
+                    None,
+                    sqlite_node_id_bytes(node).to_vec(),
+                    None,
+                    None,
+                    None,
+                    value,
+                )
+            }
         };
 
         let known_state_bytes = known_state.as_ref().map(vv_to_bytes).transpose()?;
@@ -225,8 +302,9 @@ impl treecrdt_core::Storage for SqliteOpStorage {
 
         let insert_sql = CString::new(
             "INSERT OR IGNORE INTO ops \
-             (replica,counter,lamport,kind,parent,node,new_parent,order_key,known_state,payload,op_ref) \
-             VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11)",
+             (replica,counter,lamport,kind,parent,node,new_parent,order_key,known_state,payload,op_ref,\
+              group_first,group_len,field) \
+             VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11,?12,?13,?14)",
         )
         .expect("insert op sql");
         let mut stmt: *mut sqlite3_stmt = null_mut();
@@ -327,6 +405,19 @@ impl treecrdt_core::Storage for SqliteOpStorage {
                 OPREF_V0_WIDTH as c_int,
                 None,
             ) != SQLITE_OK as c_int;
+            if let Some(group) = op.meta.group {
+                bind_err |= sqlite_bind_int64(stmt, 12, group.first as i64) != SQLITE_OK as c_int;
+                bind_err |= sqlite_bind_int64(stmt, 13, group.len as i64) != SQLITE_OK as c_int;
+            } else {
+                bind_err |= sqlite_bind_null(stmt, 12) != SQLITE_OK as c_int;
+                bind_err |= sqlite_bind_null(stmt, 13) != SQLITE_OK as c_int;
+            }
+            if let Some(ref name) = field {
+                bind_err |=
+                    sqlite_bind_text(stmt, 14, name.as_ptr(), -1, None) != SQLITE_OK as c_int;
+            } else {
+                bind_err |= sqlite_bind_null(stmt, 14) != SQLITE_OK as c_int;
+            }
         }
 
         if bind_err {
@@ -350,7 +441,8 @@ impl treecrdt_core::Storage for SqliteOpStorage {
 
     fn load_since(&self, lamport: Lamport) -> treecrdt_core::Result<Vec<treecrdt_core::Operation>> {
         let sql = CString::new(
-            "SELECT replica,counter,lamport,kind,parent,node,new_parent,order_key,known_state,payload \
+            "SELECT replica,counter,lamport,kind,parent,node,new_parent,order_key,known_state,payload,\
+             group_first,group_len,field \
              FROM ops \
              WHERE lamport > ?1 \
              ORDER BY lamport, replica, counter",
@@ -393,7 +485,8 @@ impl treecrdt_core::Storage for SqliteOpStorage {
         visit: &mut dyn FnMut(treecrdt_core::Operation) -> treecrdt_core::Result<()>,
     ) -> treecrdt_core::Result<()> {
         let sql = CString::new(
-            "SELECT replica,counter,lamport,kind,parent,node,new_parent,order_key,known_state,payload \
+            "SELECT replica,counter,lamport,kind,parent,node,new_parent,order_key,known_state,payload,\
+             group_first,group_len,field \
              FROM ops \
              WHERE lamport > ?1 \
              ORDER BY lamport, replica, counter",
@@ -447,7 +540,7 @@ impl treecrdt_core::Storage for SqliteOpStorage {
             0
         };
         unsafe { sqlite_finalize(stmt) };
-        val
+        val.max(self.purged().map_or(0, |purged| purged.lamport))
     }
 
     fn latest_counter(&self, replica: &treecrdt_core::ReplicaId) -> treecrdt_core::Result<u64> {
@@ -485,7 +578,7 @@ impl treecrdt_core::Storage for SqliteOpStorage {
             return Err(sqlite_rc_error(finalize_rc, "finalize max counter failed"));
         }
 
-        Ok(val)
+        Ok(val.max(self.purged()?.version_vector.get(replica)))
     }
 }
 
@@ -496,7 +589,8 @@ impl treecrdt_core::FrontierRewindStorage for SqliteOpStorage {
         visit: &mut dyn FnMut(treecrdt_core::Operation) -> treecrdt_core::Result<()>,
     ) -> treecrdt_core::Result<()> {
         let sql = CString::new(
-            "SELECT replica,counter,lamport,kind,parent,node,new_parent,order_key,known_state,payload \
+            "SELECT replica,counter,lamport,kind,parent,node,new_parent,order_key,known_state,payload,\
+             group_first,group_len,field \
              FROM ops \
              WHERE (lamport > ?1 OR (lamport = ?1 AND (replica > ?2 OR (replica = ?2 AND counter >= ?3)))) \
              ORDER BY lamport, replica, counter",
@@ -559,7 +653,8 @@ impl treecrdt_core::FrontierRewindStorage for SqliteOpStorage {
         before: &treecrdt_core::MaterializationFrontierRef<'_>,
     ) -> treecrdt_core::Result<Option<treecrdt_core::Operation>> {
         let sql = CString::new(
-            "SELECT replica,counter,lamport,kind,parent,node,new_parent,order_key,known_state,payload \
+            "SELECT replica,counter,lamport,kind,parent,node,new_parent,order_key,known_state,payload,\
+             group_first,group_len,field \
              FROM ops \
              WHERE node = ?1 \
                AND kind IN ('insert', 'move') \
@@ -628,7 +723,8 @@ impl treecrdt_core::FrontierRewindStorage for SqliteOpStorage {
         before: &treecrdt_core::MaterializationFrontierRef<'_>,
     ) -> treecrdt_core::Result<Option<treecrdt_core::Operation>> {
         let sql = CString::new(
-            "SELECT replica,counter,lamport,kind,parent,node,new_parent,order_key,known_state,payload \
+            "SELECT replica,counter,lamport,kind,parent,node,new_parent,order_key,known_state,payload,\
+             group_first,group_len,field \
              FROM ops \
              WHERE node = ?1 \
                AND (kind = 'payload' OR (kind = 'insert' AND payload IS NOT NULL)) \
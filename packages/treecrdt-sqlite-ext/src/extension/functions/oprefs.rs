@@ -1,4 +1,5 @@
-use super::util::sqlite_result_json;
+use super::access::check_read;
+use super::util::{sqlite_err_from_core, sqlite_result_json};
 use super::*;
 
 pub(super) unsafe extern "C" fn treecrdt_oprefs_all(
@@ -91,6 +92,10 @@ pub(super) unsafe extern "C" fn treecrdt_oprefs_children(
         sqlite_result_error_code(ctx, rc);
         return;
     }
+    if let Err(err) = check_read(db, NodeId(u128::from_be_bytes(parent))) {
+        sqlite_result_error_code(ctx, sqlite_err_from_core(err));
+        return;
+    }
 
     let sql = CString::new("SELECT op_ref FROM oprefs_children WHERE parent = ?1 ORDER BY seq")
         .expect("static sql");
@@ -1,6 +1,13 @@
-use super::util::sqlite_result_json;
+use super::access::retain_readable;
+use super::op_storage::SqliteOpStorage;
+use super::util::{
+    read_optional_blob16, read_required_blob, sqlite_err_from_core, sqlite_result_bytes,
+    sqlite_result_json,
+};
 use super::*;
 
+use treecrdt_core::{OperationKind, ReplicaId, Storage};
+
 pub(super) unsafe extern "C" fn treecrdt_ops_by_oprefs(
     ctx: *mut sqlite3_context,
     argc: c_int,
@@ -52,7 +59,8 @@ pub(super) unsafe extern "C" fn treecrdt_ops_by_oprefs(
 
     let db = sqlite_context_db_handle(ctx);
     let sql = CString::new(
-        "SELECT replica,counter,lamport,kind,parent,node,new_parent,order_key,known_state,payload \
+        "SELECT replica,counter,lamport,kind,parent,node,new_parent,order_key,known_state,payload,\
+             group_first,group_len,field \
          FROM ops \
          WHERE op_ref = ?1",
     )
@@ -122,6 +130,10 @@ pub(super) unsafe extern "C" fn treecrdt_ops_by_oprefs(
         sqlite_result_error_code(ctx, finalize_rc);
         return;
     }
+    if let Err(rc) = retain_readable(db, &mut ops, |op| NodeId(u128::from_be_bytes(op.node))) {
+        sqlite_result_error_code(ctx, rc);
+        return;
+    }
 
     sqlite_result_json(ctx, &ops);
 }
@@ -138,6 +150,10 @@ struct JsonOp {
     order_key: Option<Vec<u8>>,
     known_state: Option<Vec<u8>>,
     payload: Option<Vec<u8>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group: Option<treecrdt_core::OperationGroup>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field: Option<String>,
 }
 
 pub(super) unsafe extern "C" fn treecrdt_ops_since(
@@ -159,7 +175,8 @@ pub(super) unsafe extern "C" fn treecrdt_ops_since(
 
     let db = sqlite_context_db_handle(ctx);
     let sql = CString::new(
-        "SELECT replica,counter,lamport,kind,parent,node,new_parent,order_key,known_state,payload \
+        "SELECT replica,counter,lamport,kind,parent,node,new_parent,order_key,known_state,payload,\
+             group_first,group_len,field \
          FROM ops \
          WHERE lamport > ?1 \
          AND (?2 IS NULL OR parent = ?2 OR node = ?2 OR new_parent = ?2) \
@@ -213,6 +230,10 @@ pub(super) unsafe extern "C" fn treecrdt_ops_since(
         sqlite_result_error_code(ctx, finalize_rc);
         return;
     }
+    if let Err(rc) = retain_readable(db, &mut ops, |op| NodeId(u128::from_be_bytes(op.node))) {
+        sqlite_result_error_code(ctx, rc);
+        return;
+    }
 
     sqlite_result_json(ctx, &ops);
 }
@@ -275,6 +296,22 @@ fn read_row(stmt: *mut sqlite3_stmt) -> Result<JsonOp, c_int> {
             }
         };
 
+        let group = if sqlite_column_type(stmt, 10) == SQLITE_NULL as c_int {
+            None
+        } else {
+            Some(treecrdt_core::OperationGroup {
+                first: sqlite_column_int64(stmt, 10).max(0) as u64,
+                len: sqlite_column_int64(stmt, 11).max(0) as u32,
+            })
+        };
+        let field = if sqlite_column_type(stmt, 12) == SQLITE_NULL as c_int {
+            None
+        } else {
+            let ptr = sqlite_column_text(stmt, 12) as *const u8;
+            let len = sqlite_column_bytes(stmt, 12) as usize;
+            Some(String::from_utf8_lossy(slice::from_raw_parts(ptr, len)).into_owned())
+        };
+
         Ok(JsonOp {
             replica,
             counter,
@@ -286,6 +323,127 @@ fn read_row(stmt: *mut sqlite3_stmt) -> Result<JsonOp, c_int> {
             order_key,
             known_state,
             payload,
+            group,
+            field,
         })
     }
 }
+
+fn touches(op: &treecrdt_core::Operation, root: NodeId) -> bool {
+    match &op.kind {
+        OperationKind::Insert { parent, node, .. } => *parent == root || *node == root,
+        OperationKind::Move {
+            node, new_parent, ..
+        } => *node == root || *new_parent == root,
+        OperationKind::Delete { node }
+        | OperationKind::Tombstone { node }
+        | OperationKind::Restore { node }
+        | OperationKind::Payload { node, .. }
+        | OperationKind::PayloadField { node, .. } => *node == root,
+    }
+}
+
+/// `treecrdt_ops_since` in the binary op encoding (`treecrdt_core::decode_ops`). Args: lamport
+/// INT [, root BLOB]. Returns a BLOB.
+pub(super) unsafe extern "C" fn treecrdt_ops_since_blob(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if !(argc == 1 || argc == 2) {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_ops_since_blob expects lamport [, root]\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+    let args = unsafe { std::slice::from_raw_parts(argv, argc as usize) };
+    let lamport: Lamport = unsafe { sqlite_value_int64(args[0]) as Lamport };
+    let root = if argc == 2 {
+        match read_optional_blob16(args[1]) {
+            Ok(root) => root.map(|bytes| NodeId(u128::from_be_bytes(bytes))),
+            Err(_) => {
+                sqlite_result_error(
+                    ctx,
+                    b"treecrdt_ops_since_blob: root must be a 16-byte BLOB\0".as_ptr()
+                        as *const c_char,
+                );
+                return;
+            }
+        }
+    } else {
+        None
+    };
+
+    let db = sqlite_context_db_handle(ctx);
+    let doc_id = match load_doc_id(db) {
+        Ok(doc_id) => doc_id.unwrap_or_default(),
+        Err(rc) => {
+            sqlite_result_error_code(ctx, rc);
+            return;
+        }
+    };
+    let mut ops = match SqliteOpStorage::with_doc_id(db, doc_id).load_since(lamport) {
+        Ok(ops) => ops,
+        Err(err) => {
+            sqlite_result_error_code(ctx, sqlite_err_from_core(err));
+            return;
+        }
+    };
+    if let Some(root) = root {
+        ops.retain(|op| touches(op, root));
+    }
+    if let Err(rc) = retain_readable(db, &mut ops, |op| op.kind.node()) {
+        sqlite_result_error_code(ctx, rc);
+        return;
+    }
+    sqlite_result_bytes(ctx, &treecrdt_core::encode_ops(&ops));
+}
+
+/// Highest lamport in the op log, counting ops `treecrdt_purge_stable` dropped. Args: none.
+pub(super) unsafe extern "C" fn treecrdt_head_lamport(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    _argv: *mut *mut sqlite3_value,
+) {
+    if argc != 0 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_head_lamport expects 0 args\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+    let db = sqlite_context_db_handle(ctx);
+    let lamport = SqliteOpStorage::with_doc_id(db, Vec::new()).latest_lamport();
+    sqlite_result_int64(ctx, lamport.min(i64::MAX as Lamport) as i64);
+}
+
+/// Highest counter `replica` has used in the op log, counting ops `treecrdt_purge_stable`
+/// dropped. Args: replica BLOB.
+pub(super) unsafe extern "C" fn treecrdt_replica_max_counter(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if argc != 1 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_replica_max_counter expects 1 arg (replica)\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+    let Ok(replica) = read_required_blob(args[0]) else {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_replica_max_counter: replica must be a BLOB\0".as_ptr() as *const c_char,
+        );
+        return;
+    };
+    let db = sqlite_context_db_handle(ctx);
+    let storage = SqliteOpStorage::with_doc_id(db, Vec::new());
+    match storage.latest_counter(&ReplicaId::new(replica)) {
+        Ok(counter) => sqlite_result_int64(ctx, counter.min(i64::MAX as u64) as i64),
+        Err(err) => sqlite_result_error_code(ctx, sqlite_err_from_core(err)),
+    }
+}
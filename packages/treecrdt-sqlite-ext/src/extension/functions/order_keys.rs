@@ -0,0 +1,112 @@
+use super::access::check_read;
+use super::util::{read_blob16, sqlite_err_from_core, sqlite_result_json};
+use super::*;
+
+use treecrdt_core::OrderKeyStats;
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonOrderKeyStats {
+    children: u64,
+    max_len: u64,
+    total_len: u64,
+    avg_len: f64,
+}
+
+fn order_key_stats(db: *mut sqlite3, parent: [u8; 16]) -> Result<OrderKeyStats, c_int> {
+    let sql = CString::new(
+        "SELECT COUNT(*), COALESCE(MAX(length(order_key)), 0), \
+         COALESCE(SUM(length(order_key)), 0) \
+         FROM tree_nodes WHERE parent = ?1 AND tombstone = 0",
+    )
+    .expect("static sql");
+    let mut stmt: *mut sqlite3_stmt = null_mut();
+    let rc = sqlite_prepare_v2(db, sql.as_ptr(), -1, &mut stmt, null_mut());
+    if rc != SQLITE_OK as c_int {
+        return Err(rc);
+    }
+    let bind_rc = unsafe {
+        sqlite_bind_blob(
+            stmt,
+            1,
+            parent.as_ptr() as *const c_void,
+            parent.len() as c_int,
+            None,
+        )
+    };
+    if bind_rc != SQLITE_OK as c_int {
+        unsafe { sqlite_finalize(stmt) };
+        return Err(bind_rc);
+    }
+
+    let step_rc = unsafe { sqlite_step(stmt) };
+    if step_rc != SQLITE_ROW as c_int {
+        unsafe { sqlite_finalize(stmt) };
+        return Err(step_rc);
+    }
+    let stats = unsafe {
+        OrderKeyStats {
+            children: sqlite_column_int64(stmt, 0) as u64,
+            max_len: sqlite_column_int64(stmt, 1) as u64,
+            total_len: sqlite_column_int64(stmt, 2) as u64,
+        }
+    };
+    let finalize_rc = unsafe { sqlite_finalize(stmt) };
+    if finalize_rc != SQLITE_OK as c_int {
+        return Err(finalize_rc);
+    }
+    Ok(stats)
+}
+
+/// Order key lengths of a parent's visible children. Args: parent BLOB.
+///
+/// Returns `{children, maxLen, totalLen, avgLen}` with lengths in bytes; parents whose keys have
+/// grown long can be re-keyed with `treecrdt_local_rebalance`.
+pub(super) unsafe extern "C" fn treecrdt_order_key_stats(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if argc != 1 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_order_key_stats expects 1 arg (parent)\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+    let parent = match read_blob16(args[0]) {
+        Ok(v) => v,
+        Err(_) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_order_key_stats: parent must be 16-byte BLOB\0".as_ptr()
+                    as *const c_char,
+            );
+            return;
+        }
+    };
+
+    let db = sqlite_context_db_handle(ctx);
+    if let Err(rc) = ensure_materialized(db) {
+        sqlite_result_error_code(ctx, rc);
+        return;
+    }
+    if let Err(err) = check_read(db, NodeId(u128::from_be_bytes(parent))) {
+        sqlite_result_error_code(ctx, sqlite_err_from_core(err));
+        return;
+    }
+
+    match order_key_stats(db, parent) {
+        Ok(stats) => sqlite_result_json(
+            ctx,
+            &JsonOrderKeyStats {
+                children: stats.children,
+                max_len: stats.max_len,
+                total_len: stats.total_len,
+                avg_len: stats.avg_len(),
+            },
+        ),
+        Err(rc) => sqlite_result_error_code(ctx, rc),
+    }
+}
@@ -0,0 +1,79 @@
+use super::util::read_text;
+use super::*;
+
+use treecrdt_core::OrphanPolicy;
+
+// Like the payload mode this is document state: every connection has to materialize deletes the
+// same way.
+const ORPHAN_POLICY_META_KEY: &str = "orphan_policy";
+
+fn policy_name(policy: OrphanPolicy) -> &'static str {
+    match policy {
+        OrphanPolicy::Defensive => "defensive",
+        OrphanPolicy::KeepParent => "keep_parent",
+        OrphanPolicy::LostAndFound => "lost_and_found",
+    }
+}
+
+pub(super) fn load_orphan_policy(db: *mut sqlite3) -> Result<OrphanPolicy, c_int> {
+    let Some(bytes) = load_meta(db, ORPHAN_POLICY_META_KEY)? else {
+        return Ok(OrphanPolicy::Defensive);
+    };
+    Ok([OrphanPolicy::KeepParent, OrphanPolicy::LostAndFound]
+        .into_iter()
+        .find(|policy| bytes == policy_name(*policy).as_bytes())
+        .unwrap_or(OrphanPolicy::Defensive))
+}
+
+/// Choose what deletes do with nodes placed under them concurrently. Args: policy TEXT, one of
+/// `'defensive'` (the default), `'keep_parent'` or `'lost_and_found'`.
+///
+/// Materialized state depends on the policy, so changing it replays the whole op log. Returns 1.
+pub(super) unsafe extern "C" fn treecrdt_set_orphan_policy(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if argc != 1 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_set_orphan_policy expects 1 arg (policy)\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+    let policy = match read_text(args[0]).as_str() {
+        "defensive" => OrphanPolicy::Defensive,
+        "keep_parent" => OrphanPolicy::KeepParent,
+        "lost_and_found" => OrphanPolicy::LostAndFound,
+        _ => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_set_orphan_policy: policy must be 'defensive', 'keep_parent' or 'lost_and_found'\0"
+                    .as_ptr() as *const c_char,
+            );
+            return;
+        }
+    };
+
+    let db = sqlite_context_db_handle(ctx);
+    let result = (|| {
+        if load_orphan_policy(db)? == policy {
+            return Ok(());
+        }
+        store_meta(db, ORPHAN_POLICY_META_KEY, policy_name(policy))?;
+        set_tree_meta_replay_frontier(
+            db,
+            &treecrdt_core::MaterializationFrontier {
+                lamport: 0,
+                replica: Vec::new(),
+                counter: 0,
+            },
+        )?;
+        ensure_materialized(db).map(|_| ())
+    })();
+    match result {
+        Ok(()) => sqlite_result_int(ctx, 1),
+        Err(rc) => sqlite_result_error_code(ctx, rc),
+    }
+}
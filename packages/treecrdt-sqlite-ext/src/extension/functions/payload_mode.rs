@@ -0,0 +1,72 @@
+use super::util::read_text;
+use super::*;
+
+use treecrdt_core::PayloadMode;
+
+// The mode is document state rather than connection state: every connection has to keep the same
+// `tree_payload_values` rows up to date.
+const PAYLOAD_MODE_META_KEY: &str = "payload_mode";
+
+fn mode_name(mode: PayloadMode) -> &'static str {
+    match mode {
+        PayloadMode::LastWriterWins => "last_writer_wins",
+        PayloadMode::MultiValue => "multi_value",
+    }
+}
+
+pub(super) fn load_payload_mode(db: *mut sqlite3) -> Result<PayloadMode, c_int> {
+    match load_meta(db, PAYLOAD_MODE_META_KEY)? {
+        Some(bytes) if bytes == mode_name(PayloadMode::MultiValue).as_bytes() => {
+            Ok(PayloadMode::MultiValue)
+        }
+        _ => Ok(PayloadMode::LastWriterWins),
+    }
+}
+
+/// Choose how concurrent payload writes are materialized. Args: mode TEXT, either
+/// `'last_writer_wins'` (the default) or `'multi_value'`.
+///
+/// In `'multi_value'` mode `tree_payload_values` holds every payload write of a node that no other
+/// write has seen, and local payload writes resolve them. Switching it on starts from the current
+/// winners; switching it off drops the table's rows. Returns 1.
+pub(super) unsafe extern "C" fn treecrdt_set_payload_mode(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if argc != 1 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_set_payload_mode expects 1 arg (mode)\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+    let mode = match read_text(args[0]).as_str() {
+        "last_writer_wins" => PayloadMode::LastWriterWins,
+        "multi_value" => PayloadMode::MultiValue,
+        _ => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_set_payload_mode: mode must be 'last_writer_wins' or 'multi_value'\0"
+                    .as_ptr() as *const c_char,
+            );
+            return;
+        }
+    };
+
+    let db = sqlite_context_db_handle(ctx);
+    if let Err(rc) = store_meta(db, PAYLOAD_MODE_META_KEY, mode_name(mode)) {
+        sqlite_result_error_code(ctx, rc);
+        return;
+    }
+    if mode == PayloadMode::LastWriterWins {
+        let sql = CString::new("DELETE FROM tree_payload_values").expect("clear payload values");
+        let rc = sqlite_exec(db, sql.as_ptr(), None, null_mut(), null_mut());
+        if rc != SQLITE_OK as c_int {
+            sqlite_result_error_code(ctx, rc);
+            return;
+        }
+    }
+    sqlite_result_int(ctx, 1);
+}
@@ -1,6 +1,9 @@
+use super::payload_mode::load_payload_mode;
 use super::statement::LazyStatement;
 use super::*;
 
+use treecrdt_core::{PayloadMode, PayloadValue};
+
 fn sqlite_node_id_bytes(node: NodeId) -> [u8; 16] {
     node.0.to_be_bytes()
 }
@@ -11,15 +14,26 @@ fn sqlite_rc_error(rc: c_int, context: &str) -> treecrdt_core::Error {
 
 pub(super) struct SqlitePayloadStore {
     db: *mut sqlite3,
+    mode: PayloadMode,
     select: LazyStatement,
     upsert: LazyStatement,
     delete: LazyStatement,
+    select_field: LazyStatement,
+    select_fields: LazyStatement,
+    upsert_field: LazyStatement,
+    delete_fields: LazyStatement,
+    select_values: LazyStatement,
+    insert_value: LazyStatement,
+    delete_values: LazyStatement,
 }
 
 impl SqlitePayloadStore {
     pub(super) fn prepare(db: *mut sqlite3) -> treecrdt_core::Result<Self> {
+        let mode =
+            load_payload_mode(db).map_err(|rc| sqlite_rc_error(rc, "load payload mode failed"))?;
         Ok(Self {
             db,
+            mode,
             select: LazyStatement::new(
                 db,
                 c"SELECT payload, last_lamport, last_replica, last_counter FROM tree_payload WHERE node = ?1 LIMIT 1",
@@ -29,8 +43,226 @@ impl SqlitePayloadStore {
                 c"INSERT INTO tree_payload(node,payload,last_lamport,last_replica,last_counter) VALUES (?1,?2,?3,?4,?5) ON CONFLICT(node) DO UPDATE SET payload = excluded.payload, last_lamport = excluded.last_lamport, last_replica = excluded.last_replica, last_counter = excluded.last_counter",
             ),
             delete: LazyStatement::new(db, c"DELETE FROM tree_payload WHERE node = ?1"),
+            select_field: LazyStatement::new(
+                db,
+                c"SELECT field, value, last_lamport, last_replica, last_counter FROM tree_payload_fields WHERE node = ?1 AND field = ?2 LIMIT 1",
+            ),
+            select_fields: LazyStatement::new(
+                db,
+                c"SELECT field, value, last_lamport, last_replica, last_counter FROM tree_payload_fields WHERE node = ?1 ORDER BY field",
+            ),
+            upsert_field: LazyStatement::new(
+                db,
+                c"INSERT INTO tree_payload_fields(node,field,value,last_lamport,last_replica,last_counter) VALUES (?1,?2,?3,?4,?5,?6) ON CONFLICT(node, field) DO UPDATE SET value = excluded.value, last_lamport = excluded.last_lamport, last_replica = excluded.last_replica, last_counter = excluded.last_counter",
+            ),
+            delete_fields: LazyStatement::new(
+                db,
+                c"DELETE FROM tree_payload_fields WHERE node = ?1",
+            ),
+            select_values: LazyStatement::new(
+                db,
+                c"SELECT payload, lamport, replica, counter, known_state FROM tree_payload_values WHERE node = ?1 ORDER BY lamport, replica, counter",
+            ),
+            insert_value: LazyStatement::new(
+                db,
+                c"INSERT INTO tree_payload_values(node,payload,lamport,replica,counter,known_state) VALUES (?1,?2,?3,?4,?5,?6)",
+            ),
+            delete_values: LazyStatement::new(
+                db,
+                c"DELETE FROM tree_payload_values WHERE node = ?1",
+            ),
         })
     }
+
+    /// Step a statement that only binds `node` as ?1 to completion.
+    fn exec_for_node(
+        &self,
+        stmt: &LazyStatement,
+        node: NodeId,
+        context: &str,
+    ) -> treecrdt_core::Result<()> {
+        let node_bytes = sqlite_node_id_bytes(node);
+        let stmt = stmt.get()?;
+        unsafe {
+            sqlite_clear_bindings(stmt);
+            sqlite_reset(stmt);
+            let bind_rc = sqlite_bind_blob(
+                stmt,
+                1,
+                node_bytes.as_ptr() as *const c_void,
+                node_bytes.len() as c_int,
+                None,
+            );
+            if bind_rc != SQLITE_OK as c_int {
+                sqlite_reset(stmt);
+                return Err(sqlite_rc_error(bind_rc, context));
+            }
+            let step_rc = sqlite_step(stmt);
+            sqlite_reset(stmt);
+            if step_rc != SQLITE_DONE as c_int {
+                return Err(sqlite_rc_error(step_rc, context));
+            }
+        }
+        Ok(())
+    }
+
+    fn insert_payload_value(
+        &self,
+        node: NodeId,
+        value: &PayloadValue,
+    ) -> treecrdt_core::Result<()> {
+        let node_bytes = sqlite_node_id_bytes(node);
+        let known_state = serde_json::to_vec(&value.known_state)
+            .map_err(|e| treecrdt_core::Error::Storage(e.to_string()))?;
+        let stmt = self.insert_value.get()?;
+        unsafe {
+            sqlite_clear_bindings(stmt);
+            sqlite_reset(stmt);
+        }
+        let mut bind_err = false;
+        unsafe {
+            bind_err |= sqlite_bind_blob(
+                stmt,
+                1,
+                node_bytes.as_ptr() as *const c_void,
+                node_bytes.len() as c_int,
+                None,
+            ) != SQLITE_OK as c_int;
+            if let Some(ref bytes) = value.payload {
+                bind_err |= sqlite_bind_blob(
+                    stmt,
+                    2,
+                    bytes.as_ptr() as *const c_void,
+                    bytes.len() as c_int,
+                    None,
+                ) != SQLITE_OK as c_int;
+            } else {
+                bind_err |= sqlite_bind_null(stmt, 2) != SQLITE_OK as c_int;
+            }
+            bind_err |= sqlite_bind_int64(stmt, 3, value.lamport as i64) != SQLITE_OK as c_int;
+            bind_err |= sqlite_bind_blob(
+                stmt,
+                4,
+                value.writer.replica.as_bytes().as_ptr() as *const c_void,
+                value.writer.replica.as_bytes().len() as c_int,
+                None,
+            ) != SQLITE_OK as c_int;
+            bind_err |=
+                sqlite_bind_int64(stmt, 5, value.writer.counter as i64) != SQLITE_OK as c_int;
+            bind_err |= sqlite_bind_blob(
+                stmt,
+                6,
+                known_state.as_ptr() as *const c_void,
+                known_state.len() as c_int,
+                None,
+            ) != SQLITE_OK as c_int;
+        }
+        if bind_err {
+            unsafe { sqlite_reset(stmt) };
+            return Err(sqlite_rc_error(
+                SQLITE_ERROR as c_int,
+                "bind insert payload value failed",
+            ));
+        }
+        let step_rc = unsafe { sqlite_step(stmt) };
+        unsafe { sqlite_reset(stmt) };
+        if step_rc != SQLITE_DONE as c_int {
+            return Err(sqlite_rc_error(step_rc, "insert payload value step failed"));
+        }
+        Ok(())
+    }
+
+    /// Run a `tree_payload_fields` select bound to `node` (and `field`, if given) and collect
+    /// its rows.
+    fn field_rows(
+        &self,
+        stmt: &LazyStatement,
+        node: NodeId,
+        field: Option<&str>,
+    ) -> treecrdt_core::Result<Vec<treecrdt_core::PayloadFieldEntry>> {
+        let node_bytes = sqlite_node_id_bytes(node);
+        let stmt = stmt.get()?;
+        unsafe {
+            sqlite_clear_bindings(stmt);
+            sqlite_reset(stmt);
+            let mut bind_err = sqlite_bind_blob(
+                stmt,
+                1,
+                node_bytes.as_ptr() as *const c_void,
+                node_bytes.len() as c_int,
+                None,
+            ) != SQLITE_OK as c_int;
+            if let Some(field) = field {
+                bind_err |= sqlite_bind_text(
+                    stmt,
+                    2,
+                    field.as_ptr() as *const c_char,
+                    field.len() as c_int,
+                    None,
+                ) != SQLITE_OK as c_int;
+            }
+            if bind_err {
+                sqlite_reset(stmt);
+                return Err(sqlite_rc_error(
+                    SQLITE_ERROR as c_int,
+                    "bind select payload fields failed",
+                ));
+            }
+
+            let mut entries = Vec::new();
+            loop {
+                let step_rc = sqlite_step(stmt);
+                if step_rc == SQLITE_DONE as c_int {
+                    break;
+                }
+                if step_rc != SQLITE_ROW as c_int {
+                    sqlite_reset(stmt);
+                    return Err(sqlite_rc_error(
+                        step_rc,
+                        "select payload fields step failed",
+                    ));
+                }
+                let name_ptr = sqlite_column_text(stmt, 0) as *const u8;
+                let name_len = sqlite_column_bytes(stmt, 0) as usize;
+                let field = if name_ptr.is_null() {
+                    String::new()
+                } else {
+                    String::from_utf8_lossy(slice::from_raw_parts(name_ptr, name_len)).into_owned()
+                };
+                let value = if sqlite_column_type(stmt, 1) == SQLITE_NULL as c_int {
+                    None
+                } else {
+                    let ptr = sqlite_column_blob(stmt, 1) as *const u8;
+                    let len = sqlite_column_bytes(stmt, 1) as usize;
+                    if ptr.is_null() {
+                        Some(Vec::new())
+                    } else {
+                        Some(slice::from_raw_parts(ptr, len).to_vec())
+                    }
+                };
+                let lamport = sqlite_column_int64(stmt, 2).max(0) as Lamport;
+                let rep_ptr = sqlite_column_blob(stmt, 3) as *const u8;
+                let rep_len = sqlite_column_bytes(stmt, 3) as usize;
+                let replica = if rep_ptr.is_null() || rep_len == 0 {
+                    Vec::new()
+                } else {
+                    slice::from_raw_parts(rep_ptr, rep_len).to_vec()
+                };
+                let counter = sqlite_column_int64(stmt, 4).max(0) as u64;
+                entries.push(treecrdt_core::PayloadFieldEntry {
+                    field,
+                    value,
+                    lamport,
+                    writer: treecrdt_core::OperationId {
+                        replica: treecrdt_core::ReplicaId(replica),
+                        counter,
+                    },
+                });
+            }
+            sqlite_reset(stmt);
+            Ok(entries)
+        }
+    }
 }
 
 impl treecrdt_core::PayloadStore for SqlitePayloadStore {
@@ -40,6 +272,24 @@ impl treecrdt_core::PayloadStore for SqlitePayloadStore {
         if rc != SQLITE_OK as c_int {
             return Err(sqlite_rc_error(rc, "sqlite_exec reset tree_payload failed"));
         }
+        let clear_sql =
+            CString::new("DELETE FROM tree_payload_fields").expect("clear payload fields sql");
+        let rc = sqlite_exec(self.db, clear_sql.as_ptr(), None, null_mut(), null_mut());
+        if rc != SQLITE_OK as c_int {
+            return Err(sqlite_rc_error(
+                rc,
+                "sqlite_exec reset tree_payload_fields failed",
+            ));
+        }
+        let clear_sql =
+            CString::new("DELETE FROM tree_payload_values").expect("clear payload values sql");
+        let rc = sqlite_exec(self.db, clear_sql.as_ptr(), None, null_mut(), null_mut());
+        if rc != SQLITE_OK as c_int {
+            return Err(sqlite_rc_error(
+                rc,
+                "sqlite_exec reset tree_payload_values failed",
+            ));
+        }
         Ok(())
     }
 
@@ -200,6 +450,191 @@ impl treecrdt_core::PayloadStore for SqlitePayloadStore {
         }
         Ok(())
     }
+
+    fn payload_field(
+        &self,
+        node: NodeId,
+        field: &str,
+    ) -> treecrdt_core::Result<Option<treecrdt_core::PayloadFieldEntry>> {
+        Ok(self.field_rows(&self.select_field, node, Some(field))?.pop())
+    }
+
+    fn payload_fields(
+        &self,
+        node: NodeId,
+    ) -> treecrdt_core::Result<Vec<treecrdt_core::PayloadFieldEntry>> {
+        self.field_rows(&self.select_fields, node, None)
+    }
+
+    fn set_payload_field(
+        &mut self,
+        node: NodeId,
+        entry: treecrdt_core::PayloadFieldEntry,
+    ) -> treecrdt_core::Result<()> {
+        let node_bytes = sqlite_node_id_bytes(node);
+        let stmt = self.upsert_field.get()?;
+        unsafe {
+            sqlite_clear_bindings(stmt);
+            sqlite_reset(stmt);
+        }
+        let mut bind_err = false;
+        unsafe {
+            bind_err |= sqlite_bind_blob(
+                stmt,
+                1,
+                node_bytes.as_ptr() as *const c_void,
+                node_bytes.len() as c_int,
+                None,
+            ) != SQLITE_OK as c_int;
+            bind_err |= sqlite_bind_text(
+                stmt,
+                2,
+                entry.field.as_ptr() as *const c_char,
+                entry.field.len() as c_int,
+                None,
+            ) != SQLITE_OK as c_int;
+            if let Some(ref bytes) = entry.value {
+                bind_err |= sqlite_bind_blob(
+                    stmt,
+                    3,
+                    bytes.as_ptr() as *const c_void,
+                    bytes.len() as c_int,
+                    None,
+                ) != SQLITE_OK as c_int;
+            } else {
+                bind_err |= sqlite_bind_null(stmt, 3) != SQLITE_OK as c_int;
+            }
+            bind_err |= sqlite_bind_int64(stmt, 4, entry.lamport as i64) != SQLITE_OK as c_int;
+            bind_err |= sqlite_bind_blob(
+                stmt,
+                5,
+                entry.writer.replica.as_bytes().as_ptr() as *const c_void,
+                entry.writer.replica.as_bytes().len() as c_int,
+                None,
+            ) != SQLITE_OK as c_int;
+            bind_err |=
+                sqlite_bind_int64(stmt, 6, entry.writer.counter as i64) != SQLITE_OK as c_int;
+        }
+        if bind_err {
+            unsafe { sqlite_reset(stmt) };
+            return Err(sqlite_rc_error(
+                SQLITE_ERROR as c_int,
+                "bind upsert payload field failed",
+            ));
+        }
+        let step_rc = unsafe { sqlite_step(stmt) };
+        unsafe { sqlite_reset(stmt) };
+        if step_rc != SQLITE_DONE as c_int {
+            return Err(sqlite_rc_error(step_rc, "upsert payload field step failed"));
+        }
+        Ok(())
+    }
+
+    fn mode(&self) -> PayloadMode {
+        self.mode
+    }
+
+    fn payload_values(&self, node: NodeId) -> treecrdt_core::Result<Vec<PayloadValue>> {
+        if self.mode != PayloadMode::MultiValue {
+            return Ok(Vec::new());
+        }
+        let node_bytes = sqlite_node_id_bytes(node);
+        let stmt = self.select_values.get()?;
+        unsafe {
+            sqlite_clear_bindings(stmt);
+            sqlite_reset(stmt);
+            let bind_rc = sqlite_bind_blob(
+                stmt,
+                1,
+                node_bytes.as_ptr() as *const c_void,
+                node_bytes.len() as c_int,
+                None,
+            );
+            if bind_rc != SQLITE_OK as c_int {
+                sqlite_reset(stmt);
+                return Err(sqlite_rc_error(
+                    bind_rc,
+                    "bind select payload values failed",
+                ));
+            }
+
+            let mut values = Vec::new();
+            loop {
+                let step_rc = sqlite_step(stmt);
+                if step_rc == SQLITE_DONE as c_int {
+                    break;
+                }
+                if step_rc != SQLITE_ROW as c_int {
+                    sqlite_reset(stmt);
+                    return Err(sqlite_rc_error(
+                        step_rc,
+                        "select payload values step failed",
+                    ));
+                }
+                let payload = if sqlite_column_type(stmt, 0) == SQLITE_NULL as c_int {
+                    None
+                } else {
+                    let ptr = sqlite_column_blob(stmt, 0) as *const u8;
+                    let len = sqlite_column_bytes(stmt, 0) as usize;
+                    if ptr.is_null() {
+                        Some(Vec::new())
+                    } else {
+                        Some(slice::from_raw_parts(ptr, len).to_vec())
+                    }
+                };
+                let lamport = sqlite_column_int64(stmt, 1).max(0) as Lamport;
+                let rep_ptr = sqlite_column_blob(stmt, 2) as *const u8;
+                let rep_len = sqlite_column_bytes(stmt, 2) as usize;
+                let replica = if rep_ptr.is_null() || rep_len == 0 {
+                    Vec::new()
+                } else {
+                    slice::from_raw_parts(rep_ptr, rep_len).to_vec()
+                };
+                let counter = sqlite_column_int64(stmt, 3).max(0) as u64;
+                let vv_ptr = sqlite_column_blob(stmt, 4) as *const u8;
+                let vv_len = sqlite_column_bytes(stmt, 4) as usize;
+                let known_state = if vv_ptr.is_null() || vv_len == 0 {
+                    VersionVector::new()
+                } else {
+                    match serde_json::from_slice(slice::from_raw_parts(vv_ptr, vv_len)) {
+                        Ok(vv) => vv,
+                        Err(e) => {
+                            sqlite_reset(stmt);
+                            return Err(treecrdt_core::Error::Storage(e.to_string()));
+                        }
+                    }
+                };
+                values.push(PayloadValue {
+                    payload,
+                    lamport,
+                    writer: treecrdt_core::OperationId {
+                        replica: treecrdt_core::ReplicaId(replica),
+                        counter,
+                    },
+                    known_state,
+                });
+            }
+            sqlite_reset(stmt);
+            Ok(values)
+        }
+    }
+
+    fn set_payload_values(
+        &mut self,
+        node: NodeId,
+        values: Vec<PayloadValue>,
+    ) -> treecrdt_core::Result<()> {
+        if self.mode != PayloadMode::MultiValue {
+            return Err(treecrdt_core::Error::InvalidOperation(
+                "payload mode is not multi_value".into(),
+            ));
+        }
+        self.exec_for_node(&self.delete_values, node, "delete payload values failed")?;
+        for value in &values {
+            self.insert_payload_value(node, value)?;
+        }
+        Ok(())
+    }
 }
 
 impl treecrdt_core::ExactPayloadStore for SqlitePayloadStore {
@@ -226,6 +661,38 @@ impl treecrdt_core::ExactPayloadStore for SqlitePayloadStore {
                 return Err(sqlite_rc_error(step_rc, "delete payload step failed"));
             }
         }
+        self.exec_for_node(&self.delete_values, node, "delete payload values failed")
+    }
+
+    fn clear_payload_fields(&mut self, node: NodeId) -> treecrdt_core::Result<()> {
+        let node_bytes = sqlite_node_id_bytes(node);
+        let stmt = self.delete_fields.get()?;
+        unsafe {
+            sqlite_clear_bindings(stmt);
+            sqlite_reset(stmt);
+            let bind_rc = sqlite_bind_blob(
+                stmt,
+                1,
+                node_bytes.as_ptr() as *const c_void,
+                node_bytes.len() as c_int,
+                None,
+            );
+            if bind_rc != SQLITE_OK as c_int {
+                sqlite_reset(stmt);
+                return Err(sqlite_rc_error(
+                    bind_rc,
+                    "bind delete payload fields failed",
+                ));
+            }
+            let step_rc = sqlite_step(stmt);
+            sqlite_reset(stmt);
+            if step_rc != SQLITE_DONE as c_int {
+                return Err(sqlite_rc_error(
+                    step_rc,
+                    "delete payload fields step failed",
+                ));
+            }
+        }
         Ok(())
     }
 }
@@ -0,0 +1,264 @@
+use super::materialize::node_hex;
+use super::node_store::SqliteNodeStore;
+use super::payload_store::SqlitePayloadStore;
+use super::stability::load_stability;
+use super::statement::LazyStatement;
+use super::util::{sqlite_err_from_core, sqlite_result_json};
+use super::*;
+
+use treecrdt_core::{purge_stable_subtrees, ReplicaId};
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonPurgeReport {
+    roots: Vec<String>,
+    nodes: Vec<String>,
+    payloads: u64,
+    oprefs_children: u64,
+    ops: u64,
+}
+
+const PURGED_OPS_META_KEY: &str = "purged_ops";
+
+/// Ops `include_ops` dropped from the log.
+///
+/// They still count as seen: re-delivered copies are ignored, and local counters and the
+/// lamport clock never fall back below them.
+#[derive(Default, serde::Deserialize, serde::Serialize)]
+pub(super) struct PurgedOps {
+    pub(super) version_vector: VersionVector,
+    pub(super) lamport: Lamport,
+}
+
+pub(super) fn load_purged_ops(db: *mut sqlite3) -> Result<PurgedOps, c_int> {
+    match load_meta(db, PURGED_OPS_META_KEY)? {
+        Some(bytes) if !bytes.is_empty() => {
+            serde_json::from_slice(&bytes).map_err(|_| SQLITE_ERROR as c_int)
+        }
+        _ => Ok(PurgedOps::default()),
+    }
+}
+
+fn store_purged_ops(db: *mut sqlite3, purged: &PurgedOps) -> Result<(), c_int> {
+    let json = serde_json::to_string(purged).map_err(|_| SQLITE_ERROR as c_int)?;
+    store_meta(db, PURGED_OPS_META_KEY, &json)
+}
+
+/// Version vector of every op in the local log, including purged ones.
+fn local_version_vector(db: *mut sqlite3) -> Result<VersionVector, c_int> {
+    let sql = CString::new("SELECT replica, counter FROM ops").expect("static sql");
+    let mut stmt: *mut sqlite3_stmt = null_mut();
+    let rc = sqlite_prepare_v2(db, sql.as_ptr(), -1, &mut stmt, null_mut());
+    if rc != SQLITE_OK as c_int {
+        return Err(rc);
+    }
+
+    let mut vv = load_purged_ops(db)?.version_vector;
+    loop {
+        let step_rc = unsafe { sqlite_step(stmt) };
+        if step_rc == SQLITE_ROW as c_int {
+            let ptr = unsafe { sqlite_column_blob(stmt, 0) } as *const u8;
+            let len = unsafe { sqlite_column_bytes(stmt, 0) } as usize;
+            let replica = if ptr.is_null() {
+                Vec::new()
+            } else {
+                unsafe { slice::from_raw_parts(ptr, len) }.to_vec()
+            };
+            let counter = unsafe { sqlite_column_int64(stmt, 1) }.max(0) as u64;
+            vv.observe(&ReplicaId::new(replica), counter);
+        } else if step_rc == SQLITE_DONE as c_int {
+            break;
+        } else {
+            unsafe { sqlite_finalize(stmt) };
+            return Err(step_rc);
+        }
+    }
+
+    let finalize_rc = unsafe { sqlite_finalize(stmt) };
+    if finalize_rc != SQLITE_OK as c_int {
+        return Err(finalize_rc);
+    }
+    Ok(vv)
+}
+
+/// Record the ids and lamport of `node`'s ops, selected by `stmt`, in `purged`.
+fn observe_ops_for_node(
+    stmt: &LazyStatement,
+    node: NodeId,
+    purged: &mut PurgedOps,
+) -> Result<(), c_int> {
+    let stmt = stmt.get().map_err(|_| SQLITE_ERROR as c_int)?;
+    let node_bytes = node.0.to_be_bytes();
+    unsafe {
+        sqlite_clear_bindings(stmt);
+        sqlite_reset(stmt);
+        let bind_rc = sqlite_bind_blob(
+            stmt,
+            1,
+            node_bytes.as_ptr() as *const c_void,
+            node_bytes.len() as c_int,
+            None,
+        );
+        if bind_rc != SQLITE_OK as c_int {
+            sqlite_reset(stmt);
+            return Err(bind_rc);
+        }
+        loop {
+            let step_rc = sqlite_step(stmt);
+            if step_rc == SQLITE_DONE as c_int {
+                break;
+            }
+            if step_rc != SQLITE_ROW as c_int {
+                sqlite_reset(stmt);
+                return Err(step_rc);
+            }
+            let ptr = sqlite_column_blob(stmt, 0) as *const u8;
+            let len = sqlite_column_bytes(stmt, 0) as usize;
+            let replica = if ptr.is_null() {
+                Vec::new()
+            } else {
+                slice::from_raw_parts(ptr, len).to_vec()
+            };
+            let counter = sqlite_column_int64(stmt, 1).max(0) as u64;
+            let lamport = sqlite_column_int64(stmt, 2).max(0) as Lamport;
+            purged.version_vector.observe(&ReplicaId::new(replica), counter);
+            purged.lamport = purged.lamport.max(lamport);
+        }
+        sqlite_reset(stmt);
+        sqlite_clear_bindings(stmt);
+    }
+    Ok(())
+}
+
+/// Run `stmt` with `node` bound to `?1` and return the number of rows it changed.
+fn delete_for_node(db: *mut sqlite3, stmt: &LazyStatement, node: NodeId) -> Result<u64, c_int> {
+    let stmt = stmt.get().map_err(|_| SQLITE_ERROR as c_int)?;
+    let node_bytes = node.0.to_be_bytes();
+    unsafe {
+        sqlite_clear_bindings(stmt);
+        sqlite_reset(stmt);
+        let bind_rc = sqlite_bind_blob(
+            stmt,
+            1,
+            node_bytes.as_ptr() as *const c_void,
+            node_bytes.len() as c_int,
+            None,
+        );
+        if bind_rc != SQLITE_OK as c_int {
+            sqlite_reset(stmt);
+            return Err(bind_rc);
+        }
+        let step_rc = sqlite_step(stmt);
+        sqlite_reset(stmt);
+        sqlite_clear_bindings(stmt);
+        if step_rc != SQLITE_DONE as c_int {
+            return Err(step_rc);
+        }
+    }
+    Ok(sqlite_changes(db).max(0) as u64)
+}
+
+fn purge_stable_in_savepoint(
+    db: *mut sqlite3,
+    include_ops: bool,
+) -> Result<JsonPurgeReport, c_int> {
+    ensure_materialized(db)?;
+    let local = local_version_vector(db)?;
+    let settled = load_stability(db)?.settled_frontier(&local);
+
+    let mut nodes = SqliteNodeStore::prepare(db).map_err(|_| SQLITE_ERROR as c_int)?;
+    let mut payloads = SqlitePayloadStore::prepare(db).map_err(|_| SQLITE_ERROR as c_int)?;
+    let report =
+        purge_stable_subtrees(&mut nodes, &mut payloads, &settled).map_err(sqlite_err_from_core)?;
+
+    let delete_parent_refs =
+        LazyStatement::new(db, c"DELETE FROM oprefs_children WHERE parent = ?1");
+    let delete_op_refs = LazyStatement::new(
+        db,
+        c"DELETE FROM oprefs_children WHERE op_ref IN (SELECT op_ref FROM ops WHERE node = ?1)",
+    );
+    let select_ops = LazyStatement::new(
+        db,
+        c"SELECT replica, counter, lamport FROM ops WHERE node = ?1",
+    );
+    let delete_ops = LazyStatement::new(db, c"DELETE FROM ops WHERE node = ?1");
+
+    let mut purged = load_purged_ops(db)?;
+    let mut oprefs_children = 0;
+    let mut ops = 0;
+    for &node in &report.nodes {
+        oprefs_children += delete_for_node(db, &delete_parent_refs, node)?;
+        if include_ops {
+            oprefs_children += delete_for_node(db, &delete_op_refs, node)?;
+            observe_ops_for_node(&select_ops, node, &mut purged)?;
+            ops += delete_for_node(db, &delete_ops, node)?;
+        }
+    }
+    if ops > 0 {
+        store_purged_ops(db, &purged)?;
+    }
+
+    Ok(JsonPurgeReport {
+        roots: report.roots.iter().copied().map(node_hex).collect(),
+        nodes: report.nodes.iter().copied().map(node_hex).collect(),
+        payloads: report.payloads.len() as u64,
+        oprefs_children,
+        ops,
+    })
+}
+
+/// Permanently remove deleted subtrees that can no longer be revived. Args: optional
+/// `include_ops` INTEGER (default 0) to also drop the purged nodes' ops from the log.
+///
+/// Uses the settled frontier of the acks recorded with `treecrdt_ack_version_vector`, so nothing
+/// is purged until every tracked peer has acknowledged the deletions. Dropped ops are gone for
+/// sync as well, but their ids and lamport stay in `meta`: copies peers send again are ignored,
+/// and locally minted ids never reuse them.
+///
+/// Returns `{roots, nodes, payloads, oprefsChildren, ops}` describing what was removed.
+pub(super) unsafe extern "C" fn treecrdt_purge_stable(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if argc > 1 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_purge_stable expects 0 or 1 args (include_ops)\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+    let include_ops = if argc == 1 {
+        let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+        (unsafe { sqlite_value_int64(args[0]) }) != 0
+    } else {
+        false
+    };
+
+    let db = sqlite_context_db_handle(ctx);
+    let begin = CString::new("SAVEPOINT treecrdt_purge").expect("static");
+    let commit = CString::new("RELEASE treecrdt_purge").expect("static");
+    let rollback =
+        CString::new("ROLLBACK TO treecrdt_purge; RELEASE treecrdt_purge").expect("static");
+    let rc = sqlite_exec(db, begin.as_ptr(), None, null_mut(), null_mut());
+    if rc != SQLITE_OK as c_int {
+        sqlite_result_error_code(ctx, rc);
+        return;
+    }
+
+    match purge_stable_in_savepoint(db, include_ops) {
+        Ok(report) => {
+            let rc = sqlite_exec(db, commit.as_ptr(), None, null_mut(), null_mut());
+            if rc != SQLITE_OK as c_int {
+                sqlite_exec(db, rollback.as_ptr(), None, null_mut(), null_mut());
+                sqlite_result_error_code(ctx, rc);
+                return;
+            }
+            sqlite_result_json(ctx, &report);
+        }
+        Err(rc) => {
+            sqlite_exec(db, rollback.as_ptr(), None, null_mut(), null_mut());
+            sqlite_result_error_code(ctx, rc);
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use super::sqlite_api::*;
 
 use std::ffi::CString;
-use std::os::raw::{c_int, c_void};
+use std::os::raw::{c_char, c_int, c_void};
 use std::ptr::null_mut;
 use std::slice;
 
@@ -21,13 +21,29 @@ impl MaterializationCursor for TreeMeta {
 }
 
 pub(super) fn load_doc_id(db: *mut sqlite3) -> Result<Option<Vec<u8>>, c_int> {
-    let sql =
-        CString::new("SELECT value FROM meta WHERE key = 'doc_id' LIMIT 1").expect("doc id sql");
+    load_meta(db, "doc_id")
+}
+
+pub(super) fn load_meta(db: *mut sqlite3, key: &str) -> Result<Option<Vec<u8>>, c_int> {
+    let sql = CString::new("SELECT value FROM meta WHERE key = ?1 LIMIT 1").expect("meta sql");
     let mut stmt: *mut sqlite3_stmt = null_mut();
     let rc = sqlite_prepare_v2(db, sql.as_ptr(), -1, &mut stmt, null_mut());
     if rc != SQLITE_OK as c_int {
         return Err(rc);
     }
+    let bind_rc = unsafe {
+        sqlite_bind_text(
+            stmt,
+            1,
+            key.as_ptr() as *const c_char,
+            key.len() as c_int,
+            None,
+        )
+    };
+    if bind_rc != SQLITE_OK as c_int {
+        unsafe { sqlite_finalize(stmt) };
+        return Err(bind_rc);
+    }
 
     let step_rc = unsafe { sqlite_step(stmt) };
     if step_rc == SQLITE_ROW as c_int {
@@ -55,6 +71,52 @@ pub(super) fn load_doc_id(db: *mut sqlite3) -> Result<Option<Vec<u8>>, c_int> {
     }
 }
 
+/// Insert or overwrite a `meta` entry.
+pub(super) fn store_meta(db: *mut sqlite3, key: &str, value: &str) -> Result<(), c_int> {
+    let sql = CString::new(
+        "INSERT INTO meta(key, value) VALUES (?1, ?2) \
+         ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+    )
+    .expect("store meta sql");
+    let mut stmt: *mut sqlite3_stmt = null_mut();
+    let rc = sqlite_prepare_v2(db, sql.as_ptr(), -1, &mut stmt, null_mut());
+    if rc != SQLITE_OK as c_int {
+        return Err(rc);
+    }
+
+    let mut bind_err = false;
+    unsafe {
+        bind_err |= sqlite_bind_text(
+            stmt,
+            1,
+            key.as_ptr() as *const c_char,
+            key.len() as c_int,
+            None,
+        ) != SQLITE_OK as c_int;
+        bind_err |= sqlite_bind_text(
+            stmt,
+            2,
+            value.as_ptr() as *const c_char,
+            value.len() as c_int,
+            None,
+        ) != SQLITE_OK as c_int;
+    }
+    if bind_err {
+        unsafe { sqlite_finalize(stmt) };
+        return Err(SQLITE_ERROR as c_int);
+    }
+
+    let step_rc = unsafe { sqlite_step(stmt) };
+    let finalize_rc = unsafe { sqlite_finalize(stmt) };
+    if step_rc != SQLITE_DONE as c_int {
+        return Err(step_rc);
+    }
+    if finalize_rc != SQLITE_OK as c_int {
+        return Err(finalize_rc);
+    }
+    Ok(())
+}
+
 pub(super) fn load_tree_meta(db: *mut sqlite3) -> Result<TreeMeta, c_int> {
     let sql = CString::new(
         "SELECT head_lamport, head_replica, head_counter, head_seq, \
@@ -240,6 +302,48 @@ pub(super) fn update_tree_meta_head<R: AsRef<[u8]>>(
     Ok(())
 }
 
+fn table_has_column(db: *mut sqlite3, table: &str, column: &str) -> Result<bool, c_int> {
+    let sql = CString::new("SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2")
+        .expect("table info sql");
+    let mut stmt: *mut sqlite3_stmt = null_mut();
+    let rc = sqlite_prepare_v2(db, sql.as_ptr(), -1, &mut stmt, null_mut());
+    if rc != SQLITE_OK as c_int {
+        return Err(rc);
+    }
+    let bind_rc = unsafe {
+        let mut rc = sqlite_bind_text(
+            stmt,
+            1,
+            table.as_ptr() as *const c_char,
+            table.len() as c_int,
+            None,
+        );
+        if rc == SQLITE_OK as c_int {
+            rc = sqlite_bind_text(
+                stmt,
+                2,
+                column.as_ptr() as *const c_char,
+                column.len() as c_int,
+                None,
+            );
+        }
+        rc
+    };
+    if bind_rc != SQLITE_OK as c_int {
+        unsafe { sqlite_finalize(stmt) };
+        return Err(bind_rc);
+    }
+    let step_rc = unsafe { sqlite_step(stmt) };
+    let finalize_rc = unsafe { sqlite_finalize(stmt) };
+    if step_rc != SQLITE_ROW as c_int && step_rc != SQLITE_DONE as c_int {
+        return Err(step_rc);
+    }
+    if finalize_rc != SQLITE_OK as c_int {
+        return Err(finalize_rc);
+    }
+    Ok(step_rc == SQLITE_ROW as c_int)
+}
+
 pub(super) fn ensure_schema(db: *mut sqlite3) -> Result<(), c_int> {
     ensure_api_initialized()?;
 
@@ -263,6 +367,9 @@ CREATE TABLE IF NOT EXISTS ops (
   op_ref BLOB,
   known_state BLOB,
   payload BLOB,
+  group_first INTEGER,
+  group_len INTEGER,
+  field TEXT,
   PRIMARY KEY (replica, counter)
 );
 "#;
@@ -287,7 +394,11 @@ CREATE TABLE IF NOT EXISTS tree_nodes (
   order_key BLOB,
   tombstone INTEGER NOT NULL DEFAULT 0,
   last_change BLOB,
-  deleted_at BLOB
+  deleted_at BLOB,
+  hard_deleted INTEGER NOT NULL DEFAULT 0,
+  deleted_lamport INTEGER,
+  deleted_replica BLOB,
+  deleted_counter INTEGER
 );
 "#;
     const OPREFS_CHILDREN: &str = r#"
@@ -306,6 +417,29 @@ CREATE TABLE IF NOT EXISTS tree_payload (
   last_replica BLOB NOT NULL,
   last_counter INTEGER NOT NULL
 );
+"#;
+    const TREE_PAYLOAD_FIELDS: &str = r#"
+CREATE TABLE IF NOT EXISTS tree_payload_fields (
+  node BLOB NOT NULL,
+  field TEXT NOT NULL,
+  value BLOB,
+  last_lamport INTEGER NOT NULL,
+  last_replica BLOB NOT NULL,
+  last_counter INTEGER NOT NULL,
+  PRIMARY KEY (node, field)
+);
+"#;
+    // Concurrent payload values, only kept in multi-value payload mode.
+    const TREE_PAYLOAD_VALUES: &str = r#"
+CREATE TABLE IF NOT EXISTS tree_payload_values (
+  node BLOB NOT NULL,
+  payload BLOB,
+  lamport INTEGER NOT NULL,
+  replica BLOB NOT NULL,
+  counter INTEGER NOT NULL,
+  known_state BLOB NOT NULL,
+  PRIMARY KEY (node, replica, counter)
+);
 "#;
 
     let rc_meta = {
@@ -322,6 +456,23 @@ CREATE TABLE IF NOT EXISTS tree_payload (
     if rc_ops != SQLITE_OK as c_int {
         return Err(rc_ops);
     }
+    // Op logs created before op groups or payload fields existed lack those columns.
+    for (column, add) in [
+        (
+            "group_first",
+            "ALTER TABLE ops ADD COLUMN group_first INTEGER",
+        ),
+        ("group_len", "ALTER TABLE ops ADD COLUMN group_len INTEGER"),
+        ("field", "ALTER TABLE ops ADD COLUMN field TEXT"),
+    ] {
+        if !table_has_column(db, "ops", column)? {
+            let sql = CString::new(add).expect("ops migration");
+            let rc = sqlite_exec(db, sql.as_ptr(), None, null_mut(), null_mut());
+            if rc != SQLITE_OK as c_int {
+                return Err(rc);
+            }
+        }
+    }
     let rc_tree_meta = {
         let sql = CString::new(TREE_META).expect("tree_meta schema");
         sqlite_exec(db, sql.as_ptr(), None, null_mut(), null_mut())
@@ -336,6 +487,33 @@ CREATE TABLE IF NOT EXISTS tree_payload (
     if rc_nodes != SQLITE_OK as c_int {
         return Err(rc_nodes);
     }
+    // Materialized trees created before hard deletes or trash listings lack those columns.
+    for (column, add) in [
+        (
+            "hard_deleted",
+            "ALTER TABLE tree_nodes ADD COLUMN hard_deleted INTEGER NOT NULL DEFAULT 0",
+        ),
+        (
+            "deleted_lamport",
+            "ALTER TABLE tree_nodes ADD COLUMN deleted_lamport INTEGER",
+        ),
+        (
+            "deleted_replica",
+            "ALTER TABLE tree_nodes ADD COLUMN deleted_replica BLOB",
+        ),
+        (
+            "deleted_counter",
+            "ALTER TABLE tree_nodes ADD COLUMN deleted_counter INTEGER",
+        ),
+    ] {
+        if !table_has_column(db, "tree_nodes", column)? {
+            let sql = CString::new(add).expect("tree_nodes migration");
+            let rc = sqlite_exec(db, sql.as_ptr(), None, null_mut(), null_mut());
+            if rc != SQLITE_OK as c_int {
+                return Err(rc);
+            }
+        }
+    }
     let rc_oprefs = {
         let sql = CString::new(OPREFS_CHILDREN).expect("oprefs_children schema");
         sqlite_exec(db, sql.as_ptr(), None, null_mut(), null_mut())
@@ -351,6 +529,20 @@ CREATE TABLE IF NOT EXISTS tree_payload (
     if rc_tree_payload != SQLITE_OK as c_int {
         return Err(rc_tree_payload);
     }
+    let rc_tree_payload_fields = {
+        let sql = CString::new(TREE_PAYLOAD_FIELDS).expect("tree_payload_fields schema");
+        sqlite_exec(db, sql.as_ptr(), None, null_mut(), null_mut())
+    };
+    if rc_tree_payload_fields != SQLITE_OK as c_int {
+        return Err(rc_tree_payload_fields);
+    }
+    let rc_tree_payload_values = {
+        let sql = CString::new(TREE_PAYLOAD_VALUES).expect("tree_payload_values schema");
+        sqlite_exec(db, sql.as_ptr(), None, null_mut(), null_mut())
+    };
+    if rc_tree_payload_values != SQLITE_OK as c_int {
+        return Err(rc_tree_payload_values);
+    }
 
     const INDEXES: &str = r#"
 CREATE INDEX IF NOT EXISTS idx_ops_lamport ON ops(lamport, replica, counter);
@@ -119,6 +119,7 @@ mod ffi {
         pub fn sqlite3_result_error_code(ctx: *mut sqlite3_context, code: c_int);
         pub fn sqlite3_result_int(ctx: *mut sqlite3_context, value: c_int);
         pub fn sqlite3_result_int64(ctx: *mut sqlite3_context, value: i64);
+        pub fn sqlite3_result_null(ctx: *mut sqlite3_context);
         pub fn sqlite3_result_error(ctx: *mut sqlite3_context, msg: *const c_char, n: c_int);
 
         pub fn sqlite3_context_db_handle(ctx: *mut sqlite3_context) -> *mut sqlite3;
@@ -131,6 +132,14 @@ mod ffi {
 
         pub fn sqlite3_changes(db: *mut sqlite3) -> c_int;
 
+        pub fn sqlite3_get_clientdata(db: *mut sqlite3, name: *const c_char) -> *mut c_void;
+        pub fn sqlite3_set_clientdata(
+            db: *mut sqlite3,
+            name: *const c_char,
+            data: *mut c_void,
+            destructor: Option<unsafe extern "C" fn(*mut c_void)>,
+        ) -> c_int;
+
         pub fn sqlite3_auto_extension(xEntryPoint: Option<unsafe extern "C" fn()>) -> c_int;
     }
 
@@ -259,6 +268,61 @@ pub(super) fn sqlite_changes(db: *mut sqlite3) -> c_int {
     }
 }
 
+/// First SQLite release with per-connection client data.
+#[cfg(feature = "ext-sqlite")]
+const CLIENTDATA_VERSION: c_int = 3_044_000;
+
+/// The API table of a loading SQLite only has the client-data entries from 3.44 on.
+#[cfg(feature = "ext-sqlite")]
+fn clientdata_api<'a>() -> Option<&'a sqlite3_api_routines> {
+    let api = api()?;
+    let version = unsafe { (api.libversion_number?)() };
+    (version >= CLIENTDATA_VERSION).then_some(api)
+}
+
+/// The client data stored on `db` under `name`, or null if there is none (or SQLite predates
+/// client data).
+pub(super) fn sqlite_get_clientdata(db: *mut sqlite3, name: *const c_char) -> *mut c_void {
+    #[cfg(feature = "ext-sqlite")]
+    {
+        match clientdata_api().and_then(|api| api.get_clientdata) {
+            Some(get) => unsafe { get(db, name) },
+            None => std::ptr::null_mut(),
+        }
+    }
+    #[cfg(feature = "static-link")]
+    unsafe {
+        ffi::sqlite3_get_clientdata(db, name)
+    }
+}
+
+/// Store `data` on `db` under `name`; SQLite runs `destructor` on it when it is replaced or the
+/// connection closes, or right away if storing it fails. Fails with `SQLITE_ERROR` if SQLite
+/// predates client data.
+pub(super) fn sqlite_set_clientdata(
+    db: *mut sqlite3,
+    name: *const c_char,
+    data: *mut c_void,
+    destructor: Option<unsafe extern "C" fn(*mut c_void)>,
+) -> c_int {
+    #[cfg(feature = "ext-sqlite")]
+    {
+        match clientdata_api().and_then(|api| api.set_clientdata) {
+            Some(set) => unsafe { set(db, name, data, destructor) },
+            None => {
+                if let (Some(destructor), false) = (destructor, data.is_null()) {
+                    unsafe { destructor(data) };
+                }
+                SQLITE_ERROR as c_int
+            }
+        }
+    }
+    #[cfg(feature = "static-link")]
+    unsafe {
+        ffi::sqlite3_set_clientdata(db, name, data, destructor)
+    }
+}
+
 pub(super) unsafe fn sqlite_bind_blob(
     stmt: *mut sqlite3_stmt,
     idx: c_int,
@@ -446,6 +510,25 @@ pub(super) fn sqlite_result_text(
     }
 }
 
+pub(super) fn sqlite_result_blob(
+    ctx: *mut sqlite3_context,
+    val: *const c_void,
+    len: c_int,
+    destructor: Option<unsafe extern "C" fn(*mut c_void)>,
+) {
+    #[cfg(feature = "ext-sqlite")]
+    {
+        let api = api().expect("api table");
+        unsafe {
+            (api.result_blob.unwrap())(ctx, val, len, destructor);
+        }
+    }
+    #[cfg(feature = "static-link")]
+    unsafe {
+        ffi::sqlite3_result_blob(ctx, val, len, destructor);
+    }
+}
+
 pub(super) fn sqlite_result_error_code(ctx: *mut sqlite3_context, code: c_int) {
     #[cfg(feature = "ext-sqlite")]
     {
@@ -476,6 +559,36 @@ pub(super) fn sqlite_result_int(ctx: *mut sqlite3_context, val: c_int) {
     }
 }
 
+pub(super) fn sqlite_result_int64(ctx: *mut sqlite3_context, val: i64) {
+    #[cfg(feature = "ext-sqlite")]
+    {
+        if let Some(api) = api() {
+            unsafe {
+                (api.result_int64.unwrap())(ctx, val);
+            }
+        }
+    }
+    #[cfg(feature = "static-link")]
+    unsafe {
+        ffi::sqlite3_result_int64(ctx, val);
+    }
+}
+
+pub(super) fn sqlite_result_null(ctx: *mut sqlite3_context) {
+    #[cfg(feature = "ext-sqlite")]
+    {
+        if let Some(api) = api() {
+            unsafe {
+                (api.result_null.unwrap())(ctx);
+            }
+        }
+    }
+    #[cfg(feature = "static-link")]
+    unsafe {
+        ffi::sqlite3_result_null(ctx);
+    }
+}
+
 pub(super) fn sqlite_result_error(ctx: *mut sqlite3_context, msg: *const c_char) {
     #[cfg(feature = "ext-sqlite")]
     {
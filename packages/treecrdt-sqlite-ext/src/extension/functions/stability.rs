@@ -0,0 +1,157 @@
+use super::util::{read_blob, sqlite_result_json};
+use super::*;
+
+use treecrdt_core::{ReplicaId, StabilityTracker};
+
+// Per-peer acknowledged version vectors live in `meta` as one JSON document.
+const STABILITY_META_KEY: &str = "stability_acks";
+
+pub(super) fn load_stability(db: *mut sqlite3) -> Result<StabilityTracker, c_int> {
+    match load_meta(db, STABILITY_META_KEY)? {
+        Some(bytes) if !bytes.is_empty() => {
+            serde_json::from_slice(&bytes).map_err(|_| SQLITE_ERROR as c_int)
+        }
+        _ => Ok(StabilityTracker::new()),
+    }
+}
+
+fn store_stability(db: *mut sqlite3, tracker: &StabilityTracker) -> Result<(), c_int> {
+    let json = serde_json::to_string(tracker).map_err(|_| SQLITE_ERROR as c_int)?;
+    store_meta(db, STABILITY_META_KEY, &json)
+}
+
+/// Read-modify-write the tracker inside a savepoint so concurrent connections cannot lose acks.
+fn update_stability(
+    db: *mut sqlite3,
+    update: impl FnOnce(&mut StabilityTracker),
+) -> Result<StabilityTracker, c_int> {
+    let begin = CString::new("SAVEPOINT treecrdt_stability").expect("static");
+    let commit = CString::new("RELEASE treecrdt_stability").expect("static");
+    let rollback =
+        CString::new("ROLLBACK TO treecrdt_stability; RELEASE treecrdt_stability").expect("static");
+    let rc = sqlite_exec(db, begin.as_ptr(), None, null_mut(), null_mut());
+    if rc != SQLITE_OK as c_int {
+        return Err(rc);
+    }
+
+    let result = load_stability(db).and_then(|mut tracker| {
+        update(&mut tracker);
+        store_stability(db, &tracker)?;
+        Ok(tracker)
+    });
+    match result {
+        Ok(tracker) => {
+            let rc = sqlite_exec(db, commit.as_ptr(), None, null_mut(), null_mut());
+            if rc != SQLITE_OK as c_int {
+                sqlite_exec(db, rollback.as_ptr(), None, null_mut(), null_mut());
+                return Err(rc);
+            }
+            Ok(tracker)
+        }
+        Err(rc) => {
+            sqlite_exec(db, rollback.as_ptr(), None, null_mut(), null_mut());
+            Err(rc)
+        }
+    }
+}
+
+/// Record a peer's acknowledged version vector. Args: peer BLOB, vv TEXT (VersionVector JSON).
+/// Returns the resulting stable frontier as JSON.
+pub(super) unsafe extern "C" fn treecrdt_ack_version_vector(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if argc != 2 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_ack_version_vector expects 2 args (peer, vv)\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+    let Some(peer) = read_blob(args[0]) else {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_ack_version_vector: NULL peer\0".as_ptr() as *const c_char,
+        );
+        return;
+    };
+    let vv_ptr = unsafe { sqlite_value_text(args[1]) } as *const u8;
+    let vv_len = unsafe { sqlite_value_bytes(args[1]) } as usize;
+    if vv_ptr.is_null() {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_ack_version_vector: NULL vv\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+    let acked = match deserialize_version_vector(unsafe { slice::from_raw_parts(vv_ptr, vv_len) }) {
+        Ok(vv) => vv,
+        Err(_) => {
+            sqlite_result_error(
+                ctx,
+                b"treecrdt_ack_version_vector: invalid vv json\0".as_ptr() as *const c_char,
+            );
+            return;
+        }
+    };
+
+    let db = sqlite_context_db_handle(ctx);
+    let peer = ReplicaId::new(peer);
+    match update_stability(db, |tracker| tracker.acknowledge(&peer, &acked)) {
+        Ok(tracker) => sqlite_result_json(ctx, &tracker.stable_frontier()),
+        Err(rc) => sqlite_result_error_code(ctx, rc),
+    }
+}
+
+/// Stop tracking a peer. Args: peer BLOB. Returns 1 if the peer was tracked, 0 otherwise.
+pub(super) unsafe extern "C" fn treecrdt_forget_peer(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if argc != 1 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_forget_peer expects 1 arg (peer)\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+    let Some(peer) = read_blob(args[0]) else {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_forget_peer: NULL peer\0".as_ptr() as *const c_char,
+        );
+        return;
+    };
+
+    let db = sqlite_context_db_handle(ctx);
+    let peer = ReplicaId::new(peer);
+    let mut removed = false;
+    match update_stability(db, |tracker| removed = tracker.remove_peer(&peer).is_some()) {
+        Ok(_) => sqlite_result_int(ctx, removed as c_int),
+        Err(rc) => sqlite_result_error_code(ctx, rc),
+    }
+}
+
+/// Greatest lower bound of all acknowledged version vectors, as VersionVector JSON.
+pub(super) unsafe extern "C" fn treecrdt_stable_frontier(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    _argv: *mut *mut sqlite3_value,
+) {
+    if argc != 0 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_stable_frontier expects 0 args\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+    let db = sqlite_context_db_handle(ctx);
+    match load_stability(db) {
+        Ok(tracker) => sqlite_result_json(ctx, &tracker.stable_frontier()),
+        Err(rc) => sqlite_result_error_code(ctx, rc),
+    }
+}
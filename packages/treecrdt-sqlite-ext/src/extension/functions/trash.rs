@@ -0,0 +1,245 @@
+use super::access::check_read;
+use super::materialize::{json_source, node_hex, JsonMaterializationSource};
+use super::util::{read_text, sqlite_err_from_core, sqlite_result_json};
+use super::*;
+
+use treecrdt_core::{
+    MaterializationSource, MaterializationSourceOperation, OperationId, ReplicaId,
+    TRASH_PREVIEW_LEN,
+};
+
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonTrashCursor {
+    lamport: u64,
+    replica: Vec<u8>,
+    counter: u64,
+    node: String,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonTrashEntry {
+    node: String,
+    parent: Option<String>,
+    source: Option<JsonMaterializationSource>,
+    deleted_at: VersionVector,
+    hard_deleted: bool,
+    payload_preview: Option<Vec<u8>>,
+    cursor: JsonTrashCursor,
+}
+
+unsafe fn column_blob(stmt: *mut sqlite3_stmt, col: c_int) -> Option<Vec<u8>> {
+    if unsafe { sqlite_column_type(stmt, col) } == SQLITE_NULL as c_int {
+        return None;
+    }
+    let ptr = unsafe { sqlite_column_blob(stmt, col) } as *const u8;
+    let len = unsafe { sqlite_column_bytes(stmt, col) } as usize;
+    if ptr.is_null() {
+        return Some(Vec::new());
+    }
+    Some(unsafe { slice::from_raw_parts(ptr, len) }.to_vec())
+}
+
+fn node_from_column(bytes: Option<Vec<u8>>) -> Result<Option<NodeId>, c_int> {
+    match bytes {
+        None => Ok(None),
+        Some(bytes) => {
+            let bytes: [u8; 16] = bytes.try_into().map_err(|_| SQLITE_ERROR as c_int)?;
+            Ok(Some(NodeId(u128::from_be_bytes(bytes))))
+        }
+    }
+}
+
+fn trash_list(
+    db: *mut sqlite3,
+    after: Option<(JsonTrashCursor, [u8; 16])>,
+    limit: i64,
+) -> Result<Vec<JsonTrashEntry>, c_int> {
+    let sql = CString::new(format!(
+        "SELECT n.node, n.parent, n.deleted_lamport, n.deleted_replica, n.deleted_counter, \
+         n.deleted_at, n.hard_deleted, substr(p.payload, 1, {TRASH_PREVIEW_LEN}) \
+         FROM tree_nodes n \
+         LEFT JOIN tree_nodes up ON up.node = n.parent \
+         LEFT JOIN tree_payload p ON p.node = n.node \
+         WHERE n.tombstone = 1 AND COALESCE(up.tombstone, 0) = 0 \
+           AND (?1 IS NULL OR (COALESCE(n.deleted_lamport, 0), COALESCE(n.deleted_replica, X''), \
+                COALESCE(n.deleted_counter, 0), n.node) < (?1, ?2, ?3, ?4)) \
+         ORDER BY COALESCE(n.deleted_lamport, 0) DESC, COALESCE(n.deleted_replica, X'') DESC, \
+                  COALESCE(n.deleted_counter, 0) DESC, n.node DESC \
+         LIMIT ?5"
+    ))
+    .expect("trash sql");
+    let mut stmt: *mut sqlite3_stmt = null_mut();
+    let rc = sqlite_prepare_v2(db, sql.as_ptr(), -1, &mut stmt, null_mut());
+    if rc != SQLITE_OK as c_int {
+        return Err(rc);
+    }
+
+    let mut bind_err = false;
+    unsafe {
+        if let Some((cursor, node)) = &after {
+            bind_err |= sqlite_bind_int64(stmt, 1, cursor.lamport as i64) != SQLITE_OK as c_int;
+            bind_err |= sqlite_bind_blob(
+                stmt,
+                2,
+                cursor.replica.as_ptr() as *const c_void,
+                cursor.replica.len() as c_int,
+                None,
+            ) != SQLITE_OK as c_int;
+            bind_err |= sqlite_bind_int64(stmt, 3, cursor.counter as i64) != SQLITE_OK as c_int;
+            bind_err |= sqlite_bind_blob(
+                stmt,
+                4,
+                node.as_ptr() as *const c_void,
+                node.len() as c_int,
+                None,
+            ) != SQLITE_OK as c_int;
+        }
+        bind_err |= sqlite_bind_int64(stmt, 5, limit) != SQLITE_OK as c_int;
+    }
+    if bind_err {
+        unsafe { sqlite_finalize(stmt) };
+        return Err(SQLITE_ERROR as c_int);
+    }
+
+    let mut out = Vec::new();
+    loop {
+        let step_rc = unsafe { sqlite_step(stmt) };
+        if step_rc == SQLITE_DONE as c_int {
+            break;
+        }
+        if step_rc != SQLITE_ROW as c_int {
+            unsafe { sqlite_finalize(stmt) };
+            return Err(step_rc);
+        }
+        let row = unsafe {
+            (
+                node_from_column(column_blob(stmt, 0)),
+                node_from_column(column_blob(stmt, 1)),
+                (sqlite_column_type(stmt, 2) != SQLITE_NULL as c_int)
+                    .then(|| sqlite_column_int64(stmt, 2).max(0) as u64),
+                column_blob(stmt, 3).unwrap_or_default(),
+                sqlite_column_int64(stmt, 4).max(0) as u64,
+                column_blob(stmt, 5),
+                sqlite_column_int64(stmt, 6) != 0,
+                column_blob(stmt, 7),
+            )
+        };
+        let (node, parent, lamport, replica, counter, deleted_at, hard_deleted, preview) = row;
+        let (node, parent) = match (node, parent) {
+            (Ok(Some(node)), Ok(parent)) => (node, parent),
+            _ => {
+                unsafe { sqlite_finalize(stmt) };
+                return Err(SQLITE_ERROR as c_int);
+            }
+        };
+        let deleted_at = match deleted_at {
+            Some(bytes) => match serde_json::from_slice(&bytes) {
+                Ok(vv) => vv,
+                Err(_) => {
+                    unsafe { sqlite_finalize(stmt) };
+                    return Err(SQLITE_ERROR as c_int);
+                }
+            },
+            None => VersionVector::new(),
+        };
+        // Deleted nodes the caller may not read are left out of the page.
+        match check_read(db, node) {
+            Ok(()) => {}
+            Err(treecrdt_core::Error::AccessDenied(_)) => continue,
+            Err(err) => {
+                unsafe { sqlite_finalize(stmt) };
+                return Err(sqlite_err_from_core(err));
+            }
+        }
+        let source = lamport.map(|lamport| MaterializationSource {
+            operation: MaterializationSourceOperation {
+                id: OperationId {
+                    replica: ReplicaId(replica.clone()),
+                    counter,
+                },
+                lamport,
+            },
+        });
+        out.push(JsonTrashEntry {
+            node: node_hex(node),
+            parent: parent.filter(|parent| *parent != NodeId::TRASH).map(node_hex),
+            source: json_source(&source),
+            deleted_at,
+            hard_deleted,
+            payload_preview: preview,
+            cursor: JsonTrashCursor {
+                lamport: lamport.unwrap_or(0),
+                replica: if lamport.is_some() {
+                    replica
+                } else {
+                    Vec::new()
+                },
+                counter: if lamport.is_some() { counter } else { 0 },
+                node: node_hex(node),
+            },
+        });
+    }
+
+    let finalize_rc = unsafe { sqlite_finalize(stmt) };
+    if finalize_rc != SQLITE_OK as c_int {
+        return Err(finalize_rc);
+    }
+    Ok(out)
+}
+
+/// Deleted subtrees, newest delete first, for a trash view. Args: cursor TEXT (NULL for the first
+/// page, else the `cursor` of the previous page's last entry), limit INTEGER.
+///
+/// Lists nodes that are deleted while their parent is not. Returns `[{node, parent, source,
+/// deletedAt, hardDeleted, payloadPreview, cursor}]`: `parent` is where `treecrdt_local_restore`
+/// puts the node back, `source` the delete or tombstone op, and `payloadPreview` the first 256
+/// bytes of the payload. Entries the access policy hides are skipped, so a page can come back
+/// shorter than `limit` before the listing ends.
+pub(super) unsafe extern "C" fn treecrdt_trash_list(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    if argc != 2 {
+        sqlite_result_error(
+            ctx,
+            b"treecrdt_trash_list expects 2 args (cursor,limit)\0".as_ptr() as *const c_char,
+        );
+        return;
+    }
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+    let after = if unsafe { sqlite_value_type(args[0]) } == SQLITE_NULL as c_int {
+        None
+    } else {
+        let cursor =
+            serde_json::from_str::<JsonTrashCursor>(&read_text(args[0]))
+                .ok()
+                .and_then(|cursor| {
+                    let node = u128::from_str_radix(&cursor.node, 16).ok()?;
+                    Some((cursor, node.to_be_bytes()))
+                });
+        match cursor {
+            Some(cursor) => Some(cursor),
+            None => {
+                sqlite_result_error(
+                    ctx,
+                    b"treecrdt_trash_list: invalid cursor\0".as_ptr() as *const c_char,
+                );
+                return;
+            }
+        }
+    };
+    let limit = unsafe { sqlite_value_int64(args[1]) };
+
+    let db = sqlite_context_db_handle(ctx);
+    if let Err(rc) = ensure_materialized(db) {
+        sqlite_result_error_code(ctx, rc);
+        return;
+    }
+    match trash_list(db, after, limit) {
+        Ok(entries) => sqlite_result_json(ctx, &entries),
+        Err(rc) => sqlite_result_error_code(ctx, rc),
+    }
+}
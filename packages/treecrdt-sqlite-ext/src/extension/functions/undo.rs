@@ -0,0 +1,209 @@
+use super::local_ops::{run_inverse_op, JsonLocalOpResult};
+use super::util::{read_required_blob, sqlite_result_json};
+use super::*;
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+use treecrdt_core::{Error, UndoManager, UndoRecord};
+
+// Undo history is editor session state, not document state: it lives in memory per connection
+// and replica, and is dropped when the connection closes (see `release_connection_state`).
+type UndoKey = (usize, Vec<u8>);
+
+fn registry() -> &'static Mutex<HashMap<UndoKey, UndoManager>> {
+    static REGISTRY: OnceLock<Mutex<HashMap<UndoKey, UndoManager>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn with_manager<R>(db: *mut sqlite3, replica: &[u8], f: impl FnOnce(&mut UndoManager) -> R) -> R {
+    let mut managers = registry().lock().unwrap_or_else(|e| e.into_inner());
+    f(managers.entry((db as usize, replica.to_vec())).or_default())
+}
+
+pub(super) fn record_undo(db: *mut sqlite3, replica: &[u8], record: UndoRecord) {
+    with_manager(db, replica, |manager| manager.record(record));
+}
+
+pub(super) fn record_undo_group(db: *mut sqlite3, replica: &[u8], records: Vec<UndoRecord>) {
+    with_manager(db, replica, |manager| {
+        manager.begin();
+        for record in records {
+            manager.record(record);
+        }
+        manager.commit();
+    });
+}
+
+/// Drop the undo history of every replica on one connection. Closing the connection does this
+/// too.
+pub fn clear_undo_history(db: *mut sqlite3) {
+    let mut managers = registry().lock().unwrap_or_else(|e| e.into_inner());
+    managers.retain(|(handle, _), _| *handle != db as usize);
+}
+
+#[derive(Clone, Copy)]
+enum Direction {
+    Undo,
+    Redo,
+}
+
+impl Direction {
+    fn name(self) -> &'static str {
+        match self {
+            Direction::Undo => "treecrdt_undo",
+            Direction::Redo => "treecrdt_redo",
+        }
+    }
+}
+
+fn step_in_savepoint(
+    db: *mut sqlite3,
+    replica: &[u8],
+    direction: Direction,
+) -> Result<Vec<JsonLocalOpResult>, c_int> {
+    let doc_id = load_doc_id(db)?.ok_or(SQLITE_ERROR as c_int)?;
+    // The manager is taken out of the registry while ops run.
+    let mut manager = with_manager(db, replica, std::mem::take);
+    let mut results = Vec::new();
+    let mut failed = None;
+    let mut invert = |record: &UndoRecord| match run_inverse_op(db, &doc_id, replica, record) {
+        Ok(Some((out, inverse))) => {
+            results.push(out);
+            Ok(Some(inverse))
+        }
+        Ok(None) => Ok(None),
+        Err(rc) => {
+            failed = Some(rc);
+            Err(Error::Storage(format!("sqlite error {rc}")))
+        }
+    };
+    let res = match direction {
+        Direction::Undo => manager.undo_with(&mut invert),
+        Direction::Redo => manager.redo_with(&mut invert),
+    };
+    with_manager(db, replica, |slot| *slot = manager);
+    match res {
+        Ok(_) => Ok(results),
+        Err(_) => Err(failed.unwrap_or(SQLITE_ERROR as c_int)),
+    }
+}
+
+unsafe fn undo_step(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+    direction: Direction,
+) {
+    if let Err(rc) = ensure_api_initialized() {
+        sqlite_result_error_code(ctx, rc);
+        return;
+    }
+    let name = direction.name();
+    if argc != 1 {
+        let msg = CString::new(format!("{name} expects 1 arg (replica)")).expect("static");
+        sqlite_result_error(ctx, msg.as_ptr());
+        return;
+    }
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+    let Ok(replica) = read_required_blob(args[0]) else {
+        let msg = CString::new(format!("{name}: NULL replica")).expect("static");
+        sqlite_result_error(ctx, msg.as_ptr());
+        return;
+    };
+
+    let db = sqlite_context_db_handle(ctx);
+    // Every inverse is rolled back on failure, so the history must go back to how it was too.
+    let before = with_manager(db, &replica, |manager| manager.clone());
+    let restore = || with_manager(db, &replica, |manager| *manager = before.clone());
+    let begin = CString::new(format!("SAVEPOINT {name}")).expect("static");
+    let commit = CString::new(format!("RELEASE {name}")).expect("static");
+    let rollback = CString::new(format!("ROLLBACK TO {name}; RELEASE {name}")).expect("static");
+    let rc = sqlite_exec(db, begin.as_ptr(), None, null_mut(), null_mut());
+    if rc != SQLITE_OK as c_int {
+        sqlite_result_error_code(ctx, rc);
+        return;
+    }
+
+    match step_in_savepoint(db, &replica, direction) {
+        Ok(results) => {
+            let rc = sqlite_exec(db, commit.as_ptr(), None, null_mut(), null_mut());
+            if rc != SQLITE_OK as c_int {
+                sqlite_exec(db, rollback.as_ptr(), None, null_mut(), null_mut());
+                restore();
+                sqlite_result_error_code(ctx, rc);
+                return;
+            }
+            sqlite_result_json(ctx, &results);
+        }
+        Err(rc) => {
+            sqlite_exec(db, rollback.as_ptr(), None, null_mut(), null_mut());
+            restore();
+            sqlite_result_error_code(ctx, rc);
+        }
+    }
+}
+
+/// Revert `replica`'s most recent local transaction on this connection. Args: replica BLOB.
+///
+/// Every reverted op is undone by a new local op; ops superseded by later edits are skipped.
+/// Returns the committed ops as a JSON array of `treecrdt_local_*` results, empty when there was
+/// nothing (left) to undo.
+pub(super) unsafe extern "C" fn treecrdt_undo(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    unsafe { undo_step(ctx, argc, argv, Direction::Undo) }
+}
+
+/// Re-apply `replica`'s most recently undone transaction. Args: replica BLOB. Returns the same
+/// shape as `treecrdt_undo`. Any new local op clears the redo history.
+pub(super) unsafe extern "C" fn treecrdt_redo(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    unsafe { undo_step(ctx, argc, argv, Direction::Redo) }
+}
+
+unsafe fn undo_group(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+    name: &str,
+    f: fn(&mut UndoManager),
+) {
+    if argc != 1 {
+        let msg = CString::new(format!("{name} expects 1 arg (replica)")).expect("static");
+        sqlite_result_error(ctx, msg.as_ptr());
+        return;
+    }
+    let args = unsafe { slice::from_raw_parts(argv, argc as usize) };
+    let Ok(replica) = read_required_blob(args[0]) else {
+        let msg = CString::new(format!("{name}: NULL replica")).expect("static");
+        sqlite_result_error(ctx, msg.as_ptr());
+        return;
+    };
+    with_manager(sqlite_context_db_handle(ctx), &replica, f);
+    sqlite_result_int(ctx, 1);
+}
+
+/// Start grouping `replica`'s local ops into one undo transaction. Args: replica BLOB.
+/// Groups nest; only the outermost `treecrdt_undo_commit` closes them.
+pub(super) unsafe extern "C" fn treecrdt_undo_begin(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    unsafe { undo_group(ctx, argc, argv, "treecrdt_undo_begin", UndoManager::begin) }
+}
+
+/// Close the undo transaction opened by `treecrdt_undo_begin`. Args: replica BLOB.
+pub(super) unsafe extern "C" fn treecrdt_undo_commit(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    unsafe { undo_group(ctx, argc, argv, "treecrdt_undo_commit", UndoManager::commit) }
+}
@@ -28,6 +28,36 @@ pub(super) fn sqlite_result_json_string(ctx: *mut sqlite3_context, json: String)
     }
 }
 
+const BLOB_LEN_PREFIX: usize = std::mem::size_of::<usize>();
+
+/// Hand `bytes` to SQLite as a BLOB result without copying them again.
+///
+/// SQLite only gives the destructor the data pointer back, so the allocation carries its own
+/// length in a prefix right before the data.
+pub(super) fn sqlite_result_bytes(ctx: *mut sqlite3_context, bytes: &[u8]) {
+    let Ok(len) = c_int::try_from(bytes.len()) else {
+        sqlite_result_error_code(ctx, SQLITE_ERROR as c_int);
+        return;
+    };
+    let mut buf = Vec::with_capacity(BLOB_LEN_PREFIX + bytes.len());
+    buf.extend_from_slice(&(BLOB_LEN_PREFIX + bytes.len()).to_ne_bytes());
+    buf.extend_from_slice(bytes);
+    let buf = Box::into_raw(buf.into_boxed_slice()) as *mut u8;
+    let data = unsafe { buf.add(BLOB_LEN_PREFIX) };
+    sqlite_result_blob(ctx, data as *const c_void, len, Some(drop_blob));
+}
+
+unsafe extern "C" fn drop_blob(ptr: *mut c_void) {
+    if ptr.is_null() {
+        return;
+    }
+    unsafe {
+        let buf = (ptr as *mut u8).sub(BLOB_LEN_PREFIX);
+        let total = usize::from_ne_bytes(*(buf as *const [u8; BLOB_LEN_PREFIX]));
+        drop(Box::from_raw(slice::from_raw_parts_mut(buf, total)));
+    }
+}
+
 pub(super) unsafe extern "C" fn drop_cstring(ptr: *mut c_void) {
     if !ptr.is_null() {
         unsafe {
@@ -87,6 +117,12 @@ pub(super) fn read_text(val: *mut sqlite3_value) -> String {
     }
 }
 
-pub(super) fn sqlite_err_from_core(_: treecrdt_core::Error) -> c_int {
-    SQLITE_ERROR as c_int
+// SQLITE_AUTH from sqlite3.h; not every binding we build against re-exports it.
+const SQLITE_AUTH_CODE: c_int = 23;
+
+pub(super) fn sqlite_err_from_core(err: treecrdt_core::Error) -> c_int {
+    match err {
+        treecrdt_core::Error::AccessDenied(_) => SQLITE_AUTH_CODE,
+        _ => SQLITE_ERROR as c_int,
+    }
 }
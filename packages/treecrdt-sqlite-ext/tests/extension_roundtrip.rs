@@ -28,6 +28,8 @@ struct JsonOp {
     order_key: Option<Vec<u8>>,
     known_state: Option<Vec<u8>>,
     payload: Option<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    field: Option<String>,
 }
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -72,6 +74,12 @@ enum JsonMaterializationChange {
         payload: Option<Vec<u8>>,
         source: Option<JsonMaterializationSource>,
     },
+    PayloadField {
+        node: String,
+        field: String,
+        value: Option<Vec<u8>>,
+        source: Option<JsonMaterializationSource>,
+    },
 }
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -183,6 +191,17 @@ fn json_outcome_to_core(outcome: JsonMaterializationOutcome) -> MaterializationO
                     payload,
                     source: source_to_core(source),
                 },
+                JsonMaterializationChange::PayloadField {
+                    node,
+                    field,
+                    value,
+                    source,
+                } => MaterializationChange::PayloadField {
+                    node: hex_to_node_id(&node),
+                    field,
+                    value,
+                    source: source_to_core(source),
+                },
             })
             .collect(),
     }
@@ -200,6 +219,7 @@ fn vv_to_bytes(vv: &VersionVector) -> Vec<u8> {
 }
 
 fn json_op(op: &Operation) -> JsonOp {
+    let mut field = None;
     let (kind, parent, node, new_parent, order_key, payload) = match &op.kind {
         OperationKind::Insert {
             parent,
@@ -230,6 +250,9 @@ fn json_op(op: &Operation) -> JsonOp {
         OperationKind::Tombstone { node } => {
             ("tombstone", None, node.0.to_be_bytes(), None, None, None)
         }
+        OperationKind::Restore { node } => {
+            ("restore", None, node.0.to_be_bytes(), None, None, None)
+        }
         OperationKind::Payload { node, payload } => (
             "payload",
             None,
@@ -238,6 +261,21 @@ fn json_op(op: &Operation) -> JsonOp {
             None,
             payload.clone(),
         ),
+        OperationKind::PayloadField {
+            node,
+            field: name,
+            value,
+        } => {
+            field = Some(name.clone());
+            (
+                "payload_field",
+                None,
+                node.0.to_be_bytes(),
+                None,
+                None,
+                value.clone(),
+            )
+        }
     };
 
     JsonOp {
@@ -251,6 +289,7 @@ fn json_op(op: &Operation) -> JsonOp {
         order_key,
         known_state: op.meta.known_state.as_ref().map(vv_to_bytes),
         payload,
+        field,
     }
 }
 
@@ -1252,6 +1291,329 @@ fn local_payload_does_not_prepare_an_unused_delete_statement() {
     result.expect("payload writes should not prepare the unused payload-delete statement");
 }
 
+#[test]
+fn stability_acks_persist_in_meta_and_yield_stable_frontier() {
+    let conn = setup_conn();
+    let a = ReplicaId::new(b"a");
+    let b = ReplicaId::new(b"b");
+
+    let frontier = |conn: &Connection| -> VersionVector {
+        let json: String = conn
+            .query_row("SELECT treecrdt_stable_frontier()", [], |row| row.get(0))
+            .unwrap();
+        serde_json::from_str(&json).unwrap()
+    };
+    let ack = |conn: &Connection, peer: &ReplicaId, vv: &VersionVector| -> VersionVector {
+        let json: String = conn
+            .query_row(
+                "SELECT treecrdt_ack_version_vector(?1, ?2)",
+                rusqlite::params![peer.as_bytes(), serde_json::to_string(vv).unwrap()],
+                |row| row.get(0),
+            )
+            .unwrap();
+        serde_json::from_str(&json).unwrap()
+    };
+
+    assert!(frontier(&conn).is_empty());
+
+    let mut a_vv = VersionVector::new();
+    a_vv.observe(&a, 1);
+    a_vv.observe(&a, 2);
+    a_vv.observe(&b, 1);
+    let mut b_vv = VersionVector::new();
+    b_vv.observe(&a, 1);
+    b_vv.observe(&b, 1);
+    b_vv.observe(&b, 2);
+
+    assert_eq!(ack(&conn, &a, &a_vv), a_vv);
+    let stable = ack(&conn, &b, &b_vv);
+    let mut expected = VersionVector::new();
+    expected.observe(&a, 1);
+    expected.observe(&b, 1);
+    assert_eq!(stable, expected);
+    assert_eq!(frontier(&conn), expected);
+
+    let stored: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM meta WHERE key = 'stability_acks'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(stored, 1);
+
+    let forget = |peer: &ReplicaId| -> i64 {
+        conn.query_row(
+            "SELECT treecrdt_forget_peer(?1)",
+            rusqlite::params![peer.as_bytes()],
+            |row| row.get(0),
+        )
+        .unwrap()
+    };
+    assert_eq!(forget(&b), 1);
+    assert_eq!(forget(&b), 0);
+    assert_eq!(frontier(&conn), a_vv);
+}
+
+#[test]
+fn purge_stable_drops_acknowledged_deleted_subtrees() {
+    let conn = setup_conn();
+
+    let replica = b"r1".to_vec();
+    let root = node_bytes(0);
+    let a = node_bytes(1);
+    let child = node_bytes(2);
+    let b = node_bytes(3);
+
+    for (parent, node, payload) in [
+        (&root, &a, None),
+        (&a, &child, Some(vec![7u8])),
+        (&root, &b, None),
+    ] {
+        let _: String = conn
+            .query_row(
+                "SELECT treecrdt_local_insert(?1, ?2, ?3, 'last', NULL, ?4)",
+                rusqlite::params![replica.clone(), parent.clone(), node.clone(), payload],
+                |row| row.get(0),
+            )
+            .unwrap();
+    }
+    let _: String = conn
+        .query_row(
+            "SELECT treecrdt_local_delete(?1, ?2)",
+            rusqlite::params![replica.clone(), a.clone()],
+            |row| row.get(0),
+        )
+        .unwrap();
+
+    let purge = |conn: &Connection| -> serde_json::Value {
+        let json: String =
+            conn.query_row("SELECT treecrdt_purge_stable(1)", [], |row| row.get(0)).unwrap();
+        serde_json::from_str(&json).unwrap()
+    };
+
+    // No peer has acknowledged anything yet.
+    assert_eq!(purge(&conn)["nodes"], serde_json::json!([]));
+
+    let mut vv = VersionVector::new();
+    for counter in 1..=4 {
+        vv.observe(&ReplicaId::new(replica.clone()), counter);
+    }
+    let _: String = conn
+        .query_row(
+            "SELECT treecrdt_ack_version_vector(?1, ?2)",
+            rusqlite::params![replica.clone(), serde_json::to_string(&vv).unwrap()],
+            |row| row.get(0),
+        )
+        .unwrap();
+
+    let history: String =
+        conn.query_row("SELECT treecrdt_ops_since(0)", [], |row| row.get(0)).unwrap();
+
+    let report = purge(&conn);
+    assert_eq!(report["roots"], serde_json::json!([format!("{:032x}", 1)]));
+    assert_eq!(
+        report["nodes"],
+        serde_json::json!([format!("{:032x}", 1), format!("{:032x}", 2)])
+    );
+    assert_eq!(report["payloads"], serde_json::json!(1));
+    assert_eq!(report["ops"], serde_json::json!(3));
+
+    let remaining: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM tree_nodes WHERE node IN (?1, ?2)",
+            rusqlite::params![a, child],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(remaining, 0);
+    assert_eq!(visible_children(&conn, &root), vec![b.clone()]);
+
+    // Heads still count the dropped ops, so the delete (counter 4, lamport 4) is not forgotten.
+    let head_lamport: i64 =
+        conn.query_row("SELECT treecrdt_head_lamport()", [], |row| row.get(0)).unwrap();
+    assert_eq!(head_lamport, 4);
+    let max_counter: i64 = conn
+        .query_row(
+            "SELECT treecrdt_replica_max_counter(?1)",
+            rusqlite::params![replica.clone()],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(max_counter, 4);
+
+    // Redelivering the purged ops is a no-op instead of resurrecting the subtree.
+    let _: String = conn
+        .query_row("SELECT treecrdt_append_ops(?1)", [history], |row| {
+            row.get(0)
+        })
+        .unwrap();
+    let op_count: i64 = conn.query_row("SELECT COUNT(*) FROM ops", [], |row| row.get(0)).unwrap();
+    assert_eq!(op_count, 1);
+    assert_eq!(visible_children(&conn, &root), vec![b.clone()]);
+
+    let json: String = conn
+        .query_row(
+            "SELECT treecrdt_local_insert(?1, ?2, ?3, 'last', NULL, NULL)",
+            rusqlite::params![replica.clone(), root.clone(), node_bytes(4)],
+            |row| row.get(0),
+        )
+        .unwrap();
+    let inserted = decode_ops_or_local_result(&json);
+    assert_eq!(inserted[0].counter, 5);
+    assert!(inserted[0].lamport > 4);
+}
+
+#[test]
+fn undo_and_redo_group_local_ops_per_replica() {
+    let conn = setup_conn();
+
+    let replica = b"r1".to_vec();
+    let root = node_bytes(0);
+    let a = node_bytes(1);
+    let b = node_bytes(2);
+
+    let undo = |conn: &Connection, func: &str| -> Vec<serde_json::Value> {
+        let json: String = conn
+            .query_row(
+                &format!("SELECT {func}(?1)"),
+                rusqlite::params![replica.clone()],
+                |row| row.get(0),
+            )
+            .unwrap();
+        serde_json::from_str(&json).unwrap()
+    };
+    let group = |conn: &Connection, func: &str| {
+        let _: i64 = conn
+            .query_row(
+                &format!("SELECT {func}(?1)"),
+                rusqlite::params![replica.clone()],
+                |row| row.get(0),
+            )
+            .unwrap();
+    };
+
+    group(&conn, "treecrdt_undo_begin");
+    for node in [&a, &b] {
+        let _: String = conn
+            .query_row(
+                "SELECT treecrdt_local_insert(?1, ?2, ?3, 'last', NULL, NULL)",
+                rusqlite::params![replica.clone(), root.clone(), node.clone()],
+                |row| row.get(0),
+            )
+            .unwrap();
+    }
+    group(&conn, "treecrdt_undo_commit");
+    let _: String = conn
+        .query_row(
+            "SELECT treecrdt_local_payload(?1, ?2, ?3)",
+            rusqlite::params![replica.clone(), a.clone(), vec![1u8]],
+            |row| row.get(0),
+        )
+        .unwrap();
+
+    let reverted = undo(&conn, "treecrdt_undo");
+    assert_eq!(reverted.len(), 1);
+    assert_eq!(reverted[0]["op"]["kind"], serde_json::json!("payload"));
+    assert_eq!(reverted[0]["op"]["payload"], serde_json::Value::Null);
+
+    let reverted = undo(&conn, "treecrdt_undo");
+    assert_eq!(reverted.len(), 2);
+    assert!(visible_children(&conn, &root).is_empty());
+
+    let reapplied = undo(&conn, "treecrdt_redo");
+    assert_eq!(reapplied.len(), 2);
+    assert_eq!(visible_children(&conn, &root), vec![a.clone(), b.clone()]);
+
+    // Undo only ever appends ops: 3 edits, 3 inverses, 2 redos.
+    let ops: i64 = conn.query_row("SELECT COUNT(*) FROM ops", [], |row| row.get(0)).unwrap();
+    assert_eq!(ops, 8);
+
+    // Another replica's history is separate.
+    let json: String = conn
+        .query_row(
+            "SELECT treecrdt_undo(?1)",
+            rusqlite::params![b"r2".to_vec()],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(json, "[]");
+}
+
+#[test]
+fn tree_at_rebuilds_the_tree_at_a_past_version_vector() {
+    let conn = setup_conn();
+
+    let replica = b"r1".to_vec();
+    let root = node_bytes(0);
+    let a = node_bytes(1);
+    let b = node_bytes(2);
+
+    for node in [&a, &b] {
+        let _: String = conn
+            .query_row(
+                "SELECT treecrdt_local_insert(?1, ?2, ?3, 'last', NULL, ?4)",
+                rusqlite::params![replica.clone(), root.clone(), node.clone(), vec![1u8]],
+                |row| row.get(0),
+            )
+            .unwrap();
+    }
+    let _: String = conn
+        .query_row(
+            "SELECT treecrdt_local_delete(?1, ?2)",
+            rusqlite::params![replica.clone(), a.clone()],
+            |row| row.get(0),
+        )
+        .unwrap();
+
+    let tree_at = |counter: u64| -> serde_json::Value {
+        let mut vv = VersionVector::new();
+        for c in 1..=counter {
+            vv.observe(&ReplicaId::new(replica.clone()), c);
+        }
+        let json: String = conn
+            .query_row(
+                "SELECT treecrdt_tree_at(?1)",
+                rusqlite::params![serde_json::to_string(&vv).unwrap()],
+                |row| row.get(0),
+            )
+            .unwrap();
+        serde_json::from_str(&json).unwrap()
+    };
+    let row = |rows: &serde_json::Value, id: u128| -> serde_json::Value {
+        let hex = format!("{:032x}", id);
+        rows.as_array()
+            .unwrap()
+            .iter()
+            .find(|row| row["node"] == serde_json::json!(hex))
+            .cloned()
+            .unwrap_or(serde_json::Value::Null)
+    };
+
+    let before_second = tree_at(1);
+    assert_eq!(
+        row(&before_second, 1)["parent"],
+        serde_json::json!(format!("{:032x}", 0))
+    );
+    assert_eq!(row(&before_second, 1)["payload"], serde_json::json!([1]));
+    assert_eq!(row(&before_second, 2), serde_json::Value::Null);
+
+    let before_delete = tree_at(2);
+    assert_eq!(
+        row(&before_delete, 1)["tombstone"],
+        serde_json::json!(false)
+    );
+    assert_eq!(
+        row(&before_delete, 2)["tombstone"],
+        serde_json::json!(false)
+    );
+
+    let now = tree_at(3);
+    assert_eq!(row(&now, 1)["tombstone"], serde_json::json!(true));
+    // The live materialized tree is unaffected.
+    assert_eq!(visible_children(&conn, &root), vec![b]);
+}
+
 fn setup_conn() -> Connection {
     let ext_path = find_extension().expect("extension dylib path");
     let conn = Connection::open_in_memory().unwrap();
@@ -1305,3 +1667,1159 @@ fn extension_filenames() -> (String, String) {
         "libtreecrdt_sqlite_ext.dll".into(),
     );
 }
+
+#[test]
+fn tree_diff_lists_the_changes_between_two_version_vectors() {
+    let conn = setup_conn();
+
+    let replica = b"r1".to_vec();
+    let root = node_bytes(0);
+    let a = node_bytes(1);
+    let b = node_bytes(2);
+
+    for node in [&a, &b] {
+        let _: String = conn
+            .query_row(
+                "SELECT treecrdt_local_insert(?1, ?2, ?3, 'last', NULL, NULL)",
+                rusqlite::params![replica.clone(), root.clone(), node.clone()],
+                |row| row.get(0),
+            )
+            .unwrap();
+    }
+    let _: String = conn
+        .query_row(
+            "SELECT treecrdt_local_move(?1, ?2, ?3, 'first', NULL)",
+            rusqlite::params![replica.clone(), b.clone(), a.clone()],
+            |row| row.get(0),
+        )
+        .unwrap();
+    let _: String = conn
+        .query_row(
+            "SELECT treecrdt_local_payload(?1, ?2, ?3)",
+            rusqlite::params![replica.clone(), a.clone(), vec![7u8]],
+            |row| row.get(0),
+        )
+        .unwrap();
+
+    let vv = |counter: u64| -> String {
+        let mut vv = VersionVector::new();
+        for c in 1..=counter {
+            vv.observe(&ReplicaId::new(replica.clone()), c);
+        }
+        serde_json::to_string(&vv).unwrap()
+    };
+    let json: String = conn
+        .query_row(
+            "SELECT treecrdt_tree_diff(?1, ?2)",
+            rusqlite::params![vv(2), vv(4)],
+            |row| row.get(0),
+        )
+        .unwrap();
+    let changes: serde_json::Value = serde_json::from_str(&json).unwrap();
+    let changes = changes.as_array().unwrap();
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[0]["kind"], serde_json::json!("move"));
+    assert_eq!(changes[0]["node"], serde_json::json!(format!("{:032x}", 2)));
+    assert_eq!(
+        changes[0]["parentAfter"],
+        serde_json::json!(format!("{:032x}", 1))
+    );
+    assert_eq!(changes[0]["source"]["operation"]["id"]["counter"], 3);
+    assert_eq!(changes[1]["kind"], serde_json::json!("payload"));
+    assert_eq!(changes[1]["payload"], serde_json::json!([7]));
+
+    let json: String = conn
+        .query_row(
+            "SELECT treecrdt_tree_diff(?1, ?1)",
+            rusqlite::params![vv(4)],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(json, "[]");
+}
+
+#[test]
+fn binary_append_and_ops_since_roundtrip() {
+    let conn = setup_conn();
+
+    let a = ReplicaId::new(b"a");
+    let b = ReplicaId::new(b"b");
+    let mut known = VersionVector::new();
+    known.observe(&a, 1);
+    known.observe(&a, 2);
+    let ops = vec![
+        Operation::insert(&a, 1, 1, NodeId::ROOT, NodeId(1), vec![0x80]),
+        Operation::insert_with_payload(&b, 1, 2, NodeId(1), NodeId(2), vec![0x80], vec![7u8]),
+        Operation::insert(&a, 2, 3, NodeId::ROOT, NodeId(3), vec![0x90]),
+        Operation::delete(&a, 3, 4, NodeId(3), Some(known)),
+    ];
+    let _: String = conn
+        .query_row(
+            "SELECT treecrdt_append_ops_blob(?1)",
+            rusqlite::params![treecrdt_core::encode_ops(&ops)],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(visible_children(&conn, &node_bytes(0)), vec![node_bytes(1)]);
+
+    let since = |sql: &str, params: &[&dyn rusqlite::ToSql]| -> Vec<Operation> {
+        let bytes: Vec<u8> = conn.query_row(sql, params, |row| row.get(0)).unwrap();
+        treecrdt_core::decode_ops(&bytes).unwrap()
+    };
+    assert_eq!(since("SELECT treecrdt_ops_since_blob(0)", &[]), ops);
+    assert_eq!(since("SELECT treecrdt_ops_since_blob(3)", &[]), ops[3..]);
+    assert_eq!(
+        since("SELECT treecrdt_ops_since_blob(0, ?1)", &[&node_bytes(1)]),
+        ops[..2]
+    );
+
+    let err = conn
+        .query_row(
+            "SELECT treecrdt_append_ops_blob(?1)",
+            rusqlite::params![vec![0xffu8, 0]],
+            |row| row.get::<_, String>(0),
+        )
+        .unwrap_err();
+    assert!(err.to_string().contains("failed to decode"));
+}
+
+#[test]
+fn local_batch_commits_edits_atomically_with_one_outcome() {
+    let conn = setup_conn();
+
+    let replica = b"r1".to_vec();
+    let root = node_bytes(0);
+    let folder = node_bytes(10);
+    let children: Vec<Vec<u8>> = (1..=3).map(node_bytes).collect();
+    for node in &children {
+        let _: String = conn
+            .query_row(
+                "SELECT treecrdt_local_insert(?1, ?2, ?3, 'last', NULL, NULL)",
+                rusqlite::params![replica.clone(), root.clone(), node.clone()],
+                |row| row.get(0),
+            )
+            .unwrap();
+    }
+
+    let batch = |edits: serde_json::Value| -> rusqlite::Result<String> {
+        conn.query_row(
+            "SELECT treecrdt_local_batch(?1, ?2)",
+            rusqlite::params![replica.clone(), edits.to_string()],
+            |row| row.get(0),
+        )
+    };
+
+    // The second move refers to a node that is not under `folder`, so nothing is kept.
+    let failed = batch(serde_json::json!([
+        {"kind": "insert", "parent": root, "node": folder, "placement": "first"},
+        {"kind": "move", "node": children[0], "new_parent": folder, "placement": "last"},
+        {"kind": "move", "node": children[1], "new_parent": folder, "placement": "after",
+         "after": children[2]},
+    ]));
+    assert!(failed.is_err());
+    assert_eq!(visible_children(&conn, &root), children);
+
+    let json = batch(serde_json::json!([
+        {"kind": "insert", "parent": root, "node": folder, "placement": "first"},
+        {"kind": "payload", "node": folder, "payload": [7]},
+        {"kind": "move", "node": children[0], "new_parent": folder, "placement": "last"},
+        {"kind": "move", "node": children[2], "new_parent": folder, "placement": "after",
+         "after": children[0]},
+        {"kind": "move", "node": children[1], "new_parent": folder, "placement": "last"},
+    ]))
+    .unwrap();
+    let result: serde_json::Value = serde_json::from_str(&json).unwrap();
+    let counters: Vec<u64> = result["ops"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|op| op["counter"].as_u64().unwrap())
+        .collect();
+    assert_eq!(counters, vec![4, 5, 6, 7, 8]);
+    let outcome: JsonMaterializationOutcome =
+        serde_json::from_value(result["outcome"].clone()).unwrap();
+    let outcome = json_outcome_to_core(outcome);
+    assert_eq!(outcome.changes.len(), 4);
+    assert_eq!(read_tree_meta(&conn).3, outcome.head_seq as i64);
+
+    assert_eq!(visible_children(&conn, &root), vec![folder.clone()]);
+    assert_eq!(
+        visible_children(&conn, &folder),
+        vec![
+            children[0].clone(),
+            children[2].clone(),
+            children[1].clone()
+        ]
+    );
+    assert_eq!(payload_bytes(&conn, &folder), Some(vec![7]));
+
+    // The whole batch is one undo step.
+    let json: String = conn
+        .query_row(
+            "SELECT treecrdt_undo(?1)",
+            rusqlite::params![replica.clone()],
+            |row| row.get(0),
+        )
+        .unwrap();
+    let reverted: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+    assert_eq!(reverted.len(), 5);
+    assert_eq!(visible_children(&conn, &root), children);
+}
+
+#[test]
+fn atomic_groups_hold_back_partial_groups() {
+    let writer = setup_conn();
+    let reader = setup_conn();
+
+    let replica = b"r1".to_vec();
+    let root = node_bytes(0);
+    let folder = node_bytes(10);
+    let children: Vec<Vec<u8>> = (1..=2).map(node_bytes).collect();
+    let _: String = writer
+        .query_row(
+            "SELECT treecrdt_local_batch(?1, ?2)",
+            rusqlite::params![
+                replica,
+                serde_json::json!([
+                    {"kind": "insert", "parent": root, "node": folder, "placement": "last"},
+                    {"kind": "insert", "parent": folder, "node": children[0], "placement": "last"},
+                    {"kind": "insert", "parent": folder, "node": children[1], "placement": "last"},
+                ])
+                .to_string()
+            ],
+            |row| row.get(0),
+        )
+        .unwrap();
+    let json: String =
+        writer.query_row("SELECT treecrdt_ops_since(0)", [], |row| row.get(0)).unwrap();
+    let ops: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+    assert!(ops.iter().all(|op| op["group"] == serde_json::json!({"first": 1, "len": 3})));
+
+    let append = |ops: &[serde_json::Value]| -> String {
+        reader
+            .query_row(
+                "SELECT treecrdt_append_ops(?1)",
+                [serde_json::Value::from(ops.to_vec()).to_string()],
+                |row| row.get(0),
+            )
+            .unwrap()
+    };
+    let _: String = reader
+        .query_row("SELECT treecrdt_set_atomic_groups(1)", [], |row| row.get(0))
+        .unwrap();
+    append(&ops[..2]);
+    assert!(visible_children(&reader, &root).is_empty());
+
+    let outcome: JsonMaterializationOutcome = serde_json::from_str(&append(&ops[2..])).unwrap();
+    assert_eq!(json_outcome_to_core(outcome).changes.len(), 3);
+    assert_eq!(visible_children(&reader, &root), vec![folder.clone()]);
+    assert_eq!(visible_children(&reader, &folder), children);
+
+    // Groups persist with their ops, so a third replica syncing from the reader buffers too.
+    let json: String =
+        reader.query_row("SELECT treecrdt_ops_since(0)", [], |row| row.get(0)).unwrap();
+    let relayed: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+    assert_eq!(relayed, ops);
+
+    // With a timeout of zero, an incomplete group is applied on the next append.
+    let late = setup_conn();
+    let _: String = late
+        .query_row("SELECT treecrdt_set_atomic_groups(1, 0)", [], |row| {
+            row.get(0)
+        })
+        .unwrap();
+    let _: String = late
+        .query_row(
+            "SELECT treecrdt_append_ops(?1)",
+            [serde_json::Value::from(ops[..1].to_vec()).to_string()],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(visible_children(&late, &root), vec![folder]);
+
+    // Buffers outlive connections; turn them off so a later handle starts clean.
+    for conn in [&reader, &late] {
+        let _: String = conn
+            .query_row("SELECT treecrdt_set_atomic_groups(0)", [], |row| row.get(0))
+            .unwrap();
+    }
+
+    // Disabling appends whatever is still held back.
+    let waiting = setup_conn();
+    let _: String = waiting
+        .query_row("SELECT treecrdt_set_atomic_groups(1)", [], |row| row.get(0))
+        .unwrap();
+    let _: String = waiting
+        .query_row(
+            "SELECT treecrdt_append_ops(?1)",
+            [serde_json::Value::from(ops[..1].to_vec()).to_string()],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert!(visible_children(&waiting, &root).is_empty());
+    let _: String = waiting
+        .query_row("SELECT treecrdt_set_atomic_groups(0)", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(visible_children(&waiting, &root), vec![node_bytes(10)]);
+}
+
+#[test]
+fn atomic_groups_keep_held_ops_when_the_append_fails() {
+    let writer = setup_conn();
+    let reader = setup_conn();
+
+    let root = node_bytes(0);
+    let folder = node_bytes(10);
+    let _: String = writer
+        .query_row(
+            "SELECT treecrdt_local_batch(?1, ?2)",
+            rusqlite::params![
+                b"r1".to_vec(),
+                serde_json::json!([
+                    {"kind": "insert", "parent": root, "node": folder, "placement": "last"},
+                    {"kind": "insert", "parent": folder, "node": node_bytes(1), "placement": "last"},
+                ])
+                .to_string()
+            ],
+            |row| row.get(0),
+        )
+        .unwrap();
+    let json: String =
+        writer.query_row("SELECT treecrdt_ops_since(0)", [], |row| row.get(0)).unwrap();
+    let ops: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+
+    let _: String = reader
+        .query_row("SELECT treecrdt_set_atomic_groups(1, 0)", [], |row| {
+            row.get(0)
+        })
+        .unwrap();
+    reader
+        .execute_batch(
+            "CREATE TEMP TRIGGER reject_ops BEFORE INSERT ON ops BEGIN \
+             SELECT RAISE(ABORT, 'rejected'); END;",
+        )
+        .unwrap();
+    // With a timeout of zero the first member is released right away, and the append fails.
+    let failed: rusqlite::Result<String> = reader.query_row(
+        "SELECT treecrdt_append_ops(?1)",
+        [serde_json::Value::from(ops[..1].to_vec()).to_string()],
+        |row| row.get(0),
+    );
+    assert!(failed.is_err());
+
+    // Hold the member back, then fail to release it: it stays buffered.
+    let _: String = reader
+        .query_row("SELECT treecrdt_set_atomic_groups(1)", [], |row| row.get(0))
+        .unwrap();
+    reader.execute_batch("DROP TRIGGER reject_ops").unwrap();
+    let _: String = reader
+        .query_row(
+            "SELECT treecrdt_append_ops(?1)",
+            [serde_json::Value::from(ops[..1].to_vec()).to_string()],
+            |row| row.get(0),
+        )
+        .unwrap();
+    reader
+        .execute_batch(
+            "CREATE TEMP TRIGGER reject_ops BEFORE INSERT ON ops BEGIN \
+             SELECT RAISE(ABORT, 'rejected'); END;",
+        )
+        .unwrap();
+    let disabled: rusqlite::Result<String> =
+        reader.query_row("SELECT treecrdt_set_atomic_groups(0)", [], |row| row.get(0));
+    assert!(disabled.is_err());
+    reader.execute_batch("DROP TRIGGER reject_ops").unwrap();
+    assert!(visible_children(&reader, &root).is_empty());
+
+    // Still enabled, so the rest of the group completes it.
+    let _: String = reader
+        .query_row(
+            "SELECT treecrdt_append_ops(?1)",
+            [serde_json::Value::from(ops[1..].to_vec()).to_string()],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(visible_children(&reader, &root), vec![folder.clone()]);
+    assert_eq!(visible_children(&reader, &folder), vec![node_bytes(1)]);
+}
+
+#[test]
+fn local_duplicate_copies_a_branch_in_one_transaction() {
+    let conn = setup_conn();
+
+    let replica = b"r1".to_vec();
+    let root = node_bytes(0);
+    let folder = node_bytes(1);
+    let insert = |parent: &[u8], node: &[u8], payload: Option<Vec<u8>>| {
+        let _: String = conn
+            .query_row(
+                "SELECT treecrdt_local_insert(?1, ?2, ?3, 'last', NULL, ?4)",
+                rusqlite::params![replica.clone(), parent, node, payload],
+                |row| row.get(0),
+            )
+            .unwrap();
+    };
+    insert(&root, &folder, Some(b"folder".to_vec()));
+    insert(&folder, &node_bytes(2), Some(vec![2]));
+    insert(&folder, &node_bytes(3), None);
+    insert(&root, &node_bytes(4), None);
+
+    let duplicate = || -> String {
+        conn.query_row(
+            "SELECT treecrdt_local_duplicate(?1, ?2, ?3, 'after', ?2)",
+            rusqlite::params![replica.clone(), folder.clone(), root.clone()],
+            |row| row.get(0),
+        )
+        .unwrap()
+    };
+    let result: serde_json::Value = serde_json::from_str(&duplicate()).unwrap();
+    let mapping: Vec<(Vec<u8>, Vec<u8>)> = result["mapping"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| {
+            let bytes = |value: &serde_json::Value| -> Vec<u8> {
+                serde_json::from_value(value.clone()).unwrap()
+            };
+            (bytes(&entry["source"]), bytes(&entry["copy"]))
+        })
+        .collect();
+    let sources: Vec<Vec<u8>> = mapping.iter().map(|(source, _)| source.clone()).collect();
+    assert_eq!(sources, vec![folder.clone(), node_bytes(2), node_bytes(3)]);
+    assert_eq!(result["ops"].as_array().unwrap().len(), 3);
+
+    let copy = mapping[0].1.clone();
+    assert_eq!(
+        visible_children(&conn, &root),
+        vec![folder.clone(), copy.clone(), node_bytes(4)]
+    );
+    assert_eq!(
+        visible_children(&conn, &copy),
+        vec![mapping[1].1.clone(), mapping[2].1.clone()]
+    );
+    assert_eq!(payload_bytes(&conn, &copy), Some(b"folder".to_vec()));
+    assert_eq!(payload_bytes(&conn, &mapping[1].1), Some(vec![2]));
+
+    // A second copy gets ids of its own.
+    let again: serde_json::Value = serde_json::from_str(&duplicate()).unwrap();
+    let second: Vec<u8> = serde_json::from_value(again["mapping"][0]["copy"].clone()).unwrap();
+    assert_ne!(second, copy);
+    assert_eq!(visible_children(&conn, &root).len(), 4);
+
+    // The whole copy is one undo step.
+    let _: String = conn
+        .query_row("SELECT treecrdt_undo(?1)", [replica.clone()], |row| {
+            row.get(0)
+        })
+        .unwrap();
+    assert_eq!(
+        visible_children(&conn, &root),
+        vec![folder.clone(), copy, node_bytes(4)]
+    );
+}
+
+#[test]
+fn local_insert_many_pastes_siblings_with_short_keys() {
+    let conn = setup_conn();
+
+    let replica = b"r1".to_vec();
+    let root = node_bytes(0);
+    for n in [1, 2] {
+        let _: String = conn
+            .query_row(
+                "SELECT treecrdt_local_insert(?1, ?2, ?3, 'last', NULL, NULL)",
+                rusqlite::params![replica.clone(), root.clone(), node_bytes(n)],
+                |row| row.get(0),
+            )
+            .unwrap();
+    }
+
+    let lines: Vec<serde_json::Value> = (10..210)
+        .map(|n| serde_json::json!({ "node": node_bytes(n), "payload": [n as u8] }))
+        .collect();
+    let result: String = conn
+        .query_row(
+            "SELECT treecrdt_local_insert_many(?1, ?2, 'after', ?3, ?4)",
+            rusqlite::params![
+                replica.clone(),
+                root.clone(),
+                node_bytes(1),
+                serde_json::Value::Array(lines).to_string()
+            ],
+            |row| row.get(0),
+        )
+        .unwrap();
+    let result: serde_json::Value = serde_json::from_str(&result).unwrap();
+    let ops = result["ops"].as_array().unwrap();
+    assert_eq!(ops.len(), 200);
+    for op in ops {
+        let key: Vec<u8> = serde_json::from_value(op["order_key"].clone()).unwrap();
+        assert!(key.len() <= 4);
+    }
+
+    let mut expected = vec![node_bytes(1)];
+    expected.extend((10..210).map(node_bytes));
+    expected.push(node_bytes(2));
+    assert_eq!(visible_children(&conn, &root), expected);
+    assert_eq!(payload_bytes(&conn, &node_bytes(42)), Some(vec![42]));
+
+    // A bad placement commits nothing.
+    let err = conn.query_row(
+        "SELECT treecrdt_local_insert_many(?1, ?2, 'after', ?3, ?4)",
+        rusqlite::params![
+            replica.clone(),
+            root.clone(),
+            node_bytes(999),
+            serde_json::json!([{ "node": node_bytes(300) }]).to_string()
+        ],
+        |row| row.get::<_, String>(0),
+    );
+    assert!(err.is_err());
+    assert_eq!(visible_children(&conn, &root).len(), 202);
+}
+
+#[test]
+fn order_key_stats_and_rebalance_shorten_hot_parents() {
+    let conn = setup_conn();
+
+    let replica = b"r1".to_vec();
+    let root = node_bytes(0);
+    let insert = |node: u128, placement: &str, after: Option<Vec<u8>>| {
+        let _: String = conn
+            .query_row(
+                "SELECT treecrdt_local_insert(?1, ?2, ?3, ?4, ?5, NULL)",
+                rusqlite::params![
+                    replica.clone(),
+                    root.clone(),
+                    node_bytes(node),
+                    placement,
+                    after
+                ],
+                |row| row.get(0),
+            )
+            .unwrap();
+    };
+    insert(1, "last", None);
+    insert(2, "last", None);
+    for n in 3..100 {
+        insert(n, "after", Some(node_bytes(1)));
+    }
+    let stats = || -> serde_json::Value {
+        let json: String = conn
+            .query_row(
+                "SELECT treecrdt_order_key_stats(?1)",
+                [root.clone()],
+                |row| row.get(0),
+            )
+            .unwrap();
+        serde_json::from_str(&json).unwrap()
+    };
+    let before = stats();
+    assert_eq!(before["children"], 99);
+    assert!(before["maxLen"].as_u64().unwrap() > 8);
+    let order = visible_children(&conn, &root);
+
+    let result: String = conn
+        .query_row(
+            "SELECT treecrdt_local_rebalance(?1, ?2)",
+            rusqlite::params![replica.clone(), root.clone()],
+            |row| row.get(0),
+        )
+        .unwrap();
+    let result: serde_json::Value = serde_json::from_str(&result).unwrap();
+    assert!(!result["ops"].as_array().unwrap().is_empty());
+    let after = stats();
+    assert!(after["maxLen"].as_u64().unwrap() <= 4);
+    assert!(after["avgLen"].as_f64().unwrap() < before["avgLen"].as_f64().unwrap());
+    assert_eq!(visible_children(&conn, &root), order);
+
+    // A parent without children has nothing to re-key.
+    let empty: String = conn
+        .query_row(
+            "SELECT treecrdt_local_rebalance(?1, ?2)",
+            rusqlite::params![replica.clone(), node_bytes(5)],
+            |row| row.get(0),
+        )
+        .unwrap();
+    let empty: serde_json::Value = serde_json::from_str(&empty).unwrap();
+    assert!(empty["ops"].as_array().unwrap().is_empty());
+}
+
+#[test]
+fn local_reorder_moves_only_out_of_place_children() {
+    let conn = setup_conn();
+
+    let replica = b"r1".to_vec();
+    let root = node_bytes(0);
+    for n in 1..=6u128 {
+        let _: String = conn
+            .query_row(
+                "SELECT treecrdt_local_insert(?1, ?2, ?3, 'last', NULL, NULL)",
+                rusqlite::params![replica.clone(), root.clone(), node_bytes(n)],
+                |row| row.get(0),
+            )
+            .unwrap();
+    }
+    let reorder = |order: &[u128]| -> serde_json::Result<serde_json::Value> {
+        let children: Vec<Vec<u8>> = order.iter().map(|n| node_bytes(*n)).collect();
+        let json: String = conn
+            .query_row(
+                "SELECT treecrdt_local_reorder(?1, ?2, ?3)",
+                rusqlite::params![
+                    replica.clone(),
+                    root.clone(),
+                    serde_json::to_string(&children).unwrap()
+                ],
+                |row| row.get(0),
+            )
+            .unwrap();
+        serde_json::from_str(&json)
+    };
+
+    let result = reorder(&[2, 3, 1, 4, 6, 5]).unwrap();
+    assert_eq!(result["ops"].as_array().unwrap().len(), 2);
+    let expected: Vec<Vec<u8>> = [2, 3, 1, 4, 6, 5].iter().map(|n| node_bytes(*n)).collect();
+    assert_eq!(visible_children(&conn, &root), expected);
+
+    let unchanged = reorder(&[2, 3, 1, 4, 6, 5]).unwrap();
+    assert!(unchanged["ops"].as_array().unwrap().is_empty());
+
+    let children: Vec<Vec<u8>> = [2, 3, 1].iter().map(|n| node_bytes(*n)).collect();
+    let err = conn.query_row(
+        "SELECT treecrdt_local_reorder(?1, ?2, ?3)",
+        rusqlite::params![
+            replica.clone(),
+            root.clone(),
+            serde_json::to_string(&children).unwrap()
+        ],
+        |row| row.get::<_, String>(0),
+    );
+    assert!(err.is_err());
+    assert_eq!(visible_children(&conn, &root), expected);
+}
+
+fn payload_fields(conn: &Connection, node: &[u8]) -> Vec<(String, Option<Vec<u8>>)> {
+    let node_arr = <[u8; 16]>::try_from(node).unwrap();
+    let mut stmt = conn
+        .prepare("SELECT field, value FROM tree_payload_fields WHERE node = ?1 ORDER BY field")
+        .unwrap();
+    let rows = stmt
+        .query_map(rusqlite::params![node_arr], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })
+        .unwrap();
+    rows.map(|row| row.unwrap()).collect()
+}
+
+#[test]
+fn payload_fields_merge_per_field_across_local_and_remote_writes() {
+    let conn = setup_conn();
+
+    let replica = b"r1".to_vec();
+    let root = node_bytes(0);
+    let node = node_bytes(1);
+    let _: String = conn
+        .query_row(
+            "SELECT treecrdt_local_insert(?1, ?2, ?3, 'last', NULL, X'00')",
+            rusqlite::params![replica.clone(), root.clone(), node.clone()],
+            |row| row.get(0),
+        )
+        .unwrap();
+    let local: JsonLocalOpResult = serde_json::from_str(
+        &conn
+            .query_row(
+                "SELECT treecrdt_local_payload_field(?1, ?2, 'title', X'6869')",
+                rusqlite::params![replica.clone(), node.clone()],
+                |row| row.get::<_, String>(0),
+            )
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(local.op.kind, "payload_field");
+    assert_eq!(local.op.field.as_deref(), Some("title"));
+    assert!(matches!(
+        local.outcome.changes.as_slice(),
+        [JsonMaterializationChange::PayloadField { field, .. }] if field == "title"
+    ));
+
+    // A concurrent remote write to another field, and a losing one to the same field.
+    let remote = ReplicaId::new(b"r2");
+    let node_id = bytes_to_node_id(&node);
+    append_ops_json(
+        &conn,
+        &json_ops(&[
+            Operation::payload_field(&remote, 1, 2, node_id, "color", Some(b"red".to_vec())),
+            Operation::payload_field(&remote, 2, 1, node_id, "title", Some(b"old".to_vec())),
+        ]),
+    );
+    assert_eq!(
+        payload_fields(&conn, &node),
+        vec![
+            ("color".to_string(), Some(b"red".to_vec())),
+            ("title".to_string(), Some(b"hi".to_vec())),
+        ]
+    );
+    assert_eq!(payload_bytes(&conn, &node), Some(vec![0]));
+
+    let ops: Vec<JsonOp> = serde_json::from_str(
+        &conn
+            .query_row("SELECT treecrdt_ops_since(0)", [], |row| {
+                row.get::<_, String>(0)
+            })
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        ops.iter().filter(|op| op.field.is_some()).count(),
+        3,
+        "field ops round-trip through the op log"
+    );
+
+    // Rebuilding from the op log gives the same fields.
+    conn.execute(
+        "UPDATE tree_meta \
+         SET replay_lamport = 0, replay_replica = X'', replay_counter = 0 \
+         WHERE id = 1",
+        [],
+    )
+    .unwrap();
+    let _: String = conn
+        .query_row("SELECT treecrdt_ensure_materialized()", [], |row| {
+            row.get(0)
+        })
+        .unwrap();
+    assert_eq!(payload_fields(&conn, &node).len(), 2);
+}
+
+fn payload_values(conn: &Connection, node: &[u8]) -> Vec<Option<Vec<u8>>> {
+    let node_arr = <[u8; 16]>::try_from(node).unwrap();
+    let mut stmt = conn
+        .prepare(
+            "SELECT payload FROM tree_payload_values WHERE node = ?1 \
+             ORDER BY lamport, replica, counter",
+        )
+        .unwrap();
+    let rows = stmt.query_map(rusqlite::params![node_arr], |row| row.get(0)).unwrap();
+    rows.map(|row| row.unwrap()).collect()
+}
+
+#[test]
+fn multi_value_payload_mode_keeps_concurrent_writes_until_resolved() {
+    let conn = setup_conn();
+    let _: i64 = conn
+        .query_row(
+            "SELECT treecrdt_set_payload_mode('multi_value')",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+
+    let replica = b"r1".to_vec();
+    let root = node_bytes(0);
+    let node = node_bytes(1);
+    let local_payload = |payload: &[u8]| -> JsonLocalOpResult {
+        serde_json::from_str(
+            &conn
+                .query_row(
+                    "SELECT treecrdt_local_payload(?1, ?2, ?3)",
+                    rusqlite::params![replica.clone(), node.clone(), payload.to_vec()],
+                    |row| row.get::<_, String>(0),
+                )
+                .unwrap(),
+        )
+        .unwrap()
+    };
+    let _: String = conn
+        .query_row(
+            "SELECT treecrdt_local_insert(?1, ?2, ?3, 'last', NULL, X'00')",
+            rusqlite::params![replica.clone(), root.clone(), node.clone()],
+            |row| row.get(0),
+        )
+        .unwrap();
+    let ours = local_payload(b"ours");
+    assert!(
+        ours.op.known_state.is_some(),
+        "multi-value writes carry what they saw"
+    );
+
+    // A remote write that only saw the insert is concurrent with ours.
+    let mut seen_insert = VersionVector::new();
+    seen_insert.observe(&ReplicaId::new(replica.clone()), 1);
+    let mut theirs = Operation::set_payload(
+        &ReplicaId::new(b"r2"),
+        1,
+        2,
+        bytes_to_node_id(&node),
+        b"theirs".to_vec(),
+    );
+    theirs.meta.known_state = Some(seen_insert);
+    append_ops_json(&conn, &json_ops(&[theirs]));
+    assert_eq!(
+        payload_values(&conn, &node),
+        vec![Some(b"ours".to_vec()), Some(b"theirs".to_vec())]
+    );
+    assert_eq!(payload_bytes(&conn, &node), Some(b"theirs".to_vec()));
+
+    local_payload(b"merged");
+    assert_eq!(payload_values(&conn, &node), vec![Some(b"merged".to_vec())]);
+
+    let _: i64 = conn
+        .query_row(
+            "SELECT treecrdt_set_payload_mode('last_writer_wins')",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert!(payload_values(&conn, &node).is_empty());
+    assert_eq!(payload_bytes(&conn, &node), Some(b"merged".to_vec()));
+}
+
+#[test]
+fn orphan_policy_decides_where_concurrent_children_of_deleted_nodes_go() {
+    let conn = setup_conn();
+    let set_policy = |policy: &str| {
+        let _: i64 = conn
+            .query_row(
+                "SELECT treecrdt_set_orphan_policy(?1)",
+                rusqlite::params![policy],
+                |row| row.get(0),
+            )
+            .unwrap();
+    };
+    set_policy("lost_and_found");
+
+    let replica = b"r1".to_vec();
+    let root = node_bytes(0);
+    let lost_and_found = node_bytes_from_id(NodeId::LOST_AND_FOUND);
+    let (folder, old, file) = (node_bytes(1), node_bytes(2), node_bytes(3));
+    for (parent, node) in [(&root, &folder), (&folder, &old)] {
+        let _: String = conn
+            .query_row(
+                "SELECT treecrdt_local_insert(?1, ?2, ?3, 'last', NULL, NULL)",
+                rusqlite::params![replica.clone(), parent.clone(), node.clone()],
+                |row| row.get(0),
+            )
+            .unwrap();
+    }
+    let _: String = conn
+        .query_row(
+            "SELECT treecrdt_local_delete(?1, ?2)",
+            rusqlite::params![replica.clone(), folder.clone()],
+            |row| row.get(0),
+        )
+        .unwrap();
+
+    // Inserted concurrently with the delete, and ordered before it.
+    let insert = Operation::insert(
+        &ReplicaId::new(b"r2"),
+        1,
+        2,
+        bytes_to_node_id(&folder),
+        bytes_to_node_id(&file),
+        vec![0x80],
+    );
+    append_ops_json(&conn, &json_ops(&[insert]));
+    assert!(visible_children(&conn, &root).is_empty());
+    assert_eq!(visible_children(&conn, &lost_and_found), vec![file.clone()]);
+
+    // Switching policies rematerializes the document.
+    set_policy("defensive");
+    assert_eq!(visible_children(&conn, &root), vec![folder.clone()]);
+    assert_eq!(visible_children(&conn, &folder), vec![file, old]);
+    assert!(visible_children(&conn, &lost_and_found).is_empty());
+
+    let err = conn
+        .query_row("SELECT treecrdt_set_orphan_policy('hide')", [], |row| {
+            row.get::<_, i64>(0)
+        })
+        .unwrap_err();
+    assert!(err.to_string().contains("policy must be"));
+}
+
+#[test]
+fn local_restore_brings_back_deleted_nodes_on_every_replica() {
+    let writer = setup_conn();
+    let replica = b"r1".to_vec();
+    let root = node_bytes(0);
+    let (folder, file) = (node_bytes(1), node_bytes(2));
+    let local = |sql: &str, params: &[&dyn rusqlite::ToSql]| -> String {
+        writer.query_row(sql, params, |row| row.get(0)).unwrap()
+    };
+    local(
+        "SELECT treecrdt_local_insert(?1, ?2, ?3, 'last', NULL, NULL)",
+        rusqlite::params![replica, root, folder],
+    );
+    local(
+        "SELECT treecrdt_local_insert(?1, ?2, ?3, 'last', NULL, X'6869')",
+        rusqlite::params![replica, folder, file],
+    );
+    local(
+        "SELECT treecrdt_local_delete(?1, ?2)",
+        rusqlite::params![replica, folder],
+    );
+    assert!(visible_children(&writer, &root).is_empty());
+
+    // Restoring the file brings back the deleted folder it sits in.
+    let result: JsonLocalOpResult = serde_json::from_str(&local(
+        "SELECT treecrdt_local_restore(?1, ?2)",
+        rusqlite::params![replica, file],
+    ))
+    .unwrap();
+    assert_eq!(result.op.kind, "restore");
+    assert_eq!(
+        json_outcome_to_core(result.outcome).changes,
+        vec![MaterializationChange::Restore {
+            node: bytes_to_node_id(&folder),
+            parent_after: Some(NodeId::ROOT),
+            payload: None,
+            source: Some(MaterializationSource {
+                operation: MaterializationSourceOperation {
+                    id: OperationId {
+                        replica: ReplicaId::new(replica.clone()),
+                        counter: 4,
+                    },
+                    lamport: 4,
+                },
+            }),
+        }]
+    );
+    assert_eq!(visible_children(&writer, &root), vec![folder.clone()]);
+    assert_eq!(visible_children(&writer, &folder), vec![file.clone()]);
+    let err = writer.query_row(
+        "SELECT treecrdt_local_restore(?1, ?2)",
+        rusqlite::params![replica, folder],
+        |row| row.get::<_, String>(0),
+    );
+    assert!(err.is_err());
+
+    // A reader receiving the restore ahead of the ops it undoes ends up in the same place.
+    let json: String =
+        writer.query_row("SELECT treecrdt_ops_since(0)", [], |row| row.get(0)).unwrap();
+    let mut ops: Vec<JsonOp> = serde_json::from_str(&json).unwrap();
+    ops.reverse();
+    let reader = setup_conn();
+    for op in &ops {
+        append_ops_json(&reader, std::slice::from_ref(op));
+    }
+    assert_eq!(visible_children(&reader, &root), vec![folder.clone()]);
+    assert_eq!(visible_children(&reader, &folder), vec![file]);
+}
+
+#[test]
+fn local_tombstone_is_not_revived_by_concurrent_inserts() {
+    let (a, b) = (setup_conn(), setup_conn());
+    let (ra, rb) = (b"r1".to_vec(), b"r2".to_vec());
+    let root = node_bytes(0);
+    let (folder, file) = (node_bytes(1), node_bytes(2));
+    let ops_from = |conn: &Connection, replica: &[u8]| -> Vec<JsonOp> {
+        let json: String =
+            conn.query_row("SELECT treecrdt_ops_since(0)", [], |row| row.get(0)).unwrap();
+        let ops: Vec<JsonOp> = serde_json::from_str(&json).unwrap();
+        ops.into_iter().filter(|op| op.replica == replica).collect()
+    };
+    a.query_row(
+        "SELECT treecrdt_local_insert(?1, ?2, ?3, 'last', NULL, NULL)",
+        rusqlite::params![ra, root, folder],
+        |row| row.get::<_, String>(0),
+    )
+    .unwrap();
+    append_ops_json(&b, &ops_from(&a, &ra));
+
+    // `b` adds a file the tombstone never sees; a plain delete would come back for it.
+    b.query_row(
+        "SELECT treecrdt_local_insert(?1, ?2, ?3, 'last', NULL, NULL)",
+        rusqlite::params![rb, folder, file],
+        |row| row.get::<_, String>(0),
+    )
+    .unwrap();
+    let result: JsonLocalOpResult = serde_json::from_str(
+        &a.query_row(
+            "SELECT treecrdt_local_tombstone(?1, ?2)",
+            rusqlite::params![ra, folder],
+            |row| row.get::<_, String>(0),
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(result.op.kind, "tombstone");
+
+    append_ops_json(&a, &ops_from(&b, &rb));
+    append_ops_json(&b, &ops_from(&a, &ra)[1..]);
+    for conn in [&a, &b] {
+        assert!(visible_children(conn, &root).is_empty());
+        let hard_deleted: i64 = conn
+            .query_row(
+                "SELECT hard_deleted FROM tree_nodes WHERE node = ?1",
+                rusqlite::params![folder],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(hard_deleted, 1);
+    }
+    let err = a.query_row(
+        "SELECT treecrdt_local_restore(?1, ?2)",
+        rusqlite::params![ra, file],
+        |row| row.get::<_, String>(0),
+    );
+    assert!(err.is_err());
+}
+
+#[test]
+fn trash_list_pages_through_deleted_subtrees_newest_first() {
+    let conn = setup_conn();
+    let replica = b"r1".to_vec();
+    let root = node_bytes(0);
+    let (folder, file, note) = (node_bytes(1), node_bytes(2), node_bytes(3));
+    let local = |sql: &str, params: &[&dyn rusqlite::ToSql]| -> String {
+        conn.query_row(sql, params, |row| row.get(0)).unwrap()
+    };
+    local(
+        "SELECT treecrdt_local_insert(?1, ?2, ?3, 'last', NULL, NULL)",
+        rusqlite::params![replica, root, folder],
+    );
+    local(
+        "SELECT treecrdt_local_insert(?1, ?2, ?3, 'last', NULL, X'6869')",
+        rusqlite::params![replica, folder, file],
+    );
+    local(
+        "SELECT treecrdt_local_insert(?1, ?2, ?3, 'last', NULL, X'6e6f7465')",
+        rusqlite::params![replica, root, note],
+    );
+    for node in [&file, &note, &folder] {
+        local(
+            "SELECT treecrdt_local_delete(?1, ?2)",
+            rusqlite::params![replica, node],
+        );
+    }
+
+    let page = |cursor: Option<String>, limit: i64| -> Vec<serde_json::Value> {
+        let json: String = conn
+            .query_row(
+                "SELECT treecrdt_trash_list(?1, ?2)",
+                rusqlite::params![cursor, limit],
+                |row| row.get(0),
+            )
+            .unwrap();
+        serde_json::from_str(&json).unwrap()
+    };
+    let hex = |bytes: &[u8]| format!("{:032x}", bytes_to_node_id(bytes).0);
+
+    // The file went with its folder, so only the folder and the note are listed.
+    let first = page(None, 1);
+    assert_eq!(first.len(), 1);
+    assert_eq!(first[0]["node"], hex(&folder));
+    assert_eq!(first[0]["parent"], hex(&root));
+    assert_eq!(first[0]["source"]["operation"]["id"]["counter"], 6);
+    assert_eq!(first[0]["hardDeleted"], false);
+    let rest = page(Some(first[0]["cursor"].to_string()), 10);
+    assert_eq!(rest.len(), 1);
+    assert_eq!(rest[0]["node"], hex(&note));
+    assert_eq!(
+        rest[0]["payloadPreview"],
+        serde_json::json!(b"note".to_vec())
+    );
+    assert!(page(Some(rest[0]["cursor"].to_string()), 10).is_empty());
+
+    // Restoring the folder leaves the file, deleted on its own, in the trash.
+    local(
+        "SELECT treecrdt_local_restore(?1, ?2)",
+        rusqlite::params![replica, folder],
+    );
+    let nodes: Vec<_> = page(None, 10).iter().map(|entry| entry["node"].clone()).collect();
+    assert_eq!(nodes, vec![hex(&note), hex(&file)]);
+}
+
+#[test]
+fn navigation_queries_walk_the_visible_tree() {
+    let conn = setup_conn();
+    let replica = b"r1".to_vec();
+    let root = node_bytes(0);
+    let [a, b, c, d, e] = [1, 2, 3, 4, 5].map(node_bytes);
+    for (parent, node) in [(&root, &a), (&a, &b), (&b, &c), (&a, &d), (&root, &e)] {
+        let _: String = conn
+            .query_row(
+                "SELECT treecrdt_local_insert(?1, ?2, ?3, 'last', NULL, NULL)",
+                rusqlite::params![replica, parent, node],
+                |row| row.get(0),
+            )
+            .unwrap();
+    }
+    let hex = |bytes: &[u8]| format!("{:032x}", bytes_to_node_id(bytes).0);
+    let ancestors = |node: &[u8]| -> Vec<String> {
+        let json: String = conn
+            .query_row("SELECT treecrdt_ancestors(?1)", [node], |row| row.get(0))
+            .unwrap();
+        serde_json::from_str(&json).unwrap()
+    };
+    let int = |sql: &str, node: &[u8]| -> Option<i64> {
+        conn.query_row(sql, [node], |row| row.get(0)).unwrap()
+    };
+    let is_descendant = |node: &[u8], ancestor: &[u8]| -> bool {
+        conn.query_row(
+            "SELECT treecrdt_is_descendant(?1, ?2)",
+            [node, ancestor],
+            |row| row.get(0),
+        )
+        .unwrap()
+    };
+    let descendants = |root: &[u8], max_depth: Option<i64>| -> Vec<(String, i64)> {
+        let json: String = conn
+            .query_row(
+                "SELECT treecrdt_descendants(?1, ?2)",
+                rusqlite::params![root, max_depth],
+                |row| row.get(0),
+            )
+            .unwrap();
+        let rows: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+        rows.iter()
+            .map(|row| {
+                (
+                    row["node"].as_str().unwrap().to_string(),
+                    row["depth"].as_i64().unwrap(),
+                )
+            })
+            .collect()
+    };
+
+    assert_eq!(ancestors(&c), vec![hex(&b), hex(&a), hex(&root)]);
+    assert!(ancestors(&root).is_empty());
+    assert_eq!(int("SELECT treecrdt_depth(?1)", &c), Some(3));
+    assert_eq!(int("SELECT treecrdt_depth(?1)", &root), Some(0));
+    assert!(is_descendant(&c, &a));
+    assert!(!is_descendant(&a, &c));
+    assert!(!is_descendant(&a, &a));
+    assert_eq!(
+        descendants(&root, None),
+        vec![
+            (hex(&a), 1),
+            (hex(&b), 2),
+            (hex(&c), 3),
+            (hex(&d), 2),
+            (hex(&e), 1)
+        ]
+    );
+    assert_eq!(
+        descendants(&root, Some(1)),
+        vec![(hex(&a), 1), (hex(&e), 1)]
+    );
+    assert_eq!(int("SELECT treecrdt_index_in_parent(?1)", &d), Some(1));
+    assert_eq!(int("SELECT treecrdt_index_in_parent(?1)", &e), Some(1));
+    assert_eq!(int("SELECT treecrdt_index_in_parent(?1)", &root), None);
+
+    let _: String = conn
+        .query_row(
+            "SELECT treecrdt_local_delete(?1, ?2)",
+            rusqlite::params![replica, b],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert!(ancestors(&c).is_empty());
+    assert_eq!(int("SELECT treecrdt_depth(?1)", &c), None);
+    assert!(!is_descendant(&c, &a));
+    assert!(descendants(&b, None).is_empty());
+    assert_eq!(
+        descendants(&root, None),
+        vec![(hex(&a), 1), (hex(&d), 2), (hex(&e), 1)]
+    );
+    assert_eq!(int("SELECT treecrdt_index_in_parent(?1)", &d), Some(0));
+}
@@ -558,22 +558,20 @@ async function treecrdtTreePayload(
 }
 
 /**
- * Fetch the maximum lamport seen in the op log.
+ * Fetch the maximum lamport seen in the op log, including purged ops.
  */
 async function treecrdtHeadLamport(runner: SqliteRunner): Promise<number> {
-  return sqliteGetNumber(runner, 'SELECT COALESCE(MAX(lamport), 0) FROM ops');
+  return sqliteGetNumber(runner, 'SELECT treecrdt_head_lamport()');
 }
 
 /**
- * Fetch the maximum counter observed for a replica id.
+ * Fetch the maximum counter observed for a replica id, including purged ops.
  */
 async function treecrdtReplicaMaxCounter(
   runner: SqliteRunner,
   replica: Uint8Array,
 ): Promise<number> {
-  return sqliteGetNumber(runner, 'SELECT COALESCE(MAX(counter), 0) FROM ops WHERE replica = ?1', [
-    replica,
-  ]);
+  return sqliteGetNumber(runner, 'SELECT treecrdt_replica_max_counter(?1)', [replica]);
 }
 
 export type TreecrdtSqlitePlacement =
@@ -4,9 +4,13 @@
 
 use serde::{Deserialize, Serialize};
 use serde_wasm_bindgen::to_value;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
 use treecrdt_core::{
-    Lamport, LamportClock, MaterializationOutcome, MemoryStorage, NodeId, Operation, OperationKind,
-    ReplicaId, TreeCrdt,
+    Lamport, LamportClock, LocalEdit, LocalPlacement, MaterializationOutcome, MemoryStorage,
+    NodeId, Operation, OperationId, OperationKind, PreparedLocalOp, ReplicaId, StagedLocalOp,
+    Storage, TreeCrdt, UndoManager,
 };
 use wasm_bindgen::prelude::*;
 
@@ -23,6 +27,8 @@ struct JsOp {
     #[serde(default)]
     known_state: Option<Vec<u8>>,
     payload: Option<String>, // hex
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    field: Option<String>,
 }
 
 fn hex_to_bytes(hex: &str) -> Result<Vec<u8>, String> {
@@ -56,6 +62,7 @@ fn node_to_hex(id: NodeId) -> String {
 }
 
 fn op_to_js(op: &Operation) -> JsOp {
+    let mut field = None;
     let (kind, parent, node, new_parent, order_key, payload) = match &op.kind {
         OperationKind::Insert {
             parent,
@@ -84,6 +91,7 @@ fn op_to_js(op: &Operation) -> JsOp {
         ),
         OperationKind::Delete { node } => ("delete", None, *node, None, None, None),
         OperationKind::Tombstone { node } => ("tombstone", None, *node, None, None, None),
+        OperationKind::Restore { node } => ("restore", None, *node, None, None, None),
         OperationKind::Payload { node, payload } => (
             "payload",
             None,
@@ -92,6 +100,21 @@ fn op_to_js(op: &Operation) -> JsOp {
             None,
             payload.as_deref().map(bytes_to_hex),
         ),
+        OperationKind::PayloadField {
+            node,
+            field: name,
+            value,
+        } => {
+            field = Some(name.clone());
+            (
+                "payload_field",
+                None,
+                *node,
+                None,
+                None,
+                value.as_deref().map(bytes_to_hex),
+            )
+        }
     };
     let known_state = op.meta.known_state.as_ref().and_then(|vv| serde_json::to_vec(vv).ok());
     JsOp {
@@ -105,6 +128,7 @@ fn op_to_js(op: &Operation) -> JsOp {
         order_key,
         known_state,
         payload,
+        field,
     }
 }
 
@@ -152,6 +176,7 @@ fn js_to_op(js: JsOp) -> Result<Operation, String> {
             Operation::delete(&replica, counter, lamport, hex_to_node(&js.node)?, Some(vv))
         }
         "tombstone" => Operation::tombstone(&replica, counter, lamport, hex_to_node(&js.node)?),
+        "restore" => Operation::restore(&replica, counter, lamport, hex_to_node(&js.node)?),
         "payload" => Operation::payload(
             &replica,
             counter,
@@ -159,14 +184,125 @@ fn js_to_op(js: JsOp) -> Result<Operation, String> {
             hex_to_node(&js.node)?,
             js.payload.as_deref().map(hex_to_bytes).transpose()?,
         ),
+        "payload_field" => Operation::payload_field(
+            &replica,
+            counter,
+            lamport,
+            hex_to_node(&js.node)?,
+            js.field.ok_or("payload_field op missing field")?,
+            js.payload.as_deref().map(hex_to_bytes).transpose()?,
+        ),
         _ => return Err("unknown kind".into()),
     };
     Ok(op)
 }
 
+/// One edit of a `localBatch` call; `kind` is insert/move/delete/restore/payload/payload_field.
+#[derive(Deserialize)]
+struct JsLocalEdit {
+    kind: String,
+    node: String,
+    #[serde(default)]
+    parent: Option<String>,
+    #[serde(default)]
+    new_parent: Option<String>,
+    #[serde(default)]
+    placement: Option<String>,
+    #[serde(default)]
+    after: Option<String>,
+    #[serde(default)]
+    payload: Option<String>, // hex
+    #[serde(default)]
+    field: Option<String>,
+}
+
+fn js_to_local_edit(js: JsLocalEdit) -> Result<LocalEdit, JsValue> {
+    let to_js = |e: String| JsValue::from_str(&e);
+    let node = hex_to_node(&js.node).map_err(to_js)?;
+    let required = |hex: Option<&str>, field: &str| {
+        let hex =
+            hex.ok_or_else(|| JsValue::from_str(&format!("{} edit missing {field}", js.kind)))?;
+        hex_to_node(hex).map_err(to_js)
+    };
+    let payload = || js.payload.as_deref().map(hex_to_bytes).transpose().map_err(to_js);
+    let placement = || placement_from_js(js.placement.as_deref().unwrap_or(""), js.after.clone());
+    match js.kind.as_str() {
+        "insert" => Ok(LocalEdit::Insert {
+            parent: required(js.parent.as_deref(), "parent")?,
+            node,
+            placement: placement()?,
+            payload: payload()?,
+        }),
+        "move" => Ok(LocalEdit::Move {
+            node,
+            new_parent: required(js.new_parent.as_deref(), "new_parent")?,
+            placement: placement()?,
+        }),
+        "delete" => Ok(LocalEdit::Delete { node }),
+        "tombstone" => Ok(LocalEdit::Tombstone { node }),
+        "restore" => Ok(LocalEdit::Restore { node }),
+        "payload" => Ok(LocalEdit::Payload {
+            node,
+            payload: payload()?,
+        }),
+        "payload_field" => Ok(LocalEdit::PayloadField {
+            node,
+            field: js
+                .field
+                .clone()
+                .ok_or_else(|| JsValue::from_str("payload_field edit missing field"))?,
+            value: payload()?,
+        }),
+        _ => Err(JsValue::from_str("unknown kind")),
+    }
+}
+
+#[derive(Serialize)]
+struct JsDuplicateResult {
+    ops: Vec<JsOp>,
+    /// `[original, copy]` pairs, parents before children.
+    mapping: Vec<[String; 2]>,
+}
+
+/// Node id for the copy of `source` made by the duplicate that started at `lamport`. Only needs
+/// to be unique, so a 128-bit std hash of the replica, clock and source is enough here.
+fn duplicate_node_id(replica: &ReplicaId, lamport: Lamport, source: NodeId) -> NodeId {
+    let half = |salt: u8| {
+        let mut hasher = DefaultHasher::new();
+        (b"treecrdt/duplicate/v0", salt).hash(&mut hasher);
+        replica.as_bytes().hash(&mut hasher);
+        lamport.hash(&mut hasher);
+        source.0.hash(&mut hasher);
+        hasher.finish() as u128
+    };
+    NodeId(half(0) << 64 | half(1))
+}
+
 #[wasm_bindgen]
 pub struct WasmTree {
     inner: TreeCrdt<MemoryStorage, LamportClock>,
+    undo: UndoManager,
+}
+
+fn core_err(e: treecrdt_core::Error) -> JsValue {
+    JsValue::from_str(&format!("{:?}", e))
+}
+
+fn ops_to_value(ops: &[Operation]) -> Result<JsValue, JsValue> {
+    let mapped: Vec<JsOp> = ops.iter().map(op_to_js).collect();
+    to_value(&mapped).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+fn placement_from_js(
+    placement: &str,
+    after_hex: Option<String>,
+) -> Result<LocalPlacement, JsValue> {
+    let after = after_hex
+        .as_deref()
+        .map(hex_to_node)
+        .transpose()
+        .map_err(|e| JsValue::from_str(&e))?;
+    LocalPlacement::from_parts(placement, after).map_err(core_err)
 }
 
 #[wasm_bindgen]
@@ -178,7 +314,248 @@ impl WasmTree {
         WasmTree {
             inner: TreeCrdt::new(replica, MemoryStorage::default(), LamportClock::default())
                 .unwrap(),
+            undo: UndoManager::new(),
+        }
+    }
+
+    /// Commit a prepared local op and record it for undo. Returns the op.
+    fn commit_local(
+        &mut self,
+        prepared: treecrdt_core::Result<PreparedLocalOp>,
+    ) -> Result<JsValue, JsValue> {
+        let prepared = prepared.map_err(core_err)?;
+        let record = self.inner.undo_record(&prepared.op).map_err(core_err)?;
+        let (op, _) = self.inner.commit_prepared_local(prepared).map_err(core_err)?;
+        self.undo.record(record);
+        to_value(&op_to_js(&op)).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    #[wasm_bindgen(js_name = localInsert)]
+    pub fn local_insert(
+        &mut self,
+        parent_hex: String,
+        node_hex: String,
+        placement: String,
+        after_hex: Option<String>,
+        payload: Option<Vec<u8>>,
+    ) -> Result<JsValue, JsValue> {
+        let parent = hex_to_node(&parent_hex).map_err(|e| JsValue::from_str(&e))?;
+        let node = hex_to_node(&node_hex).map_err(|e| JsValue::from_str(&e))?;
+        let placement = placement_from_js(&placement, after_hex)?;
+        let prepared = self.inner.prepare_local_insert(parent, node, placement, payload);
+        self.commit_local(prepared)
+    }
+
+    #[wasm_bindgen(js_name = localMove)]
+    pub fn local_move(
+        &mut self,
+        node_hex: String,
+        new_parent_hex: String,
+        placement: String,
+        after_hex: Option<String>,
+    ) -> Result<JsValue, JsValue> {
+        let node = hex_to_node(&node_hex).map_err(|e| JsValue::from_str(&e))?;
+        let new_parent = hex_to_node(&new_parent_hex).map_err(|e| JsValue::from_str(&e))?;
+        let placement = placement_from_js(&placement, after_hex)?;
+        let prepared = self.inner.prepare_local_move(node, new_parent, placement);
+        self.commit_local(prepared)
+    }
+
+    #[wasm_bindgen(js_name = localDelete)]
+    pub fn local_delete(&mut self, node_hex: String) -> Result<JsValue, JsValue> {
+        let node = hex_to_node(&node_hex).map_err(|e| JsValue::from_str(&e))?;
+        let prepared = self.inner.prepare_local_delete(node);
+        self.commit_local(prepared)
+    }
+
+    #[wasm_bindgen(js_name = localTombstone)]
+    pub fn local_tombstone(&mut self, node_hex: String) -> Result<JsValue, JsValue> {
+        let node = hex_to_node(&node_hex).map_err(|e| JsValue::from_str(&e))?;
+        let prepared = self.inner.prepare_local_tombstone(node);
+        self.commit_local(prepared)
+    }
+
+    #[wasm_bindgen(js_name = localRestore)]
+    pub fn local_restore(&mut self, node_hex: String) -> Result<JsValue, JsValue> {
+        let node = hex_to_node(&node_hex).map_err(|e| JsValue::from_str(&e))?;
+        let prepared = self.inner.prepare_local_restore(node);
+        self.commit_local(prepared)
+    }
+
+    #[wasm_bindgen(js_name = localPayload)]
+    pub fn local_payload(
+        &mut self,
+        node_hex: String,
+        payload: Option<Vec<u8>>,
+    ) -> Result<JsValue, JsValue> {
+        let node = hex_to_node(&node_hex).map_err(|e| JsValue::from_str(&e))?;
+        let prepared = self.inner.prepare_local_payload(node, payload);
+        self.commit_local(prepared)
+    }
+
+    /// Set one field of a node's payload map; `None` clears it.
+    #[wasm_bindgen(js_name = localPayloadField)]
+    pub fn local_payload_field(
+        &mut self,
+        node_hex: String,
+        field: String,
+        value: Option<Vec<u8>>,
+    ) -> Result<JsValue, JsValue> {
+        let node = hex_to_node(&node_hex).map_err(|e| JsValue::from_str(&e))?;
+        let prepared = self.inner.prepare_local_payload_field(node, &field, value);
+        self.commit_local(prepared)
+    }
+
+    /// Commit several local edits as one undo transaction. Later edits see the earlier ones, so
+    /// an edit may place a node after one inserted earlier in the batch. If any edit fails,
+    /// none of them is kept. The ops form one op group, so peers that buffer groups show them
+    /// all at once. Returns the new ops to send to peers.
+    #[wasm_bindgen(js_name = localBatch)]
+    pub fn local_batch(&mut self, edits_json: String) -> Result<JsValue, JsValue> {
+        let edits: Vec<JsLocalEdit> =
+            serde_json::from_str(&edits_json).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let edits = edits.into_iter().map(js_to_local_edit).collect::<Result<Vec<_>, _>>()?;
+
+        let mut tx = self.inner.local_transaction();
+        if edits.len() > 1 {
+            tx = tx.grouped(edits.len() as u32);
+        }
+        let mut failed = None;
+        for edit in edits {
+            if let Err(err) = tx.stage(edit) {
+                failed = Some(err);
+                break;
+            }
+        }
+        if let Some(err) = failed {
+            let staged = tx.staged().to_vec();
+            self.discard_staged(&staged).map_err(core_err)?;
+            return Err(core_err(err));
+        }
+        let staged = tx.finish().map_err(core_err)?;
+
+        self.undo.begin();
+        for entry in &staged {
+            self.undo.record(entry.undo.clone());
+        }
+        self.undo.commit();
+        let ops: Vec<Operation> = staged.into_iter().map(|entry| entry.op).collect();
+        ops_to_value(&ops)
+    }
+
+    /// Copy the visible subtree of `source` under `new_parent` with fresh node ids, as one undo
+    /// transaction and one op group. If any insert fails, none of them is kept. Returns the new
+    /// ops and the `[original, copy]` id pairs.
+    #[wasm_bindgen(js_name = localDuplicate)]
+    pub fn local_duplicate(
+        &mut self,
+        source_hex: String,
+        new_parent_hex: String,
+        placement: String,
+        after_hex: Option<String>,
+    ) -> Result<JsValue, JsValue> {
+        let source = hex_to_node(&source_hex).map_err(|e| JsValue::from_str(&e))?;
+        let new_parent = hex_to_node(&new_parent_hex).map_err(|e| JsValue::from_str(&e))?;
+        let placement = placement_from_js(&placement, after_hex)?;
+
+        let replica = self.inner.replica_id().clone();
+        let lamport = self.inner.lamport();
+        let copy = match self.inner.local_duplicate(source, new_parent, placement, |node| {
+            duplicate_node_id(&replica, lamport, node)
+        }) {
+            Ok(copy) => copy,
+            Err(err) => {
+                // The inserts staged before the failure are the local ops newer than `lamport`.
+                let staged: HashSet<OperationId> = self
+                    .inner
+                    .operations_since(lamport)
+                    .map_err(core_err)?
+                    .into_iter()
+                    .filter(|op| op.meta.id.replica == replica)
+                    .map(|op| op.meta.id)
+                    .collect();
+                self.discard_ops(&staged).map_err(core_err)?;
+                return Err(core_err(err));
+            }
+        };
+
+        self.undo.begin();
+        for entry in &copy.staged {
+            self.undo.record(entry.undo.clone());
+        }
+        self.undo.commit();
+        let result = JsDuplicateResult {
+            ops: copy.staged.iter().map(|entry| op_to_js(&entry.op)).collect(),
+            mapping: copy
+                .mapping
+                .iter()
+                .map(|(original, copy)| [node_to_hex(*original), node_to_hex(*copy)])
+                .collect(),
+        };
+        to_value(&result).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Rebuild the tree without the ops of a failed batch; the in-memory op log cannot drop
+    /// ops in place.
+    fn discard_staged(&mut self, staged: &[StagedLocalOp]) -> treecrdt_core::Result<()> {
+        let dropped: HashSet<OperationId> =
+            staged.iter().map(|entry| entry.op.meta.id.clone()).collect();
+        self.discard_ops(&dropped)
+    }
+
+    fn discard_ops(&mut self, dropped: &HashSet<OperationId>) -> treecrdt_core::Result<()> {
+        if dropped.is_empty() {
+            return Ok(());
+        }
+        let mut storage = MemoryStorage::default();
+        for op in self.inner.operations_since(0)? {
+            if !dropped.contains(&op.meta.id) {
+                storage.apply(op)?;
+            }
         }
+        let mut rebuilt = TreeCrdt::new(
+            self.inner.replica_id().clone(),
+            storage,
+            LamportClock::default(),
+        )?;
+        rebuilt.replay_from_storage()?;
+        self.inner = rebuilt;
+        Ok(())
+    }
+
+    /// Revert the most recent local transaction. Returns the new ops to send to peers.
+    #[wasm_bindgen]
+    pub fn undo(&mut self) -> Result<JsValue, JsValue> {
+        let ops = self.undo.undo(&mut self.inner).map_err(core_err)?;
+        ops_to_value(&ops)
+    }
+
+    /// Re-apply the most recently undone transaction. Returns the new ops to send to peers.
+    #[wasm_bindgen]
+    pub fn redo(&mut self) -> Result<JsValue, JsValue> {
+        let ops = self.undo.redo(&mut self.inner).map_err(core_err)?;
+        ops_to_value(&ops)
+    }
+
+    /// Group the following local ops into one undo transaction until `undoCommit`.
+    #[wasm_bindgen(js_name = undoBegin)]
+    pub fn undo_begin(&mut self) {
+        self.undo.begin();
+    }
+
+    #[wasm_bindgen(js_name = undoCommit)]
+    pub fn undo_commit(&mut self) {
+        self.undo.commit();
+    }
+
+    #[wasm_bindgen(js_name = canUndo)]
+    pub fn can_undo(&self) -> bool {
+        self.undo.can_undo()
+    }
+
+    #[wasm_bindgen(js_name = canRedo)]
+    pub fn can_redo(&self) -> bool {
+        self.undo.can_redo()
     }
 
     #[wasm_bindgen(js_name = appendOp)]
@@ -223,6 +600,21 @@ impl WasmTree {
         to_value(&mapped).map_err(|e| JsValue::from_str(&e.to_string()))
     }
 
+    /// Apply a batch of remote ops in the binary encoding produced by `opsSinceBlob`.
+    #[wasm_bindgen(js_name = appendOpsBlob)]
+    pub fn append_ops_blob(&mut self, ops: Vec<u8>) -> Result<(), JsValue> {
+        for op in treecrdt_core::decode_ops(&ops).map_err(core_err)? {
+            self.inner.apply_remote(op).map_err(core_err)?;
+        }
+        Ok(())
+    }
+
+    #[wasm_bindgen(js_name = opsSinceBlob)]
+    pub fn ops_since_blob(&self, lamport: u64) -> Result<Vec<u8>, JsValue> {
+        let ops = self.inner.operations_since(lamport).map_err(core_err)?;
+        Ok(treecrdt_core::encode_ops(&ops))
+    }
+
     #[wasm_bindgen(js_name = subtreeKnownState)]
     pub fn subtree_known_state(&self, node_hex: String) -> Result<Vec<u8>, JsValue> {
         let node = hex_to_node(&node_hex).map_err(|e| JsValue::from_str(&e))?;
@@ -233,6 +625,13 @@ impl WasmTree {
         serde_json::to_vec(&vv).map_err(|e| JsValue::from_str(&e.to_string()))
     }
 
+    #[wasm_bindgen(js_name = subtreeKnownStateBlob)]
+    pub fn subtree_known_state_blob(&self, node_hex: String) -> Result<Vec<u8>, JsValue> {
+        let node = hex_to_node(&node_hex).map_err(|e| JsValue::from_str(&e))?;
+        let vv = self.inner.subtree_version_vector(node).map_err(core_err)?;
+        Ok(treecrdt_core::encode_version_vector(&vv))
+    }
+
     #[wasm_bindgen(js_name = treeChildren)]
     pub fn tree_children(&self, parent_hex: String) -> Result<JsValue, JsValue> {
         let parent = hex_to_node(&parent_hex).map_err(|e| JsValue::from_str(&e))?;
@@ -286,6 +685,17 @@ impl WasmTree {
         self.inner.payload(node).map_err(|e| JsValue::from_str(&format!("{:?}", e)))
     }
 
+    /// The set fields of a node's payload map as `[field, bytes]` pairs in field name order.
+    #[wasm_bindgen(js_name = treePayloadFields)]
+    pub fn tree_payload_fields(&self, node_hex: String) -> Result<JsValue, JsValue> {
+        let node = hex_to_node(&node_hex).map_err(|e| JsValue::from_str(&e))?;
+        let fields = self
+            .inner
+            .payload_fields(node)
+            .map_err(|e| JsValue::from_str(&format!("{:?}", e)))?;
+        to_value(&fields).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
     #[wasm_bindgen(js_name = treeDump)]
     pub fn tree_dump(&self) -> Result<JsValue, JsValue> {
         #[derive(Serialize)]